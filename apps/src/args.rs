@@ -444,6 +444,8 @@ Options:
   --qpack-max-table-capacity BYTES  Max capacity of QPACK dynamic table decoding. Any value other that 0 is currently unsupported.
   --qpack-blocked-streams STREAMS   Limit of streams that can be blocked while decoding. Any value other that 0 is currently unsupported.
   --disable-gso               Disable GSO (linux only).
+  --source-rate PKTS          Max UDP datagrams per second accepted from each source IP, 0 to disable [default: 0].
+  --source-burst PKTS         Max burst of UDP datagrams accepted from each source IP [default: 100].
   -h --help                   Show this screen.
 ";
 
@@ -456,6 +458,8 @@ pub struct ServerArgs {
     pub cert: String,
     pub key: String,
     pub disable_gso: bool,
+    pub source_rate: u64,
+    pub source_burst: u64,
 }
 
 impl Args for ServerArgs {
@@ -470,6 +474,12 @@ impl Args for ServerArgs {
         let key = args.get_str("--key").to_string();
         let disable_gso = args.get_bool("--disable-gso");
 
+        let source_rate = args.get_str("--source-rate");
+        let source_rate = source_rate.parse::<u64>().unwrap();
+
+        let source_burst = args.get_str("--source-burst");
+        let source_burst = source_burst.parse::<u64>().unwrap();
+
         ServerArgs {
             listen,
             no_retry,
@@ -478,6 +488,120 @@ impl Args for ServerArgs {
             cert,
             key,
             disable_gso,
+            source_rate,
+            source_burst,
+        }
+    }
+}
+
+pub const PERF_USAGE: &str = "Usage:
+  quiche-perf client [options] ADDRESS
+  quiche-perf server [options]
+  quiche-perf -h | --help
+
+Options:
+  --listen <addr>             Listen on the given IP:port [default: 127.0.0.1:4433]
+  --cert <file>               TLS certificate path [default: src/bin/cert.crt]
+  --key <file>                TLS certificate key path [default: src/bin/cert.key]
+  --connections NUM           Number of parallel connections [default: 1].
+  --streams NUM               Number of streams on each connection [default: 1].
+  --download BYTES            Bytes requested from the server on each stream [default: 10000000].
+  --upload BYTES              Bytes sent to the server on each stream [default: 0].
+  --max-data BYTES            Connection-wide flow control limit [default: 10000000].
+  --max-stream-data BYTES     Per-stream flow control limit [default: 1000000].
+  --max-streams-bidi STREAMS  Number of allowed concurrent streams [default: 100].
+  --idle-timeout TIMEOUT      Idle timeout in milliseconds [default: 30000].
+  --cc-algorithm NAME         Specify which congestion control algorithm to use [default: cubic].
+  --disable-hystart           Disable HyStart++.
+  --plugin FILE ...           Load the given WebAssembly plugin, and advertise support for it.
+  --no-verify                 Don't verify server's certificate.
+  -h --help                   Show this screen.
+";
+
+/// Arguments of the perf tool, for both the client and the server.
+pub struct PerfArgs {
+    pub client: bool,
+    pub address: String,
+    pub listen: String,
+    pub cert: String,
+    pub key: String,
+    pub connections: usize,
+    pub streams: u64,
+    pub download: u64,
+    pub upload: u64,
+    pub max_data: u64,
+    pub max_stream_data: u64,
+    pub max_streams_bidi: u64,
+    pub idle_timeout: u64,
+    pub cc_algorithm: String,
+    pub disable_hystart: bool,
+    pub plugins: Vec<String>,
+    pub no_verify: bool,
+}
+
+impl Args for PerfArgs {
+    fn with_docopt(docopt: &docopt::Docopt) -> Self {
+        let args = docopt.parse().unwrap_or_else(|e| e.exit());
+
+        let client = args.get_bool("client");
+        let address = args.get_str("ADDRESS").to_string();
+        let listen = args.get_str("--listen").to_string();
+        let cert = args.get_str("--cert").to_string();
+        let key = args.get_str("--key").to_string();
+
+        let connections = args.get_str("--connections");
+        let connections = connections.parse::<usize>().unwrap();
+
+        let streams = args.get_str("--streams");
+        let streams = streams.parse::<u64>().unwrap();
+
+        let download = args.get_str("--download");
+        let download = download.parse::<u64>().unwrap();
+
+        let upload = args.get_str("--upload");
+        let upload = upload.parse::<u64>().unwrap();
+
+        let max_data = args.get_str("--max-data");
+        let max_data = max_data.parse::<u64>().unwrap();
+
+        let max_stream_data = args.get_str("--max-stream-data");
+        let max_stream_data = max_stream_data.parse::<u64>().unwrap();
+
+        let max_streams_bidi = args.get_str("--max-streams-bidi");
+        let max_streams_bidi = max_streams_bidi.parse::<u64>().unwrap();
+
+        let idle_timeout = args.get_str("--idle-timeout");
+        let idle_timeout = idle_timeout.parse::<u64>().unwrap();
+
+        let cc_algorithm = args.get_str("--cc-algorithm").to_string();
+        let disable_hystart = args.get_bool("--disable-hystart");
+
+        let plugins = args
+            .get_vec("--plugin")
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let no_verify = args.get_bool("--no-verify");
+
+        PerfArgs {
+            client,
+            address,
+            listen,
+            cert,
+            key,
+            connections,
+            streams,
+            download,
+            upload,
+            max_data,
+            max_stream_data,
+            max_streams_bidi,
+            idle_timeout,
+            cc_algorithm,
+            disable_hystart,
+            plugins,
+            no_verify,
         }
     }
 }
@@ -0,0 +1,737 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Load generator implementing the QUIC perf protocol.
+//!
+//! On each stream, the client sends the number of bytes it wants to download
+//! as a 64-bit big-endian integer, followed by the bytes it uploads, and
+//! then finishes the stream. The server answers with the requested number of
+//! bytes and finishes the stream as well.
+//!
+//! Both ends report throughput and CPU usage, so that runs with different
+//! congestion controllers or plugins can be compared with each other.
+
+#[macro_use]
+extern crate log;
+
+use std::collections::HashMap;
+
+use std::net::ToSocketAddrs;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use ring::rand::*;
+
+use quiche_apps::args::*;
+
+use quiche_apps::common::*;
+
+const MAX_BUF_SIZE: usize = 65507;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// Length of the request header, holding the number of bytes to download.
+const HEADER_LEN: usize = 8;
+
+/// A connection, wrapped so that the plugins given on the command line can be
+/// inserted into it.
+type PerfConn = Box<quiche::PluginizableConnection<quiche::Connection>>;
+
+/// Payload of the uploaded and downloaded bytes.
+static ZEROS: [u8; 16384] = [0; 16384];
+
+/// A stream of a client connection.
+#[derive(Default)]
+struct ClientStream {
+    /// Bytes of the request written so far, including the header.
+    sent: u64,
+
+    /// Bytes of the response received so far.
+    received: u64,
+
+    finished: bool,
+}
+
+/// A client connection and the socket it uses.
+struct PerfClient {
+    socket: mio::net::UdpSocket,
+
+    conn: PerfConn,
+
+    streams: HashMap<u64, ClientStream>,
+
+    /// Number of streams opened so far.
+    opened: u64,
+
+    /// Number of streams that received their whole response.
+    finished: u64,
+
+    received: u64,
+}
+
+/// A stream of a server connection.
+#[derive(Default)]
+struct ServerStream {
+    /// Request header, until it is complete.
+    header: Vec<u8>,
+
+    /// Bytes of the response still to be written, once the header was
+    /// received.
+    remaining: Option<u64>,
+
+    fin_sent: bool,
+}
+
+/// A server connection.
+struct PerfServerConn {
+    conn: PerfConn,
+
+    streams: HashMap<u64, ServerStream>,
+
+    started: Instant,
+
+    sent: u64,
+
+    received: u64,
+}
+
+fn main() {
+    env_logger::builder()
+        .default_format_timestamp_nanos(true)
+        .init();
+
+    // Parse CLI parameters.
+    let docopt = docopt::Docopt::new(PERF_USAGE).unwrap();
+    let args = PerfArgs::with_docopt(&docopt);
+
+    let mut config = make_config(&args);
+
+    if args.client {
+        run_client(&args, &mut config);
+    } else {
+        run_server(&args, &mut config);
+    }
+}
+
+fn make_config(args: &PerfArgs) -> quiche::Config {
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    if args.client {
+        config.verify_peer(!args.no_verify);
+    } else {
+        config.load_cert_chain_from_pem_file(&args.cert).unwrap();
+        config.load_priv_key_from_pem_file(&args.key).unwrap();
+    }
+
+    config.set_application_protos(&alpns::PERF).unwrap();
+
+    config.set_max_idle_timeout(args.idle_timeout);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(args.max_data);
+    config.set_initial_max_stream_data_bidi_local(args.max_stream_data);
+    config.set_initial_max_stream_data_bidi_remote(args.max_stream_data);
+    config.set_initial_max_streams_bidi(args.max_streams_bidi);
+    config.set_disable_active_migration(true);
+
+    config.set_cc_algorithm_name(&args.cc_algorithm).unwrap();
+
+    if args.disable_hystart {
+        config.enable_hystart(false);
+    }
+
+    // Plugins are advertised with the name of their file, without extension.
+    let mut plugins = Vec::with_capacity(args.plugins.len());
+
+    for path in &args.plugins {
+        config.load_plugin_from_file(path).unwrap();
+
+        let name = std::path::Path::new(path).file_stem().unwrap();
+        plugins.push(name.to_string_lossy().into_owned());
+    }
+
+    let plugins: Vec<&[u8]> = plugins.iter().map(|p| p.as_bytes()).collect();
+    config.set_plugin_support(&plugins).unwrap();
+
+    config
+}
+
+fn run_client(args: &PerfArgs, config: &mut quiche::Config) {
+    let mut buf = [0; MAX_BUF_SIZE];
+    let mut out = [0; MAX_BUF_SIZE];
+
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    let peer_addr = args.address.to_socket_addrs().unwrap().next().unwrap();
+
+    let bind_addr = match peer_addr {
+        std::net::SocketAddr::V4(_) => "0.0.0.0:0",
+        std::net::SocketAddr::V6(_) => "[::]:0",
+    };
+
+    let rng = SystemRandom::new();
+
+    // Each connection uses its own socket, so that packets don't need to be
+    // demultiplexed by connection ID.
+    let mut clients = Vec::with_capacity(args.connections);
+
+    for i in 0..args.connections {
+        let mut socket =
+            mio::net::UdpSocket::bind(bind_addr.parse().unwrap()).unwrap();
+        poll.registry()
+            .register(&mut socket, mio::Token(i), mio::Interest::READABLE)
+            .unwrap();
+
+        let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+        rng.fill(&mut scid[..]).unwrap();
+        let scid = quiche::ConnectionId::from_ref(&scid);
+
+        let local_addr = socket.local_addr().unwrap();
+
+        let conn = quiche::connect(None, &scid, local_addr, peer_addr, config)
+            .unwrap()
+            .pluginize()
+            .unwrap();
+
+        info!("connecting to {} from {}", peer_addr, local_addr);
+
+        clients.push(PerfClient {
+            socket,
+            conn,
+            streams: HashMap::new(),
+            opened: 0,
+            finished: 0,
+            received: 0,
+        });
+    }
+
+    let header = args.download.to_be_bytes();
+    let request_len = HEADER_LEN as u64 + args.upload;
+
+    let cpu_start = cpu_time();
+    let start = Instant::now();
+
+    loop {
+        let timeout = clients
+            .iter()
+            .filter_map(|c| c.conn.get_conn().timeout())
+            .min();
+
+        poll.poll(&mut events, timeout).unwrap();
+
+        // If the event loop reported no events, it means that a timeout
+        // has expired, so handle it without attempting to read packets.
+        if events.is_empty() {
+            trace!("timed out");
+
+            clients
+                .iter_mut()
+                .for_each(|c| c.conn.get_conn_mut().on_timeout());
+        }
+
+        for event in &events {
+            let client = &mut clients[event.token().0];
+            let local_addr = client.socket.local_addr().unwrap();
+
+            loop {
+                let (len, from) = match client.socket.recv_from(&mut buf) {
+                    Ok(v) => v,
+
+                    Err(e) => {
+                        if e.kind() == std::io::ErrorKind::WouldBlock {
+                            trace!("{}: recv() would block", local_addr);
+                            break;
+                        }
+
+                        panic!("recv() failed: {:?}", e);
+                    },
+                };
+
+                let recv_info = quiche::RecvInfo {
+                    to: local_addr,
+                    from,
+                };
+
+                if let Err(e) =
+                    client.conn.get_conn_mut().recv(&mut buf[..len], recv_info)
+                {
+                    error!("{}: recv failed: {:?}", local_addr, e);
+                }
+            }
+        }
+
+        for client in clients.iter_mut() {
+            if client.conn.get_conn().is_established() {
+                handle_client_streams(
+                    client,
+                    args,
+                    &header,
+                    request_len,
+                    &mut buf,
+                );
+            }
+
+            loop {
+                let (write, send_info) =
+                    match client.conn.get_conn_mut().send(&mut out) {
+                        Ok(v) => v,
+
+                        Err(quiche::Error::Done) => break,
+
+                        Err(e) => {
+                            error!("send failed: {:?}", e);
+
+                            client
+                                .conn
+                                .get_conn_mut()
+                                .close(false, 0x1, b"fail")
+                                .ok();
+                            break;
+                        },
+                    };
+
+                if let Err(e) = client.socket.send_to(&out[..write], send_info.to)
+                {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        trace!("send() would block");
+                        break;
+                    }
+
+                    panic!("send() failed: {:?}", e);
+                }
+            }
+        }
+
+        if clients.iter().all(|c| c.conn.get_conn().is_closed()) {
+            break;
+        }
+    }
+
+    let elapsed = start.elapsed();
+    let (user, system) = cpu_time_since(cpu_start);
+
+    let mut downloaded = 0;
+    let mut failed = 0;
+
+    for (i, client) in clients.iter().enumerate() {
+        let stats = client.conn.get_conn().stats();
+
+        let plugins: Vec<String> = client
+            .conn
+            .get_conn()
+            .negotiated_plugins()
+            .iter()
+            .map(|p| String::from_utf8_lossy(p).into_owned())
+            .collect();
+
+        println!(
+            "connection {}: received {} bytes on {}/{} streams, lost {}, \
+             retransmitted {}, plugins {:?}",
+            i,
+            client.received,
+            client.finished,
+            args.streams,
+            stats.lost,
+            stats.retrans,
+            plugins,
+        );
+
+        downloaded += client.received;
+
+        if client.finished < args.streams {
+            failed += 1;
+        }
+    }
+
+    println!(
+        "downloaded {} bytes in {:?} ({:.3} Mbit/s), cpu user {:?} system {:?}",
+        downloaded,
+        elapsed,
+        mbps(downloaded, elapsed),
+        user,
+        system,
+    );
+
+    if failed > 0 {
+        error!("{} connections did not complete", failed);
+        std::process::exit(-1);
+    }
+}
+
+/// Opens the client's streams, writes the requests and reads the responses.
+fn handle_client_streams(
+    client: &mut PerfClient, args: &PerfArgs, header: &[u8; HEADER_LEN],
+    request_len: u64, buf: &mut [u8],
+) {
+    while client.opened < args.streams &&
+        client.conn.get_conn().peer_streams_left_bidi() > 0
+    {
+        client
+            .streams
+            .insert(client.opened * 4, ClientStream::default());
+        client.opened += 1;
+    }
+
+    for (&stream_id, stream) in client.streams.iter_mut() {
+        while stream.sent < request_len {
+            let (data, fin) = if stream.sent < HEADER_LEN as u64 {
+                (&header[stream.sent as usize..], args.upload == 0)
+            } else {
+                let left = request_len - stream.sent;
+                let len = std::cmp::min(left, ZEROS.len() as u64);
+
+                (&ZEROS[..len as usize], len == left)
+            };
+
+            match client.conn.get_conn_mut().stream_send(stream_id, data, fin) {
+                Ok(written) => {
+                    stream.sent += written as u64;
+
+                    if written < data.len() {
+                        break;
+                    }
+                },
+
+                Err(quiche::Error::Done) => break,
+
+                Err(e) => {
+                    error!("stream {} send failed: {:?}", stream_id, e);
+                    break;
+                },
+            }
+        }
+    }
+
+    for stream_id in client.conn.get_conn().readable() {
+        while let Ok((read, fin)) =
+            client.conn.get_conn_mut().stream_recv(stream_id, buf)
+        {
+            client.received += read as u64;
+
+            let stream = client.streams.entry(stream_id).or_default();
+            stream.received += read as u64;
+
+            if fin && !stream.finished {
+                stream.finished = true;
+                client.finished += 1;
+
+                if stream.received != args.download {
+                    error!(
+                        "stream {} received {} bytes instead of {}",
+                        stream_id, stream.received, args.download
+                    );
+                }
+            }
+        }
+    }
+
+    if client.finished == args.streams {
+        client.conn.get_conn_mut().close(true, 0x0, b"done").ok();
+    }
+}
+
+fn run_server(args: &PerfArgs, config: &mut quiche::Config) {
+    let mut buf = [0; MAX_BUF_SIZE];
+    let mut out = [0; MAX_BUF_SIZE];
+
+    let mut poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    let mut socket =
+        mio::net::UdpSocket::bind(args.listen.parse().unwrap()).unwrap();
+    poll.registry()
+        .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+        .unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
+    info!("listening on {}", local_addr);
+
+    let rng = SystemRandom::new();
+    let conn_id_seed =
+        ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
+
+    let mut conns: HashMap<quiche::ConnectionId<'static>, PerfServerConn> =
+        HashMap::new();
+
+    let cpu_start = cpu_time();
+
+    loop {
+        let timeout = conns
+            .values()
+            .filter_map(|c| c.conn.get_conn().timeout())
+            .min();
+
+        poll.poll(&mut events, timeout).unwrap();
+
+        if events.is_empty() {
+            trace!("timed out");
+
+            conns
+                .values_mut()
+                .for_each(|c| c.conn.get_conn_mut().on_timeout());
+        }
+
+        'read: loop {
+            if events.is_empty() {
+                break;
+            }
+
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        trace!("recv() would block");
+                        break 'read;
+                    }
+
+                    panic!("recv() failed: {:?}", e);
+                },
+            };
+
+            let pkt_buf = &mut buf[..len];
+
+            let hdr = match quiche::Header::from_slice(
+                pkt_buf,
+                quiche::MAX_CONN_ID_LEN,
+            ) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    error!("Parsing packet header failed: {:?}", e);
+                    continue 'read;
+                },
+            };
+
+            let conn_id = ring::hmac::sign(&conn_id_seed, &hdr.dcid);
+            let conn_id = &conn_id.as_ref()[..quiche::MAX_CONN_ID_LEN];
+            let conn_id: quiche::ConnectionId<'static> = conn_id.to_vec().into();
+
+            let key = if conns.contains_key(&hdr.dcid) {
+                hdr.dcid.clone().into_owned()
+            } else if conns.contains_key(&conn_id) {
+                conn_id
+            } else {
+                if hdr.ty != quiche::Type::Initial {
+                    error!("Packet is not Initial");
+                    continue 'read;
+                }
+
+                if !quiche::version_is_supported(hdr.version) {
+                    let len =
+                        quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut out)
+                            .unwrap();
+
+                    socket.send_to(&out[..len], from).ok();
+                    continue 'read;
+                }
+
+                let conn =
+                    quiche::accept(&conn_id, None, local_addr, from, config)
+                        .unwrap()
+                        .pluginize()
+                        .unwrap();
+
+                info!("new connection from {}", from);
+
+                conns.insert(conn_id.clone(), PerfServerConn {
+                    conn,
+                    streams: HashMap::new(),
+                    started: Instant::now(),
+                    sent: 0,
+                    received: 0,
+                });
+
+                conn_id
+            };
+
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+            };
+
+            let c = conns.get_mut(&key).unwrap();
+
+            if let Err(e) = c.conn.get_conn_mut().recv(pkt_buf, recv_info) {
+                error!("{} recv failed: {:?}", c.conn.get_conn().trace_id(), e);
+            }
+        }
+
+        for c in conns.values_mut() {
+            if c.conn.get_conn().is_established() {
+                handle_server_streams(c, &mut buf);
+            }
+
+            loop {
+                let (write, send_info) =
+                    match c.conn.get_conn_mut().send(&mut out) {
+                        Ok(v) => v,
+
+                        Err(quiche::Error::Done) => break,
+
+                        Err(e) => {
+                            error!(
+                                "{} send failed: {:?}",
+                                c.conn.get_conn().trace_id(),
+                                e
+                            );
+
+                            c.conn.get_conn_mut().close(false, 0x1, b"fail").ok();
+                            break;
+                        },
+                    };
+
+                if let Err(e) = socket.send_to(&out[..write], send_info.to) {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        trace!("send() would block");
+                        break;
+                    }
+
+                    panic!("send() failed: {:?}", e);
+                }
+            }
+        }
+
+        // Report and garbage collect closed connections.
+        conns.retain(|_, c| {
+            if !c.conn.get_conn().is_closed() {
+                return true;
+            }
+
+            let elapsed = c.started.elapsed();
+            let (user, system) = cpu_time_since(cpu_start);
+
+            println!(
+                "{}: sent {} bytes, received {} bytes in {:?} ({:.3} Mbit/s), \
+                 total cpu user {:?} system {:?}",
+                c.conn.get_conn().trace_id(),
+                c.sent,
+                c.received,
+                elapsed,
+                mbps(c.sent, elapsed),
+                user,
+                system,
+            );
+
+            false
+        });
+    }
+}
+
+/// Reads the requests of a server connection and writes the responses.
+fn handle_server_streams(c: &mut PerfServerConn, buf: &mut [u8]) {
+    for stream_id in c.conn.get_conn().readable() {
+        while let Ok((read, _)) =
+            c.conn.get_conn_mut().stream_recv(stream_id, buf)
+        {
+            c.received += read as u64;
+
+            let stream = c.streams.entry(stream_id).or_default();
+
+            if stream.remaining.is_some() {
+                continue;
+            }
+
+            let needed = HEADER_LEN - stream.header.len();
+            let read = std::cmp::min(read, needed);
+            stream.header.extend_from_slice(&buf[..read]);
+
+            if stream.header.len() == HEADER_LEN {
+                let mut header = [0; HEADER_LEN];
+                header.copy_from_slice(&stream.header);
+
+                stream.remaining = Some(u64::from_be_bytes(header));
+            }
+        }
+    }
+
+    for (&stream_id, stream) in c.streams.iter_mut() {
+        let remaining = match stream.remaining.as_mut() {
+            Some(v) => v,
+
+            None => continue,
+        };
+
+        while !stream.fin_sent {
+            let len = std::cmp::min(*remaining, ZEROS.len() as u64);
+            let fin = len == *remaining;
+
+            match c.conn.get_conn_mut().stream_send(
+                stream_id,
+                &ZEROS[..len as usize],
+                fin,
+            ) {
+                Ok(written) => {
+                    *remaining -= written as u64;
+                    c.sent += written as u64;
+
+                    if written as u64 == len && fin {
+                        stream.fin_sent = true;
+                    } else if (written as u64) < len {
+                        break;
+                    }
+                },
+
+                Err(quiche::Error::Done) => break,
+
+                Err(e) => {
+                    error!("stream {} send failed: {:?}", stream_id, e);
+
+                    stream.fin_sent = true;
+                },
+            }
+        }
+    }
+}
+
+/// Returns the user and system CPU time used by the process so far.
+fn cpu_time() -> (Duration, Duration) {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    unsafe {
+        libc::getrusage(libc::RUSAGE_SELF, &mut usage);
+    }
+
+    let duration = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) +
+            Duration::from_micros(tv.tv_usec as u64)
+    };
+
+    (duration(usage.ru_utime), duration(usage.ru_stime))
+}
+
+fn cpu_time_since(start: (Duration, Duration)) -> (Duration, Duration) {
+    let (user, system) = cpu_time();
+
+    (user.saturating_sub(start.0), system.saturating_sub(start.1))
+}
+
+fn mbps(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1e6
+}
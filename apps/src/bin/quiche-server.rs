@@ -29,8 +29,6 @@ extern crate log;
 
 use std::io;
 
-use std::net;
-
 use std::io::prelude::*;
 
 use std::collections::HashMap;
@@ -41,10 +39,14 @@ use std::cell::RefCell;
 
 use ring::rand::*;
 
+use quiche::TokenMinter;
+
 use quiche_apps::args::*;
 
 use quiche_apps::common::*;
 
+use quiche_apps::recvfrom::*;
+
 use quiche_apps::sendto::*;
 
 const MAX_BUF_SIZE: usize = 65507;
@@ -98,6 +100,10 @@ fn main() {
 
     trace!("GSO detected: {}", enable_gso);
 
+    let enable_gro = detect_gro(&socket) && set_gro(&socket);
+
+    trace!("GRO enabled: {}", enable_gro);
+
     // Create the configuration for the QUIC connections.
     let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 
@@ -139,6 +145,21 @@ fn main() {
         config.enable_early_data();
     }
 
+    // Address validation tokens are encrypted with keys rotated every hour,
+    // and NEW_TOKEN tokens don't outlive the overlap window of the ring.
+    let key_ring = std::sync::Arc::new(quiche::KeyRing::new(
+        std::time::Duration::from_secs(3600),
+        std::time::Duration::from_secs(3600),
+    ));
+
+    let token_minter = std::sync::Arc::new(quiche::KeyRingTokenMinter::new(
+        key_ring,
+        std::time::Duration::from_secs(10),
+        std::time::Duration::from_secs(3600),
+    ));
+
+    config.set_token_minter(Some(token_minter.clone()));
+
     if conn_args.no_grease {
         config.grease(false);
     }
@@ -165,6 +186,19 @@ fn main() {
 
     let mut pkt_count = 0;
 
+    let mut source_limiter = if args.source_rate > 0 {
+        let mut limiter =
+            quiche::SourceRateLimiter::new(args.source_rate, args.source_burst);
+
+        limiter.set_overflow_callback(Some(Box::new(|from| {
+            warn!("rate limiting packets from {}", from);
+        })));
+
+        Some(limiter)
+    } else {
+        None
+    };
+
     let mut continue_write = false;
 
     let local_addr = socket.local_addr().unwrap();
@@ -195,23 +229,31 @@ fn main() {
                 break 'read;
             }
 
-            let (len, from) = match socket.recv_from(&mut buf) {
-                Ok(v) => v,
+            let (len, from, segment_size) =
+                match recv_from(&socket, &mut buf, enable_gro) {
+                    Ok(v) => v,
 
-                Err(e) => {
-                    // There are no more UDP packets to read, so end the read
-                    // loop.
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        trace!("recv() would block");
-                        break 'read;
-                    }
+                    Err(e) => {
+                        // There are no more UDP packets to read, so end the read
+                        // loop.
+                        if e.kind() == std::io::ErrorKind::WouldBlock {
+                            trace!("recv() would block");
+                            break 'read;
+                        }
 
-                    panic!("recv() failed: {:?}", e);
-                },
-            };
+                        panic!("recv() failed: {:?}", e);
+                    },
+                };
 
             trace!("got {} bytes", len);
 
+            if let Some(limiter) = source_limiter.as_mut() {
+                if !limiter.allow(from.ip(), std::time::Instant::now()) {
+                    trace!("dropped packet from {}", from);
+                    continue 'read;
+                }
+            }
+
             let pkt_buf = &mut buf[..len];
 
             if let Some(target_path) = conn_args.dump_packet_path.as_ref() {
@@ -225,9 +267,11 @@ fn main() {
 
             pkt_count += 1;
 
-            // Parse the QUIC packet's header.
+            // Parse the header of the first QUIC packet. GRO only coalesces
+            // datagrams of the same flow, so all of them are routed to the
+            // connection of the first one.
             let hdr = match quiche::Header::from_slice(
-                pkt_buf,
+                &mut pkt_buf[..segment_size],
                 quiche::MAX_CONN_ID_LEN,
             ) {
                 Ok(v) => v,
@@ -277,26 +321,30 @@ fn main() {
                 let mut scid = [0; quiche::MAX_CONN_ID_LEN];
                 scid.copy_from_slice(&conn_id);
 
-                let mut odcid = None;
+                let mut token: &[u8] = &[];
 
                 if !args.no_retry {
                     // Token is always present in Initial packets.
-                    let token = hdr.token.as_ref().unwrap();
-
-                    // Do stateless retry if the client didn't send a token.
-                    if token.is_empty() {
+                    token = hdr.token.as_ref().unwrap();
+
+                    // Do stateless retry if the client didn't send a token,
+                    // or sent a NEW_TOKEN token that is not valid anymore.
+                    if token.is_empty() ||
+                        (!token_minter.is_retry_token(token) &&
+                            token_minter.validate(token, &from).is_err())
+                    {
                         warn!("Doing stateless retry");
 
                         let scid = quiche::ConnectionId::from_ref(&scid);
-                        let new_token = mint_token(&hdr, &from);
 
-                        let len = quiche::retry(
+                        let len = quiche::retry_minted(
                             &hdr.scid,
                             &hdr.dcid,
                             &scid,
-                            &new_token,
+                            &from,
                             hdr.version,
                             &mut out,
+                            &config,
                         )
                         .unwrap();
 
@@ -313,15 +361,6 @@ fn main() {
                         continue 'read;
                     }
 
-                    odcid = validate_token(&from, token);
-
-                    // The token was not valid, meaning the retry failed, so
-                    // drop the packet.
-                    if odcid.is_none() {
-                        error!("Invalid address validation token");
-                        continue;
-                    }
-
                     if scid.len() != hdr.dcid.len() {
                         error!("Invalid destination connection ID");
                         continue 'read;
@@ -337,14 +376,22 @@ fn main() {
                 debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, scid);
 
                 #[allow(unused_mut)]
-                let mut conn = quiche::accept(
+                let mut conn = match quiche::accept_with_token(
                     &scid,
-                    odcid.as_ref(),
+                    token,
                     local_addr,
                     from,
                     &mut config,
-                )
-                .unwrap();
+                ) {
+                    Ok(v) => v,
+
+                    // The Retry token was not valid, meaning the retry failed,
+                    // so drop the packet.
+                    Err(_) => {
+                        error!("Invalid address validation token");
+                        continue;
+                    },
+                };
 
                 if let Some(keylog) = &mut keylog {
                     if let Ok(keylog) = keylog.try_clone() {
@@ -403,8 +450,15 @@ fn main() {
                 from,
             };
 
-            // Process potentially coalesced packets.
-            let read = match client.conn.recv(pkt_buf, recv_info) {
+            let mut segments = [quiche::RecvSegment {
+                buf: pkt_buf,
+                segment_size,
+                info: recv_info,
+            }];
+
+            // Process potentially coalesced packets, in each of the datagrams
+            // coalesced by GRO.
+            let read = match client.conn.recv_batch(&mut segments) {
                 Ok(v) => v,
 
                 Err(e) => {
@@ -556,12 +610,14 @@ fn main() {
                     client.max_datagram_size *
                     client.max_datagram_size;
             let mut total_write = 0;
-            let mut dst_info = None;
+            let mut batches = Vec::new();
 
+            // Packets of the same size sent on the same path are batched, so
+            // that each batch can be sent using GSO.
             while total_write < max_send_burst {
-                let (write, send_info) = match client
+                let (write, segment_size, send_info) = match client
                     .conn
-                    .send(&mut out[total_write..max_send_burst])
+                    .send_batch(&mut out[total_write..max_send_burst])
                 {
                     Ok(v) => v,
 
@@ -578,28 +634,26 @@ fn main() {
                     },
                 };
 
-                total_write += write;
-
-                // Use the first packet time to send, not the last.
-                let _ = dst_info.get_or_insert(send_info);
+                let range = total_write..total_write + write;
+                batches.push((range, segment_size, send_info));
 
-                if write < client.max_datagram_size {
-                    break;
-                }
+                total_write += write;
             }
 
-            if total_write == 0 || dst_info.is_none() {
+            if total_write == 0 {
                 break;
             }
 
-            if let Err(e) = send_to(
-                &socket,
-                &out[..total_write],
-                &dst_info.unwrap(),
-                client.max_datagram_size,
-                pacing,
-                enable_gso,
-            ) {
+            let batches: Vec<SendBatch> = batches
+                .into_iter()
+                .map(|(range, segment_size, send_info)| SendBatch {
+                    buf: &out[range],
+                    send_info,
+                    segment_size,
+                })
+                .collect();
+
+            if let Err(e) = send_batches(&socket, &batches, pacing, enable_gso) {
                 if e.kind() == std::io::ErrorKind::WouldBlock {
                     trace!("send() would block");
                     break;
@@ -637,62 +691,6 @@ fn main() {
     }
 }
 
-/// Generate a stateless retry token.
-///
-/// The token includes the static string `"quiche"` followed by the IP address
-/// of the client and by the original destination connection ID generated by the
-/// client.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn mint_token(hdr: &quiche::Header, src: &net::SocketAddr) -> Vec<u8> {
-    let mut token = Vec::new();
-
-    token.extend_from_slice(b"quiche");
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    token.extend_from_slice(&addr);
-    token.extend_from_slice(&hdr.dcid);
-
-    token
-}
-
-/// Validates a stateless retry token.
-///
-/// This checks that the ticket includes the `"quiche"` static string, and that
-/// the client IP address matches the address stored in the ticket.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn validate_token<'a>(
-    src: &net::SocketAddr, token: &'a [u8],
-) -> Option<quiche::ConnectionId<'a>> {
-    if token.len() < 6 {
-        return None;
-    }
-
-    if &token[..6] != b"quiche" {
-        return None;
-    }
-
-    let token = &token[6..];
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    if token.len() < addr.len() || &token[..addr.len()] != addr.as_slice() {
-        return None;
-    }
-
-    Some(quiche::ConnectionId::from_ref(&token[addr.len()..]))
-}
-
 fn handle_path_events(client: &mut Client) {
     while let Some(qe) = client.conn.path_event_next() {
         match qe {
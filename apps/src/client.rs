@@ -26,6 +26,8 @@
 
 use crate::args::*;
 use crate::common::*;
+use crate::recvfrom::*;
+use crate::sendto::*;
 
 use std::net::ToSocketAddrs;
 
@@ -39,6 +41,8 @@ use ring::rand::*;
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
+const MAX_SEND_BUF_SIZE: usize = 65507;
+
 #[derive(Debug)]
 pub enum ClientError {
     HandshakeFail,
@@ -51,7 +55,7 @@ pub fn connect(
     output_sink: impl FnMut(String) + 'static,
 ) -> Result<(), ClientError> {
     let mut buf = [0; 65535];
-    let mut out = [0; MAX_DATAGRAM_SIZE];
+    let mut out = [0; MAX_SEND_BUF_SIZE];
 
     let output_sink =
         Rc::new(RefCell::new(output_sink)) as Rc<RefCell<dyn FnMut(_)>>;
@@ -98,6 +102,21 @@ pub fn connect(
         None
     };
 
+    // Both sockets are bound to the same kind of address, so GRO support
+    // only needs to be detected once, but it needs to be enabled on each.
+    let enable_gro = detect_gro(&socket) &&
+        set_gro(&socket) &&
+        migrate_socket.as_ref().map_or(true, set_gro);
+
+    trace!("GRO enabled: {}", enable_gro);
+
+    let enable_gso = detect_gso(&socket, MAX_DATAGRAM_SIZE) &&
+        migrate_socket
+            .as_ref()
+            .map_or(true, |s| detect_gso(s, MAX_DATAGRAM_SIZE));
+
+    trace!("GSO detected: {}", enable_gso);
+
     // Create the configuration for the QUIC connection.
     let mut config = quiche::Config::new(args.version).unwrap();
 
@@ -264,22 +283,23 @@ pub fn connect(
 
             let local_addr = socket.local_addr().unwrap();
             'read: loop {
-                let (len, from) = match socket.recv_from(&mut buf) {
-                    Ok(v) => v,
-
-                    Err(e) => {
-                        // There are no more UDP packets to read on this socket.
-                        // Process subsequent events.
-                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                            trace!("{}: recv() would block", local_addr);
-                            break 'read;
-                        }
+                let (len, from, segment_size) =
+                    match recv_from(socket, &mut buf, enable_gro) {
+                        Ok(v) => v,
 
-                        return Err(ClientError::Other(format!(
-                            "{local_addr}: recv() failed: {e:?}"
-                        )));
-                    },
-                };
+                        Err(e) => {
+                            // There are no more UDP packets to read on this
+                            // socket. Process subsequent events.
+                            if e.kind() == std::io::ErrorKind::WouldBlock {
+                                trace!("{}: recv() would block", local_addr);
+                                break 'read;
+                            }
+
+                            return Err(ClientError::Other(format!(
+                                "{local_addr}: recv() failed: {e:?}"
+                            )));
+                        },
+                    };
 
                 trace!("{}: got {} bytes", local_addr, len);
 
@@ -299,8 +319,14 @@ pub fn connect(
                     from,
                 };
 
+                let mut segments = [quiche::RecvSegment {
+                    buf: &mut buf[..len],
+                    segment_size,
+                    info: recv_info,
+                }];
+
                 // Process potentially coalesced packets.
-                let read = match conn.recv(&mut buf[..len], recv_info) {
+                let read = match conn.recv_batch(&mut segments) {
                     Ok(v) => v,
 
                     Err(e) => {
@@ -509,11 +535,12 @@ pub fn connect(
 
             for peer_addr in conn.paths_iter(local_addr) {
                 loop {
-                    let (write, send_info) = match conn.send_on_path(
-                        &mut out,
-                        Some(local_addr),
-                        Some(peer_addr),
-                    ) {
+                    let (write, segment_size, send_info) = match conn
+                        .send_batch_on_path(
+                            &mut out,
+                            Some(local_addr),
+                            Some(peer_addr),
+                        ) {
                         Ok(v) => v,
 
                         Err(quiche::Error::Done) => {
@@ -536,7 +563,14 @@ pub fn connect(
                         },
                     };
 
-                    if let Err(e) = socket.send_to(&out[..write], send_info.to) {
+                    if let Err(e) = send_to(
+                        socket,
+                        &out[..write],
+                        &send_info,
+                        segment_size,
+                        false,
+                        enable_gso,
+                    ) {
                         if e.kind() == std::io::ErrorKind::WouldBlock {
                             trace!(
                                 "{} -> {}: send() would block",
@@ -65,6 +65,7 @@ pub mod alpns {
         [b"hq-interop", b"hq-29", b"hq-28", b"hq-27", b"http/0.9"];
     pub const HTTP_3: [&[u8]; 4] = [b"h3", b"h3-29", b"h3-28", b"h3-27"];
     pub const SIDUCK: [&[u8]; 2] = [b"siduck", b"siduck-00"];
+    pub const PERF: [&[u8]; 1] = [b"perf"];
 }
 
 pub struct PartialRequest {
@@ -768,8 +769,29 @@ impl HttpConn for Http09Conn {
                         s
                     );
 
-                    let body = std::fs::read(path.as_path())
-                        .unwrap_or_else(|_| b"Not Found!\r\n".to_vec());
+                    // Files are read lazily by the connection as the
+                    // response is sent, instead of being buffered whole.
+                    if let Ok(file) = std::fs::File::open(path.as_path()) {
+                        info!(
+                            "{} sending file {:?} on stream {}",
+                            conn.trace_id(),
+                            path,
+                            s
+                        );
+
+                        if let Err(e) = conn.stream_send_reader(s, file) {
+                            error!(
+                                "{} stream send failed {:?}",
+                                conn.trace_id(),
+                                e
+                            );
+                            return Err(From::from(e));
+                        }
+
+                        continue;
+                    }
+
+                    let body = b"Not Found!\r\n".to_vec();
 
                     info!(
                         "{} sending response of size {} on stream {}",
@@ -1502,6 +1524,25 @@ impl HttpConn for Http3Conn {
                     );
                 },
 
+                Ok((stream_id, quiche::h3::Event::InterimHeaders { list })) => {
+                    debug!(
+                        "got interim response headers {:?} on stream id {}",
+                        hdrs_to_strings(&list),
+                        stream_id
+                    );
+                },
+
+                Ok((stream_id, quiche::h3::Event::Trailers { list })) => {
+                    debug!(
+                        "got response trailers {:?} on stream id {}",
+                        hdrs_to_strings(&list),
+                        stream_id
+                    );
+                },
+
+                // Server push is not enabled by the client.
+                Ok((_, quiche::h3::Event::PushPromise { .. })) => unreachable!(),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
@@ -1708,6 +1749,41 @@ impl HttpConn for Http3Conn {
                         conn.trace_id(),
                         prioritized_element_id
                     );
+
+                    // Responses that were already started are reprioritized
+                    // right away, others pick up the update once the request
+                    // is handled.
+                    #[cfg(feature = "sfv")]
+                    if let Some(resp) =
+                        partial_responses.get_mut(&prioritized_element_id)
+                    {
+                        let id = prioritized_element_id;
+
+                        let priority = if resp.headers.is_some() {
+                            match self.h3_conn.take_last_priority_update(id) {
+                                Ok(v) =>
+                                    quiche::h3::Priority::try_from(v.as_slice())
+                                        .ok(),
+
+                                Err(_) => None,
+                            }
+                        } else {
+                            self.h3_conn.apply_priority_update(conn, id).ok()
+                        };
+
+                        if let Some(priority) = priority {
+                            if resp.headers.is_some() {
+                                resp.priority = Some(priority);
+                            }
+
+                            info!(
+                                "{} reprioritizing response on stream {} as {:?}",
+                                conn.trace_id(),
+                                id,
+                                priority
+                            );
+                        }
+                    }
                 },
 
                 Ok((goaway_id, quiche::h3::Event::GoAway)) => {
@@ -1720,6 +1796,14 @@ impl HttpConn for Http3Conn {
                         .send_goaway(conn, self.largest_processed_request)?;
                 },
 
+                // Request streams are not read past their headers.
+                Ok((_, quiche::h3::Event::Trailers { .. })) => (),
+
+                Ok((_, quiche::h3::Event::InterimHeaders { .. })) =>
+                    unreachable!(),
+
+                Ok((_, quiche::h3::Event::PushPromise { .. })) => unreachable!(),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
@@ -0,0 +1,141 @@
+// Copyright (C) 2023, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io;
+
+use std::net::SocketAddr;
+
+/// For Linux, try to detect GRO is available, without enabling it.
+#[cfg(target_os = "linux")]
+pub fn detect_gro(socket: &mio::net::UdpSocket) -> bool {
+    use nix::sys::socket::getsockopt;
+    use nix::sys::socket::sockopt::UdpGroSegment;
+    use std::os::unix::io::AsRawFd;
+
+    getsockopt(socket.as_raw_fd(), UdpGroSegment).is_ok()
+}
+
+/// For non-Linux, there is no GRO support.
+#[cfg(not(target_os = "linux"))]
+pub fn detect_gro(_socket: &mio::net::UdpSocket) -> bool {
+    false
+}
+
+/// For Linux, try to enable GRO on the socket.
+#[cfg(target_os = "linux")]
+pub fn set_gro(socket: &mio::net::UdpSocket) -> bool {
+    use nix::sys::socket::setsockopt;
+    use nix::sys::socket::sockopt::UdpGroSegment;
+    use std::os::unix::io::AsRawFd;
+
+    setsockopt(socket.as_raw_fd(), UdpGroSegment, &true).is_ok()
+}
+
+/// For non-Linux, there is no GRO support.
+#[cfg(not(target_os = "linux"))]
+pub fn set_gro(_socket: &mio::net::UdpSocket) -> bool {
+    false
+}
+
+/// Receive packets using recvmsg() with GRO.
+#[cfg(target_os = "linux")]
+fn recv_from_gro(
+    socket: &mio::net::UdpSocket, buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, usize)> {
+    use nix::sys::socket::recvmsg;
+    use nix::sys::socket::ControlMessageOwned;
+    use nix::sys::socket::MsgFlags;
+    use nix::sys::socket::SockaddrStorage;
+    use std::io::IoSliceMut;
+    use std::os::unix::io::AsRawFd;
+
+    let mut iov = [IoSliceMut::new(buf)];
+    let mut cmsg_buf = nix::cmsg_space!(u16);
+
+    let msg = recvmsg::<SockaddrStorage>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buf),
+        MsgFlags::empty(),
+    )?;
+
+    // Without a GRO control message, a single datagram was received.
+    let mut segment_size = msg.bytes;
+
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::UdpGroSegments(v) = cmsg {
+            segment_size = v as usize;
+        }
+    }
+
+    match msg.address.as_ref().and_then(sockaddr_to_std) {
+        Some(from) => Ok((msg.bytes, from, segment_size)),
+
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "recvmsg() returned no source address",
+        )),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sockaddr_to_std(
+    addr: &nix::sys::socket::SockaddrStorage,
+) -> Option<SocketAddr> {
+    if let Some(v) = addr.as_sockaddr_in() {
+        return Some(SocketAddr::V4((*v).into()));
+    }
+
+    if let Some(v) = addr.as_sockaddr_in6() {
+        return Some(SocketAddr::V6((*v).into()));
+    }
+
+    None
+}
+
+/// For non-Linux platforms.
+#[cfg(not(target_os = "linux"))]
+fn recv_from_gro(
+    _socket: &mio::net::UdpSocket, _buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, usize)> {
+    panic!("recv_from_gro() should not be called on non-linux platforms");
+}
+
+/// A wrapper function of recv_from().
+/// - when GRO is enabled, receive packets using recv_from_gro(), which can
+/// return several coalesced datagrams of the returned segment size.
+/// Otherwise, receive a single datagram using socket.recv_from().
+pub fn recv_from(
+    socket: &mio::net::UdpSocket, buf: &mut [u8], enable_gro: bool,
+) -> io::Result<(usize, SocketAddr, usize)> {
+    if enable_gro {
+        return recv_from_gro(socket, buf);
+    }
+
+    let (len, from) = socket.recv_from(buf)?;
+
+    Ok((len, from, len))
+}
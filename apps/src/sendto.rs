@@ -44,11 +44,40 @@ pub fn detect_gso(_socket: &mio::net::UdpSocket, _segment_size: usize) -> bool {
     false
 }
 
+/// A buffer of datagrams of the same size, but for the last one, to be sent
+/// to the same destination.
+pub struct SendBatch<'a> {
+    pub buf: &'a [u8],
+    pub send_info: quiche::SendInfo,
+    pub segment_size: usize,
+}
+
+/// Returns the time a packet must be sent at, as expected by SO_TXTIME.
+#[cfg(target_os = "linux")]
+fn send_time(send_info: &quiche::SendInfo) -> u64 {
+    let nanos_per_sec: u64 = 1_000_000_000;
+
+    let mut time_spec = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            &send_info.at as *const _ as *const libc::timespec,
+            &mut time_spec,
+            1,
+        )
+    };
+
+    time_spec.tv_sec as u64 * nanos_per_sec + time_spec.tv_nsec as u64
+}
+
 /// Send packets using sendmsg() with GSO.
 #[cfg(target_os = "linux")]
 fn send_to_gso_pacing(
     socket: &mio::net::UdpSocket, buf: &[u8], send_info: &quiche::SendInfo,
-    segment_size: usize,
+    segment_size: usize, pacing: bool,
 ) -> io::Result<usize> {
     use nix::sys::socket::sendmsg;
     use nix::sys::socket::ControlMessage;
@@ -64,34 +93,18 @@ fn send_to_gso_pacing(
 
     // GSO option.
     let cmsg_gso = ControlMessage::UdpGsoSegments(&segment_size);
-    let nanos_per_sec: u64 = 1_000_000_000;
 
     // Pacing option.
-    let mut time_spec = libc::timespec {
-        tv_sec: 0,
-        tv_nsec: 0,
-    };
+    let send_time = send_time(send_info);
+    let cmsg_txtime = ControlMessage::TxTime(&send_time);
 
-    unsafe {
-        std::ptr::copy_nonoverlapping(
-            &send_info.at as *const _ as *const libc::timespec,
-            &mut time_spec,
-            1,
-        )
+    let cmsgs = if pacing {
+        vec![cmsg_gso, cmsg_txtime]
+    } else {
+        vec![cmsg_gso]
     };
 
-    let send_time =
-        time_spec.tv_sec as u64 * nanos_per_sec + time_spec.tv_nsec as u64;
-
-    let cmsg_txtime = ControlMessage::TxTime(&send_time);
-
-    match sendmsg(
-        sockfd,
-        &iov,
-        &[cmsg_gso, cmsg_txtime],
-        MsgFlags::empty(),
-        Some(&dst),
-    ) {
+    match sendmsg(sockfd, &iov, &cmsgs, MsgFlags::empty(), Some(&dst)) {
         Ok(v) => Ok(v),
         Err(e) => Err(e.into()),
     }
@@ -101,20 +114,21 @@ fn send_to_gso_pacing(
 #[cfg(not(target_os = "linux"))]
 fn send_to_gso_pacing(
     _socket: &mio::net::UdpSocket, _buf: &[u8], _send_info: &quiche::SendInfo,
-    _segment_size: usize,
+    _segment_size: usize, _pacing: bool,
 ) -> io::Result<usize> {
     panic!("send_to_gso() should not be called on non-linux platforms");
 }
 
 /// A wrapper function of send_to().
-/// - when GSO and SO_TXTIME enabled, send a packet using send_to_gso().
+/// - when GSO is enabled, send a packet using send_to_gso(), along with its
+/// send time when SO_TXTIME is enabled.
 /// Otherwise, send packet using socket.send_to().
 pub fn send_to(
     socket: &mio::net::UdpSocket, buf: &[u8], send_info: &quiche::SendInfo,
     segment_size: usize, pacing: bool, enable_gso: bool,
 ) -> io::Result<usize> {
-    if pacing && enable_gso {
-        match send_to_gso_pacing(socket, buf, send_info, segment_size) {
+    if enable_gso {
+        match send_to_gso_pacing(socket, buf, send_info, segment_size, pacing) {
             Ok(v) => {
                 return Ok(v);
             },
@@ -144,3 +158,131 @@ pub fn send_to(
 
     Ok(written)
 }
+
+/// Send several batches of packets using a single sendmmsg() call with GSO.
+#[cfg(target_os = "linux")]
+fn send_mmsg_gso(
+    socket: &mio::net::UdpSocket, batches: &[SendBatch], pacing: bool,
+) -> io::Result<usize> {
+    use nix::sys::socket::SockaddrLike;
+    use nix::sys::socket::SockaddrStorage;
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    let mut iovs: Vec<libc::iovec> = batches
+        .iter()
+        .map(|b| libc::iovec {
+            iov_base: b.buf.as_ptr() as *mut libc::c_void,
+            iov_len: b.buf.len(),
+        })
+        .collect();
+
+    let addrs: Vec<SockaddrStorage> = batches
+        .iter()
+        .map(|b| SockaddrStorage::from(b.send_info.to))
+        .collect();
+
+    // Room for the GSO and pacing options of each message, using u64 words
+    // to keep the control messages aligned.
+    let (gso_space, txtime_space) = unsafe {
+        (
+            libc::CMSG_SPACE(mem::size_of::<u16>() as u32) as usize,
+            libc::CMSG_SPACE(mem::size_of::<u64>() as u32) as usize,
+        )
+    };
+    let cmsg_len = gso_space + txtime_space;
+    let cmsg_words = (cmsg_len + 7) / 8;
+    let mut cmsg_buf = vec![0u64; cmsg_words * batches.len()];
+
+    let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(batches.len());
+
+    for (i, batch) in batches.iter().enumerate() {
+        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+
+        hdr.msg_name = addrs[i].as_ptr() as *mut libc::c_void;
+        hdr.msg_namelen = addrs[i].len();
+        hdr.msg_iov = &mut iovs[i];
+        hdr.msg_iovlen = 1;
+        hdr.msg_control =
+            cmsg_buf[i * cmsg_words..].as_mut_ptr() as *mut libc::c_void;
+        hdr.msg_controllen = if pacing { cmsg_len } else { gso_space } as _;
+
+        unsafe {
+            // GSO option.
+            let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+            std::ptr::write_unaligned(
+                libc::CMSG_DATA(cmsg) as *mut u16,
+                batch.segment_size as u16,
+            );
+
+            // Pacing option.
+            if pacing {
+                let cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_TXTIME;
+                (*cmsg).cmsg_len =
+                    libc::CMSG_LEN(mem::size_of::<u64>() as u32) as _;
+                std::ptr::write_unaligned(
+                    libc::CMSG_DATA(cmsg) as *mut u64,
+                    send_time(&batch.send_info),
+                );
+            }
+        }
+
+        msgs.push(libc::mmsghdr {
+            msg_hdr: hdr,
+            msg_len: 0,
+        });
+    }
+
+    let sent = unsafe {
+        libc::sendmmsg(socket.as_raw_fd(), msgs.as_mut_ptr(), msgs.len() as _, 0)
+    };
+
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(msgs[..sent as usize]
+        .iter()
+        .map(|m| m.msg_len as usize)
+        .sum())
+}
+
+/// For non-Linux platforms.
+#[cfg(not(target_os = "linux"))]
+fn send_mmsg_gso(
+    _socket: &mio::net::UdpSocket, _batches: &[SendBatch], _pacing: bool,
+) -> io::Result<usize> {
+    panic!("send_mmsg_gso() should not be called on non-linux platforms");
+}
+
+/// A wrapper function of sendmmsg().
+/// - when GSO is enabled, send all batches using send_mmsg_gso().
+/// Otherwise, send each batch using send_to().
+pub fn send_batches(
+    socket: &mio::net::UdpSocket, batches: &[SendBatch], pacing: bool,
+    enable_gso: bool,
+) -> io::Result<usize> {
+    if enable_gso {
+        return send_mmsg_gso(socket, batches, pacing);
+    }
+
+    let mut written = 0;
+
+    for batch in batches {
+        written += send_to(
+            socket,
+            batch.buf,
+            &batch.send_info,
+            batch.segment_size,
+            pacing,
+            enable_gso,
+        )?;
+    }
+
+    Ok(written)
+}
@@ -248,6 +248,9 @@ impl From<EventType> for EventImportance {
             EventType::QpackEventType(QpackEventType::InstructionParsed) =>
                 EventImportance::Base,
 
+            EventType::GenericEventType(GenericEventType::Message) =>
+                EventImportance::Base,
+
             _ => unimplemented!(),
         }
     }
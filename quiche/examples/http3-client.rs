@@ -288,6 +288,28 @@ fn main() {
                         info!("GOAWAY id={}", goaway_id);
                     },
 
+                    Ok((
+                        stream_id,
+                        quiche::h3::Event::InterimHeaders { list },
+                    )) => {
+                        info!(
+                            "got interim response headers {:?} on stream id {}",
+                            hdrs_to_strings(&list),
+                            stream_id
+                        );
+                    },
+
+                    Ok((stream_id, quiche::h3::Event::Trailers { list })) => {
+                        info!(
+                            "got response trailers {:?} on stream id {}",
+                            hdrs_to_strings(&list),
+                            stream_id
+                        );
+                    },
+
+                    Ok((_, quiche::h3::Event::PushPromise { .. })) =>
+                        unreachable!(),
+
                     Err(quiche::h3::Error::Done) => {
                         break;
                     },
@@ -27,12 +27,12 @@
 #[macro_use]
 extern crate log;
 
-use std::net;
-
 use std::collections::HashMap;
 
 use ring::rand::*;
 
+use quiche::TokenMinter;
+
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
 struct PartialResponse {
@@ -106,6 +106,21 @@ fn main() {
     config.set_disable_active_migration(true);
     config.enable_early_data();
 
+    // Address validation tokens are encrypted with keys rotated every hour,
+    // and NEW_TOKEN tokens don't outlive the overlap window of the ring.
+    let key_ring = std::sync::Arc::new(quiche::KeyRing::new(
+        std::time::Duration::from_secs(3600),
+        std::time::Duration::from_secs(3600),
+    ));
+
+    let token_minter = std::sync::Arc::new(quiche::KeyRingTokenMinter::new(
+        key_ring,
+        std::time::Duration::from_secs(10),
+        std::time::Duration::from_secs(3600),
+    ));
+
+    config.set_token_minter(Some(token_minter.clone()));
+
     let rng = SystemRandom::new();
     let conn_id_seed =
         ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
@@ -212,19 +227,22 @@ fn main() {
                 // Token is always present in Initial packets.
                 let token = hdr.token.as_ref().unwrap();
 
-                // Do stateless retry if the client didn't send a token.
-                if token.is_empty() {
+                // Do stateless retry if the client didn't send a token, or
+                // sent a NEW_TOKEN token that is not valid anymore.
+                if token.is_empty() ||
+                    (!token_minter.is_retry_token(token) &&
+                        token_minter.validate(token, &from).is_err())
+                {
                     warn!("Doing stateless retry");
 
-                    let new_token = mint_token(&hdr, &from);
-
-                    let len = quiche::retry(
+                    let len = quiche::retry_minted(
                         &hdr.scid,
                         &hdr.dcid,
                         &scid,
-                        &new_token,
+                        &from,
                         hdr.version,
                         &mut out,
+                        &config,
                     )
                     .unwrap();
 
@@ -241,15 +259,6 @@ fn main() {
                     continue 'read;
                 }
 
-                let odcid = validate_token(&from, token);
-
-                // The token was not valid, meaning the retry failed, so
-                // drop the packet.
-                if odcid.is_none() {
-                    error!("Invalid address validation token");
-                    continue 'read;
-                }
-
                 if scid.len() != hdr.dcid.len() {
                     error!("Invalid destination connection ID");
                     continue 'read;
@@ -261,14 +270,22 @@ fn main() {
 
                 debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, scid);
 
-                let conn = quiche::accept(
+                let conn = match quiche::accept_with_token(
                     &scid,
-                    odcid.as_ref(),
+                    token,
                     local_addr,
                     from,
                     &mut config,
-                )
-                .unwrap();
+                ) {
+                    Ok(v) => v,
+
+                    // The Retry token was not valid, meaning the retry failed,
+                    // so drop the packet.
+                    Err(_) => {
+                        error!("Invalid address validation token");
+                        continue 'read;
+                    },
+                };
 
                 let client = Client {
                     conn,
@@ -387,62 +404,6 @@ fn main() {
     }
 }
 
-/// Generate a stateless retry token.
-///
-/// The token includes the static string `"quiche"` followed by the IP address
-/// of the client and by the original destination connection ID generated by the
-/// client.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn mint_token(hdr: &quiche::Header, src: &net::SocketAddr) -> Vec<u8> {
-    let mut token = Vec::new();
-
-    token.extend_from_slice(b"quiche");
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    token.extend_from_slice(&addr);
-    token.extend_from_slice(&hdr.dcid);
-
-    token
-}
-
-/// Validates a stateless retry token.
-///
-/// This checks that the ticket includes the `"quiche"` static string, and that
-/// the client IP address matches the address stored in the ticket.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn validate_token<'a>(
-    src: &net::SocketAddr, token: &'a [u8],
-) -> Option<quiche::ConnectionId<'a>> {
-    if token.len() < 6 {
-        return None;
-    }
-
-    if &token[..6] != b"quiche" {
-        return None;
-    }
-
-    let token = &token[6..];
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    if token.len() < addr.len() || &token[..addr.len()] != addr.as_slice() {
-        return None;
-    }
-
-    Some(quiche::ConnectionId::from_ref(&token[addr.len()..]))
-}
-
 /// Handles incoming HTTP/0.9 requests.
 fn handle_stream(client: &mut Client, stream_id: u64, buf: &[u8], root: &str) {
     let conn = &mut client.conn;
@@ -467,8 +428,23 @@ fn handle_stream(client: &mut Client, stream_id: u64, buf: &[u8], root: &str) {
             stream_id
         );
 
-        let body = std::fs::read(path.as_path())
-            .unwrap_or_else(|_| b"Not Found!\r\n".to_vec());
+        // Let the connection read the file as the response is sent.
+        if let Ok(file) = std::fs::File::open(path.as_path()) {
+            info!(
+                "{} sending file {:?} on stream {}",
+                conn.trace_id(),
+                path,
+                stream_id
+            );
+
+            if let Err(e) = conn.stream_send_reader(stream_id, file) {
+                error!("{} stream send failed {:?}", conn.trace_id(), e);
+            }
+
+            return;
+        }
+
+        let body = b"Not Found!\r\n".to_vec();
 
         info!(
             "{} sending response of size {} on stream {}",
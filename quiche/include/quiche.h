@@ -58,6 +58,9 @@ extern "C" {
 // The current QUIC wire version.
 #define QUICHE_PROTOCOL_VERSION 0x00000001
 
+// QUIC version 2 (RFC 9369).
+#define QUICHE_PROTOCOL_VERSION_V2 0x6b3343cf
+
 // The maximum length of a connection ID.
 #define QUICHE_MAX_CONN_ID_LEN 20
 
@@ -115,6 +118,9 @@ enum quiche_error {
 
     // Error in congestion control.
     QUICHE_ERR_CONGESTION_CONTROL = -14,
+
+    // The handshake did not complete within the configured timeout.
+    QUICHE_ERR_HANDSHAKE_TIMEOUT = -22,
 };
 
 // Returns a human readable string with the quiche version number.
@@ -149,9 +155,15 @@ int quiche_config_load_verify_locations_from_directory(quiche_config *config,
 // Configures whether to verify the peer's certificate.
 void quiche_config_verify_peer(quiche_config *config, bool v);
 
+// Configures whether server connections require clients to present a certificate.
+void quiche_config_verify_peer_required(quiche_config *config, bool v);
+
 // Configures whether to send GREASE.
 void quiche_config_grease(quiche_config *config, bool v);
 
+// Configures whether to grease the QUIC bit, as defined by RFC 9287.
+void quiche_config_grease_quic_bit(quiche_config *config, bool v);
+
 // Enables logging of secrets.
 void quiche_config_log_keys(quiche_config *config);
 
@@ -167,6 +179,11 @@ int quiche_config_set_application_protos(quiche_config *config,
 // no timeout.
 void quiche_config_set_max_idle_timeout(quiche_config *config, uint64_t v);
 
+// Sets the maximum duration of the handshake, in milliseconds, after which the
+// connection is closed with QUICHE_ERR_HANDSHAKE_TIMEOUT. Default is no limit
+// other than the idle timeout.
+void quiche_config_set_handshake_timeout(quiche_config *config, uint64_t v);
+
 // Sets the `max_udp_payload_size transport` parameter.
 void quiche_config_set_max_recv_udp_payload_size(quiche_config *config, size_t v);
 
@@ -209,6 +226,27 @@ enum quiche_cc_algorithm {
 // Sets the congestion control algorithm used.
 void quiche_config_set_cc_algorithm(quiche_config *config, enum quiche_cc_algorithm algo);
 
+// Configures whether to enable the multipath extension.
+void quiche_config_set_multipath(quiche_config *config, bool v);
+
+enum quiche_path_scheduler_algorithm {
+    QUICHE_PATH_SCHEDULER_LOWEST_RTT = 0,
+    QUICHE_PATH_SCHEDULER_ROUND_ROBIN = 1,
+    QUICHE_PATH_SCHEDULER_REDUNDANT = 2,
+};
+
+// Sets the multipath path scheduler used by name.
+int quiche_config_set_path_scheduler_name(quiche_config *config, const char *name);
+
+// Sets the multipath path scheduler used.
+void quiche_config_set_path_scheduler(quiche_config *config,
+                                      enum quiche_path_scheduler_algorithm algo);
+
+// Adds a plugin to be inserted into the connections created with the given
+// configuration, loaded from the WebAssembly file at |path|. An error is
+// returned if the file can't be read or isn't a WebAssembly module.
+int quiche_config_load_plugin(quiche_config *config, const char *path);
+
 // Configures whether to use HyStart++.
 void quiche_config_enable_hystart(quiche_config *config, bool v);
 
@@ -226,6 +264,9 @@ void quiche_config_set_max_connection_window(quiche_config *config, uint64_t v);
 // Sets the maximum stream window.
 void quiche_config_set_max_stream_window(quiche_config *config, uint64_t v);
 
+// Sets the maximum amount of data buffered for sending on each stream.
+void quiche_config_set_max_stream_send_buffer(quiche_config *config, uint64_t v);
+
 // Sets the limit of active connection IDs.
 void quiche_config_set_active_connection_id_limit(quiche_config *config, uint64_t v);
 
@@ -330,6 +371,14 @@ typedef struct {
 ssize_t quiche_conn_send(quiche_conn *conn, uint8_t *out, size_t out_len,
                          quiche_send_info *out_info);
 
+// Writes a single QUIC packet to be sent to the peer from the specified
+// local address |from| to the destination address |to|. Either address can
+// be NULL, in which case any path is used.
+ssize_t quiche_conn_send_on_path(quiche_conn *conn, uint8_t *out, size_t out_len,
+                                 const struct sockaddr *from, socklen_t from_len,
+                                 const struct sockaddr *to, socklen_t to_len,
+                                 quiche_send_info *out_info);
+
 // Returns the size of the send quantum, in bytes.
 size_t quiche_conn_send_quantum(const quiche_conn *conn);
 
@@ -341,6 +390,9 @@ ssize_t quiche_conn_stream_recv(quiche_conn *conn, uint64_t stream_id,
 ssize_t quiche_conn_stream_send(quiche_conn *conn, uint64_t stream_id,
                                 const uint8_t *buf, size_t buf_len, bool fin);
 
+// Finishes the sending side of a stream, without writing more data.
+int quiche_conn_stream_finish(quiche_conn *conn, uint64_t stream_id);
+
 // The side of the stream to be shut down.
 enum quiche_shutdown {
     QUICHE_SHUTDOWN_READ = 0,
@@ -365,6 +417,11 @@ bool quiche_conn_stream_readable(const quiche_conn *conn, uint64_t stream_id);
 // available.
 int64_t quiche_conn_stream_readable_next(quiche_conn *conn);
 
+// Returns true if the stream has enough data to read, and sets the amount of
+// data needed for it to be reported as readable.
+int quiche_conn_stream_readable_threshold(quiche_conn *conn, uint64_t stream_id,
+                                          size_t len);
+
 // Returns true if the stream has enough send capacity.
 //
 // On error a value lower than 0 is returned.
@@ -397,10 +454,49 @@ uint64_t quiche_conn_timeout_as_millis(const quiche_conn *conn);
 // Processes a timeout event.
 void quiche_conn_on_timeout(quiche_conn *conn);
 
+enum quiche_conn_event_type {
+    // The handshake was completed.
+    QUICHE_CONN_EVENT_ESTABLISHED = 0,
+
+    // The stream has data to read.
+    QUICHE_CONN_EVENT_STREAM_READABLE = 1,
+
+    // The stream has capacity to send data.
+    QUICHE_CONN_EVENT_STREAM_WRITABLE = 2,
+
+    // A DATAGRAM was received.
+    QUICHE_CONN_EVENT_DGRAM_RECEIVED = 3,
+
+    // The connection was closed.
+    QUICHE_CONN_EVENT_CLOSED = 4,
+};
+
+// Registers a callback notified of the connection's events after each call
+// to quiche_conn_recv() and quiche_conn_on_timeout(), instead of iterating
+// over readable and writable streams. The |stream_id| argument is only
+// meaningful for stream events. Stream and DATAGRAM events are notified as
+// long as the condition holds, while the ESTABLISHED and CLOSED events are
+// only notified once. Passing NULL unregisters the current callback.
+void quiche_conn_set_event_cb(quiche_conn *conn,
+                              void (*cb)(quiche_conn *conn,
+                                         enum quiche_conn_event_type ev,
+                                         uint64_t stream_id,
+                                         void *argp),
+                              void *argp);
+
+// Notifies the callback registered with quiche_conn_set_event_cb() of the
+// connection's events right away, e.g. after sending stream data.
+void quiche_conn_dispatch_events(quiche_conn *conn);
+
 // Closes the connection with the given error and reason.
 int quiche_conn_close(quiche_conn *conn, bool app, uint64_t err,
                       const uint8_t *reason, size_t reason_len);
 
+// Closes the connection with the given transport error, frame type and reason.
+int quiche_conn_close_with_frame_type(quiche_conn *conn, uint64_t err,
+                                      uint64_t frame_type,
+                                      const uint8_t *reason, size_t reason_len);
+
 // Returns a string uniquely representing the connection.
 void quiche_conn_trace_id(const quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
@@ -449,17 +545,25 @@ bool quiche_conn_is_timed_out(const quiche_conn *conn);
 
 // Returns true if a connection error was received, and updates the provided
 // parameters accordingly.
+//
+// The frame type that triggered the error is only carried by transport errors,
+// and is 0 when it is unknown, or for application errors.
 bool quiche_conn_peer_error(const quiche_conn *conn,
                             bool *is_app,
                             uint64_t *error_code,
+                            uint64_t *frame_type,
                             const uint8_t **reason,
                             size_t *reason_len);
 
 // Returns true if a connection error was queued or sent, and updates the provided
 // parameters accordingly.
+//
+// The frame type that triggered the error is only carried by transport errors,
+// and is 0 when it is unknown, or for application errors.
 bool quiche_conn_local_error(const quiche_conn *conn,
                              bool *is_app,
                              uint64_t *error_code,
+                             uint64_t *frame_type,
                              const uint8_t **reason,
                              size_t *reason_len);
 
@@ -584,9 +688,39 @@ typedef struct {
     // The estimated round-trip time of the path (in nanoseconds).
     uint64_t rtt;
 
+    // The most recent round-trip time sample of the path (in nanoseconds).
+    uint64_t latest_rtt;
+
+    // The round-trip time variation of the path (in nanoseconds).
+    uint64_t rttvar;
+
+    // The minimum round-trip time observed on the path (in nanoseconds).
+    uint64_t min_rtt;
+
+    // The number of times the minimum round-trip time was refreshed.
+    uint64_t min_rtt_refreshes;
+
     // The size of the path's congestion window in bytes.
     size_t cwnd;
 
+    // The number of bytes in flight on this path.
+    size_t bytes_in_flight;
+
+    // The number of times sending became limited by the congestion window.
+    uint64_t cwnd_limited;
+
+    // The number of consecutive probe timeouts on this path.
+    uint32_t pto_count;
+
+    // The current pacing rate in bytes/s.
+    uint64_t pacing_rate;
+
+    // The number of persistent congestion episodes on this path.
+    uint64_t persistent_congestion;
+
+    // The number of bursts of consecutive packets lost on this path.
+    uint64_t loss_bursts;
+
     // The number of sent bytes on this path.
     uint64_t sent_bytes;
 
@@ -604,6 +738,16 @@ typedef struct {
 
     // The most recent data delivery rate estimate in bytes/s.
     uint64_t delivery_rate;
+
+    // Whether the delivery rate sample was taken while application-limited.
+    bool delivery_rate_app_limited;
+
+    // The interval of the delivery rate sample (in nanoseconds).
+    uint64_t delivery_rate_interval;
+
+    // The number of bytes that can still be sent before the peer's address is
+    // validated, or QUICHE_ERR_DONE if the limit doesn't apply.
+    ssize_t anti_amplification_allowance;
 } quiche_path_stats;
 
 
@@ -651,6 +795,165 @@ ssize_t quiche_conn_send_ack_eliciting_on_path(quiche_conn *conn,
                            const struct sockaddr *local, size_t local_len,
                            const struct sockaddr *peer, size_t peer_len);
 
+// Requests the stack to perform path validation of the proposed 4-tuple.
+// On success, |seq| holds the sequence number of the Destination Connection
+// ID used on that path.
+int quiche_conn_probe_path(quiche_conn *conn,
+                           const struct sockaddr *local, socklen_t local_len,
+                           const struct sockaddr *peer, socklen_t peer_len,
+                           uint64_t *seq);
+
+// Migrates the connection to a new local address |local|.
+int quiche_conn_migrate_source(quiche_conn *conn,
+                               const struct sockaddr *local, socklen_t local_len,
+                               uint64_t *seq);
+
+// Migrates the connection over the given network path.
+int quiche_conn_migrate(quiche_conn *conn,
+                        const struct sockaddr *local, socklen_t local_len,
+                        const struct sockaddr *peer, socklen_t peer_len,
+                        uint64_t *seq);
+
+// Returns 1 if the given network path has been validated, 0 if not, or a
+// negative error code if the path does not exist.
+int quiche_conn_is_path_validated(const quiche_conn *conn,
+                                  const struct sockaddr *local, socklen_t local_len,
+                                  const struct sockaddr *peer, socklen_t peer_len);
+
+// An iterator over socket addresses.
+typedef struct quiche_socket_addr_iter quiche_socket_addr_iter;
+
+// Returns an iterator over the peer addresses of the paths that can be used
+// to send packets from the local address |from|.
+quiche_socket_addr_iter *quiche_conn_paths_iter(const quiche_conn *conn,
+                                                const struct sockaddr *from,
+                                                socklen_t from_len);
+
+// Fetches the next peer address from the given iterator. Returns false if
+// there are no more elements in the iterator.
+bool quiche_socket_addr_iter_next(quiche_socket_addr_iter *iter,
+                                  struct sockaddr_storage *peer,
+                                  socklen_t *peer_len);
+
+// Frees the given socket address iterator object.
+void quiche_socket_addr_iter_free(quiche_socket_addr_iter *iter);
+
+enum quiche_path_event_type {
+    QUICHE_PATH_EVENT_NEW,
+    QUICHE_PATH_EVENT_VALIDATED,
+    QUICHE_PATH_EVENT_FAILED_VALIDATION,
+    QUICHE_PATH_EVENT_CLOSED,
+    QUICHE_PATH_EVENT_REUSED_SOURCE_CONNECTION_ID,
+    QUICHE_PATH_EVENT_PEER_MIGRATED,
+};
+
+typedef struct quiche_path_event quiche_path_event;
+
+// Retrieves the next event. Returns NULL if there is no event to process.
+quiche_path_event *quiche_conn_path_event_next(quiche_conn *conn);
+
+// Returns the type of the event.
+enum quiche_path_event_type quiche_path_event_type(quiche_path_event *ev);
+
+// Returns the local and peer addresses of the path the event relates to. For
+// QUICHE_PATH_EVENT_REUSED_SOURCE_CONNECTION_ID, this is the new path.
+void quiche_path_event_addrs(quiche_path_event *ev,
+                             struct sockaddr_storage *local, socklen_t *local_len,
+                             struct sockaddr_storage *peer, socklen_t *peer_len);
+
+// Should only be called on an event of type
+// QUICHE_PATH_EVENT_REUSED_SOURCE_CONNECTION_ID, and returns the sequence
+// number of the reused Source Connection ID and the path it was used on.
+void quiche_path_event_reused_source_connection_id(quiche_path_event *ev,
+                                                   uint64_t *cid_seq,
+                                                   struct sockaddr_storage *old_local,
+                                                   socklen_t *old_local_len,
+                                                   struct sockaddr_storage *old_peer,
+                                                   socklen_t *old_peer_len);
+
+// Frees the path event object.
+void quiche_path_event_free(quiche_path_event *ev);
+
+// Provides a new Source Connection ID with its stateless reset token to the
+// peer. |reset_token| must contain 16 bytes.
+int quiche_conn_new_scid(quiche_conn *conn,
+                         const uint8_t *scid, size_t scid_len,
+                         const uint8_t *reset_token, bool retire_if_needed,
+                         uint64_t *seq);
+
+// Requests the retirement of the Destination Connection ID used by the host
+// to reach its peer.
+int quiche_conn_retire_dcid(quiche_conn *conn, uint64_t dcid_seq);
+
+// Returns the number of Source Connection IDs that can still be provided.
+size_t quiche_conn_source_cids_left(const quiche_conn *conn);
+
+// Returns the number of spare Destination Connection IDs.
+size_t quiche_conn_available_dcids(const quiche_conn *conn);
+
+// Returns true if the multipath extension has been negotiated.
+bool quiche_conn_is_multipath(const quiche_conn *conn);
+
+// Opens an additional path between |local| and |peer|, once the multipath
+// extension has been negotiated.
+int quiche_conn_create_path(quiche_conn *conn,
+                            const struct sockaddr *local, socklen_t local_len,
+                            const struct sockaddr *peer, socklen_t peer_len,
+                            uint64_t *seq);
+
+enum quiche_path_status {
+    QUICHE_PATH_STATUS_STANDBY = 0,
+    QUICHE_PATH_STATUS_AVAILABLE = 1,
+};
+
+// Advertises the status of the given path to the peer.
+int quiche_conn_set_path_status(quiche_conn *conn,
+                                const struct sockaddr *local, socklen_t local_len,
+                                const struct sockaddr *peer, socklen_t peer_len,
+                                enum quiche_path_status status);
+
+// Abandons the given path.
+int quiche_conn_abandon_path(quiche_conn *conn,
+                             const struct sockaddr *local, socklen_t local_len,
+                             const struct sockaddr *peer, socklen_t peer_len,
+                             uint64_t error_code,
+                             const uint8_t *reason, size_t reason_len);
+
+// Inserts the plugin loaded from the WebAssembly file at |path| into the
+// connection. The connection must have been created with a configuration
+// that has at least one plugin.
+int quiche_conn_insert_plugin(quiche_conn *conn, const char *path);
+
+// Returns true if the connection was created with plugins.
+bool quiche_conn_is_pluginized(quiche_conn *conn);
+
+typedef struct {
+    // The trace ID of the connection.
+    const uint8_t *trace_id;
+    size_t trace_id_len;
+
+    // The name of the failed operation.
+    const uint8_t *operation;
+    size_t operation_len;
+
+    // The reason of the failure.
+    const uint8_t *message;
+    size_t message_len;
+
+    // Whether the failure was a panic of the host, rather than a trap of the
+    // plugin.
+    bool panic;
+} quiche_plugin_failure;
+
+// Registers a callback receiving the failed plugin operations of all
+// connections. Passing NULL unregisters the current callback. The callback
+// and |argp| are replaced together, but failures reported concurrently by
+// other threads might still be passed to the previous callback. The fields of
+// the failure are only valid during the callback.
+void quiche_set_plugin_failure_cb(void (*cb)(const quiche_plugin_failure *failure,
+                                             void *argp),
+                                  void *argp);
+
 // Frees the connection object.
 void quiche_conn_free(quiche_conn *conn);
 
@@ -799,6 +1102,9 @@ void quiche_h3_config_set_qpack_blocked_streams(quiche_h3_config *config, uint64
 // Sets the `SETTINGS_ENABLE_CONNECT_PROTOCOL` setting.
 void quiche_h3_config_enable_extended_connect(quiche_h3_config *config, bool enabled);
 
+// Sets the maximum push ID a client allows the server to use.
+void quiche_h3_config_set_max_push_id(quiche_h3_config *config, uint64_t v);
+
 // Frees the HTTP/3 config object.
 void quiche_h3_config_free(quiche_h3_config *config);
 
@@ -821,6 +1127,9 @@ enum quiche_h3_event_type {
     QUICHE_H3_EVENT_GOAWAY,
     QUICHE_H3_EVENT_RESET,
     QUICHE_H3_EVENT_PRIORITY_UPDATE,
+    QUICHE_H3_EVENT_PUSH_PROMISE,
+    QUICHE_H3_EVENT_INTERIM_HEADERS,
+    QUICHE_H3_EVENT_TRAILERS,
 };
 
 typedef struct quiche_h3_event quiche_h3_event;
@@ -854,9 +1163,23 @@ int quiche_h3_for_each_setting(quiche_h3_conn *conn,
                                          uint64_t value, void *argp),
                                void *argp);
 
+// Returns the value of the peer's HTTP/3 setting with the given identifier
+// in |value|. Returns QUICHE_H3_ERR_DONE if the setting was not received.
+int quiche_h3_peer_setting(quiche_h3_conn *conn, uint64_t identifier,
+                           uint64_t *value);
+
 // Check whether data will follow the headers on the stream.
 bool quiche_h3_event_headers_has_body(quiche_h3_event *ev);
 
+// Returns the push ID of a PUSH_PROMISE event.
+uint64_t quiche_h3_event_push_promise_id(quiche_h3_event *ev);
+
+// Returns the error code of a RESET event.
+uint64_t quiche_h3_event_reset_error_code(quiche_h3_event *ev);
+
+// Check whether or not server push is enabled by the peer.
+bool quiche_h3_push_enabled_by_peer(quiche_h3_conn *conn);
+
 // Check whether or not extended connection is enabled by the peer
 bool quiche_h3_extended_connect_enabled_by_peer(quiche_h3_conn *conn);
 
@@ -893,11 +1216,42 @@ int quiche_h3_send_response_with_priority(quiche_h3_conn *conn,
                             quiche_h3_header *headers, size_t headers_len,
                             quiche_h3_priority *priority, bool fin);
 
+// Sends an HTTP/3 informational (1xx) response on the specified stream.
+int quiche_h3_send_interim_response(quiche_h3_conn *conn,
+                                    quiche_conn *quic_conn, uint64_t stream_id,
+                                    quiche_h3_header *headers,
+                                    size_t headers_len);
+
+// Sends an HTTP/3 trailer section on the specified stream, terminating it.
+int quiche_h3_send_trailers(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                            uint64_t stream_id, quiche_h3_header *headers,
+                            size_t headers_len);
+
+// Promises a server push associated with the specified request stream.
+int64_t quiche_h3_push(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                       uint64_t stream_id, quiche_h3_header *headers,
+                       size_t headers_len);
+
+// Returns the push ID of the specified push stream, if known.
+bool quiche_h3_push_id(quiche_h3_conn *conn, uint64_t stream_id,
+                       uint64_t *push_id);
+
 // Sends an HTTP/3 body chunk on the given stream.
 ssize_t quiche_h3_send_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t stream_id, uint8_t *body, size_t body_len,
                             bool fin);
 
+// Returns how many bytes of a body chunk of |len| bytes can currently be sent
+// on the given stream.
+ssize_t quiche_h3_send_body_capacity(const quiche_h3_conn *conn,
+                                     const quiche_conn *quic_conn,
+                                     uint64_t stream_id, size_t len);
+
+// Returns 1 if a body chunk of |len| bytes can be sent in full on the given
+// stream, 0 otherwise. On error a value lower than 0 is returned.
+int quiche_h3_send_body_readiness(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                                  uint64_t stream_id, size_t len);
+
 // Reads request or response body data into the provided buffer.
 ssize_t quiche_h3_recv_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t stream_id, uint8_t *out, size_t out_len);
@@ -914,6 +1268,17 @@ int quiche_h3_send_priority_update_for_request(quiche_h3_conn *conn,
                                                uint64_t stream_id,
                                                quiche_h3_priority *priority);
 
+// Changes the priority of the response sent on the specified stream.
+int quiche_h3_reprioritize(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                           uint64_t stream_id, quiche_h3_priority *priority);
+
+// Applies the last received PRIORITY_UPDATE frame for a request stream to its
+// response. On success, the new priority is written in |applied|.
+int quiche_h3_apply_priority_update(quiche_h3_conn *conn,
+                                    quiche_conn *quic_conn,
+                                    uint64_t stream_id,
+                                    quiche_h3_priority *applied);
+
 // Take the last received PRIORITY_UPDATE frame for a stream.
 //
 // The `cb` callback will be called once. `cb` should check the validity of
@@ -930,6 +1295,12 @@ int quiche_h3_take_last_priority_update(quiche_h3_conn *conn,
 bool quiche_h3_dgram_enabled_by_peer(quiche_h3_conn *conn,
                                      quiche_conn *quic_conn);
 
+// Returns the maximum HTTP/3 DATAGRAM payload that can be sent with the
+// given flow ID.
+ssize_t quiche_h3_dgram_max_writable_len(quiche_h3_conn *conn,
+                                         quiche_conn *quic_conn,
+                                         uint64_t flow_id);
+
 // Writes data to the DATAGRAM send queue.
 ssize_t quiche_h3_send_dgram(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t flow_id, uint8_t *data, size_t data_len);
@@ -939,6 +1310,18 @@ ssize_t quiche_h3_recv_dgram(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t *flow_id, size_t *flow_id_len,
                             uint8_t *out, size_t out_len);
 
+// Sends a GOAWAY frame to initiate graceful connection closure.
+int quiche_h3_send_goaway(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                          uint64_t id);
+
+// Sends a GOAWAY frame allowing the requests (or pushes) processed so far to
+// complete, and rejecting any later one. Returns the ID sent in the frame.
+int64_t quiche_h3_goaway(quiche_h3_conn *conn, quiche_conn *quic_conn);
+
+// Sends a MAX_PUSH_ID frame to allow the server to push more responses.
+int quiche_h3_send_max_push_id(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                               uint64_t push_id);
+
 // Frees the HTTP/3 connection object.
 void quiche_h3_conn_free(quiche_h3_conn *conn);
 
@@ -0,0 +1,367 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::cmp;
+use std::time;
+
+/// The `min_ack_delay` transport parameter advertised when the ACK frequency
+/// extension is enabled, in microseconds.
+pub const DEFAULT_MIN_ACK_DELAY: u64 = 1_000;
+
+// The number of ACKs the default policy asks the peer to send per congestion
+// window.
+const ACKS_PER_CWND: usize = 4;
+
+// The largest Ack-Eliciting Threshold requested by the default policy.
+const MAX_ACK_ELICITING_THRESHOLD: u64 = 10;
+
+/// The ACK rate requested with an ACK_FREQUENCY frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AckFrequencyParams {
+    /// The number of ack-eliciting packets that can be received before an
+    /// ACK must be sent.
+    pub ack_eliciting_threshold: u64,
+
+    /// The maximum time an ACK can be delayed.
+    pub max_ack_delay: time::Duration,
+
+    /// The packet reordering that triggers an immediate ACK, or `0` to never
+    /// send an immediate ACK on reordering.
+    pub reordering_threshold: u64,
+}
+
+impl AckFrequencyParams {
+    /// Returns the parameters requested by the default policy.
+    ///
+    /// The peer is asked to send about `ACKS_PER_CWND` ACKs per congestion
+    /// window, so that the ACK rate decreases as the window grows, without
+    /// changing the delay it advertised in its `max_ack_delay` transport
+    /// parameter.
+    pub fn from_cwnd(
+        cwnd: usize, max_datagram_size: usize, max_ack_delay: time::Duration,
+        min_ack_delay: time::Duration,
+    ) -> Self {
+        let packets = cwnd / cmp::max(max_datagram_size, 1) / ACKS_PER_CWND;
+
+        AckFrequencyParams {
+            ack_eliciting_threshold: (packets as u64)
+                .clamp(1, MAX_ACK_ELICITING_THRESHOLD),
+
+            max_ack_delay: cmp::max(max_ack_delay, min_ack_delay),
+
+            reordering_threshold: 1,
+        }
+    }
+}
+
+/// Keeps track of the ACK rate requested by the local endpoint.
+#[derive(Default)]
+pub struct AckFrequencySend {
+    /// The sequence number of the next ACK_FREQUENCY frame.
+    next_seq: u64,
+
+    /// The parameters last requested from the peer.
+    requested: Option<AckFrequencyParams>,
+
+    /// Whether an ACK_FREQUENCY frame needs to be sent.
+    pending: bool,
+}
+
+impl AckFrequencySend {
+    /// Requests the given parameters from the peer, if they differ from the
+    /// ones last requested.
+    pub fn update(&mut self, params: AckFrequencyParams) {
+        if self.requested != Some(params) {
+            self.requested = Some(params);
+            self.pending = true;
+        }
+    }
+
+    /// Returns the sequence number and parameters of the ACK_FREQUENCY frame
+    /// to send, if any.
+    pub fn pop_to_send(&mut self) -> Option<(u64, AckFrequencyParams)> {
+        if !self.pending {
+            return None;
+        }
+
+        let params = self.requested?;
+
+        let seq = self.next_seq;
+
+        self.next_seq += 1;
+        self.pending = false;
+
+        Some((seq, params))
+    }
+
+    /// Handles the loss of the ACK_FREQUENCY frame with the given sequence
+    /// number.
+    ///
+    /// Only the most recent frame is sent again, as the peer ignores older
+    /// ones.
+    pub fn on_lost(&mut self, seq: u64) {
+        if seq + 1 == self.next_seq {
+            self.pending = true;
+        }
+    }
+
+    /// Returns whether an ACK_FREQUENCY frame needs to be sent.
+    ///
+    /// This is the case until parameters were first requested from the peer.
+    pub fn has_pending(&self) -> bool {
+        self.pending || self.requested.is_none()
+    }
+
+    /// Returns the parameters last requested from the peer.
+    pub fn requested(&self) -> Option<AckFrequencyParams> {
+        self.requested
+    }
+}
+
+/// Keeps track of the ACK rate requested by the peer.
+pub struct AckFrequencyRecv {
+    /// The largest ACK_FREQUENCY sequence number received.
+    largest_seq: Option<u64>,
+
+    /// The parameters requested by the peer.
+    params: AckFrequencyParams,
+
+    /// The number of ack-eliciting packets received since the last ACK.
+    unacked: u64,
+
+    /// When the pending ACK must be sent at the latest.
+    ack_timer: Option<time::Instant>,
+
+    /// Whether an IMMEDIATE_ACK frame was received since the last ACK.
+    immediate: bool,
+}
+
+impl Default for AckFrequencyRecv {
+    fn default() -> Self {
+        AckFrequencyRecv {
+            largest_seq: None,
+
+            params: AckFrequencyParams {
+                ack_eliciting_threshold: 1,
+                max_ack_delay: time::Duration::ZERO,
+                reordering_threshold: 1,
+            },
+
+            unacked: 0,
+
+            ack_timer: None,
+
+            immediate: false,
+        }
+    }
+}
+
+impl AckFrequencyRecv {
+    /// Returns whether the peer requested an ACK rate.
+    ///
+    /// Until then, an ACK is sent for every ack-eliciting packet.
+    pub fn is_active(&self) -> bool {
+        self.largest_seq.is_some()
+    }
+
+    /// Handles an ACK_FREQUENCY frame. Frames older than the last one
+    /// received are ignored.
+    pub fn on_ack_frequency(&mut self, seq: u64, params: AckFrequencyParams) {
+        if self.largest_seq.map_or(false, |largest| seq <= largest) {
+            return;
+        }
+
+        self.largest_seq = Some(seq);
+        self.params = params;
+    }
+
+    /// Handles an IMMEDIATE_ACK frame.
+    pub fn on_immediate_ack(&mut self) {
+        self.immediate = true;
+    }
+
+    /// Handles an ack-eliciting packet, and returns whether an ACK must be
+    /// sent right away.
+    ///
+    /// `reordering` is how far the packet number is from the next expected
+    /// one.
+    pub fn on_ack_eliciting_packet(
+        &mut self, reordering: u64, now: time::Instant,
+    ) -> bool {
+        if !self.is_active() {
+            return true;
+        }
+
+        self.unacked += 1;
+
+        let reordered = self.params.reordering_threshold > 0 &&
+            reordering >= self.params.reordering_threshold;
+
+        if self.immediate ||
+            reordered ||
+            self.unacked > self.params.ack_eliciting_threshold
+        {
+            return true;
+        }
+
+        self.ack_timer
+            .get_or_insert(now + self.params.max_ack_delay);
+
+        false
+    }
+
+    /// Returns when the pending ACK must be sent at the latest.
+    pub fn ack_timer(&self) -> Option<time::Instant> {
+        self.ack_timer
+    }
+
+    /// Returns whether the pending ACK must be sent because the ACK timer
+    /// expired.
+    pub fn on_timeout(&mut self, now: time::Instant) -> bool {
+        match self.ack_timer {
+            Some(timer) if timer <= now => {
+                self.ack_timer = None;
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Resets the state once an ACK was sent.
+    pub fn on_ack_sent(&mut self) {
+        self.unacked = 0;
+        self.ack_timer = None;
+        self.immediate = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_cwnd() {
+        let delay = time::Duration::from_millis(25);
+        let min_delay = time::Duration::from_millis(1);
+
+        let params =
+            AckFrequencyParams::from_cwnd(12_000, 1_200, delay, min_delay);
+        assert_eq!(params.ack_eliciting_threshold, 2);
+        assert_eq!(params.max_ack_delay, delay);
+
+        // The threshold grows with the congestion window, up to a limit.
+        let params =
+            AckFrequencyParams::from_cwnd(1_200_000, 1_200, delay, min_delay);
+        assert_eq!(params.ack_eliciting_threshold, 10);
+
+        let params = AckFrequencyParams::from_cwnd(1_200, 1_200, delay, delay);
+        assert_eq!(params.ack_eliciting_threshold, 1);
+    }
+
+    #[test]
+    fn send() {
+        let mut s = AckFrequencySend::default();
+        assert!(s.has_pending());
+        assert_eq!(s.pop_to_send(), None);
+
+        let params = AckFrequencyParams {
+            ack_eliciting_threshold: 4,
+            max_ack_delay: time::Duration::from_millis(25),
+            reordering_threshold: 1,
+        };
+
+        s.update(params);
+        assert_eq!(s.pop_to_send(), Some((0, params)));
+        assert_eq!(s.pop_to_send(), None);
+        assert!(!s.has_pending());
+
+        // Same parameters, nothing to send.
+        s.update(params);
+        assert_eq!(s.pop_to_send(), None);
+
+        let params = AckFrequencyParams {
+            ack_eliciting_threshold: 8,
+            ..params
+        };
+
+        s.update(params);
+        assert_eq!(s.pop_to_send(), Some((1, params)));
+
+        // Only the latest frame is retransmitted.
+        s.on_lost(0);
+        assert_eq!(s.pop_to_send(), None);
+
+        s.on_lost(1);
+        assert_eq!(s.pop_to_send(), Some((2, params)));
+    }
+
+    #[test]
+    fn recv() {
+        let mut r = AckFrequencyRecv::default();
+        let now = time::Instant::now();
+
+        // ACK every ack-eliciting packet by default.
+        assert!(!r.is_active());
+        assert!(r.on_ack_eliciting_packet(0, now));
+
+        let delay = time::Duration::from_millis(25);
+
+        r.on_ack_frequency(1, AckFrequencyParams {
+            ack_eliciting_threshold: 2,
+            max_ack_delay: delay,
+            reordering_threshold: 0,
+        });
+        assert!(r.is_active());
+
+        assert!(!r.on_ack_eliciting_packet(0, now));
+        assert!(!r.on_ack_eliciting_packet(5, now));
+        assert_eq!(r.ack_timer(), Some(now + delay));
+        assert!(r.on_ack_eliciting_packet(0, now));
+
+        r.on_ack_sent();
+        assert_eq!(r.ack_timer(), None);
+
+        // The ACK timer expires.
+        assert!(!r.on_ack_eliciting_packet(0, now));
+        assert!(!r.on_timeout(now));
+        assert!(r.on_timeout(now + delay));
+        r.on_ack_sent();
+
+        // IMMEDIATE_ACK.
+        r.on_immediate_ack();
+        assert!(r.on_ack_eliciting_packet(0, now));
+        r.on_ack_sent();
+
+        // Older frames are ignored.
+        r.on_ack_frequency(0, AckFrequencyParams {
+            ack_eliciting_threshold: 0,
+            max_ack_delay: delay,
+            reordering_threshold: 1,
+        });
+        assert!(!r.on_ack_eliciting_packet(0, now));
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::sync::Arc;
+
+use crate::Connection;
+use crate::Result;
+
+/// A payload sent to many connections.
+///
+/// The payload is copied once when the `Broadcast` is created. Writing it on
+/// the streams or in the DATAGRAM frames of any number of connections then
+/// only queues references to that single buffer, which is released once all
+/// connections sent it.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let peer = "127.0.0.1:1234".parse().unwrap();
+/// # let local = "127.0.0.1:4321".parse().unwrap();
+/// # let mut conns = vec![
+/// #     quiche::accept(&scid, None, local, peer, &mut config)?,
+/// # ];
+/// let update = quiche::Broadcast::new(b"price: 42");
+///
+/// let results = update.stream_send(conns.iter_mut().map(|c| (c, 1)), true);
+/// # Ok::<(), quiche::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Broadcast {
+    data: Arc<[u8]>,
+}
+
+impl Broadcast {
+    /// Creates a new broadcast of the given payload.
+    pub fn new(data: &[u8]) -> Broadcast {
+        Broadcast {
+            data: Arc::from(data),
+        }
+    }
+
+    /// Returns the length of the payload.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if the payload is empty.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Writes the payload on a stream of each of the given connections.
+    ///
+    /// The result of [`stream_send_shared()`] is returned for each connection,
+    /// in order. When only part of the payload was written on a connection,
+    /// the rest can be written later with [`stream_send_from()`].
+    ///
+    /// [`stream_send_shared()`]:
+    /// struct.Connection.html#method.stream_send_shared
+    /// [`stream_send_from()`]: struct.Broadcast.html#method.stream_send_from
+    pub fn stream_send<'a, I>(&self, targets: I, fin: bool) -> Vec<Result<usize>>
+    where
+        I: IntoIterator<Item = (&'a mut Connection, u64)>,
+    {
+        targets
+            .into_iter()
+            .map(|(conn, stream_id)| {
+                conn.stream_send_shared(stream_id, &self.data, 0, fin)
+            })
+            .collect()
+    }
+
+    /// Writes the payload starting at `start` on a stream of a connection.
+    ///
+    /// This is used to resume a partial write by [`stream_send()`].
+    ///
+    /// [`stream_send()`]: struct.Broadcast.html#method.stream_send
+    pub fn stream_send_from(
+        &self, conn: &mut Connection, stream_id: u64, start: usize, fin: bool,
+    ) -> Result<usize> {
+        conn.stream_send_shared(stream_id, &self.data, start, fin)
+    }
+
+    /// Sends the payload in a DATAGRAM frame on each of the given connections.
+    ///
+    /// The result of [`dgram_send_shared()`] is returned for each connection,
+    /// in order.
+    ///
+    /// [`dgram_send_shared()`]:
+    /// struct.Connection.html#method.dgram_send_shared
+    #[cfg(feature = "datagram")]
+    pub fn dgram_send<'a, I>(&self, conns: I) -> Vec<Result<()>>
+    where
+        I: IntoIterator<Item = &'a mut Connection>,
+    {
+        conns
+            .into_iter()
+            .map(|conn| conn.dgram_send_shared(&self.data))
+            .collect()
+    }
+}
+
+impl From<Vec<u8>> for Broadcast {
+    fn from(data: Vec<u8>) -> Broadcast {
+        Broadcast { data: data.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+    use crate::Config;
+
+    fn pipe() -> testing::Pipe {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        #[cfg(feature = "datagram")]
+        config.enable_dgram(true, 10, 10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        pipe
+    }
+
+    #[test]
+    fn stream_send() {
+        let mut pipes = [pipe(), pipe()];
+
+        let broadcast = Broadcast::new(b"hello, world");
+
+        let results = broadcast
+            .stream_send(pipes.iter_mut().map(|p| (&mut p.server, 1)), true);
+        assert_eq!(results, vec![Ok(12), Ok(12)]);
+
+        // Both connections reference the same buffer.
+        assert_eq!(Arc::strong_count(&broadcast.data), 3);
+
+        for pipe in pipes.iter_mut() {
+            assert_eq!(pipe.advance(), Ok(()));
+
+            let mut b = [0; 15];
+            assert_eq!(pipe.client.stream_recv(1, &mut b), Ok((12, true)));
+            assert_eq!(&b[..12], b"hello, world");
+        }
+
+        // The buffer is released once acknowledged by all peers.
+        assert_eq!(Arc::strong_count(&broadcast.data), 1);
+    }
+
+    #[test]
+    fn stream_send_partial() {
+        let mut pipe = pipe();
+
+        let broadcast = Broadcast::from(b"0123456789abcdefghij".to_vec());
+
+        // The stream flow control limit is 15 bytes.
+        let results =
+            broadcast.stream_send(std::iter::once((&mut pipe.server, 1)), true);
+        assert_eq!(results, vec![Ok(15)]);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 20];
+        assert_eq!(pipe.client.stream_recv(1, &mut b), Ok((15, false)));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            broadcast.stream_send_from(&mut pipe.server, 1, 15, true),
+            Ok(5)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_recv(1, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"fghij");
+    }
+
+    #[test]
+    #[cfg(feature = "datagram")]
+    fn dgram_send() {
+        let mut pipes = [pipe(), pipe()];
+
+        let broadcast = Broadcast::new(b"hello");
+
+        let results =
+            broadcast.dgram_send(pipes.iter_mut().map(|p| &mut p.server));
+        assert_eq!(results, vec![Ok(()), Ok(())]);
+
+        for pipe in pipes.iter_mut() {
+            assert_eq!(pipe.advance(), Ok(()));
+
+            let mut b = [0; 15];
+            assert_eq!(pipe.client.dgram_recv(&mut b), Ok(5));
+            assert_eq!(&b[..5], b"hello");
+        }
+    }
+}
@@ -216,8 +216,10 @@ fn target_dir_path() -> std::path::PathBuf {
 }
 
 fn main() {
+    // BoringSSL is not needed when using rustls.
     if cfg!(feature = "boringssl-vendored") &&
-        !cfg!(feature = "boringssl-boring-crate")
+        !cfg!(feature = "boringssl-boring-crate") &&
+        !cfg!(feature = "rustls")
     {
         let bssl_dir = std::env::var("QUICHE_BSSL_PATH").unwrap_or_else(|_| {
             let mut cfg = get_boringssl_cmake_config();
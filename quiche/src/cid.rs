@@ -250,6 +250,14 @@ pub struct ConnectionIdentifiers {
 
     /// Does the host use zero-length destination Connection ID.
     zero_length_dcid: bool,
+
+    /// Whether destination Connection IDs exceeding the limit are retired
+    /// instead of raising an error.
+    retire_dcids_over_limit: bool,
+
+    /// The number of destination Connection IDs received in excess of the
+    /// limit.
+    dcids_over_limit: usize,
 }
 
 impl ConnectionIdentifiers {
@@ -323,6 +331,23 @@ impl ConnectionIdentifiers {
         }
     }
 
+    /// Sets whether destination Connection IDs provided by the peer in excess
+    /// of the limit are immediately retired. Otherwise, [`new_dcid()`] raises
+    /// an [`IdLimit`].
+    ///
+    /// [`new_dcid()`]: struct.ConnectionIdentifiers.html#method.new_dcid
+    /// [`IdLimit`]: enum.Error.html#IdLimit
+    pub fn set_retire_dcids_over_limit(&mut self, v: bool) {
+        self.retire_dcids_over_limit = v;
+    }
+
+    /// Returns the number of destination Connection IDs provided by the peer
+    /// in excess of the limit.
+    #[inline]
+    pub fn dcids_over_limit(&self) -> usize {
+        self.dcids_over_limit
+    }
+
     /// Gets the destination Connection ID associated with the provided sequence
     /// number.
     #[inline]
@@ -432,11 +457,19 @@ impl ConnectionIdentifiers {
     /// NEW_CONNECTION_ID frame) and process all its related metadata.
     ///
     /// Returns an error if the provided Connection ID or its metadata are
-    /// invalid.
+    /// invalid. If the Connection ID exceeds the limit of active destination
+    /// Connection IDs, it is retired right away if
+    /// [`set_retire_dcids_over_limit()`] was enabled, and an [`IdLimit`] is
+    /// raised otherwise. An [`IdLimit`] is always raised when too many
+    /// retired Connection IDs are still waiting to be announced to the peer.
     ///
     /// Returns a list of tuples (DCID sequence number, Path ID), containing the
     /// sequence number of retired DCIDs that were linked to their respective
     /// Path ID.
+    ///
+    /// [`set_retire_dcids_over_limit()`]:
+    /// struct.ConnectionIdentifiers.html#method.set_retire_dcids_over_limit
+    /// [`IdLimit`]: enum.Error.html#IdLimit
     pub fn new_dcid(
         &mut self, cid: ConnectionId<'static>, seq: u64, reset_token: u128,
         retire_prior_to: u64,
@@ -462,6 +495,15 @@ impl ConnectionIdentifiers {
             return Ok(retired_path_ids);
         }
 
+        // An endpoint SHOULD limit the number of connection IDs it has retired
+        // locally for which RETIRE_CONNECTION_ID frames have not yet been
+        // acknowledged, and treat an excess as a connection error of type
+        // CONNECTION_ID_LIMIT_ERROR. Otherwise a peer could make the queue of
+        // pending retirements grow without bound.
+        if self.retire_dcid_seqs.len() >= 2 * self.dcids.capacity {
+            return Err(Error::IdLimit);
+        }
+
         // The value in the Retire Prior To field MUST be less than or equal to
         // the value in the Sequence Number field. Receiving a value in the
         // Retire Prior To field that is greater than that in the Sequence
@@ -502,9 +544,9 @@ impl ConnectionIdentifiers {
         // the value advertised in its active_connection_id_limit transport
         // parameter, an endpoint MUST close the connection with an error of type
         // CONNECTION_ID_LIMIT_ERROR.
-        if retire_prior_to > self.largest_peer_retire_prior_to {
+        let res = if retire_prior_to > self.largest_peer_retire_prior_to {
             let retired = &mut self.retire_dcid_seqs;
-            self.dcids.remove_lower_than_and_insert(
+            let res = self.dcids.remove_lower_than_and_insert(
                 retire_prior_to,
                 new_entry,
                 |e| {
@@ -514,10 +556,26 @@ impl ConnectionIdentifiers {
                         retired_path_ids.push((e.seq, pid));
                     }
                 },
-            )?;
+            );
             self.largest_peer_retire_prior_to = retire_prior_to;
+            res
         } else {
-            self.dcids.insert(new_entry)?;
+            self.dcids.insert(new_entry)
+        };
+
+        match res {
+            Err(Error::IdLimit) => {
+                self.dcids_over_limit += 1;
+
+                if !self.retire_dcids_over_limit {
+                    return Err(Error::IdLimit);
+                }
+
+                // The new identifier doesn't fit, retire it right away.
+                self.retire_dcid_seqs.push_back(seq);
+            },
+
+            res => res?,
         }
 
         Ok(retired_path_ids)
@@ -860,6 +918,43 @@ mod tests {
         assert_eq!(ids.next_advertise_new_scid_seq(), None);
     }
 
+    #[test]
+    fn new_dcid_over_limit() {
+        let (scid, _) = create_cid_and_reset_token(16);
+        let (dcid, _) = create_cid_and_reset_token(16);
+
+        let mut ids = ConnectionIdentifiers::new(2, &scid, 0, None);
+        ids.set_initial_dcid(dcid, None, Some(0));
+
+        let (dcid2, rt2) = create_cid_and_reset_token(16);
+        assert_eq!(ids.new_dcid(dcid2, 1, rt2, 0), Ok(vec![]));
+        assert_eq!(ids.dcids_over_limit(), 0);
+
+        // The peer exceeds the limit of 2 active Connection IDs.
+        let (dcid3, rt3) = create_cid_and_reset_token(16);
+        assert_eq!(ids.new_dcid(dcid3.clone(), 2, rt3, 0), Err(Error::IdLimit));
+        assert_eq!(ids.dcids_over_limit(), 1);
+        assert_eq!(ids.has_retire_dcids(), false);
+
+        // When allowed, the extra Connection ID is retired instead.
+        ids.set_retire_dcids_over_limit(true);
+
+        assert_eq!(ids.new_dcid(dcid3, 2, rt3, 0), Ok(vec![]));
+        assert_eq!(ids.dcids_over_limit(), 2);
+        assert_eq!(ids.dcids.len(), 2);
+        assert_eq!(ids.next_retire_dcid_seq(), Some(2));
+
+        // Too many retirements are pending.
+        for seq in 3..6 {
+            let (dcid, rt) = create_cid_and_reset_token(16);
+            assert_eq!(ids.new_dcid(dcid, seq, rt, 0), Ok(vec![]));
+        }
+
+        let (dcid6, rt6) = create_cid_and_reset_token(16);
+        assert_eq!(ids.new_dcid(dcid6, 6, rt6, 0), Err(Error::IdLimit));
+        assert_eq!(ids.dcids_over_limit(), 5);
+    }
+
     #[test]
     fn new_dcid_event() {
         let (scid, _) = create_cid_and_reset_token(16);
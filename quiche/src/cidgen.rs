@@ -0,0 +1,477 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+#[cfg(not(feature = "rustls"))]
+use libc::c_int;
+#[cfg(not(feature = "rustls"))]
+use libc::c_uint;
+
+use crate::rand;
+use crate::ConnectionId;
+use crate::Error;
+use crate::Result;
+
+/// The highest config rotation codepoint. The next one marks unroutable
+/// Connection IDs.
+const MAX_CONFIG_ID: u8 = 6;
+
+const MIN_SERVER_ID_LEN: usize = 1;
+
+const MIN_NONCE_LEN: usize = 4;
+
+/// The longest server ID and nonce fitting in a Connection ID, after its
+/// first octet.
+const MAX_PLAINTEXT_LEN: usize = crate::MAX_CONN_ID_LEN - 1;
+
+const MAX_HALF_LEN: usize = (MAX_PLAINTEXT_LEN + 1) / 2;
+
+const AES_BLOCK_LEN: usize = 16;
+
+/// A source of Connection IDs.
+///
+/// Servers configured with a generator through
+/// [`set_connection_id_generator()`] use it to create the Source Connection
+/// ID of the connections returned by [`accept_generated()`], and the
+/// additional ones issued by [`new_generated_source_cid()`].
+///
+/// This lets the Connection IDs carry information about the server, for
+/// example to route packets to it from a load balancer, as done by
+/// [`QuicLbGenerator`].
+///
+/// [`set_connection_id_generator()`]:
+/// struct.Config.html#method.set_connection_id_generator
+/// [`accept_generated()`]: fn.accept_generated.html
+/// [`new_generated_source_cid()`]:
+/// struct.Connection.html#method.new_generated_source_cid
+/// [`QuicLbGenerator`]: struct.QuicLbGenerator.html
+pub trait ConnectionIdGenerator: Send + Sync {
+    /// Returns a new Connection ID.
+    ///
+    /// Connection IDs must not be linkable to each other by an observer, and
+    /// must be different from any previously returned one.
+    fn generate(&self) -> ConnectionId<'static>;
+}
+
+/// A [`ConnectionIdGenerator`] following the QUIC-LB specification.
+///
+/// Connection IDs encode the server ID and the config rotation codepoint
+/// shared with the load balancer, as specified in
+/// draft-ietf-quic-load-balancers. The first octet holds the config rotation
+/// codepoint in its three most significant bits, and the length of the rest
+/// of the Connection ID in the others. It is followed by the server ID and a
+/// random nonce.
+///
+/// Without a key, the server ID is written in plaintext. Otherwise the
+/// server ID and the nonce are encrypted with AES-128, in a single pass when
+/// they are 16 bytes long, and with a four-pass Feistel network otherwise.
+///
+/// [`ConnectionIdGenerator`]: trait.ConnectionIdGenerator.html
+///
+/// ## Examples:
+///
+/// ```
+/// use quiche::ConnectionIdGenerator;
+///
+/// let key = [0x8f; 16];
+/// let generator =
+///     quiche::QuicLbGenerator::new(0, &[0x31, 0x44, 0x1a], 6, Some(&key))?;
+///
+/// let cid = generator.generate();
+/// assert_eq!(cid.len(), 10);
+/// assert_eq!(generator.server_id(&cid), Some(vec![0x31, 0x44, 0x1a]));
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub struct QuicLbGenerator {
+    config_id: u8,
+
+    server_id: Vec<u8>,
+
+    nonce_len: usize,
+
+    keys: Option<Box<AesKeys>>,
+}
+
+impl QuicLbGenerator {
+    /// Creates a generator for the given config rotation codepoint, server ID
+    /// and nonce length.
+    ///
+    /// The config rotation codepoint must be at most 6, the nonce must be at
+    /// least 4 bytes long, and the server ID and the nonce must fit in 19
+    /// bytes altogether. Otherwise, [`InvalidState`] is returned.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn new(
+        config_id: u8, server_id: &[u8], nonce_len: usize, key: Option<&[u8; 16]>,
+    ) -> Result<QuicLbGenerator> {
+        if config_id > MAX_CONFIG_ID ||
+            server_id.len() < MIN_SERVER_ID_LEN ||
+            nonce_len < MIN_NONCE_LEN ||
+            server_id.len() + nonce_len > MAX_PLAINTEXT_LEN
+        {
+            return Err(Error::InvalidState);
+        }
+
+        let keys = match key {
+            Some(key) => Some(Box::new(AesKeys::new(key)?)),
+
+            None => None,
+        };
+
+        Ok(QuicLbGenerator {
+            config_id,
+            server_id: server_id.to_vec(),
+            nonce_len,
+            keys,
+        })
+    }
+
+    /// Returns the length of the generated Connection IDs.
+    pub fn cid_len(&self) -> usize {
+        1 + self.plaintext_len()
+    }
+
+    /// Decodes the server ID of a Connection ID.
+    ///
+    /// This is what a load balancer sharing the generator's configuration
+    /// does to route packets. `None` is returned if the Connection ID doesn't
+    /// have the expected length or config rotation codepoint.
+    pub fn server_id(&self, cid: &[u8]) -> Option<Vec<u8>> {
+        if cid.len() != self.cid_len() || cid[0] >> 5 != self.config_id {
+            return None;
+        }
+
+        let mut plaintext = cid[1..].to_vec();
+
+        if let Some(keys) = &self.keys {
+            decrypt(keys, &mut plaintext);
+        }
+
+        plaintext.truncate(self.server_id.len());
+
+        Some(plaintext)
+    }
+
+    fn plaintext_len(&self) -> usize {
+        self.server_id.len() + self.nonce_len
+    }
+}
+
+impl ConnectionIdGenerator for QuicLbGenerator {
+    fn generate(&self) -> ConnectionId<'static> {
+        let mut cid = vec![0; self.cid_len()];
+
+        cid[0] = (self.config_id << 5) | self.plaintext_len() as u8;
+
+        let (server_id, nonce) = cid[1..].split_at_mut(self.server_id.len());
+        server_id.copy_from_slice(&self.server_id);
+        rand::rand_bytes(nonce);
+
+        if let Some(keys) = &self.keys {
+            encrypt(keys, &mut cid[1..]);
+        }
+
+        ConnectionId::from_vec(cid)
+    }
+}
+
+/// AES-128 key schedules for both directions.
+#[cfg(not(feature = "rustls"))]
+struct AesKeys {
+    enc: AES_KEY,
+
+    dec: AES_KEY,
+}
+
+#[cfg(not(feature = "rustls"))]
+impl AesKeys {
+    fn new(key: &[u8; 16]) -> Result<AesKeys> {
+        let mut keys = AesKeys {
+            enc: AES_KEY::default(),
+            dec: AES_KEY::default(),
+        };
+
+        let rc = unsafe {
+            AES_set_encrypt_key(key.as_ptr(), 128, &mut keys.enc) |
+                AES_set_decrypt_key(key.as_ptr(), 128, &mut keys.dec)
+        };
+
+        if rc != 0 {
+            return Err(Error::CryptoFail);
+        }
+
+        Ok(keys)
+    }
+
+    fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_LEN]) {
+        let input = *block;
+
+        unsafe { AES_encrypt(input.as_ptr(), block.as_mut_ptr(), &self.enc) };
+    }
+
+    fn decrypt_block(&self, block: &mut [u8; AES_BLOCK_LEN]) {
+        let input = *block;
+
+        unsafe { AES_decrypt(input.as_ptr(), block.as_mut_ptr(), &self.dec) };
+    }
+}
+
+/// AES-128 cipher, without BoringSSL.
+#[cfg(feature = "rustls")]
+struct AesKeys(aes::Aes128);
+
+#[cfg(feature = "rustls")]
+impl AesKeys {
+    fn new(key: &[u8; 16]) -> Result<AesKeys> {
+        use aes::cipher::KeyInit;
+
+        Ok(AesKeys(aes::Aes128::new(key.into())))
+    }
+
+    fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_LEN]) {
+        use aes::cipher::BlockEncrypt;
+
+        self.0.encrypt_block(block.into());
+    }
+
+    fn decrypt_block(&self, block: &mut [u8; AES_BLOCK_LEN]) {
+        use aes::cipher::BlockDecrypt;
+
+        self.0.decrypt_block(block.into());
+    }
+}
+
+fn encrypt(keys: &AesKeys, plaintext: &mut [u8]) {
+    if plaintext.len() == AES_BLOCK_LEN {
+        let mut block = [0; AES_BLOCK_LEN];
+        block.copy_from_slice(plaintext);
+
+        keys.encrypt_block(&mut block);
+
+        plaintext.copy_from_slice(&block);
+        return;
+    }
+
+    let len = plaintext.len();
+    let (mut left, mut right) = split(plaintext);
+
+    feistel_round(keys, &left, &mut right, len, 1);
+    feistel_round(keys, &right, &mut left, len, 2);
+    feistel_round(keys, &left, &mut right, len, 3);
+    feistel_round(keys, &right, &mut left, len, 4);
+
+    merge(&left, &right, plaintext);
+}
+
+fn decrypt(keys: &AesKeys, ciphertext: &mut [u8]) {
+    if ciphertext.len() == AES_BLOCK_LEN {
+        let mut block = [0; AES_BLOCK_LEN];
+        block.copy_from_slice(ciphertext);
+
+        keys.decrypt_block(&mut block);
+
+        ciphertext.copy_from_slice(&block);
+        return;
+    }
+
+    let len = ciphertext.len();
+    let (mut left, mut right) = split(ciphertext);
+
+    feistel_round(keys, &right, &mut left, len, 4);
+    feistel_round(keys, &left, &mut right, len, 3);
+    feistel_round(keys, &right, &mut left, len, 2);
+    feistel_round(keys, &left, &mut right, len, 1);
+
+    merge(&left, &right, ciphertext);
+}
+
+/// Splits `buf` into two halves of `ceil(len / 2)` bytes. When the length is
+/// odd, the middle byte is shared: the left half holds its most significant
+/// nibble, and the right half its least significant one.
+fn split(buf: &[u8]) -> ([u8; MAX_HALF_LEN], [u8; MAX_HALF_LEN]) {
+    let len = buf.len();
+    let half_len = (len + 1) / 2;
+
+    let mut left = [0; MAX_HALF_LEN];
+    let mut right = [0; MAX_HALF_LEN];
+
+    left[..half_len].copy_from_slice(&buf[..half_len]);
+    right[..half_len].copy_from_slice(&buf[len - half_len..]);
+
+    if len % 2 == 1 {
+        left[half_len - 1] &= 0xf0;
+        right[0] &= 0x0f;
+    }
+
+    (left, right)
+}
+
+/// Writes back the halves created by `split()` into `buf`.
+fn merge(left: &[u8], right: &[u8], buf: &mut [u8]) {
+    let len = buf.len();
+    let half_len = (len + 1) / 2;
+
+    buf[len - half_len..].copy_from_slice(&right[..half_len]);
+    buf[..half_len].copy_from_slice(&left[..half_len]);
+
+    if len % 2 == 1 {
+        buf[half_len - 1] |= right[0];
+    }
+}
+
+/// XORs one half with the expansion of the other one for the given pass.
+///
+/// Odd passes update the right half with the last bytes of the encrypted
+/// expansion, and even passes the left half with its first bytes.
+fn feistel_round(
+    keys: &AesKeys, src: &[u8; MAX_HALF_LEN], dst: &mut [u8; MAX_HALF_LEN],
+    len: usize, pass: u8,
+) {
+    let half_len = (len + 1) / 2;
+
+    let mut block = [0; AES_BLOCK_LEN];
+    block[..half_len].copy_from_slice(&src[..half_len]);
+    block[AES_BLOCK_LEN - 2] = len as u8;
+    block[AES_BLOCK_LEN - 1] = pass;
+
+    keys.encrypt_block(&mut block);
+
+    let mask = if pass % 2 == 1 {
+        &block[AES_BLOCK_LEN - half_len..]
+    } else {
+        &block[..half_len]
+    };
+
+    for (b, m) in dst.iter_mut().zip(mask) {
+        *b ^= m;
+    }
+
+    // Keep the shared nibble of the middle byte out of the other half.
+    if len % 2 == 1 {
+        if pass % 2 == 1 {
+            dst[0] &= 0x0f;
+        } else {
+            dst[half_len - 1] &= 0xf0;
+        }
+    }
+}
+
+// NOTE: This structure is copied from <openssl/aes.h> in order to be able to
+// statically allocate it.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[cfg(not(feature = "rustls"))]
+struct AES_KEY {
+    rd_key: [u32; 60],
+    rounds: c_uint,
+}
+
+#[cfg(not(feature = "rustls"))]
+impl Default for AES_KEY {
+    fn default() -> Self {
+        AES_KEY {
+            rd_key: [0; 60],
+            rounds: 0,
+        }
+    }
+}
+
+#[cfg(not(feature = "rustls"))]
+extern {
+    fn AES_set_encrypt_key(
+        key: *const u8, bits: c_uint, aeskey: *mut AES_KEY,
+    ) -> c_int;
+
+    fn AES_set_decrypt_key(
+        key: *const u8, bits: c_uint, aeskey: *mut AES_KEY,
+    ) -> c_int;
+
+    fn AES_encrypt(inp: *const u8, out: *mut u8, key: *const AES_KEY);
+
+    fn AES_decrypt(inp: *const u8, out: *mut u8, key: *const AES_KEY);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plaintext() {
+        let generator =
+            QuicLbGenerator::new(2, &[0x31, 0x44, 0x1a], 5, None).unwrap();
+
+        let cid = generator.generate();
+        assert_eq!(cid.len(), 9);
+        assert_eq!(cid[0], 0x48);
+        assert_eq!(&cid[1..4], &[0x31, 0x44, 0x1a]);
+
+        assert_eq!(generator.server_id(&cid), Some(vec![0x31, 0x44, 0x1a]));
+    }
+
+    #[test]
+    fn encrypted() {
+        let key = [0xfd; 16];
+        let server_id = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+
+        // Single-pass, and four-pass with odd and even lengths.
+        for &(server_id_len, nonce_len) in &[(8, 8), (3, 6), (4, 6), (8, 11)] {
+            let server_id = &server_id[..server_id_len];
+
+            let generator =
+                QuicLbGenerator::new(0, server_id, nonce_len, Some(&key))
+                    .unwrap();
+
+            let a = generator.generate();
+            let b = generator.generate();
+
+            assert_eq!(a.len(), 1 + server_id_len + nonce_len);
+            assert_eq!(a[0] as usize, server_id_len + nonce_len);
+            assert_ne!(a, b);
+
+            assert_eq!(generator.server_id(&a).as_deref(), Some(server_id));
+            assert_eq!(generator.server_id(&b).as_deref(), Some(server_id));
+        }
+    }
+
+    #[test]
+    fn other_config() {
+        let key = [0xfd; 16];
+
+        let a = QuicLbGenerator::new(1, &[0x01, 0x02], 6, Some(&key)).unwrap();
+        let b = QuicLbGenerator::new(2, &[0x01, 0x02], 6, Some(&key)).unwrap();
+
+        let cid = a.generate();
+        assert_eq!(b.server_id(&cid), None);
+        assert_eq!(b.server_id(&cid[..5]), None);
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(QuicLbGenerator::new(7, &[0x01], 6, None).is_err());
+        assert!(QuicLbGenerator::new(0, &[], 6, None).is_err());
+        assert!(QuicLbGenerator::new(0, &[0x01], 3, None).is_err());
+        assert!(QuicLbGenerator::new(0, &[0x01; 10], 10, None).is_err());
+    }
+}
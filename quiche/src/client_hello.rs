@@ -0,0 +1,280 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::convert::TryInto;
+
+use crate::crypto;
+use crate::frame;
+use crate::packet;
+
+use crate::Error;
+use crate::Result;
+
+const CLIENT_HELLO: u8 = 1;
+
+const EXT_SERVER_NAME: u16 = 0;
+
+const EXT_ALPN: u16 = 16;
+
+const EXT_QUIC_TRANSPORT_PARAMETERS: u16 = 0x39;
+
+const EXT_QUIC_TRANSPORT_PARAMETERS_LEGACY: u16 = 0xffa5;
+
+const HOST_NAME: u8 = 0;
+
+/// The server name and application protocols offered by a client.
+///
+/// This is extracted from the TLS ClientHello message carried by a client's
+/// first Initial packet using [`peek_client_hello()`].
+///
+/// [`peek_client_hello()`]: fn.peek_client_hello.html
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientHello {
+    /// The server name requested by the client, if any.
+    pub server_name: Option<String>,
+
+    /// The application protocols offered by the client, in order of
+    /// preference.
+    pub application_protos: Vec<Vec<u8>>,
+
+    /// The QUIC versions supported by the client, as listed in its
+    /// `version_information` transport parameter, if any.
+    pub available_versions: Vec<u32>,
+}
+
+/// Parses the ClientHello carried by the Initial packet in `buf`.
+///
+/// The buffer itself is left untouched, so it can later be passed to
+/// [`recv()`] as-is.
+///
+/// [`recv()`]: struct.Connection.html#method.recv
+pub fn peek(buf: &[u8]) -> Result<ClientHello> {
+    let mut buf = buf.to_vec();
+
+    let mut b = octets::OctetsMut::with_slice(&mut buf);
+
+    let mut hdr = packet::Header::from_bytes(&mut b, crate::MAX_CONN_ID_LEN)?;
+
+    if hdr.ty != packet::Type::Initial {
+        return Err(Error::InvalidPacket);
+    }
+
+    if !crate::version_is_supported(hdr.version) {
+        return Err(Error::UnknownVersion);
+    }
+
+    let payload_len = b.get_varint()? as usize;
+
+    if payload_len > b.cap() {
+        return Err(Error::InvalidPacket);
+    }
+
+    let (aead, _) = crypto::derive_initial_key_material(
+        &crypto::default_provider(),
+        &hdr.dcid,
+        hdr.version,
+        true,
+    )?;
+
+    packet::decrypt_hdr(&mut b, &mut hdr, &aead)?;
+
+    let pn = packet::decode_pkt_num(0, hdr.pkt_num, hdr.pkt_num_len);
+
+    let mut payload =
+        packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len, payload_len, &aead)?;
+
+    // Clients may split the ClientHello over several CRYPTO frames, and send
+    // them out of order.
+    let mut frames = Vec::new();
+
+    while payload.cap() > 0 {
+        let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
+
+        if let frame::Frame::Crypto { data } = frame {
+            frames.push(data);
+        }
+    }
+
+    frames.sort_by_key(|data| data.off());
+
+    let mut msg = Vec::new();
+
+    for data in frames {
+        let off: usize = data.off().try_into().map_err(|_| Error::Done)?;
+
+        if off > msg.len() {
+            break;
+        }
+
+        let skip = msg.len() - off;
+
+        if skip < data.len() {
+            msg.extend_from_slice(&data[skip..]);
+        }
+    }
+
+    parse(&msg)
+}
+
+fn parse(msg: &[u8]) -> Result<ClientHello> {
+    let mut b = octets::Octets::with_slice(msg);
+
+    if b.cap() < 4 {
+        return Err(Error::Done);
+    }
+
+    if b.get_u8()? != CLIENT_HELLO {
+        return Err(Error::TlsFail);
+    }
+
+    let len = b.get_u24()? as usize;
+
+    // The rest of the ClientHello is in a later packet.
+    if len > b.cap() {
+        return Err(Error::Done);
+    }
+
+    let mut b = b.get_bytes(len)?;
+
+    parse_body(&mut b).map_err(|_| Error::TlsFail)
+}
+
+fn parse_body(b: &mut octets::Octets) -> Result<ClientHello> {
+    let mut hello = ClientHello::default();
+
+    // Skip legacy_version and random.
+    b.skip(2 + 32)?;
+
+    // Skip legacy_session_id, cipher_suites and legacy_compression_methods.
+    b.get_bytes_with_u8_length()?;
+    b.get_bytes_with_u16_length()?;
+    b.get_bytes_with_u8_length()?;
+
+    let mut exts = b.get_bytes_with_u16_length()?;
+
+    while exts.cap() > 0 {
+        let ty = exts.get_u16()?;
+        let mut data = exts.get_bytes_with_u16_length()?;
+
+        match ty {
+            EXT_SERVER_NAME => {
+                let mut names = data.get_bytes_with_u16_length()?;
+
+                while names.cap() > 0 {
+                    let name_ty = names.get_u8()?;
+                    let name = names.get_bytes_with_u16_length()?;
+
+                    if name_ty == HOST_NAME {
+                        let name = std::str::from_utf8(name.as_ref())
+                            .map_err(|_| Error::TlsFail)?;
+
+                        hello.server_name = Some(name.to_string());
+                    }
+                }
+            },
+
+            EXT_ALPN => {
+                let mut protos = data.get_bytes_with_u16_length()?;
+
+                while protos.cap() > 0 {
+                    let proto = protos.get_bytes_with_u8_length()?;
+
+                    hello.application_protos.push(proto.to_vec());
+                }
+            },
+
+            EXT_QUIC_TRANSPORT_PARAMETERS |
+            EXT_QUIC_TRANSPORT_PARAMETERS_LEGACY => {
+                // Invalid parameters are reported by the handshake itself.
+                if let Ok(tp) =
+                    crate::TransportParams::decode(None, data.as_ref(), true)
+                {
+                    if let Some(info) = tp.version_information {
+                        hello.available_versions = info.available_versions;
+                    }
+                }
+            },
+
+            _ => (),
+        }
+    }
+
+    Ok(hello)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    #[test]
+    fn peek_initial() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        assert_eq!(
+            peek(&buf[..len]),
+            Ok(ClientHello {
+                server_name: Some("quic.tech".to_string()),
+                application_protos: vec![b"proto1".to_vec(), b"proto2".to_vec()],
+                available_versions: crate::SUPPORTED_VERSIONS.to_vec(),
+            })
+        );
+
+        // The packet can still be processed normally.
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
+
+    #[test]
+    fn peek_truncated() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        assert_eq!(peek(&buf[..len - 1]), Err(Error::InvalidPacket));
+    }
+
+    #[test]
+    fn peek_not_initial() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        assert_eq!(peek(&buf[..len]), Err(Error::InvalidPacket));
+    }
+}
@@ -24,12 +24,18 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+#[cfg(not(feature = "rustls"))]
 use std::mem::MaybeUninit;
 
+use std::sync::Arc;
+
 use ring::aead;
 use ring::hkdf;
+use ring::hmac;
 
+#[cfg(not(feature = "rustls"))]
 use libc::c_int;
+#[cfg(not(feature = "rustls"))]
 use libc::c_void;
 
 use crate::Error;
@@ -71,6 +77,7 @@ pub enum Algorithm {
 }
 
 impl Algorithm {
+    #[cfg(not(feature = "rustls"))]
     fn get_evp_aead(self) -> *const EVP_AEAD {
         match self {
             Algorithm::AES128_GCM => unsafe { EVP_aead_aes_128_gcm() },
@@ -81,6 +88,15 @@ impl Algorithm {
         }
     }
 
+    #[cfg(feature = "rustls")]
+    fn get_ring_aead(self) -> &'static aead::Algorithm {
+        match self {
+            Algorithm::AES128_GCM => &aead::AES_128_GCM,
+            Algorithm::AES256_GCM => &aead::AES_256_GCM,
+            Algorithm::ChaCha20_Poly1305 => &aead::CHACHA20_POLY1305,
+        }
+    }
+
     fn get_ring_hp(self) -> &'static aead::quic::Algorithm {
         match self {
             Algorithm::AES128_GCM => &aead::quic::AES_128,
@@ -124,38 +140,182 @@ impl Algorithm {
             Algorithm::ChaCha20_Poly1305 => 12,
         }
     }
+
+    /// Returns the number of packets that can be protected with a single key,
+    /// as defined in RFC 9001 Section 6.6.
+    pub fn confidentiality_limit(self) -> u64 {
+        match self {
+            Algorithm::AES128_GCM => 1 << 23,
+            Algorithm::AES256_GCM => 1 << 23,
+            // The limit is larger than the number of possible packets.
+            Algorithm::ChaCha20_Poly1305 => std::u64::MAX,
+        }
+    }
+
+    /// Returns the number of received packets that can fail authentication
+    /// across all keys, as defined in RFC 9001 Section 6.6.
+    pub fn integrity_limit(self) -> u64 {
+        match self {
+            Algorithm::AES128_GCM => 1 << 52,
+            Algorithm::AES256_GCM => 1 << 52,
+            Algorithm::ChaCha20_Poly1305 => 1 << 36,
+        }
+    }
+}
+
+/// Provides the cryptographic primitives used for packet protection.
+///
+/// By default, the AEAD is implemented with BoringSSL (or ring, when using the
+/// `rustls` feature), and header protection and HKDF with ring. A custom
+/// provider can be set with [`Config::set_crypto_provider()`], for example to
+/// use hardware-offloaded or FIPS-certified implementations.
+///
+/// [`Config::set_crypto_provider()`]:
+/// struct.Config.html#method.set_crypto_provider
+pub trait CryptoProvider: Send + Sync {
+    /// Creates a packet protection context for the given AEAD and key.
+    fn new_packet_key(
+        &self, alg: Algorithm, key: &[u8],
+    ) -> Result<Box<dyn PacketKey>>;
+
+    /// Creates a header protection context for the given AEAD and key.
+    fn new_header_key(
+        &self, alg: Algorithm, key: &[u8],
+    ) -> Result<Box<dyn HeaderKey>>;
+
+    /// Performs HKDF-Extract with the hash function of the given AEAD's cipher
+    /// suite, and returns the pseudorandom key.
+    fn hkdf_extract(
+        &self, alg: Algorithm, salt: &[u8], ikm: &[u8],
+    ) -> Result<Vec<u8>>;
+
+    /// Performs HKDF-Expand with the hash function of the given AEAD's cipher
+    /// suite, filling `out` with output keying material.
+    fn hkdf_expand(
+        &self, alg: Algorithm, prk: &[u8], info: &[u8], out: &mut [u8],
+    ) -> Result<()>;
+}
+
+/// An AEAD context used to protect the payload of packets.
+pub trait PacketKey: Send + Sync {
+    /// Decrypts and authenticates `buf` in place, which ends with the
+    /// authentication tag, and returns the length of the plaintext.
+    fn open(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8]) -> Result<usize>;
+
+    /// Encrypts the first `in_len` bytes of `buf` in place, followed by
+    /// `extra_in` if any, and appends the authentication tag.
+    ///
+    /// On success the total length written to `buf` is returned.
+    fn seal(
+        &self, nonce: &[u8], ad: &[u8], buf: &mut [u8], in_len: usize,
+        extra_in: Option<&[u8]>,
+    ) -> Result<usize>;
+}
+
+/// A context used to protect the header of packets.
+pub trait HeaderKey: Send + Sync {
+    /// Returns the mask computed from the given ciphertext sample.
+    fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]>;
+}
+
+/// The default crypto provider.
+pub struct DefaultCryptoProvider;
+
+impl CryptoProvider for DefaultCryptoProvider {
+    fn new_packet_key(
+        &self, alg: Algorithm, key: &[u8],
+    ) -> Result<Box<dyn PacketKey>> {
+        Ok(Box::new(DefaultPacketKey {
+            ctx: make_aead_ctx(alg, key)?,
+
+            tag_len: alg.tag_len(),
+        }))
+    }
+
+    fn new_header_key(
+        &self, alg: Algorithm, key: &[u8],
+    ) -> Result<Box<dyn HeaderKey>> {
+        let key = aead::quic::HeaderProtectionKey::new(alg.get_ring_hp(), key)
+            .map_err(|_| Error::CryptoFail)?;
+
+        Ok(Box::new(DefaultHeaderKey(key)))
+    }
+
+    fn hkdf_extract(
+        &self, alg: Algorithm, salt: &[u8], ikm: &[u8],
+    ) -> Result<Vec<u8>> {
+        // HKDF-Extract is HMAC keyed with the salt, which ring doesn't expose
+        // as part of its HKDF API.
+        let key = hmac::Key::new(alg.get_ring_digest().hmac_algorithm(), salt);
+
+        Ok(hmac::sign(&key, ikm).as_ref().to_vec())
+    }
+
+    fn hkdf_expand(
+        &self, alg: Algorithm, prk: &[u8], info: &[u8], out: &mut [u8],
+    ) -> Result<()> {
+        let prk = hkdf::Prk::new_less_safe(alg.get_ring_digest(), prk);
+
+        prk.expand(&[info], ArbitraryOutputLen(out.len()))
+            .map_err(|_| Error::CryptoFail)?
+            .fill(out)
+            .map_err(|_| Error::CryptoFail)?;
+
+        Ok(())
+    }
+}
+
+/// Returns the provider used when none is configured.
+pub(crate) fn default_provider() -> Arc<dyn CryptoProvider> {
+    Arc::new(DefaultCryptoProvider)
 }
 
 pub struct Open {
     alg: Algorithm,
 
-    ctx: EVP_AEAD_CTX,
+    version: u32,
+
+    secret: Vec<u8>,
+
+    provider: Arc<dyn CryptoProvider>,
+
+    ctx: Box<dyn PacketKey>,
+
+    hp_key: Box<dyn HeaderKey>,
 
-    hp_key: aead::quic::HeaderProtectionKey,
+    hp_key_raw: Vec<u8>,
 
     nonce: Vec<u8>,
 }
 
 impl Open {
     pub fn new(
-        alg: Algorithm, key: &[u8], iv: &[u8], hp_key: &[u8],
+        provider: &Arc<dyn CryptoProvider>, alg: Algorithm, key: &[u8],
+        iv: &[u8], hp_key: &[u8],
     ) -> Result<Open> {
         Ok(Open {
             alg,
 
-            ctx: make_aead_ctx(alg, key)?,
+            version: crate::PROTOCOL_VERSION_V1,
+
+            secret: Vec::new(),
+
+            provider: provider.clone(),
+
+            ctx: provider.new_packet_key(alg, key)?,
 
-            hp_key: aead::quic::HeaderProtectionKey::new(
-                alg.get_ring_hp(),
-                hp_key,
-            )
-            .map_err(|_| Error::CryptoFail)?,
+            hp_key: provider.new_header_key(alg, hp_key)?,
+
+            hp_key_raw: Vec::from(hp_key),
 
             nonce: Vec::from(iv),
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8]) -> Result<Open> {
+    pub fn from_secret(
+        provider: &Arc<dyn CryptoProvider>, aead: Algorithm, version: u32,
+        secret: &[u8],
+    ) -> Result<Open> {
         let key_len = aead.key_len();
         let nonce_len = aead.nonce_len();
 
@@ -163,15 +323,51 @@ impl Open {
         let mut iv = vec![0; nonce_len];
         let mut pn_key = vec![0; key_len];
 
-        derive_pkt_key(aead, secret, &mut key)?;
-        derive_pkt_iv(aead, secret, &mut iv)?;
-        derive_hdr_key(aead, secret, &mut pn_key)?;
+        let p = provider.as_ref();
+
+        derive_pkt_key(p, aead, version, secret, &mut key)?;
+        derive_pkt_iv(p, aead, version, secret, &mut iv)?;
+        derive_hdr_key(p, aead, version, secret, &mut pn_key)?;
+
+        let mut open = Open::new(provider, aead, &key, &iv, &pn_key)?;
+        open.version = version;
+        open.secret = Vec::from(secret);
+
+        Ok(open)
+    }
+
+    /// Derives the packet protection keys of the next key phase.
+    ///
+    /// The header protection key is not updated. This fails with
+    /// `CryptoFail` if the context was not created from a secret.
+    pub fn derive_next_packet_key(&self) -> Result<Open> {
+        let p = self.provider.as_ref();
+
+        let next_secret =
+            derive_next_secret(p, self.alg, self.version, &self.secret)?;
+
+        let mut key = vec![0; self.alg.key_len()];
+        let mut iv = vec![0; self.alg.nonce_len()];
+
+        derive_pkt_key(p, self.alg, self.version, &next_secret, &mut key)?;
+        derive_pkt_iv(p, self.alg, self.version, &next_secret, &mut iv)?;
 
-        Open::new(aead, &key, &iv, &pn_key)
+        let mut open =
+            Open::new(&self.provider, self.alg, &key, &iv, &self.hp_key_raw)?;
+        open.version = self.version;
+        open.secret = next_secret;
+
+        Ok(open)
     }
 
     pub fn open_with_u64_counter(
         &self, counter: u64, ad: &[u8], buf: &mut [u8],
+    ) -> Result<usize> {
+        self.open_with_path_and_u64_counter(0, counter, ad, buf)
+    }
+
+    pub fn open_with_path_and_u64_counter(
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
     ) -> Result<usize> {
         if cfg!(feature = "fuzzing") {
             return Ok(buf.len());
@@ -179,35 +375,13 @@ impl Open {
 
         let tag_len = self.alg().tag_len();
 
-        let mut out_len = match buf.len().checked_sub(tag_len) {
-            Some(n) => n,
-            None => return Err(Error::CryptoFail),
-        };
-
-        let max_out_len = out_len;
-
-        let nonce = make_nonce(&self.nonce, counter);
-
-        let rc = unsafe {
-            EVP_AEAD_CTX_open(
-                &self.ctx,          // ctx
-                buf.as_mut_ptr(),   // out
-                &mut out_len,       // out_len
-                max_out_len,        // max_out_len
-                nonce[..].as_ptr(), // nonce
-                nonce.len(),        // nonce_len
-                buf.as_ptr(),       // inp
-                buf.len(),          // in_len
-                ad.as_ptr(),        // ad
-                ad.len(),           // ad_len
-            )
-        };
-
-        if rc != 1 {
+        if buf.len() < tag_len {
             return Err(Error::CryptoFail);
         }
 
-        Ok(out_len)
+        let nonce = make_nonce(&self.nonce, path_id, counter);
+
+        self.ctx.open(&nonce, ad, buf)
     }
 
     pub fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]> {
@@ -215,12 +389,7 @@ impl Open {
             return Ok(<[u8; 5]>::default());
         }
 
-        let mask = self
-            .hp_key
-            .new_mask(sample)
-            .map_err(|_| Error::CryptoFail)?;
-
-        Ok(mask)
+        self.hp_key.new_mask(sample)
     }
 
     pub fn alg(&self) -> Algorithm {
@@ -231,33 +400,49 @@ impl Open {
 pub struct Seal {
     alg: Algorithm,
 
-    ctx: EVP_AEAD_CTX,
+    version: u32,
+
+    secret: Vec<u8>,
+
+    provider: Arc<dyn CryptoProvider>,
+
+    ctx: Box<dyn PacketKey>,
 
-    hp_key: aead::quic::HeaderProtectionKey,
+    hp_key: Box<dyn HeaderKey>,
+
+    hp_key_raw: Vec<u8>,
 
     nonce: Vec<u8>,
 }
 
 impl Seal {
     pub fn new(
-        alg: Algorithm, key: &[u8], iv: &[u8], hp_key: &[u8],
+        provider: &Arc<dyn CryptoProvider>, alg: Algorithm, key: &[u8],
+        iv: &[u8], hp_key: &[u8],
     ) -> Result<Seal> {
         Ok(Seal {
             alg,
 
-            ctx: make_aead_ctx(alg, key)?,
+            version: crate::PROTOCOL_VERSION_V1,
+
+            secret: Vec::new(),
 
-            hp_key: aead::quic::HeaderProtectionKey::new(
-                alg.get_ring_hp(),
-                hp_key,
-            )
-            .map_err(|_| Error::CryptoFail)?,
+            provider: provider.clone(),
+
+            ctx: provider.new_packet_key(alg, key)?,
+
+            hp_key: provider.new_header_key(alg, hp_key)?,
+
+            hp_key_raw: Vec::from(hp_key),
 
             nonce: Vec::from(iv),
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8]) -> Result<Seal> {
+    pub fn from_secret(
+        provider: &Arc<dyn CryptoProvider>, aead: Algorithm, version: u32,
+        secret: &[u8],
+    ) -> Result<Seal> {
         let key_len = aead.key_len();
         let nonce_len = aead.nonce_len();
 
@@ -265,16 +450,53 @@ impl Seal {
         let mut iv = vec![0; nonce_len];
         let mut pn_key = vec![0; key_len];
 
-        derive_pkt_key(aead, secret, &mut key)?;
-        derive_pkt_iv(aead, secret, &mut iv)?;
-        derive_hdr_key(aead, secret, &mut pn_key)?;
+        let p = provider.as_ref();
+
+        derive_pkt_key(p, aead, version, secret, &mut key)?;
+        derive_pkt_iv(p, aead, version, secret, &mut iv)?;
+        derive_hdr_key(p, aead, version, secret, &mut pn_key)?;
+
+        let mut seal = Seal::new(provider, aead, &key, &iv, &pn_key)?;
+        seal.version = version;
+        seal.secret = Vec::from(secret);
+
+        Ok(seal)
+    }
+
+    /// Derives the packet protection keys of the next key phase.
+    ///
+    /// The header protection key is not updated. This fails with
+    /// `CryptoFail` if the context was not created from a secret.
+    pub fn derive_next_packet_key(&self) -> Result<Seal> {
+        let p = self.provider.as_ref();
+
+        let next_secret =
+            derive_next_secret(p, self.alg, self.version, &self.secret)?;
+
+        let mut key = vec![0; self.alg.key_len()];
+        let mut iv = vec![0; self.alg.nonce_len()];
 
-        Seal::new(aead, &key, &iv, &pn_key)
+        derive_pkt_key(p, self.alg, self.version, &next_secret, &mut key)?;
+        derive_pkt_iv(p, self.alg, self.version, &next_secret, &mut iv)?;
+
+        let mut seal =
+            Seal::new(&self.provider, self.alg, &key, &iv, &self.hp_key_raw)?;
+        seal.version = self.version;
+        seal.secret = next_secret;
+
+        Ok(seal)
     }
 
     pub fn seal_with_u64_counter(
         &self, counter: u64, ad: &[u8], buf: &mut [u8], in_len: usize,
         extra_in: Option<&[u8]>,
+    ) -> Result<usize> {
+        self.seal_with_path_and_u64_counter(0, counter, ad, buf, in_len, extra_in)
+    }
+
+    pub fn seal_with_path_and_u64_counter(
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
+        in_len: usize, extra_in: Option<&[u8]>,
     ) -> Result<usize> {
         if cfg!(feature = "fuzzing") {
             if let Some(extra) = extra_in {
@@ -287,44 +509,16 @@ impl Seal {
 
         let tag_len = self.alg().tag_len();
 
-        let mut out_tag_len = tag_len;
-
-        let (extra_in_ptr, extra_in_len) = match extra_in {
-            Some(v) => (v.as_ptr(), v.len()),
-
-            None => (std::ptr::null(), 0),
-        };
+        let extra_in_len = extra_in.map_or(0, |v| v.len());
 
         // Make sure all the outputs combined fit in the buffer.
         if in_len + tag_len + extra_in_len > buf.len() {
             return Err(Error::CryptoFail);
         }
 
-        let nonce = make_nonce(&self.nonce, counter);
-
-        let rc = unsafe {
-            EVP_AEAD_CTX_seal_scatter(
-                &self.ctx,                  // ctx
-                buf.as_mut_ptr(),           // out
-                buf[in_len..].as_mut_ptr(), // out_tag
-                &mut out_tag_len,           // out_tag_len
-                tag_len + extra_in_len,     // max_out_tag_len
-                nonce[..].as_ptr(),         // nonce
-                nonce.len(),                // nonce_len
-                buf.as_ptr(),               // inp
-                in_len,                     // in_len
-                extra_in_ptr,               // extra_in
-                extra_in_len,               // extra_in_len
-                ad.as_ptr(),                // ad
-                ad.len(),                   // ad_len
-            )
-        };
-
-        if rc != 1 {
-            return Err(Error::CryptoFail);
-        }
+        let nonce = make_nonce(&self.nonce, path_id, counter);
 
-        Ok(in_len + out_tag_len)
+        self.ctx.seal(&nonce, ad, buf, in_len, extra_in)
     }
 
     pub fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]> {
@@ -332,12 +526,7 @@ impl Seal {
             return Ok(<[u8; 5]>::default());
         }
 
-        let mask = self
-            .hp_key
-            .new_mask(sample)
-            .map_err(|_| Error::CryptoFail)?;
-
-        Ok(mask)
+        self.hp_key.new_mask(sample)
     }
 
     pub fn alg(&self) -> Algorithm {
@@ -346,7 +535,7 @@ impl Seal {
 }
 
 pub fn derive_initial_key_material(
-    cid: &[u8], version: u32, is_server: bool,
+    provider: &Arc<dyn CryptoProvider>, cid: &[u8], version: u32, is_server: bool,
 ) -> Result<(Open, Seal)> {
     let mut secret = [0; 32];
 
@@ -355,49 +544,58 @@ pub fn derive_initial_key_material(
     let key_len = aead.key_len();
     let nonce_len = aead.nonce_len();
 
-    let initial_secret = derive_initial_secret(cid, version);
+    let p = provider.as_ref();
+
+    let initial_secret = derive_initial_secret(p, cid, version)?;
 
     // Client.
     let mut client_key = vec![0; key_len];
     let mut client_iv = vec![0; nonce_len];
     let mut client_hp_key = vec![0; key_len];
 
-    derive_client_initial_secret(&initial_secret, &mut secret)?;
-    derive_pkt_key(aead, &secret, &mut client_key)?;
-    derive_pkt_iv(aead, &secret, &mut client_iv)?;
-    derive_hdr_key(aead, &secret, &mut client_hp_key)?;
+    derive_client_initial_secret(p, &initial_secret, &mut secret)?;
+    derive_pkt_key(p, aead, version, &secret, &mut client_key)?;
+    derive_pkt_iv(p, aead, version, &secret, &mut client_iv)?;
+    derive_hdr_key(p, aead, version, &secret, &mut client_hp_key)?;
 
     // Server.
     let mut server_key = vec![0; key_len];
     let mut server_iv = vec![0; nonce_len];
     let mut server_hp_key = vec![0; key_len];
 
-    derive_server_initial_secret(&initial_secret, &mut secret)?;
-    derive_pkt_key(aead, &secret, &mut server_key)?;
-    derive_pkt_iv(aead, &secret, &mut server_iv)?;
-    derive_hdr_key(aead, &secret, &mut server_hp_key)?;
+    derive_server_initial_secret(p, &initial_secret, &mut secret)?;
+    derive_pkt_key(p, aead, version, &secret, &mut server_key)?;
+    derive_pkt_iv(p, aead, version, &secret, &mut server_iv)?;
+    derive_hdr_key(p, aead, version, &secret, &mut server_hp_key)?;
 
     let (open, seal) = if is_server {
         (
-            Open::new(aead, &client_key, &client_iv, &client_hp_key)?,
-            Seal::new(aead, &server_key, &server_iv, &server_hp_key)?,
+            Open::new(provider, aead, &client_key, &client_iv, &client_hp_key)?,
+            Seal::new(provider, aead, &server_key, &server_iv, &server_hp_key)?,
         )
     } else {
         (
-            Open::new(aead, &server_key, &server_iv, &server_hp_key)?,
-            Seal::new(aead, &client_key, &client_iv, &client_hp_key)?,
+            Open::new(provider, aead, &server_key, &server_iv, &server_hp_key)?,
+            Seal::new(provider, aead, &client_key, &client_iv, &client_hp_key)?,
         )
     };
 
     Ok((open, seal))
 }
 
-fn derive_initial_secret(secret: &[u8], version: u32) -> hkdf::Prk {
+fn derive_initial_secret(
+    provider: &dyn CryptoProvider, secret: &[u8], version: u32,
+) -> Result<Vec<u8>> {
     const INITIAL_SALT: [u8; 20] = [
         0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6,
         0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb, 0x7f, 0x0a,
     ];
 
+    const INITIAL_SALT_V2: [u8; 20] = [
+        0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe,
+        0x6e, 0x26, 0x9d, 0xcb, 0xf9, 0xbd, 0x2e, 0xd9,
+    ];
+
     const INITIAL_SALT_DRAFT29: [u8; 20] = [
         0xaf, 0xbf, 0xec, 0x28, 0x99, 0x93, 0xd2, 0x4c, 0x9e, 0x97, 0x86, 0xf1,
         0x9c, 0x61, 0x11, 0xe0, 0x43, 0x90, 0xa8, 0x99,
@@ -414,27 +612,36 @@ fn derive_initial_secret(secret: &[u8], version: u32) -> hkdf::Prk {
 
         crate::PROTOCOL_VERSION_DRAFT29 => &INITIAL_SALT_DRAFT29,
 
+        crate::PROTOCOL_VERSION_V2 => &INITIAL_SALT_V2,
+
         _ => &INITIAL_SALT,
     };
 
-    let salt = hkdf::Salt::new(hkdf::HKDF_SHA256, salt);
-    salt.extract(secret)
+    // Initial packets are always protected with AES-128-GCM, so the initial
+    // secrets are derived with SHA-256.
+    provider.hkdf_extract(Algorithm::AES128_GCM, salt, secret)
 }
 
-fn derive_client_initial_secret(prk: &hkdf::Prk, out: &mut [u8]) -> Result<()> {
+fn derive_client_initial_secret(
+    provider: &dyn CryptoProvider, prk: &[u8], out: &mut [u8],
+) -> Result<()> {
     const LABEL: &[u8] = b"client in";
-    hkdf_expand_label(prk, LABEL, out)
+    hkdf_expand_label(provider, Algorithm::AES128_GCM, prk, LABEL, out)
 }
 
-fn derive_server_initial_secret(prk: &hkdf::Prk, out: &mut [u8]) -> Result<()> {
+fn derive_server_initial_secret(
+    provider: &dyn CryptoProvider, prk: &[u8], out: &mut [u8],
+) -> Result<()> {
     const LABEL: &[u8] = b"server in";
-    hkdf_expand_label(prk, LABEL, out)
+    hkdf_expand_label(provider, Algorithm::AES128_GCM, prk, LABEL, out)
 }
 
 pub fn derive_hdr_key(
-    aead: Algorithm, secret: &[u8], out: &mut [u8],
+    provider: &dyn CryptoProvider, aead: Algorithm, version: u32, secret: &[u8],
+    out: &mut [u8],
 ) -> Result<()> {
     const LABEL: &[u8] = b"quic hp";
+    const LABEL_V2: &[u8] = b"quicv2 hp";
 
     let key_len = aead.key_len();
 
@@ -442,14 +649,16 @@ pub fn derive_hdr_key(
         return Err(Error::CryptoFail);
     }
 
-    let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
+    let label = version_label(version, LABEL, LABEL_V2);
+    hkdf_expand_label(provider, aead, secret, label, &mut out[..key_len])
 }
 
 pub fn derive_pkt_key(
-    aead: Algorithm, secret: &[u8], out: &mut [u8],
+    provider: &dyn CryptoProvider, aead: Algorithm, version: u32, secret: &[u8],
+    out: &mut [u8],
 ) -> Result<()> {
     const LABEL: &[u8] = b"quic key";
+    const LABEL_V2: &[u8] = b"quicv2 key";
 
     let key_len = aead.key_len();
 
@@ -457,14 +666,34 @@ pub fn derive_pkt_key(
         return Err(Error::CryptoFail);
     }
 
-    let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
+    let label = version_label(version, LABEL, LABEL_V2);
+    hkdf_expand_label(provider, aead, secret, label, &mut out[..key_len])
+}
+
+fn derive_next_secret(
+    provider: &dyn CryptoProvider, aead: Algorithm, version: u32, secret: &[u8],
+) -> Result<Vec<u8>> {
+    const LABEL: &[u8] = b"quic ku";
+    const LABEL_V2: &[u8] = b"quicv2 ku";
+
+    if secret.is_empty() {
+        return Err(Error::CryptoFail);
+    }
+
+    let mut out = vec![0; secret.len()];
+
+    let label = version_label(version, LABEL, LABEL_V2);
+    hkdf_expand_label(provider, aead, secret, label, &mut out)?;
+
+    Ok(out)
 }
 
 pub fn derive_pkt_iv(
-    aead: Algorithm, secret: &[u8], out: &mut [u8],
+    provider: &dyn CryptoProvider, aead: Algorithm, version: u32, secret: &[u8],
+    out: &mut [u8],
 ) -> Result<()> {
     const LABEL: &[u8] = b"quic iv";
+    const LABEL_V2: &[u8] = b"quicv2 iv";
 
     let nonce_len = aead.nonce_len();
 
@@ -472,11 +701,60 @@ pub fn derive_pkt_iv(
         return Err(Error::CryptoFail);
     }
 
-    let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..nonce_len])
+    let label = version_label(version, LABEL, LABEL_V2);
+    hkdf_expand_label(provider, aead, secret, label, &mut out[..nonce_len])
+}
+
+/// Returns the HKDF label to use for the given QUIC version, as QUIC v2 uses
+/// its own labels.
+fn version_label(
+    version: u32, label_v1: &'static [u8], label_v2: &'static [u8],
+) -> &'static [u8] {
+    if version == crate::PROTOCOL_VERSION_V2 {
+        label_v2
+    } else {
+        label_v1
+    }
+}
+
+// The AEAD used for packet protection is provided by BoringSSL, unless the
+// rustls backend is used, in which case ring is used instead so that no C
+// library is needed.
+#[cfg(not(feature = "rustls"))]
+type AeadCtx = EVP_AEAD_CTX;
+
+#[cfg(feature = "rustls")]
+type AeadCtx = aead::LessSafeKey;
+
+struct DefaultPacketKey {
+    ctx: AeadCtx,
+
+    tag_len: usize,
+}
+
+impl PacketKey for DefaultPacketKey {
+    fn open(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8]) -> Result<usize> {
+        aead_open(&self.ctx, nonce, ad, buf, self.tag_len)
+    }
+
+    fn seal(
+        &self, nonce: &[u8], ad: &[u8], buf: &mut [u8], in_len: usize,
+        extra_in: Option<&[u8]>,
+    ) -> Result<usize> {
+        aead_seal(&self.ctx, nonce, ad, buf, in_len, extra_in, self.tag_len)
+    }
+}
+
+struct DefaultHeaderKey(aead::quic::HeaderProtectionKey);
+
+impl HeaderKey for DefaultHeaderKey {
+    fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]> {
+        self.0.new_mask(sample).map_err(|_| Error::CryptoFail)
+    }
 }
 
-fn make_aead_ctx(alg: Algorithm, key: &[u8]) -> Result<EVP_AEAD_CTX> {
+#[cfg(not(feature = "rustls"))]
+fn make_aead_ctx(alg: Algorithm, key: &[u8]) -> Result<AeadCtx> {
     let mut ctx = MaybeUninit::uninit();
 
     let ctx = unsafe {
@@ -501,25 +779,136 @@ fn make_aead_ctx(alg: Algorithm, key: &[u8]) -> Result<EVP_AEAD_CTX> {
     Ok(ctx)
 }
 
+#[cfg(not(feature = "rustls"))]
+fn aead_open(
+    ctx: &AeadCtx, nonce: &[u8], ad: &[u8], buf: &mut [u8], tag_len: usize,
+) -> Result<usize> {
+    let mut out_len = buf.len() - tag_len;
+
+    let max_out_len = out_len;
+
+    let rc = unsafe {
+        EVP_AEAD_CTX_open(
+            ctx,              // ctx
+            buf.as_mut_ptr(), // out
+            &mut out_len,     // out_len
+            max_out_len,      // max_out_len
+            nonce.as_ptr(),   // nonce
+            nonce.len(),      // nonce_len
+            buf.as_ptr(),     // inp
+            buf.len(),        // in_len
+            ad.as_ptr(),      // ad
+            ad.len(),         // ad_len
+        )
+    };
+
+    if rc != 1 {
+        return Err(Error::CryptoFail);
+    }
+
+    Ok(out_len)
+}
+
+#[cfg(not(feature = "rustls"))]
+fn aead_seal(
+    ctx: &AeadCtx, nonce: &[u8], ad: &[u8], buf: &mut [u8], in_len: usize,
+    extra_in: Option<&[u8]>, tag_len: usize,
+) -> Result<usize> {
+    let mut out_tag_len = tag_len;
+
+    let (extra_in_ptr, extra_in_len) = match extra_in {
+        Some(v) => (v.as_ptr(), v.len()),
+
+        None => (std::ptr::null(), 0),
+    };
+
+    let rc = unsafe {
+        EVP_AEAD_CTX_seal_scatter(
+            ctx,                        // ctx
+            buf.as_mut_ptr(),           // out
+            buf[in_len..].as_mut_ptr(), // out_tag
+            &mut out_tag_len,           // out_tag_len
+            tag_len + extra_in_len,     // max_out_tag_len
+            nonce.as_ptr(),             // nonce
+            nonce.len(),                // nonce_len
+            buf.as_ptr(),               // inp
+            in_len,                     // in_len
+            extra_in_ptr,               // extra_in
+            extra_in_len,               // extra_in_len
+            ad.as_ptr(),                // ad
+            ad.len(),                   // ad_len
+        )
+    };
+
+    if rc != 1 {
+        return Err(Error::CryptoFail);
+    }
+
+    Ok(in_len + out_tag_len)
+}
+
+#[cfg(feature = "rustls")]
+fn make_aead_ctx(alg: Algorithm, key: &[u8]) -> Result<AeadCtx> {
+    let key = aead::UnboundKey::new(alg.get_ring_aead(), key)
+        .map_err(|_| Error::CryptoFail)?;
+
+    Ok(aead::LessSafeKey::new(key))
+}
+
+#[cfg(feature = "rustls")]
+fn aead_open(
+    ctx: &AeadCtx, nonce: &[u8], ad: &[u8], buf: &mut [u8], _tag_len: usize,
+) -> Result<usize> {
+    let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| Error::CryptoFail)?;
+
+    let out = ctx
+        .open_in_place(nonce, aead::Aad::from(ad), buf)
+        .map_err(|_| Error::CryptoFail)?;
+
+    Ok(out.len())
+}
+
+#[cfg(feature = "rustls")]
+fn aead_seal(
+    ctx: &AeadCtx, nonce: &[u8], ad: &[u8], buf: &mut [u8], in_len: usize,
+    extra_in: Option<&[u8]>, tag_len: usize,
+) -> Result<usize> {
+    // The extra input is encrypted right after the plaintext, as done by
+    // BoringSSL's EVP_AEAD_CTX_seal_scatter().
+    let extra_in = extra_in.unwrap_or(&[]);
+    let len = in_len + extra_in.len();
+
+    buf[in_len..len].copy_from_slice(extra_in);
+
+    let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| Error::CryptoFail)?;
+
+    let tag = ctx
+        .seal_in_place_separate_tag(nonce, aead::Aad::from(ad), &mut buf[..len])
+        .map_err(|_| Error::CryptoFail)?;
+
+    buf[len..len + tag_len].copy_from_slice(tag.as_ref());
+
+    Ok(len + tag_len)
+}
+
 fn hkdf_expand_label(
-    prk: &hkdf::Prk, label: &[u8], out: &mut [u8],
+    provider: &dyn CryptoProvider, aead: Algorithm, prk: &[u8], label: &[u8],
+    out: &mut [u8],
 ) -> Result<()> {
     const LABEL_PREFIX: &[u8] = b"tls13 ";
 
     let out_len = (out.len() as u16).to_be_bytes();
     let label_len = (LABEL_PREFIX.len() + label.len()) as u8;
 
-    let info = [&out_len, &[label_len][..], LABEL_PREFIX, label, &[0][..]];
+    let info =
+        [&out_len, &[label_len][..], LABEL_PREFIX, label, &[0][..]].concat();
 
-    prk.expand(&info, ArbitraryOutputLen(out.len()))
-        .map_err(|_| Error::CryptoFail)?
-        .fill(out)
-        .map_err(|_| Error::CryptoFail)?;
-
-    Ok(())
+    provider.hkdf_expand(aead, prk, &info, out)
 }
 
-fn make_nonce(iv: &[u8], counter: u64) -> [u8; aead::NONCE_LEN] {
+fn make_nonce(iv: &[u8], path_id: u32, counter: u64) -> [u8; aead::NONCE_LEN] {
     let mut nonce = [0; aead::NONCE_LEN];
     nonce.copy_from_slice(iv);
 
@@ -529,6 +918,13 @@ fn make_nonce(iv: &[u8], counter: u64) -> [u8; aead::NONCE_LEN] {
         *a ^= b;
     }
 
+    // With the multipath extension, the path identifier is XORed with the
+    // bytes preceding the counter. A zero path identifier leaves the nonce
+    // unchanged.
+    for (a, b) in nonce[..4].iter_mut().zip(path_id.to_be_bytes().iter()) {
+        *a ^= b;
+    }
+
     nonce
 }
 
@@ -545,12 +941,14 @@ impl hkdf::KeyType for ArbitraryOutputLen {
 
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
+#[cfg(not(feature = "rustls"))]
 struct EVP_AEAD(c_void);
 
 // NOTE: This structure is copied from <openssl/aead.h> in order to be able to
 // statically allocate it. While it is not often modified upstream, it needs to
 // be kept in sync.
 #[repr(C)]
+#[cfg(not(feature = "rustls"))]
 struct EVP_AEAD_CTX {
     aead: libc::uintptr_t,
     opaque: [u8; 580],
@@ -558,6 +956,7 @@ struct EVP_AEAD_CTX {
     tag_len: u8,
 }
 
+#[cfg(not(feature = "rustls"))]
 extern {
     // EVP_AEAD
     fn EVP_aead_aes_128_gcm() -> *const EVP_AEAD;
@@ -600,13 +999,16 @@ mod tests {
         let mut hdr_key = [0; 16];
 
         let aead = Algorithm::AES128_GCM;
+        let version = crate::PROTOCOL_VERSION_V1;
+
+        let p = DefaultCryptoProvider;
 
-        let initial_secret =
-            derive_initial_secret(&dcid, crate::PROTOCOL_VERSION_V1);
+        let initial_secret = derive_initial_secret(&p, &dcid, version).unwrap();
 
         // Client.
         assert!(
-            derive_client_initial_secret(&initial_secret, &mut secret).is_ok()
+            derive_client_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
         );
         let expected_client_initial_secret = [
             0xc0, 0x0c, 0xf1, 0x51, 0xca, 0x5b, 0xe0, 0x75, 0xed, 0x0e, 0xbf,
@@ -615,21 +1017,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key = [
             0x1f, 0x36, 0x96, 0x13, 0xdd, 0x76, 0xd5, 0x46, 0x77, 0x30, 0xef,
             0xcb, 0xe3, 0xb1, 0xa2, 0x2d,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv = [
             0xfa, 0x04, 0x4b, 0x2f, 0x42, 0xa3, 0xfd, 0x3b, 0x46, 0xfb, 0x25,
             0x5c,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
         let expected_client_hdr_key = [
             0x9f, 0x50, 0x44, 0x9e, 0x04, 0xa0, 0xe8, 0x10, 0x28, 0x3a, 0x1e,
             0x99, 0x33, 0xad, 0xed, 0xd2,
@@ -638,7 +1040,8 @@ mod tests {
 
         // Server.
         assert!(
-            derive_server_initial_secret(&initial_secret, &mut secret).is_ok()
+            derive_server_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
         );
         let expected_server_initial_secret = [
             0x3c, 0x19, 0x98, 0x28, 0xfd, 0x13, 0x9e, 0xfd, 0x21, 0x6c, 0x15,
@@ -647,21 +1050,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key = [
             0xcf, 0x3a, 0x53, 0x31, 0x65, 0x3c, 0x36, 0x4c, 0x88, 0xf0, 0xf3,
             0x79, 0xb6, 0x06, 0x7e, 0x37,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv = [
             0x0a, 0xc1, 0x49, 0x3c, 0xa1, 0x90, 0x58, 0x53, 0xb0, 0xbb, 0xa0,
             0x3e,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key = [
             0xc2, 0x06, 0xb8, 0xd9, 0xb9, 0xf0, 0xf3, 0x76, 0x44, 0x43, 0x0b,
             0x49, 0x0e, 0xea, 0xa3, 0x14,
@@ -669,6 +1072,89 @@ mod tests {
         assert_eq!(&hdr_key, &expected_server_hdr_key);
     }
 
+    #[test]
+    fn derive_initial_secrets_v2() {
+        let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut secret = [0; 32];
+        let mut pkt_key = [0; 16];
+        let mut pkt_iv = [0; 12];
+        let mut hdr_key = [0; 16];
+
+        let aead = Algorithm::AES128_GCM;
+        let version = crate::PROTOCOL_VERSION_V2;
+
+        let p = DefaultCryptoProvider;
+
+        let initial_secret = derive_initial_secret(&p, &dcid, version).unwrap();
+
+        // Client.
+        assert!(
+            derive_client_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
+        );
+        let expected_client_initial_secret = [
+            0x14, 0xec, 0x9d, 0x6e, 0xb9, 0xfd, 0x7a, 0xf8, 0x3b, 0xf5, 0xa6,
+            0x68, 0xbc, 0x17, 0xa7, 0xe2, 0x83, 0x76, 0x6a, 0xad, 0xe7, 0xec,
+            0xd0, 0x89, 0x1f, 0x70, 0xf9, 0xff, 0x7f, 0x4b, 0xf4, 0x7b,
+        ];
+        assert_eq!(&secret, &expected_client_initial_secret);
+
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_client_pkt_key = [
+            0x8b, 0x1a, 0x0b, 0xc1, 0x21, 0x28, 0x42, 0x90, 0xa2, 0x9e, 0x09,
+            0x71, 0xb5, 0xcd, 0x04, 0x5d,
+        ];
+        assert_eq!(&pkt_key, &expected_client_pkt_key);
+
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_client_pkt_iv = [
+            0x91, 0xf7, 0x3e, 0x23, 0x51, 0xd8, 0xfa, 0x91, 0x66, 0x0e, 0x90,
+            0x9f,
+        ];
+        assert_eq!(&pkt_iv, &expected_client_pkt_iv);
+
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_client_hdr_key = [
+            0x45, 0xb9, 0x5e, 0x15, 0x23, 0x5d, 0x6f, 0x45, 0xa6, 0xb1, 0x9c,
+            0xbc, 0xb0, 0x29, 0x4b, 0xa9,
+        ];
+        assert_eq!(&hdr_key, &expected_client_hdr_key);
+
+        // Server.
+        assert!(
+            derive_server_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
+        );
+        let expected_server_initial_secret = [
+            0x02, 0x63, 0xdb, 0x17, 0x82, 0x73, 0x1b, 0xf4, 0x58, 0x8e, 0x7e,
+            0x4d, 0x93, 0xb7, 0x46, 0x39, 0x07, 0xcb, 0x8c, 0xd8, 0x20, 0x0b,
+            0x5d, 0xa5, 0x5a, 0x8b, 0xd4, 0x88, 0xea, 0xfc, 0x37, 0xc1,
+        ];
+        assert_eq!(&secret, &expected_server_initial_secret);
+
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_server_pkt_key = [
+            0x82, 0xdb, 0x63, 0x78, 0x61, 0xd5, 0x5e, 0x1d, 0x01, 0x1f, 0x19,
+            0xea, 0x71, 0xd5, 0xd2, 0xa7,
+        ];
+        assert_eq!(&pkt_key, &expected_server_pkt_key);
+
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_server_pkt_iv = [
+            0xdd, 0x13, 0xc2, 0x76, 0x49, 0x9c, 0x02, 0x49, 0xd3, 0x31, 0x06,
+            0x52,
+        ];
+        assert_eq!(&pkt_iv, &expected_server_pkt_iv);
+
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_server_hdr_key = [
+            0xed, 0xf6, 0xd0, 0x5c, 0x83, 0x12, 0x12, 0x01, 0xb4, 0x36, 0xe1,
+            0x68, 0x77, 0x59, 0x3c, 0x3a,
+        ];
+        assert_eq!(&hdr_key, &expected_server_hdr_key);
+    }
+
     #[test]
     fn derive_initial_secrets_draft29() {
         let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
@@ -679,13 +1165,16 @@ mod tests {
         let mut hdr_key = [0; 16];
 
         let aead = Algorithm::AES128_GCM;
+        let version = crate::PROTOCOL_VERSION_DRAFT29;
 
-        let initial_secret =
-            derive_initial_secret(&dcid, crate::PROTOCOL_VERSION_DRAFT29);
+        let p = DefaultCryptoProvider;
+
+        let initial_secret = derive_initial_secret(&p, &dcid, version).unwrap();
 
         // Client.
         assert!(
-            derive_client_initial_secret(&initial_secret, &mut secret).is_ok()
+            derive_client_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
         );
         let expected_client_initial_secret = [
             0x00, 0x88, 0x11, 0x92, 0x88, 0xf1, 0xd8, 0x66, 0x73, 0x3c, 0xee,
@@ -694,21 +1183,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key = [
             0x17, 0x52, 0x57, 0xa3, 0x1e, 0xb0, 0x9d, 0xea, 0x93, 0x66, 0xd8,
             0xbb, 0x79, 0xad, 0x80, 0xba,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv = [
             0x6b, 0x26, 0x11, 0x4b, 0x9c, 0xba, 0x2b, 0x63, 0xa9, 0xe8, 0xdd,
             0x4f,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
         let expected_client_hdr_key = [
             0x9d, 0xdd, 0x12, 0xc9, 0x94, 0xc0, 0x69, 0x8b, 0x89, 0x37, 0x4a,
             0x9c, 0x07, 0x7a, 0x30, 0x77,
@@ -717,7 +1206,8 @@ mod tests {
 
         // Server.
         assert!(
-            derive_server_initial_secret(&initial_secret, &mut secret).is_ok()
+            derive_server_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
         );
         let expected_server_initial_secret = [
             0x00, 0x6f, 0x88, 0x13, 0x59, 0x24, 0x4d, 0xd9, 0xad, 0x1a, 0xcf,
@@ -726,21 +1216,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key = [
             0x14, 0x9d, 0x0b, 0x16, 0x62, 0xab, 0x87, 0x1f, 0xbe, 0x63, 0xc4,
             0x9b, 0x5e, 0x65, 0x5a, 0x5d,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv = [
             0xba, 0xb2, 0xb1, 0x2a, 0x4c, 0x76, 0x01, 0x6a, 0xce, 0x47, 0x85,
             0x6d,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key = [
             0xc0, 0xc4, 0x99, 0xa6, 0x5a, 0x60, 0x02, 0x4a, 0x18, 0xa2, 0x50,
             0x97, 0x4e, 0xa0, 0x1d, 0xfa,
@@ -758,13 +1248,16 @@ mod tests {
         let mut hdr_key = [0; 16];
 
         let aead = Algorithm::AES128_GCM;
+        let version = crate::PROTOCOL_VERSION_DRAFT27;
+
+        let p = DefaultCryptoProvider;
 
-        let initial_secret =
-            derive_initial_secret(&dcid, crate::PROTOCOL_VERSION_DRAFT27);
+        let initial_secret = derive_initial_secret(&p, &dcid, version).unwrap();
 
         // Client.
         assert!(
-            derive_client_initial_secret(&initial_secret, &mut secret).is_ok()
+            derive_client_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
         );
         let expected_client_initial_secret = [
             0xfd, 0xa3, 0x95, 0x3a, 0xec, 0xc0, 0x40, 0xe4, 0x8b, 0x34, 0xe2,
@@ -773,21 +1266,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key = [
             0xaf, 0x7f, 0xd7, 0xef, 0xeb, 0xd2, 0x18, 0x78, 0xff, 0x66, 0x81,
             0x12, 0x48, 0x98, 0x36, 0x94,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv = [
             0x86, 0x81, 0x35, 0x94, 0x10, 0xa7, 0x0b, 0xb9, 0xc9, 0x2f, 0x04,
             0x20,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
         let expected_client_hdr_key = [
             0xa9, 0x80, 0xb8, 0xb4, 0xfb, 0x7d, 0x9f, 0xbc, 0x13, 0xe8, 0x14,
             0xc2, 0x31, 0x64, 0x25, 0x3d,
@@ -796,7 +1289,8 @@ mod tests {
 
         // Server.
         assert!(
-            derive_server_initial_secret(&initial_secret, &mut secret).is_ok()
+            derive_server_initial_secret(&p, &initial_secret, &mut secret)
+                .is_ok()
         );
         let expected_server_initial_secret = [
             0x55, 0x43, 0x66, 0xb8, 0x19, 0x12, 0xff, 0x90, 0xbe, 0x41, 0xf1,
@@ -805,21 +1299,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key = [
             0x5d, 0x51, 0xda, 0x9e, 0xe8, 0x97, 0xa2, 0x1b, 0x26, 0x59, 0xcc,
             0xc7, 0xe5, 0xbf, 0xa5, 0x77,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv = [
             0x5e, 0x5a, 0xe6, 0x51, 0xfd, 0x1e, 0x84, 0x95, 0xaf, 0x13, 0x50,
             0x8b,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key = [
             0xa8, 0xed, 0x82, 0xe6, 0x66, 0x4f, 0x86, 0x5a, 0xed, 0xf6, 0x10,
             0x69, 0x43, 0xf9, 0x5f, 0xb8,
@@ -836,12 +1330,15 @@ mod tests {
         ];
 
         let aead = Algorithm::ChaCha20_Poly1305;
+        let version = crate::PROTOCOL_VERSION_V1;
+
+        let p = DefaultCryptoProvider;
 
         let mut pkt_key = [0; 32];
         let mut pkt_iv = [0; 12];
         let mut hdr_key = [0; 32];
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(&p, aead, version, &secret, &mut pkt_key).is_ok());
         let expected_pkt_key = [
             0xc6, 0xd9, 0x8f, 0xf3, 0x44, 0x1c, 0x3f, 0xe1, 0xb2, 0x18, 0x20,
             0x94, 0xf6, 0x9c, 0xaa, 0x2e, 0xd4, 0xb7, 0x16, 0xb6, 0x54, 0x88,
@@ -849,19 +1346,27 @@ mod tests {
         ];
         assert_eq!(&pkt_key, &expected_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(&p, aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_pkt_iv = [
             0xe0, 0x45, 0x9b, 0x34, 0x74, 0xbd, 0xd0, 0xe4, 0x4a, 0x41, 0xc1,
             0x44,
         ];
         assert_eq!(&pkt_iv, &expected_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(&p, aead, version, &secret, &mut hdr_key).is_ok());
         let expected_hdr_key = [
             0x25, 0xa2, 0x82, 0xb9, 0xe8, 0x2f, 0x06, 0xf2, 0x1f, 0x48, 0x89,
             0x17, 0xa4, 0xfc, 0x8f, 0x1b, 0x73, 0x57, 0x36, 0x85, 0x60, 0x85,
             0x97, 0xd0, 0xef, 0xcb, 0x07, 0x6b, 0x0a, 0xb7, 0xa7, 0xa4,
         ];
         assert_eq!(&hdr_key, &expected_hdr_key);
+
+        let next_secret = derive_next_secret(&p, aead, version, &secret).unwrap();
+        let expected_next_secret = [
+            0x12, 0x23, 0x50, 0x47, 0x55, 0x03, 0x6d, 0x55, 0x63, 0x42, 0xee,
+            0x93, 0x61, 0xd2, 0x53, 0x42, 0x1a, 0x82, 0x6c, 0x9e, 0xcd, 0xf3,
+            0xc7, 0x14, 0x86, 0x84, 0xb3, 0x6b, 0x71, 0x48, 0x81, 0xf9,
+        ];
+        assert_eq!(&next_secret[..], &expected_next_secret);
     }
 }
@@ -28,16 +28,95 @@ use crate::Error;
 use crate::Result;
 
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time;
+
+/// The payload of an outgoing DATAGRAM frame.
+///
+/// Payloads queued on many connections at once are shared instead of being
+/// copied for each of them.
+pub enum DatagramBuf {
+    Owned(Vec<u8>),
+
+    Shared(Arc<[u8]>),
+}
+
+impl AsRef<[u8]> for DatagramBuf {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            DatagramBuf::Owned(v) => v,
+
+            DatagramBuf::Shared(v) => v,
+        }
+    }
+}
+
+/// DATAGRAM queue state that can be inspected or updated by plugins.
+#[cfg(feature = "plugins")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DatagramField {
+    /// The number of DATAGRAMs in the send queue. Writing a smaller value
+    /// drops the oldest DATAGRAMs.
+    SendQueueLen,
+
+    /// The total size of the DATAGRAMs in the send queue.
+    SendQueueByteSize,
+
+    /// The size of the oldest DATAGRAM in the send queue.
+    SendQueueFrontLen,
+
+    /// The time in microseconds since the oldest DATAGRAM in the send queue
+    /// was queued.
+    SendQueueFrontAge,
+
+    /// The number of DATAGRAMs in the receive queue. Writing a smaller value
+    /// drops the oldest DATAGRAMs.
+    RecvQueueLen,
+
+    /// The total size of the DATAGRAMs in the receive queue.
+    RecvQueueByteSize,
+
+    /// The size of the oldest DATAGRAM in the receive queue.
+    RecvQueueFrontLen,
+
+    /// The time in microseconds since the oldest DATAGRAM in the receive
+    /// queue was queued.
+    RecvQueueFrontAge,
+
+    /// The number of DATAGRAMs to move from the front of the send queue to
+    /// its back. Reading it always returns `0`.
+    SendQueueRequeue,
+}
+
+/// The urgency of DATAGRAMs queued without an explicit one.
+pub const DEFAULT_URGENCY: u8 = 127;
+
+/// A queued DATAGRAM.
+struct Entry<T> {
+    data: T,
+
+    /// The time the DATAGRAM was queued at.
+    queued_at: time::Instant,
+
+    /// The DATAGRAM's urgency (lower is better).
+    urgency: u8,
+
+    /// The time after which the DATAGRAM is dropped instead of being sent.
+    expiry: Option<time::Instant>,
+}
 
 /// Keeps track of DATAGRAM frames.
+///
+/// DATAGRAMs are ordered by urgency, and in the order they were queued for
+/// the same urgency.
 #[derive(Default)]
-pub struct DatagramQueue {
-    queue: Option<VecDeque<Vec<u8>>>,
+pub struct DatagramQueue<T = Vec<u8>> {
+    queue: Option<VecDeque<Entry<T>>>,
     queue_max_len: usize,
     queue_bytes_size: usize,
 }
 
-impl DatagramQueue {
+impl<T: AsRef<[u8]>> DatagramQueue<T> {
     pub fn new(queue_max_len: usize) -> Self {
         DatagramQueue {
             queue: None,
@@ -46,26 +125,64 @@ impl DatagramQueue {
         }
     }
 
-    pub fn push(&mut self, data: Vec<u8>) -> Result<()> {
+    pub fn push(&mut self, data: T) -> Result<()> {
+        self.push_at(data, time::Instant::now())
+    }
+
+    pub fn push_at(&mut self, data: T, now: time::Instant) -> Result<()> {
+        self.push_with(data, DEFAULT_URGENCY, None, now)
+    }
+
+    /// Queues a DATAGRAM with the given urgency, behind the DATAGRAMs of
+    /// lower or equal urgency. It is dropped instead of being sent if it is
+    /// still queued after `expiry`.
+    pub fn push_with(
+        &mut self, data: T, urgency: u8, expiry: Option<time::Instant>,
+        now: time::Instant,
+    ) -> Result<()> {
         if self.is_full() {
             return Err(Error::Done);
         }
 
-        self.queue_bytes_size += data.len();
-        self.queue
-            .get_or_insert_with(Default::default)
-            .push_back(data);
+        self.queue_bytes_size += data.as_ref().len();
+
+        let q = self.queue.get_or_insert_with(Default::default);
+
+        // Most DATAGRAMs share the same urgency, so look for the insertion
+        // point from the back of the queue.
+        let pos = q
+            .iter()
+            .rposition(|e| e.urgency <= urgency)
+            .map_or(0, |i| i + 1);
+
+        q.insert(pos, Entry {
+            data,
+            queued_at: now,
+            urgency,
+            expiry,
+        });
 
         Ok(())
     }
 
     pub fn peek_front_len(&self) -> Option<usize> {
-        self.queue.as_ref().and_then(|q| q.front().map(|d| d.len()))
+        self.queue
+            .as_ref()
+            .and_then(|q| q.front().map(|e| e.data.as_ref().len()))
+    }
+
+    /// Returns how long the oldest DATAGRAM has been queued for.
+    pub fn peek_front_age(&self, now: time::Instant) -> Option<time::Duration> {
+        self.queue.as_ref().and_then(|q| {
+            q.front()
+                .map(|e| now.saturating_duration_since(e.queued_at))
+        })
     }
 
     pub fn peek_front_bytes(&self, buf: &mut [u8], len: usize) -> Result<usize> {
         match self.queue.as_ref().and_then(|q| q.front()) {
-            Some(d) => {
+            Some(e) => {
+                let d = e.data.as_ref();
                 let len = std::cmp::min(len, d.len());
                 if buf.len() < len {
                     return Err(Error::BufferTooShort);
@@ -79,23 +196,70 @@ impl DatagramQueue {
         }
     }
 
-    pub fn pop(&mut self) -> Option<Vec<u8>> {
-        if let Some(d) = self.queue.as_mut().and_then(|q| q.pop_front()) {
-            self.queue_bytes_size = self.queue_bytes_size.saturating_sub(d.len());
-            return Some(d);
+    pub fn pop(&mut self) -> Option<T> {
+        if let Some(e) = self.queue.as_mut().and_then(|q| q.pop_front()) {
+            self.queue_bytes_size =
+                self.queue_bytes_size.saturating_sub(e.data.as_ref().len());
+            return Some(e.data);
         }
 
         None
     }
 
+    /// Drops the DATAGRAMs that expired at `now`, and returns their number.
+    pub fn purge_expired(&mut self, now: time::Instant) -> usize {
+        let q = match self.queue.as_mut() {
+            Some(q) => q,
+
+            None => return 0,
+        };
+
+        let len = q.len();
+
+        q.retain(|e| e.expiry.map_or(true, |t| t > now));
+
+        let dropped = len - q.len();
+
+        if dropped > 0 {
+            self.queue_bytes_size =
+                q.iter().fold(0, |total, e| total + e.data.as_ref().len());
+        }
+
+        dropped
+    }
+
+    /// Drops the oldest DATAGRAMs until at most `len` are left, and returns
+    /// the number of dropped DATAGRAMs.
+    pub fn truncate_front(&mut self, len: usize) -> usize {
+        let dropped = self.len().saturating_sub(len);
+
+        for _ in 0..dropped {
+            self.pop();
+        }
+
+        dropped
+    }
+
+    /// Moves the `n` DATAGRAMs at the front of the queue to its back, keeping
+    /// the time they were first queued, regardless of their urgency.
+    pub fn requeue_front(&mut self, n: usize) {
+        if let Some(q) = self.queue.as_mut() {
+            if !q.is_empty() {
+                let n = n % q.len();
+                q.rotate_left(n);
+            }
+        }
+    }
+
     pub fn has_pending(&self) -> bool {
         !self.queue.as_ref().map(|q| q.is_empty()).unwrap_or(true)
     }
 
     pub fn purge<F: Fn(&[u8]) -> bool>(&mut self, f: F) {
         if let Some(q) = self.queue.as_mut() {
-            q.retain(|d| !f(d));
-            self.queue_bytes_size = q.iter().fold(0, |total, d| total + d.len());
+            q.retain(|e| !f(e.data.as_ref()));
+            self.queue_bytes_size =
+                q.iter().fold(0, |total, e| total + e.data.as_ref().len());
         }
     }
 
@@ -111,3 +275,65 @@ impl DatagramQueue {
         self.queue_bytes_size
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn front_age() {
+        let mut q: DatagramQueue = DatagramQueue::new(10);
+
+        let now = time::Instant::now();
+        assert_eq!(q.peek_front_age(now), None);
+
+        q.push_at(b"hello".to_vec(), now).unwrap();
+        q.push_at(b"world".to_vec(), now + Duration::from_millis(5))
+            .unwrap();
+
+        let later = now + Duration::from_millis(10);
+        assert_eq!(q.peek_front_age(later), Some(Duration::from_millis(10)));
+
+        // Requeued DATAGRAMs keep their age.
+        q.requeue_front(1);
+        assert_eq!(q.peek_front_age(later), Some(Duration::from_millis(5)));
+
+        assert_eq!(q.truncate_front(1), 1);
+        assert_eq!(q.pop(), Some(b"hello".to_vec()));
+        assert_eq!(q.truncate_front(1), 0);
+    }
+
+    #[test]
+    fn urgency_and_expiry() {
+        let mut q: DatagramQueue = DatagramQueue::new(10);
+
+        let now = time::Instant::now();
+        let expiry = now + Duration::from_millis(10);
+
+        q.push_at(b"a".to_vec(), now).unwrap();
+        q.push_with(b"bb".to_vec(), 0, Some(expiry), now).unwrap();
+        q.push_with(b"ccc".to_vec(), 0, None, now).unwrap();
+        q.push_with(b"dddd".to_vec(), 255, Some(expiry), now)
+            .unwrap();
+        q.push_at(b"eeeee".to_vec(), now).unwrap();
+        assert_eq!(q.byte_size(), 15);
+
+        // Nothing expired yet.
+        assert_eq!(q.purge_expired(now), 0);
+        assert_eq!(q.len(), 5);
+
+        assert_eq!(q.purge_expired(expiry), 2);
+        assert_eq!(q.len(), 3);
+        assert_eq!(q.byte_size(), 9);
+
+        // More urgent DATAGRAMs come first, then in the order they were
+        // queued.
+        assert_eq!(q.pop(), Some(b"ccc".to_vec()));
+        assert_eq!(q.pop(), Some(b"a".to_vec()));
+        assert_eq!(q.pop(), Some(b"eeeee".to_vec()));
+        assert_eq!(q.pop(), None);
+        assert_eq!(q.byte_size(), 0);
+    }
+}
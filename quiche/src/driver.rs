@@ -0,0 +1,584 @@
+// Copyright (C) 2023, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Sans-I/O connection driver.
+//!
+//! Every integration needs to make the same calls on a connection after
+//! feeding it with a packet or a timer expiration: reading the data received
+//! on streams, generating the packets to send and re-arming the connection's
+//! timer. A [`Driver`] wraps a connection and makes those calls, and returns
+//! their outcome as a list of [`Action`]s that the application carries out
+//! with its own I/O and timer facilities:
+//!
+//! ```no_run
+//! use quiche::driver::Action;
+//!
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! let socket = std::net::UdpSocket::bind(local).unwrap();
+//!
+//! let conn = quiche::connect(None, &scid, local, peer, &mut config)?;
+//! let mut driver = quiche::driver::Driver::new(conn);
+//!
+//! let mut buf = [0; 65535];
+//! let mut timer = None;
+//!
+//! let mut actions = driver.poll();
+//!
+//! loop {
+//!     for action in actions.drain(..) {
+//!         match action {
+//!             Action::Send { data, info } => {
+//!                 socket.send_to(&data, info.to).ok();
+//!             },
+//!
+//!             Action::ArmTimer(at) => timer = Some(at),
+//!
+//!             Action::DisarmTimer => timer = None,
+//!
+//!             Action::StreamData {
+//!                 stream_id, data, ..
+//!             } => {
+//!                 println!("Got {} bytes on stream {}", data.len(), stream_id);
+//!             },
+//!
+//!             Action::Closed => return Ok(()),
+//!
+//!             _ => (),
+//!         }
+//!     }
+//!
+//!     let timeout = timer.map(|t: std::time::Instant| {
+//!         t.saturating_duration_since(std::time::Instant::now())
+//!             .max(std::time::Duration::from_millis(1))
+//!     });
+//!     socket.set_read_timeout(timeout).unwrap();
+//!
+//!     actions = match socket.recv_from(&mut buf) {
+//!         Ok((len, from)) => {
+//!             let info = quiche::RecvInfo { from, to: local };
+//!
+//!             driver.recv(&mut buf[..len], info)
+//!         },
+//!
+//!         Err(_) => driver.on_timeout(),
+//!     };
+//! }
+//! # Ok::<(), quiche::Error>(())
+//! ```
+//!
+//! [`Driver`]: struct.Driver.html
+//! [`Action`]: enum.Action.html
+
+use std::cmp;
+
+use std::collections::BTreeSet;
+
+use std::time;
+
+use crate::Connection;
+use crate::Error;
+use crate::PathEvent;
+use crate::RecvInfo;
+use crate::Result;
+use crate::SendInfo;
+
+// The size of the buffers packets are sent from, and stream data is read into.
+const BUF_SIZE: usize = 65535;
+
+/// The default maximum amount of stream data read by each call.
+const DEFAULT_MAX_READ_LEN: usize = 1024 * 1024;
+
+/// An action the application needs to carry out for a connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Send a packet to the peer.
+    Send {
+        /// The packet.
+        data: Vec<u8>,
+
+        /// Where and when to send the packet.
+        info: SendInfo,
+    },
+
+    /// Arm the connection's timer, replacing the previous one. The driver's
+    /// [`on_timeout()`] method needs to be called when the timer fires.
+    ///
+    /// [`on_timeout()`]: struct.Driver.html#method.on_timeout
+    ArmTimer(time::Instant),
+
+    /// Disarm the connection's timer.
+    DisarmTimer,
+
+    /// The handshake completed.
+    Established,
+
+    /// Data was received on a stream.
+    StreamData {
+        /// The stream's ID.
+        stream_id: u64,
+
+        /// The received data, which is empty when only the FIN was received.
+        data: Vec<u8>,
+
+        /// Whether the peer finished the stream.
+        fin: bool,
+    },
+
+    /// The peer reset a stream with the given error code.
+    StreamReset {
+        /// The stream's ID.
+        stream_id: u64,
+
+        /// The error code sent by the peer.
+        error_code: u64,
+    },
+
+    /// A stream which couldn't accept all of the data written to it with
+    /// [`stream_send()`] can accept more.
+    ///
+    /// [`stream_send()`]: struct.Driver.html#method.stream_send
+    StreamWritable(u64),
+
+    /// A DATAGRAM frame was received.
+    #[cfg(feature = "datagram")]
+    Datagram(Vec<u8>),
+
+    /// A path event occurred.
+    Path(PathEvent),
+
+    /// The connection is closed, and the driver can be dropped.
+    Closed,
+}
+
+/// Drives a connection, turning its state changes into [`Action`]s.
+///
+/// [`Action`]: enum.Action.html
+pub struct Driver {
+    conn: Connection,
+
+    buf: Vec<u8>,
+
+    /// The streams waiting for capacity to be written to.
+    blocked: BTreeSet<u64>,
+
+    /// The last timer returned to the application.
+    timer: Option<time::Instant>,
+
+    /// The maximum amount of stream data read by each call.
+    max_read_len: usize,
+
+    established: bool,
+
+    closed: bool,
+}
+
+impl Driver {
+    /// Creates a driver for the given connection.
+    pub fn new(conn: Connection) -> Driver {
+        Driver {
+            conn,
+
+            buf: vec![0; BUF_SIZE],
+
+            blocked: BTreeSet::new(),
+
+            timer: None,
+
+            max_read_len: DEFAULT_MAX_READ_LEN,
+
+            established: false,
+
+            closed: false,
+        }
+    }
+
+    /// Returns the connection.
+    pub fn conn(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Returns the connection mutably.
+    ///
+    /// The driver's [`poll()`] method needs to be called after changing the
+    /// connection's state, for example after writing to streams.
+    ///
+    /// [`poll()`]: struct.Driver.html#method.poll
+    pub fn conn_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Sets the maximum number of bytes of stream data read by each call,
+    /// across all streams.
+    ///
+    /// This bounds the memory used by the [`StreamData`] actions returned at
+    /// once. The data left in the connection's buffers is returned by the
+    /// next call, and [`Connection::is_readable()`] tells whether there is
+    /// any, so that the application can call [`poll()`] once it consumed the
+    /// previous data.
+    ///
+    /// The default value is 1MiB.
+    ///
+    /// [`StreamData`]: enum.Action.html#variant.StreamData
+    /// [`Connection::is_readable()`]:
+    /// ../struct.Connection.html#method.is_readable
+    /// [`poll()`]: struct.Driver.html#method.poll
+    pub fn set_max_read_len(&mut self, v: usize) {
+        self.max_read_len = v;
+    }
+
+    /// Processes a packet received from the peer, and returns the resulting
+    /// actions.
+    ///
+    /// Packets that can't be processed are dropped, and errors that are fatal
+    /// to the connection close it.
+    pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Vec<Action> {
+        if let Err(e) = self.conn.recv(buf, info) {
+            trace!("{} recv failed: {:?}", self.conn.trace_id(), e);
+        }
+
+        self.poll()
+    }
+
+    /// Processes the expiration of the connection's timer, and returns the
+    /// resulting actions.
+    pub fn on_timeout(&mut self) -> Vec<Action> {
+        // The timer fired, so it needs to be armed again if still needed.
+        self.timer = None;
+
+        self.conn.on_timeout();
+
+        self.poll()
+    }
+
+    /// Writes data to a stream, as [`Connection::stream_send()`] does.
+    ///
+    /// When the stream doesn't accept all of the data, a [`StreamWritable`]
+    /// action is returned once it can accept more.
+    ///
+    /// [`Connection::stream_send()`]:
+    /// ../struct.Connection.html#method.stream_send
+    /// [`StreamWritable`]: enum.Action.html#variant.StreamWritable
+    pub fn stream_send(
+        &mut self, stream_id: u64, buf: &[u8], fin: bool,
+    ) -> Result<usize> {
+        let res = self.conn.stream_send(stream_id, buf, fin);
+
+        match res {
+            Ok(len) if len < buf.len() => {
+                self.blocked.insert(stream_id);
+            },
+
+            Err(Error::Done) => {
+                self.blocked.insert(stream_id);
+            },
+
+            _ => (),
+        }
+
+        res
+    }
+
+    /// Returns the actions resulting from the changes made to the connection
+    /// since the last call.
+    ///
+    /// Stream data is read up to the limit set with [`set_max_read_len()`].
+    ///
+    /// [`set_max_read_len()`]: struct.Driver.html#method.set_max_read_len
+    pub fn poll(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        if !self.established && self.conn.is_established() {
+            self.established = true;
+
+            actions.push(Action::Established);
+        }
+
+        let mut read_left = self.max_read_len;
+
+        for stream_id in self.conn.readable() {
+            if read_left == 0 {
+                break;
+            }
+
+            read_left -= self.read_stream(stream_id, read_left, &mut actions);
+        }
+
+        #[cfg(feature = "datagram")]
+        while let Ok(data) = self.conn.dgram_recv_vec() {
+            actions.push(Action::Datagram(data));
+        }
+
+        while let Some(e) = self.conn.path_event_next() {
+            actions.push(Action::Path(e));
+        }
+
+        let conn = &self.conn;
+        self.blocked.retain(|&stream_id| {
+            match conn.stream_capacity(stream_id) {
+                Ok(0) => true,
+
+                Ok(_) => {
+                    actions.push(Action::StreamWritable(stream_id));
+                    false
+                },
+
+                // The stream is gone.
+                Err(_) => false,
+            }
+        });
+
+        loop {
+            let (len, info) = match self.conn.send(&mut self.buf) {
+                Ok(v) => v,
+
+                Err(Error::Done) => break,
+
+                Err(e) => {
+                    trace!("{} send failed: {:?}", self.conn.trace_id(), e);
+
+                    self.conn.close(false, 0x1, b"fail").ok();
+                    break;
+                },
+            };
+
+            actions.push(Action::Send {
+                data: self.buf[..len].to_vec(),
+                info,
+            });
+        }
+
+        let timer = self.conn.timeout_instant();
+
+        if timer != self.timer {
+            self.timer = timer;
+
+            actions.push(match timer {
+                Some(at) => Action::ArmTimer(at),
+
+                None => Action::DisarmTimer,
+            });
+        }
+
+        if !self.closed && self.conn.is_closed() {
+            self.closed = true;
+
+            actions.push(Action::Closed);
+        }
+
+        actions
+    }
+
+    /// Reads at most `max_len` bytes from the stream, and returns the number
+    /// of bytes read.
+    fn read_stream(
+        &mut self, stream_id: u64, max_len: usize, actions: &mut Vec<Action>,
+    ) -> usize {
+        let mut data = Vec::new();
+        let mut fin = false;
+
+        while data.len() < max_len {
+            let len = cmp::min(self.buf.len(), max_len - data.len());
+
+            match self.conn.stream_recv(stream_id, &mut self.buf[..len]) {
+                Ok((len, f)) => {
+                    data.extend_from_slice(&self.buf[..len]);
+                    fin = f;
+
+                    if fin {
+                        break;
+                    }
+                },
+
+                Err(Error::Done) => break,
+
+                Err(Error::StreamReset(error_code)) => {
+                    actions.push(Action::StreamReset {
+                        stream_id,
+                        error_code,
+                    });
+
+                    return 0;
+                },
+
+                Err(_) => break,
+            }
+        }
+
+        let len = data.len();
+
+        if !data.is_empty() || fin {
+            actions.push(Action::StreamData {
+                stream_id,
+                data,
+                fin,
+            });
+        }
+
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    // Delivers the packets sent by the client in `actions` to the server, and
+    // the ones sent in response back to the client, until there are none
+    // left. The other actions of the client and the server are returned.
+    fn exchange(
+        client: &mut Driver, server: &mut Driver, mut actions: Vec<Action>,
+    ) -> (Vec<Action>, Vec<Action>) {
+        let mut client_actions = Vec::new();
+        let mut server_actions = Vec::new();
+
+        let mut from_client = true;
+
+        while !actions.is_empty() {
+            let (to, others) = if from_client {
+                (&mut *server, &mut client_actions)
+            } else {
+                (&mut *client, &mut server_actions)
+            };
+
+            let mut next = Vec::new();
+
+            for action in actions {
+                match action {
+                    Action::Send { mut data, info } => {
+                        let info = RecvInfo {
+                            from: info.from,
+                            to: info.to,
+                        };
+
+                        next.extend(to.recv(&mut data, info));
+                    },
+
+                    _ => others.push(action),
+                }
+            }
+
+            actions = next;
+            from_client = !from_client;
+        }
+
+        (client_actions, server_actions)
+    }
+
+    #[test]
+    fn handshake_and_stream_data() {
+        let testing::Pipe { client, server } = testing::Pipe::new().unwrap();
+
+        let mut client = Driver::new(client);
+        let mut server = Driver::new(server);
+
+        // The client's timer is armed along with its first flight.
+        let actions = client.poll();
+        assert!(actions.iter().any(|a| matches!(a, Action::ArmTimer(_))));
+
+        let (client_actions, server_actions) =
+            exchange(&mut client, &mut server, actions);
+        assert!(client_actions.contains(&Action::Established));
+        assert!(server_actions.contains(&Action::Established));
+
+        // The stream only accepts 15 bytes because of flow control.
+        assert_eq!(client.stream_send(0, &[0xab; 20], false), Ok(15));
+
+        let actions = client.poll();
+        assert!(!actions.contains(&Action::StreamWritable(0)));
+
+        let (client_actions, server_actions) =
+            exchange(&mut client, &mut server, actions);
+        assert!(server_actions.contains(&Action::StreamData {
+            stream_id: 0,
+            data: vec![0xab; 15],
+            fin: false,
+        }));
+
+        // The server read the data, so the stream can accept more.
+        assert!(client_actions.contains(&Action::StreamWritable(0)));
+
+        assert_eq!(client.stream_send(0, &[0xab; 5], true), Ok(5));
+
+        let actions = client.poll();
+
+        let (_, server_actions) = exchange(&mut client, &mut server, actions);
+        assert!(server_actions.contains(&Action::StreamData {
+            stream_id: 0,
+            data: vec![0xab; 5],
+            fin: true,
+        }));
+    }
+
+    #[test]
+    fn max_read_len() {
+        let testing::Pipe { client, server } = testing::Pipe::new().unwrap();
+
+        let mut client = Driver::new(client);
+        let mut server = Driver::new(server);
+        server.set_max_read_len(10);
+
+        let actions = client.poll();
+        exchange(&mut client, &mut server, actions);
+
+        assert_eq!(client.stream_send(0, &[0xab; 15], true), Ok(15));
+
+        // The packets are delivered to the server without its responses being
+        // sent back, so that it doesn't read again.
+        let mut server_actions = Vec::new();
+
+        for action in client.poll() {
+            if let Action::Send { mut data, info } = action {
+                let info = RecvInfo {
+                    from: info.from,
+                    to: info.to,
+                };
+
+                server_actions.extend(server.recv(&mut data, info));
+            }
+        }
+
+        // Only the first 10 bytes are read.
+        assert!(server_actions.contains(&Action::StreamData {
+            stream_id: 0,
+            data: vec![0xab; 10],
+            fin: false,
+        }));
+        assert!(server.conn().is_readable());
+
+        let actions = server.poll();
+        assert!(actions.contains(&Action::StreamData {
+            stream_id: 0,
+            data: vec![0xab; 5],
+            fin: true,
+        }));
+        assert!(!server.conn().is_readable());
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::frame::EcnCounts;
+use crate::packet;
+
+// The number of packets marked while testing whether the path supports ECN
+// (RFC 9000 Appendix A.4).
+const ECN_TESTING_PACKETS: usize = 10;
+
+/// The ECN codepoint of an IP packet.
+///
+/// The value of each variant is the one of the two ECN bits of the IP header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum EcnCodepoint {
+    /// Not ECN-Capable Transport.
+    NotEct = 0,
+    /// ECN-Capable Transport, ECT(1).
+    Ect1   = 1,
+    /// ECN-Capable Transport, ECT(0).
+    Ect0   = 2,
+    /// Congestion Experienced.
+    Ce     = 3,
+}
+
+impl From<u8> for EcnCodepoint {
+    /// Converts the Traffic Class (or IPv4 TOS) byte of an IP packet to its
+    /// ECN codepoint.
+    fn from(tos: u8) -> Self {
+        match tos & 0x03 {
+            0x01 => EcnCodepoint::Ect1,
+
+            0x02 => EcnCodepoint::Ect0,
+
+            0x03 => EcnCodepoint::Ce,
+
+            _ => EcnCodepoint::NotEct,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Validation {
+    /// The first packets are being marked.
+    Testing,
+
+    /// The testing packets were sent, but none of them was acknowledged yet.
+    Unknown,
+
+    /// The path supports ECN.
+    Capable,
+
+    /// The path or the peer doesn't support ECN.
+    Failed,
+}
+
+/// Keeps track of the ECN codepoints of sent and received packets.
+pub struct EcnState {
+    /// Whether outgoing packets are marked.
+    enabled: bool,
+
+    /// Whether the path supports ECN.
+    validation: Validation,
+
+    /// The number of packets sent marked with ECT(0).
+    marked_sent: usize,
+
+    /// The ECN counts received in each packet number space.
+    recv_counts: [EcnCounts; packet::Epoch::count()],
+
+    /// The ECN counts last reported by the peer in each packet number space.
+    peer_counts: [EcnCounts; packet::Epoch::count()],
+}
+
+impl EcnState {
+    pub fn new(enabled: bool) -> Self {
+        EcnState {
+            enabled,
+            validation: Validation::Testing,
+            marked_sent: 0,
+            recv_counts: Default::default(),
+            peer_counts: Default::default(),
+        }
+    }
+
+    /// Returns the codepoint outgoing packets are marked with.
+    pub fn codepoint(&self) -> EcnCodepoint {
+        match self.validation {
+            Validation::Testing | Validation::Capable if self.enabled =>
+                EcnCodepoint::Ect0,
+
+            _ => EcnCodepoint::NotEct,
+        }
+    }
+
+    /// Returns whether the path was validated as supporting ECN.
+    pub fn is_capable(&self) -> bool {
+        self.validation == Validation::Capable
+    }
+
+    /// Returns the number of packets sent marked with ECT(0).
+    pub fn marked_sent(&self) -> usize {
+        self.marked_sent
+    }
+
+    pub fn on_packet_sent(&mut self, codepoint: EcnCodepoint) {
+        if codepoint == EcnCodepoint::Ect0 {
+            self.marked_sent += 1;
+        }
+    }
+
+    /// Ends the testing period once enough packets were marked.
+    ///
+    /// This is only called once a whole datagram was sent, as all the packets
+    /// of a datagram are marked with the same codepoint.
+    pub fn on_datagram_sent(&mut self) {
+        if self.validation == Validation::Testing &&
+            self.marked_sent >= ECN_TESTING_PACKETS
+        {
+            self.validation = Validation::Unknown;
+        }
+    }
+
+    pub fn on_packet_received(
+        &mut self, epoch: packet::Epoch, codepoint: EcnCodepoint,
+    ) {
+        let counts = &mut self.recv_counts[epoch];
+
+        match codepoint {
+            EcnCodepoint::NotEct => (),
+
+            EcnCodepoint::Ect1 => counts.ect1_count += 1,
+
+            EcnCodepoint::Ect0 => counts.ect0_count += 1,
+
+            EcnCodepoint::Ce => counts.ecn_ce_count += 1,
+        }
+    }
+
+    /// Returns the ECN counts to report in an ACK frame of the given packet
+    /// number space, if any marked packet was received.
+    pub fn recv_counts(&self, epoch: packet::Epoch) -> Option<EcnCounts> {
+        let counts = self.recv_counts[epoch];
+
+        if counts == EcnCounts::default() {
+            return None;
+        }
+
+        Some(counts)
+    }
+
+    /// Returns the sum of the ECN counts received in all packet number spaces.
+    pub fn total_recv_counts(&self) -> EcnCounts {
+        sum_counts(&self.recv_counts)
+    }
+
+    /// Returns the sum of the ECN counts reported by the peer in all packet
+    /// number spaces.
+    pub fn total_peer_counts(&self) -> EcnCounts {
+        sum_counts(&self.peer_counts)
+    }
+
+    /// Validates the ECN counts of an ACK frame of the given packet number
+    /// space, as described in RFC 9000 Appendix A.4.
+    ///
+    /// `newly_acked_marked` is the number of marked packets newly acknowledged
+    /// by the frame, and `lost_marked` the total number of marked packets
+    /// declared lost so far.
+    ///
+    /// Returns whether the number of packets marked ECN-CE increased, which
+    /// signals congestion.
+    pub fn on_ack_received(
+        &mut self, epoch: packet::Epoch, counts: Option<&EcnCounts>,
+        newly_acked_marked: usize, lost_marked: usize,
+    ) -> bool {
+        if self.validation == Validation::Failed {
+            return false;
+        }
+
+        // All the testing packets were lost.
+        if self.validation == Validation::Unknown &&
+            newly_acked_marked == 0 &&
+            lost_marked >= self.marked_sent
+        {
+            self.validation = Validation::Failed;
+            return false;
+        }
+
+        let counts = match counts {
+            Some(v) => v,
+
+            // Marked packets were acknowledged without ECN counts, so either
+            // the peer doesn't support ECN, or the path clears the marks.
+            None if newly_acked_marked > 0 => {
+                self.validation = Validation::Failed;
+                return false;
+            },
+
+            None => return false,
+        };
+
+        let prev = self.peer_counts[epoch];
+
+        // ECN counts can't decrease, can't exceed the number of marked
+        // packets, and ECT(1) is never sent.
+        if counts.ect0_count < prev.ect0_count ||
+            counts.ecn_ce_count < prev.ecn_ce_count ||
+            counts.ect1_count != 0 ||
+            counts.ect0_count + counts.ecn_ce_count > self.marked_sent as u64
+        {
+            self.validation = Validation::Failed;
+            return false;
+        }
+
+        let ect0_increase = counts.ect0_count - prev.ect0_count;
+        let ce_increase = counts.ecn_ce_count - prev.ecn_ce_count;
+
+        // The marks of some of the newly acknowledged packets were cleared.
+        if ect0_increase + ce_increase < newly_acked_marked as u64 {
+            self.validation = Validation::Failed;
+            return false;
+        }
+
+        self.peer_counts[epoch] = *counts;
+
+        if newly_acked_marked > 0 {
+            self.validation = Validation::Capable;
+        }
+
+        ce_increase > 0
+    }
+}
+
+fn sum_counts(counts: &[EcnCounts]) -> EcnCounts {
+    counts
+        .iter()
+        .fold(EcnCounts::default(), |acc, c| EcnCounts {
+            ect0_count: acc.ect0_count + c.ect0_count,
+            ect1_count: acc.ect1_count + c.ect1_count,
+            ecn_ce_count: acc.ecn_ce_count + c.ecn_ce_count,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(ect0_count: u64, ecn_ce_count: u64) -> EcnCounts {
+        EcnCounts {
+            ect0_count,
+            ect1_count: 0,
+            ecn_ce_count,
+        }
+    }
+
+    #[test]
+    fn codepoint_from_tos() {
+        assert_eq!(EcnCodepoint::from(0xb8), EcnCodepoint::NotEct);
+        assert_eq!(EcnCodepoint::from(0xb9), EcnCodepoint::Ect1);
+        assert_eq!(EcnCodepoint::from(0xba), EcnCodepoint::Ect0);
+        assert_eq!(EcnCodepoint::from(0xbb), EcnCodepoint::Ce);
+    }
+
+    #[test]
+    fn validation() {
+        let epoch = packet::Epoch::Application;
+
+        let mut ecn = EcnState::new(true);
+
+        for _ in 0..ECN_TESTING_PACKETS {
+            assert_eq!(ecn.codepoint(), EcnCodepoint::Ect0);
+            ecn.on_packet_sent(EcnCodepoint::Ect0);
+            ecn.on_datagram_sent();
+        }
+
+        // Marking stops until the testing packets are acknowledged.
+        assert_eq!(ecn.codepoint(), EcnCodepoint::NotEct);
+
+        assert!(!ecn.on_ack_received(epoch, Some(&counts(2, 0)), 2, 0));
+        assert!(ecn.is_capable());
+        assert_eq!(ecn.codepoint(), EcnCodepoint::Ect0);
+
+        // Congestion experienced.
+        assert!(ecn.on_ack_received(epoch, Some(&counts(4, 1)), 3, 0));
+        assert!(!ecn.on_ack_received(epoch, Some(&counts(5, 1)), 1, 0));
+        assert_eq!(ecn.total_peer_counts(), counts(5, 1));
+
+        // The counts decreased.
+        assert!(!ecn.on_ack_received(epoch, Some(&counts(4, 1)), 0, 0));
+        assert!(!ecn.is_capable());
+        assert_eq!(ecn.codepoint(), EcnCodepoint::NotEct);
+    }
+
+    #[test]
+    fn validation_failed() {
+        let epoch = packet::Epoch::Application;
+
+        // Marks are cleared on the path.
+        let mut ecn = EcnState::new(true);
+        ecn.on_packet_sent(EcnCodepoint::Ect0);
+        ecn.on_packet_sent(EcnCodepoint::Ect0);
+
+        assert!(!ecn.on_ack_received(epoch, Some(&counts(1, 0)), 2, 0));
+        assert_eq!(ecn.codepoint(), EcnCodepoint::NotEct);
+
+        // The peer doesn't report ECN counts.
+        let mut ecn = EcnState::new(true);
+        ecn.on_packet_sent(EcnCodepoint::Ect0);
+
+        assert!(!ecn.on_ack_received(epoch, None, 1, 0));
+        assert_eq!(ecn.codepoint(), EcnCodepoint::NotEct);
+
+        // All the testing packets are lost.
+        let mut ecn = EcnState::new(true);
+
+        for _ in 0..ECN_TESTING_PACKETS {
+            ecn.on_packet_sent(EcnCodepoint::Ect0);
+            ecn.on_datagram_sent();
+        }
+
+        assert!(!ecn.on_ack_received(epoch, None, 0, ECN_TESTING_PACKETS));
+        assert!(!ecn.on_ack_received(epoch, Some(&counts(1, 0)), 1, 0));
+        assert!(!ecn.is_capable());
+    }
+
+    #[test]
+    fn recv_counts() {
+        let mut ecn = EcnState::new(false);
+
+        assert_eq!(ecn.codepoint(), EcnCodepoint::NotEct);
+
+        ecn.on_packet_received(packet::Epoch::Application, EcnCodepoint::NotEct);
+        assert_eq!(ecn.recv_counts(packet::Epoch::Application), None);
+
+        ecn.on_packet_received(packet::Epoch::Application, EcnCodepoint::Ect0);
+        ecn.on_packet_received(packet::Epoch::Application, EcnCodepoint::Ce);
+        ecn.on_packet_received(packet::Epoch::Handshake, EcnCodepoint::Ect1);
+
+        assert_eq!(
+            ecn.recv_counts(packet::Epoch::Application),
+            Some(counts(1, 1))
+        );
+        assert_eq!(ecn.recv_counts(packet::Epoch::Initial), None);
+        assert_eq!(ecn.total_recv_counts(), EcnCounts {
+            ect0_count: 1,
+            ect1_count: 1,
+            ecn_ce_count: 1,
+        });
+    }
+}
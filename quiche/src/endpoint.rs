@@ -0,0 +1,963 @@
+// Copyright (C) 2023, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Server-side endpoint.
+//!
+//! A server needs to route the packets it receives to the right connection
+//! based on their Destination Connection ID, and to answer the packets that
+//! don't belong to any connection: by creating a new connection, or with a
+//! Version Negotiation, Retry or Stateless Reset packet. An [`Endpoint`]
+//! implements that logic on top of a table of connections, without doing
+//! any I/O itself.
+//!
+//! [`Endpoint`]: ../struct.Endpoint.html
+
+use std::cmp;
+use std::time;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use std::net::SocketAddr;
+
+use ring::hmac;
+
+use crate::packet;
+use crate::rand;
+
+use crate::AddressToken;
+use crate::Config;
+use crate::Connection;
+use crate::ConnectionId;
+use crate::EcnCodepoint;
+use crate::Error;
+use crate::RecvInfo;
+use crate::Result;
+use crate::SendInfo;
+
+/// The maximum number of stateless packets waiting to be sent.
+///
+/// Packets are dropped once this is reached, so that a flood of unknown
+/// packets doesn't make the endpoint buffer an unbounded amount of data.
+const MAX_STATELESS_PACKETS: usize = 1024;
+
+/// The maximum length of a Stateless Reset packet.
+const MAX_STATELESS_RESET_LEN: usize = 43;
+
+/// The minimum length of a Stateless Reset packet, as mandated by RFC 9000
+/// to make it indistinguishable from a regular short header packet.
+const MIN_STATELESS_RESET_LEN: usize = 21;
+
+/// The length of a stateless reset token.
+const RESET_TOKEN_LEN: usize = 16;
+
+/// An opaque handle to a connection owned by an [`Endpoint`].
+///
+/// [`Endpoint`]: struct.Endpoint.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionHandle(u64);
+
+/// The outcome of an [`Endpoint::recv()`] call.
+///
+/// [`Endpoint::recv()`]: struct.Endpoint.html#method.recv
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndpointEvent {
+    /// A new connection was created for the packet.
+    NewConnection(ConnectionHandle),
+
+    /// The packet was processed by an existing connection.
+    Connection(ConnectionHandle),
+
+    /// A Version Negotiation, Retry or Stateless Reset packet was queued in
+    /// response to the packet.
+    Stateless,
+
+    /// The packet was dropped.
+    Dropped,
+}
+
+struct Entry {
+    conn: Connection,
+
+    /// The connection IDs routed to the connection.
+    ids: Vec<Vec<u8>>,
+
+    /// Whether the connection is in the endpoint's ready queue.
+    ready: bool,
+}
+
+/// A server-side QUIC endpoint.
+///
+/// Packets received on the endpoint's socket are passed to [`recv()`], and
+/// the packets to send are collected with [`send()`] until it returns
+/// [`Done`]. The connection timers are handled with [`timeout()`] and
+/// [`on_timeout()`], and connections are removed from the endpoint once
+/// closed with [`remove_closed()`]:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+/// let local = "127.0.0.1:4433".parse().unwrap();
+/// let socket = std::net::UdpSocket::bind(local).unwrap();
+///
+/// let mut endpoint = quiche::Endpoint::new(config, local);
+/// let mut buf = [0; 65535];
+///
+/// loop {
+///     socket.set_read_timeout(endpoint.timeout()).unwrap();
+///
+///     match socket.recv_from(&mut buf) {
+///         Ok((len, from)) => {
+///             endpoint.recv(&mut buf[..len], from);
+///         },
+///
+///         Err(_) => endpoint.on_timeout(),
+///     }
+///
+///     while let Ok((len, info)) = endpoint.send(&mut buf) {
+///         socket.send_to(&buf[..len], info.to).unwrap();
+///     }
+///
+///     endpoint.remove_closed();
+/// }
+/// # Ok::<(), quiche::Error>(())
+/// ```
+///
+/// The connections use source connection IDs of [`MAX_CONN_ID_LEN`] bytes,
+/// so that the packets with a short header can be routed to them. Each
+/// source connection ID is advertised with a stateless reset token derived
+/// from it, which lets the endpoint reset the connections it lost the state
+/// of, e.g. after a restart, as long as the same key is configured with
+/// [`set_stateless_reset_key()`].
+///
+/// [`recv()`]: struct.Endpoint.html#method.recv
+/// [`send()`]: struct.Endpoint.html#method.send
+/// [`Done`]: enum.Error.html#variant.Done
+/// [`timeout()`]: struct.Endpoint.html#method.timeout
+/// [`on_timeout()`]: struct.Endpoint.html#method.on_timeout
+/// [`remove_closed()`]: struct.Endpoint.html#method.remove_closed
+/// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+/// [`set_stateless_reset_key()`]:
+/// struct.Endpoint.html#method.set_stateless_reset_key
+pub struct Endpoint {
+    config: Config,
+
+    local: SocketAddr,
+
+    conns: BTreeMap<ConnectionHandle, Entry>,
+
+    ids: HashMap<Vec<u8>, ConnectionHandle>,
+
+    next_handle: u64,
+
+    /// The connections that might have packets to send, in the order they
+    /// get to send them.
+    ready: VecDeque<ConnectionHandle>,
+
+    stateless: VecDeque<(Vec<u8>, SocketAddr)>,
+
+    reset_key: hmac::Key,
+
+    retry: bool,
+}
+
+impl Endpoint {
+    /// Creates a new endpoint bound to the `local` address.
+    ///
+    /// The connections accepted by the endpoint are created with `config`.
+    pub fn new(config: Config, local: SocketAddr) -> Endpoint {
+        let mut key = [0; 32];
+        rand::rand_bytes(&mut key);
+
+        Endpoint {
+            config,
+
+            local,
+
+            conns: BTreeMap::new(),
+
+            ids: HashMap::new(),
+
+            next_handle: 0,
+
+            ready: VecDeque::new(),
+
+            stateless: VecDeque::new(),
+
+            reset_key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+
+            retry: false,
+        }
+    }
+
+    /// Sets the key the stateless reset tokens are derived from.
+    ///
+    /// Endpoints that need to reset the connections of a previous instance,
+    /// or of another server behind the same load balancer, need to share
+    /// this key. Only the connections accepted afterwards use it.
+    ///
+    /// The default value is a random key.
+    pub fn set_stateless_reset_key(&mut self, key: &[u8]) {
+        self.reset_key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    }
+
+    /// Configures whether to validate the address of clients with a Retry
+    /// packet before creating connections.
+    ///
+    /// The Retry tokens are created and validated by the minter set with
+    /// [`set_token_minter()`] on the endpoint's configuration, and this has
+    /// no effect when no minter is configured. Clients with a valid NEW_TOKEN
+    /// token are not sent a Retry packet, while an invalid NEW_TOKEN token is
+    /// handled as if the client didn't send any.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`set_token_minter()`]: struct.Config.html#method.set_token_minter
+    pub fn enable_retry(&mut self, v: bool) {
+        self.retry = v;
+    }
+
+    /// Processes a packet received from `from`.
+    ///
+    /// The packet is passed to the connection it belongs to, if any, or to
+    /// a new connection if it's a client's Initial packet. Otherwise the
+    /// endpoint might queue a stateless packet in response, to be sent with
+    /// [`send()`].
+    ///
+    /// [`send()`]: struct.Endpoint.html#method.send
+    pub fn recv(&mut self, buf: &mut [u8], from: SocketAddr) -> EndpointEvent {
+        let dcid_len = crate::MAX_CONN_ID_LEN;
+
+        let hdr = match packet::Header::from_slice(buf, dcid_len) {
+            Ok(v) => v,
+
+            Err(e) => {
+                trace!("dropping unparsable packet from {}: {:?}", from, e);
+                return EndpointEvent::Dropped;
+            },
+        };
+
+        let recv_info = RecvInfo {
+            from,
+            to: self.local,
+        };
+
+        if let Some(&handle) = self.ids.get(&hdr.dcid[..]) {
+            if let Some(entry) = self.conns.get_mut(&handle) {
+                if let Err(e) = entry.conn.recv(buf, recv_info) {
+                    trace!("{} recv failed: {:?}", entry.conn.trace_id(), e);
+                }
+
+                while let Some(cid) = entry.conn.retired_scid_next() {
+                    self.ids.remove(&cid[..]);
+                    entry.ids.retain(|id| id[..] != cid[..]);
+                }
+            }
+
+            self.mark_ready(handle);
+
+            return EndpointEvent::Connection(handle);
+        }
+
+        if hdr.ty == packet::Type::Short {
+            return self.stateless_reset(&hdr.dcid, buf.len(), from);
+        }
+
+        // Clients pad their Initial packets, so smaller packets can't start
+        // a connection, and aren't answered to avoid amplification attacks.
+        if buf.len() < crate::MIN_CLIENT_INITIAL_LEN {
+            return EndpointEvent::Dropped;
+        }
+
+        if !crate::version_is_supported(hdr.version) {
+            let mut out = [0; crate::MIN_CLIENT_INITIAL_LEN];
+
+            return match crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut out)
+            {
+                Ok(len) => self.queue_stateless(out[..len].to_vec(), from),
+
+                Err(_) => EndpointEvent::Dropped,
+            };
+        }
+
+        if hdr.ty != packet::Type::Initial {
+            return EndpointEvent::Dropped;
+        }
+
+        let validated =
+            validate_initial(&self.config, &hdr, from, self.retry, || {
+                self.new_cid()
+            });
+
+        let (scid, token) = match validated {
+            InitialToken::Accept(scid, token) => (scid, token),
+
+            InitialToken::Retry(pkt) => return self.queue_stateless(pkt, from),
+
+            InitialToken::Drop => return EndpointEvent::Dropped,
+        };
+
+        let reset_token = self.reset_token(&scid);
+        self.config.set_stateless_reset_token(Some(reset_token));
+
+        let conn =
+            accept_initial(&scid, token, self.local, from, &mut self.config);
+
+        let mut conn = match conn {
+            Ok(v) => v,
+
+            Err(e) => {
+                trace!("failed to accept connection from {}: {:?}", from, e);
+                return EndpointEvent::Dropped;
+            },
+        };
+
+        if let Err(e) = conn.recv(buf, recv_info) {
+            trace!("{} first recv failed: {:?}", conn.trace_id(), e);
+            return EndpointEvent::Dropped;
+        }
+
+        let handle = ConnectionHandle(self.next_handle);
+        self.next_handle += 1;
+
+        let mut ids = vec![scid];
+
+        // Retransmissions of the client's first packets still use the
+        // Destination Connection ID it initially picked.
+        if hdr.dcid[..] != ids[0][..] {
+            ids.push(hdr.dcid.to_vec());
+        }
+
+        for id in &ids {
+            self.ids.insert(id.clone(), handle);
+        }
+
+        self.conns.insert(handle, Entry {
+            conn,
+            ids,
+            ready: false,
+        });
+
+        self.mark_ready(handle);
+
+        EndpointEvent::NewConnection(handle)
+    }
+
+    /// Writes a single packet to be sent to a peer.
+    ///
+    /// Stateless packets are written first, and then the packets of the
+    /// connections in turn. Only the connections that received packets, had
+    /// their timer fire, or were accessed with [`conn_mut()`] since they last
+    /// ran out of packets to send are polled. The [`Done`] error is returned
+    /// when there is nothing to send.
+    ///
+    /// [`conn_mut()`]: struct.Endpoint.html#method.conn_mut
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn send(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        if let Some((pkt, to)) = self.stateless.front() {
+            if out.len() < pkt.len() {
+                return Err(Error::BufferTooShort);
+            }
+
+            let len = pkt.len();
+            out[..len].copy_from_slice(pkt);
+
+            let info = SendInfo {
+                from: self.local,
+                to: *to,
+                at: time::Instant::now(),
+                ecn: EcnCodepoint::NotEct,
+            };
+
+            self.stateless.pop_front();
+
+            return Ok((len, info));
+        }
+
+        // Each connection is polled at most once per call, so that a
+        // connection failing to send can't keep the endpoint busy.
+        for _ in 0..self.ready.len() {
+            let handle = match self.ready.pop_front() {
+                Some(v) => v,
+
+                None => break,
+            };
+
+            let entry = match self.conns.get_mut(&handle) {
+                Some(v) => v,
+
+                None => continue,
+            };
+
+            match entry.conn.send(out) {
+                // The connection goes to the back of the queue, so that all
+                // connections get a chance to send.
+                Ok(v) => {
+                    self.ready.push_back(handle);
+                    return Ok(v);
+                },
+
+                Err(Error::Done) => entry.ready = false,
+
+                Err(e) => {
+                    error!("{} send failed: {:?}", entry.conn.trace_id(), e);
+
+                    entry.conn.close(false, 0x1, b"fail").ok();
+
+                    self.ready.push_back(handle);
+                },
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Returns the amount of time until the earliest connection timeout
+    /// event, if any.
+    pub fn timeout(&self) -> Option<time::Duration> {
+        self.conns
+            .values()
+            .filter_map(|entry| entry.conn.timeout())
+            .min()
+    }
+
+    /// Processes the timeout events of the connections whose timer expired.
+    pub fn on_timeout(&mut self) {
+        let now = time::Instant::now();
+
+        for (handle, entry) in self.conns.iter_mut() {
+            if entry.conn.timeout_instant().map_or(false, |t| t <= now) {
+                entry.conn.on_timeout();
+
+                if !entry.ready {
+                    entry.ready = true;
+                    self.ready.push_back(*handle);
+                }
+            }
+        }
+    }
+
+    /// Removes the closed connections from the endpoint, and returns them.
+    pub fn remove_closed(&mut self) -> Vec<(ConnectionHandle, Connection)> {
+        let closed: Vec<ConnectionHandle> = self
+            .conns
+            .iter()
+            .filter(|(_, entry)| entry.conn.is_closed())
+            .map(|(handle, _)| *handle)
+            .collect();
+
+        let mut removed = Vec::with_capacity(closed.len());
+
+        for handle in closed {
+            if let Some(entry) = self.conns.remove(&handle) {
+                for id in &entry.ids {
+                    self.ids.remove(id);
+                }
+
+                removed.push((handle, entry.conn));
+            }
+        }
+
+        removed
+    }
+
+    /// Provides a new source connection ID to the connection's peer.
+    ///
+    /// This is the same as [`Connection::new_source_cid()`], but the
+    /// connection ID and its stateless reset token are created by the
+    /// endpoint, which routes the packets using it to the connection.
+    ///
+    /// [`Connection::new_source_cid()`]:
+    /// struct.Connection.html#method.new_source_cid
+    pub fn new_source_cid(&mut self, handle: ConnectionHandle) -> Result<u64> {
+        let scid = self.new_cid();
+        let reset_token = self.reset_token(&scid);
+
+        let entry = self.conns.get_mut(&handle).ok_or(Error::InvalidState)?;
+
+        let seq = entry.conn.new_source_cid(
+            &ConnectionId::from_ref(&scid),
+            reset_token,
+            false,
+        )?;
+
+        self.ids.insert(scid.clone(), handle);
+        entry.ids.push(scid);
+
+        self.mark_ready(handle);
+
+        Ok(seq)
+    }
+
+    /// Returns the connection with the given handle, if it exists.
+    pub fn conn(&self, handle: ConnectionHandle) -> Option<&Connection> {
+        self.conns.get(&handle).map(|entry| &entry.conn)
+    }
+
+    /// Returns a mutable reference to the connection with the given handle,
+    /// if it exists.
+    ///
+    /// The connection is then polled by [`send()`], as it might have new
+    /// packets to send.
+    ///
+    /// [`send()`]: struct.Endpoint.html#method.send
+    pub fn conn_mut(
+        &mut self, handle: ConnectionHandle,
+    ) -> Option<&mut Connection> {
+        self.mark_ready(handle);
+
+        self.conns.get_mut(&handle).map(|entry| &mut entry.conn)
+    }
+
+    /// Returns an iterator over the handles of the endpoint's connections.
+    pub fn connections(&self) -> impl Iterator<Item = ConnectionHandle> + '_ {
+        self.conns.keys().copied()
+    }
+
+    /// Queues the connection to be polled by `send()`, unless it already is.
+    fn mark_ready(&mut self, handle: ConnectionHandle) {
+        if let Some(entry) = self.conns.get_mut(&handle) {
+            if !entry.ready {
+                entry.ready = true;
+                self.ready.push_back(handle);
+            }
+        }
+    }
+
+    fn new_cid(&self) -> Vec<u8> {
+        loop {
+            let mut cid = vec![0; crate::MAX_CONN_ID_LEN];
+            rand::rand_bytes(&mut cid);
+
+            if !self.ids.contains_key(&cid) {
+                return cid;
+            }
+        }
+    }
+
+    fn reset_token(&self, cid: &[u8]) -> u128 {
+        let tag = hmac::sign(&self.reset_key, cid);
+
+        let mut token = [0; RESET_TOKEN_LEN];
+        token.copy_from_slice(&tag.as_ref()[..RESET_TOKEN_LEN]);
+
+        u128::from_be_bytes(token)
+    }
+
+    fn stateless_reset(
+        &mut self, dcid: &[u8], len: usize, from: SocketAddr,
+    ) -> EndpointEvent {
+        // Stateless Reset packets are smaller than the packets triggering
+        // them, so that two endpoints can't keep resetting each other.
+        if len <= MIN_STATELESS_RESET_LEN {
+            return EndpointEvent::Dropped;
+        }
+
+        let len = cmp::min(len - 1, MAX_STATELESS_RESET_LEN);
+
+        let mut pkt = vec![0; len];
+        rand::rand_bytes(&mut pkt[..len - RESET_TOKEN_LEN]);
+
+        // Short header form, with the fixed bit set.
+        pkt[0] = (pkt[0] & 0x3f) | 0x40;
+
+        let token = self.reset_token(dcid);
+        pkt[len - RESET_TOKEN_LEN..].copy_from_slice(&token.to_be_bytes());
+
+        self.queue_stateless(pkt, from)
+    }
+
+    fn queue_stateless(&mut self, pkt: Vec<u8>, to: SocketAddr) -> EndpointEvent {
+        if self.stateless.len() >= MAX_STATELESS_PACKETS {
+            return EndpointEvent::Dropped;
+        }
+
+        self.stateless.push_back((pkt, to));
+
+        EndpointEvent::Stateless
+    }
+}
+
+/// The outcome of validating the token of a client's Initial packet with
+/// [`validate_initial()`].
+///
+/// [`validate_initial()`]: fn.validate_initial.html
+pub(crate) enum InitialToken {
+    /// A connection can be created with the given source connection ID, and
+    /// the validated token if any.
+    Accept(Vec<u8>, Option<AddressToken>),
+
+    /// The client needs to be sent the given Retry packet first.
+    Retry(Vec<u8>),
+
+    /// The packet needs to be dropped.
+    Drop,
+}
+
+/// Validates the token of the Initial packet with header `hdr` received from
+/// `from`, with the token minter of `config`.
+///
+/// When `retry` is true and a minter is configured, clients without a valid
+/// token are sent a Retry packet. The source connection IDs of new
+/// connections are created with `new_cid`.
+pub(crate) fn validate_initial(
+    config: &Config, hdr: &packet::Header, from: SocketAddr, retry: bool,
+    new_cid: impl FnOnce() -> Vec<u8>,
+) -> InitialToken {
+    let token = hdr.token.as_deref().unwrap_or(&[]);
+
+    let minter = config.token_minter.as_ref();
+
+    let token = match minter {
+        Some(minter) if !token.is_empty() =>
+            match minter.validate(token, &from) {
+                Ok(v) => Some(v),
+
+                // An invalid Retry token means the retry failed.
+                Err(e) if minter.is_retry_token(token) => {
+                    trace!("invalid Retry token from {}: {:?}", from, e);
+                    return InitialToken::Drop;
+                },
+
+                // An invalid NEW_TOKEN token is handled as if the client
+                // didn't send any token (RFC 9000 section 8.1.3).
+                Err(e) => {
+                    trace!("ignoring invalid token from {}: {:?}", from, e);
+                    None
+                },
+            },
+
+        _ => None,
+    };
+
+    match token {
+        // The client's new Destination Connection ID is the one chosen by the
+        // endpoint in the Retry packet.
+        Some(token @ AddressToken::Retry { .. })
+            if hdr.dcid.len() == crate::MAX_CONN_ID_LEN =>
+            InitialToken::Accept(hdr.dcid.to_vec(), Some(token)),
+
+        Some(AddressToken::Retry { .. }) => InitialToken::Drop,
+
+        Some(token @ AddressToken::NewToken) =>
+            InitialToken::Accept(new_cid(), Some(token)),
+
+        None if retry && minter.is_some() => {
+            let new_scid = ConnectionId::from_vec(new_cid());
+
+            let mut out = [0; crate::MIN_CLIENT_INITIAL_LEN];
+
+            match crate::retry_minted(
+                &hdr.scid,
+                &hdr.dcid,
+                &new_scid,
+                &from,
+                hdr.version,
+                &mut out,
+                config,
+            ) {
+                Ok(len) => InitialToken::Retry(out[..len].to_vec()),
+
+                Err(_) => InitialToken::Drop,
+            }
+        },
+
+        None => InitialToken::Accept(new_cid(), None),
+    }
+}
+
+/// Creates a connection for a client whose Initial packet was accepted by
+/// [`validate_initial()`].
+///
+/// [`validate_initial()`]: fn.validate_initial.html
+pub(crate) fn accept_initial(
+    scid: &[u8], token: Option<AddressToken>, local: SocketAddr,
+    peer: SocketAddr, config: &mut Config,
+) -> Result<Connection> {
+    let scid = ConnectionId::from_ref(scid);
+
+    match token {
+        Some(token) => crate::accept_validated(&scid, token, local, peer, config),
+
+        None => crate::accept(&scid, None, local, peer, config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::testing;
+    use crate::KeyRing;
+    use crate::KeyRingTokenMinter;
+    use crate::TokenMinter;
+
+    fn server_config() -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        config
+    }
+
+    fn token_minter() -> KeyRingTokenMinter {
+        let ring = KeyRing::new(
+            time::Duration::from_secs(60),
+            time::Duration::from_secs(60),
+        );
+
+        KeyRingTokenMinter::new(
+            Arc::new(ring),
+            time::Duration::from_secs(10),
+            time::Duration::from_secs(60),
+        )
+    }
+
+    fn retry_endpoint() -> Endpoint {
+        let mut config = server_config();
+        config.set_token_minter(Some(Arc::new(token_minter())));
+
+        let mut endpoint = Endpoint::new(config, testing::Pipe::server_addr());
+        endpoint.enable_retry(true);
+
+        endpoint
+    }
+
+    fn client(version: u32) -> Connection {
+        let mut config = Config::new(version).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+
+        let mut scid = [0; 16];
+        rand::rand_bytes(&mut scid);
+
+        crate::connect(
+            Some("quic.tech"),
+            &ConnectionId::from_ref(&scid),
+            testing::Pipe::client_addr(),
+            testing::Pipe::server_addr(),
+            &mut config,
+        )
+        .unwrap()
+    }
+
+    /// Exchanges packets between the client and the endpoint until neither
+    /// has anything to send, and returns the endpoint's events.
+    fn exchange(
+        client: &mut Connection, endpoint: &mut Endpoint,
+    ) -> Vec<EndpointEvent> {
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
+
+        let mut buf = [0; 65535];
+        let mut events = Vec::new();
+
+        loop {
+            let mut sent = false;
+
+            while let Ok((len, _)) = client.send(&mut buf) {
+                events.push(endpoint.recv(&mut buf[..len], client_addr));
+                sent = true;
+            }
+
+            while let Ok((len, info)) = endpoint.send(&mut buf) {
+                assert_eq!(info.to, client_addr);
+
+                let recv_info = RecvInfo {
+                    from: server_addr,
+                    to: client_addr,
+                };
+
+                client.recv(&mut buf[..len], recv_info).ok();
+                sent = true;
+            }
+
+            if !sent {
+                return events;
+            }
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let mut endpoint =
+            Endpoint::new(server_config(), testing::Pipe::server_addr());
+
+        let mut client = client(crate::PROTOCOL_VERSION);
+
+        let events = exchange(&mut client, &mut endpoint);
+
+        let handle = match events[0] {
+            EndpointEvent::NewConnection(handle) => handle,
+
+            event => panic!("unexpected event {:?}", event),
+        };
+
+        assert!(events[1..]
+            .iter()
+            .all(|e| *e == EndpointEvent::Connection(handle)));
+
+        assert!(client.is_established());
+        assert!(endpoint.conn(handle).unwrap().is_established());
+        assert_eq!(endpoint.connections().collect::<Vec<_>>(), vec![handle]);
+
+        // Closed connections are removed from the endpoint.
+        assert_eq!(client.close(true, 0x0, b""), Ok(()));
+        exchange(&mut client, &mut endpoint);
+
+        let conn = endpoint.conn_mut(handle).unwrap();
+        assert!(conn.is_draining());
+
+        // Expire the draining period right away.
+        conn.draining_timer = Some(time::Instant::now());
+        endpoint.on_timeout();
+
+        let removed = endpoint.remove_closed();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0, handle);
+        assert!(endpoint.conn(handle).is_none());
+    }
+
+    #[test]
+    fn retry() {
+        let mut endpoint = retry_endpoint();
+
+        let mut client = client(crate::PROTOCOL_VERSION);
+
+        let events = exchange(&mut client, &mut endpoint);
+
+        // The first Initial is answered with a Retry packet.
+        assert_eq!(events[0], EndpointEvent::Stateless);
+
+        let handle = match events[1] {
+            EndpointEvent::NewConnection(handle) => handle,
+
+            event => panic!("unexpected event {:?}", event),
+        };
+
+        assert!(client.is_established());
+
+        let conn = endpoint.conn(handle).unwrap();
+        assert!(conn.is_established());
+        assert!(conn.paths.get_active().unwrap().verified_peer_address);
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        let mut endpoint = retry_endpoint();
+
+        // Tokens minted with another key can't be validated.
+        let other = token_minter();
+        let from = testing::Pipe::client_addr();
+
+        // An invalid NEW_TOKEN token is ignored, so a Retry is sent.
+        let mut new_token_client = client(crate::PROTOCOL_VERSION);
+
+        let token = other.mint(&AddressToken::NewToken, &from).unwrap();
+        new_token_client.token = Some(token);
+
+        let events = exchange(&mut new_token_client, &mut endpoint);
+
+        assert_eq!(events[0], EndpointEvent::Stateless);
+        assert!(matches!(events[1], EndpointEvent::NewConnection(_)));
+        assert!(new_token_client.is_established());
+
+        // An invalid Retry token means the retry failed.
+        let mut retry_client = client(crate::PROTOCOL_VERSION);
+
+        let token = AddressToken::Retry {
+            odcid: ConnectionId::from_vec(vec![0xba; 16]),
+        };
+        retry_client.token = Some(other.mint(&token, &from).unwrap());
+
+        let events = exchange(&mut retry_client, &mut endpoint);
+
+        assert_eq!(events, vec![EndpointEvent::Dropped]);
+        assert!(!retry_client.is_established());
+    }
+
+    #[test]
+    fn version_negotiation() {
+        let mut endpoint =
+            Endpoint::new(server_config(), testing::Pipe::server_addr());
+
+        let mut client = client(0xbabababa);
+
+        let events = exchange(&mut client, &mut endpoint);
+
+        assert_eq!(events[0], EndpointEvent::Stateless);
+        assert!(matches!(events[1], EndpointEvent::NewConnection(_)));
+
+        assert!(client.is_established());
+        assert_eq!(client.version, crate::PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn stateless_reset() {
+        let mut endpoint =
+            Endpoint::new(server_config(), testing::Pipe::server_addr());
+
+        let from = testing::Pipe::client_addr();
+
+        let mut buf = [0; 65535];
+
+        // Too short to be answered with a smaller Stateless Reset.
+        let mut pkt = [0x40; MIN_STATELESS_RESET_LEN];
+        assert_eq!(endpoint.recv(&mut pkt, from), EndpointEvent::Dropped);
+        assert_eq!(endpoint.send(&mut buf), Err(Error::Done));
+
+        let mut pkt = [0x40; 50];
+        assert_eq!(endpoint.recv(&mut pkt, from), EndpointEvent::Stateless);
+
+        let (len, info) = endpoint.send(&mut buf).unwrap();
+        assert_eq!(len, MAX_STATELESS_RESET_LEN);
+        assert_eq!(info.to, from);
+        assert_eq!(buf[0] & 0xc0, 0x40);
+
+        let dcid = &pkt[1..1 + crate::MAX_CONN_ID_LEN];
+        assert_eq!(
+            buf[len - RESET_TOKEN_LEN..len],
+            endpoint.reset_token(dcid).to_be_bytes()
+        );
+
+        assert_eq!(endpoint.send(&mut buf), Err(Error::Done));
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::VecDeque;
+
+/// A queue of events to be notified to the application.
+///
+/// Once the queue holds `max_len` events, new events are dropped until the
+/// application retrieves some of them, so that a slow consumer doesn't make
+/// the queue grow without bounds.
+pub struct EventQueue<T> {
+    events: VecDeque<T>,
+
+    /// The maximum number of queued events.
+    max_len: usize,
+
+    /// The number of events dropped because the queue was full.
+    dropped: usize,
+}
+
+impl<T> EventQueue<T> {
+    pub fn new(max_len: usize) -> Self {
+        EventQueue {
+            events: VecDeque::new(),
+            max_len,
+            dropped: 0,
+        }
+    }
+
+    /// Sets the maximum number of queued events.
+    ///
+    /// Events already queued are kept, even if there are more of them.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Queues an event, unless the queue is full. Returns whether the event
+    /// was queued.
+    pub fn push(&mut self, ev: T) -> bool {
+        if self.events.len() >= self.max_len {
+            self.dropped += 1;
+            return false;
+        }
+
+        self.events.push_back(ev);
+
+        true
+    }
+
+    /// Returns the oldest queued event.
+    pub fn pop(&mut self) -> Option<T> {
+        self.events.pop_front()
+    }
+
+    /// Returns the number of events dropped because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_when_full() {
+        let mut queue = EventQueue::new(2);
+
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(!queue.push(3));
+        assert_eq!(queue.dropped(), 1);
+
+        assert_eq!(queue.pop(), Some(1));
+        assert!(queue.push(4));
+
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(4));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.dropped(), 1);
+    }
+
+    #[test]
+    fn set_max_len() {
+        let mut queue = EventQueue::new(3);
+
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(queue.push(3));
+
+        // Queued events are kept.
+        queue.set_max_len(1);
+        assert!(!queue.push(4));
+
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert!(!queue.push(5));
+
+        assert_eq!(queue.pop(), Some(3));
+        assert!(queue.push(6));
+        assert_eq!(queue.dropped(), 2);
+    }
+}
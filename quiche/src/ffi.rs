@@ -28,6 +28,7 @@ use std::ffi;
 use std::ptr;
 use std::slice;
 use std::sync::atomic;
+use std::time;
 
 use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
@@ -206,11 +207,21 @@ pub extern fn quiche_config_verify_peer(config: &mut Config, v: bool) {
     config.verify_peer(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_verify_peer_required(config: &mut Config, v: bool) {
+    config.verify_peer_required(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_grease(config: &mut Config, v: bool) {
     config.grease(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_grease_quic_bit(config: &mut Config, v: bool) {
+    config.grease_quic_bit(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_log_keys(config: &mut Config) {
     config.log_keys();
@@ -241,6 +252,11 @@ pub extern fn quiche_config_set_max_idle_timeout(config: &mut Config, v: u64) {
     config.set_max_idle_timeout(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_handshake_timeout(config: &mut Config, v: u64) {
+    config.set_handshake_timeout(time::Duration::from_millis(v));
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_max_recv_udp_payload_size(
     config: &mut Config, v: size_t,
@@ -324,6 +340,45 @@ pub extern fn quiche_config_set_cc_algorithm(
     config.set_cc_algorithm(algo);
 }
 
+#[cfg(feature = "multipath")]
+#[no_mangle]
+pub extern fn quiche_config_set_multipath(config: &mut Config, v: bool) {
+    config.set_multipath(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_path_scheduler_name(
+    config: &mut Config, name: *const c_char,
+) -> c_int {
+    let name = unsafe { ffi::CStr::from_ptr(name).to_str().unwrap() };
+    match config.set_path_scheduler_name(name) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_path_scheduler(
+    config: &mut Config, algo: PathSchedulerAlgorithm,
+) {
+    config.set_path_scheduler(algo);
+}
+
+#[cfg(feature = "plugins")]
+#[no_mangle]
+pub extern fn quiche_config_load_plugin(
+    config: &mut Config, path: *const c_char,
+) -> c_int {
+    let path = unsafe { ffi::CStr::from_ptr(path).to_str().unwrap() };
+
+    match config.load_plugin_from_file(path) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_enable_hystart(config: &mut Config, v: bool) {
     config.enable_hystart(v);
@@ -334,6 +389,7 @@ pub extern fn quiche_config_enable_pacing(config: &mut Config, v: bool) {
     config.enable_pacing(v);
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_config_enable_dgram(
     config: &mut Config, enabled: bool, recv_queue_len: size_t,
@@ -361,6 +417,13 @@ pub extern fn quiche_config_set_max_stream_window(config: &mut Config, v: u64) {
     config.set_max_stream_window(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_max_stream_send_buffer(
+    config: &mut Config, v: u64,
+) {
+    config.set_max_stream_send_buffer(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_active_connection_id_limit(
     config: &mut Config, v: u64,
@@ -472,7 +535,7 @@ pub extern fn quiche_accept(
     let peer = std_addr_from_c(peer, peer_len);
 
     match accept(&scid, odcid.as_ref(), local, peer, config) {
-        Ok(c) => Box::into_raw(Box::new(c)),
+        Ok(c) => conn_into_c(c),
 
         Err(_) => ptr::null_mut(),
     }
@@ -497,7 +560,7 @@ pub extern fn quiche_connect(
     let peer = std_addr_from_c(peer, peer_len);
 
     match connect(server_name, &scid, local, peer, config) {
-        Ok(c) => Box::into_raw(Box::new(c)),
+        Ok(c) => conn_into_c(c),
 
         Err(_) => ptr::null_mut(),
     }
@@ -554,6 +617,7 @@ pub extern fn quiche_retry(
 }
 
 #[no_mangle]
+#[cfg(not(feature = "rustls"))]
 pub extern fn quiche_conn_new_with_tls(
     scid: *const u8, scid_len: size_t, odcid: *const u8, odcid_len: size_t,
     local: &sockaddr, local_len: socklen_t, peer: &sockaddr, peer_len: socklen_t,
@@ -584,7 +648,7 @@ pub extern fn quiche_conn_new_with_tls(
         tls,
         is_server,
     ) {
-        Ok(c) => Box::into_raw(Box::new(c)),
+        Ok(c) => conn_into_c(c),
 
         Err(_) => ptr::null_mut(),
     }
@@ -711,11 +775,15 @@ pub extern fn quiche_conn_recv(
 
     let buf = unsafe { slice::from_raw_parts_mut(buf, buf_len) };
 
-    match conn.recv(buf, info.into()) {
+    let res = match conn.recv(buf, info.into()) {
         Ok(v) => v as ssize_t,
 
         Err(e) => e.to_c(),
-    }
+    };
+
+    dispatch_events(conn);
+
+    res
 }
 
 #[repr(C)]
@@ -752,6 +820,35 @@ pub extern fn quiche_conn_send(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_send_on_path(
+    conn: &mut Connection, out: *mut u8, out_len: size_t, from: *const sockaddr,
+    from_len: socklen_t, to: *const sockaddr, to_len: socklen_t,
+    out_info: &mut SendInfo,
+) -> ssize_t {
+    if out_len > <ssize_t>::max_value() as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let from = optional_std_addr_from_c(from, from_len);
+    let to = optional_std_addr_from_c(to, to_len);
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    match conn.send_on_path(out, from, to) {
+        Ok((v, info)) => {
+            out_info.from_len = std_addr_to_c(&info.from, &mut out_info.from);
+            out_info.to_len = std_addr_to_c(&info.to, &mut out_info.to);
+
+            std_time_to_c(&info.at, &mut out_info.at);
+
+            v as ssize_t
+        },
+
+        Err(e) => e.to_c(),
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stream_recv(
     conn: &mut Connection, stream_id: u64, out: *mut u8, out_len: size_t,
@@ -792,6 +889,17 @@ pub extern fn quiche_conn_stream_send(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stream_finish(
+    conn: &mut Connection, stream_id: u64,
+) -> c_int {
+    match conn.stream_finish(stream_id) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stream_priority(
     conn: &mut Connection, stream_id: u64, urgency: u8, incremental: bool,
@@ -837,6 +945,19 @@ pub extern fn quiche_conn_stream_readable_next(conn: &mut Connection) -> i64 {
     conn.stream_readable_next().map(|v| v as i64).unwrap_or(-1)
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stream_readable_threshold(
+    conn: &mut Connection, stream_id: u64, len: usize,
+) -> c_int {
+    match conn.stream_readable_threshold(stream_id, len) {
+        Ok(true) => 1,
+
+        Ok(false) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stream_writable(
     conn: &mut Connection, stream_id: u64, len: usize,
@@ -922,6 +1043,20 @@ pub extern fn quiche_conn_close(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_close_with_frame_type(
+    conn: &mut Connection, err: u64, frame_type: u64, reason: *const u8,
+    reason_len: size_t,
+) -> c_int {
+    let reason = unsafe { slice::from_raw_parts(reason, reason_len) };
+
+    match conn.close_with_frame_type(err, frame_type, reason) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_timeout_as_nanos(conn: &Connection) -> u64 {
     match conn.timeout() {
@@ -942,7 +1077,104 @@ pub extern fn quiche_conn_timeout_as_millis(conn: &Connection) -> u64 {
 
 #[no_mangle]
 pub extern fn quiche_conn_on_timeout(conn: &mut Connection) {
-    conn.on_timeout()
+    conn.on_timeout();
+
+    dispatch_events(conn);
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnEventType {
+    Established    = 0,
+    StreamReadable = 1,
+    StreamWritable = 2,
+    DgramReceived  = 3,
+    Closed         = 4,
+}
+
+type ConnEventCallback =
+    extern fn(*mut Connection, ConnEventType, u64, *mut c_void);
+
+pub struct ConnEventHandler {
+    cb: ConnEventCallback,
+    argp: atomic::AtomicPtr<c_void>,
+
+    /// Whether the one-off events were already notified.
+    established: bool,
+    closed: bool,
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_event_cb(
+    conn: &mut Connection, cb: Option<ConnEventCallback>, argp: *mut c_void,
+) {
+    conn.event_handler = cb.map(|cb| ConnEventHandler {
+        cb,
+        argp: atomic::AtomicPtr::new(argp),
+        established: false,
+        closed: false,
+    });
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_dispatch_events(conn: &mut Connection) {
+    dispatch_events(conn);
+}
+
+/// Notifies the connection's event callback, if any, of its current state.
+///
+/// The events are collected first, so that the callback is free to call back
+/// into the connection.
+fn dispatch_events(conn: *mut Connection) {
+    let (cb, argp, events) = {
+        let conn = unsafe { &mut *conn };
+
+        let (cb, argp, mut established, mut closed) = match &conn.event_handler {
+            Some(h) => (
+                h.cb,
+                h.argp.load(atomic::Ordering::Relaxed),
+                h.established,
+                h.closed,
+            ),
+
+            None => return,
+        };
+
+        let mut events = Vec::new();
+
+        if !established && conn.is_established() {
+            established = true;
+            events.push((ConnEventType::Established, 0));
+        }
+
+        for stream_id in conn.readable() {
+            events.push((ConnEventType::StreamReadable, stream_id));
+        }
+
+        for stream_id in conn.writable() {
+            events.push((ConnEventType::StreamWritable, stream_id));
+        }
+
+        if conn.dgram_recv_front_len().is_some() {
+            events.push((ConnEventType::DgramReceived, 0));
+        }
+
+        if !closed && conn.is_closed() {
+            closed = true;
+            events.push((ConnEventType::Closed, 0));
+        }
+
+        if let Some(h) = conn.event_handler.as_mut() {
+            h.established = established;
+            h.closed = closed;
+        }
+
+        (cb, argp, events)
+    };
+
+    for (ev, stream_id) in events {
+        cb(conn, ev, stream_id, argp);
+    }
 }
 
 #[no_mangle]
@@ -1042,12 +1274,13 @@ pub extern fn quiche_conn_is_timed_out(conn: &Connection) -> bool {
 #[no_mangle]
 pub extern fn quiche_conn_peer_error(
     conn: &Connection, is_app: *mut bool, error_code: *mut u64,
-    reason: &mut *const u8, reason_len: &mut size_t,
+    frame_type: *mut u64, reason: &mut *const u8, reason_len: &mut size_t,
 ) -> bool {
     match &conn.peer_error {
         Some(conn_err) => unsafe {
             *is_app = conn_err.is_app;
             *error_code = conn_err.error_code;
+            *frame_type = conn_err.frame_type;
             *reason = conn_err.reason.as_ptr();
             *reason_len = conn_err.reason.len();
 
@@ -1061,12 +1294,13 @@ pub extern fn quiche_conn_peer_error(
 #[no_mangle]
 pub extern fn quiche_conn_local_error(
     conn: &Connection, is_app: *mut bool, error_code: *mut u64,
-    reason: &mut *const u8, reason_len: &mut size_t,
+    frame_type: *mut u64, reason: &mut *const u8, reason_len: &mut size_t,
 ) -> bool {
     match &conn.local_error {
         Some(conn_err) => unsafe {
             *is_app = conn_err.is_app;
             *error_code = conn_err.error_code;
+            *frame_type = conn_err.frame_type;
             *reason = conn_err.reason.as_ptr();
             *reason_len = conn_err.reason.len();
 
@@ -1168,13 +1402,26 @@ pub struct PathStats {
     lost: usize,
     retrans: usize,
     rtt: u64,
+    latest_rtt: u64,
+    rttvar: u64,
+    min_rtt: u64,
+    min_rtt_refreshes: u64,
     cwnd: usize,
+    bytes_in_flight: usize,
+    cwnd_limited: u64,
+    pto_count: u32,
+    pacing_rate: u64,
+    persistent_congestion: u64,
+    loss_bursts: u64,
     sent_bytes: u64,
     recv_bytes: u64,
     lost_bytes: u64,
     stream_retrans_bytes: u64,
     pmtu: usize,
     delivery_rate: u64,
+    delivery_rate_app_limited: bool,
+    delivery_rate_interval: u64,
+    anti_amplification_allowance: ssize_t,
 }
 
 #[no_mangle]
@@ -1195,13 +1442,31 @@ pub extern fn quiche_conn_path_stats(
     out.lost = stats.lost;
     out.retrans = stats.retrans;
     out.rtt = stats.rtt.as_nanos() as u64;
+    out.latest_rtt = stats.latest_rtt.as_nanos() as u64;
+    out.rttvar = stats.rttvar.as_nanos() as u64;
+    out.min_rtt = stats.min_rtt.as_nanos() as u64;
+    out.min_rtt_refreshes = stats.min_rtt_refreshes;
     out.cwnd = stats.cwnd;
+    out.bytes_in_flight = stats.bytes_in_flight;
+    out.cwnd_limited = stats.cwnd_limited;
+    out.pto_count = stats.pto_count;
+    out.pacing_rate = stats.pacing_rate;
+    out.persistent_congestion = stats.persistent_congestion;
+    out.loss_bursts = stats.loss_bursts;
     out.sent_bytes = stats.sent_bytes;
     out.recv_bytes = stats.recv_bytes;
     out.lost_bytes = stats.lost_bytes;
     out.stream_retrans_bytes = stats.stream_retrans_bytes;
     out.pmtu = stats.pmtu;
     out.delivery_rate = stats.delivery_rate;
+    out.delivery_rate_app_limited = stats.delivery_rate_sample.is_app_limited;
+    out.delivery_rate_interval =
+        stats.delivery_rate_sample.interval.as_nanos() as u64;
+    out.anti_amplification_allowance = match stats.anti_amplification_allowance {
+        None => Error::Done.to_c(),
+
+        Some(v) => v as ssize_t,
+    };
 
     0
 }
@@ -1224,11 +1489,13 @@ pub extern fn quiche_conn_dgram_recv_front_len(conn: &Connection) -> ssize_t {
     }
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_conn_dgram_recv_queue_len(conn: &Connection) -> ssize_t {
     conn.dgram_recv_queue_len() as ssize_t
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_conn_dgram_recv_queue_byte_size(
     conn: &Connection,
@@ -1236,11 +1503,13 @@ pub extern fn quiche_conn_dgram_recv_queue_byte_size(
     conn.dgram_recv_queue_byte_size() as ssize_t
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_conn_dgram_send_queue_len(conn: &Connection) -> ssize_t {
     conn.dgram_send_queue_len() as ssize_t
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_conn_dgram_send_queue_byte_size(
     conn: &Connection,
@@ -1248,6 +1517,7 @@ pub extern fn quiche_conn_dgram_send_queue_byte_size(
     conn.dgram_send_queue_byte_size() as ssize_t
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_conn_dgram_send(
     conn: &mut Connection, buf: *const u8, buf_len: size_t,
@@ -1265,6 +1535,7 @@ pub extern fn quiche_conn_dgram_send(
     }
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_conn_dgram_recv(
     conn: &mut Connection, out: *mut u8, out_len: size_t,
@@ -1284,6 +1555,7 @@ pub extern fn quiche_conn_dgram_recv(
     out_len as ssize_t
 }
 
+#[cfg(feature = "datagram")]
 #[no_mangle]
 pub extern fn quiche_conn_dgram_purge_outgoing(
     conn: &mut Connection, f: extern fn(*const u8, size_t) -> bool,
@@ -1317,8 +1589,380 @@ pub extern fn quiche_conn_send_ack_eliciting_on_path(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_probe_path(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t, seq: *mut u64,
+) -> c_int {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match conn.probe_path(local, peer) {
+        Ok(v) => {
+            unsafe { *seq = v };
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_migrate_source(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t, seq: *mut u64,
+) -> c_int {
+    let local = std_addr_from_c(local, local_len);
+
+    match conn.migrate_source(local) {
+        Ok(v) => {
+            unsafe { *seq = v };
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_migrate(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t, seq: *mut u64,
+) -> c_int {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match conn.migrate(local, peer) {
+        Ok(v) => {
+            unsafe { *seq = v };
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_is_path_validated(
+    conn: &Connection, local: &sockaddr, local_len: socklen_t, peer: &sockaddr,
+    peer_len: socklen_t,
+) -> c_int {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match conn.is_path_validated(local, peer) {
+        Ok(v) => v as c_int,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_paths_iter(
+    conn: &Connection, from: &sockaddr, from_len: socklen_t,
+) -> *mut SocketAddrIter {
+    let from = std_addr_from_c(from, from_len);
+
+    Box::into_raw(Box::new(conn.paths_iter(from)))
+}
+
+#[no_mangle]
+pub extern fn quiche_socket_addr_iter_next(
+    iter: &mut SocketAddrIter, peer: &mut sockaddr_storage,
+    peer_len: &mut socklen_t,
+) -> bool {
+    if let Some(v) = iter.next() {
+        *peer_len = std_addr_to_c(&v, peer);
+        return true;
+    }
+
+    false
+}
+
+#[no_mangle]
+pub extern fn quiche_socket_addr_iter_free(iter: *mut SocketAddrIter) {
+    unsafe { Box::from_raw(iter) };
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_path_event_next(
+    conn: &mut Connection,
+) -> *mut PathEvent {
+    match conn.path_event_next() {
+        Some(v) => Box::into_raw(Box::new(v)),
+
+        None => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_path_event_type(ev: &PathEvent) -> u32 {
+    match ev {
+        PathEvent::New(..) => 0,
+
+        PathEvent::Validated(..) => 1,
+
+        PathEvent::FailedValidation(..) => 2,
+
+        PathEvent::Closed(..) => 3,
+
+        PathEvent::ReusedSourceConnectionId(..) => 4,
+
+        PathEvent::PeerMigrated(..) => 5,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_path_event_addrs(
+    ev: &PathEvent, local: &mut sockaddr_storage, local_len: &mut socklen_t,
+    peer: &mut sockaddr_storage, peer_len: &mut socklen_t,
+) {
+    let (local_addr, peer_addr) = match ev {
+        PathEvent::New(l, p) |
+        PathEvent::Validated(l, p) |
+        PathEvent::FailedValidation(l, p) |
+        PathEvent::Closed(l, p) |
+        PathEvent::PeerMigrated(l, p) => (l, p),
+
+        PathEvent::ReusedSourceConnectionId(_, _, (l, p)) => (l, p),
+    };
+
+    *local_len = std_addr_to_c(local_addr, local);
+    *peer_len = std_addr_to_c(peer_addr, peer);
+}
+
+#[no_mangle]
+pub extern fn quiche_path_event_reused_source_connection_id(
+    ev: &PathEvent, cid_seq: &mut u64, old_local: &mut sockaddr_storage,
+    old_local_len: &mut socklen_t, old_peer: &mut sockaddr_storage,
+    old_peer_len: &mut socklen_t,
+) {
+    match ev {
+        PathEvent::ReusedSourceConnectionId(id, (local, peer), _) => {
+            *cid_seq = *id;
+            *old_local_len = std_addr_to_c(local, old_local);
+            *old_peer_len = std_addr_to_c(peer, old_peer);
+        },
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_path_event_free(ev: *mut PathEvent) {
+    unsafe { Box::from_raw(ev) };
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_new_scid(
+    conn: &mut Connection, scid: *const u8, scid_len: size_t,
+    reset_token: *const u8, retire_if_needed: bool, seq: *mut u64,
+) -> c_int {
+    let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
+    let scid = ConnectionId::from_ref(scid);
+
+    let reset_token = unsafe { slice::from_raw_parts(reset_token, 16) };
+    let reset_token = match reset_token.try_into() {
+        Ok(rt) => rt,
+        Err(_) => unreachable!(),
+    };
+    let reset_token = u128::from_be_bytes(reset_token);
+
+    match conn.new_source_cid(&scid, reset_token, retire_if_needed) {
+        Ok(v) => {
+            unsafe { *seq = v };
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_retire_dcid(
+    conn: &mut Connection, dcid_seq: u64,
+) -> c_int {
+    match conn.retire_destination_cid(dcid_seq) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_source_cids_left(conn: &Connection) -> size_t {
+    conn.source_cids_left()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_available_dcids(conn: &Connection) -> size_t {
+    conn.available_dcids()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_is_multipath(conn: &Connection) -> bool {
+    conn.is_multipath()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_create_path(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t, seq: *mut u64,
+) -> c_int {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match conn.create_path(local, peer) {
+        Ok(v) => {
+            unsafe { *seq = v };
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_path_status(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t, status: PathStatus,
+) -> c_int {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match conn.set_path_status(local, peer, status) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_abandon_path(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t, error_code: u64, reason: *const u8,
+    reason_len: size_t,
+) -> c_int {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    let reason = unsafe { slice::from_raw_parts(reason, reason_len) };
+
+    match conn.abandon_path(local, peer, error_code, reason) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[cfg(feature = "plugins")]
+#[no_mangle]
+pub extern fn quiche_conn_insert_plugin(
+    conn: &mut Connection, path: *const c_char,
+) -> c_int {
+    let path = unsafe { ffi::CStr::from_ptr(path).to_str().unwrap() };
+
+    match conn.insert_plugin(path) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[cfg(feature = "plugins")]
+#[no_mangle]
+pub extern fn quiche_conn_is_pluginized(conn: &mut Connection) -> bool {
+    conn.get_pluginizable_connection().is_some()
+}
+
+#[cfg(feature = "plugins")]
+#[repr(C)]
+pub struct PluginFailureInfo {
+    trace_id: *const u8,
+    trace_id_len: size_t,
+    operation: *const u8,
+    operation_len: size_t,
+    message: *const u8,
+    message_len: size_t,
+    panic: bool,
+}
+
+#[cfg(feature = "plugins")]
+type PluginFailureCallback = extern fn(&PluginFailureInfo, *mut c_void);
+
+/// A registered callback, along with the argument it is passed.
+#[cfg(feature = "plugins")]
+struct PluginFailureCb {
+    cb: PluginFailureCallback,
+    argp: *mut c_void,
+}
+
+/// The registered callback. The callback and its argument are swapped as a
+/// single pointer, so that a failure reported concurrently with a new
+/// registration never pairs one's callback with the other's argument.
+#[cfg(feature = "plugins")]
+static PLUGIN_FAILURE_CB: atomic::AtomicPtr<PluginFailureCb> =
+    atomic::AtomicPtr::new(ptr::null_mut());
+
+#[cfg(feature = "plugins")]
+fn plugin_failure_sink(report: &PluginFailureReport) {
+    let cb = PLUGIN_FAILURE_CB.load(atomic::Ordering::Acquire);
+
+    // Safety: registered callbacks are never freed.
+    let cb = match unsafe { cb.as_ref() } {
+        Some(v) => v,
+
+        None => return,
+    };
+
+    let (panic, message) = match &report.failure {
+        PluginFailure::Trap(m) => (false, m),
+
+        PluginFailure::Panic(m) => (true, m),
+    };
+
+    let info = PluginFailureInfo {
+        trace_id: report.trace_id.as_ptr(),
+        trace_id_len: report.trace_id.len(),
+        operation: report.operation.as_ptr(),
+        operation_len: report.operation.len(),
+        message: message.as_ptr(),
+        message_len: message.len(),
+        panic,
+    };
+
+    (cb.cb)(&info, cb.argp);
+}
+
+#[cfg(feature = "plugins")]
+#[no_mangle]
+pub extern fn quiche_set_plugin_failure_cb(
+    cb: Option<PluginFailureCallback>, argp: *mut c_void,
+) {
+    let new = cb.map_or(ptr::null_mut(), |cb| {
+        Box::into_raw(Box::new(PluginFailureCb { cb, argp }))
+    });
+
+    // The previous callback is leaked rather than freed, as a failure might
+    // still be reported with it on another thread. Callbacks are expected to
+    // be registered once, or a handful of times.
+    PLUGIN_FAILURE_CB.swap(new, atomic::Ordering::AcqRel);
+
+    if new.is_null() {
+        set_plugin_failure_sink(None);
+    } else {
+        set_plugin_failure_sink(Some(plugin_failure_sink));
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_free(conn: *mut Connection) {
+    // Pluginized connections are owned by their pluginizable connection.
+    #[cfg(feature = "plugins")]
+    if let Some(pc) = unsafe { &mut *conn }.get_pluginizable_connection() {
+        unsafe { Box::from_raw(pc as *mut PluginizableConnection<Connection>) };
+        return;
+    }
+
     unsafe { Box::from_raw(conn) };
 }
 
@@ -1394,6 +2038,31 @@ fn std_addr_from_c(addr: &sockaddr, addr_len: socklen_t) -> SocketAddr {
     }
 }
 
+/// Moves a new connection to the heap, pluginizing it first if plugins were
+/// added to its configuration.
+fn conn_into_c(conn: Connection) -> *mut Connection {
+    #[cfg(feature = "plugins")]
+    if !conn.plugin_files.is_empty() {
+        return match conn.pluginize() {
+            Ok(pc) => Box::leak(pc).get_conn_mut() as *mut Connection,
+
+            Err(_) => ptr::null_mut(),
+        };
+    }
+
+    Box::into_raw(Box::new(conn))
+}
+
+fn optional_std_addr_from_c(
+    addr: *const sockaddr, addr_len: socklen_t,
+) -> Option<SocketAddr> {
+    if addr.is_null() || addr_len == 0 {
+        return None;
+    }
+
+    Some(std_addr_from_c(unsafe { &*addr }, addr_len))
+}
+
 fn std_addr_to_c(addr: &SocketAddr, out: &mut sockaddr_storage) -> socklen_t {
     let sin_port = addr.port().to_be();
 
@@ -1511,6 +2180,43 @@ mod tests {
     #[cfg(windows)]
     use winapi::um::ws2tcpip::inet_ntop;
 
+    extern fn record_event(
+        _conn: *mut Connection, ev: ConnEventType, stream_id: u64,
+        argp: *mut c_void,
+    ) {
+        let events = unsafe { &mut *(argp as *mut Vec<(ConnEventType, u64)>) };
+        events.push((ev, stream_id));
+    }
+
+    #[test]
+    fn conn_events() {
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        let mut events: Vec<(ConnEventType, u64)> = Vec::new();
+
+        quiche_conn_set_event_cb(
+            &mut pipe.server,
+            Some(record_event),
+            &mut events as *mut _ as *mut c_void,
+        );
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        quiche_conn_dispatch_events(&mut pipe.server);
+        assert_eq!(events, vec![(ConnEventType::Established, 0)]);
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // One-off events are only notified once.
+        events.clear();
+        quiche_conn_dispatch_events(&mut pipe.server);
+        assert_eq!(events, vec![
+            (ConnEventType::StreamReadable, 0),
+            (ConnEventType::StreamWritable, 0),
+        ]);
+    }
+
     #[test]
     fn addr_v4() {
         let addr = "127.0.0.1:8080".parse().unwrap();
@@ -47,7 +47,17 @@ pub const MAX_DGRAM_OVERHEAD: usize = 2;
 pub const MAX_STREAM_OVERHEAD: usize = 12;
 pub const MAX_STREAM_SIZE: u64 = 1 << 62;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+// Frame types of the multipath extension.
+const MP_ACK_FRAME_TYPE: u64 = 0x1522_8c00;
+const MP_ACK_ECN_FRAME_TYPE: u64 = 0x1522_8c01;
+const PATH_ABANDON_FRAME_TYPE: u64 = 0x1522_8c05;
+const PATH_STATUS_FRAME_TYPE: u64 = 0x1522_8c06;
+
+// Frame types of the ACK frequency extension.
+const ACK_FREQUENCY_FRAME_TYPE: u64 = 0xaf;
+const IMMEDIATE_ACK_FRAME_TYPE: u64 = 0x1f;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct EcnCounts {
     pub ect0_count: u64,
     pub ect1_count: u64,
@@ -60,7 +70,10 @@ pub enum Frame {
         len: usize,
     },
 
-    Ping,
+    Ping {
+        // Set when the frame is sent in a PMTU probe, to the probed size.
+        mtu_probe: Option<usize>,
+    },
 
     ACK {
         ack_delay: u64,
@@ -182,11 +195,48 @@ pub enum Frame {
         ty: u64,
         tag: u64,
     },
+
+    MpAck {
+        space_identifier: u64,
+        ack_delay: u64,
+        ranges: ranges::RangeSet,
+        ecn_counts: Option<EcnCounts>,
+    },
+
+    PathAbandon {
+        dcid_seq_num: u64,
+        error_code: u64,
+        reason: Vec<u8>,
+    },
+
+    PathStatus {
+        dcid_seq_num: u64,
+        seq_num: u64,
+        status: u64,
+    },
+
+    AckFrequency {
+        seq_num: u64,
+        ack_eliciting_threshold: u64,
+        request_max_ack_delay: u64,
+        reordering_threshold: u64,
+    },
+
+    ImmediateAck,
 }
 
 impl Frame {
     pub fn from_bytes_with_type(
         frame_type: u64, b: &mut octets::Octets, pkt: packet::Type,
+    ) -> Result<Frame> {
+        Frame::from_bytes_with_factory(frame_type, b, pkt, None)
+    }
+
+    /// Parses a frame of the given type, storing the data of STREAM frames in
+    /// buffers allocated by `factory`, if any.
+    pub fn from_bytes_with_factory(
+        frame_type: u64, b: &mut octets::Octets, pkt: packet::Type,
+        factory: Option<&dyn stream::BufFactory>,
     ) -> Result<Frame> {
         let frame = match frame_type {
             0x00 => {
@@ -201,7 +251,7 @@ impl Frame {
                 Frame::Padding { len }
             },
 
-            0x01 => Frame::Ping,
+            0x01 => Frame::Ping { mtu_probe: None },
 
             0x02..=0x03 => parse_ack_frame(frame_type, b)?,
 
@@ -228,7 +278,7 @@ impl Frame {
                 token: b.get_bytes_with_varint_length()?.to_vec(),
             },
 
-            0x08..=0x0f => parse_stream_frame(frame_type, b)?,
+            0x08..=0x0f => parse_stream_frame(frame_type, b, factory)?,
 
             0x10 => Frame::MaxData {
                 max: b.get_varint()?,
@@ -310,6 +360,30 @@ impl Frame {
 
             0x30 | 0x31 => parse_datagram_frame(frame_type, b)?,
 
+            MP_ACK_FRAME_TYPE | MP_ACK_ECN_FRAME_TYPE =>
+                parse_mp_ack_frame(frame_type, b)?,
+
+            PATH_ABANDON_FRAME_TYPE => Frame::PathAbandon {
+                dcid_seq_num: b.get_varint()?,
+                error_code: b.get_varint()?,
+                reason: b.get_bytes_with_varint_length()?.to_vec(),
+            },
+
+            PATH_STATUS_FRAME_TYPE => Frame::PathStatus {
+                dcid_seq_num: b.get_varint()?,
+                seq_num: b.get_varint()?,
+                status: b.get_varint()?,
+            },
+
+            ACK_FREQUENCY_FRAME_TYPE => Frame::AckFrequency {
+                seq_num: b.get_varint()?,
+                ack_eliciting_threshold: b.get_varint()?,
+                request_max_ack_delay: b.get_varint()?,
+                reordering_threshold: b.get_varint()?,
+            },
+
+            IMMEDIATE_ACK_FRAME_TYPE => Frame::ImmediateAck,
+
             _ => return Err(Error::InvalidFrame),
         };
 
@@ -327,6 +401,11 @@ impl Frame {
             (packet::Type::ZeroRTT, Frame::RetireConnectionId { .. }) => false,
             (packet::Type::ZeroRTT, Frame::ConnectionClose { .. }) => false,
 
+            // Multipath frames are only sent once the handshake is complete.
+            (packet::Type::ZeroRTT, Frame::MpAck { .. }) => false,
+            (packet::Type::ZeroRTT, Frame::PathAbandon { .. }) => false,
+            (packet::Type::ZeroRTT, Frame::PathStatus { .. }) => false,
+
             // ACK, CRYPTO and CONNECTION_CLOSE can be sent on all other packet
             // types.
             (_, Frame::ACK { .. }) => true,
@@ -370,7 +449,7 @@ impl Frame {
                 }
             },
 
-            Frame::Ping => {
+            Frame::Ping { .. } => {
                 b.put_varint(0x01)?;
             },
 
@@ -385,33 +464,7 @@ impl Frame {
                     b.put_varint(0x03)?;
                 }
 
-                let mut it = ranges.iter().rev();
-
-                let first = it.next().unwrap();
-                let ack_block = (first.end - 1) - first.start;
-
-                b.put_varint(first.end - 1)?;
-                b.put_varint(*ack_delay)?;
-                b.put_varint(it.len() as u64)?;
-                b.put_varint(ack_block)?;
-
-                let mut smallest_ack = first.start;
-
-                for block in it {
-                    let gap = smallest_ack - block.end - 1;
-                    let ack_block = (block.end - 1) - block.start;
-
-                    b.put_varint(gap)?;
-                    b.put_varint(ack_block)?;
-
-                    smallest_ack = block.start;
-                }
-
-                if let Some(ecn) = ecn_counts {
-                    b.put_varint(ecn.ect0_count)?;
-                    b.put_varint(ecn.ect1_count)?;
-                    b.put_varint(ecn.ecn_ce_count)?;
-                }
+                encode_ack_body(*ack_delay, ranges, ecn_counts.as_ref(), b)?;
             },
 
             Frame::ResetStream {
@@ -582,6 +635,66 @@ impl Frame {
             Frame::DatagramHeader { .. } => (),
 
             Frame::Extension { .. } => unreachable!(),
+
+            Frame::MpAck {
+                space_identifier,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                if ecn_counts.is_none() {
+                    b.put_varint(MP_ACK_FRAME_TYPE)?;
+                } else {
+                    b.put_varint(MP_ACK_ECN_FRAME_TYPE)?;
+                }
+
+                b.put_varint(*space_identifier)?;
+
+                encode_ack_body(*ack_delay, ranges, ecn_counts.as_ref(), b)?;
+            },
+
+            Frame::PathAbandon {
+                dcid_seq_num,
+                error_code,
+                reason,
+            } => {
+                b.put_varint(PATH_ABANDON_FRAME_TYPE)?;
+
+                b.put_varint(*dcid_seq_num)?;
+                b.put_varint(*error_code)?;
+                b.put_varint(reason.len() as u64)?;
+                b.put_bytes(reason.as_ref())?;
+            },
+
+            Frame::PathStatus {
+                dcid_seq_num,
+                seq_num,
+                status,
+            } => {
+                b.put_varint(PATH_STATUS_FRAME_TYPE)?;
+
+                b.put_varint(*dcid_seq_num)?;
+                b.put_varint(*seq_num)?;
+                b.put_varint(*status)?;
+            },
+
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                b.put_varint(ACK_FREQUENCY_FRAME_TYPE)?;
+
+                b.put_varint(*seq_num)?;
+                b.put_varint(*ack_eliciting_threshold)?;
+                b.put_varint(*request_max_ack_delay)?;
+                b.put_varint(*reordering_threshold)?;
+            },
+
+            Frame::ImmediateAck => {
+                b.put_varint(IMMEDIATE_ACK_FRAME_TYPE)?;
+            },
         }
 
         Ok(before - b.cap())
@@ -591,43 +704,15 @@ impl Frame {
         match self {
             Frame::Padding { len } => *len,
 
-            Frame::Ping => 1,
+            Frame::Ping { .. } => 1,
 
             Frame::ACK {
                 ack_delay,
                 ranges,
                 ecn_counts,
             } => {
-                let mut it = ranges.iter().rev();
-
-                let first = it.next().unwrap();
-                let ack_block = (first.end - 1) - first.start;
-
-                let mut len = 1 + // frame type
-                    octets::varint_len(first.end - 1) + // largest_ack
-                    octets::varint_len(*ack_delay) + // ack_delay
-                    octets::varint_len(it.len() as u64) + // block_count
-                    octets::varint_len(ack_block); // first_block
-
-                let mut smallest_ack = first.start;
-
-                for block in it {
-                    let gap = smallest_ack - block.end - 1;
-                    let ack_block = (block.end - 1) - block.start;
-
-                    len += octets::varint_len(gap) + // gap
-                           octets::varint_len(ack_block); // ack_block
-
-                    smallest_ack = block.start;
-                }
-
-                if let Some(ecn) = ecn_counts {
-                    len += octets::varint_len(ecn.ect0_count) +
-                        octets::varint_len(ecn.ect1_count) +
-                        octets::varint_len(ecn.ecn_ce_count);
-                }
-
-                len
+                1 + // frame type
+                ack_body_wire_len(*ack_delay, ranges, ecn_counts.as_ref())
             },
 
             Frame::ResetStream {
@@ -799,6 +884,55 @@ impl Frame {
             },
 
             Frame::Extension { .. } => unreachable!(),
+
+            Frame::MpAck {
+                space_identifier,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                octets::varint_len(MP_ACK_FRAME_TYPE) + // frame type
+                octets::varint_len(*space_identifier) + // space_identifier
+                ack_body_wire_len(*ack_delay, ranges, ecn_counts.as_ref())
+            },
+
+            Frame::PathAbandon {
+                dcid_seq_num,
+                error_code,
+                reason,
+            } => {
+                octets::varint_len(PATH_ABANDON_FRAME_TYPE) + // frame type
+                octets::varint_len(*dcid_seq_num) + // dcid_seq_num
+                octets::varint_len(*error_code) + // error_code
+                octets::varint_len(reason.len() as u64) + // reason_len
+                reason.len() // reason
+            },
+
+            Frame::PathStatus {
+                dcid_seq_num,
+                seq_num,
+                status,
+            } => {
+                octets::varint_len(PATH_STATUS_FRAME_TYPE) + // frame type
+                octets::varint_len(*dcid_seq_num) + // dcid_seq_num
+                octets::varint_len(*seq_num) + // seq_num
+                octets::varint_len(*status) // status
+            },
+
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                octets::varint_len(ACK_FREQUENCY_FRAME_TYPE) + // frame type
+                octets::varint_len(*seq_num) + // seq_num
+                octets::varint_len(*ack_eliciting_threshold) + // threshold
+                octets::varint_len(*request_max_ack_delay) + // max_ack_delay
+                octets::varint_len(*reordering_threshold) // reordering
+            },
+
+            Frame::ImmediateAck => octets::varint_len(IMMEDIATE_ACK_FRAME_TYPE),
         }
     }
 
@@ -808,6 +942,7 @@ impl Frame {
             self,
             Frame::Padding { .. } |
                 Frame::ACK { .. } |
+                Frame::MpAck { .. } |
                 Frame::ApplicationClose { .. } |
                 Frame::ConnectionClose { .. }
         )
@@ -1014,13 +1149,23 @@ impl Frame {
                 raw_length: None,
                 raw: None,
             },
+
+            Frame::MpAck { .. } |
+            Frame::PathAbandon { .. } |
+            Frame::PathStatus { .. } |
+            Frame::AckFrequency { .. } |
+            Frame::ImmediateAck => QuicFrame::Unknown {
+                raw_frame_type: self.ty(),
+                raw_length: None,
+                raw: None,
+            },
         }
     }
 
     pub fn ty(&self) -> u64 {
         match self {
             Frame::Padding { .. } => 0x00,
-            Frame::Ping => 0x01,
+            Frame::Ping { .. } => 0x01,
             Frame::ACK { .. } => 0x02,
             Frame::ResetStream { .. } => 0x04,
             Frame::StopSending { .. } => 0x05,
@@ -1047,6 +1192,11 @@ impl Frame {
             Frame::Datagram { .. } => 0x30,
             Frame::DatagramHeader { .. } => 0x30,
             Frame::Extension { ty, .. } => *ty,
+            Frame::MpAck { .. } => MP_ACK_FRAME_TYPE,
+            Frame::PathAbandon { .. } => PATH_ABANDON_FRAME_TYPE,
+            Frame::PathStatus { .. } => PATH_STATUS_FRAME_TYPE,
+            Frame::AckFrequency { .. } => ACK_FREQUENCY_FRAME_TYPE,
+            Frame::ImmediateAck => IMMEDIATE_ACK_FRAME_TYPE,
         }
     }
 }
@@ -1058,7 +1208,7 @@ impl std::fmt::Debug for Frame {
                 write!(f, "PADDING len={len}")?;
             },
 
-            Frame::Ping => {
+            Frame::Ping { .. } => {
                 write!(f, "PING")?;
             },
 
@@ -1217,6 +1367,56 @@ impl std::fmt::Debug for Frame {
 
             Frame::Extension { ty, tag } =>
                 write!(f, "EXTENSION ty={ty:x} tag={tag:x}")?,
+
+            Frame::MpAck {
+                space_identifier,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                write!(
+                    f,
+                    "ACK_MP space={space_identifier} delay={ack_delay} blocks={ranges:?} ecn_counts={ecn_counts:?}"
+                )?;
+            },
+
+            Frame::PathAbandon {
+                dcid_seq_num,
+                error_code,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "PATH_ABANDON dcid_seq_num={dcid_seq_num} err={error_code:x} reason={reason:x?}"
+                )?;
+            },
+
+            Frame::PathStatus {
+                dcid_seq_num,
+                seq_num,
+                status,
+            } => {
+                write!(
+                    f,
+                    "PATH_STATUS dcid_seq_num={dcid_seq_num} seq_num={seq_num} status={status}"
+                )?;
+            },
+
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                write!(
+                    f,
+                    "ACK_FREQUENCY seq_num={seq_num} threshold={ack_eliciting_threshold} max_ack_delay={request_max_ack_delay} reordering={reordering_threshold}"
+                )?;
+            },
+
+            Frame::ImmediateAck => {
+                write!(f, "IMMEDIATE_ACK")?;
+            },
         }
 
         Ok(())
@@ -1224,8 +1424,31 @@ impl std::fmt::Debug for Frame {
 }
 
 fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
-    let first = ty as u8;
+    let (ack_delay, ranges, ecn_counts) = parse_ack_body(ty & 0x01 != 0, b)?;
+
+    Ok(Frame::ACK {
+        ack_delay,
+        ranges,
+        ecn_counts,
+    })
+}
+
+fn parse_mp_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+    let space_identifier = b.get_varint()?;
+
+    let (ack_delay, ranges, ecn_counts) = parse_ack_body(ty & 0x01 != 0, b)?;
+
+    Ok(Frame::MpAck {
+        space_identifier,
+        ack_delay,
+        ranges,
+        ecn_counts,
+    })
+}
 
+fn parse_ack_body(
+    has_ecn: bool, b: &mut octets::Octets,
+) -> Result<(u64, ranges::RangeSet, Option<EcnCounts>)> {
     let largest_ack = b.get_varint()?;
     let ack_delay = b.get_varint()?;
     let block_count = b.get_varint()?;
@@ -1260,7 +1483,7 @@ fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
         ranges.insert(smallest_ack..largest_ack + 1);
     }
 
-    let ecn_counts = if first & 0x01 != 0 {
+    let ecn_counts = if has_ecn {
         let ecn = EcnCounts {
             ect0_count: b.get_varint()?,
             ect1_count: b.get_varint()?,
@@ -1272,11 +1495,76 @@ fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
         None
     };
 
-    Ok(Frame::ACK {
-        ack_delay,
-        ranges,
-        ecn_counts,
-    })
+    Ok((ack_delay, ranges, ecn_counts))
+}
+
+fn encode_ack_body(
+    ack_delay: u64, ranges: &ranges::RangeSet, ecn_counts: Option<&EcnCounts>,
+    b: &mut octets::OctetsMut,
+) -> Result<()> {
+    let mut it = ranges.iter().rev();
+
+    let first = it.next().unwrap();
+    let ack_block = (first.end - 1) - first.start;
+
+    b.put_varint(first.end - 1)?;
+    b.put_varint(ack_delay)?;
+    b.put_varint(it.len() as u64)?;
+    b.put_varint(ack_block)?;
+
+    let mut smallest_ack = first.start;
+
+    for block in it {
+        let gap = smallest_ack - block.end - 1;
+        let ack_block = (block.end - 1) - block.start;
+
+        b.put_varint(gap)?;
+        b.put_varint(ack_block)?;
+
+        smallest_ack = block.start;
+    }
+
+    if let Some(ecn) = ecn_counts {
+        b.put_varint(ecn.ect0_count)?;
+        b.put_varint(ecn.ect1_count)?;
+        b.put_varint(ecn.ecn_ce_count)?;
+    }
+
+    Ok(())
+}
+
+fn ack_body_wire_len(
+    ack_delay: u64, ranges: &ranges::RangeSet, ecn_counts: Option<&EcnCounts>,
+) -> usize {
+    let mut it = ranges.iter().rev();
+
+    let first = it.next().unwrap();
+    let ack_block = (first.end - 1) - first.start;
+
+    let mut len = octets::varint_len(first.end - 1) + // largest_ack
+        octets::varint_len(ack_delay) + // ack_delay
+        octets::varint_len(it.len() as u64) + // block_count
+        octets::varint_len(ack_block); // first_block
+
+    let mut smallest_ack = first.start;
+
+    for block in it {
+        let gap = smallest_ack - block.end - 1;
+        let ack_block = (block.end - 1) - block.start;
+
+        len += octets::varint_len(gap) + // gap
+               octets::varint_len(ack_block); // ack_block
+
+        smallest_ack = block.start;
+    }
+
+    if let Some(ecn) = ecn_counts {
+        len += octets::varint_len(ecn.ect0_count) +
+            octets::varint_len(ecn.ect1_count) +
+            octets::varint_len(ecn.ecn_ce_count);
+    }
+
+    len
 }
 
 pub fn encode_crypto_header(
@@ -1333,7 +1621,9 @@ pub fn encode_dgram_header(length: u64, b: &mut octets::OctetsMut) -> Result<()>
     Ok(())
 }
 
-fn parse_stream_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+fn parse_stream_frame(
+    ty: u64, b: &mut octets::Octets, factory: Option<&dyn stream::BufFactory>,
+) -> Result<Frame> {
     let first = ty as u8;
 
     let stream_id = b.get_varint()?;
@@ -1357,7 +1647,12 @@ fn parse_stream_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
     let fin = first & 0x01 != 0;
 
     let data = b.get_bytes(len)?;
-    let data = stream::RangeBuf::from(data.as_ref(), offset, fin);
+    let data = match factory {
+        Some(factory) =>
+            stream::RangeBuf::from_factory(factory, data.as_ref(), offset, fin),
+
+        None => stream::RangeBuf::from(data.as_ref(), offset, fin),
+    };
 
     Ok(Frame::Stream { stream_id, data })
 }
@@ -1412,7 +1707,7 @@ mod tests {
     fn ping() {
         let mut d = [42; 128];
 
-        let frame = Frame::Ping;
+        let frame = Frame::Ping { mtu_probe: None };
 
         let wire_len = {
             let mut b = octets::OctetsMut::with_slice(&mut d);
@@ -2136,4 +2431,160 @@ mod tests {
 
         assert_eq!(frame_data, data);
     }
+
+    #[test]
+    fn mp_ack() {
+        let mut d = [42; 128];
+
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(4..7);
+        ranges.insert(9..12);
+        ranges.insert(15..19);
+        ranges.insert(3000..5000);
+
+        let frame = Frame::MpAck {
+            space_identifier: 3,
+            ack_delay: 874_656_534,
+            ranges,
+            ecn_counts: None,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 21);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn path_abandon() {
+        let mut d = [42; 128];
+
+        let frame = Frame::PathAbandon {
+            dcid_seq_num: 3,
+            error_code: 42,
+            reason: b"bye".to_vec(),
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 10);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn path_status() {
+        let mut d = [42; 128];
+
+        let frame = Frame::PathStatus {
+            dcid_seq_num: 2,
+            seq_num: 7,
+            status: 2,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 7);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn ack_frequency() {
+        let mut d = [42; 128];
+
+        let frame = Frame::AckFrequency {
+            seq_num: 1,
+            ack_eliciting_threshold: 4,
+            request_max_ack_delay: 25_000,
+            reordering_threshold: 1,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 9);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn immediate_ack() {
+        let mut d = [42; 128];
+
+        let frame = Frame::ImmediateAck;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 1);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
 }
@@ -0,0 +1,369 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use super::Error;
+use super::Result;
+
+pub const DATAGRAM_CAPSULE_TYPE_ID: u64 = 0x0;
+
+/// The Context ID of HTTP Datagrams carrying UDP payloads ([RFC 9298]).
+///
+/// [RFC 9298]: https://www.rfc-editor.org/rfc/rfc9298.html
+pub const UDP_PAYLOAD_CONTEXT_ID: u64 = 0x0;
+
+const CONNECT_UDP_PATH_PREFIX: &str = "/.well-known/masque/udp/";
+
+/// A capsule of the Capsule Protocol ([RFC 9297]).
+///
+/// [RFC 9297]: https://www.rfc-editor.org/rfc/rfc9297.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Capsule {
+    /// A DATAGRAM capsule, carrying an HTTP Datagram payload.
+    Datagram { payload: Vec<u8> },
+
+    /// A capsule of a type not known to quiche, to be handled by the
+    /// application.
+    Unknown { ty: u64, payload: Vec<u8> },
+}
+
+impl Capsule {
+    /// Parses a capsule from the start of the given buffer.
+    ///
+    /// On success the capsule and the number of bytes it occupies are
+    /// returned. [`BufferTooShort`] is returned if the buffer doesn't contain
+    /// a whole capsule yet.
+    ///
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    pub fn from_bytes(buf: &[u8]) -> Result<(Capsule, usize)> {
+        let mut b = octets::Octets::with_slice(buf);
+
+        let ty = b.get_varint()?;
+        let payload = b.get_bytes_with_varint_length()?.to_vec();
+
+        let capsule = match ty {
+            DATAGRAM_CAPSULE_TYPE_ID => Capsule::Datagram { payload },
+
+            _ => Capsule::Unknown { ty, payload },
+        };
+
+        Ok((capsule, b.off()))
+    }
+
+    /// Serializes the capsule into the given buffer.
+    ///
+    /// On success the number of bytes written is returned.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut b = octets::OctetsMut::with_slice(buf);
+
+        b.put_varint(self.ty())?;
+        b.put_varint(self.payload().len() as u64)?;
+        b.put_bytes(self.payload())?;
+
+        Ok(b.off())
+    }
+
+    /// Returns the number of bytes the serialized capsule occupies.
+    pub fn wire_len(&self) -> usize {
+        let len = self.payload().len();
+
+        octets::varint_len(self.ty()) + octets::varint_len(len as u64) + len
+    }
+
+    fn ty(&self) -> u64 {
+        match self {
+            Capsule::Datagram { .. } => DATAGRAM_CAPSULE_TYPE_ID,
+
+            Capsule::Unknown { ty, .. } => *ty,
+        }
+    }
+
+    fn payload(&self) -> &[u8] {
+        match self {
+            Capsule::Datagram { payload } => payload,
+
+            Capsule::Unknown { payload, .. } => payload,
+        }
+    }
+}
+
+/// Returns the payload of an HTTP Datagram carrying the given UDP payload.
+///
+/// The UDP payload is prefixed with the [`UDP_PAYLOAD_CONTEXT_ID`] Context ID,
+/// as required by [RFC 9298]. The result can be sent with [`send_dgram()`] or
+/// in a [`Capsule::Datagram`].
+///
+/// [`UDP_PAYLOAD_CONTEXT_ID`]: constant.UDP_PAYLOAD_CONTEXT_ID.html
+/// [`send_dgram()`]: struct.Connection.html#method.send_dgram
+/// [`Capsule::Datagram`]: enum.Capsule.html#variant.Datagram
+/// [RFC 9298]: https://www.rfc-editor.org/rfc/rfc9298.html
+pub fn encode_udp_payload(payload: &[u8]) -> Vec<u8> {
+    let mut d = vec![0; octets::varint_len(UDP_PAYLOAD_CONTEXT_ID)];
+
+    let mut b = octets::OctetsMut::with_slice(&mut d);
+    // The buffer is sized for the Context ID, so this can't fail.
+    let _ = b.put_varint(UDP_PAYLOAD_CONTEXT_ID);
+
+    d.extend_from_slice(payload);
+
+    d
+}
+
+/// Parses the Context ID at the start of an HTTP Datagram payload.
+///
+/// On success the Context ID and the rest of the payload are returned. HTTP
+/// Datagrams with the [`UDP_PAYLOAD_CONTEXT_ID`] Context ID carry a UDP
+/// payload, while those with unknown Context IDs should be dropped.
+///
+/// [`UDP_PAYLOAD_CONTEXT_ID`]: constant.UDP_PAYLOAD_CONTEXT_ID.html
+pub fn decode_context_id(buf: &[u8]) -> Result<(u64, &[u8])> {
+    let mut b = octets::Octets::with_slice(buf);
+
+    let context_id = b.get_varint()?;
+
+    Ok((context_id, &buf[b.off()..]))
+}
+
+/// Returns the `:path` of a CONNECT-UDP request to the given target.
+///
+/// This uses the default URI template of [RFC 9298], with the template
+/// variables percent-encoded, e.g. the colons of IPv6 addresses.
+///
+/// [RFC 9298]: https://www.rfc-editor.org/rfc/rfc9298.html
+pub fn connect_udp_path(host: &str, port: u16) -> String {
+    format!(
+        "{}{}/{}/",
+        CONNECT_UDP_PATH_PREFIX,
+        percent_encode(host),
+        port
+    )
+}
+
+/// Parses the target host and port from the `:path` of a CONNECT-UDP request.
+///
+/// [`MessageError`] is returned if the path doesn't follow the default URI
+/// template of [RFC 9298].
+///
+/// [`MessageError`]: enum.Error.html#variant.MessageError
+/// [RFC 9298]: https://www.rfc-editor.org/rfc/rfc9298.html
+pub fn parse_connect_udp_path(path: &[u8]) -> Result<(String, u16)> {
+    let path = std::str::from_utf8(path).map_err(|_| Error::MessageError)?;
+
+    let target = path
+        .strip_prefix(CONNECT_UDP_PATH_PREFIX)
+        .and_then(|t| t.strip_suffix('/'))
+        .ok_or(Error::MessageError)?;
+
+    let (host, port) = target.split_once('/').ok_or(Error::MessageError)?;
+
+    if host.is_empty() {
+        return Err(Error::MessageError);
+    }
+
+    let host = percent_decode(host)?;
+    let port = percent_decode(port)?
+        .parse()
+        .map_err(|_| Error::MessageError)?;
+
+    Ok((host, port))
+}
+
+/// Percent-encodes all characters outside of the unreserved set of
+/// [RFC 3986], as done by simple string expansion of URI templates.
+///
+/// [RFC 3986]: https://www.rfc-editor.org/rfc/rfc3986.html#section-2.3
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+
+    out
+}
+
+/// Decodes all percent-encoded octets of a URI template variable.
+///
+/// [`MessageError`] is returned for malformed escapes and for values that
+/// aren't valid UTF-8 once decoded.
+///
+/// [`MessageError`]: enum.Error.html#variant.MessageError
+fn percent_decode(s: &str) -> Result<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+
+        let hi = bytes.next().and_then(|c| (c as char).to_digit(16));
+        let lo = bytes.next().and_then(|c| (c as char).to_digit(16));
+
+        match (hi, lo) {
+            (Some(hi), Some(lo)) => out.push((hi << 4 | lo) as u8),
+
+            _ => return Err(Error::MessageError),
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| Error::MessageError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capsule_datagram() {
+        let mut d = [42; 128];
+
+        let capsule = Capsule::Datagram {
+            payload: b"hello".to_vec(),
+        };
+
+        let wire_len = capsule.to_bytes(&mut d).unwrap();
+        assert_eq!(wire_len, 7);
+        assert_eq!(wire_len, capsule.wire_len());
+
+        assert_eq!(Capsule::from_bytes(&d[..wire_len]), Ok((capsule, 7)));
+    }
+
+    #[test]
+    fn capsule_unknown() {
+        let mut d = [42; 128];
+
+        let capsule = Capsule::Unknown {
+            ty: 0x1234,
+            payload: vec![1, 2, 3],
+        };
+
+        let wire_len = capsule.to_bytes(&mut d).unwrap();
+        assert_eq!(wire_len, 6);
+
+        assert_eq!(Capsule::from_bytes(&d[..wire_len]), Ok((capsule, 6)));
+    }
+
+    #[test]
+    fn capsule_incomplete() {
+        let mut d = [42; 128];
+
+        let capsule = Capsule::Datagram {
+            payload: b"hello".to_vec(),
+        };
+
+        let wire_len = capsule.to_bytes(&mut d).unwrap();
+
+        assert_eq!(
+            Capsule::from_bytes(&d[..wire_len - 1]),
+            Err(Error::BufferTooShort)
+        );
+        assert_eq!(Capsule::from_bytes(&[]), Err(Error::BufferTooShort));
+    }
+
+    #[test]
+    fn connect_udp_paths() {
+        let path = connect_udp_path("192.0.2.6", 443);
+        assert_eq!(path, "/.well-known/masque/udp/192.0.2.6/443/");
+        assert_eq!(
+            parse_connect_udp_path(path.as_bytes()),
+            Ok(("192.0.2.6".to_string(), 443))
+        );
+
+        let path = connect_udp_path("2001:db8::42", 53);
+        assert_eq!(path, "/.well-known/masque/udp/2001%3Adb8%3A%3A42/53/");
+        assert_eq!(
+            parse_connect_udp_path(path.as_bytes()),
+            Ok(("2001:db8::42".to_string(), 53))
+        );
+
+        assert_eq!(
+            parse_connect_udp_path(b"/.well-known/masque/udp/example.org/"),
+            Err(Error::MessageError)
+        );
+        assert_eq!(
+            parse_connect_udp_path(b"/.well-known/masque/udp/example.org/x/"),
+            Err(Error::MessageError)
+        );
+        assert_eq!(parse_connect_udp_path(b"/test"), Err(Error::MessageError));
+    }
+
+    #[test]
+    fn connect_udp_paths_percent_encoded() {
+        let path = connect_udp_path("fe80::1%eth0", 443);
+        assert_eq!(path, "/.well-known/masque/udp/fe80%3A%3A1%25eth0/443/");
+        assert_eq!(
+            parse_connect_udp_path(path.as_bytes()),
+            Ok(("fe80::1%eth0".to_string(), 443))
+        );
+
+        assert_eq!(
+            parse_connect_udp_path(
+                b"/.well-known/masque/udp/%65xample.org/%34%34%33/"
+            ),
+            Ok(("example.org".to_string(), 443))
+        );
+        assert_eq!(
+            parse_connect_udp_path(
+                b"/.well-known/masque/udp/2001%3adb8%3A%3A1/53/"
+            ),
+            Ok(("2001:db8::1".to_string(), 53))
+        );
+
+        assert_eq!(
+            parse_connect_udp_path(b"/.well-known/masque/udp/example%2/443/"),
+            Err(Error::MessageError)
+        );
+        assert_eq!(
+            parse_connect_udp_path(b"/.well-known/masque/udp/example%zz/443/"),
+            Err(Error::MessageError)
+        );
+        assert_eq!(
+            parse_connect_udp_path(b"/.well-known/masque/udp/%ff/443/"),
+            Err(Error::MessageError)
+        );
+    }
+
+    #[test]
+    fn udp_payload_context_id() {
+        let d = encode_udp_payload(b"hello");
+        assert_eq!(d, b"\x00hello");
+        assert_eq!(
+            decode_context_id(&d),
+            Ok((UDP_PAYLOAD_CONTEXT_ID, &b"hello"[..]))
+        );
+
+        assert_eq!(
+            decode_context_id(&[0x40, 0x2a, 1, 2]),
+            Ok((42, &[1, 2][..]))
+        );
+        assert_eq!(decode_context_id(&[]), Err(Error::BufferTooShort));
+    }
+}
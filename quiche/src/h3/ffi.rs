@@ -77,6 +77,11 @@ pub extern fn quiche_h3_config_enable_extended_connect(
     config.enable_extended_connect(enabled);
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_config_set_max_push_id(config: &mut h3::Config, v: u64) {
+    config.set_max_push_id(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_config_free(config: *mut h3::Config) {
     unsafe { Box::from_raw(config) };
@@ -116,6 +121,26 @@ pub extern fn quiche_h3_for_each_setting(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_peer_setting(
+    conn: &h3::Connection, identifier: u64, value: &mut u64,
+) -> c_int {
+    let raw = match conn.peer_settings_raw() {
+        Some(v) => v,
+
+        None => return h3::Error::Done.to_c() as c_int,
+    };
+
+    match raw.iter().find(|(id, _)| *id == identifier) {
+        Some((_, v)) => {
+            *value = *v;
+            0
+        },
+
+        None => h3::Error::Done.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_conn_poll(
     conn: &mut h3::Connection, quic_conn: &mut Connection,
@@ -150,6 +175,12 @@ pub extern fn quiche_h3_event_type(ev: &h3::Event) -> u32 {
         h3::Event::Reset { .. } => 5,
 
         h3::Event::PriorityUpdate { .. } => 6,
+
+        h3::Event::PushPromise { .. } => 7,
+
+        h3::Event::InterimHeaders { .. } => 8,
+
+        h3::Event::Trailers { .. } => 9,
     }
 }
 
@@ -168,7 +199,10 @@ pub extern fn quiche_h3_event_for_each_header(
     argp: *mut c_void,
 ) -> c_int {
     match ev {
-        h3::Event::Headers { list, .. } =>
+        h3::Event::Headers { list, .. } |
+        h3::Event::InterimHeaders { list } |
+        h3::Event::Trailers { list } |
+        h3::Event::PushPromise { list, .. } =>
             for h in list {
                 let rc = cb(
                     h.name().as_ptr(),
@@ -198,6 +232,29 @@ pub extern fn quiche_h3_event_headers_has_body(ev: &h3::Event) -> bool {
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_event_push_promise_id(ev: &h3::Event) -> u64 {
+    match ev {
+        h3::Event::PushPromise { push_id, .. } => *push_id,
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_event_reset_error_code(ev: &h3::Event) -> u64 {
+    match ev {
+        h3::Event::Reset(error_code) => *error_code,
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_push_enabled_by_peer(conn: &h3::Connection) -> bool {
+    conn.push_enabled_by_peer()
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_extended_connect_enabled_by_peer(
     conn: &h3::Connection,
@@ -267,6 +324,62 @@ pub extern fn quiche_h3_send_response_with_priority(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_send_interim_response(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    headers: *const Header, headers_len: size_t,
+) -> c_int {
+    let resp_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_interim_response(quic_conn, stream_id, &resp_headers) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_trailers(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    headers: *const Header, headers_len: size_t,
+) -> c_int {
+    let trailers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_trailers(quic_conn, stream_id, &trailers) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_push(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    headers: *const Header, headers_len: size_t,
+) -> i64 {
+    let req_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.push(quic_conn, stream_id, &req_headers) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_push_id(
+    conn: &h3::Connection, stream_id: u64, push_id: &mut u64,
+) -> bool {
+    match conn.push_id(stream_id) {
+        Some(v) => {
+            *push_id = v;
+            true
+        },
+
+        None => false,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_send_body(
     conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
@@ -285,6 +398,31 @@ pub extern fn quiche_h3_send_body(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_send_body_capacity(
+    conn: &h3::Connection, quic_conn: &Connection, stream_id: u64, len: size_t,
+) -> ssize_t {
+    match conn.send_body_capacity(quic_conn, stream_id, len) {
+        Ok(v) => v as ssize_t,
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_body_readiness(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    len: size_t,
+) -> c_int {
+    match conn.send_body_readiness(quic_conn, stream_id, len) {
+        Ok(true) => 1,
+
+        Ok(false) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_recv_body(
     conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
@@ -333,6 +471,35 @@ pub extern fn quiche_h3_send_priority_update_for_request(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_reprioritize(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    priority: &Priority,
+) -> c_int {
+    match conn.reprioritize(quic_conn, stream_id, priority) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+#[cfg(feature = "sfv")]
+pub extern fn quiche_h3_apply_priority_update(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    applied: &mut Priority,
+) -> c_int {
+    match conn.apply_priority_update(quic_conn, stream_id) {
+        Ok(v) => {
+            applied.urgency = v.urgency;
+            applied.incremental = v.incremental;
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_take_last_priority_update(
     conn: &mut h3::Connection, prioritized_element_id: u64,
@@ -365,6 +532,17 @@ pub extern fn quiche_h3_dgram_enabled_by_peer(
     conn.dgram_enabled_by_peer(quic_conn)
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_dgram_max_writable_len(
+    conn: &h3::Connection, quic_conn: &Connection, flow_id: u64,
+) -> ssize_t {
+    match conn.dgram_max_writable_len(quic_conn, flow_id) {
+        None => h3::Error::Done.to_c(),
+
+        Some(v) => v as ssize_t,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_send_dgram(
     conn: &mut h3::Connection, quic_conn: &mut Connection, flow_id: u64,
@@ -405,6 +583,39 @@ pub extern fn quiche_h3_recv_dgram(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_send_goaway(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, id: u64,
+) -> c_int {
+    match conn.send_goaway(quic_conn, id) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_goaway(
+    conn: &mut h3::Connection, quic_conn: &mut Connection,
+) -> i64 {
+    match conn.goaway(quic_conn) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_max_push_id(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+) -> c_int {
+    match conn.send_max_push_id(quic_conn, push_id) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_conn_free(conn: *mut h3::Connection) {
     unsafe { Box::from_raw(conn) };
@@ -43,7 +43,7 @@ pub const SETTINGS_QPACK_MAX_TABLE_CAPACITY: u64 = 0x1;
 pub const SETTINGS_MAX_FIELD_SECTION_SIZE: u64 = 0x6;
 pub const SETTINGS_QPACK_BLOCKED_STREAMS: u64 = 0x7;
 pub const SETTINGS_ENABLE_CONNECT_PROTOCOL: u64 = 0x8;
-pub const SETTINGS_H3_DATAGRAM: u64 = 0x276;
+pub const SETTINGS_H3_DATAGRAM: u64 = 0x33;
 
 // Permit between 16 maximally-encoded and 128 minimally-encoded SETTINGS.
 const MAX_SETTINGS_PAYLOAD_SIZE: usize = 256;
@@ -725,7 +725,7 @@ mod tests {
             raw: Some(raw_settings),
         };
 
-        let frame_payload_len = 11;
+        let frame_payload_len = 10;
         let frame_header_len = 2;
 
         let wire_len = {
@@ -781,7 +781,7 @@ mod tests {
             raw: Some(raw_settings),
         };
 
-        let frame_payload_len = 13;
+        let frame_payload_len = 12;
         let frame_header_len = 2;
 
         let wire_len = {
@@ -928,7 +928,7 @@ mod tests {
             raw: Some(raw_settings),
         };
 
-        let frame_payload_len = 3;
+        let frame_payload_len = 2;
         let frame_header_len = 2;
 
         let wire_len = {
@@ -174,12 +174,20 @@
 //!
 //!         Ok((_flow_id, quiche::h3::Event::Datagram)) => (),
 //!
+//!         Ok((stream_id, quiche::h3::Event::Trailers{list})) => {
+//!             // Request trailers were received, handle them.
+//!         },
+//!
+//!         Ok((_, quiche::h3::Event::InterimHeaders{..})) => (),
+//!
 //!         Ok((_flow_id, quiche::h3::Event::PriorityUpdate)) => (),
 //!
 //!         Ok((goaway_id, quiche::h3::Event::GoAway)) => {
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((_, quiche::h3::Event::PushPromise{..})) => (),
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -237,12 +245,22 @@
 //!
 //!         Ok((_flow_id, quiche::h3::Event::Datagram)) => (),
 //!
+//!         Ok((stream_id, quiche::h3::Event::InterimHeaders{list})) => {
+//!             // Informational response (e.g. 103 Early Hints), handle it.
+//!         },
+//!
+//!         Ok((stream_id, quiche::h3::Event::Trailers{list})) => {
+//!             // Response trailers were received, handle them.
+//!         },
+//!
 //!         Ok((_prioritized_element_id, quiche::h3::Event::PriorityUpdate)) => (),
 //!
 //!         Ok((goaway_id, quiche::h3::Event::GoAway)) => {
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((_stream_id, quiche::h3::Event::PushPromise{..})) => (),
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -264,6 +282,57 @@
 //! repeatedly will generate an [`Event`] for each of these. The application may
 //! use these event to do additional HTTP semantic validation.
 //!
+//! Informational (1xx) responses that precede the final response are reported
+//! as `InterimHeaders` events, and a trailer section following the body as a
+//! `Trailers` event. They are sent with [`send_interim_response()`] and
+//! [`send_trailers()`] respectively.
+//!
+//! ## Tunneling with CONNECT and CONNECT-UDP
+//!
+//! With the CONNECT method, the body of a request stream carries the bytes of
+//! a TCP tunnel in both directions, using [`send_body()`] and [`recv_body()`].
+//!
+//! CONNECT-UDP ([RFC 9298]) is an extended CONNECT request, which requires
+//! [`enable_extended_connect()`], with the `connect-udp` protocol and a
+//! `:path` identifying the target, as returned by [`connect_udp_path()`]. UDP
+//! payloads are then sent as HTTP Datagrams ([RFC 9297]), prefixed with their
+//! Context ID by [`encode_udp_payload()`], either in DATAGRAM frames using
+//! [`send_dgram()`] with the quarter stream ID of the request (i.e.
+//! `stream_id / 4`) as flow ID, or as [`Capsule`]s in the body of the request
+//! stream using [`send_capsule()`]:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, local, peer, &mut config).unwrap();
+//! # let h3_config = quiche::h3::Config::new()?;
+//! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
+//! let path = quiche::h3::connect_udp_path("192.0.2.6", 443);
+//!
+//! let req = vec![
+//!     quiche::h3::Header::new(b":method", b"CONNECT"),
+//!     quiche::h3::Header::new(b":protocol", b"connect-udp"),
+//!     quiche::h3::Header::new(b":scheme", b"https"),
+//!     quiche::h3::Header::new(b":authority", b"proxy.example.org"),
+//!     quiche::h3::Header::new(b":path", path.as_bytes()),
+//!     quiche::h3::Header::new(b"capsule-protocol", b"?1"),
+//! ];
+//!
+//! let stream_id = h3_conn.send_request(&mut conn, &req, false)?;
+//!
+//! // Once the proxy accepted the request.
+//! let payload = quiche::h3::encode_udp_payload(b"udp payload");
+//! h3_conn.send_dgram(&mut conn, stream_id / 4, &payload)?;
+//! # Ok::<(), quiche::h3::Error>(())
+//! ```
+//!
+//! Proxies can retrieve the target of CONNECT-UDP requests with
+//! [`parse_connect_udp_path()`], the capsules received in the request body
+//! with [`Capsule::from_bytes()`], and the Context ID of HTTP Datagrams with
+//! [`decode_context_id()`].
+//!
 //! ## HTTP/3 protocol errors
 //!
 //! Quiche is responsible for managing the HTTP/3 connection, ensuring it is in
@@ -285,6 +354,21 @@
 //! [`send_request()`]: struct.Connection.html#method.send_response
 //! [`send_response()`]: struct.Connection.html#method.send_response
 //! [`send_body()`]: struct.Connection.html#method.send_body
+//! [`send_interim_response()`]: struct.Connection.html#method.send_interim_response
+//! [`send_trailers()`]: struct.Connection.html#method.send_trailers
+//! [`recv_body()`]: struct.Connection.html#method.recv_body
+//! [`send_dgram()`]: struct.Connection.html#method.send_dgram
+//! [`send_capsule()`]: struct.Connection.html#method.send_capsule
+//! [`enable_extended_connect()`]:
+//! struct.Config.html#method.enable_extended_connect
+//! [`connect_udp_path()`]: fn.connect_udp_path.html
+//! [`parse_connect_udp_path()`]: fn.parse_connect_udp_path.html
+//! [`Capsule`]: enum.Capsule.html
+//! [`Capsule::from_bytes()`]: enum.Capsule.html#method.from_bytes
+//! [`encode_udp_payload()`]: fn.encode_udp_payload.html
+//! [`decode_context_id()`]: fn.decode_context_id.html
+//! [RFC 9297]: https://www.rfc-editor.org/rfc/rfc9297.html
+//! [RFC 9298]: https://www.rfc-editor.org/rfc/rfc9298.html
 
 use std::collections::VecDeque;
 
@@ -515,6 +599,7 @@ pub struct Config {
     qpack_max_table_capacity: Option<u64>,
     qpack_blocked_streams: Option<u64>,
     connect_protocol_enabled: Option<u64>,
+    max_push_id: Option<u64>,
 }
 
 impl Config {
@@ -525,6 +610,7 @@ impl Config {
             qpack_max_table_capacity: None,
             qpack_blocked_streams: None,
             connect_protocol_enabled: None,
+            max_push_id: None,
         })
     }
 
@@ -565,6 +651,17 @@ impl Config {
             self.connect_protocol_enabled = None;
         }
     }
+
+    /// Sets the maximum push ID a client allows the server to use.
+    ///
+    /// When set, clients send it in a MAX_PUSH_ID frame right after their
+    /// SETTINGS, which allows the server to push up to `v + 1` responses.
+    /// This has no effect on servers.
+    ///
+    /// By default server push is disabled.
+    pub fn set_max_push_id(&mut self, v: u64) {
+        self.max_push_id = Some(v);
+    }
 }
 
 /// A trait for types with associated string name and value.
@@ -660,6 +757,32 @@ pub enum Event {
         has_body: bool,
     },
 
+    /// Informational (1xx) response headers were received.
+    ///
+    /// This only occurs at clients, any number of times before the [`Headers`]
+    /// event of the final response, for example when receiving a `100
+    /// Continue` or `103 Early Hints` response.
+    ///
+    /// [`Headers`]: enum.Event.html#variant.Headers
+    InterimHeaders {
+        /// The list of received header fields.
+        list: Vec<Header>,
+    },
+
+    /// Trailers were received after the body.
+    ///
+    /// A trailer section terminates the request or response: receiving DATA or
+    /// HEADERS frames after it is a connection error. The next event for the
+    /// stream is therefore [`Finished`], once the peer ends the stream, unless
+    /// it is reset first (see [`Reset`]).
+    ///
+    /// [`Finished`]: enum.Event.html#variant.Finished
+    /// [`Reset`]: enum.Event.html#variant.Reset
+    Trailers {
+        /// The list of received trailer fields.
+        list: Vec<Header>,
+    },
+
     /// Data was received.
     ///
     /// This indicates that the application can use the [`recv_body()`] method
@@ -711,6 +834,22 @@ pub enum Event {
 
     /// GOAWAY was received.
     GoAway,
+
+    /// PUSH_PROMISE was received.
+    ///
+    /// This only occurs at clients, and returns the ID of the request stream
+    /// the push is associated with. The pushed response is then received on a
+    /// push stream like any other response, and the [`push_id()`] method can
+    /// be used to match that stream with its promise.
+    ///
+    /// [`push_id()`]: struct.Connection.html#method.push_id
+    PushPromise {
+        /// The push ID of the promised response.
+        push_id: u64,
+
+        /// The list of header fields of the promised request.
+        list: Vec<Header>,
+    },
 }
 
 /// Extensible Priorities parameters.
@@ -719,7 +858,7 @@ pub enum Event {
 /// Structured Fields Dictionary field value. I.e, use `TryFrom` to parse the
 /// value of a Priority header field or a PRIORITY_UPDATE frame. Using this
 /// trait requires the `sfv` feature to be enabled.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub struct Priority {
     urgency: u8,
@@ -743,6 +882,16 @@ impl Priority {
             incremental,
         }
     }
+
+    /// Returns the urgency of the priority.
+    pub const fn urgency(&self) -> u8 {
+        self.urgency
+    }
+
+    /// Returns true if the priority is incremental.
+    pub const fn incremental(&self) -> bool {
+        self.incremental
+    }
 }
 
 #[cfg(feature = "sfv")]
@@ -846,7 +995,9 @@ pub struct Connection {
     local_qpack_streams: QpackStreams,
     peer_qpack_streams: QpackStreams,
 
-    max_push_id: u64,
+    local_max_push_id: Option<u64>,
+    peer_max_push_id: Option<u64>,
+    next_push_id: u64,
 
     finished_streams: VecDeque<u64>,
 
@@ -855,6 +1006,9 @@ pub struct Connection {
     local_goaway_id: Option<u64>,
     peer_goaway_id: Option<u64>,
 
+    largest_processed_request: Option<u64>,
+    largest_processed_push: Option<u64>,
+
     dgram_event_triggered: bool,
 }
 
@@ -908,7 +1062,9 @@ impl Connection {
                 decoder_stream_id: None,
             },
 
-            max_push_id: 0,
+            local_max_push_id: if is_server { None } else { config.max_push_id },
+            peer_max_push_id: None,
+            next_push_id: 0,
 
             finished_streams: VecDeque::new(),
 
@@ -917,6 +1073,9 @@ impl Connection {
             local_goaway_id: None,
             peer_goaway_id: None,
 
+            largest_processed_request: None,
+            largest_processed_push: None,
+
             dgram_event_triggered: false,
         })
     }
@@ -1071,6 +1230,100 @@ impl Connection {
     pub fn send_response_with_priority<T: NameValue>(
         &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
         priority: &Priority, fin: bool,
+    ) -> Result<()> {
+        self.reprioritize(conn, stream_id, priority)?;
+
+        self.send_headers(conn, stream_id, headers, fin)?;
+
+        Ok(())
+    }
+
+    /// Sends an HTTP/3 informational (1xx) response on the specified stream.
+    ///
+    /// Any number of informational responses, such as `100 Continue` or `103
+    /// Early Hints`, can be sent before the final response is sent with
+    /// [`send_response()`]. The `headers` must contain a `:status`
+    /// pseudo-header with a 1xx value.
+    ///
+    /// The [`FrameUnexpected`] error is returned when used by a client, when
+    /// the status is not informational, or when the final response was already
+    /// sent.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`send_response()`]: struct.Connection.html#method.send_response
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_interim_response<T: NameValue>(
+        &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
+    ) -> Result<()> {
+        if !self.is_server || !is_informational(headers) {
+            return Err(Error::FrameUnexpected);
+        }
+
+        match self.streams.get(&stream_id) {
+            Some(s) if !s.local_initialized() => (),
+
+            _ => return Err(Error::FrameUnexpected),
+        };
+
+        self.send_headers(conn, stream_id, headers, false)
+    }
+
+    /// Sends an HTTP/3 trailer section on the specified stream.
+    ///
+    /// Trailers can be sent by both clients and servers after the request or
+    /// response headers and body. The trailer section terminates the stream,
+    /// so no more body can be sent afterwards.
+    ///
+    /// The [`FrameUnexpected`] error is returned when the headers were not
+    /// sent yet, or when trailers were already sent on the stream.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_trailers<T: NameValue>(
+        &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
+    ) -> Result<()> {
+        match self.streams.get(&stream_id) {
+            Some(s) if s.local_initialized() && !s.trailers_sent() => (),
+
+            _ => return Err(Error::FrameUnexpected),
+        };
+
+        self.send_headers(conn, stream_id, headers, true)?;
+
+        // The stream might have been collected already, if it is complete.
+        if let Some(s) = self.streams.get_mut(&stream_id) {
+            s.mark_trailers_sent();
+        }
+
+        Ok(())
+    }
+
+    /// Changes the priority of the response sent on the specified stream.
+    ///
+    /// This can be used by servers to reprioritize a response after it was
+    /// started with [`send_response_with_priority()`], for example when the
+    /// client sent a PRIORITY_UPDATE frame. Any data that is not sent yet is
+    /// scheduled according to the new priority.
+    ///
+    /// The `priority` parameter represents [Extensible Priority]
+    /// parameters. If the urgency is outside the range 0-7, it will be clamped
+    /// to 7.
+    ///
+    /// [`send_response_with_priority()`]: struct.Connection.html#method.send_response_with_priority
+    /// [Extensible Priority]: https://www.rfc-editor.org/rfc/rfc9218.html#section-4.
+    pub fn reprioritize(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        priority: &Priority,
     ) -> Result<()> {
         if !self.streams.contains_key(&stream_id) {
             return Err(Error::FrameUnexpected);
@@ -1084,11 +1337,135 @@ impl Connection {
 
         conn.stream_priority(stream_id, urgency, priority.incremental)?;
 
-        self.send_headers(conn, stream_id, headers, fin)?;
-
         Ok(())
     }
 
+    /// Promises a server push associated with the specified request stream.
+    ///
+    /// A PUSH_PROMISE frame carrying the provided request `headers` is sent on
+    /// the request stream, and a new push stream is opened for the promised
+    /// response. The response is then sent on that stream with
+    /// [`send_response()`] and [`send_body()`], like any other response.
+    ///
+    /// On success the push stream ID is returned.
+    ///
+    /// Only servers can push, and only once the client allowed it by sending a
+    /// MAX_PUSH_ID frame (see [`push_enabled_by_peer()`]). The [`IdError`]
+    /// error is returned when all the push IDs allowed by the client are used.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`send_response()`]: struct.Connection.html#method.send_response
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    /// [`push_enabled_by_peer()`]: struct.Connection.html#method.push_enabled_by_peer
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn push<T: NameValue>(
+        &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
+    ) -> Result<u64> {
+        if !self.is_server || stream_id % 4 != 0 {
+            return Err(Error::FrameUnexpected);
+        }
+
+        if !self.streams.contains_key(&stream_id) {
+            return Err(Error::FrameUnexpected);
+        }
+
+        if !self.push_enabled_by_peer() {
+            return Err(Error::IdError);
+        }
+
+        let push_id = self.next_push_id;
+
+        let header_block = self.encode_header_block(headers)?;
+
+        let payload_len = octets::varint_len(push_id) + header_block.len();
+        let wire_len = octets::varint_len(frame::PUSH_PROMISE_FRAME_TYPE_ID) +
+            octets::varint_len(payload_len as u64) +
+            payload_len;
+
+        let frame = frame::Frame::PushPromise {
+            push_id,
+            header_block,
+        };
+
+        // The PUSH_PROMISE frame needs to be sent atomically, so make sure the
+        // request stream has enough capacity before opening the push stream.
+        match conn.stream_writable(stream_id, wire_len) {
+            Ok(true) => (),
+
+            Ok(false) => return Err(Error::StreamBlocked),
+
+            Err(e) => return Err(e.into()),
+        };
+
+        let push_stream_id =
+            self.open_uni_stream(conn, stream::HTTP3_PUSH_STREAM_TYPE_ID)?;
+
+        let mut d = [0; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(push_stream_id, b.put_varint(push_id)?, false)?;
+
+        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
+                stream_id: push_stream_id,
+                owner: Some(H3Owner::Local),
+                old: None,
+                new: H3StreamType::Push,
+                associated_push_id: Some(push_id),
+            });
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        let mut d = vec![0; wire_len];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        frame.to_bytes(&mut b)?;
+
+        let off = b.off();
+        conn.stream_send(stream_id, &d[..off], false)?;
+
+        trace!(
+            "{} tx frm PUSH_PROMISE stream={} push_id={} push_stream={}",
+            conn.trace_id(),
+            stream_id,
+            push_id,
+            push_stream_id
+        );
+
+        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                stream_id,
+                length: Some(payload_len as u64),
+                frame: frame.to_qlog(),
+                raw: None,
+            });
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        self.streams.insert(
+            push_stream_id,
+            stream::Stream::new_push(push_stream_id, push_id),
+        );
+
+        self.next_push_id += 1;
+
+        Ok(push_stream_id)
+    }
+
+    /// Returns whether the given stream is a push stream opened locally.
+    fn is_local_push_stream(&self, stream_id: u64) -> bool {
+        self.is_server &&
+            self.streams
+                .get(&stream_id)
+                .map_or(false, |s| s.ty() == Some(stream::Type::Push))
+    }
+
     fn encode_header_block<T: NameValue>(
         &mut self, headers: &[T],
     ) -> Result<Vec<u8>> {
@@ -1178,8 +1555,12 @@ impl Connection {
             q.add_event_data_now(ev_data).ok();
         });
 
-        if let Some(s) = self.streams.get_mut(&stream_id) {
-            s.initialize_local();
+        // Informational responses leave the stream uninitialized, as the final
+        // response is still to be sent.
+        if !is_informational(headers) {
+            if let Some(s) = self.streams.get_mut(&stream_id) {
+                s.initialize_local();
+            }
         }
 
         if fin && conn.stream_finished(stream_id) {
@@ -1202,7 +1583,13 @@ impl Connection {
     /// application should retry the operation once the stream is reported as
     /// writable again.
     ///
+    /// The number of bytes that will be written can be known in advance using
+    /// [`send_body_capacity()`], and [`send_body_readiness()`] can be used to
+    /// wait until a whole body chunk can be written at once.
+    ///
     /// [`Done`]: enum.Error.html#variant.Done
+    /// [`send_body_capacity()`]: struct.Connection.html#method.send_body_capacity
+    /// [`send_body_readiness()`]: struct.Connection.html#method.send_body_readiness
     pub fn send_body(
         &mut self, conn: &mut super::Connection, stream_id: u64, body: &[u8],
         fin: bool,
@@ -1211,20 +1598,7 @@ impl Connection {
         let mut b = octets::OctetsMut::with_slice(&mut d);
 
         // Validate that it is sane to send data on the stream.
-        if stream_id % 4 != 0 {
-            return Err(Error::FrameUnexpected);
-        }
-
-        match self.streams.get(&stream_id) {
-            Some(s) =>
-                if !s.local_initialized() {
-                    return Err(Error::FrameUnexpected);
-                },
-
-            None => {
-                return Err(Error::FrameUnexpected);
-            },
-        };
+        self.validate_body_stream(stream_id)?;
 
         // Avoid sending 0-length DATA frames when the fin flag is false.
         if body.is_empty() && !fin {
@@ -1310,6 +1684,97 @@ impl Connection {
         Ok(written)
     }
 
+    /// Returns how many bytes of a body chunk of `len` bytes can currently be
+    /// sent on the given stream.
+    ///
+    /// This accounts for the DATA frame overhead, as well as for the flow
+    /// control and congestion limits of the underlying QUIC stream, so the
+    /// returned value is exactly the number of bytes that [`send_body()`]
+    /// would write for a chunk of the same length, as long as nothing else is
+    /// sent on the connection in the meantime. It can be lower than `len`, and
+    /// `0` when the stream is blocked.
+    ///
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    pub fn send_body_capacity(
+        &self, conn: &super::Connection, stream_id: u64, len: usize,
+    ) -> Result<usize> {
+        self.validate_body_stream(stream_id)?;
+
+        let overhead = octets::varint_len(frame::DATA_FRAME_TYPE_ID) +
+            octets::varint_len(len as u64);
+
+        let stream_cap = conn.stream_capacity(stream_id)?;
+
+        Ok(std::cmp::min(len, stream_cap.saturating_sub(overhead)))
+    }
+
+    /// Returns whether a body chunk of `len` bytes can be sent in full on the
+    /// given stream.
+    ///
+    /// When `false` is returned, the stream is not going to be reported as
+    /// writable again by the transport (e.g. by [`stream_writable_next()`])
+    /// until [`send_body()`] can write the whole chunk in a single call. This
+    /// allows applications to wait for enough capacity, rather than splitting
+    /// their buffers across partial writes.
+    ///
+    /// [`stream_writable_next()`]: ../struct.Connection.html#method.stream_writable_next
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    pub fn send_body_readiness(
+        &mut self, conn: &mut super::Connection, stream_id: u64, len: usize,
+    ) -> Result<bool> {
+        self.validate_body_stream(stream_id)?;
+
+        let overhead = octets::varint_len(frame::DATA_FRAME_TYPE_ID) +
+            octets::varint_len(len as u64);
+
+        Ok(conn.stream_writable(stream_id, overhead + len)?)
+    }
+
+    /// Checks that a body can be sent on the given stream.
+    fn validate_body_stream(&self, stream_id: u64) -> Result<()> {
+        if stream_id % 4 != 0 && !self.is_local_push_stream(stream_id) {
+            return Err(Error::FrameUnexpected);
+        }
+
+        match self.streams.get(&stream_id) {
+            Some(s) if s.local_initialized() && !s.trailers_sent() => Ok(()),
+
+            _ => Err(Error::FrameUnexpected),
+        }
+    }
+
+    /// Sends a capsule in the body of the given request or response stream.
+    ///
+    /// The capsule is sent atomically in a single DATA frame. The
+    /// [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for it. When this happens the application
+    /// should retry the operation once the stream is reported as writable
+    /// again.
+    ///
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_capsule(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        capsule: &Capsule,
+    ) -> Result<()> {
+        let mut d = vec![0; capsule.wire_len()];
+        let len = capsule.to_bytes(&mut d)?;
+
+        let overhead = octets::varint_len(frame::DATA_FRAME_TYPE_ID) +
+            octets::varint_len(len as u64);
+
+        match conn.stream_writable(stream_id, overhead + len) {
+            Ok(true) => (),
+
+            Ok(false) => return Err(Error::StreamBlocked),
+
+            Err(e) => return Err(e.into()),
+        };
+
+        self.send_body(conn, stream_id, &d[..len], false)?;
+
+        Ok(())
+    }
+
     /// Returns whether the peer enabled HTTP/3 DATAGRAM frame support.
     ///
     /// Support is signalled by the peer's SETTINGS, so this method always
@@ -1333,6 +1798,30 @@ impl Connection {
         self.peer_settings.connect_protocol_enabled == Some(1)
     }
 
+    /// Returns whether the peer allows a new server push.
+    ///
+    /// Clients allow pushes by sending a MAX_PUSH_ID frame, so this method
+    /// always returns false until it has been processed using the [`poll()`]
+    /// method, and once all the allowed push IDs have been used. It also
+    /// returns false after a GOAWAY frame was received from the client.
+    ///
+    /// [`poll()`]: struct.Connection.html#method.poll
+    pub fn push_enabled_by_peer(&self) -> bool {
+        let max_push_id = match self.peer_max_push_id {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        if let Some(goaway_id) = self.peer_goaway_id {
+            if self.next_push_id >= goaway_id {
+                return false;
+            }
+        }
+
+        self.next_push_id <= max_push_id
+    }
+
     /// Sends an HTTP/3 DATAGRAM with the specified flow ID.
     pub fn send_dgram(
         &mut self, conn: &mut super::Connection, flow_id: u64, buf: &[u8],
@@ -1476,6 +1965,24 @@ impl Connection {
         Ok(total)
     }
 
+    /// Sends a PRIORITY_UPDATE frame on the control stream with specified
+    /// request stream ID and priority.
+    ///
+    /// The `priority` parameter represents [Extensible Priority]
+    /// parameters. If the urgency is outside the range 0-7, it will be clamped
+    /// to 7.
+    ///
+    /// This is equivalent to [`send_priority_update_for_request()`].
+    ///
+    /// [`send_priority_update_for_request()`]: struct.Connection.html#method.send_priority_update_for_request
+    /// [Extensible Priority]: https://www.rfc-editor.org/rfc/rfc9218.html#section-4.
+    pub fn send_priority_update(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        priority: &Priority,
+    ) -> Result<()> {
+        self.send_priority_update_for_request(conn, stream_id, priority)
+    }
+
     /// Sends a PRIORITY_UPDATE frame on the control stream with specified
     /// request stream ID and priority.
     ///
@@ -1602,6 +2109,33 @@ impl Connection {
         Err(Error::Done)
     }
 
+    /// Applies the last PRIORITY_UPDATE received for a request stream to its
+    /// response.
+    ///
+    /// This takes the last PRIORITY_UPDATE like [`take_last_priority_update()`]
+    /// does, and reprioritizes the response with [`reprioritize()`]. On
+    /// success the new priority is returned.
+    ///
+    /// [`Done`] is returned if there is no PRIORITY_UPDATE to apply, or if its
+    /// field value can't be parsed.
+    ///
+    /// [`take_last_priority_update()`]: struct.Connection.html#method.take_last_priority_update
+    /// [`reprioritize()`]: struct.Connection.html#method.reprioritize
+    /// [`Done`]: enum.Error.html#variant.Done
+    #[cfg(feature = "sfv")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sfv")))]
+    pub fn apply_priority_update(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+    ) -> Result<Priority> {
+        let field_value = self.take_last_priority_update(stream_id)?;
+
+        let priority = Priority::try_from(field_value.as_slice())?;
+
+        self.reprioritize(conn, stream_id, &priority)?;
+
+        Ok(priority)
+    }
+
     /// Processes HTTP/3 data received from the peer.
     ///
     /// On success it returns an [`Event`] and an ID, or [`Done`] when there are
@@ -1611,32 +2145,40 @@ impl Connection {
     /// will not be reported again by calling this method again, until the event
     /// is re-armed.
     ///
-    /// The events [`Headers`], [`Data`] and [`Finished`] return a stream ID,
-    /// which is used in methods [`recv_body()`], [`send_response()`] or
-    /// [`send_body()`].
+    /// The events [`Headers`], [`InterimHeaders`], [`Trailers`], [`Data`] and
+    /// [`Finished`] return a stream ID, which is used in methods
+    /// [`recv_body()`], [`send_response()`] or [`send_body()`].
     ///
     /// The event [`Datagram`] returns a dummy value of `0`, this should be
     /// ignored by the application.
     ///
     /// The event [`GoAway`] returns an ID that depends on the connection role.
-    /// A client receives the largest processed stream ID. A server receives the
-    /// the largest permitted push ID.
+    /// A client receives the smallest request stream ID that the server will
+    /// not process, so requests on that stream or later ones can be retried on
+    /// a new connection. A server receives the smallest push ID that the client
+    /// will not accept.
     ///
     /// The event [`PriorityUpdate`] only occurs at servers. It returns a
     /// prioritized element ID that is used in the method
     /// [`take_last_priority_update()`], which rearms the event for that ID.
     ///
+    /// The event [`PushPromise`] only occurs at clients. It returns the ID of
+    /// the request stream the push is associated with.
+    ///
     /// If an error occurs while processing data, the connection is closed with
     /// the appropriate error code, using the transport's [`close()`] method.
     ///
     /// [`Event`]: enum.Event.html
     /// [`Done`]: enum.Error.html#variant.Done
     /// [`Headers`]: enum.Event.html#variant.Headers
+    /// [`InterimHeaders`]: enum.Event.html#variant.InterimHeaders
+    /// [`Trailers`]: enum.Event.html#variant.Trailers
     /// [`Data`]: enum.Event.html#variant.Data
     /// [`Finished`]: enum.Event.html#variant.Finished
     /// [`Datagram`]: enum.Event.html#variant.Datagram
     /// [`GoAway`]: enum.Event.html#variant.GoAWay
     /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+    /// [`PushPromise`]: enum.Event.html#variant.PushPromise
     /// [`recv_body()`]: struct.Connection.html#method.recv_body
     /// [`send_response()`]: struct.Connection.html#method.send_response
     /// [`send_body()`]: struct.Connection.html#method.send_body
@@ -1735,10 +2277,16 @@ impl Connection {
 
     /// Sends a GOAWAY frame to initiate graceful connection closure.
     ///
-    /// When quiche is used in the server role, the `id` parameter is the stream
-    /// ID of the highest processed request. This can be any valid ID between 0
-    /// and 2^62-4. However, the ID cannot be increased. Failure to satisfy
-    /// these conditions will return an error.
+    /// When quiche is used in the server role, the `id` parameter is the
+    /// smallest request stream ID that will not be processed. This can be any
+    /// valid client-initiated bidirectional stream ID between 0 and 2^62-4.
+    /// When used in the client role, the `id` parameter is the smallest push
+    /// ID that will not be accepted. In both cases the ID cannot be increased.
+    /// Failure to satisfy these conditions will return an error.
+    ///
+    /// Once the frame is sent, new requests (or pushes) with an ID greater than
+    /// or equal to `id` are rejected, while the ones with a smaller ID are
+    /// processed as usual.
     ///
     /// This method does not close the QUIC connection. Applications are
     /// required to call [`close()`] themselves.
@@ -1747,15 +2295,6 @@ impl Connection {
     pub fn send_goaway(
         &mut self, conn: &mut super::Connection, id: u64,
     ) -> Result<()> {
-        let mut id = id;
-
-        // TODO: server push
-        //
-        // In the meantime always send 0 from client.
-        if !self.is_server {
-            id = 0;
-        }
-
         if self.is_server && id % 4 != 0 {
             return Err(Error::IdError);
         }
@@ -1801,6 +2340,105 @@ impl Connection {
         Ok(())
     }
 
+    /// Sends a GOAWAY frame allowing all the requests (or pushes) processed so
+    /// far to complete.
+    ///
+    /// This is a convenience wrapper around [`send_goaway()`] that uses the ID
+    /// following the largest request stream ID (when used in the server role)
+    /// or push ID (when used in the client role) received so far. Any request
+    /// or push initiated by the peer after this point is rejected.
+    ///
+    /// On success the ID sent in the GOAWAY frame is returned. Applications can
+    /// call [`close()`] once all the outstanding requests have completed, in
+    /// order to drain the connection gracefully.
+    ///
+    /// [`send_goaway()`]: struct.Connection.html#method.send_goaway
+    /// [`close()`]: ../struct.Connection.html#method.close
+    pub fn goaway(&mut self, conn: &mut super::Connection) -> Result<u64> {
+        let id = if self.is_server {
+            self.largest_processed_request.map_or(0, |id| id + 4)
+        } else {
+            self.largest_processed_push.map_or(0, |id| id + 1)
+        };
+
+        // Never increase a previously sent ID.
+        let id = match self.local_goaway_id {
+            Some(sent) => std::cmp::min(id, sent),
+
+            None => id,
+        };
+
+        self.send_goaway(conn, id)?;
+
+        Ok(id)
+    }
+
+    /// Sends a MAX_PUSH_ID frame to allow the server to push more responses.
+    ///
+    /// Only clients can send MAX_PUSH_ID frames, and the push ID limit cannot
+    /// be reduced. Failure to satisfy these conditions will return an error.
+    /// The initial limit is set with [`Config::set_max_push_id()`].
+    ///
+    /// [`Config::set_max_push_id()`]: struct.Config.html#method.set_max_push_id
+    pub fn send_max_push_id(
+        &mut self, conn: &mut super::Connection, push_id: u64,
+    ) -> Result<()> {
+        if self.is_server {
+            return Err(Error::FrameUnexpected);
+        }
+
+        if let Some(max_push_id) = self.local_max_push_id {
+            if push_id < max_push_id {
+                return Err(Error::IdError);
+            }
+        }
+
+        if let Some(stream_id) = self.control_stream_id {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            let frame = frame::Frame::MaxPushId { push_id };
+
+            let wire_len = frame.to_bytes(&mut b)?;
+            let stream_cap = conn.stream_capacity(stream_id)?;
+
+            if stream_cap < wire_len {
+                return Err(Error::StreamBlocked);
+            }
+
+            trace!("{} tx frm {:?}", conn.trace_id(), frame);
+
+            qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+                let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                    stream_id,
+                    length: Some(octets::varint_len(push_id) as u64),
+                    frame: frame.to_qlog(),
+                    raw: None,
+                });
+
+                q.add_event_data_now(ev_data).ok();
+            });
+
+            let off = b.off();
+            conn.stream_send(stream_id, &d[..off], false)?;
+        }
+
+        self.local_max_push_id = Some(push_id);
+
+        Ok(())
+    }
+
+    /// Returns the push ID of the specified push stream.
+    ///
+    /// Clients can use this to match a response received on a push stream with
+    /// the corresponding [`PushPromise`] event. `None` is returned if the
+    /// stream is not a push stream, or if its push ID was not received yet.
+    ///
+    /// [`PushPromise`]: enum.Event.html#variant.PushPromise
+    pub fn push_id(&self, stream_id: u64) -> Option<u64> {
+        self.streams.get(&stream_id).and_then(|s| s.push_id())
+    }
+
     /// Gets the raw settings from peer including unknown and reserved types.
     ///
     /// The order of settings is the same as received in the SETTINGS frame.
@@ -1824,7 +2462,8 @@ impl Connection {
                 conn.stream_priority(stream_id, 0, true)?;
             },
 
-            // TODO: Server push
+            // Push streams use the default priority until the response is
+            // sent.
             stream::HTTP3_PUSH_STREAM_TYPE_ID => (),
 
             // Anything else is a GREASE stream, so make it the least important.
@@ -2099,6 +2738,10 @@ impl Connection {
             });
         }
 
+        if let Some(push_id) = self.local_max_push_id {
+            self.send_max_push_id(conn, push_id)?;
+        }
+
         Ok(())
     }
 
@@ -2139,6 +2782,30 @@ impl Connection {
     fn process_readable_stream(
         &mut self, conn: &mut super::Connection, stream_id: u64, polling: bool,
     ) -> Result<(u64, Event)> {
+        if self.is_server &&
+            crate::stream::is_bidi(stream_id) &&
+            !self.streams.contains_key(&stream_id)
+        {
+            // Reject new requests beyond the ID sent in a GOAWAY frame.
+            if self.local_goaway_id.map_or(false, |id| stream_id >= id) {
+                trace!(
+                    "{} rejecting request {} after GOAWAY",
+                    conn.trace_id(),
+                    stream_id
+                );
+
+                let e = Error::RequestRejected.to_wire();
+
+                conn.stream_shutdown(stream_id, crate::Shutdown::Read, e)?;
+                conn.stream_shutdown(stream_id, crate::Shutdown::Write, e)?;
+
+                return Err(Error::Done);
+            }
+
+            self.largest_processed_request =
+                self.largest_processed_request.max(Some(stream_id));
+        }
+
         self.streams
             .entry(stream_id)
             .or_insert_with(|| stream::Stream::new(stream_id, false));
@@ -2265,10 +2932,36 @@ impl Connection {
                         Err(_) => continue,
                     };
 
+                    if self.local_max_push_id.map_or(true, |max| varint > max) {
+                        conn.close(
+                            true,
+                            Error::IdError.to_wire(),
+                            b"Push stream received with push ID beyond MAX_PUSH_ID",
+                        )?;
+
+                        return Err(Error::IdError);
+                    }
+
                     if let Err(e) = stream.set_push_id(varint) {
                         conn.close(true, e.to_wire(), b"")?;
                         return Err(e);
                     }
+
+                    // Cancel pushes beyond the ID sent in a GOAWAY frame.
+                    if self.local_goaway_id.map_or(false, |id| varint >= id) {
+                        conn.stream_shutdown(
+                            stream_id,
+                            crate::Shutdown::Read,
+                            Error::RequestCancelled.to_wire(),
+                        )?;
+
+                        stream.finished();
+
+                        break;
+                    }
+
+                    self.largest_processed_push =
+                        self.largest_processed_push.max(Some(varint));
                 },
 
                 stream::State::FrameType => {
@@ -2573,6 +3266,28 @@ impl Connection {
                     q.add_event_data_now(ev_data).ok();
                 });
 
+                if let Some(s) = self.streams.get_mut(&stream_id) {
+                    // Any header section after the final one is a trailer
+                    // section.
+                    if s.headers_received() {
+                        s.mark_trailers_received();
+
+                        return Ok((stream_id, Event::Trailers {
+                            list: headers,
+                        }));
+                    }
+
+                    // Only responses can be informational, and any number of
+                    // them can precede the final one.
+                    if !self.is_server && is_informational(&headers) {
+                        return Ok((stream_id, Event::InterimHeaders {
+                            list: headers,
+                        }));
+                    }
+
+                    s.mark_headers_received();
+                }
+
                 let has_body = !conn.stream_finished(stream_id);
 
                 return Ok((stream_id, Event::Headers {
@@ -2654,7 +3369,7 @@ impl Connection {
                     return Err(Error::FrameUnexpected);
                 }
 
-                if push_id < self.max_push_id {
+                if self.peer_max_push_id.map_or(false, |max| push_id < max) {
                     conn.close(
                         true,
                         Error::IdError.to_wire(),
@@ -2664,10 +3379,13 @@ impl Connection {
                     return Err(Error::IdError);
                 }
 
-                self.max_push_id = push_id;
+                self.peer_max_push_id = Some(push_id);
             },
 
-            frame::Frame::PushPromise { .. } => {
+            frame::Frame::PushPromise {
+                push_id,
+                header_block,
+            } => {
                 if self.is_server {
                     conn.close(
                         true,
@@ -2688,7 +3406,53 @@ impl Connection {
                     return Err(Error::FrameUnexpected);
                 }
 
-                // TODO: implement more checks and PUSH_PROMISE event
+                if self.local_max_push_id.map_or(true, |max| push_id > max) {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PUSH_PROMISE received with push ID beyond MAX_PUSH_ID",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                let max_size = self
+                    .local_settings
+                    .max_field_section_size
+                    .unwrap_or(std::u64::MAX);
+
+                let headers = match self
+                    .qpack_decoder
+                    .decode(&header_block[..], max_size)
+                {
+                    Ok(v) => v,
+
+                    Err(e) => {
+                        let e = match e {
+                            qpack::Error::HeaderListTooLarge =>
+                                Error::ExcessiveLoad,
+
+                            _ => Error::QpackDecompressionFailed,
+                        };
+
+                        conn.close(true, e.to_wire(), b"Error parsing headers.")?;
+
+                        return Err(e);
+                    },
+                };
+
+                // Ignore promises beyond the ID sent in a GOAWAY frame.
+                if self.local_goaway_id.map_or(false, |id| push_id >= id) {
+                    return Err(Error::Done);
+                }
+
+                self.largest_processed_push =
+                    self.largest_processed_push.max(Some(push_id));
+
+                return Ok((stream_id, Event::PushPromise {
+                    push_id,
+                    list: headers,
+                }));
             },
 
             frame::Frame::CancelPush { .. } => {
@@ -2821,6 +3585,17 @@ impl Connection {
     }
 }
 
+/// Returns whether the header list is an informational (1xx) response.
+fn is_informational<T: NameValue>(headers: &[T]) -> bool {
+    headers.iter().any(|h| {
+        h.name() == b":status" &&
+            h.value().len() == 3 &&
+            h.value()[0] == b'1' &&
+            // 101 (Switching Protocols) is not supported (RFC 9114 Section 4.5).
+            h.value() != b"101"
+    })
+}
+
 /// Generates an HTTP/3 GREASE variable length integer.
 fn grease_value() -> u64 {
     let n = super::rand::rand_u64_uniform(148_764_065_110_560_899);
@@ -3597,6 +4372,213 @@ mod tests {
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
+    #[test]
+    /// Send informational responses before the final response.
+    fn interim_response() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let early_hints = vec![
+            Header::new(b":status", b"103"),
+            Header::new(b"link", b"</style.css>; rel=preload; as=style"),
+        ];
+
+        // Only informational responses are allowed, and only by servers.
+        assert_eq!(
+            s.server
+                .send_interim_response(&mut s.pipe.server, stream, &[
+                    Header::new(b":status", b"200")
+                ]),
+            Err(Error::FrameUnexpected)
+        );
+        assert_eq!(
+            s.client.send_interim_response(
+                &mut s.pipe.client,
+                stream,
+                &early_hints
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        // HTTP/3 doesn't support 101 (Switching Protocols).
+        assert_eq!(
+            s.server
+                .send_interim_response(&mut s.pipe.server, stream, &[
+                    Header::new(b":status", b"101")
+                ]),
+            Err(Error::FrameUnexpected)
+        );
+
+        s.server
+            .send_interim_response(&mut s.pipe.server, stream, &early_hints)
+            .unwrap();
+
+        let resp = s.send_response(stream, true).unwrap();
+
+        // No more informational responses after the final one.
+        assert_eq!(
+            s.server.send_interim_response(
+                &mut s.pipe.server,
+                stream,
+                &early_hints
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        let ev_interim = Event::InterimHeaders { list: early_hints };
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_interim)));
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send trailers after the request and response bodies.
+    fn request_response_with_trailers() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let body = s.send_body_client(stream, false).unwrap();
+
+        let trailers = vec![Header::new(b"checksum", b"1234")];
+
+        s.client
+            .send_trailers(&mut s.pipe.client, stream, &trailers)
+            .unwrap();
+        s.advance().ok();
+
+        // The stream is terminated by the trailers.
+        assert_eq!(
+            s.client
+                .send_trailers(&mut s.pipe.client, stream, &trailers),
+            Err(Error::FrameUnexpected)
+        );
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, stream, &body, true),
+            Err(Error::FrameUnexpected)
+        );
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        let ev_trailers = Event::Trailers {
+            list: trailers.clone(),
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        let body = s.send_body_server(stream, false).unwrap();
+
+        s.server
+            .send_trailers(&mut s.pipe.server, stream, &trailers)
+            .unwrap();
+        s.advance().ok();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
+
+        let ev_trailers = Event::Trailers { list: trailers };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Ok((stream, ev_trailers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send DATA after trailers on a request stream, bypassing the checks in
+    /// `send_trailers()`.
+    fn data_after_trailers() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let trailers = vec![Header::new(b"checksum", b"1234")];
+
+        s.client
+            .send_headers(&mut s.pipe.client, stream, &trailers, false)
+            .unwrap();
+        s.send_body_client(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        let ev_trailers = Event::Trailers {
+            list: trailers.clone(),
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+
+        // The same applies to responses on locally initiated streams.
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        s.server
+            .send_headers(&mut s.pipe.server, stream, &trailers, false)
+            .unwrap();
+        s.send_body_server(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
+
+        let ev_trailers = Event::Trailers { list: trailers };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, ev_trailers)));
+        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+    }
+
     #[test]
     /// Try to send DATA frames before HEADERS.
     fn body_response_before_headers() {
@@ -3623,6 +4605,61 @@ mod tests {
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
+    #[test]
+    /// Query how much of a request body can be sent under flow control.
+    fn send_body_capacity_and_readiness() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        // No body can be sent before the request headers.
+        assert_eq!(
+            s.client.send_body_capacity(&s.pipe.client, 0, 10),
+            Err(Error::FrameUnexpected)
+        );
+
+        let (stream, _) = s.send_request(false).unwrap();
+
+        let body = vec![42; 1000];
+
+        let stream_cap = s.pipe.client.stream_capacity(stream).unwrap();
+        assert!(stream_cap < body.len());
+
+        // The stream's capacity minus the DATA frame header.
+        let body_cap = s
+            .client
+            .send_body_capacity(&s.pipe.client, stream, body.len())
+            .unwrap();
+        assert_eq!(body_cap, stream_cap - 3);
+
+        assert_eq!(
+            s.client
+                .send_body_readiness(&mut s.pipe.client, stream, body.len()),
+            Ok(false)
+        );
+        assert_eq!(
+            s.client.send_body_readiness(&mut s.pipe.client, stream, 10),
+            Ok(true)
+        );
+
+        // The partial write matches the reported capacity.
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, stream, &body, true),
+            Ok(body_cap)
+        );
+
+        let rest = &body[body_cap..];
+
+        assert_eq!(
+            s.client
+                .send_body_capacity(&s.pipe.client, stream, rest.len()),
+            Ok(0)
+        );
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, stream, rest, true),
+            Err(Error::Done)
+        );
+    }
+
     #[test]
     /// Try to send DATA frames on wrong streams, ensure the API returns an
     /// error before anything hits the transport layer.
@@ -3763,63 +4800,206 @@ mod tests {
             has_body: true,
         };
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
-    }
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
+
+    #[test]
+    /// Send a sequence of MAX_PUSH_ID frames from the client that attempt to
+    /// reduce the limit.
+    fn max_push_id_from_client_limit_reduction() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 2 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::IdError));
+    }
+
+    #[test]
+    /// Send a MAX_PUSH_ID frame from the server, which is forbidden.
+    fn max_push_id_from_server() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.send_frame_server(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+    }
+
+    #[test]
+    /// Send a PUSH_PROMISE frame from the client, which is forbidden.
+    fn push_promise_from_client() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let header_block = s.client.encode_header_block(&req).unwrap();
+
+        s.send_frame_client(
+            frame::Frame::PushPromise {
+                push_id: 1,
+                header_block,
+            },
+            stream,
+            false,
+        )
+        .unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
+
+    #[test]
+    /// Push a response from the server, once allowed by the client.
+    fn server_push() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_max_push_id(0);
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        s.handshake().unwrap();
+
+        assert!(s.server.push_enabled_by_peer());
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let promised = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/style.css"),
+        ];
+
+        let push_stream = s
+            .server
+            .push(&mut s.pipe.server, stream, &promised)
+            .unwrap();
+        s.advance().ok();
+
+        // The client only allowed a single push.
+        assert!(!s.server.push_enabled_by_peer());
+        assert_eq!(
+            s.server.push(&mut s.pipe.server, stream, &promised),
+            Err(Error::IdError)
+        );
+
+        let ev_promise = Event::PushPromise {
+            push_id: 0,
+            list: promised,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_promise)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.client.push_id(push_stream), Some(0));
+
+        let resp = s.send_response(push_stream, false).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_client(), Ok((push_stream, ev_headers)));
 
-    #[test]
-    /// Send a sequence of MAX_PUSH_ID frames from the client that attempt to
-    /// reduce the limit.
-    fn max_push_id_from_client_limit_reduction() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        let body = s.send_body_server(push_stream, true).unwrap();
 
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 2 },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let mut recv_buf = vec![0; body.len()];
 
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 1 },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        assert_eq!(s.poll_client(), Ok((push_stream, Event::Data)));
+        assert_eq!(
+            s.recv_body_client(push_stream, &mut recv_buf),
+            Ok(body.len())
+        );
+        assert_eq!(recv_buf, body);
 
-        assert_eq!(s.poll_server(), Err(Error::IdError));
+        assert_eq!(s.poll_client(), Ok((push_stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Send a MAX_PUSH_ID frame from the server, which is forbidden.
-    fn max_push_id_from_server() {
+    /// Try to push a response from the server when the client didn't allow it.
+    fn server_push_not_allowed() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_server(
-            frame::Frame::MaxPushId { push_id: 1 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        assert!(!s.server.push_enabled_by_peer());
 
-        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(
+            s.server.push(&mut s.pipe.server, stream, &req),
+            Err(Error::IdError)
+        );
     }
 
     #[test]
-    /// Send a PUSH_PROMISE frame from the client, which is forbidden.
-    fn push_promise_from_client() {
+    /// Send a PUSH_PROMISE frame from the server with a push ID the client
+    /// didn't allow.
+    fn push_promise_beyond_max_push_id() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
         let (stream, req) = s.send_request(false).unwrap();
 
-        let header_block = s.client.encode_header_block(&req).unwrap();
+        let header_block = s.server.encode_header_block(&req).unwrap();
 
-        s.send_frame_client(
+        s.send_frame_server(
             frame::Frame::PushPromise {
-                push_id: 1,
+                push_id: 0,
                 header_block,
             },
             stream,
@@ -3827,13 +5007,7 @@ mod tests {
         )
         .unwrap();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: true,
-        };
-
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+        assert_eq!(s.poll_client(), Err(Error::IdError));
     }
 
     #[test]
@@ -3902,8 +5076,7 @@ mod tests {
 
         s.advance().ok();
 
-        // TODO: server push
-        assert_eq!(s.poll_server(), Ok((0, Event::GoAway)));
+        assert_eq!(s.poll_server(), Ok((100, Event::GoAway)));
     }
 
     #[test]
@@ -3976,6 +5149,109 @@ mod tests {
         assert_eq!(s.poll_client(), Err(Error::IdError));
     }
 
+    #[test]
+    /// Requests received after the server sent GOAWAY are rejected.
+    fn goaway_from_server_rejects_new_requests() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        assert_eq!(s.server.goaway(&mut s.pipe.server), Ok(4));
+
+        let (rejected, _) = s.send_request(true).unwrap();
+        assert_eq!(rejected, 4);
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.advance().ok();
+
+        assert_eq!(s.poll_client(), Ok((4, Event::GoAway)));
+        assert_eq!(
+            s.poll_client(),
+            Ok((rejected, Event::Reset(Error::RequestRejected.to_wire())))
+        );
+
+        // The request sent before GOAWAY can still be answered.
+        let resp = s.send_response(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+    }
+
+    #[test]
+    /// Pushes promised after the client sent GOAWAY are cancelled.
+    fn goaway_from_client_cancels_pushes() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(10);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_max_push_id(1);
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        s.server.push(&mut s.pipe.server, stream, &req).unwrap();
+        s.advance().ok();
+
+        let ev_promise = Event::PushPromise {
+            push_id: 0,
+            list: req.clone(),
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_promise)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        assert_eq!(s.client.goaway(&mut s.pipe.client), Ok(1));
+
+        let push_stream =
+            s.server.push(&mut s.pipe.server, stream, &req).unwrap();
+        s.advance().ok();
+
+        // Neither the promise nor the push stream are surfaced.
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.client.push_id(push_stream), Some(1));
+
+        assert_eq!(s.poll_server(), Ok((1, Event::GoAway)));
+        assert!(!s.server.push_enabled_by_peer());
+    }
+
     #[test]
     #[cfg(feature = "sfv")]
     fn parse_priority_field_value() {
@@ -4080,6 +5356,58 @@ mod tests {
         assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
+    #[test]
+    #[cfg(feature = "sfv")]
+    /// Apply a PRIORITY_UPDATE received after the response was started.
+    fn priority_update_reprioritize_response() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        s.send_response(stream, false).unwrap();
+
+        let transport = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(
+            transport.urgency,
+            PRIORITY_URGENCY_OFFSET + PRIORITY_URGENCY_DEFAULT
+        );
+        assert!(!transport.incremental);
+
+        s.client
+            .send_priority_update(
+                &mut s.pipe.client,
+                stream,
+                &Priority::new(1, true),
+            )
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(
+            s.server.apply_priority_update(&mut s.pipe.server, stream),
+            Ok(Priority::new(1, true))
+        );
+        assert_eq!(
+            s.server.apply_priority_update(&mut s.pipe.server, stream),
+            Err(Error::Done)
+        );
+
+        let transport = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(transport.urgency, PRIORITY_URGENCY_OFFSET + 1);
+        assert!(transport.incremental);
+    }
+
     #[test]
     /// Send a PRIORITY_UPDATE for request stream from the client.
     fn priority_update_single_stream_rearm() {
@@ -5940,31 +7268,24 @@ mod tests {
 
         assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
 
-        // Send more data, then HEADERS, then more data.
+        // Send more data, then trailers.
         let body = s.send_body_client(stream, false).unwrap();
 
         let trailers = vec![Header::new(b"hello", b"world")];
 
         s.client
-            .send_headers(&mut s.pipe.client, stream, &trailers, false)
+            .send_trailers(&mut s.pipe.client, stream, &trailers)
             .unwrap();
 
-        let ev_trailers = Event::Headers {
-            list: trailers,
-            has_body: true,
-        };
+        let ev_trailers = Event::Trailers { list: trailers };
 
         s.advance().ok();
 
-        s.send_body_client(stream, false).unwrap();
-
         assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
         assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
 
         assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
-
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
         let (stream, req) = s.send_request(false).unwrap();
 
@@ -6295,8 +7616,116 @@ mod tests {
         assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
+
+    #[test]
+    /// Sends HTTP Datagrams through a CONNECT-UDP tunnel, both in DATAGRAM
+    /// frames and in capsules.
+    fn connect_udp() {
+        let mut buf = [0; 65535];
+
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.enable_dgram(true, 3, 3);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+        h3_config.enable_extended_connect(true);
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.handshake(), Ok(()));
+
+        assert!(s.client.extended_connect_enabled_by_peer());
+        assert!(s.client.dgram_enabled_by_peer(&s.pipe.client));
+
+        let path = connect_udp_path("192.0.2.6", 443);
+
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"connect-udp"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", path.as_bytes()),
+            Header::new(b"capsule-protocol", b"?1"),
+        ];
+
+        let stream = s
+            .client
+            .send_request(&mut s.pipe.client, &req, false)
+            .unwrap();
+        s.advance().ok();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(
+            parse_connect_udp_path(path.as_bytes()),
+            Ok(("192.0.2.6".to_string(), 443))
+        );
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+
+        // HTTP Datagram in a DATAGRAM frame, with the quarter stream ID.
+        let payload = encode_udp_payload(b"hello");
+
+        s.client
+            .send_dgram(&mut s.pipe.client, stream / 4, &payload)
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok((7, stream / 4, 1)));
+        assert_eq!(
+            decode_context_id(&buf[1..7]),
+            Ok((UDP_PAYLOAD_CONTEXT_ID, &b"hello"[..]))
+        );
+
+        // HTTP Datagram in a capsule.
+        let capsule = Capsule::Datagram {
+            payload: encode_udp_payload(b"world"),
+        };
+
+        s.client
+            .send_capsule(&mut s.pipe.client, stream, &capsule)
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+
+        let len = s.recv_body_server(stream, &mut buf).unwrap();
+        assert_eq!(Capsule::from_bytes(&buf[..len]), Ok((capsule, len)));
+    }
 }
 
+pub use connect::connect_udp_path;
+pub use connect::decode_context_id;
+pub use connect::encode_udp_payload;
+pub use connect::parse_connect_udp_path;
+pub use connect::Capsule;
+pub use connect::UDP_PAYLOAD_CONTEXT_ID;
+
+mod connect;
 #[cfg(feature = "ffi")]
 mod ffi;
 mod frame;
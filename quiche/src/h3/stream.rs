@@ -154,8 +154,21 @@ pub struct Stream {
     /// Whether a `Data` event has been triggered for this stream.
     data_event_triggered: bool,
 
+    /// Whether the final (i.e. non-informational) header section has been
+    /// received.
+    headers_received: bool,
+
+    /// Whether a trailer section has been received.
+    trailers_received: bool,
+
+    /// Whether a trailer section has been sent.
+    trailers_sent: bool,
+
     /// The last `PRIORITY_UPDATE` frame encoded field value, if any.
     last_priority_update: Option<Vec<u8>>,
+
+    /// The push ID of a push stream, once known.
+    push_id: Option<u64>,
 }
 
 impl Stream {
@@ -195,10 +208,26 @@ impl Stream {
 
             data_event_triggered: false,
 
+            headers_received: false,
+            trailers_received: false,
+            trailers_sent: false,
+
             last_priority_update: None,
+
+            push_id: None,
         }
     }
 
+    /// Creates a new local push stream for the given push ID.
+    pub fn new_push(id: u64, push_id: u64) -> Stream {
+        let mut stream = Stream::new(id, true);
+
+        stream.ty = Some(Type::Push);
+        stream.push_id = Some(push_id);
+
+        stream
+    }
+
     pub fn ty(&self) -> Option<Type> {
         self.ty
     }
@@ -233,16 +262,21 @@ impl Stream {
     }
 
     /// Sets the push ID and transitions to the next state.
-    pub fn set_push_id(&mut self, _id: u64) -> Result<()> {
+    pub fn set_push_id(&mut self, id: u64) -> Result<()> {
         assert_eq!(self.state, State::PushId);
 
-        // TODO: implement push ID.
+        self.push_id = Some(id);
 
         self.state_transition(State::FrameType, 1, true)?;
 
         Ok(())
     }
 
+    /// Returns the push ID of a push stream, if known.
+    pub fn push_id(&self) -> Option<u64> {
+        self.push_id
+    }
+
     /// Sets the frame type and transitions to the next state.
     pub fn set_frame_type(&mut self, ty: u64) -> Result<()> {
         assert_eq!(self.state, State::FrameType);
@@ -283,6 +317,20 @@ impl Stream {
             },
 
             Some(Type::Request) => {
+                // A trailer section terminates the message, so neither DATA
+                // nor further HEADERS can follow it (RFC 9114 Section 4.1).
+                if self.trailers_received {
+                    match ty {
+                        frame::DATA_FRAME_TYPE_ID =>
+                            return Err(Error::FrameUnexpected),
+
+                        frame::HEADERS_FRAME_TYPE_ID =>
+                            return Err(Error::FrameUnexpected),
+
+                        _ => (),
+                    }
+                }
+
                 // Request stream starts uninitialized and only HEADERS
                 // is accepted. Other frames cause an error.
                 if !self.is_local {
@@ -440,6 +488,36 @@ impl Stream {
         self.local_initialized
     }
 
+    /// Marks the final header section as received.
+    pub fn mark_headers_received(&mut self) {
+        self.headers_received = true;
+    }
+
+    /// Whether the final header section has been received.
+    pub fn headers_received(&self) -> bool {
+        self.headers_received
+    }
+
+    /// Marks the trailer section as received.
+    pub fn mark_trailers_received(&mut self) {
+        self.trailers_received = true;
+    }
+
+    /// Whether the trailer section has been received.
+    pub fn trailers_received(&self) -> bool {
+        self.trailers_received
+    }
+
+    /// Marks the trailer section as sent.
+    pub fn mark_trailers_sent(&mut self) {
+        self.trailers_sent = true;
+    }
+
+    /// Whether the trailer section has been sent.
+    pub fn trailers_sent(&self) -> bool {
+        self.trailers_sent
+    }
+
     /// Tries to fill the state buffer by reading data from the given cursor.
     ///
     /// This is intended to replace `try_fill_buffer()` in tests, in order to
@@ -1060,6 +1138,7 @@ mod tests {
 
         stream.set_push_id(push_id).unwrap();
         assert_eq!(stream.state, State::FrameType);
+        assert_eq!(stream.push_id(), Some(1));
 
         // Parse the HEADERS frame type.
         stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
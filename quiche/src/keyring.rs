@@ -0,0 +1,498 @@
+// Copyright (C) 2023, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use ring::aead;
+
+use crate::Error;
+use crate::Result;
+
+/// The length of the name identifying a key.
+pub(crate) const KEY_NAME_LEN: usize = 16;
+
+const TICKET_KEY_LEN: usize = 16;
+
+const TOKEN_KEY_LEN: usize = 16;
+
+const TOKEN_NONCE_LEN: usize = 12;
+
+// The maximum number of keys kept in a ring, whatever the overlap window.
+const MAX_KEYS: usize = 8;
+
+/// A key protecting session tickets.
+///
+/// Keys are made of 48 bytes of key material, as accepted by
+/// [`set_ticket_key()`]: a 16 bytes name identifying the key, followed by
+/// the key itself. Tickets are only accepted by the servers that know the key
+/// they were encrypted with.
+///
+/// [`set_ticket_key()`]: struct.Config.html#method.set_ticket_key
+#[derive(Clone, Copy)]
+pub struct TicketKey {
+    pub(crate) name: [u8; KEY_NAME_LEN],
+
+    pub(crate) aes_key: [u8; TICKET_KEY_LEN],
+
+    pub(crate) hmac_key: [u8; TICKET_KEY_LEN],
+}
+
+impl TicketKey {
+    /// Creates a key from its key material.
+    ///
+    /// [`TlsFail`] is returned if `key` is not 48 bytes long.
+    ///
+    /// [`TlsFail`]: enum.Error.html#variant.TlsFail
+    pub fn new(key: &[u8]) -> Result<TicketKey> {
+        if key.len() != KEY_NAME_LEN + 2 * TICKET_KEY_LEN {
+            return Err(Error::TlsFail);
+        }
+
+        let (name, rest) = key.split_at(KEY_NAME_LEN);
+        let (aes_key, hmac_key) = rest.split_at(TICKET_KEY_LEN);
+
+        let mut key = TicketKey {
+            name: [0; KEY_NAME_LEN],
+            aes_key: [0; TICKET_KEY_LEN],
+            hmac_key: [0; TICKET_KEY_LEN],
+        };
+
+        key.name.copy_from_slice(name);
+        key.aes_key.copy_from_slice(aes_key);
+        key.hmac_key.copy_from_slice(hmac_key);
+
+        Ok(key)
+    }
+
+    /// Returns the name identifying the key.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+}
+
+/// The session ticket keys configured by the application, shared by the
+/// connections of a config.
+pub(crate) struct TicketKeys {
+    // The encryption key first, followed by the keys that are only used to
+    // decrypt tickets, from the most to the least recent.
+    keys: Mutex<VecDeque<TicketKey>>,
+}
+
+impl TicketKeys {
+    /// Creates a set whose first key encrypts new tickets, while all of them
+    /// decrypt tickets.
+    pub fn new(keys: &[TicketKey]) -> Result<TicketKeys> {
+        if keys.is_empty() || keys.len() > MAX_KEYS {
+            return Err(Error::TlsFail);
+        }
+
+        Ok(TicketKeys {
+            keys: Mutex::new(keys.iter().copied().collect()),
+        })
+    }
+
+    /// Makes `key` the encryption key, while the previous ones are kept to
+    /// decrypt tickets, up to the maximum number of keys.
+    pub fn rotate(&self, key: TicketKey) {
+        if let Ok(mut keys) = self.keys.lock() {
+            keys.retain(|k| k.name != key.name);
+
+            keys.push_front(key);
+            keys.truncate(MAX_KEYS);
+        }
+    }
+
+    /// Returns the key to encrypt a new session ticket with.
+    pub fn encryption_key(&self) -> Option<TicketKey> {
+        self.keys.lock().ok()?.front().copied()
+    }
+
+    /// Returns the key to decrypt a session ticket encrypted with the key
+    /// `name`, along with whether the ticket should be renewed because that
+    /// key is not the encryption key.
+    pub fn decryption_key(&self, name: &[u8]) -> Option<(TicketKey, bool)> {
+        let keys = self.keys.lock().ok()?;
+
+        let (i, key) = keys.iter().enumerate().find(|(_, k)| k.name == name)?;
+
+        Some((*key, i > 0))
+    }
+}
+
+struct Key {
+    ticket: TicketKey,
+
+    token_key: [u8; TOKEN_KEY_LEN],
+
+    created: Instant,
+
+    retired: Option<Instant>,
+}
+
+impl Key {
+    fn generate(now: Instant) -> Key {
+        let mut ticket = TicketKey {
+            name: [0; KEY_NAME_LEN],
+            aes_key: [0; TICKET_KEY_LEN],
+            hmac_key: [0; TICKET_KEY_LEN],
+        };
+
+        crate::rand::rand_bytes(&mut ticket.name);
+        crate::rand::rand_bytes(&mut ticket.aes_key);
+        crate::rand::rand_bytes(&mut ticket.hmac_key);
+
+        let mut token_key = [0; TOKEN_KEY_LEN];
+        crate::rand::rand_bytes(&mut token_key);
+
+        Key {
+            ticket,
+            token_key,
+            created: now,
+            retired: None,
+        }
+    }
+
+    fn token_key(&self) -> Result<aead::LessSafeKey> {
+        let key = aead::UnboundKey::new(&aead::AES_128_GCM, &self.token_key)
+            .map_err(|_| Error::CryptoFail)?;
+
+        Ok(aead::LessSafeKey::new(key))
+    }
+}
+
+/// A set of keys protecting address validation tokens and session tickets.
+///
+/// The ring holds a current key, which is used to encrypt new tokens and
+/// tickets, and is replaced by a freshly generated one every rotation
+/// interval. Replaced keys are still accepted to decrypt tokens and tickets
+/// during the overlap window, so that rotating keys doesn't invalidate all of
+/// the outstanding ones at once.
+///
+/// A ring is meant to be shared by all the connections of an endpoint, by
+/// configuring it on each [`Config`] with [`set_key_ring()`]. Session tickets
+/// are then protected with the keys of the ring, and applications can use
+/// [`seal_token()`] and [`open_token()`] to protect the tokens they pass to
+/// [`retry()`].
+///
+/// [`Config`]: struct.Config.html
+/// [`set_key_ring()`]: struct.Config.html#method.set_key_ring
+/// [`seal_token()`]: struct.KeyRing.html#method.seal_token
+/// [`open_token()`]: struct.KeyRing.html#method.open_token
+/// [`retry()`]: fn.retry.html
+///
+/// ## Examples:
+///
+/// ```
+/// # use std::time::Duration;
+/// let ring =
+///     quiche::KeyRing::new(Duration::from_secs(3600), Duration::from_secs(600));
+///
+/// let token = ring.seal_token(b"127.0.0.1")?;
+/// assert_eq!(ring.open_token(&token)?, b"127.0.0.1");
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub struct KeyRing {
+    rotation_interval: Duration,
+
+    overlap: Duration,
+
+    // The current key first, followed by the retired ones from the most to the
+    // least recent.
+    keys: Mutex<VecDeque<Key>>,
+}
+
+impl KeyRing {
+    /// Creates a ring whose current key is replaced every `rotation_interval`,
+    /// and whose replaced keys remain valid for decryption during `overlap`.
+    pub fn new(rotation_interval: Duration, overlap: Duration) -> KeyRing {
+        let mut keys = VecDeque::with_capacity(MAX_KEYS);
+        keys.push_front(Key::generate(Instant::now()));
+
+        KeyRing {
+            rotation_interval,
+            overlap,
+            keys: Mutex::new(keys),
+        }
+    }
+
+    /// Replaces the current key with a new one, ahead of the schedule.
+    ///
+    /// The replaced key remains valid for decryption during the overlap
+    /// window.
+    pub fn rotate(&self) {
+        if let Ok(mut keys) = self.keys.lock() {
+            self.rotate_keys(&mut keys, Instant::now());
+        }
+    }
+
+    /// Encrypts and authenticates `data` with the current key, and returns the
+    /// resulting token.
+    ///
+    /// The token can only be decrypted with [`open_token()`] while the key is
+    /// valid, and is 44 bytes larger than `data`.
+    ///
+    /// [`open_token()`]: struct.KeyRing.html#method.open_token
+    pub fn seal_token(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.seal_token_at(data, Instant::now())
+    }
+
+    /// Decrypts a token created by [`seal_token()`], and returns the data it
+    /// holds.
+    ///
+    /// [`CryptoFail`] is returned if the token was tampered with, or if it was
+    /// not created with a key of the ring that is still valid.
+    ///
+    /// [`seal_token()`]: struct.KeyRing.html#method.seal_token
+    /// [`CryptoFail`]: enum.Error.html#variant.CryptoFail
+    pub fn open_token(&self, token: &[u8]) -> Result<Vec<u8>> {
+        self.open_token_at(token, Instant::now())
+    }
+
+    fn seal_token_at(&self, data: &[u8], now: Instant) -> Result<Vec<u8>> {
+        let mut keys = self.keys.lock().map_err(|_| Error::CryptoFail)?;
+
+        let key = self.current_key(&mut keys, now);
+
+        let mut nonce = [0; TOKEN_NONCE_LEN];
+        crate::rand::rand_bytes(&mut nonce);
+
+        let mut token = Vec::with_capacity(
+            KEY_NAME_LEN +
+                TOKEN_NONCE_LEN +
+                data.len() +
+                aead::AES_128_GCM.tag_len(),
+        );
+
+        token.extend_from_slice(&key.ticket.name);
+        token.extend_from_slice(&nonce);
+
+        let mut payload = data.to_vec();
+
+        key.token_key()?
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(&key.ticket.name),
+                &mut payload,
+            )
+            .map_err(|_| Error::CryptoFail)?;
+
+        token.extend_from_slice(&payload);
+
+        Ok(token)
+    }
+
+    fn open_token_at(&self, token: &[u8], now: Instant) -> Result<Vec<u8>> {
+        if token.len() < KEY_NAME_LEN + TOKEN_NONCE_LEN {
+            return Err(Error::CryptoFail);
+        }
+
+        let (name, rest) = token.split_at(KEY_NAME_LEN);
+        let (nonce, payload) = rest.split_at(TOKEN_NONCE_LEN);
+
+        let mut keys = self.keys.lock().map_err(|_| Error::CryptoFail)?;
+
+        let key = self
+            .find_key(&mut keys, name, now)
+            .ok_or(Error::CryptoFail)?;
+
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| Error::CryptoFail)?;
+
+        let mut payload = payload.to_vec();
+
+        let data = key
+            .token_key()?
+            .open_in_place(nonce, aead::Aad::from(name), &mut payload)
+            .map_err(|_| Error::CryptoFail)?;
+
+        Ok(data.to_vec())
+    }
+
+    /// Returns the key to encrypt a new session ticket with.
+    pub(crate) fn ticket_encryption_key(&self) -> Option<TicketKey> {
+        let mut keys = self.keys.lock().ok()?;
+
+        Some(self.current_key(&mut keys, Instant::now()).ticket)
+    }
+
+    /// Returns the key to decrypt a session ticket encrypted with the key
+    /// `name`, along with whether the ticket should be renewed because that
+    /// key is not the current one anymore.
+    pub(crate) fn ticket_decryption_key(
+        &self, name: &[u8],
+    ) -> Option<(TicketKey, bool)> {
+        let mut keys = self.keys.lock().ok()?;
+
+        let key = self.find_key(&mut keys, name, Instant::now())?;
+
+        Some((key.ticket, key.retired.is_some()))
+    }
+
+    fn current_key<'a>(
+        &self, keys: &'a mut VecDeque<Key>, now: Instant,
+    ) -> &'a Key {
+        let expired = keys.front().map_or(true, |k| {
+            now.saturating_duration_since(k.created) >= self.rotation_interval
+        });
+
+        if expired {
+            self.rotate_keys(keys, now);
+        }
+
+        &keys[0]
+    }
+
+    fn find_key<'a>(
+        &self, keys: &'a mut VecDeque<Key>, name: &[u8], now: Instant,
+    ) -> Option<&'a Key> {
+        // Apply any scheduled rotation first, so that keys retired since are
+        // only accepted during the overlap window.
+        self.current_key(keys, now);
+
+        let overlap = self.overlap;
+        keys.retain(|k| {
+            k.retired
+                .map_or(true, |t| now.saturating_duration_since(t) < overlap)
+        });
+
+        keys.iter().find(|k| k.ticket.name == name)
+    }
+
+    fn rotate_keys(&self, keys: &mut VecDeque<Key>, now: Instant) {
+        if let Some(key) = keys.front_mut() {
+            key.retired = Some(now);
+        }
+
+        keys.push_front(Key::generate(now));
+        keys.truncate(MAX_KEYS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_open_token() {
+        let ring = KeyRing::new(Duration::from_secs(60), Duration::from_secs(10));
+
+        let token = ring.seal_token(b"hello").unwrap();
+        assert_eq!(token.len(), 5 + 44);
+        assert_eq!(ring.open_token(&token), Ok(b"hello".to_vec()));
+
+        // Tampered tokens are rejected.
+        let mut tampered = token.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(ring.open_token(&tampered), Err(Error::CryptoFail));
+
+        assert_eq!(ring.open_token(&token[..20]), Err(Error::CryptoFail));
+
+        // Tokens of another ring are rejected.
+        let other =
+            KeyRing::new(Duration::from_secs(60), Duration::from_secs(10));
+        assert_eq!(other.open_token(&token), Err(Error::CryptoFail));
+    }
+
+    #[test]
+    fn scheduled_rotation() {
+        let ring = KeyRing::new(Duration::from_secs(60), Duration::from_secs(10));
+
+        let now = Instant::now();
+
+        let old = ring.seal_token_at(b"old", now).unwrap();
+
+        // The key is rotated, and the old token remains valid during the
+        // overlap window.
+        let now = now + Duration::from_secs(60);
+
+        let new = ring.seal_token_at(b"new", now).unwrap();
+        assert_ne!(old[..KEY_NAME_LEN], new[..KEY_NAME_LEN]);
+
+        assert_eq!(ring.open_token_at(&old, now), Ok(b"old".to_vec()));
+        assert_eq!(ring.open_token_at(&new, now), Ok(b"new".to_vec()));
+
+        let now = now + Duration::from_secs(9);
+        assert_eq!(ring.open_token_at(&old, now), Ok(b"old".to_vec()));
+
+        // Past the overlap window, the old key is dropped.
+        let now = now + Duration::from_secs(1);
+        assert_eq!(ring.open_token_at(&old, now), Err(Error::CryptoFail));
+        assert_eq!(ring.open_token_at(&new, now), Ok(b"new".to_vec()));
+    }
+
+    #[test]
+    fn manual_rotation() {
+        let ring = KeyRing::new(Duration::from_secs(60), Duration::from_secs(10));
+
+        let current = ring.ticket_encryption_key().unwrap();
+        assert!(!ring.ticket_decryption_key(&current.name).unwrap().1);
+
+        ring.rotate();
+
+        let next = ring.ticket_encryption_key().unwrap();
+        assert_ne!(current.name, next.name);
+
+        // Tickets of the previous key are still accepted, but renewed.
+        assert!(ring.ticket_decryption_key(&current.name).unwrap().1);
+        assert!(!ring.ticket_decryption_key(&next.name).unwrap().1);
+
+        assert!(ring.ticket_decryption_key(&[0; KEY_NAME_LEN]).is_none());
+    }
+
+    #[test]
+    fn ticket_keys_rotation() {
+        let key = |b: u8| TicketKey::new(&[b; 48]).unwrap();
+
+        assert!(TicketKey::new(&[0; 32]).is_err());
+        assert!(TicketKeys::new(&[]).is_err());
+
+        let keys = TicketKeys::new(&[key(1), key(2)]).unwrap();
+        assert_eq!(keys.encryption_key().unwrap().name, [1; KEY_NAME_LEN]);
+
+        assert!(!keys.decryption_key(&[1; KEY_NAME_LEN]).unwrap().1);
+        assert!(keys.decryption_key(&[2; KEY_NAME_LEN]).unwrap().1);
+        assert!(keys.decryption_key(&[3; KEY_NAME_LEN]).is_none());
+
+        keys.rotate(key(3));
+        assert_eq!(keys.encryption_key().unwrap().name, [3; KEY_NAME_LEN]);
+
+        // Tickets of the previous keys are still accepted, but renewed.
+        assert!(!keys.decryption_key(&[3; KEY_NAME_LEN]).unwrap().1);
+        assert!(keys.decryption_key(&[1; KEY_NAME_LEN]).unwrap().1);
+        assert!(keys.decryption_key(&[2; KEY_NAME_LEN]).unwrap().1);
+
+        // The oldest keys are dropped past the maximum number of keys.
+        for b in 4..12 {
+            keys.rotate(key(b));
+        }
+
+        assert!(keys.decryption_key(&[3; KEY_NAME_LEN]).is_none());
+        assert!(keys.decryption_key(&[4; KEY_NAME_LEN]).unwrap().1);
+    }
+}
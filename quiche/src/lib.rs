@@ -249,8 +249,14 @@
 //! socket option on Linux), or custom methods (for example by using user-space
 //! timers).
 //!
+//! By default, packets sent back to back share the same time, up to the send
+//! quantum, so that they can be sent together (e.g. using GSO). Applications
+//! that offload pacing to the kernel can use [`set_pacing_granularity()`] to
+//! get finer grained times, down to a different time for each packet.
+//!
 //! [pace]: https://datatracker.ietf.org/doc/html/rfc9002#section-7.7
 //! [`SO_TXTIME`]: https://man7.org/linux/man-pages/man8/tc-etf.8.html
+//! [`set_pacing_granularity()`]: struct.Config.html#method.set_pacing_granularity
 //!
 //! ## Sending and receiving stream data
 //!
@@ -352,7 +358,14 @@
 //! Note that the CC algorithm should be configured before calling [`connect()`]
 //! or [`accept()`]. Otherwise the connection will use a default CC algorithm.
 //!
+//! Applications can also provide their own algorithm by implementing the
+//! [`CongestionControl`] trait, and passing it to
+//! [`set_custom_congestion_control()`].
+//!
 //! [`CongestionControlAlgorithm`]: enum.CongestionControlAlgorithm.html
+//! [`CongestionControl`]: trait.CongestionControl.html
+//! [`set_custom_congestion_control()`]:
+//! struct.Config.html#method.set_custom_congestion_control
 //!
 //! ## Feature flags
 //!
@@ -365,14 +378,36 @@
 //!   [boring] crate. It takes precedence over `boringssl-vendored` if both
 //!   features are enabled.
 //!
+//! * `rustls`: Use [rustls] for the TLS handshake and [ring] for packet
+//!   protection instead of BoringSSL, so that no C library is needed. It takes
+//!   precedence over the BoringSSL features. Sessions can't be exported with
+//!   [`session()`] with this backend.
+//!
 //! * `pkg-config-meta`: Generate pkg-config metadata file for libquiche.
 //!
 //! * `ffi`: Build and expose the FFI API.
 //!
 //! * `qlog`: Enable support for the [qlog] logging format.
 //!
+//! * `plugins` (default): Enable support for protocol plugins. Without it, the
+//!   plugin operations are not compiled in at all.
+//!
+//! * `multipath` (default): Enable support for the multipath extension.
+//!
+//! * `datagram` (default): Enable support for DATAGRAM frames.
+//!
+//! * `h3` (default): Build the HTTP/3 module. This requires `datagram`.
+//!
+//! * `tokio`: Build the [`tokio`] module, which drives connections with the
+//!   [tokio] runtime and exposes their streams as async I/O types.
+//!
 //! [feature flags]: https://doc.rust-lang.org/cargo/reference/manifest.html#the-features-section
 //! [boring]: https://crates.io/crates/boring
+//! [`tokio`]: tokio/index.html
+//! [tokio]: https://tokio.rs
+//! [rustls]: https://crates.io/crates/rustls
+//! [ring]: https://crates.io/crates/ring
+//! [`session()`]: struct.Connection.html#method.session
 //! [qlog]: https://datatracker.ietf.org/doc/html/draft-ietf-quic-qlog-main-schema
 
 #![allow(clippy::upper_case_acronyms)]
@@ -383,15 +418,21 @@
 extern crate log;
 
 use octets::OctetsMut;
+#[cfg(feature = "plugins")]
 use octets::OctetsMutPtr;
+#[cfg(feature = "plugins")]
 use pluginop::api::ToPluginizableConnection;
+#[cfg(feature = "plugins")]
 use pluginop::common::quic::FrameSendOrder;
+#[cfg(feature = "plugins")]
 use pluginop::common::quic::Registration;
+#[cfg(feature = "plugins")]
 use pluginop::common::PluginOp;
-use pluginop::pluginop_macro::pluginop;
-use pluginop::pluginop_macro::pluginop_param;
-use pluginop::pluginop_macro::pluginop_result_param;
+#[cfg(feature = "plugins")]
+use pluginop::common::PluginVal;
+#[cfg(feature = "plugins")]
 use pluginop::ParentReferencer;
+#[cfg(feature = "plugins")]
 use pluginop::PluginizableConnection;
 #[cfg(feature = "qlog")]
 use qlog::events::connectivity::TransportOwner;
@@ -413,6 +454,7 @@ use qlog::events::EventType;
 use qlog::events::RawInfo;
 
 use std::cmp;
+use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::time;
 
@@ -420,14 +462,25 @@ use std::net::SocketAddr;
 
 use std::str::FromStr;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 
+use std::sync::Arc;
+
 use smallvec::SmallVec;
 
 /// The current QUIC wire version.
 pub const PROTOCOL_VERSION: u32 = PROTOCOL_VERSION_V1;
 
+/// QUIC version 2, as defined in RFC 9369.
+///
+/// Applications can use this with [`Config::new()`] to start connections
+/// using version 2 instead of version 1.
+///
+/// [`Config::new()`]: struct.Config.html#method.new
+pub const PROTOCOL_VERSION_V2: u32 = 0x6b33_43cf;
+
 /// Supported QUIC versions.
 ///
 /// Note that the older ones might not be fully supported.
@@ -436,6 +489,15 @@ const PROTOCOL_VERSION_DRAFT27: u32 = 0xff00_001b;
 const PROTOCOL_VERSION_DRAFT28: u32 = 0xff00_001c;
 const PROTOCOL_VERSION_DRAFT29: u32 = 0xff00_001d;
 
+/// The supported QUIC versions, in order of preference.
+const SUPPORTED_VERSIONS: [u32; 5] = [
+    PROTOCOL_VERSION_V1,
+    PROTOCOL_VERSION_V2,
+    PROTOCOL_VERSION_DRAFT29,
+    PROTOCOL_VERSION_DRAFT28,
+    PROTOCOL_VERSION_DRAFT27,
+];
+
 /// The maximum length of a connection ID.
 pub const MAX_CONN_ID_LEN: usize = crate::packet::MAX_CID_LEN as usize;
 
@@ -470,6 +532,16 @@ const MAX_SEND_UDP_PAYLOAD_SIZE: usize = 1200;
 // The default length of DATAGRAM queues.
 const DEFAULT_MAX_DGRAM_QUEUE_LEN: usize = 0;
 
+// The default maximum number of events of each kind queued for the application.
+const DEFAULT_MAX_QUEUED_EVENTS: usize = 1024;
+
+// The default number of bytes read ahead from a stream's reader.
+const DEFAULT_STREAM_READ_AHEAD: usize = 64 * 1024;
+
+// The maximum number of datagrams written by a single call to `send_batch()`,
+// as supported by UDP GSO on Linux.
+const MAX_SEND_BATCH_SEGMENTS: usize = 64;
+
 // The DATAGRAM standard recommends either none or 65536 as maximum DATAGRAM
 // frames size. We enforce the recommendation for forward compatibility.
 const MAX_DGRAM_FRAME_SIZE: u64 = 65536;
@@ -496,6 +568,40 @@ const CONNECTION_WINDOW_FACTOR: f64 = 1.5;
 // validation as failed.
 const MAX_PROBING_TIMEOUTS: usize = 3;
 
+// How many consecutive junk datagrams a peer address can send before it is
+// throttled, with the `Throttle` junk datagram policy.
+const JUNK_THROTTLE_THRESHOLD: usize = 8;
+
+// How long datagrams from a throttled peer address are dropped.
+const JUNK_THROTTLE_DURATION: time::Duration = time::Duration::from_secs(1);
+
+// The maximum number of peer addresses tracked for throttling.
+const MAX_JUNK_SENDERS: usize = 16;
+
+// The maximum number of stream data ranges queued on a path by the redundant
+// path scheduler. Redundant copies are best effort, so the oldest ones are
+// dropped beyond this.
+const MAX_REDUNDANT_RANGES: usize = 256;
+
+// The transport parameter used for compatible version negotiation, as defined
+// by RFC 9368.
+const VERSION_INFORMATION_PARAM_ID: u64 = 0x0011;
+
+// The transport parameter used to advertise the supported plugins.
+const PLUGIN_SUPPORT_PARAM_ID: u64 = 0x706c_7567;
+
+// The transport parameter used to negotiate the multipath extension, as defined
+// by draft-ietf-quic-multipath-05.
+const ENABLE_MULTIPATH_PARAM_ID: u64 = 0x0f73_9bbc_1b66_6d05;
+
+// The transport parameter used to negotiate the ACK frequency extension, as
+// defined by draft-ietf-quic-ack-frequency.
+const MIN_ACK_DELAY_PARAM_ID: u64 = 0xff04_de1b;
+
+// The transport parameter used to advertise that packets with the QUIC bit
+// cleared can be received, as defined by RFC 9287.
+const GREASE_QUIC_BIT_PARAM_ID: u64 = 0x2ab2;
+
 /// A specialized [`Result`] type for quiche operations.
 ///
 /// This type is used throughout quiche's public API for any operation that
@@ -572,6 +678,21 @@ pub enum Error {
     /// Not enough available identifiers.
     OutOfIdentifiers,
 
+    /// A key update violated the key update rules, e.g. a new update was
+    /// initiated before the previous one was confirmed.
+    KeyUpdate,
+
+    /// Version negotiation failed, e.g. the version information advertised by
+    /// the peer does not match the negotiated version.
+    VersionNegotiation,
+
+    /// The number of packets protected with the same keys, or failing
+    /// authentication, reached the limits of the AEAD in use.
+    AeadLimitReached,
+
+    /// The handshake didn't complete within the configured handshake timeout.
+    HandshakeTimeout,
+
     /// Available for the plugins to suspend the sending process.
     SuspendSendingProcess,
 }
@@ -586,6 +707,10 @@ impl Error {
             Error::FlowControl => 0x3,
             Error::StreamLimit => 0x4,
             Error::FinalSize => 0x6,
+            Error::IdLimit => 0x9,
+            Error::KeyUpdate => 0xe,
+            Error::AeadLimitReached => 0xf,
+            Error::VersionNegotiation => 0x11,
             _ => 0xa,
         }
     }
@@ -611,6 +736,10 @@ impl Error {
             Error::StreamReset { .. } => -16,
             Error::IdLimit => -17,
             Error::OutOfIdentifiers => -18,
+            Error::KeyUpdate => -19,
+            Error::VersionNegotiation => -20,
+            Error::AeadLimitReached => -21,
+            Error::HandshakeTimeout => -22,
             Error::SuspendSendingProcess => -1000,
         }
     }
@@ -659,6 +788,224 @@ pub struct SendInfo {
     ///
     /// [Pacing]: index.html#pacing
     pub at: time::Instant,
+
+    /// The ECN codepoint the packet should be marked with.
+    ///
+    /// See [`enable_ecn()`] for more details.
+    ///
+    /// [`enable_ecn()`]: struct.Config.html#method.enable_ecn
+    pub ecn: EcnCodepoint,
+}
+
+/// A buffer of UDP datagrams received from the same peer, to be processed by
+/// [`recv_batch()`].
+///
+/// This is typically filled by a single read from a socket with Generic
+/// Receive Offload (GRO) enabled, where consecutive datagrams are coalesced
+/// in the same buffer, and all of them but the last one have the same size.
+///
+/// [`recv_batch()`]: struct.Connection.html#method.recv_batch
+#[derive(Debug)]
+pub struct RecvSegment<'a> {
+    /// The received datagrams.
+    pub buf: &'a mut [u8],
+
+    /// The size of each datagram in `buf`, except for the last one which can
+    /// be shorter. When `0`, `buf` holds a single datagram.
+    pub segment_size: usize,
+
+    /// Ancillary information about the datagrams.
+    pub info: RecvInfo,
+}
+
+/// Limits on the work performed by a single call to [`recv_with_budget()`].
+///
+/// A limit of `None` means that the corresponding resource is not limited.
+///
+/// [`recv_with_budget()`]: struct.Connection.html#method.recv_with_budget
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecvBudget {
+    /// The maximum number of coalesced packets processed.
+    pub max_packets: Option<usize>,
+
+    /// The maximum number of frames processed. As packets are always processed
+    /// as a whole, this limit can be exceeded by the frames of the last
+    /// processed packet.
+    pub max_frames: Option<usize>,
+}
+
+/// A datagram partially processed by [`recv_with_budget()`].
+///
+/// It is returned when the budget is exhausted before the end of the datagram,
+/// and must be passed back along with the rest of the datagram to resume its
+/// processing. It can only be used once.
+///
+/// [`recv_with_budget()`]: struct.Connection.html#method.recv_with_budget
+#[derive(Debug, PartialEq, Eq)]
+pub struct RecvCursor {
+    /// The information of the datagram.
+    info: RecvInfo,
+
+    /// The number of bytes of the datagram left to process.
+    left: usize,
+}
+
+/// How received datagrams that don't contain any valid QUIC packet are
+/// handled.
+///
+/// Such junk datagrams only contain padding or garbage, and are commonly
+/// caused by middleboxes, or by attackers trying to use the endpoint as a
+/// reflector.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum JunkDatagramPolicy {
+    /// Junk datagrams are dropped.
+    Ignore   = 0,
+    /// Junk datagrams are dropped, and counted in [`Stats`] (default).
+    ///
+    /// [`Stats`]: struct.Stats.html
+    Count    = 1,
+    /// Like `Count`, and once a peer address sent too many consecutive junk
+    /// datagrams, all datagrams from that address are dropped for a while
+    /// without being processed.
+    ///
+    /// Addresses of validated paths are never throttled, as junk datagrams
+    /// can be spoofed by an off-path attacker to cut the peer off.
+    Throttle = 2,
+}
+
+/// How Connection IDs provided by the peer in excess of the advertised
+/// `active_connection_id_limit` transport parameter are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionIdLimitPolicy {
+    /// The connection is closed with a CONNECTION_ID_LIMIT_ERROR (default).
+    Close,
+    /// The extra Connection IDs are retired right away, and counted in
+    /// [`Stats`].
+    ///
+    /// [`Stats`]: struct.Stats.html
+    Retire,
+}
+
+/// How 1-RTT packets are padded to hide their actual length.
+///
+/// 1-RTT packets only carry a short header, but their length can still be
+/// used by on-path observers to infer what is being sent, or to link packets
+/// sent over different paths with different connection IDs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShortPacketPadding {
+    /// 1-RTT packets are not padded beyond what the protocol requires
+    /// (default).
+    None,
+    /// 1-RTT packets are padded to at least the given length in bytes.
+    Min(usize),
+    /// The length of 1-RTT packets is rounded up to a multiple of the given
+    /// number of bytes.
+    Bucket(usize),
+}
+
+impl ShortPacketPadding {
+    /// Returns the padded length of a packet of the given length.
+    fn padded_len(self, len: usize) -> usize {
+        match self {
+            ShortPacketPadding::None => len,
+
+            ShortPacketPadding::Min(min) => cmp::max(len, min),
+
+            ShortPacketPadding::Bucket(0) => len,
+
+            ShortPacketPadding::Bucket(bucket) =>
+                (len + bucket - 1) / bucket * bucket,
+        }
+    }
+}
+
+/// When 1-RTT keys are rotated automatically.
+///
+/// Endpoints need to update their keys before reaching the confidentiality
+/// limit of the AEAD in use, but deployments can choose to rotate them much
+/// more often to limit how much data a single key protects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyUpdateInterval {
+    /// Keys are only updated when requested by the application or the peer
+    /// (default).
+    None,
+    /// Keys are updated once the given number of packets were sent with
+    /// them.
+    Packets(u64),
+    /// Keys are updated once the given number of bytes were sent with them.
+    Bytes(u64),
+}
+
+/// A set of settings tuned for a common deployment.
+///
+/// See [`Config::apply_profile()`].
+///
+/// [`Config::apply_profile()`]: struct.Config.html#method.apply_profile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigProfile {
+    /// Interactive traffic, e.g. requests and responses of web pages or RPCs,
+    /// where the latency of small transfers matters most.
+    LowLatency,
+
+    /// Large transfers, e.g. file downloads or video, where throughput
+    /// matters most.
+    Bulk,
+
+    /// Hosts serving many connections, or embedded devices, where the memory
+    /// used by each connection matters most.
+    ConstrainedMemory,
+
+    /// Testing against other QUIC implementations, sticking to the standard
+    /// behaviors and disabling the extensions of this fork.
+    Interop,
+}
+
+/// A set of experiment toggles.
+///
+/// Each of the 64 bits enables an experiment arm, whose meaning is agreed on
+/// by the application and the plugins it loads. This allows flipping the
+/// behavior of a plugin without shipping a different binary for each arm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Experiments(u64);
+
+impl Experiments {
+    /// Creates a set of experiments from its bit representation.
+    pub fn from_bits(bits: u64) -> Experiments {
+        Experiments(bits)
+    }
+
+    /// Returns the bit representation of the set.
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Enables or disables the experiment `bit`.
+    ///
+    /// Bits beyond 63 are ignored.
+    pub fn set(&mut self, bit: u32, enabled: bool) {
+        let mask = 1u64.checked_shl(bit).unwrap_or(0);
+
+        if enabled {
+            self.0 |= mask;
+        } else {
+            self.0 &= !mask;
+        }
+    }
+
+    /// Returns whether the experiment `bit` is enabled.
+    pub fn is_set(self, bit: u32) -> bool {
+        self.0 & 1u64.checked_shl(bit).unwrap_or(0) != 0
+    }
+}
+
+/// Junk datagrams received from a peer address.
+struct JunkSender {
+    /// The number of consecutive junk datagrams received.
+    count: usize,
+
+    /// When datagrams from the address can be processed again, if throttled.
+    throttled_until: Option<time::Instant>,
 }
 
 /// Represents information carried by `CONNECTION_CLOSE` frames.
@@ -670,10 +1017,44 @@ pub struct ConnectionError {
     /// The error code carried by the `CONNECTION_CLOSE` frame.
     pub error_code: u64,
 
+    /// The type of the frame that triggered the error.
+    ///
+    /// This is only carried by transport `CONNECTION_CLOSE` frames, and is 0
+    /// when the frame type is unknown, or for application errors.
+    pub frame_type: u64,
+
     /// The reason carried by the `CONNECTION_CLOSE` frame.
     pub reason: Vec<u8>,
 }
 
+impl ConnectionError {
+    /// Returns whether the error is a `CRYPTO_ERROR`, i.e. the connection was
+    /// closed because of a TLS alert.
+    pub fn is_crypto(&self) -> bool {
+        self.tls_alert().is_some()
+    }
+
+    /// Returns the TLS alert that caused the connection to be closed, if any.
+    ///
+    /// The alert is carried by the `CRYPTO_ERROR` transport error codes, in
+    /// the range 0x0100-0x01ff.
+    pub fn tls_alert(&self) -> Option<u8> {
+        if self.is_app {
+            return None;
+        }
+
+        self.error_code
+            .checked_sub(tls::TLS_ALERT_ERROR)
+            .and_then(|alert| alert.try_into().ok())
+    }
+
+    /// Returns the description of the TLS alert that caused the connection to
+    /// be closed, e.g. `"handshake_failure"`, if any.
+    pub fn tls_alert_description(&self) -> Option<&'static str> {
+        self.tls_alert().and_then(tls::alert_description)
+    }
+}
+
 /// The side of the stream to be shut down.
 ///
 /// This should be used when calling [`stream_shutdown()`].
@@ -710,6 +1091,8 @@ pub struct Config {
 
     version: u32,
 
+    supported_versions: Vec<u32>,
+
     tls_ctx: tls::Context,
 
     application_protos: Vec<Vec<u8>>,
@@ -718,19 +1101,98 @@ pub struct Config {
 
     cc_algorithm: CongestionControlAlgorithm,
 
+    custom_cc: Option<Box<dyn CongestionControl>>,
+
+    cubic_beta: f64,
+
+    cubic_c: f64,
+
+    path_scheduler: PathSchedulerAlgorithm,
+
     hystart: bool,
 
+    handshake_priority: bool,
+
     pacing: bool,
 
+    pacing_granularity: Option<time::Duration>,
+
+    initial_rtt: Option<time::Duration>,
+
+    min_rtt_expiry: time::Duration,
+
+    keepalive_interval: Option<time::Duration>,
+
+    handshake_timeout: Option<time::Duration>,
+
+    pmtud: bool,
+
+    ecn: bool,
+
     dgram_recv_max_queue_len: usize,
     dgram_send_max_queue_len: usize,
+    dgram_send_low_watermark: usize,
+
+    max_queued_events: usize,
 
     max_send_udp_payload_size: usize,
 
+    anti_amplification_factor: usize,
+
     max_connection_window: u64,
     max_stream_window: u64,
 
+    max_stream_send_buffer: u64,
+
+    stream_read_ahead: usize,
+
+    buf_factory: Option<Arc<dyn BufFactory>>,
+
+    stream_dup_alarm: Option<u64>,
+
     disable_dcid_reuse: bool,
+
+    client_store: Option<Arc<dyn ClientStore>>,
+
+    key_ring: Option<Arc<KeyRing>>,
+
+    ticket_keys: Option<Arc<keyring::TicketKeys>>,
+
+    token_minter: Option<Arc<dyn TokenMinter>>,
+
+    cid_generator: Option<Arc<dyn ConnectionIdGenerator>>,
+
+    metrics: Option<Arc<dyn Metrics>>,
+
+    delivery_rate_observer: Option<Arc<dyn DeliveryRateObserver>>,
+
+    crypto_provider: Option<Arc<dyn CryptoProvider>>,
+
+    cert_verifier: Option<Arc<dyn CertVerifier>>,
+
+    keylog_callback: Option<Arc<tls::KeylogCallback>>,
+
+    experiments: Experiments,
+
+    error_on_late_packets: bool,
+
+    #[cfg(feature = "plugins")]
+    max_plugin_mutations: usize,
+
+    #[cfg(feature = "plugins")]
+    plugin_files: Vec<std::path::PathBuf>,
+
+    junk_datagram_policy: JunkDatagramPolicy,
+
+    cid_limit_policy: ConnectionIdLimitPolicy,
+
+    short_packet_padding: ShortPacketPadding,
+
+    deterministic_frame_order: bool,
+
+    key_update_interval: KeyUpdateInterval,
+
+    rate_limits: ratelimit::RateLimits,
 }
 
 // See https://quicwg.org/base-drafts/rfc9000.html#section-15
@@ -757,7 +1219,7 @@ impl Config {
     /// [`SslContext`].
     ///
     /// [`SslContext`]: https://docs.rs/boring/latest/boring/ssl/struct.SslContext.html
-    #[cfg(feature = "boringssl-boring-crate")]
+    #[cfg(all(feature = "boringssl-boring-crate", not(feature = "rustls")))]
     #[cfg_attr(docsrs, doc(cfg(feature = "boringssl-boring-crate")))]
     pub fn with_boring_ssl_ctx(
         version: u32, tls_ctx: boring::ssl::SslContext,
@@ -773,22 +1235,90 @@ impl Config {
         Ok(Config {
             local_transport_params: TransportParams::default(),
             version,
+            supported_versions: SUPPORTED_VERSIONS.to_vec(),
             tls_ctx,
             application_protos: Vec::new(),
             grease: true,
             cc_algorithm: CongestionControlAlgorithm::CUBIC,
+            custom_cc: None,
+            cubic_beta: recovery::cubic::BETA_CUBIC,
+            cubic_c: recovery::cubic::C,
+            path_scheduler: PathSchedulerAlgorithm::LowestRtt,
             hystart: true,
+            handshake_priority: true,
             pacing: true,
+            pacing_granularity: None,
+            initial_rtt: None,
+            min_rtt_expiry: recovery::RTT_WINDOW,
+            keepalive_interval: None,
+            handshake_timeout: None,
+            pmtud: false,
+            ecn: false,
 
             dgram_recv_max_queue_len: DEFAULT_MAX_DGRAM_QUEUE_LEN,
             dgram_send_max_queue_len: DEFAULT_MAX_DGRAM_QUEUE_LEN,
+            dgram_send_low_watermark: 0,
+
+            max_queued_events: DEFAULT_MAX_QUEUED_EVENTS,
 
             max_send_udp_payload_size: MAX_SEND_UDP_PAYLOAD_SIZE,
 
+            anti_amplification_factor: MAX_AMPLIFICATION_FACTOR,
+
             max_connection_window: MAX_CONNECTION_WINDOW,
             max_stream_window: stream::MAX_STREAM_WINDOW,
 
+            max_stream_send_buffer: std::u64::MAX,
+
+            stream_read_ahead: DEFAULT_STREAM_READ_AHEAD,
+
+            buf_factory: None,
+
+            stream_dup_alarm: None,
+
             disable_dcid_reuse: false,
+
+            client_store: None,
+
+            key_ring: None,
+
+            ticket_keys: None,
+
+            token_minter: None,
+
+            cid_generator: None,
+
+            metrics: None,
+
+            delivery_rate_observer: None,
+
+            crypto_provider: None,
+
+            cert_verifier: None,
+
+            keylog_callback: None,
+
+            experiments: Experiments::default(),
+
+            error_on_late_packets: false,
+
+            #[cfg(feature = "plugins")]
+            max_plugin_mutations: plugin::DEFAULT_MAX_PLUGIN_MUTATIONS,
+
+            #[cfg(feature = "plugins")]
+            plugin_files: Vec::new(),
+
+            junk_datagram_policy: JunkDatagramPolicy::Count,
+
+            cid_limit_policy: ConnectionIdLimitPolicy::Close,
+
+            short_packet_padding: ShortPacketPadding::None,
+
+            deterministic_frame_order: false,
+
+            key_update_interval: KeyUpdateInterval::None,
+
+            rate_limits: ratelimit::RateLimits::default(),
         })
     }
 
@@ -797,6 +1327,14 @@ impl Config {
     /// The content of `file` is parsed as a PEM-encoded leaf certificate,
     /// followed by optional intermediate certificates.
     ///
+    /// Client connections present the certificate to servers that request
+    /// one, as done when [`verify_peer()`] or [`verify_peer_required()`] is
+    /// enabled on the server.
+    ///
+    /// [`verify_peer()`]: struct.Config.html#method.verify_peer
+    /// [`verify_peer_required()`]:
+    /// struct.Config.html#method.verify_peer_required
+    ///
     /// ## Examples:
     ///
     /// ```no_run
@@ -865,6 +1403,100 @@ impl Config {
         self.tls_ctx.set_verify(verify);
     }
 
+    /// Configures whether server connections require clients to present a
+    /// certificate.
+    ///
+    /// When enabled, the client's certificate is verified against the trusted
+    /// CA certificates, and the handshake fails if the client doesn't present
+    /// one. When disabled, the client's certificate is still verified if
+    /// presented.
+    ///
+    /// This overrides the value set with [`verify_peer()`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`verify_peer()`]: struct.Config.html#method.verify_peer
+    pub fn verify_peer_required(&mut self, required: bool) {
+        self.tls_ctx.set_verify_required(required);
+    }
+
+    /// Sets the verifier that vets the certificate chain of peers.
+    ///
+    /// Depending on its [`mode()`], the verifier is called either once the
+    /// handshake is completed, on top of the verification performed by the TLS
+    /// library, or during the handshake instead of verifying the chain against
+    /// the trusted CA certificates. The latter also enables peer verification,
+    /// as with [`verify_peer()`].
+    ///
+    /// When the verifier rejects the chain, the handshake fails and the error
+    /// it returned is sent to the peer as the TLS alert.
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`mode()`]: trait.CertVerifier.html#method.mode
+    /// [`verify_peer()`]: struct.Config.html#method.verify_peer
+    pub fn set_cert_verifier(&mut self, v: Option<Arc<dyn CertVerifier>>) {
+        self.tls_ctx.set_cert_verifier(v.clone());
+
+        self.cert_verifier = v;
+    }
+
+    /// Sets the callback that verifies the certificate chain of peers during
+    /// the handshake.
+    ///
+    /// This is a shorthand for [`set_cert_verifier()`] with a verifier in the
+    /// [`Replace`] mode calling the given closure.
+    ///
+    /// [`set_cert_verifier()`]: struct.Config.html#method.set_cert_verifier
+    /// [`Replace`]: enum.CertVerifyMode.html#variant.Replace
+    pub fn set_verify_callback(
+        &mut self,
+        cb: Box<dyn FnMut(&[&[u8]]) -> std::result::Result<(), u64> + Send>,
+    ) {
+        self.set_cert_verifier(Some(Arc::new(tls::CallbackCertVerifier::new(
+            cb,
+        ))));
+    }
+
+    /// Sets the QUIC versions supported by the endpoint, in order of
+    /// preference.
+    ///
+    /// The versions are advertised in the `version_information` transport
+    /// parameter, as defined by RFC 9368. When receiving a Version Negotiation
+    /// packet, a client switches to the first of them also listed by the
+    /// server. A server rejects Initial packets of any other version with
+    /// [`UnknownVersion`].
+    ///
+    /// Clients still send their first Initial packet with the version the
+    /// config was created with. When the client also supports a compatible
+    /// version that comes first in the server's list, such as QUIC v2 for a
+    /// QUIC v1 connection, the server upgrades the connection to it without
+    /// an additional round-trip. 0-RTT packets of the original version are
+    /// not accepted after such an upgrade.
+    ///
+    /// Servers should send Version Negotiation packets listing the same
+    /// versions with [`negotiate_version_with_config()`].
+    ///
+    /// If the list is empty or contains a version that is not supported,
+    /// [`UnknownVersion`] is returned.
+    ///
+    /// The default value is all the versions supported by quiche, starting
+    /// with the most recent one.
+    ///
+    /// [`UnknownVersion`]: enum.Error.html#variant.UnknownVersion
+    /// [`negotiate_version_with_config()`]: fn.negotiate_version_with_config.html
+    pub fn set_supported_versions(&mut self, versions: &[u32]) -> Result<()> {
+        if versions.is_empty() ||
+            !versions.iter().all(|&v| version_is_supported(v))
+        {
+            return Err(Error::UnknownVersion);
+        }
+
+        self.supported_versions = versions.to_vec();
+
+        Ok(())
+    }
+
     /// Configures whether to send GREASE values.
     ///
     /// The default value is `true`.
@@ -884,6 +1516,24 @@ impl Config {
         self.tls_ctx.enable_keylog();
     }
 
+    /// Sets the callback that receives the cryptographic secrets of
+    /// connections, in the [keylog] format.
+    ///
+    /// The callback is called with each line of the keylog, without its
+    /// trailing newline, for all the connections created with this config.
+    /// This enables logging of secrets, as with [`log_keys()`], and is used
+    /// along with the writer set with [`set_keylog()`] on the connection, if
+    /// any.
+    ///
+    /// [keylog]: https://developer.mozilla.org/en-US/docs/Mozilla/Projects/NSS/Key_Log_Format
+    /// [`log_keys()`]: struct.Config.html#method.log_keys
+    /// [`set_keylog()`]: struct.Connection.html#method.set_keylog
+    pub fn set_keylog_callback(&mut self, cb: Box<dyn FnMut(&str) + Send>) {
+        self.tls_ctx.enable_keylog();
+
+        self.keylog_callback = Some(Arc::new(std::sync::Mutex::new(cb)));
+    }
+
     /// Configures the session ticket key material.
     ///
     /// On the server this key will be used to encrypt and decrypt session
@@ -898,7 +1548,56 @@ impl Config {
         self.tls_ctx.set_ticket_key(key)
     }
 
+    /// Configures the keys protecting session tickets on the server.
+    ///
+    /// The first key is used to encrypt new session tickets, while tickets
+    /// encrypted with any of the keys are accepted, so that servers sharing
+    /// the keys keep resuming sessions while the encryption key is rolled
+    /// over. Tickets encrypted with another key than the first one are
+    /// renewed. At most 8 keys can be set.
+    ///
+    /// This takes precedence over [`set_ticket_key()`], while a key ring set
+    /// with [`set_key_ring()`] takes precedence over these keys.
+    ///
+    /// [`set_ticket_key()`]: struct.Config.html#method.set_ticket_key
+    /// [`set_key_ring()`]: struct.Config.html#method.set_key_ring
+    pub fn set_ticket_keys(&mut self, keys: &[TicketKey]) -> Result<()> {
+        let keys = Arc::new(keyring::TicketKeys::new(keys)?);
+
+        self.tls_ctx.set_ticket_keys(keys.clone());
+
+        self.ticket_keys = Some(keys);
+
+        Ok(())
+    }
+
+    /// Makes `key` the key encrypting new session tickets, while the keys
+    /// previously set with [`set_ticket_keys()`] are still accepted to decrypt
+    /// tickets.
+    ///
+    /// Only the 8 most recent keys are kept. The rotation applies to the
+    /// connections already created with this config as well.
+    ///
+    /// [`InvalidState`] is returned if no keys were set with
+    /// [`set_ticket_keys()`].
+    ///
+    /// [`set_ticket_keys()`]: struct.Config.html#method.set_ticket_keys
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn rotate_ticket_key(&mut self, key: TicketKey) -> Result<()> {
+        let keys = self.ticket_keys.as_ref().ok_or(Error::InvalidState)?;
+
+        keys.rotate(key);
+
+        Ok(())
+    }
+
     /// Enables sending or receiving early data.
+    ///
+    /// Clients send early data in 0-RTT packets when resuming a session with
+    /// [`set_session()`]. If the server rejects it, the data is automatically
+    /// sent again in 1-RTT packets once the handshake completes.
+    ///
+    /// [`set_session()`]: struct.Connection.html#method.set_session
     pub fn enable_early_data(&mut self) {
         self.tls_ctx.set_early_data_enabled(true);
     }
@@ -959,32 +1658,198 @@ impl Config {
         self.set_application_protos(&protos_list)
     }
 
-    /// Sets the `max_idle_timeout` transport parameter, in milliseconds.
+    /// Configures the flow control, congestion control, pacing, ACK and path
+    /// MTU settings for the given deployment profile.
     ///
-    /// The default value is infinite, that is, no timeout is used.
-    pub fn set_max_idle_timeout(&mut self, v: u64) {
-        self.local_transport_params.max_idle_timeout = v;
-    }
-
-    /// Sets the `max_udp_payload_size transport` parameter.
+    /// This gives a consistent baseline, which can be further adjusted with
+    /// the individual setters called after this method. Settings not related
+    /// to the profile, such as the certificates and application protocols,
+    /// are left untouched.
     ///
-    /// The default value is `65527`.
-    pub fn set_max_recv_udp_payload_size(&mut self, v: usize) {
-        self.local_transport_params.max_udp_payload_size = v as u64;
-    }
-
-    /// Sets the maximum outgoing UDP payload size.
+    /// The profiles roughly use:
     ///
-    /// The default and minimum value is `1200`.
-    pub fn set_max_send_udp_payload_size(&mut self, v: usize) {
-        self.max_send_udp_payload_size = cmp::max(v, MAX_SEND_UDP_PAYLOAD_SIZE);
-    }
-
-    /// Sets the `initial_max_data` transport parameter.
+    /// * `LowLatency`: BBR, 10MB connection and 1MB stream windows, and a 10ms
+    ///   maximum ACK delay.
+    /// * `Bulk`: CUBIC, 24MB connection and 16MB stream windows, ACK frequency
+    ///   negotiation and path MTU discovery.
+    /// * `ConstrainedMemory`: CUBIC, 256KB connection and 64KB stream windows
+    ///   growing up to 1MB and 256KB, and bounded send buffers.
+    /// * `Interop`: CUBIC, 10MB connection and 1MB stream windows, and none of
+    ///   the extensions.
     ///
-    /// When set to a non-zero value quiche will only allow at most `v` bytes of
-    /// incoming stream data to be buffered for the whole connection (that is,
-    /// data that is not yet read by the application) and will allow more data
+    /// ## Examples:
+    ///
+    /// ```
+    /// let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// config.apply_profile(quiche::ConfigProfile::LowLatency);
+    /// config.set_application_protos(&[b"example-proto"])?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn apply_profile(&mut self, profile: ConfigProfile) {
+        match profile {
+            ConfigProfile::LowLatency => {
+                self.set_initial_max_data(10_000_000);
+                self.set_initial_max_stream_data_bidi_local(1_000_000);
+                self.set_initial_max_stream_data_bidi_remote(1_000_000);
+                self.set_initial_max_stream_data_uni(1_000_000);
+                self.set_initial_max_streams_bidi(100);
+                self.set_initial_max_streams_uni(100);
+                self.set_max_connection_window(MAX_CONNECTION_WINDOW);
+                self.set_max_stream_window(stream::MAX_STREAM_WINDOW);
+
+                self.set_cc_algorithm(CongestionControlAlgorithm::BBR);
+                self.enable_hystart(true);
+                self.enable_pacing(true);
+
+                // Acknowledge quickly so that losses are detected and
+                // recovered from sooner.
+                self.set_max_ack_delay(10);
+                self.enable_ack_frequency(false);
+
+                self.discover_pmtu(false);
+            },
+
+            ConfigProfile::Bulk => {
+                self.set_initial_max_data(MAX_CONNECTION_WINDOW);
+                self.set_initial_max_stream_data_bidi_local(
+                    stream::MAX_STREAM_WINDOW,
+                );
+                self.set_initial_max_stream_data_bidi_remote(
+                    stream::MAX_STREAM_WINDOW,
+                );
+                self.set_initial_max_stream_data_uni(stream::MAX_STREAM_WINDOW);
+                self.set_initial_max_streams_bidi(16);
+                self.set_initial_max_streams_uni(16);
+                self.set_max_connection_window(MAX_CONNECTION_WINDOW);
+                self.set_max_stream_window(stream::MAX_STREAM_WINDOW);
+
+                self.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+                self.enable_hystart(true);
+                self.enable_pacing(true);
+
+                // Fewer ACKs leave more room for data.
+                self.set_max_ack_delay(25);
+                self.enable_ack_frequency(true);
+
+                self.discover_pmtu(true);
+            },
+
+            ConfigProfile::ConstrainedMemory => {
+                self.set_initial_max_data(256 * 1024);
+                self.set_initial_max_stream_data_bidi_local(64 * 1024);
+                self.set_initial_max_stream_data_bidi_remote(64 * 1024);
+                self.set_initial_max_stream_data_uni(64 * 1024);
+                self.set_initial_max_streams_bidi(16);
+                self.set_initial_max_streams_uni(4);
+                self.set_max_connection_window(1024 * 1024);
+                self.set_max_stream_window(256 * 1024);
+                self.set_max_stream_send_buffer(256 * 1024);
+                self.set_stream_read_ahead(16 * 1024);
+
+                self.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+                self.enable_hystart(true);
+                self.enable_pacing(true);
+
+                self.set_max_ack_delay(25);
+                self.enable_ack_frequency(false);
+
+                self.discover_pmtu(false);
+            },
+
+            ConfigProfile::Interop => {
+                self.set_initial_max_data(10_000_000);
+                self.set_initial_max_stream_data_bidi_local(1_000_000);
+                self.set_initial_max_stream_data_bidi_remote(1_000_000);
+                self.set_initial_max_stream_data_uni(1_000_000);
+                self.set_initial_max_streams_bidi(100);
+                self.set_initial_max_streams_uni(100);
+                self.set_max_connection_window(MAX_CONNECTION_WINDOW);
+                self.set_max_stream_window(stream::MAX_STREAM_WINDOW);
+
+                self.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+                self.enable_hystart(true);
+                self.enable_pacing(true);
+
+                self.set_max_ack_delay(25);
+                self.enable_ack_frequency(false);
+
+                #[cfg(feature = "multipath")]
+                self.set_multipath(false);
+
+                self.discover_pmtu(false);
+            },
+        }
+    }
+
+    /// Sets the `max_idle_timeout` transport parameter, in milliseconds.
+    ///
+    /// The default value is infinite, that is, no timeout is used.
+    pub fn set_max_idle_timeout(&mut self, v: u64) {
+        self.local_transport_params.max_idle_timeout = v;
+    }
+
+    /// Enables sending keep-alive PING frames on quiet connections.
+    ///
+    /// Once the handshake is complete, a PING frame is sent whenever no
+    /// ack-eliciting packet was sent, and no packet was received, for the
+    /// given interval. The interval is capped to half of the negotiated idle
+    /// timeout, so that the connection doesn't time out while the
+    /// application has nothing to send.
+    ///
+    /// The default value is `None`, in which case no keep-alive is sent.
+    pub fn enable_keepalive(&mut self, interval: time::Duration) {
+        self.keepalive_interval = Some(interval);
+    }
+
+    /// Sets the maximum duration of the handshake.
+    ///
+    /// Connections whose handshake isn't complete once this duration has
+    /// elapsed since their creation are closed silently, regardless of the
+    /// idle timeout, e.g. when the peer's packets are blackholed or the peer
+    /// stalls. The [`send()`] and [`recv()`] methods then return
+    /// [`HandshakeTimeout`].
+    ///
+    /// The default value is `None`, in which case the handshake is only
+    /// limited by the idle timeout.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`HandshakeTimeout`]: enum.Error.html#variant.HandshakeTimeout
+    pub fn set_handshake_timeout(&mut self, v: time::Duration) {
+        self.handshake_timeout = Some(v);
+    }
+
+    /// Sets the `max_udp_payload_size transport` parameter.
+    ///
+    /// The default value is `65527`.
+    pub fn set_max_recv_udp_payload_size(&mut self, v: usize) {
+        self.local_transport_params.max_udp_payload_size = v as u64;
+    }
+
+    /// Sets the maximum outgoing UDP payload size.
+    ///
+    /// The default and minimum value is `1200`.
+    pub fn set_max_send_udp_payload_size(&mut self, v: usize) {
+        self.max_send_udp_payload_size = cmp::max(v, MAX_SEND_UDP_PAYLOAD_SIZE);
+    }
+
+    /// Sets the anti-amplification factor of servers.
+    ///
+    /// Until the client's address is validated, a server sends at most this
+    /// many times the amount of data received on a path. RFC 9000 mandates a
+    /// factor of 3, so other values should only be used in controlled
+    /// testbeds.
+    ///
+    /// The default value is `3`.
+    pub fn set_anti_amplification_factor(&mut self, v: usize) {
+        self.anti_amplification_factor = v;
+    }
+
+    /// Sets the `initial_max_data` transport parameter.
+    ///
+    /// When set to a non-zero value quiche will only allow at most `v` bytes of
+    /// incoming stream data to be buffered for the whole connection (that is,
+    /// data that is not yet read by the application) and will allow more data
     /// to be received as the buffer is consumed by the application.
     ///
     /// When set to zero, either explicitly or via the default, quiche will not
@@ -1118,6 +1983,88 @@ impl Config {
         self.local_transport_params.disable_active_migration = v;
     }
 
+    /// Configures whether to enable the multipath extension.
+    ///
+    /// When enabled, the `enable_multipath` transport parameter is advertised
+    /// as defined by draft-ietf-quic-multipath-05. If the peer also advertises
+    /// it, and both endpoints use non-zero length connection IDs, the
+    /// connection can send non-probing packets over several paths at the same
+    /// time, each path having its own packet number space. See
+    /// [`create_path()`] for more details.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`create_path()`]: struct.Connection.html#method.create_path
+    #[cfg(feature = "multipath")]
+    pub fn set_multipath(&mut self, v: bool) {
+        self.local_transport_params.enable_multipath = v;
+    }
+
+    /// Configures whether to enable the ACK frequency extension.
+    ///
+    /// When enabled, the `min_ack_delay` transport parameter is advertised as
+    /// defined by draft-ietf-quic-ack-frequency. If the peer also advertises
+    /// it, each endpoint can ask the other to send fewer ACKs with
+    /// ACK_FREQUENCY frames, and to acknowledge a packet right away with an
+    /// IMMEDIATE_ACK frame.
+    ///
+    /// The ACK rate requested from the peer follows the congestion window, so
+    /// that fewer ACKs are sent as it grows. A plugin implementing the
+    /// `ack_frequency` operation can choose another rate.
+    ///
+    /// The default value is `false`.
+    pub fn enable_ack_frequency(&mut self, v: bool) {
+        self.local_transport_params.min_ack_delay =
+            v.then_some(ack_freq::DEFAULT_MIN_ACK_DELAY);
+    }
+
+    /// Configures whether to grease the QUIC bit.
+    ///
+    /// When enabled, the `grease_quic_bit` transport parameter is advertised
+    /// as defined by RFC 9287. If the peer also advertises it, the QUIC bit of
+    /// the packets sent to it is randomly cleared, so that middleboxes don't
+    /// come to rely on its value. Initial packets always have it set.
+    ///
+    /// Packets with the QUIC bit cleared are accepted regardless of this
+    /// setting.
+    ///
+    /// The default value is `false`.
+    pub fn grease_quic_bit(&mut self, v: bool) {
+        self.local_transport_params.grease_quic_bit = v;
+    }
+
+    /// Sets the multipath path scheduler used by string.
+    ///
+    /// The default value is `lowest-rtt`. On error `Error::InvalidState`
+    /// will be returned.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_path_scheduler_name("round-robin")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_path_scheduler_name(&mut self, name: &str) -> Result<()> {
+        self.path_scheduler = PathSchedulerAlgorithm::from_str(name)?;
+
+        Ok(())
+    }
+
+    /// Sets the path scheduler used to select the path of each packet when
+    /// the multipath extension is in use.
+    ///
+    /// The scheduler of a given connection can also be replaced with a custom
+    /// one using [`Connection::set_path_scheduler()`].
+    ///
+    /// The default value is `PathSchedulerAlgorithm::LowestRtt`.
+    ///
+    /// [`Connection::set_path_scheduler()`]:
+    /// struct.Connection.html#method.set_path_scheduler
+    pub fn set_path_scheduler(&mut self, algo: PathSchedulerAlgorithm) {
+        self.path_scheduler = algo;
+    }
+
     /// Sets the congestion control algorithm used by string.
     ///
     /// The default value is `cubic`. On error `Error::CongestionControl`
@@ -1132,6 +2079,7 @@ impl Config {
     /// ```
     pub fn set_cc_algorithm_name(&mut self, name: &str) -> Result<()> {
         self.cc_algorithm = CongestionControlAlgorithm::from_str(name)?;
+        self.custom_cc = None;
 
         Ok(())
     }
@@ -1141,6 +2089,60 @@ impl Config {
     /// The default value is `CongestionControlAlgorithm::CUBIC`.
     pub fn set_cc_algorithm(&mut self, algo: CongestionControlAlgorithm) {
         self.cc_algorithm = algo;
+        self.custom_cc = None;
+    }
+
+    /// Sets a congestion control algorithm implemented by the application.
+    ///
+    /// Each path of each connection created with this configuration uses its
+    /// own copy of `cc`, obtained with [`CongestionControl::clone_box()`].
+    /// This replaces the algorithm set with [`set_cc_algorithm()`], until it
+    /// is called again.
+    ///
+    /// [`CongestionControl::clone_box()`]:
+    /// trait.CongestionControl.html#tymethod.clone_box
+    /// [`set_cc_algorithm()`]: struct.Config.html#method.set_cc_algorithm
+    pub fn set_custom_congestion_control(
+        &mut self, cc: Box<dyn CongestionControl>,
+    ) {
+        self.custom_cc = Some(cc);
+    }
+
+    /// Sets the multiplicative window decrease factor of CUBIC.
+    ///
+    /// On a congestion event, the congestion window is reduced to `v` times
+    /// its size. Larger values make the window shrink less on losses, which
+    /// suits links with a high bandwidth-delay product, at the expense of
+    /// fairness with other flows. The value must be greater than 0 and lower
+    /// than 1, otherwise `Error::CongestionControl` is returned.
+    ///
+    /// The default value is `0.7`.
+    pub fn set_cubic_beta(&mut self, v: f64) -> Result<()> {
+        if !(v > 0.0 && v < 1.0) {
+            return Err(Error::CongestionControl);
+        }
+
+        self.cubic_beta = v;
+
+        Ok(())
+    }
+
+    /// Sets the constant that determines how aggressively CUBIC grows the
+    /// congestion window.
+    ///
+    /// Larger values make the window grow back faster after a congestion
+    /// event. The value must be greater than 0, otherwise
+    /// `Error::CongestionControl` is returned.
+    ///
+    /// The default value is `0.4`.
+    pub fn set_cubic_c(&mut self, v: f64) -> Result<()> {
+        if !(v > 0.0 && v.is_finite()) {
+            return Err(Error::CongestionControl);
+        }
+
+        self.cubic_c = v;
+
+        Ok(())
     }
 
     /// Configures whether to enable HyStart++.
@@ -1150,6 +2152,18 @@ impl Config {
         self.hystart = v;
     }
 
+    /// Configures whether Initial and Handshake packets ignore the congestion
+    /// window until the handshake is completed.
+    ///
+    /// When enabled, data written early, such as 0-RTT data, cannot delay
+    /// the handshake by filling the congestion window. Handshake packets are
+    /// always sent before application data, regardless of this setting.
+    ///
+    /// The default value is `true`.
+    pub fn enable_handshake_priority(&mut self, v: bool) {
+        self.handshake_priority = v;
+    }
+
     /// Configures whether to enable pacing.
     ///
     /// The default value is `true`.
@@ -1157,12 +2171,87 @@ impl Config {
         self.pacing = v;
     }
 
+    /// Sets the maximum duration of a burst of packets released at the same
+    /// time by the pacer.
+    ///
+    /// Packets are given the release time reported in [`SendInfo::at`], which
+    /// the application can pass to the kernel, e.g. with `SO_TXTIME`. With a
+    /// granularity of zero, each packet is given its own release time.
+    ///
+    /// The default value is `None`, in which case bursts are only limited by
+    /// the send quantum.
+    ///
+    /// [`SendInfo::at`]: struct.SendInfo.html#structfield.at
+    pub fn set_pacing_granularity(&mut self, v: time::Duration) {
+        self.pacing_granularity = Some(v);
+    }
+
+    /// Sets the RTT estimate used on new paths until an RTT sample is taken.
+    ///
+    /// This can be set from the RTT previously measured to the same peer, so
+    /// that the probe timeout and the pacing rate of the first packets match
+    /// the actual network conditions.
+    ///
+    /// The default value is 333 milliseconds, in which case packets are not
+    /// paced until an RTT sample is taken.
+    pub fn set_initial_rtt(&mut self, v: time::Duration) {
+        self.initial_rtt = Some(v);
+    }
+
+    /// Sets how long an RTT sample is considered for the minimum RTT of a
+    /// path.
+    ///
+    /// Once the minimum RTT is older than this, it is replaced by more recent
+    /// samples, so that it reflects route changes on long-lived connections.
+    /// With BBR, ProbeRTT is also entered at least this often to refresh it.
+    ///
+    /// The default value is 300 seconds.
+    pub fn set_min_rtt_expiry(&mut self, v: time::Duration) {
+        self.min_rtt_expiry = v;
+    }
+
+    /// Configures whether to discover the path MTU of each path, as described
+    /// in RFC 8899.
+    ///
+    /// When enabled, packets are initially limited to the minimum QUIC packet
+    /// size, and padded probes are sent once a path is validated to find the
+    /// largest size it supports, up to the size configured with
+    /// [`set_max_send_udp_payload_size()`]. The discovered size is reported as
+    /// the `pmtu` of [`PathStats`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`set_max_send_udp_payload_size()`]:
+    ///     struct.Config.html#method.set_max_send_udp_payload_size
+    /// [`PathStats`]: struct.PathStats.html
+    pub fn discover_pmtu(&mut self, v: bool) {
+        self.pmtud = v;
+    }
+
+    /// Configures whether to mark outgoing packets as ECN-capable, as
+    /// described in RFC 9000 Section 13.4.
+    ///
+    /// When enabled, the codepoint each packet should be marked with is
+    /// returned in the `ecn` field of [`SendInfo`], and the application is
+    /// expected to set it on the packet when sending it. Marking stops if the
+    /// path or the peer turns out not to support ECN. The codepoint of
+    /// received packets is passed to [`recv_with_ecn()`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`recv_with_ecn()`]: struct.Connection.html#method.recv_with_ecn
+    pub fn enable_ecn(&mut self, v: bool) {
+        self.ecn = v;
+    }
+
     /// Configures whether to enable receiving DATAGRAM frames.
     ///
     /// When enabled, the `max_datagram_frame_size` transport parameter is set
     /// to 65536 as recommended by draft-ietf-quic-datagram-01.
     ///
     /// The default is `false`.
+    #[cfg(feature = "datagram")]
     pub fn enable_dgram(
         &mut self, enabled: bool, recv_queue_len: usize, send_queue_len: usize,
     ) {
@@ -1175,6 +2264,35 @@ impl Config {
         self.dgram_send_max_queue_len = send_queue_len;
     }
 
+    /// Sets the low watermark of the DATAGRAM send queue.
+    ///
+    /// After a DATAGRAM was rejected because the send queue was full, the
+    /// queue is reported as writable by [`dgram_send_queue_writable()`] once
+    /// it holds at most this number of DATAGRAMs.
+    ///
+    /// The default value is `0`, i.e. the queue needs to be drained entirely.
+    ///
+    /// [`dgram_send_queue_writable()`]:
+    /// struct.Connection.html#method.dgram_send_queue_writable
+    #[cfg(feature = "datagram")]
+    pub fn set_dgram_send_queue_low_watermark(&mut self, v: usize) {
+        self.dgram_send_low_watermark = v;
+    }
+
+    /// Sets the maximum number of events of each kind queued until they are
+    /// retrieved by the application, i.e. path events, key update events,
+    /// stream events and plugin timers.
+    ///
+    /// Once a queue is full, new events are dropped, and counted in the
+    /// `dropped_events` field of [`Stats`].
+    ///
+    /// The default value is `1024`.
+    ///
+    /// [`Stats`]: struct.Stats.html
+    pub fn set_max_queued_events(&mut self, v: usize) {
+        self.max_queued_events = v;
+    }
+
     /// Sets the maximum size of the connection window.
     ///
     /// The default value is MAX_CONNECTION_WINDOW (24MBytes).
@@ -1189,6 +2307,65 @@ impl Config {
         self.max_stream_window = v;
     }
 
+    /// Sets the maximum amount of data buffered for sending on each stream.
+    ///
+    /// This includes data that was already sent but was not acked by the peer
+    /// yet. Once a stream's buffer is full, [`stream_send()`] only writes part
+    /// of the data, or returns [`Done`], until the peer acks some of it.
+    ///
+    /// The default value is `u64::MAX` (no limit).
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn set_max_stream_send_buffer(&mut self, v: u64) {
+        self.max_stream_send_buffer = v;
+    }
+
+    /// Sets the maximum number of bytes read ahead from a stream's reader.
+    ///
+    /// Data of streams sent with [`stream_send_reader()`] is only read from
+    /// the reader when it can be sent, and at most this many bytes are
+    /// buffered in the stream but not yet sent.
+    ///
+    /// The default value is `65536`.
+    ///
+    /// [`stream_send_reader()`]: struct.Connection.html#method.stream_send_reader
+    pub fn set_stream_read_ahead(&mut self, v: usize) {
+        self.stream_read_ahead = v;
+    }
+
+    /// Sets the factory allocating the buffers that hold the data received
+    /// on streams.
+    ///
+    /// The data of each STREAM frame is copied into a buffer returned by the
+    /// factory, which can then be handed to the application without further
+    /// copies with [`stream_recv_buf()`].
+    ///
+    /// By default, buffers are allocated on the heap.
+    ///
+    /// [`stream_recv_buf()`]: struct.Connection.html#method.stream_recv_buf
+    pub fn set_buf_factory(&mut self, factory: Arc<dyn BufFactory>) {
+        self.buf_factory = Some(factory);
+    }
+
+    /// Sets the number of duplicate STREAM bytes received on a connection
+    /// above which an alarm is raised.
+    ///
+    /// Duplicate bytes are retransmissions of data that was already received,
+    /// so a high amount of them hints at lost ACKs or at a misbehaving peer.
+    /// The alarm is raised at most once per connection: it is reported to the
+    /// [`Metrics`] sink as the [`StreamDupAlarms`] counter, and can be checked
+    /// with [`stream_dup_alarm()`].
+    ///
+    /// The default value is `None`, in which case no alarm is raised.
+    ///
+    /// [`Metrics`]: trait.Metrics.html
+    /// [`StreamDupAlarms`]: enum.Counter.html#variant.StreamDupAlarms
+    /// [`stream_dup_alarm()`]: struct.Connection.html#method.stream_dup_alarm
+    pub fn set_stream_dup_alarm(&mut self, v: Option<u64>) {
+        self.stream_dup_alarm = v;
+    }
+
     /// Sets the initial stateless reset token.
     ///
     /// This value is only advertised by servers. Setting a stateless retry
@@ -1212,48 +2389,413 @@ impl Config {
     pub fn set_disable_dcid_reuse(&mut self, v: bool) {
         self.disable_dcid_reuse = v;
     }
-}
 
-/// A QUIC connection.
-pub struct Connection {
-    /// The pluginized connection.
-    pc: Option<ParentReferencer<PluginizableConnection<Self>>>,
+    /// Configures the list of plugins advertised to the peer.
+    ///
+    /// The list is sent as a transport parameter, so that both endpoints can
+    /// find out which pluginized behaviors are deployed at the other side. The
+    /// peer's list can be retrieved with [`peer_plugin_support()`]. Plugin
+    /// names must not be empty.
+    ///
+    /// The default value is an empty list, in which case the transport
+    /// parameter is not sent.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_plugin_support(&[b"ack-frequency", b"multipath"])?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`peer_plugin_support()`]: struct.Connection.html#method.peer_plugin_support
+    pub fn set_plugin_support(&mut self, plugins: &[&[u8]]) -> Result<()> {
+        if plugins.iter().any(|p| p.is_empty()) {
+            return Err(Error::InvalidState);
+        }
 
-    /// QUIC wire version used for the connection.
-    version: u32,
+        self.local_transport_params.plugin_support = if plugins.is_empty() {
+            None
+        } else {
+            Some(plugins.iter().map(|p| p.to_vec()).collect())
+        };
 
-    /// Connection Identifiers.
-    ids: cid::ConnectionIdentifiers,
+        Ok(())
+    }
 
-    /// Unique opaque ID for the connection that can be used for logging.
-    trace_id: String,
+    /// Adds a custom transport parameter to be sent to the peer.
+    ///
+    /// This lets extensions negotiate their own parameters during the
+    /// handshake. The value sent by the peer for a given identifier can be
+    /// retrieved with [`peer_custom_transport_param()`].
+    ///
+    /// An error is returned if the identifier is not a valid variable-length
+    /// integer, if it's used by one of the parameters handled by quiche, or if
+    /// it was already added.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.add_custom_transport_param(0x7c5d, b"v1")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`peer_custom_transport_param()`]: struct.Connection.html#method.peer_custom_transport_param
+    pub fn add_custom_transport_param(
+        &mut self, id: u64, value: &[u8],
+    ) -> Result<()> {
+        if id >= 1 << 62 || TransportParams::is_known_param(id) {
+            return Err(Error::InvalidState);
+        }
 
-    /// Packet number spaces.
-    pkt_num_spaces: [packet::PktNumSpace; packet::Epoch::count()],
+        let params = &mut self.local_transport_params.extra_params;
 
-    /// Peer's transport parameters.
-    peer_transport_params: TransportParams,
+        if params.iter().any(|(i, _)| *i == id) {
+            return Err(Error::InvalidState);
+        }
 
-    /// Local transport parameters.
-    local_transport_params: TransportParams,
+        params.push((id, value.to_vec()));
 
-    /// TLS handshake state.
-    handshake: tls::Handshake,
+        Ok(())
+    }
 
-    /// Serialized TLS session buffer.
+    /// Sets the maximum number of writes performed by plugins that are kept
+    /// by each connection.
     ///
-    /// This field is populated when a new session ticket is processed on the
-    /// client. On the server this is empty.
-    session: Option<Vec<u8>>,
+    /// Once this many writes are kept, the oldest one is dropped for each new
+    /// write. See [`plugin_mutations()`] for more details.
+    ///
+    /// The default value is `128`.
+    ///
+    /// [`plugin_mutations()`]: struct.Connection.html#method.plugin_mutations
+    #[cfg(feature = "plugins")]
+    pub fn set_max_plugin_mutations(&mut self, v: usize) {
+        self.max_plugin_mutations = v;
+    }
 
-    /// The configuration for recovery.
-    recovery_config: recovery::RecoveryConfig,
+    /// Adds a plugin to be inserted into the connections created with this
+    /// configuration when they are pluginized.
+    ///
+    /// The plugin is loaded from the WebAssembly file at `path` by
+    /// [`Connection::pluginize()`], in the order plugins were added. The file
+    /// is checked to be a well-formed WebAssembly module right away, so that
+    /// an error is returned here rather than when connections are created if
+    /// it can't be read or isn't one.
+    ///
+    /// [`Connection::pluginize()`]: struct.Connection.html#method.pluginize
+    #[cfg(feature = "plugins")]
+    pub fn load_plugin_from_file(&mut self, path: &str) -> Result<()> {
+        let path = std::path::PathBuf::from(path);
 
-    /// The path manager.
-    paths: path::PathMap,
+        let module = std::fs::read(&path).map_err(|_| Error::InvalidState)?;
 
-    /// List of supported application protocols.
-    application_protos: Vec<Vec<u8>>,
+        if !plugin::is_wasm_module(&module) {
+            return Err(Error::InvalidState);
+        }
+
+        self.plugin_files.push(path);
+
+        Ok(())
+    }
+
+    /// Sets whether Initial and Handshake packets received after the
+    /// corresponding keys have been discarded close the connection.
+    ///
+    /// Such packets can be legitimately received when they are delayed or
+    /// duplicated by the network, and are silently dropped by default, in
+    /// which case the rest of the datagram is still processed. When set to
+    /// `true`, receiving one of them instead closes the connection with a
+    /// `PROTOCOL_VIOLATION` error.
+    ///
+    /// This is a debugging aid only, and must not be enabled on untrusted
+    /// networks. Since the keys are gone, such packets can't be authenticated,
+    /// so anyone able to forge an Initial or Handshake packet for the
+    /// connection, without knowing any of its secrets, can close it.
+    ///
+    /// In either case, such packets are counted in [`Stats`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Stats`]: struct.Stats.html
+    pub fn set_error_on_late_packets(&mut self, v: bool) {
+        self.error_on_late_packets = v;
+    }
+
+    /// Sets how received datagrams that don't contain any valid QUIC packet
+    /// are handled.
+    ///
+    /// The default value is `JunkDatagramPolicy::Count`.
+    pub fn set_junk_datagram_policy(&mut self, policy: JunkDatagramPolicy) {
+        self.junk_datagram_policy = policy;
+    }
+
+    /// Sets how Connection IDs provided by the peer in excess of the
+    /// `active_connection_id_limit` transport parameter are handled.
+    ///
+    /// In either case, the connection is closed with a
+    /// CONNECTION_ID_LIMIT_ERROR if too many retired Connection IDs are
+    /// waiting to be announced to the peer, so that a peer flooding the
+    /// endpoint with NEW_CONNECTION_ID frames can't make it use an unbounded
+    /// amount of memory.
+    ///
+    /// The default value is `ConnectionIdLimitPolicy::Close`.
+    pub fn set_connection_id_limit_policy(
+        &mut self, policy: ConnectionIdLimitPolicy,
+    ) {
+        self.cid_limit_policy = policy;
+    }
+
+    /// Sets how 1-RTT packets are padded to hide their actual length.
+    ///
+    /// Padding is only added as long as the packet still fits in the
+    /// maximum UDP payload size and the congestion window, so packets might
+    /// end up shorter than requested. Packets that only carry ACK frames are
+    /// not padded, as padding would make them count towards the bytes in
+    /// flight even though they are never acknowledged. This is independent of
+    /// the padding always applied to datagrams carrying Initial packets and to
+    /// path probes.
+    ///
+    /// The default value is `ShortPacketPadding::None`.
+    pub fn set_short_packet_padding(&mut self, padding: ShortPacketPadding) {
+        self.short_packet_padding = padding;
+    }
+
+    /// Sets whether frames are always written in the same order in packets.
+    ///
+    /// When enabled, ACK frames always come first, followed by control
+    /// frames, including those of plugins that asked to be written before
+    /// ACK frames, and then by DATAGRAM and STREAM frames. DATAGRAM frames are
+    /// also always preferred over STREAM frames, instead of alternating
+    /// between the two from one packet to the next, so the packetization only
+    /// depends on what the application queued.
+    ///
+    /// This is meant for debugging, interop testing and trace replay, as
+    /// streams can be starved by a steady flow of DATAGRAMs.
+    ///
+    /// The default value is `false`.
+    pub fn set_deterministic_frame_order(&mut self, v: bool) {
+        self.deterministic_frame_order = v;
+    }
+
+    /// Sets when connections rotate their 1-RTT keys on their own.
+    ///
+    /// Once the given number of packets or bytes were sent with the current
+    /// keys, a key update is initiated as with [`initiate_key_update()`]. If
+    /// the previous key update was not confirmed yet, it is initiated as soon
+    /// as it is. Key updates are never initiated automatically when the
+    /// multipath extension is in use.
+    ///
+    /// The default value is `KeyUpdateInterval::None`.
+    ///
+    /// [`initiate_key_update()`]:
+    /// struct.Connection.html#method.initiate_key_update
+    pub fn set_key_update_interval(&mut self, v: KeyUpdateInterval) {
+        self.key_update_interval = v;
+    }
+
+    /// Sets the store used by client connections to cache address validation
+    /// tokens and TLS sessions.
+    ///
+    /// When set, [`connect()`] offers the token and the session previously
+    /// stored for the server, and the connection records those later received
+    /// from the server. This has no effect on server connections.
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`connect()`]: fn.connect.html
+    pub fn set_client_store(&mut self, v: Option<Arc<dyn ClientStore>>) {
+        self.client_store = v;
+    }
+
+    /// Sets the key ring used by server connections to protect session
+    /// tickets.
+    ///
+    /// Session tickets are encrypted with the current key of the ring, and
+    /// tickets encrypted with a key it retired are still accepted during its
+    /// overlap window, and then renewed. The same ring can be shared by the
+    /// configs of all the connections of an endpoint. When set, this takes
+    /// precedence over [`set_ticket_key()`].
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`set_ticket_key()`]: struct.Config.html#method.set_ticket_key
+    pub fn set_key_ring(&mut self, v: Option<Arc<KeyRing>>) {
+        self.tls_ctx.set_key_ring(v.clone());
+        self.tls_ctx
+            .set_ticket_key_callback(v.is_some() || self.ticket_keys.is_some());
+        self.key_ring = v;
+    }
+
+    /// Sets the source of the address validation tokens of server
+    /// connections.
+    ///
+    /// The minter creates the tokens of the Retry packets written by
+    /// [`retry_minted()`], and validates the tokens echoed back by clients in
+    /// [`accept_with_token()`]. Server connections also send the client a
+    /// token in a NEW_TOKEN frame once the handshake is completed, so that it
+    /// can skip the Retry round-trip when connecting again.
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`retry_minted()`]: fn.retry_minted.html
+    /// [`accept_with_token()`]: fn.accept_with_token.html
+    pub fn set_token_minter(&mut self, v: Option<Arc<dyn TokenMinter>>) {
+        self.token_minter = v;
+    }
+
+    /// Sets the source of the Source Connection IDs of server connections.
+    ///
+    /// The generator creates the Source Connection ID of the connections
+    /// returned by [`accept_generated()`], and the additional ones issued by
+    /// [`new_generated_source_cid()`].
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`accept_generated()`]: fn.accept_generated.html
+    /// [`new_generated_source_cid()`]:
+    /// struct.Connection.html#method.new_generated_source_cid
+    pub fn set_connection_id_generator(
+        &mut self, v: Option<Arc<dyn ConnectionIdGenerator>>,
+    ) {
+        self.cid_generator = v;
+    }
+
+    /// Sets the sink that connections report their telemetry to.
+    ///
+    /// The same sink can be shared by the configs of all the connections of an
+    /// endpoint, so that its metrics are aggregated in a single place.
+    ///
+    /// The default value is `None`.
+    pub fn set_metrics(&mut self, v: Option<Arc<dyn Metrics>>) {
+        self.metrics = v;
+    }
+
+    /// Sets the observer that receives the delivery rate samples of
+    /// connections, each time packets are acknowledged.
+    ///
+    /// The most recent sample of each path is also reported in
+    /// [`PathStats`].
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`PathStats`]: struct.PathStats.html
+    pub fn set_delivery_rate_observer(
+        &mut self, v: Option<Arc<dyn DeliveryRateObserver>>,
+    ) {
+        self.delivery_rate_observer = v;
+    }
+
+    /// Sets the provider of the cryptographic primitives used for packet
+    /// protection.
+    ///
+    /// This allows using hardware-offloaded or FIPS-certified AEAD
+    /// implementations. The TLS handshake itself is still performed by the
+    /// TLS library.
+    ///
+    /// The default value is `None`, in which case the [`DefaultCryptoProvider`]
+    /// is used.
+    ///
+    /// [`DefaultCryptoProvider`]: struct.DefaultCryptoProvider.html
+    pub fn set_crypto_provider(&mut self, v: Option<Arc<dyn CryptoProvider>>) {
+        self.crypto_provider = v;
+    }
+
+    /// Sets the experiments enabled on connections.
+    ///
+    /// The experiments can be queried by the application with
+    /// [`experiments()`]. Plugins receive them through the `experiments`
+    /// operation, once inserted by [`pluginize()`] or [`insert_plugin()`].
+    ///
+    /// The default value is an empty set.
+    ///
+    /// [`experiments()`]: struct.Connection.html#method.experiments
+    /// [`pluginize()`]: struct.Connection.html#method.pluginize
+    /// [`insert_plugin()`]: struct.Connection.html#method.insert_plugin
+    pub fn set_experiments(&mut self, v: Experiments) {
+        self.experiments = v;
+    }
+
+    /// Limits how often the given behavior can happen.
+    ///
+    /// The behavior is allowed as long as a token bucket refilled at `rate`
+    /// tokens per second, and holding up to `burst` tokens, is not empty. A
+    /// `burst` of 0 removes the limit.
+    ///
+    /// Each connection created with this config gets its own buckets for the
+    /// per-connection behaviors. `Retry` and `NewToken` are instead limited
+    /// endpoint-wide, as the application issues them itself after checking
+    /// [`rate_limit_allow()`].
+    ///
+    /// By default, no behavior is rate-limited.
+    ///
+    /// [`rate_limit_allow()`]: struct.Config.html#method.rate_limit_allow
+    pub fn set_rate_limit(
+        &mut self, behavior: RateLimited, rate: u64, burst: u64,
+    ) {
+        self.rate_limits.set(behavior, rate, burst);
+    }
+
+    /// Returns whether the given behavior is allowed now by the limit set
+    /// with [`set_rate_limit()`], consuming a token if so.
+    ///
+    /// This is meant to be called by servers before sending a Retry packet
+    /// with [`retry()`], or issuing an address validation token.
+    ///
+    /// [`set_rate_limit()`]: struct.Config.html#method.set_rate_limit
+    /// [`retry()`]: fn.retry.html
+    pub fn rate_limit_allow(&mut self, behavior: RateLimited) -> bool {
+        self.rate_limits.allow(behavior, time::Instant::now())
+    }
+}
+
+/// A QUIC connection.
+pub struct Connection {
+    /// The pluginized connection.
+    #[cfg(feature = "plugins")]
+    pc: Option<ParentReferencer<PluginizableConnection<Self>>>,
+
+    /// QUIC wire version used for the connection.
+    version: u32,
+
+    /// QUIC versions supported by the endpoint, in order of preference.
+    supported_versions: Vec<u32>,
+
+    /// Connection Identifiers.
+    ids: cid::ConnectionIdentifiers,
+
+    /// Unique opaque ID for the connection that can be used for logging.
+    trace_id: String,
+
+    /// Packet number spaces.
+    pkt_num_spaces: [packet::PktNumSpace; packet::Epoch::count()],
+
+    /// Peer's transport parameters.
+    peer_transport_params: TransportParams,
+
+    /// Local transport parameters.
+    local_transport_params: TransportParams,
+
+    /// TLS handshake state.
+    handshake: tls::Handshake,
+
+    /// Serialized TLS session buffer.
+    ///
+    /// This field is populated when a new session ticket is processed on the
+    /// client. On the server this is empty.
+    session: Option<Vec<u8>>,
+
+    /// The configuration for recovery.
+    recovery_config: recovery::RecoveryConfig,
+
+    /// The path manager.
+    paths: path::PathMap,
+
+    /// The path the client migrates to once it is validated.
+    pending_migration: Option<usize>,
+
+    /// List of supported application protocols.
+    application_protos: Vec<Vec<u8>>,
 
     /// Total number of received packets.
     recv_count: usize,
@@ -1292,6 +2834,15 @@ pub struct Connection {
     /// This counts only STREAM and CRYPTO data.
     stream_retrans_bytes: u64,
 
+    /// Total number of duplicate STREAM bytes received over the connection.
+    stream_dup_bytes: u64,
+
+    /// Number of duplicate STREAM bytes above which an alarm is raised.
+    stream_dup_alarm: Option<u64>,
+
+    /// Whether the duplicate STREAM bytes alarm was raised.
+    stream_dup_alarm_raised: bool,
+
     /// Total number of bytes sent over the connection.
     sent_bytes: u64,
 
@@ -1329,6 +2880,15 @@ pub struct Connection {
     /// Idle timeout expiration time.
     idle_timer: Option<time::Instant>,
 
+    /// Interval after which a PING frame is sent on a quiet connection.
+    keepalive_interval: Option<time::Duration>,
+
+    /// Keep-alive expiration time.
+    keepalive_timer: Option<time::Instant>,
+
+    /// Handshake timeout expiration time.
+    handshake_timer: Option<time::Instant>,
+
     /// Draining timeout expiration time.
     draining_timer: Option<time::Instant>,
 
@@ -1348,6 +2908,14 @@ pub struct Connection {
     /// relevant for client connections.
     did_version_negotiation: bool,
 
+    /// The version of the client's first Initial packets, when the connection
+    /// was upgraded to another compatible version (RFC 9368).
+    original_version: Option<u32>,
+
+    /// The key opening the client's Initial packets of the original version,
+    /// after the server upgraded the connection.
+    original_initial_open: Option<crypto::Open>,
+
     /// Whether stateless retry has been performed.
     did_retry: bool,
 
@@ -1363,6 +2931,10 @@ pub struct Connection {
     /// Whether the connection handshake has been completed.
     handshake_completed: bool,
 
+    /// Whether Initial and Handshake packets ignore the congestion window
+    /// during the handshake.
+    handshake_priority: bool,
+
     /// Whether the HANDSHAKE_DONE frame has been sent.
     handshake_done_sent: bool,
 
@@ -1382,6 +2954,9 @@ pub struct Connection {
     // Whether the connection was timed out
     timed_out: bool,
 
+    /// Whether the connection was closed because the handshake timed out.
+    handshake_timed_out: bool,
+
     /// Whether to send GREASE.
     grease: bool,
 
@@ -1393,14 +2968,224 @@ pub struct Connection {
 
     /// DATAGRAM queues.
     dgram_recv_queue: dgram::DatagramQueue,
-    dgram_send_queue: dgram::DatagramQueue,
+    dgram_send_queue: dgram::DatagramQueue<dgram::DatagramBuf>,
+
+    /// The number of queued DATAGRAMs below which the send queue becomes
+    /// writable again after being full.
+    dgram_send_low_watermark: usize,
+
+    /// Whether a DATAGRAM was rejected because the send queue was full, and
+    /// the application wasn't notified that it became writable yet.
+    dgram_send_blocked: bool,
 
     /// Whether to emit DATAGRAM frames in the next packet.
     emit_dgram: bool,
 
+    /// Readers the data of streams is lazily pulled from.
+    stream_readers: HashMap<u64, Box<dyn std::io::Read + Send + Sync>>,
+
+    /// Maximum number of bytes read ahead from a stream's reader.
+    stream_read_ahead: usize,
+
+    /// Allocates the buffers holding the data received on streams.
+    buf_factory: Option<Arc<dyn BufFactory>>,
+
     /// Whether the connection should prevent from reusing destination
     /// Connection IDs when the peer migrates.
     disable_dcid_reuse: bool,
+
+    /// How many times the amount of data received on a path can be sent on
+    /// it, before the peer's address is validated.
+    anti_amplification_factor: usize,
+
+    /// Wall-clock time at which the connection was suspended, if any.
+    suspended_at: Option<time::SystemTime>,
+
+    /// Client-side token and session cache, along with the server identity
+    /// used to index it.
+    client_store: Option<(Arc<dyn ClientStore>, String)>,
+
+    /// Keys protecting the session tickets issued by a server.
+    key_ring: Option<Arc<KeyRing>>,
+
+    /// Session ticket keys set by the application, when there is no key ring.
+    ticket_keys: Option<Arc<keyring::TicketKeys>>,
+
+    /// Source of the address validation tokens issued by a server.
+    token_minter: Option<Arc<dyn TokenMinter>>,
+
+    /// Source of the Connection IDs issued by a server.
+    cid_generator: Option<Arc<dyn ConnectionIdGenerator>>,
+
+    /// Address validation token to send in a NEW_TOKEN frame.
+    new_token: Option<Vec<u8>>,
+
+    /// Sink of the connection's telemetry.
+    metrics: Option<Arc<dyn Metrics>>,
+
+    /// Receiver of the connection's delivery rate samples.
+    delivery_rate_observer: Option<Arc<dyn DeliveryRateObserver>>,
+
+    /// Provider of the packet protection primitives.
+    crypto_provider: Arc<dyn CryptoProvider>,
+
+    /// Verifier of the peer's certificate chain.
+    cert_verifier: Option<Arc<dyn CertVerifier>>,
+
+    /// TLS keylog callback.
+    keylog_callback: Option<Arc<tls::KeylogCallback>>,
+
+    /// Experiments enabled on the connection.
+    experiments: Experiments,
+
+    /// Time at which the connection was created.
+    created: time::Instant,
+
+    /// ACK delays reported by the peer for application data.
+    ack_delay_received: AckDelayStats,
+
+    /// ACK delays of the ACK frames sent for application data.
+    ack_delay_sent: AckDelayStats,
+
+    /// Total number of frames processed from received packets.
+    rx_frames_count: usize,
+
+    /// Timers requested by plugins.
+    #[cfg(feature = "plugins")]
+    plugin_timers: plugin::PluginTimers,
+
+    /// Whether to close the connection when receiving Initial or Handshake
+    /// packets after the corresponding keys have been discarded.
+    error_on_late_packets: bool,
+
+    /// Total number of Initial packets received after the Initial keys have
+    /// been discarded.
+    late_initial_count: usize,
+
+    /// Total number of Handshake packets received after the Handshake keys
+    /// have been discarded.
+    late_handshake_count: usize,
+
+    /// Total number of authenticated duplicate packets received.
+    dup_pkt_count: usize,
+
+    /// How received datagrams that don't contain any valid QUIC packet are
+    /// handled.
+    junk_datagram_policy: JunkDatagramPolicy,
+
+    /// Total number of junk datagrams received.
+    junk_datagram_count: usize,
+
+    /// How 1-RTT packets are padded.
+    short_packet_padding: ShortPacketPadding,
+
+    /// Whether frames are always written in the same order.
+    deterministic_frame_order: bool,
+
+    /// The token buckets of the rate-limited behaviors.
+    rate_limits: ratelimit::RateLimits,
+
+    /// Total number of actions denied by a rate limit.
+    rate_limited_count: usize,
+
+    /// Total number of failed plugin operations.
+    plugin_failure_count: usize,
+
+    /// The most recent writes to connection and recovery fields performed by
+    /// plugins.
+    #[cfg(feature = "plugins")]
+    plugin_mutations: plugin::PluginAuditLog,
+
+    /// The plugins inserted by `pluginize()`.
+    #[cfg(feature = "plugins")]
+    plugin_files: Vec<std::path::PathBuf>,
+
+    /// Total number of DATAGRAMs dropped from the send queue because their
+    /// time to live elapsed.
+    dgram_expired_count: usize,
+
+    /// Total number of bytes of junk datagrams received.
+    junk_bytes: u64,
+
+    /// Total number of datagrams dropped because their sender was throttled.
+    throttled_datagram_count: usize,
+
+    /// Peer addresses that recently sent junk datagrams.
+    junk_senders: HashMap<SocketAddr, JunkSender>,
+
+    /// Whether the multipath extension has been negotiated.
+    multipath: bool,
+
+    /// Whether the ACK frequency extension has been negotiated.
+    ack_frequency: bool,
+
+    /// Whether the QUIC bit of sent packets is greased.
+    grease_quic_bit: bool,
+
+    /// The ACK rate requested from the peer.
+    ack_freq_send: ack_freq::AckFrequencySend,
+
+    /// The ACK rate requested by the peer.
+    ack_freq_recv: ack_freq::AckFrequencyRecv,
+
+    /// Packet number spaces of the paths used with the multipath extension,
+    /// keyed by the sequence number of the Source Connection ID the peer sends
+    /// 1-RTT packets to. Packets sent to the Source Connection ID with
+    /// sequence number 0 use the Application packet number space instead.
+    mp_recv_spaces: HashMap<u64, packet::PktNumSpace>,
+
+    /// Next packet numbers of the paths used with the multipath extension,
+    /// keyed by the sequence number of the Destination Connection ID.
+    mp_next_pkt_nums: HashMap<u64, u64>,
+
+    /// Selects the path of each packet with the multipath extension.
+    path_scheduler: Box<dyn PathScheduler>,
+
+    /// Stream data ranges sent on one path that a redundant path scheduler
+    /// still needs to send on another path, keyed by path identifier.
+    redundant_streams: HashMap<usize, Vec<(u64, u64, usize, bool)>>,
+
+    /// The path, as a pair of local and peer addresses, that the data of some
+    /// streams is restricted to with the multipath extension.
+    stream_path_affinity: stream::StreamIdHashMap<(SocketAddr, SocketAddr)>,
+
+    /// The current 1-RTT key phase.
+    key_phase: bool,
+
+    /// Total number of 1-RTT key updates, initiated by either endpoint.
+    key_update_count: usize,
+
+    /// Key update events not yet reported to the application.
+    key_update_events: events::EventQueue<KeyUpdateEvent>,
+
+    /// Stream events not yet reported to the application.
+    stream_events: events::EventQueue<StreamEvent>,
+
+    /// The callback notified of events by the C API.
+    #[cfg(feature = "ffi")]
+    event_handler: Option<ffi::ConnEventHandler>,
+
+    /// When to initiate key updates automatically.
+    key_update_interval: KeyUpdateInterval,
+
+    /// Number of packets sent with the current 1-RTT keys.
+    key_phase_sent_count: u64,
+
+    /// Number of bytes sent with the current 1-RTT keys.
+    key_phase_sent_bytes: u64,
+
+    /// Number of packets received with the current 1-RTT keys.
+    key_phase_recv_count: u64,
+
+    /// Number of received packets that failed authentication.
+    aead_auth_fail_count: u64,
+
+    /// ECN marking and validation state.
+    ecn: ecn::EcnState,
+
+    /// Buffer packets are assembled in by `send_vectored()`, when they don't
+    /// fit in the first buffer provided by the application.
+    send_buf: Vec<u8>,
 }
 
 /// Creates a new server-side connection.
@@ -1461,6 +3246,30 @@ pub fn connect(
         conn.handshake.set_host_name(server_name)?;
     }
 
+    if let Some(store) = &config.client_store {
+        let server = match server_name {
+            Some(v) => v.to_string(),
+
+            None => peer.to_string(),
+        };
+
+        conn.token = store.take_token(&server);
+
+        if let Some(hints) = store.get_hints(&server) {
+            conn.apply_server_hints(&hints)?;
+        }
+
+        if let Some(session) = store.take_session(&server) {
+            // A stale or corrupted session shouldn't prevent the connection
+            // from being established, so just do a full handshake instead.
+            if let Err(e) = conn.set_session(&session) {
+                trace!("{} ignoring stored session: {:?}", conn.trace_id, e);
+            }
+        }
+
+        conn.client_store = Some((store.clone(), server));
+    }
+
     Ok(conn)
 }
 
@@ -1491,7 +3300,23 @@ pub fn connect(
 pub fn negotiate_version(
     scid: &ConnectionId, dcid: &ConnectionId, out: &mut [u8],
 ) -> Result<usize> {
-    packet::negotiate_version(scid, dcid, out)
+    packet::negotiate_version(scid, dcid, &SUPPORTED_VERSIONS, out)
+}
+
+/// Writes a version negotiation packet listing the versions of `config`.
+///
+/// This is the same as [`negotiate_version()`], but lists the versions set
+/// with [`set_supported_versions()`] instead of all the versions supported by
+/// quiche.
+///
+/// [`negotiate_version()`]: fn.negotiate_version.html
+/// [`set_supported_versions()`]:
+/// struct.Config.html#method.set_supported_versions
+#[inline]
+pub fn negotiate_version_with_config(
+    scid: &ConnectionId, dcid: &ConnectionId, config: &Config, out: &mut [u8],
+) -> Result<usize> {
+    packet::negotiate_version(scid, dcid, &config.supported_versions, out)
 }
 
 /// Writes a stateless retry packet.
@@ -1561,32 +3386,239 @@ pub fn retry(
     packet::retry(scid, dcid, new_scid, token, version, out)
 }
 
-/// Returns true if the given protocol version is supported.
-#[inline]
-pub fn version_is_supported(version: u32) -> bool {
-    matches!(
-        version,
-        PROTOCOL_VERSION_V1 |
-            PROTOCOL_VERSION_DRAFT27 |
-            PROTOCOL_VERSION_DRAFT28 |
-            PROTOCOL_VERSION_DRAFT29
-    )
-}
-
-/// Pushes a frame to the output packet if there is enough space.
+/// Writes a stateless retry packet with a token created by the configured
+/// [`TokenMinter`].
 ///
-/// Returns `true` on success, `false` otherwise. In case of failure it means
-/// there is no room to add the frame in the packet. You may retry to add the
-/// frame later.
-macro_rules! push_frame_to_pkt {
-    ($out:expr, $frames:expr, $frame:expr, $left:expr) => {{
-        if $frame.wire_len() <= $left {
-            $left -= $frame.wire_len();
-
-            $frame.to_bytes(&mut $out)?;
-
-            $frames.push($frame);
-
+/// This is the same as [`retry()`], but the token is minted for the client at
+/// `peer` by the minter set with [`set_token_minter()`], and holds the `dcid`
+/// parameter so that [`accept_with_token()`] can later recover it. The
+/// [`InvalidState`] error is returned if no minter is configured.
+///
+/// [`TokenMinter`]: trait.TokenMinter.html
+/// [`retry()`]: fn.retry.html
+/// [`set_token_minter()`]: struct.Config.html#method.set_token_minter
+/// [`accept_with_token()`]: fn.accept_with_token.html
+/// [`InvalidState`]: enum.Error.html#variant.InvalidState
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(0xbabababa)?;
+/// # let mut buf = [0; 512];
+/// # let mut out = [0; 512];
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// # let local = socket.local_addr().unwrap();
+/// let (len, peer) = socket.recv_from(&mut buf).unwrap();
+///
+/// let hdr =
+///     quiche::Header::from_slice(&mut buf[..len], quiche::MAX_CONN_ID_LEN)?;
+///
+/// let token = hdr.token.as_ref().unwrap();
+///
+/// // No token sent by client, ask it to validate its address.
+/// if token.is_empty() {
+///     let len = quiche::retry_minted(
+///         &hdr.scid,
+///         &hdr.dcid,
+///         &scid,
+///         &peer,
+///         hdr.version,
+///         &mut out,
+///         &config,
+///     )?;
+///
+///     socket.send_to(&out[..len], &peer).unwrap();
+///     return Ok(());
+/// }
+///
+/// let conn = quiche::accept_with_token(&scid, token, local, peer, &mut config)?;
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub fn retry_minted(
+    scid: &ConnectionId, dcid: &ConnectionId, new_scid: &ConnectionId,
+    peer: &SocketAddr, version: u32, out: &mut [u8], config: &Config,
+) -> Result<usize> {
+    let minter = config.token_minter.as_ref().ok_or(Error::InvalidState)?;
+
+    let token = AddressToken::Retry {
+        odcid: ConnectionId::from_vec(dcid.to_vec()),
+    };
+
+    let token = minter.mint(&token, peer)?;
+
+    packet::retry(scid, dcid, new_scid, &token, version, out)
+}
+
+/// Creates a new server-side connection for a client that sent an address
+/// validation token.
+///
+/// The token is validated by the minter set with [`set_token_minter()`]. A
+/// token from a Retry packet written by [`retry_minted()`] provides the
+/// original destination connection ID of the connection, as if it was passed
+/// to [`accept()`], while a token from a NEW_TOKEN frame sent in a previous
+/// connection validates the client's address right away.
+///
+/// An empty token is accepted without validating the client's address, and
+/// so is an invalid NEW_TOKEN token, as if the client didn't send any. An
+/// error is returned if a Retry token is not valid, in which case the packet
+/// should be dropped, or if no minter is configured.
+///
+/// [`set_token_minter()`]: struct.Config.html#method.set_token_minter
+/// [`retry_minted()`]: fn.retry_minted.html
+/// [`accept()`]: fn.accept.html
+pub fn accept_with_token(
+    scid: &ConnectionId, token: &[u8], local: SocketAddr, peer: SocketAddr,
+    config: &mut Config,
+) -> Result<Connection> {
+    if token.is_empty() {
+        return accept(scid, None, local, peer, config);
+    }
+
+    let minter = config.token_minter.clone().ok_or(Error::InvalidState)?;
+
+    let token = match minter.validate(token, &peer) {
+        Ok(v) => v,
+
+        // An invalid NEW_TOKEN token is handled as if the client didn't send
+        // any token (RFC 9000 section 8.1.3).
+        Err(_) if !minter.is_retry_token(token) =>
+            return accept(scid, None, local, peer, config),
+
+        Err(e) => return Err(e),
+    };
+
+    accept_validated(scid, token, local, peer, config)
+}
+
+/// Creates a new server-side connection for a client whose address
+/// validation token was already validated.
+pub(crate) fn accept_validated(
+    scid: &ConnectionId, token: AddressToken, local: SocketAddr,
+    peer: SocketAddr, config: &mut Config,
+) -> Result<Connection> {
+    match token {
+        AddressToken::Retry { odcid } =>
+            accept(scid, Some(&odcid), local, peer, config),
+
+        AddressToken::NewToken => {
+            let mut conn = accept(scid, None, local, peer, config)?;
+
+            // The address was validated by a previous connection.
+            conn.paths.get_active_mut()?.verified_peer_address = true;
+
+            Ok(conn)
+        },
+    }
+}
+
+/// Creates a new server-side connection with a generated Source Connection
+/// ID.
+///
+/// This is the same as [`accept()`], but the Source Connection ID is created
+/// by the generator set with [`set_connection_id_generator()`], and can be
+/// retrieved with [`source_id()`]. The [`InvalidState`] error is returned if
+/// no generator is configured.
+///
+/// [`accept()`]: fn.accept.html
+/// [`set_connection_id_generator()`]:
+/// struct.Config.html#method.set_connection_id_generator
+/// [`source_id()`]: struct.Connection.html#method.source_id
+/// [`InvalidState`]: enum.Error.html#variant.InvalidState
+pub fn accept_generated(
+    odcid: Option<&ConnectionId>, local: SocketAddr, peer: SocketAddr,
+    config: &mut Config,
+) -> Result<Connection> {
+    let generator = config.cid_generator.clone().ok_or(Error::InvalidState)?;
+
+    accept(&generator.generate(), odcid, local, peer, config)
+}
+
+/// Extracts the server name and application protocols from a client's
+/// Initial packet.
+///
+/// This can be used by servers to route, reject or pick the [`Config`] for a
+/// new connection before calling [`accept()`], without having to allocate any
+/// connection state. The packet is decrypted in a copy of `buf`, so the
+/// original buffer can then be passed to [`recv()`] unchanged.
+///
+/// [`Done`] is returned when the ClientHello doesn't fit in the packet (e.g.
+/// because of large key shares), in which case the application needs to fall
+/// back to accepting the connection and inspecting it after the handshake.
+///
+/// [`Config`]: struct.Config.html
+/// [`accept()`]: fn.accept.html
+/// [`recv()`]: struct.Connection.html#method.recv
+/// [`Done`]: enum.Error.html#variant.Done
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(0xbabababa)?;
+/// # let mut buf = [0; 512];
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// # let local = socket.local_addr().unwrap();
+/// let (len, peer) = socket.recv_from(&mut buf).unwrap();
+///
+/// let hello = quiche::peek_client_hello(&buf[..len])?;
+///
+/// if hello.server_name.as_deref() != Some("quic.tech") {
+///     // Drop connections for unknown hosts early.
+///     return Ok(());
+/// }
+///
+/// let conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+/// # Ok::<(), quiche::Error>(())
+/// ```
+#[inline]
+pub fn peek_client_hello(buf: &[u8]) -> Result<ClientHello> {
+    client_hello::peek(buf)
+}
+
+/// Returns true if the given protocol version is supported.
+#[inline]
+pub fn version_is_supported(version: u32) -> bool {
+    matches!(
+        version,
+        PROTOCOL_VERSION_V1 |
+            PROTOCOL_VERSION_V2 |
+            PROTOCOL_VERSION_DRAFT27 |
+            PROTOCOL_VERSION_DRAFT28 |
+            PROTOCOL_VERSION_DRAFT29
+    )
+}
+
+/// Returns true if the given protocol version is a final one, as opposed to
+/// one of the older drafts.
+#[inline]
+fn version_is_final(version: u32) -> bool {
+    matches!(version, PROTOCOL_VERSION_V1 | PROTOCOL_VERSION_V2)
+}
+
+/// Returns true if a connection can be upgraded from version `from` to version
+/// `to` through compatible version negotiation.
+///
+/// QUIC v1 and v2 are compatible with each other, as defined by RFC 9369.
+#[inline]
+fn versions_are_compatible(from: u32, to: u32) -> bool {
+    version_is_final(from) && version_is_final(to)
+}
+
+/// Pushes a frame to the output packet if there is enough space.
+///
+/// Returns `true` on success, `false` otherwise. In case of failure it means
+/// there is no room to add the frame in the packet. You may retry to add the
+/// frame later.
+macro_rules! push_frame_to_pkt {
+    ($out:expr, $frames:expr, $frame:expr, $left:expr) => {{
+        if $frame.wire_len() <= $left {
+            $left -= $frame.wire_len();
+
+            $frame.to_bytes(&mut $out)?;
+
+            $frames.push($frame);
+
             true
         } else {
             false
@@ -1645,6 +3677,10 @@ const QLOG_DATA_MV: EventType =
 const QLOG_METRICS: EventType =
     EventType::RecoveryEventType(RecoveryEventType::MetricsUpdated);
 
+#[cfg(all(feature = "qlog", feature = "plugins"))]
+const QLOG_MESSAGE: EventType =
+    EventType::GenericEventType(qlog::events::GenericEventType::Message);
+
 #[cfg(feature = "qlog")]
 struct QlogInfo {
     streamer: Option<qlog::streamer::QlogStreamer>,
@@ -1697,26 +3733,35 @@ impl Connection {
         path.peer_verified_local_address = is_server;
 
         // Do not allocate more than the number of active CIDs.
-        let paths = path::PathMap::new(
+        let mut paths = path::PathMap::new(
             path,
             config.local_transport_params.active_conn_id_limit as usize,
             is_server,
         );
 
+        paths.set_max_events(config.max_queued_events);
+
         let active_path_id = paths.get_active_path_id()?;
 
-        let ids = cid::ConnectionIdentifiers::new(
+        let mut ids = cid::ConnectionIdentifiers::new(
             config.local_transport_params.active_conn_id_limit as usize,
             scid,
             active_path_id,
             reset_token,
         );
 
+        ids.set_retire_dcids_over_limit(
+            config.cid_limit_policy == ConnectionIdLimitPolicy::Retire,
+        );
+
         let mut conn = Connection {
+            #[cfg(feature = "plugins")]
             pc: None,
 
             version: config.version,
 
+            supported_versions: config.supported_versions.clone(),
+
             ids,
 
             trace_id: scid_as_hex.join(""),
@@ -1739,6 +3784,8 @@ impl Connection {
 
             paths,
 
+            pending_migration: None,
+
             application_protos: config.application_protos.clone(),
 
             recv_count: 0,
@@ -1765,10 +3812,17 @@ impl Connection {
 
             stream_retrans_bytes: 0,
 
+            stream_dup_bytes: 0,
+
+            stream_dup_alarm: config.stream_dup_alarm,
+
+            stream_dup_alarm_raised: false,
+
             streams: stream::StreamMap::new(
                 config.local_transport_params.initial_max_streams_bidi,
                 config.local_transport_params.initial_max_streams_uni,
                 config.max_stream_window,
+                config.max_stream_send_buffer,
             ),
 
             odcid: None,
@@ -1785,6 +3839,14 @@ impl Connection {
 
             idle_timer: None,
 
+            keepalive_interval: config.keepalive_interval,
+
+            keepalive_timer: None,
+
+            handshake_timer: config
+                .handshake_timeout
+                .map(|t| time::Instant::now() + t),
+
             draining_timer: None,
 
             undecryptable_pkts: VecDeque::new(),
@@ -1796,6 +3858,8 @@ impl Connection {
             derived_initial_secrets: false,
 
             did_version_negotiation: false,
+            original_version: None,
+            original_initial_open: None,
 
             did_retry: false,
 
@@ -1808,6 +3872,8 @@ impl Connection {
 
             handshake_completed: false,
 
+            handshake_priority: config.handshake_priority,
+
             handshake_done_sent: false,
             handshake_done_acked: false,
 
@@ -1819,6 +3885,8 @@ impl Connection {
 
             timed_out: false,
 
+            handshake_timed_out: false,
+
             grease: config.grease,
 
             keylog: None,
@@ -1834,11 +3902,150 @@ impl Connection {
                 config.dgram_send_max_queue_len,
             ),
 
+            dgram_send_low_watermark: config.dgram_send_low_watermark,
+
+            dgram_send_blocked: false,
+
             emit_dgram: true,
 
+            stream_readers: HashMap::new(),
+
+            stream_read_ahead: config.stream_read_ahead,
+
+            buf_factory: config.buf_factory.clone(),
+
             disable_dcid_reuse: config.disable_dcid_reuse,
+
+            anti_amplification_factor: config.anti_amplification_factor,
+
+            suspended_at: None,
+
+            client_store: None,
+
+            key_ring: config.key_ring.clone(),
+
+            ticket_keys: config.ticket_keys.clone(),
+
+            token_minter: config.token_minter.clone(),
+
+            cid_generator: config.cid_generator.clone(),
+
+            new_token: None,
+
+            metrics: config.metrics.clone(),
+
+            delivery_rate_observer: config.delivery_rate_observer.clone(),
+
+            crypto_provider: config
+                .crypto_provider
+                .clone()
+                .unwrap_or_else(crypto::default_provider),
+
+            cert_verifier: config.cert_verifier.clone(),
+
+            keylog_callback: config.keylog_callback.clone(),
+
+            experiments: config.experiments,
+
+            created: time::Instant::now(),
+
+            ack_delay_received: AckDelayStats::default(),
+
+            ack_delay_sent: AckDelayStats::default(),
+
+            rx_frames_count: 0,
+
+            #[cfg(feature = "plugins")]
+            plugin_timers: plugin::PluginTimers::new(config.max_queued_events),
+
+            error_on_late_packets: config.error_on_late_packets,
+
+            late_initial_count: 0,
+
+            late_handshake_count: 0,
+
+            dup_pkt_count: 0,
+
+            junk_datagram_policy: config.junk_datagram_policy,
+
+            junk_datagram_count: 0,
+
+            short_packet_padding: config.short_packet_padding,
+
+            deterministic_frame_order: config.deterministic_frame_order,
+
+            rate_limits: config.rate_limits.clone(),
+
+            rate_limited_count: 0,
+
+            plugin_failure_count: 0,
+
+            #[cfg(feature = "plugins")]
+            plugin_mutations: plugin::PluginAuditLog::new(
+                config.max_plugin_mutations,
+            ),
+
+            #[cfg(feature = "plugins")]
+            plugin_files: config.plugin_files.clone(),
+
+            dgram_expired_count: 0,
+
+            junk_bytes: 0,
+
+            throttled_datagram_count: 0,
+
+            junk_senders: HashMap::new(),
+
+            multipath: false,
+
+            ack_frequency: false,
+
+            grease_quic_bit: false,
+
+            ack_freq_send: ack_freq::AckFrequencySend::default(),
+
+            ack_freq_recv: ack_freq::AckFrequencyRecv::default(),
+
+            mp_recv_spaces: HashMap::new(),
+
+            mp_next_pkt_nums: HashMap::new(),
+
+            path_scheduler: scheduler::new_scheduler(config.path_scheduler),
+
+            redundant_streams: HashMap::new(),
+
+            stream_path_affinity: stream::StreamIdHashMap::default(),
+
+            key_phase: false,
+
+            key_update_count: 0,
+
+            key_update_events: events::EventQueue::new(config.max_queued_events),
+
+            stream_events: events::EventQueue::new(config.max_queued_events),
+
+            #[cfg(feature = "ffi")]
+            event_handler: None,
+
+            key_update_interval: config.key_update_interval,
+
+            key_phase_sent_count: 0,
+
+            key_phase_sent_bytes: 0,
+
+            key_phase_recv_count: 0,
+
+            aead_auth_fail_count: 0,
+
+            ecn: ecn::EcnState::new(config.ecn),
+
+            send_buf: Vec::new(),
         };
 
+        if let Some(metrics) = &conn.metrics {
+            metrics.add_gauge(Gauge::Connections, 1);
+        }
+
         if let Some(odcid) = odcid {
             conn.local_transport_params
                 .original_destination_connection_id = Some(odcid.to_vec().into());
@@ -1855,7 +4062,7 @@ impl Connection {
         conn.handshake.init(is_server)?;
 
         conn.handshake
-            .use_legacy_codepoint(config.version != PROTOCOL_VERSION_V1);
+            .use_legacy_codepoint(!version_is_final(config.version));
 
         conn.encode_transport_params()?;
 
@@ -1866,6 +4073,7 @@ impl Connection {
             rand::rand_bytes(&mut dcid[..]);
 
             let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &conn.crypto_provider,
                 &dcid,
                 conn.version,
                 conn.is_server,
@@ -2065,18 +4273,299 @@ impl Connection {
     /// # Ok::<(), quiche::Error>(())
     /// ```
     pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
-        let len = buf.len();
+        self.recv_internal(buf, info, None, None, false, EcnCodepoint::NotEct)
+            .map(|(done, _)| done)
+    }
 
-        if len == 0 {
-            return Err(Error::BufferTooShort);
-        }
+    /// Processes QUIC packets received from the peer, whose header was
+    /// already parsed by the application.
+    ///
+    /// This behaves like [`recv()`], but the header of the first packet in
+    /// `buf` isn't parsed again when `hdr` is what [`Header::from_slice()`]
+    /// returned for `buf`, e.g. when it was used to find the connection the
+    /// datagram belongs to.
+    ///
+    /// Only short header packets skip parsing. Long header packets, which are
+    /// only exchanged during the handshake, are always parsed again, as is a
+    /// header that doesn't match the packet.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`Header::from_slice()`]: struct.Header.html#method.from_slice
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let (len, from) = socket.recv_from(&mut buf).unwrap();
+    ///
+    /// let hdr = quiche::Header::from_slice(&mut buf[..len], 16)?;
+    ///
+    /// // Look up the connection with `hdr.dcid`.
+    ///
+    /// let recv_info = quiche::RecvInfo { from, to: local };
+    ///
+    /// let read = conn.recv_with_header(&mut buf[..len], hdr, recv_info)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn recv_with_header(
+        &mut self, buf: &mut [u8], hdr: Header, info: RecvInfo,
+    ) -> Result<usize> {
+        self.recv_internal(
+            buf,
+            info,
+            Some(hdr),
+            None,
+            false,
+            EcnCodepoint::NotEct,
+        )
+        .map(|(done, _)| done)
+    }
 
-        let recv_pid = self.paths.path_id_from_addrs(&(info.to, info.from));
+    /// Processes QUIC packets received from the peer in several datagrams.
+    ///
+    /// This behaves like calling [`recv()`] on each of the datagrams of each
+    /// of the `segments`, in order, and splits the datagrams coalesced by GRO
+    /// according to their segment size.
+    ///
+    /// Errors are handled per datagram: a datagram that fails to be processed
+    /// is skipped, and the following ones are still processed. On success the
+    /// total number of bytes processed from the segments is returned. If no
+    /// datagram could be processed, the error of the last one is returned
+    /// instead, as [`recv()`] would.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 65535];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let segment_size = 1200;
+    /// let (len, from) = socket.recv_from(&mut buf).unwrap();
+    ///
+    /// let mut segments = [quiche::RecvSegment {
+    ///     buf: &mut buf[..len],
+    ///     segment_size,
+    ///     info: quiche::RecvInfo { from, to: local },
+    /// }];
+    ///
+    /// let read = conn.recv_batch(&mut segments)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn recv_batch(&mut self, segments: &mut [RecvSegment]) -> Result<usize> {
+        let mut done = 0;
+        let mut processed = 0;
+        let mut last_err = None;
 
-        if let Some(recv_pid) = recv_pid {
-            let recv_path = self.paths.get_mut(recv_pid)?;
+        for segment in segments.iter_mut() {
+            let segment_size = match segment.segment_size {
+                0 => segment.buf.len(),
 
-            // Keep track of how many bytes we received from the client, so we
+                v => v,
+            };
+
+            // An empty segment has no datagram to process.
+            if segment_size == 0 {
+                continue;
+            }
+
+            for dgram in segment.buf.chunks_mut(segment_size) {
+                match self.recv_internal(
+                    dgram,
+                    segment.info,
+                    None,
+                    None,
+                    false,
+                    EcnCodepoint::NotEct,
+                ) {
+                    Ok((read, _)) => {
+                        done += read;
+                        processed += 1;
+                    },
+
+                    Err(e) => {
+                        trace!(
+                            "{} skipped datagram of batch: {:?}",
+                            self.trace_id,
+                            e
+                        );
+
+                        last_err = Some(e);
+                    },
+                }
+            }
+        }
+
+        match last_err {
+            Some(e) if processed == 0 => Err(e),
+
+            _ => Ok(done),
+        }
+    }
+
+    /// Processes QUIC packets received from the peer, along with the ECN
+    /// codepoint of the IP packet that carried them.
+    ///
+    /// This behaves like [`recv()`], and additionally counts the packets per
+    /// ECN codepoint, to report the counts to the peer. The codepoint is the
+    /// two least significant bits of the IPv4 TOS or IPv6 Traffic Class byte,
+    /// which can be converted with `EcnCodepoint::from()`.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    pub fn recv_with_ecn(
+        &mut self, buf: &mut [u8], info: RecvInfo, ecn: EcnCodepoint,
+    ) -> Result<usize> {
+        self.recv_internal(buf, info, None, None, false, ecn)
+            .map(|(done, _)| done)
+    }
+
+    /// Processes QUIC packets received from the peer, within the given budget.
+    ///
+    /// This behaves like [`recv()`], except that processing stops once the
+    /// `budget` is exhausted, even if `buf` contains more coalesced packets.
+    /// This bounds the time spent processing a single datagram, e.g. so that
+    /// an event loop serving many connections stays responsive.
+    ///
+    /// On success the number of bytes processed from the input buffer is
+    /// returned, along with a [`RecvCursor`] if more packets are left to be
+    /// processed. In the latter case, the application should call this method
+    /// again with the unprocessed part of the buffer, the same `info` and the
+    /// cursor, before processing other datagrams for this connection. The
+    /// cursor tells the connection that the bytes it is given are the rest of
+    /// a datagram it already accounted for. Without a matching cursor, `buf`
+    /// is processed as a new datagram.
+    ///
+    /// [`Done`] is returned if the budget doesn't allow processing anything,
+    /// i.e. if one of its limits is zero.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`RecvCursor`]: struct.RecvCursor.html
+    /// [`Done`]: enum.Error.html#variant.Done
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let budget = quiche::RecvBudget {
+    ///     max_packets: Some(1),
+    ///     max_frames: None,
+    /// };
+    ///
+    /// let (len, from) = socket.recv_from(&mut buf).unwrap();
+    ///
+    /// let recv_info = quiche::RecvInfo { from, to: local };
+    ///
+    /// let mut off = 0;
+    /// let mut cursor = None;
+    ///
+    /// loop {
+    ///     let (read, pending) = conn.recv_with_budget(
+    ///         &mut buf[off..len],
+    ///         recv_info,
+    ///         budget,
+    ///         cursor,
+    ///     )?;
+    ///
+    ///     off += read;
+    ///
+    ///     if pending.is_none() {
+    ///         break;
+    ///     }
+    ///
+    ///     cursor = pending;
+    ///
+    ///     // Service other connections before resuming.
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn recv_with_budget(
+        &mut self, buf: &mut [u8], info: RecvInfo, budget: RecvBudget,
+        cursor: Option<RecvCursor>,
+    ) -> Result<(usize, Option<RecvCursor>)> {
+        if budget.max_packets == Some(0) || budget.max_frames == Some(0) {
+            return Err(Error::Done);
+        }
+
+        let resumed =
+            cursor.map_or(false, |c| c.info == info && c.left == buf.len());
+
+        let (done, pending) = self.recv_internal(
+            buf,
+            info,
+            None,
+            Some(budget),
+            resumed,
+            EcnCodepoint::NotEct,
+        )?;
+
+        let cursor = if pending {
+            Some(RecvCursor {
+                info,
+                left: buf.len() - done,
+            })
+        } else {
+            None
+        };
+
+        Ok((done, cursor))
+    }
+
+    /// Processes the QUIC packets of a datagram.
+    ///
+    /// When `resumed` is true, `buf` is the rest of a datagram partially
+    /// processed by a previous call, which was already accounted for.
+    fn recv_internal(
+        &mut self, buf: &mut [u8], info: RecvInfo, mut hdr: Option<Header>,
+        budget: Option<RecvBudget>, resumed: bool, ecn: EcnCodepoint,
+    ) -> Result<(usize, bool)> {
+        let len = buf.len();
+
+        if len == 0 {
+            return Err(Error::BufferTooShort);
+        }
+
+        if self.handshake_timed_out {
+            return Err(Error::HandshakeTimeout);
+        }
+
+        // When resuming the processing of a datagram, its bytes have already
+        // been accounted for.
+        if !resumed && self.is_junk_sender_throttled(info.from) {
+            trace!(
+                "{} dropping datagram from throttled address {:?}",
+                self.trace_id,
+                info.from,
+            );
+
+            self.throttled_datagram_count += 1;
+
+            return Ok((len, false));
+        }
+
+        let recv_pid = self.paths.path_id_from_addrs(&(info.to, info.from));
+
+        if let Some(recv_pid) = recv_pid {
+            let verified_peer_address =
+                self.paths.get(recv_pid)?.verified_peer_address;
+
+            // Keep track of how many bytes we received from the client, so we
             // can limit bytes sent back before address validation, to a
             // multiple of this. The limit needs to be increased early on, so
             // that if there is an error there is enough credit to send a
@@ -2087,8 +4576,10 @@ impl Connection {
             //
             // Note that we also need to limit the number of bytes we sent on a
             // path if we are not the host that initiated its usage.
-            if self.is_server && !recv_path.verified_peer_address {
-                recv_path.max_send_bytes += len * MAX_AMPLIFICATION_FACTOR;
+            if self.is_server && !verified_peer_address && !resumed {
+                let credit = self.anti_amplification_credit(len);
+
+                self.paths.get_mut(recv_pid)?.max_send_bytes += credit;
             }
         } else if !self.is_server {
             // If a client receives packets from an unknown server address,
@@ -2099,25 +4590,94 @@ impl Connection {
                 info,
             );
 
-            return Ok(len);
+            return Ok((len, false));
         }
 
         let mut done = 0;
         let mut left = len;
 
+        let mut packets = 0;
+        let frames_start = self.rx_frames_count;
+
+        let progress_start = self.recv_progress();
+
+        let dcid_len = self.source_id().len();
+
+        // Position of the destination connection ID of the first packet.
+        let mut first_dcid = None;
+
+        // Whether the budget was exhausted before the end of the datagram.
+        let mut pending = false;
+
         // Process coalesced packets.
         while left > 0 {
+            if let Some(budget) = budget {
+                let frames = self.rx_frames_count - frames_start;
+
+                if budget.max_packets.map_or(false, |max| packets >= max) ||
+                    budget.max_frames.map_or(false, |max| frames >= max)
+                {
+                    trace!(
+                        "{} recv budget exhausted with {} bytes left",
+                        self.trace_id,
+                        left
+                    );
+
+                    pending = true;
+                    break;
+                }
+            }
+
+            let off = len - left;
+
+            // Packets coalesced after the first one must have the same
+            // destination connection ID, others are ignored. They are skipped
+            // when their length is known, so that the rest of the datagram can
+            // still be processed.
+            let dcid = packet::dcid_range(&buf[off..len], dcid_len);
+
+            match (first_dcid.clone(), dcid) {
+                (None, Ok(dcid)) => first_dcid = Some(dcid),
+
+                (Some(first), Ok(dcid))
+                    if buf[first.clone()] !=
+                        buf[off + dcid.start..off + dcid.end] =>
+                {
+                    trace!(
+                        "{} ignored coalesced packet with different dcid",
+                        self.trace_id
+                    );
+
+                    let read = packet::skip_len(&mut buf[off..len])
+                        .unwrap_or(left)
+                        .min(left);
+
+                    done += read;
+                    left -= read;
+
+                    packets += 1;
+
+                    continue;
+                },
+
+                _ => (),
+            }
+
+            // The header parsed by the application, if any, only belongs to
+            // the first packet of the datagram.
             let read = match self.recv_single(
-                &mut buf[len - left..len],
+                &mut buf[off..len],
                 &info,
+                hdr.take(),
                 recv_pid,
+                ecn,
             ) {
                 Ok(v) => v,
 
                 Err(Error::Done) => {
                     // If the packet can't be processed or decrypted, check if
                     // it's a stateless reset.
-                    if self.is_stateless_reset(&buf[len - left..len]) {
+                    if self.is_stateless_reset(&buf[off..len]) {
                         trace!("{} packet is a stateless reset", self.trace_id);
 
                         self.closed = true;
@@ -2136,6 +4696,16 @@ impl Connection {
 
             done += read;
             left -= read;
+
+            packets += 1;
+        }
+
+        // A datagram processed in several calls isn't checked, as only its
+        // first part might be junk.
+        if !resumed && !pending && !self.closed {
+            let junk = self.recv_progress() == progress_start;
+
+            self.on_datagram_received(info.from, len, junk);
         }
 
         // Even though the packet was previously "accepted", it
@@ -2143,7 +4713,99 @@ impl Connection {
         // from the `recv()` method.
         self.process_undecrypted_0rtt_packets()?;
 
-        Ok(done)
+        Ok((done, pending))
+    }
+
+    /// Returns a value that changes whenever a received packet is processed,
+    /// buffered, or accounted for as a late or duplicate packet.
+    fn recv_progress(&self) -> usize {
+        self.recv_count +
+            self.undecryptable_pkts.len() +
+            self.late_initial_count +
+            self.late_handshake_count +
+            self.dup_pkt_count
+    }
+
+    /// Returns whether datagrams from the given address are dropped because
+    /// it sent too many junk datagrams.
+    fn is_junk_sender_throttled(&mut self, from: SocketAddr) -> bool {
+        let sender = match self.junk_senders.get_mut(&from) {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        match sender.throttled_until {
+            Some(until) if until > time::Instant::now() => true,
+
+            Some(_) => {
+                self.junk_senders.remove(&from);
+                false
+            },
+
+            None => false,
+        }
+    }
+
+    /// Returns whether `addr` is the peer address of a validated path.
+    fn is_validated_peer_addr(&self, addr: SocketAddr) -> bool {
+        self.paths
+            .iter()
+            .any(|(_, p)| p.peer_addr() == addr && p.validated())
+    }
+
+    /// Applies the junk datagram policy to a datagram received from the given
+    /// address.
+    fn on_datagram_received(&mut self, from: SocketAddr, len: usize, junk: bool) {
+        if !junk {
+            self.junk_senders.remove(&from);
+            return;
+        }
+
+        if self.junk_datagram_policy == JunkDatagramPolicy::Ignore {
+            return;
+        }
+
+        trace!(
+            "{} received junk datagram from {:?} len={}",
+            self.trace_id,
+            from,
+            len
+        );
+
+        self.junk_datagram_count += 1;
+        self.junk_bytes += len as u64;
+
+        if self.junk_datagram_policy != JunkDatagramPolicy::Throttle {
+            return;
+        }
+
+        // Junk claiming to come from a validated path could be spoofed, so
+        // it's only counted.
+        if self.is_validated_peer_addr(from) {
+            return;
+        }
+
+        // Stop tracking new addresses when too many are already tracked.
+        if !self.junk_senders.contains_key(&from) &&
+            self.junk_senders.len() >= MAX_JUNK_SENDERS
+        {
+            return;
+        }
+
+        let sender = self.junk_senders.entry(from).or_insert(JunkSender {
+            count: 0,
+            throttled_until: None,
+        });
+
+        sender.count += 1;
+
+        if sender.count >= JUNK_THROTTLE_THRESHOLD {
+            trace!("{} throttling address {:?}", self.trace_id, from);
+
+            sender.throttled_until =
+                Some(time::Instant::now() + JUNK_THROTTLE_DURATION);
+        }
     }
 
     fn process_undecrypted_0rtt_packets(&mut self) -> Result<()> {
@@ -2204,7 +4866,8 @@ impl Connection {
     ///
     /// [`Done`]: enum.Error.html#variant.Done
     fn recv_single(
-        &mut self, buf: &mut [u8], info: &RecvInfo, recv_pid: Option<usize>,
+        &mut self, buf: &mut [u8], info: &RecvInfo, hdr: Option<Header>,
+        recv_pid: Option<usize>, ecn: EcnCodepoint,
     ) -> Result<usize> {
         let now = time::Instant::now();
 
@@ -2224,17 +4887,36 @@ impl Connection {
 
         let buf_len = buf.len();
 
+        let dcid_len = self.source_id().len();
+
+        // Only short headers can be reused without parsing them again, as
+        // their length only depends on the destination connection ID. The
+        // header is parsed again if it doesn't match the packet.
+        let hdr = hdr.filter(|hdr| {
+            hdr.ty == packet::Type::Short &&
+                hdr.dcid.len() == dcid_len &&
+                !Header::is_long(buf[0]) &&
+                buf[1..].starts_with(&hdr.dcid)
+        });
+
         let mut b = octets::OctetsMut::with_slice(buf);
 
-        let mut hdr = Header::from_bytes(&mut b, self.source_id().len())
-            .map_err(|e| {
+        let mut hdr = match hdr {
+            Some(hdr) => {
+                b.skip(1 + dcid_len)?;
+
+                hdr
+            },
+
+            None => Header::from_bytes(&mut b, dcid_len).map_err(|e| {
                 drop_pkt_on_err(
                     e,
                     self.recv_count,
                     self.is_server,
                     &self.trace_id,
                 )
-            })?;
+            })?,
+        };
 
         if hdr.ty == packet::Type::VersionNegotiation {
             // Version negotiation packets can only be sent by the server.
@@ -2271,24 +4953,9 @@ impl Connection {
                 return Err(Error::Done);
             }
 
-            let supported_versions =
-                versions.iter().filter(|&&v| version_is_supported(v));
-
-            let mut found_version = false;
-
-            for &v in supported_versions {
-                found_version = true;
-
-                // The final version takes precedence over draft ones.
-                if v == PROTOCOL_VERSION_V1 {
-                    self.version = v;
-                    break;
-                }
-
-                self.version = cmp::max(self.version, v);
-            }
+            let version = match self.choose_version(&versions) {
+                Some(v) => v,
 
-            if !found_version {
                 // We don't support any of the versions offered.
                 //
                 // While a man-in-the-middle attacker might be able to
@@ -2296,34 +4963,17 @@ impl Connection {
                 // failure, the window of opportunity is very small and
                 // this error is quite useful for debugging, so don't just
                 // ignore the packet.
-                return Err(Error::UnknownVersion);
-            }
+                None => return Err(Error::UnknownVersion),
+            };
 
             self.did_version_negotiation = true;
 
-            // Derive Initial secrets based on the new version.
-            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
-                &self.destination_id(),
-                self.version,
-                self.is_server,
-            )?;
-
             // Reset connection state to force sending another Initial packet.
             self.drop_epoch_state(packet::Epoch::Initial, now);
             self.got_peer_conn_id = false;
             self.handshake.clear()?;
 
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
-                Some(aead_open);
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
-                Some(aead_seal);
-
-            self.handshake
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
-
-            // Encode transport parameters again, as the new version might be
-            // using a different format.
-            self.encode_transport_params()?;
+            self.set_client_version(version)?;
 
             return Err(Error::Done);
         }
@@ -2334,8 +4984,9 @@ impl Connection {
                 return Err(Error::Done);
             }
 
-            // Ignore duplicate retry.
-            if self.did_retry {
+            // Ignore duplicate retry, as well as retry received after other
+            // packets from the server (e.g. coalesced after an Initial).
+            if self.did_retry || self.recv_count > 0 {
                 return Err(Error::Done);
             }
 
@@ -2368,6 +5019,7 @@ impl Connection {
 
             // Derive Initial secrets using the new connection ID.
             let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &self.crypto_provider,
                 &hdr.scid,
                 self.version,
                 self.is_server,
@@ -2387,24 +5039,80 @@ impl Connection {
         }
 
         if self.is_server && !self.did_version_negotiation {
-            if !version_is_supported(hdr.version) {
+            if !self.supported_versions.contains(&hdr.version) {
                 return Err(Error::UnknownVersion);
             }
 
             self.version = hdr.version;
             self.did_version_negotiation = true;
 
+            // Upgrade to a compatible version the client supports, if it is
+            // preferred. This requires the whole ClientHello to be in this
+            // packet, so that the client's versions are known before the
+            // handshake starts.
+            let available = client_hello::peek(b.buf())
+                .map(|hello| hello.available_versions)
+                .unwrap_or_default();
+
+            if let Some(version) = self.choose_compatible_version(&available) {
+                self.upgrade_server_version(version, &hdr.dcid)?;
+            }
+
             self.handshake
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
+                .use_legacy_codepoint(!version_is_final(self.version));
 
             // Encode transport parameters again, as the new version might be
             // using a different format.
             self.encode_transport_params()?;
         }
 
-        if hdr.ty != packet::Type::Short && hdr.version != self.version {
+        // The first Initial packet of the server tells the client that the
+        // connection was upgraded to a compatible version. The upgrade only
+        // applies once the packet is authenticated with the new version's keys.
+        let upgrade_open = if !self.is_server &&
+            hdr.ty == packet::Type::Initial &&
+            hdr.version != self.version &&
+            self.recv_count == 0 &&
+            versions_are_compatible(self.version, hdr.version) &&
+            self.supported_versions.contains(&hdr.version)
+        {
+            let (aead_open, _) = crypto::derive_initial_key_material(
+                &self.crypto_provider,
+                &self.destination_id(),
+                hdr.version,
+                self.is_server,
+            )?;
+
+            Some(aead_open)
+        } else {
+            None
+        };
+
+        // The client keeps sending Initial packets of the original version
+        // until it receives one of the server's.
+        let is_original_initial = self.is_server &&
+            hdr.ty == packet::Type::Initial &&
+            self.original_version == Some(hdr.version);
+
+        if hdr.ty != packet::Type::Short &&
+            hdr.version != self.version &&
+            upgrade_open.is_none() &&
+            !is_original_initial
+        {
             // At this point version negotiation was already performed, so
-            // ignore packets that don't match the connection's version.
+            // ignore packets that don't match the connection's version. When
+            // the version is known the packet is skipped, so that other
+            // packets coalesced in the same datagram can still be processed.
+            if version_is_supported(hdr.version) {
+                if let Ok(payload_len) = b.get_varint() {
+                    let payload_len = payload_len as usize;
+
+                    if payload_len <= b.cap() {
+                        return Ok(b.off() + payload_len);
+                    }
+                }
+            }
+
             return Err(Error::Done);
         }
 
@@ -2437,6 +5145,7 @@ impl Connection {
         // Derive initial secrets on the server.
         if !self.derived_initial_secrets {
             let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &self.crypto_provider,
                 &hdr.dcid,
                 self.version,
                 self.is_server,
@@ -2453,8 +5162,20 @@ impl Connection {
         // Select packet number space epoch based on the received packet's type.
         let epoch = hdr.ty.to_epoch()?;
 
+        // With the multipath extension, 1-RTT packets sent to different Source
+        // Connection IDs use separate packet number spaces.
+        let mp_space_id = if self.multipath && hdr.ty == packet::Type::Short {
+            self.ids.find_scid_seq(&hdr.dcid).map_or(0, |(seq, _)| seq)
+        } else {
+            0
+        };
+
         // Select AEAD context used to open incoming packet.
-        let aead = if hdr.ty == packet::Type::ZeroRTT {
+        let aead = if upgrade_open.is_some() {
+            upgrade_open.as_ref()
+        } else if is_original_initial {
+            self.original_initial_open.as_ref()
+        } else if hdr.ty == packet::Type::ZeroRTT {
             // Only use 0-RTT key if incoming packet is 0-RTT.
             self.pkt_num_spaces[epoch].crypto_0rtt_open.as_ref()
         } else {
@@ -2466,6 +5187,33 @@ impl Connection {
         let aead = match aead {
             Some(v) => v,
 
+            None if hdr.ty != packet::Type::ZeroRTT &&
+                self.pkt_num_spaces[epoch].keys_discarded =>
+            {
+                match epoch {
+                    packet::Epoch::Initial => self.late_initial_count += 1,
+
+                    _ => self.late_handshake_count += 1,
+                }
+
+                trace!(
+                    "{} received late {:?} packet len={}",
+                    self.trace_id,
+                    hdr.ty,
+                    payload_len
+                );
+
+                // The packet can't be authenticated anymore, so this is only
+                // safe as a debugging aid.
+                if self.error_on_late_packets {
+                    return Err(Error::InvalidPacket);
+                }
+
+                // Only skip this packet, other packets coalesced in the same
+                // datagram can still be processed.
+                return Ok(b.off() + payload_len);
+            },
+
             None => {
                 if hdr.ty == packet::Type::ZeroRTT &&
                     self.undecryptable_pkts.len() < MAX_UNDECRYPTABLE_PACKETS &&
@@ -2501,7 +5249,7 @@ impl Connection {
         })?;
 
         let pn = packet::decode_pkt_num(
-            self.pkt_num_spaces[epoch].largest_rx_pkt_num,
+            self.largest_rx_pkt_num(epoch, mp_space_id),
             hdr.pkt_num,
             hdr.pkt_num_len,
         );
@@ -2517,22 +5265,81 @@ impl Connection {
             AddrTupleFmt(info.from, info.to)
         );
 
+        // A key phase different from the current one means that the packet
+        // was either protected with the previous keys, or that the peer
+        // initiated a key update.
+        let mut aead_next = None;
+
+        let aead =
+            if hdr.ty == packet::Type::Short && hdr.key_phase != self.key_phase {
+                match self.pkt_num_spaces[epoch].key_update.as_ref() {
+                    Some(ku) if pn < ku.pn_on_update => &ku.crypto_open,
+
+                    _ => {
+                        let seal = self.pkt_num_spaces[epoch]
+                            .crypto_seal
+                            .as_ref()
+                            .ok_or(Error::InvalidState)?;
+
+                        aead_next = Some((
+                            aead.derive_next_packet_key()?,
+                            seal.derive_next_packet_key()?,
+                        ));
+
+                        // `aead_next` was just set, so this never fails.
+                        &aead_next.as_ref().unwrap().0
+                    },
+                }
+            } else {
+                aead
+            };
+
         #[cfg(feature = "qlog")]
         let mut qlog_frames = vec![];
 
-        let mut payload = packet::decrypt_pkt(
+        let integrity_limit = aead.alg().integrity_limit();
+
+        let mut payload = match packet::decrypt_pkt_on_path(
             &mut b,
+            mp_space_id as u32,
             pn,
             pn_len,
             payload_len,
             aead,
-        )
-        .map_err(|e| {
-            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
-        })?;
+        ) {
+            Ok(v) => v,
+
+            Err(e) => {
+                // Too many forged packets could allow the peer to break the
+                // integrity of the AEAD, so stop using the connection then.
+                if e == Error::CryptoFail {
+                    self.aead_auth_fail_count += 1;
+
+                    if self.aead_auth_fail_count >= integrity_limit {
+                        return Err(Error::AeadLimitReached);
+                    }
+                }
+
+                return Err(drop_pkt_on_err(
+                    e,
+                    self.recv_count,
+                    self.is_server,
+                    &self.trace_id,
+                ));
+            },
+        };
+
+        if upgrade_open.is_some() {
+            self.upgrade_client_version(hdr.version)?;
+        }
 
-        if self.pkt_num_spaces[epoch].recv_pkt_num.contains(pn) {
+        if self
+            .recv_pkt_num_space_mut(epoch, mp_space_id)
+            .recv_pkt_num
+            .contains(pn)
+        {
             trace!("{} ignored duplicate packet {}", self.trace_id, pn);
+            self.dup_pkt_count += 1;
             return Err(Error::Done);
         }
 
@@ -2541,6 +5348,58 @@ impl Connection {
             return Err(Error::InvalidPacket);
         }
 
+        if let Some((open_next, seal_next)) = aead_next {
+            // The peer must not initiate a new key update before we sent it
+            // an ACK protected with the current keys (RFC 9001 Section 6.2).
+            if self.pkt_num_spaces[epoch]
+                .key_update
+                .as_ref()
+                .map_or(false, |ku| !ku.ack_sent)
+            {
+                return Err(Error::KeyUpdate);
+            }
+
+            trace!("{} peer initiated key update", self.trace_id);
+
+            let pkt_space = &mut self.pkt_num_spaces[epoch];
+
+            let open_prev = pkt_space
+                .crypto_open
+                .replace(open_next)
+                .ok_or(Error::InvalidState)?;
+
+            pkt_space.crypto_seal = Some(seal_next);
+
+            pkt_space.key_update = Some(packet::KeyUpdate {
+                crypto_open: open_prev,
+                pn_on_update: pn,
+                pn_sent_on_update: pkt_space.next_pkt_num,
+                local: false,
+                update_acked: false,
+                ack_sent: false,
+                timer: None,
+            });
+
+            self.key_phase = !self.key_phase;
+            self.key_update_count += 1;
+            self.key_phase_sent_count = 0;
+            self.key_phase_sent_bytes = 0;
+            self.key_phase_recv_count = 0;
+
+            self.key_update_events.push(KeyUpdateEvent::PeerInitiated);
+        } else if hdr.ty == packet::Type::Short && hdr.key_phase == self.key_phase
+        {
+            // Track the lowest packet number protected with the current keys,
+            // which is still unknown after a locally initiated update.
+            if let Some(ku) = self.pkt_num_spaces[epoch].key_update.as_mut() {
+                ku.pn_on_update = cmp::min(ku.pn_on_update, pn);
+            }
+        }
+
+        if hdr.ty == packet::Type::Short && hdr.key_phase == self.key_phase {
+            self.key_phase_recv_count += 1;
+        }
+
         // Now that we decrypted the packet, let's see if we can map it to an
         // existing path.
         let recv_pid = if hdr.ty == packet::Type::Short && self.got_peer_conn_id {
@@ -2572,7 +5431,7 @@ impl Connection {
 
             if !self.did_retry &&
                 (self.version >= PROTOCOL_VERSION_DRAFT28 ||
-                    self.version == PROTOCOL_VERSION_V1)
+                    version_is_final(self.version))
             {
                 self.local_transport_params
                     .original_destination_connection_id =
@@ -2613,6 +5472,8 @@ impl Connection {
                 probing = false;
             }
 
+            self.rx_frames_count += 1;
+
             if let Err(e) = self.process_frame(frame, &hdr, recv_pid, epoch, now)
             {
                 frame_processing_err = Some(e);
@@ -2699,6 +5560,27 @@ impl Connection {
                         }
                     },
 
+                    frame::Frame::MpAck {
+                        space_identifier,
+                        ranges,
+                        ..
+                    } => {
+                        let space =
+                            self.mp_recv_spaces.get_mut(&space_identifier);
+
+                        if let (Some(space), Some(largest_acked)) =
+                            (space, ranges.last())
+                        {
+                            space.recv_pkt_need_ack.remove_until(largest_acked);
+                        }
+                    },
+
+                    frame::Frame::Ping {
+                        mtu_probe: Some(size),
+                    } => {
+                        p.pmtud.on_probe_acked(size);
+                    },
+
                     frame::Frame::CryptoHeader { offset, length } => {
                         self.pkt_num_spaces[epoch]
                             .crypto_stream
@@ -2718,8 +5600,23 @@ impl Connection {
                             None => continue,
                         };
 
+                        let was_writable = stream.is_writable();
+
+                        let was_complete = stream.send.is_complete();
+
                         stream.send.ack_and_drop(offset, length);
 
+                        let writable = !was_writable && stream.is_writable();
+
+                        if !was_complete &&
+                            stream.send.is_complete() &&
+                            !stream.send.is_stopped() &&
+                            !stream.send.is_shutdown()
+                        {
+                            self.stream_events
+                                .push(StreamEvent::Acked { stream_id });
+                        }
+
                         qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
                             let ev_data = EventData::DataMoved(
                                 qlog::events::quic::DataMoved {
@@ -2742,6 +5639,12 @@ impl Connection {
                             let local = stream.local;
                             self.streams.collect(stream_id, local);
                         }
+
+                        // Acked data frees space in the send buffer, which can
+                        // make the stream writable again.
+                        if writable {
+                            self.streams.mark_writable(stream_id, true);
+                        }
                     },
 
                     frame::Frame::HandshakeDone => {
@@ -2806,34 +5709,64 @@ impl Connection {
             p.active_dcid_seq = Some(dcid_seq);
         }
 
+        // With the ACK frequency extension, the ACK of 1-RTT packets can be
+        // delayed as requested by the peer, unless they signal congestion.
+        let delay_ack = if ack_elicited &&
+            epoch == packet::Epoch::Application &&
+            mp_space_id == 0 &&
+            ecn != EcnCodepoint::Ce
+        {
+            let largest = self.pkt_num_spaces[epoch].largest_rx_pkt_num;
+
+            let reordering = if pn <= largest {
+                largest - pn
+            } else {
+                pn - largest - 1
+            };
+
+            !self.ack_freq_recv.on_ack_eliciting_packet(reordering, now)
+        } else {
+            false
+        };
+
+        // ECN counts are only reported for the packet number spaces that are
+        // not specific to a multipath path.
+        if mp_space_id == 0 {
+            self.ecn.on_packet_received(epoch, ecn);
+        }
+
+        let pkt_space = self.recv_pkt_num_space_mut(epoch, mp_space_id);
+
         // We only record the time of arrival of the largest packet number
         // that still needs to be acked, to be used for ACK delay calculation.
-        if self.pkt_num_spaces[epoch].recv_pkt_need_ack.last() < Some(pn) {
-            self.pkt_num_spaces[epoch].largest_rx_pkt_time = now;
+        if pkt_space.recv_pkt_need_ack.last() < Some(pn) {
+            pkt_space.largest_rx_pkt_time = now;
         }
 
-        self.pkt_num_spaces[epoch].recv_pkt_num.insert(pn);
+        pkt_space.recv_pkt_num.insert(pn);
 
-        self.pkt_num_spaces[epoch].recv_pkt_need_ack.push_item(pn);
+        pkt_space.recv_pkt_need_ack.push_item(pn);
 
-        self.pkt_num_spaces[epoch].ack_elicited =
-            cmp::max(self.pkt_num_spaces[epoch].ack_elicited, ack_elicited);
+        pkt_space.ack_elicited =
+            cmp::max(pkt_space.ack_elicited, ack_elicited && !delay_ack);
 
-        self.pkt_num_spaces[epoch].largest_rx_pkt_num =
-            cmp::max(self.pkt_num_spaces[epoch].largest_rx_pkt_num, pn);
+        pkt_space.largest_rx_pkt_num = cmp::max(pkt_space.largest_rx_pkt_num, pn);
 
         if !probing {
-            self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num = cmp::max(
-                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num,
-                pn,
-            );
+            pkt_space.largest_rx_non_probing_pkt_num =
+                cmp::max(pkt_space.largest_rx_non_probing_pkt_num, pn);
+
+            let largest_rx_non_probing_pkt_num =
+                pkt_space.largest_rx_non_probing_pkt_num;
 
-            // Did the peer migrated to another path?
+            // Did the peer migrated to another path? With the multipath
+            // extension, non-probing packets can be received on any path.
             let active_path_id = self.paths.get_active_path_id()?;
 
             if self.is_server &&
+                !self.multipath &&
                 recv_pid != active_path_id &&
-                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num == pn
+                largest_rx_non_probing_pkt_num == pn
             {
                 self.paths
                     .on_peer_migrated(recv_pid, self.disable_dcid_reuse)?;
@@ -2844,6 +5777,8 @@ impl Connection {
             self.idle_timer = Some(now + idle_timeout);
         }
 
+        self.reset_keepalive_timer(now);
+
         // Update send capacity.
         self.update_tx_cap();
 
@@ -2855,6 +5790,11 @@ impl Connection {
         self.recv_bytes += read as u64;
         self.paths.get_mut(recv_pid)?.recv_bytes += read as u64;
 
+        if let Some(metrics) = &self.metrics {
+            metrics.incr_counter(Counter::PacketsRecv, 1);
+            metrics.incr_counter(Counter::BytesRecv, read as u64);
+        }
+
         // An Handshake packet has been received from the client and has been
         // successfully processed, so we can drop the initial state and consider
         // the client's address to be verified.
@@ -3029,6 +5969,10 @@ impl Connection {
             return Err(Error::BufferTooShort);
         }
 
+        if self.handshake_timed_out {
+            return Err(Error::HandshakeTimeout);
+        }
+
         if self.is_closed() || self.is_draining() {
             return Err(Error::Done);
         }
@@ -3045,6 +5989,9 @@ impl Connection {
         // take care of terminating the connection as needed.
         let _ = self.process_undecrypted_0rtt_packets();
 
+        // Pull more data from the streams' readers now that it may be sent.
+        self.fill_stream_readers();
+
         // There's no point in trying to send a packet if the Initial secrets
         // have not been derived yet, so return early.
         if !self.derived_initial_secrets {
@@ -3055,10 +6002,6 @@ impl Connection {
 
         let mut done = 0;
 
-        // Limit output packet size to respect the sender and receiver's
-        // maximum UDP payload size limit.
-        let mut left = cmp::min(out.len(), self.max_send_udp_payload_size());
-
         let send_pid = match (from, to) {
             (Some(f), Some(t)) => self
                 .paths
@@ -3068,6 +6011,12 @@ impl Connection {
             _ => self.get_send_path_id(from, to)?,
         };
 
+        let pmtu_probe = self.update_pmtu(send_pid, out.len())?;
+
+        // Limit output packet size to respect the sender and receiver's
+        // maximum UDP payload size limit.
+        let mut left = cmp::min(out.len(), self.max_send_udp_payload_size());
+
         let send_path = self.paths.get_mut(send_pid)?;
 
         // Limit data sent by the server based on the amount of data received
@@ -3076,12 +6025,21 @@ impl Connection {
             left = cmp::min(left, send_path.max_send_bytes);
         }
 
+        // A PMTU probe is sent alone, in a datagram of the probed size.
+        if let Some(size) = pmtu_probe {
+            left = size;
+        }
+
+        // All the packets of a datagram are marked with the same codepoint.
+        let ecn = self.ecn.codepoint();
+
         // Generate coalesced packets.
         while left > 0 {
             let (ty, written) = match self.send_single(
                 &mut out[done..done + left],
                 send_pid,
                 has_initial,
+                pmtu_probe,
             ) {
                 Ok(v) => v,
 
@@ -3124,6 +6082,8 @@ impl Connection {
             return Err(Error::Done);
         }
 
+        self.ecn.on_datagram_sent();
+
         // Pad UDP datagram if it contains a QUIC Initial packet.
         if has_initial && left > 0 && done < MIN_CLIENT_INITIAL_LEN {
             let pad_len = cmp::min(left, MIN_CLIENT_INITIAL_LEN - done);
@@ -3142,25 +6102,265 @@ impl Connection {
             to: send_path.peer_addr(),
 
             at: send_path.recovery.get_packet_send_time(),
+
+            ecn,
         };
 
         Ok((done, info))
     }
 
-    #[pluginop_param(po = "PluginOp::ShouldSendFrame", param = "ty")]
+    /// Writes a single QUIC packet to be sent to the peer into the given
+    /// buffers.
+    ///
+    /// This behaves like [`send()`], except that the packet is written across
+    /// `bufs`, filling them in order, so that they can be passed as is to a
+    /// vectored write such as `sendmsg()`.
+    ///
+    /// Note that this is not a zero-copy API: packets are encrypted in place,
+    /// which requires them to be contiguous in memory. When the first buffer
+    /// can hold a full-sized packet, the packet is written directly in it and
+    /// no copy happens. Otherwise the packet is assembled in a buffer owned by
+    /// the connection, which is reused across calls to avoid allocating, and
+    /// then copied into `bufs`.
+    ///
+    /// On success the total number of bytes written to the buffers is
+    /// returned, or [`Done`] if there was nothing to write.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`Done`]: enum.Error.html#variant.Done
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut header = [0; 64];
+    /// # let mut payload = [0; 1500];
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let mut bufs = [
+    ///     std::io::IoSliceMut::new(&mut header),
+    ///     std::io::IoSliceMut::new(&mut payload),
+    /// ];
+    ///
+    /// let (write, send_info) = conn.send_vectored(&mut bufs)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn send_vectored(
+        &mut self, bufs: &mut [std::io::IoSliceMut],
+    ) -> Result<(usize, SendInfo)> {
+        self.send_vectored_on_path(bufs, None, None)
+    }
+
+    /// Writes a single QUIC packet to be sent to the peer into the given
+    /// buffers, from the specified local address `from` to the destination
+    /// address `to`.
+    ///
+    /// See [`send_vectored()`] and [`send_on_path()`] for more details.
+    ///
+    /// [`send_vectored()`]: struct.Connection.html#method.send_vectored
+    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
+    pub fn send_vectored_on_path(
+        &mut self, bufs: &mut [std::io::IoSliceMut], from: Option<SocketAddr>,
+        to: Option<SocketAddr>,
+    ) -> Result<(usize, SendInfo)> {
+        let max_len = self.max_send_udp_payload_size();
+
+        if let Some(first) = bufs.first_mut() {
+            if first.len() >= max_len {
+                return self.send_on_path(first, from, to);
+            }
+        }
+
+        let total_len = bufs.iter().map(|b| b.len()).sum::<usize>();
+
+        let mut out = std::mem::take(&mut self.send_buf);
+        out.resize(total_len, 0);
+
+        let res = self.send_on_path(&mut out, from, to);
+
+        if let Ok((written, _)) = res {
+            let mut off = 0;
+
+            for buf in bufs.iter_mut() {
+                if off == written {
+                    break;
+                }
+
+                let len = cmp::min(buf.len(), written - off);
+                buf[..len].copy_from_slice(&out[off..off + len]);
+
+                off += len;
+            }
+        }
+
+        self.send_buf = out;
+
+        res
+    }
+
+    /// Writes several QUIC packets to be sent to the peer, as datagrams of the
+    /// same size laid out back to back in `out`.
+    ///
+    /// This calls [`send()`] repeatedly, and stops as soon as a datagram is
+    /// shorter than the first one, or when the next datagram would not fit in
+    /// `out`, so that all the datagrams but the last one have the same size.
+    /// All of them are sent on the same path, so that the result can be passed
+    /// as-is to a socket with Generic Segmentation Offload (GSO) enabled. At
+    /// most 64 datagrams are written.
+    ///
+    /// On success the total number of bytes written, the size of each datagram
+    /// and the [`SendInfo`] of the first datagram are returned, or [`Done`] if
+    /// there was nothing to write.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`Done`]: enum.Error.html#variant.Done
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut out = [0; 65535];
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let (write, segment_size, send_info) = conn.send_batch(&mut out)?;
+    ///
+    /// // Send `out[..write]` to `send_info.to` with a single system call, using
+    /// // `segment_size` as GSO segment size.
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn send_batch(
+        &mut self, out: &mut [u8],
+    ) -> Result<(usize, usize, SendInfo)> {
+        self.send_batch_on_path(out, None, None)
+    }
+
+    /// Writes several QUIC packets to be sent to the peer, from the specified
+    /// local address `from` to the destination address `to`.
+    ///
+    /// See [`send_batch()`] and [`send_on_path()`] for more details.
+    ///
+    /// [`send_batch()`]: struct.Connection.html#method.send_batch
+    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
+    pub fn send_batch_on_path(
+        &mut self, out: &mut [u8], from: Option<SocketAddr>,
+        to: Option<SocketAddr>,
+    ) -> Result<(usize, usize, SendInfo)> {
+        let (segment_size, info) = self.send_on_path(out, from, to)?;
+
+        let mut written = segment_size;
+
+        for _ in 1..MAX_SEND_BATCH_SEGMENTS {
+            if out.len() - written < segment_size {
+                break;
+            }
+
+            let out = &mut out[written..written + segment_size];
+
+            // Following datagrams are sent on the same path as the first one.
+            let res = self.send_on_path(out, Some(info.from), Some(info.to));
+
+            let write = match res {
+                Ok((v, _)) => v,
+
+                // Errors are returned by the next call, once the datagrams
+                // written so far are sent.
+                Err(_) => break,
+            };
+
+            written += write;
+
+            if write < segment_size {
+                break;
+            }
+        }
+
+        Ok((written, segment_size, info))
+    }
+
+    /// Updates the size of the packets sent on the given path according to the
+    /// discovered PMTU, and returns the size of the PMTU probe to send on the
+    /// path, if any.
+    fn update_pmtu(
+        &mut self, send_pid: usize, max_len: usize,
+    ) -> Result<Option<usize>> {
+        let now = time::Instant::now();
+
+        // Probes are only sent in 1-RTT packets once the handshake is
+        // confirmed.
+        let can_probe = self.handshake_confirmed &&
+            self.local_error.is_none() &&
+            self.write_pkt_type(send_pid) == Ok(packet::Type::Short);
+
+        let path = self.paths.get_mut(send_pid)?;
+
+        let size = match path.update_pmtu(now) {
+            Some(v) if can_probe => v,
+
+            _ => return Ok(None),
+        };
+
+        // Probes are congestion controlled, and are not sent instead of PTO
+        // probes.
+        if size > max_len ||
+            size > path.recovery.cwnd_available() ||
+            path.recovery.loss_probes[packet::Epoch::Application] > 0
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(size))
+    }
+
+    #[cfg(feature = "plugins")]
     fn should_send_frame(
         &mut self, ty: u64, pkt_type: packet::Type, epoch: packet::Epoch,
-        is_closing: bool, left: usize, now: time::Instant,
+        is_closing: bool, left: usize,
     ) -> bool {
-        false
+        use pluginop::IntoWithPH;
+
+        let res = self.plugin_define(PluginOp::ShouldSendFrame(ty), |ph| {
+            Ok(vec![
+                pkt_type.into_with_ph(ph),
+                epoch.into_with_ph(ph),
+                is_closing.into(),
+                (left as u64).into(),
+            ])
+        });
+
+        plugin_first(res).unwrap_or(false)
     }
 
-    #[pluginop(po = "PluginOp::ShouldSendFrame", value = "2")]
     fn should_send_ack_frame(
         &mut self, pkt_type: packet::Type, epoch: packet::Epoch,
-        is_closing: bool, left: usize, now: time::Instant, send_pid: usize,
+        is_closing: bool, left: usize, send_pid: usize,
         ack_elicit_required: bool,
     ) -> bool {
+        #[cfg(feature = "plugins")]
+        {
+            use pluginop::IntoWithPH;
+
+            let res = self.plugin_define(PluginOp::ShouldSendFrame(0x02), |ph| {
+                Ok(vec![
+                    pkt_type.into_with_ph(ph),
+                    epoch.into_with_ph(ph),
+                    is_closing.into(),
+                    (left as u64).into(),
+                ])
+            });
+
+            if let Some(v) = plugin_first(res) {
+                return v;
+            }
+        }
+
+        #[cfg(not(feature = "plugins"))]
+        let _ = left;
+
         // A bit bad but still...
         let path = self.paths.get_mut(send_pid).unwrap();
         let pkt_space = &mut self.pkt_num_spaces[epoch];
@@ -3174,33 +6374,79 @@ impl Connection {
             path.active()
     }
 
-    #[pluginop_result_param(po = "PluginOp::PrepareFrame", param = "ty")]
+    #[cfg(feature = "plugins")]
     fn prepare_frame(
         &mut self, ty: u64, epoch: packet::Epoch, left: usize,
     ) -> Result<frame::Frame> {
-        Err(Error::Done)
+        use pluginop::IntoWithPH;
+
+        let res = self.plugin_define(PluginOp::PrepareFrame(ty), |ph| {
+            Ok(vec![epoch.into_with_ph(ph), (left as u64).into()])
+        });
+
+        match res.and_then(|v| v.into_iter().next()) {
+            Some(v) => self.plugin_frame(v),
+
+            None => Err(Error::Done),
+        }
     }
 
-    #[pluginop_param(po = "PluginOp::WireLen", param = "ty")]
+    #[cfg(feature = "plugins")]
     fn wire_len(&mut self, ty: u64, f: &frame::Frame) -> usize {
-        f.wire_len()
+        use pluginop::TryFromWithPH;
+
+        let res = self.plugin_define(PluginOp::WireLen(ty), |ph| {
+            Ok(vec![PluginVal::try_from_with_ph(f.clone(), ph)?])
+        });
+
+        plugin_first(res).unwrap_or_else(|| f.wire_len())
     }
 
-    #[pluginop_result_param(po = "PluginOp::WriteFrame", param = "ty")]
+    #[cfg(feature = "plugins")]
     fn write_frame(
         &mut self, ty: u64, f: &frame::Frame, b: &mut OctetsMut,
     ) -> Result<usize> {
-        f.to_bytes(b)
+        use pluginop::IntoWithPH;
+        use pluginop::TryFromWithPH;
+
+        let res = self.plugin_define(PluginOp::WriteFrame(ty), |ph| {
+            Ok(vec![
+                PluginVal::try_from_with_ph(f.clone(), ph)?,
+                OctetsMutPtr::from(&mut *b).into_with_ph(ph),
+            ])
+        });
+
+        match plugin_first(res) {
+            Some(written) => Ok(written),
+
+            None => f.to_bytes(b),
+        }
     }
 
-    #[pluginop_param(po = "PluginOp::OnFrameReserved", param = "ty")]
-    fn on_frame_reserved(&mut self, ty: u64, f: &frame::Frame) {}
+    #[cfg(feature = "plugins")]
+    fn on_frame_reserved(&mut self, ty: u64, f: &frame::Frame) {
+        use pluginop::TryFromWithPH;
 
-    #[pluginop_param(po = "PluginOp::NotifyFrame", param = "ty")]
-    fn notify_frame(&mut self, ty: u64, f: frame::Frame, lost: bool) {}
+        self.plugin_define(PluginOp::OnFrameReserved(ty), |ph| {
+            Ok(vec![PluginVal::try_from_with_ph(f.clone(), ph)?])
+        });
+    }
+
+    #[cfg_attr(not(feature = "plugins"), allow(unused_variables))]
+    fn notify_frame(&mut self, ty: u64, f: frame::Frame, lost: bool) {
+        #[cfg(feature = "plugins")]
+        {
+            use pluginop::TryFromWithPH;
+
+            self.plugin_define(PluginOp::NotifyFrame(ty), |ph| {
+                Ok(vec![PluginVal::try_from_with_ph(f, ph)?, lost.into()])
+            });
+        }
+    }
 
     fn send_single(
         &mut self, out: &mut [u8], send_pid: usize, has_initial: bool,
+        pmtu_probe: Option<usize>,
     ) -> Result<(packet::Type, usize)> {
         let now = time::Instant::now();
 
@@ -3212,6 +6458,23 @@ impl Connection {
             return Err(Error::Done);
         }
 
+        if self.is_key_update_due() {
+            // This fails while the previous key update is not confirmed, in
+            // which case it is attempted again with the next packet.
+            self.initiate_key_update().ok();
+        }
+
+        // The current keys can't protect any more packets, so they need to be
+        // updated, or the connection closed if that's not possible.
+        if self.is_confidentiality_limit_reached() &&
+            self.local_error.is_none() &&
+            self.initiate_key_update().is_err()
+        {
+            self.close(false, Error::AeadLimitReached.to_wire(), b"")
+                .ok();
+        }
+
+        #[cfg(feature = "plugins")]
         let registrations = self
             .get_pluginizable_connection()
             .map(|pc| pc.get_ph().get_registrations().to_vec())
@@ -3219,6 +6482,9 @@ impl Connection {
 
         let is_closing = self.local_error.is_some();
 
+        #[cfg(feature = "plugins")]
+        let deterministic_frame_order = self.deterministic_frame_order;
+
         let mut b = octets::OctetsMut::with_slice(out);
 
         let pkt_type = self.write_pkt_type(send_pid)?;
@@ -3228,11 +6494,18 @@ impl Connection {
         let epoch = pkt_type.to_epoch()?;
 
         let mut extension_lost: SmallVec<[frame::Frame; 1]> = SmallVec::new();
+        let mut mp_lost: SmallVec<[frame::Frame; 1]> = SmallVec::new();
 
         // Process lost frames. There might be several paths having lost frames.
         for (_, p) in self.paths.iter_mut() {
             for lost in p.recovery.lost[epoch].drain(..) {
                 match lost {
+                    frame::Frame::Ping {
+                        mtu_probe: Some(size),
+                    } => {
+                        p.pmtud.on_probe_lost(size);
+                    },
+
                     frame::Frame::CryptoHeader { offset, length } => {
                         self.pkt_num_spaces[epoch]
                             .crypto_stream
@@ -3293,6 +6566,24 @@ impl Connection {
                         self.pkt_num_spaces[epoch].ack_elicited = true;
                     },
 
+                    frame::Frame::MpAck {
+                        space_identifier, ..
+                    } => {
+                        let space =
+                            self.mp_recv_spaces.get_mut(&space_identifier);
+
+                        if let Some(space) = space {
+                            space.ack_elicited = true;
+                        }
+                    },
+
+                    frame::Frame::PathAbandon { .. } |
+                    frame::Frame::PathStatus { .. } => mp_lost.push(lost),
+
+                    frame::Frame::AckFrequency { seq_num, .. } => {
+                        self.ack_freq_send.on_lost(seq_num);
+                    },
+
                     frame::Frame::ResetStream {
                         stream_id,
                         error_code,
@@ -3310,6 +6601,10 @@ impl Connection {
                         self.handshake_done_sent = false;
                     },
 
+                    frame::Frame::NewToken { token } => {
+                        self.new_token = Some(token);
+                    },
+
                     frame::Frame::MaxStreamData { stream_id, .. } => {
                         if self.streams.get(stream_id).is_some() {
                             self.streams.mark_almost_full(stream_id, true);
@@ -3342,21 +6637,127 @@ impl Connection {
             }
         }
 
-        let is_app_limited = self.delivery_rate_check_if_app_limited();
-        let n_paths = self.paths.len();
-        let path = self.paths.get_mut(send_pid)?;
-        let pkt_space = &mut self.pkt_num_spaces[epoch];
-
+        // Multipath control frames identify their path by the sequence number
+        // of our Source Connection ID, not by the path they were lost on.
+        for lost in mp_lost {
+            match lost {
+                frame::Frame::PathAbandon {
+                    dcid_seq_num,
+                    error_code,
+                    reason,
+                } => {
+                    let path = self
+                        .paths
+                        .iter_mut()
+                        .find(|(_, p)| p.active_scid_seq == Some(dcid_seq_num));
+
+                    if let Some((_, p)) = path {
+                        p.abandon(error_code, reason);
+                    }
+                },
+
+                frame::Frame::PathStatus {
+                    dcid_seq_num,
+                    seq_num,
+                    ..
+                } => {
+                    let path = self
+                        .paths
+                        .iter_mut()
+                        .find(|(_, p)| p.active_scid_seq == Some(dcid_seq_num));
+
+                    if let Some((_, p)) = path {
+                        p.on_local_status_sent(seq_num, true);
+                    }
+                },
+
+                _ => (),
+            }
+        }
+
+        // Send again on this path the stream data that a redundant path
+        // scheduler duplicated from other paths.
+        let mut redundant_copy = false;
+
+        if pkt_type == packet::Type::Short {
+            let ranges = self.redundant_streams.remove(&send_pid);
+
+            for (stream_id, offset, length, fin) in ranges.unwrap_or_default() {
+                let stream = match self.streams.get_mut(stream_id) {
+                    Some(v) => v,
+
+                    None => continue,
+                };
+
+                let was_flushable = stream.is_flushable();
+
+                let empty_fin = length == 0 && fin;
+
+                stream.send.retransmit(offset, length);
+
+                if (stream.is_flushable() || empty_fin) && !was_flushable {
+                    let urgency = stream.urgency;
+                    let incremental = stream.incremental;
+                    self.streams.push_flushable(stream_id, urgency, incremental);
+                }
+
+                redundant_copy = true;
+            }
+        }
+
+        // Other paths the stream data sent in this packet is duplicated on.
+        let redundant_pids: SmallVec<[usize; 4]> = if self.multipath &&
+            pkt_type == packet::Type::Short &&
+            !redundant_copy &&
+            self.path_scheduler.redundant()
+        {
+            self.paths
+                .iter()
+                .filter(|(pid, p)| *pid != send_pid && p.active())
+                .map(|(pid, _)| pid)
+                .collect()
+        } else {
+            SmallVec::new()
+        };
+
+        let is_app_limited = self.delivery_rate_check_if_app_limited();
+        let n_paths = self.paths.len();
+
+        // During the handshake, Initial and Handshake packets are not held back
+        // by application data filling the congestion window.
+        let ignore_cwnd = self.handshake_priority &&
+            !self.handshake_completed &&
+            epoch != packet::Epoch::Application;
+
+        let path = self.paths.get_mut(send_pid)?;
+        let pkt_space = &mut self.pkt_num_spaces[epoch];
+
         let mut left = b.cap();
 
-        let pn = pkt_space.next_pkt_num;
+        let dcid_seq = path.active_dcid_seq.ok_or(Error::OutOfIdentifiers)?;
+
+        // With the multipath extension, 1-RTT packets sent to different
+        // Destination Connection IDs use separate packet number spaces.
+        let mp_space_id = if self.multipath && pkt_type == packet::Type::Short {
+            dcid_seq
+        } else {
+            0
+        };
+
+        let pn = if mp_space_id == 0 {
+            pkt_space.next_pkt_num
+        } else {
+            self.mp_next_pkt_nums
+                .get(&mp_space_id)
+                .copied()
+                .unwrap_or(0)
+        };
+
         let pn_len = packet::pkt_num_len(pn)?;
 
         // The AEAD overhead at the current encryption level.
         let crypto_overhead = pkt_space.crypto_overhead().ok_or(Error::Done)?;
 
-        let dcid_seq = path.active_dcid_seq.ok_or(Error::OutOfIdentifiers)?;
-
         let dcid =
             ConnectionId::from_ref(self.ids.get_dcid(dcid_seq)?.cid.as_ref());
 
@@ -3389,11 +6790,22 @@ impl Connection {
             },
 
             versions: None,
-            key_phase: false,
+            key_phase: self.key_phase,
         };
 
         hdr.to_bytes(&mut b)?;
 
+        // Clear the QUIC bit of half of the packets, but keep it in Initial
+        // packets which can be sent before the peer's transport parameters
+        // are known.
+        if self.grease_quic_bit &&
+            pkt_type != packet::Type::Initial &&
+            rand::rand_u8() & 1 == 0
+        {
+            let (_, mut pkt) = b.split_at(0)?;
+            pkt.as_mut()[0] &= !packet::FIXED_BIT;
+        }
+
         let hdr_trace = if log::max_level() == log::LevelFilter::Trace {
             Some(format!("{hdr:?}"))
         } else {
@@ -3469,11 +6881,33 @@ impl Connection {
 
         let payload_offset = b.off();
 
-        let cwnd_available =
-            path.recovery.cwnd_available().saturating_sub(overhead);
+        let cwnd_available = if ignore_cwnd {
+            usize::MAX
+        } else {
+            path.recovery.cwnd_available().saturating_sub(overhead)
+        };
+
+        // A PMTU probe only contains a PING frame, padded to the probed size.
+        if let Some(size) = pmtu_probe {
+            let frame = frame::Frame::Ping {
+                mtu_probe: Some(size),
+            };
+
+            if push_frame_to_pkt!(b, frames, frame, left) {
+                ack_eliciting = true;
+                in_flight = true;
+            }
+
+            let frame = frame::Frame::Padding { len: left };
+
+            if push_frame_to_pkt!(b, frames, frame, left) {
+                path.pmtud.on_probe_sent(size);
+            }
+        }
 
         let left_before_packing_ack_frame = left;
 
+        #[cfg(feature = "plugins")]
         for f in registrations
             .iter()
             .filter_map(|r| {
@@ -3483,11 +6917,13 @@ impl Connection {
                     None
                 }
             })
-            .filter(|f| f.send_order() == FrameSendOrder::First)
+            .filter(|f| {
+                f.send_order() == FrameSendOrder::First &&
+                    !deterministic_frame_order
+            })
         {
             let ty = f.get_type();
-            if self.should_send_frame(ty, pkt_type, epoch, is_closing, left, now)
-            {
+            if self.should_send_frame(ty, pkt_type, epoch, is_closing, left) {
                 let frame = match self.prepare_frame(ty, epoch, left) {
                     Ok(f) => f,
                     Err(Error::Done) => continue,
@@ -3521,22 +6957,21 @@ impl Connection {
             epoch,
             is_closing,
             left,
-            now,
             send_pid,
             ack_elicit_required,
         ) {
             let pkt_space = &mut self.pkt_num_spaces[epoch];
 
-            let ack_delay = pkt_space.largest_rx_pkt_time.elapsed();
+            let ack_delay_duration = pkt_space.largest_rx_pkt_time.elapsed();
 
-            let ack_delay = ack_delay.as_micros() as u64 /
+            let ack_delay = ack_delay_duration.as_micros() as u64 /
                 2_u64
                     .pow(self.local_transport_params.ack_delay_exponent as u32);
 
             let frame = frame::Frame::ACK {
                 ack_delay,
                 ranges: pkt_space.recv_pkt_need_ack.clone(),
-                ecn_counts: None, // sending ECN is not supported at this time
+                ecn_counts: self.ecn.recv_counts(epoch),
             };
 
             // When a PING frame needs to be sent, avoid sending the ACK if
@@ -3549,6 +6984,45 @@ impl Connection {
                 // available cwnd.
                 if push_frame_to_pkt!(b, frames, frame, left) {
                     pkt_space.ack_elicited = false;
+
+                    if let Some(ku) = pkt_space.key_update.as_mut() {
+                        ku.ack_sent = true;
+                    }
+
+                    if epoch == packet::Epoch::Application {
+                        self.ack_delay_sent.on_sample(ack_delay_duration);
+
+                        self.ack_freq_recv.on_ack_sent();
+                    }
+                }
+            }
+        }
+
+        // Create ACK_MP frames for the packet number spaces of other paths.
+        if self.multipath && pkt_type == packet::Type::Short && !is_closing {
+            for (&space_identifier, space) in self.mp_recv_spaces.iter_mut() {
+                if space.recv_pkt_need_ack.len() == 0 ||
+                    !(space.ack_elicited || ack_elicit_required)
+                {
+                    continue;
+                }
+
+                let ack_delay_duration = space.largest_rx_pkt_time.elapsed();
+
+                let ack_delay = ack_delay_duration.as_micros() as u64 /
+                    2_u64.pow(
+                        self.local_transport_params.ack_delay_exponent as u32,
+                    );
+
+                let frame = frame::Frame::MpAck {
+                    space_identifier,
+                    ack_delay,
+                    ranges: space.recv_pkt_need_ack.clone(),
+                    ecn_counts: None,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    space.ack_elicited = false;
                 }
             }
         }
@@ -3560,6 +7034,7 @@ impl Connection {
             cwnd_available.saturating_sub(left_before_packing_ack_frame - left),
         );
 
+        #[cfg(feature = "plugins")]
         for f in registrations
             .iter()
             .filter_map(|r| {
@@ -3569,11 +7044,18 @@ impl Connection {
                     None
                 }
             })
-            .filter(|f| f.send_order() == FrameSendOrder::AfterACK)
+            .filter(|f| match f.send_order() {
+                FrameSendOrder::AfterACK => true,
+
+                // Frames can't come before ACK frames with a deterministic
+                // order.
+                FrameSendOrder::First => deterministic_frame_order,
+
+                _ => false,
+            })
         {
             let ty = f.get_type();
-            if self.should_send_frame(ty, pkt_type, epoch, is_closing, left, now)
-            {
+            if self.should_send_frame(ty, pkt_type, epoch, is_closing, left) {
                 let frame = match self.prepare_frame(ty, epoch, left) {
                     Ok(f) => f,
                     Err(Error::Done) => continue,
@@ -3595,6 +7077,76 @@ impl Connection {
             }
         }
 
+        // Create PATH_ABANDON and PATH_STATUS frames. These can be sent on any
+        // active path.
+        if self.multipath &&
+            pkt_type == packet::Type::Short &&
+            !is_closing &&
+            self.paths.get(send_pid)?.active()
+        {
+            for (_, p) in self.paths.iter_mut() {
+                let dcid_seq_num = match p.active_scid_seq {
+                    Some(v) => v,
+
+                    None => continue,
+                };
+
+                if let Some((error_code, reason)) = p.pop_abandon_to_send() {
+                    let frame = frame::Frame::PathAbandon {
+                        dcid_seq_num,
+                        error_code,
+                        reason: reason.clone(),
+                    };
+
+                    if push_frame_to_pkt!(b, frames, frame, left) {
+                        ack_eliciting = true;
+                        in_flight = true;
+                    } else {
+                        // Try again with the next packet.
+                        p.abandon(error_code, reason);
+                    }
+                }
+
+                if let Some((seq_num, status)) = p.local_status_to_send() {
+                    let frame = frame::Frame::PathStatus {
+                        dcid_seq_num,
+                        seq_num,
+                        status: status.to_wire(),
+                    };
+
+                    if push_frame_to_pkt!(b, frames, frame, left) {
+                        p.on_local_status_sent(seq_num, false);
+
+                        ack_eliciting = true;
+                        in_flight = true;
+                    }
+                }
+            }
+        }
+
+        // Create ACK_FREQUENCY frame.
+        if self.ack_frequency && pkt_type == packet::Type::Short && !is_closing {
+            self.update_ack_frequency(send_pid)?;
+
+            if let Some((seq_num, params)) = self.ack_freq_send.pop_to_send() {
+                let frame = frame::Frame::AckFrequency {
+                    seq_num,
+                    ack_eliciting_threshold: params.ack_eliciting_threshold,
+                    request_max_ack_delay: params.max_ack_delay.as_micros()
+                        as u64,
+                    reordering_threshold: params.reordering_threshold,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    ack_eliciting = true;
+                    in_flight = true;
+                } else {
+                    // Try again with the next packet.
+                    self.ack_freq_send.on_lost(seq_num);
+                }
+            }
+        }
+
         let path = self.paths.get_mut(send_pid)?;
         let flow_control = &mut self.flow_control;
         let pkt_space = &mut self.pkt_num_spaces[epoch];
@@ -3667,6 +7219,20 @@ impl Connection {
                 }
             }
 
+            // Create NEW_TOKEN frame.
+            if let Some(token) = &self.new_token {
+                let frame = frame::Frame::NewToken {
+                    token: token.clone(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.new_token = None;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
             // Create MAX_STREAMS_BIDI frame.
             if self.streams.should_update_max_streams_bidi() {
                 let frame = frame::Frame::MaxStreamsBidi {
@@ -3878,7 +7444,7 @@ impl Connection {
                     // Create ConnectionClose frame.
                     let frame = frame::Frame::ConnectionClose {
                         error_code: conn_err.error_code,
-                        frame_type: 0,
+                        frame_type: conn_err.frame_type,
                         reason: conn_err.reason.clone(),
                     };
 
@@ -3983,6 +7549,10 @@ impl Connection {
             do_dgram
         {
             if let Some(max_dgram_payload) = max_dgram_len {
+                // Drop stale DATAGRAMs instead of sending them.
+                self.dgram_expired_count +=
+                    self.dgram_send_queue.purge_expired(now);
+
                 while let Some(len) = self.dgram_send_queue.peek_front_len() {
                     let hdr_off = b.off();
                     let hdr_len = 1 + // frame type
@@ -4012,7 +7582,7 @@ impl Connection {
                                     b.split_at(hdr_off + hdr_len)?;
 
                                 dgram_payload.as_mut()[..len]
-                                    .copy_from_slice(&data);
+                                    .copy_from_slice(data.as_ref());
 
                                 // Encode the frame's header.
                                 //
@@ -4060,7 +7630,25 @@ impl Connection {
             path.active() &&
             !dgram_emitted
         {
+            // Streams pinned to another path, put back in the queue once a
+            // stream was selected for this packet.
+            let mut pinned: SmallVec<[(u64, u8, bool); 4]> = SmallVec::new();
+
             while let Some(stream_id) = self.streams.peek_flushable() {
+                if self.stream_pinned_to_other_path(stream_id, send_pid) {
+                    self.streams.remove_flushable();
+
+                    if let Some(stream) = self.streams.get(stream_id) {
+                        pinned.push((
+                            stream_id,
+                            stream.urgency,
+                            stream.incremental,
+                        ));
+                    }
+
+                    continue;
+                }
+
                 let stream = match self.streams.get_mut(stream_id) {
                     // Avoid sending frames for streams that were already stopped.
                     //
@@ -4148,8 +7736,13 @@ impl Connection {
 
                 break;
             }
+
+            for (stream_id, urgency, incremental) in pinned {
+                self.streams.push_flushable(stream_id, urgency, incremental);
+            }
         }
 
+        #[cfg(feature = "plugins")]
         for f in registrations
             .iter()
             .filter_map(|r| {
@@ -4162,8 +7755,7 @@ impl Connection {
             .filter(|f| f.send_order() == FrameSendOrder::End)
         {
             let ty = f.get_type();
-            if self.should_send_frame(ty, pkt_type, epoch, is_closing, left, now)
-            {
+            if self.should_send_frame(ty, pkt_type, epoch, is_closing, left) {
                 let frame = match self.prepare_frame(ty, epoch, left) {
                     Ok(f) => f,
                     Err(Error::Done) => continue,
@@ -4184,8 +7776,9 @@ impl Connection {
             }
         }
 
-        // Alternate trying to send DATAGRAMs next time.
-        self.emit_dgram = !dgram_emitted;
+        // Alternate trying to send DATAGRAMs next time, unless the order of
+        // frames is fixed.
+        self.emit_dgram = !dgram_emitted || self.deterministic_frame_order;
 
         // Rust's borrowing rules.
         let path = self.paths.get_mut(send_pid)?;
@@ -4196,12 +7789,22 @@ impl Connection {
         // - if we've sent too many non ack-eliciting packets without having
         // sent an ACK eliciting one; OR
         // - the application requested an ack-eliciting frame be sent.
+        //
+        // With the ACK frequency extension, PTO probes use an IMMEDIATE_ACK
+        // frame instead, so that the peer doesn't delay its ACK.
         if (ack_elicit_required || path.needs_ack_eliciting) &&
             !ack_eliciting &&
             left >= 1 &&
             !is_closing
         {
-            let frame = frame::Frame::Ping;
+            let frame = if self.ack_frequency &&
+                ack_elicit_required &&
+                pkt_type == packet::Type::Short
+            {
+                frame::Frame::ImmediateAck
+            } else {
+                frame::Frame::Ping { mtu_probe: None }
+            };
 
             if push_frame_to_pkt!(b, frames, frame, left) {
                 ack_eliciting = true;
@@ -4241,6 +7844,23 @@ impl Connection {
             }
         }
 
+        // Pad ack-eliciting 1-RTT packets to the configured length, if any.
+        // ACK-only packets are left alone, as padding would make them count
+        // as in flight even though the peer doesn't acknowledge them.
+        if pkt_type == packet::Type::Short && ack_eliciting && left >= 1 {
+            let pkt_len = b.off() + crypto_overhead;
+            let pad_len = cmp::min(
+                self.short_packet_padding.padded_len(pkt_len) - pkt_len,
+                left,
+            );
+
+            if pad_len > 0 {
+                let frame = frame::Frame::Padding { len: pad_len };
+
+                push_frame_to_pkt!(b, frames, frame, left);
+            }
+        }
+
         // Pad payload so that it's always at least 4 bytes.
         if b.off() - payload_offset < PAYLOAD_MIN_LEN {
             let payload_len = b.off() - payload_offset;
@@ -4322,13 +7942,50 @@ impl Connection {
             }
         });
 
+        if !redundant_pids.is_empty() {
+            let affinity = &self.stream_path_affinity;
+
+            let ranges: Vec<(u64, u64, usize, bool)> = frames
+                .iter()
+                .filter_map(|f| match *f {
+                    frame::Frame::StreamHeader {
+                        stream_id,
+                        offset,
+                        length,
+                        fin,
+                    } => Some((stream_id, offset, length, fin)),
+
+                    _ => None,
+                })
+                .filter(|(stream_id, ..)| !affinity.contains_key(stream_id))
+                .collect();
+
+            if !ranges.is_empty() {
+                for pid in redundant_pids {
+                    let queued = self.redundant_streams.entry(pid).or_default();
+
+                    for range in &ranges {
+                        if !queued.contains(range) {
+                            queued.push(*range);
+                        }
+                    }
+
+                    if queued.len() > MAX_REDUNDANT_RANGES {
+                        let excess = queued.len() - MAX_REDUNDANT_RANGES;
+                        queued.drain(..excess);
+                    }
+                }
+            }
+        }
+
         let aead = match pkt_space.crypto_seal {
             Some(ref v) => v,
             None => return Err(Error::InvalidState),
         };
 
-        let written = packet::encrypt_pkt(
+        let written = packet::encrypt_pkt_on_path(
             &mut b,
+            mp_space_id as u32,
             pn,
             pn_len,
             payload_len,
@@ -4337,6 +7994,10 @@ impl Connection {
             aead,
         )?;
 
+        let ecn = self.ecn.codepoint();
+
+        self.ecn.on_packet_sent(ecn);
+
         let sent_pkt = recovery::Sent {
             pkt_num: pn,
             frames,
@@ -4351,13 +8012,18 @@ impl Connection {
             first_sent_time: now,
             is_app_limited: false,
             has_data,
+            ecn_marked: ecn == EcnCodepoint::Ect0,
         };
 
         if in_flight && is_app_limited {
             path.recovery.delivery_rate_update_app_limited(true);
         }
 
-        pkt_space.next_pkt_num += 1;
+        if mp_space_id == 0 {
+            pkt_space.next_pkt_num += 1;
+        } else {
+            *self.mp_next_pkt_nums.entry(mp_space_id).or_insert(0) += 1;
+        }
 
         let handshake_status = recovery::HandshakeStatus {
             has_handshake_keys: self.pkt_num_spaces[packet::Epoch::Handshake]
@@ -4390,6 +8056,16 @@ impl Connection {
         path.sent_count += 1;
         path.sent_bytes += written as u64;
 
+        if epoch == packet::Epoch::Application {
+            self.key_phase_sent_count += 1;
+            self.key_phase_sent_bytes += written as u64;
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.incr_counter(Counter::PacketsSent, 1);
+            metrics.incr_counter(Counter::BytesSent, written as u64);
+        }
+
         if self.dgram_send_queue.byte_size() > path.recovery.cwnd_available() {
             path.recovery.update_app_limited(false);
         }
@@ -4409,6 +8085,10 @@ impl Connection {
             }
         }
 
+        if ack_eliciting {
+            self.reset_keepalive_timer(now);
+        }
+
         if ack_eliciting {
             self.ack_eliciting_sent = true;
         }
@@ -4486,6 +8166,44 @@ impl Connection {
     pub fn stream_recv(
         &mut self, stream_id: u64, out: &mut [u8],
     ) -> Result<(usize, bool)> {
+        self.stream_recv_internal(stream_id, |recv| {
+            recv.emit(out).map(|(read, fin)| (read, read, fin))
+        })
+    }
+
+    /// Reads the next contiguous chunk of data from a stream, without copying
+    /// it.
+    ///
+    /// The returned [`StreamBuf`] references the buffer the data was stored
+    /// in when it was received, allocated by the factory set with
+    /// [`set_buf_factory()`], if any.
+    ///
+    /// On success the chunk and a flag indicating the fin state is returned
+    /// as a tuple, or [`Done`] if there is no data to read.
+    ///
+    /// [`StreamBuf`]: struct.StreamBuf.html
+    /// [`set_buf_factory()`]: struct.Config.html#method.set_buf_factory
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_recv_buf(
+        &mut self, stream_id: u64,
+    ) -> Result<(StreamBuf, bool)> {
+        self.stream_recv_internal(stream_id, |recv| {
+            recv.emit_buf().map(|(buf, fin)| {
+                let read = buf.len();
+
+                (buf, read, fin)
+            })
+        })
+    }
+
+    /// Reads data from a stream with `emit`, which returns the data read,
+    /// its length and the fin state.
+    fn stream_recv_internal<T, F>(
+        &mut self, stream_id: u64, emit: F,
+    ) -> Result<(T, bool)>
+    where
+        F: FnOnce(&mut stream::RecvBuf) -> Result<(T, usize, bool)>,
+    {
         // We can't read on our own unidirectional streams.
         if !stream::is_bidi(stream_id) &&
             stream::is_local(stream_id, self.is_server)
@@ -4507,7 +8225,7 @@ impl Connection {
         #[cfg(feature = "qlog")]
         let offset = stream.recv.off_front();
 
-        let (read, fin) = match stream.recv.emit(out) {
+        let (out, read, fin) = match emit(&mut stream.recv) {
             Ok(v) => v,
 
             Err(e) => {
@@ -4526,7 +8244,7 @@ impl Connection {
 
         self.flow_control.add_consumed(read as u64);
 
-        let readable = stream.is_readable();
+        let readable = stream.is_readable_lowat();
 
         let complete = stream.is_complete();
 
@@ -4560,7 +8278,55 @@ impl Connection {
             self.almost_full = true;
         }
 
-        Ok((read, fin))
+        Ok((out, fin))
+    }
+
+    /// Reads contiguous data from a stream into the provided slices.
+    ///
+    /// This is the same as [`stream_recv()`], but fills each slice in order
+    /// before moving to the next one, and stops as soon as there is no more
+    /// data to read or the stream is finished.
+    ///
+    /// On success the total amount of bytes read and a flag indicating the
+    /// fin state is returned as a tuple, or [`Done`] if there is no data to
+    /// read.
+    ///
+    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_recv_vectored(
+        &mut self, stream_id: u64, bufs: &mut [std::io::IoSliceMut],
+    ) -> Result<(usize, bool)> {
+        let mut total = 0;
+
+        for buf in bufs.iter_mut() {
+            if buf.is_empty() {
+                continue;
+            }
+
+            let (read, fin) = match self.stream_recv(stream_id, buf) {
+                Ok(v) => v,
+
+                Err(Error::Done) if total > 0 => break,
+
+                Err(e) => return Err(e),
+            };
+
+            total += read;
+
+            if fin {
+                return Ok((total, true));
+            }
+
+            if read < buf.len() {
+                break;
+            }
+        }
+
+        if total == 0 {
+            return Err(Error::Done);
+        }
+
+        Ok((total, false))
     }
 
     /// Writes data to a stream.
@@ -4613,16 +8379,328 @@ impl Connection {
     pub fn stream_send(
         &mut self, stream_id: u64, buf: &[u8], fin: bool,
     ) -> Result<usize> {
-        // We can't write on the peer's unidirectional streams.
-        if !stream::is_bidi(stream_id) &&
-            !stream::is_local(stream_id, self.is_server)
-        {
+        if self.stream_readers.contains_key(&stream_id) {
             return Err(Error::InvalidStreamState(stream_id));
         }
 
-        // Mark the connection as blocked if the connection-level flow control
-        // limit doesn't let us buffer all the data.
-        //
+        self.stream_send_internal(stream_id, buf, None, fin)
+    }
+
+    /// Writes data from the provided slices to a stream.
+    ///
+    /// This is the same as [`stream_send()`], but writes the data of each
+    /// slice in order, as if they were a single contiguous buffer. The fin
+    /// flag is only set once all of the data was written.
+    ///
+    /// On success the total number of bytes written is returned, or [`Done`]
+    /// if no data was written.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_send_vectored(
+        &mut self, stream_id: u64, bufs: &[std::io::IoSlice], fin: bool,
+    ) -> Result<usize> {
+        if self.stream_readers.contains_key(&stream_id) {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        let len: usize = bufs.iter().map(|b| b.len()).sum();
+
+        let mut written = 0;
+
+        for buf in bufs.iter().filter(|b| !b.is_empty()) {
+            let last = written + buf.len() == len;
+
+            let sent = match self.stream_send_internal(
+                stream_id,
+                buf,
+                None,
+                fin && last,
+            ) {
+                Ok(v) => v,
+
+                Err(Error::Done) if written > 0 => break,
+
+                Err(e) => return Err(e),
+            };
+
+            written += sent;
+
+            if sent < buf.len() {
+                break;
+            }
+        }
+
+        // Send a 0-length FIN STREAM frame when there is no data to write.
+        if len == 0 {
+            return self.stream_send_internal(stream_id, b"", None, fin);
+        }
+
+        Ok(written)
+    }
+
+    /// Finishes the sending side of a stream, without writing more data.
+    ///
+    /// This is the same as calling [`stream_send()`] with an empty buffer and
+    /// the fin flag set, and can be used to half-close a stream that was never
+    /// written to. The FIN is sent along with the data still buffered in the
+    /// stream, or in a STREAM frame without data if all of it was already
+    /// sent.
+    ///
+    /// Finishing a stream that was already finished has no effect, and the
+    /// [`Done`] error is returned if the stream was already collected.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_finish(&mut self, stream_id: u64) -> Result<()> {
+        if self.stream_readers.contains_key(&stream_id) {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        self.stream_send_internal(stream_id, b"", None, true)?;
+
+        Ok(())
+    }
+
+    /// Writes the data of a reader to a stream.
+    ///
+    /// Instead of buffering all of the data up front, the stream keeps the
+    /// reader and pulls data from it whenever [`send()`] is called, up to
+    /// what flow control and the congestion window allow, and without
+    /// buffering more than the configured [`set_stream_read_ahead()`] bytes
+    /// that have not been sent yet. This allows sending large objects, such
+    /// as files, without holding them in memory.
+    ///
+    /// The stream is finished once the reader reaches its end. If reading
+    /// fails, the stream is reset with error code `0`. Readers returning
+    /// [`WouldBlock`] are read again at the next call to [`send()`].
+    ///
+    /// Until the reader is done, writing more data on the stream with
+    /// [`stream_send()`] or attaching another reader returns the
+    /// [`InvalidStreamState`] error.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`set_stream_read_ahead()`]:
+    /// struct.Config.html#method.set_stream_read_ahead
+    /// [`WouldBlock`]:
+    /// https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.WouldBlock
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let stream_id = 0;
+    /// let file = std::fs::File::open("index.html").unwrap();
+    /// conn.stream_send_reader(stream_id, file)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_send_reader<R>(
+        &mut self, stream_id: u64, reader: R,
+    ) -> Result<()>
+    where
+        R: std::io::Read + Send + Sync + 'static,
+    {
+        if self.stream_readers.contains_key(&stream_id) {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        // Make sure the stream can be written to before keeping the reader.
+        match self.stream_send_internal(stream_id, b"", None, false) {
+            Ok(_) | Err(Error::Done) => (),
+
+            Err(e) => return Err(e),
+        }
+
+        self.stream_readers.insert(stream_id, Box::new(reader));
+
+        self.fill_stream_reader(stream_id);
+
+        Ok(())
+    }
+
+    /// Returns true if the stream's data is still being pulled from the
+    /// reader passed to [`stream_send_reader()`].
+    ///
+    /// [`stream_send_reader()`]: struct.Connection.html#method.stream_send_reader
+    pub fn stream_reader_pending(&self, stream_id: u64) -> bool {
+        self.stream_readers.contains_key(&stream_id)
+    }
+
+    /// Writes data to a stream from a shared buffer.
+    ///
+    /// This is the same as [`stream_send()`], but writes the data of `buf`
+    /// starting at `start`, and the stream keeps a reference to `buf` instead
+    /// of copying its data. This allows sending the same payload on many
+    /// connections at the cost of a single copy, see [`Broadcast`].
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`Broadcast`]: struct.Broadcast.html
+    pub fn stream_send_shared(
+        &mut self, stream_id: u64, buf: &Arc<[u8]>, start: usize, fin: bool,
+    ) -> Result<usize> {
+        if start > buf.len() {
+            return Err(Error::BufferTooShort);
+        }
+
+        if self.stream_readers.contains_key(&stream_id) {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        let data = stream::BufData::Shared(buf.clone());
+
+        let shared = Some((&data, start));
+
+        self.stream_send_internal(stream_id, &buf[start..], shared, fin)
+    }
+
+    /// Writes data to a stream from a buffer owned by the application.
+    ///
+    /// This is the same as [`stream_send()`], but the stream keeps `buf`
+    /// alive until its data is acknowledged by the peer instead of copying
+    /// it, so that large static responses can be sent without being copied.
+    ///
+    /// Like with [`stream_send()`], the number of written bytes can be lower
+    /// than the length of `buf` when the stream doesn't have enough capacity,
+    /// in which case the rest of the data is not sent. Applications should
+    /// then retry with the unwritten part of the data once the stream is
+    /// writable again, which doesn't require copying it when `buf` can be
+    /// sliced cheaply, e.g. with `bytes::Bytes`.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let stream_id = 0;
+    /// let body = vec![0; 1_000_000];
+    /// let written = conn.stream_send_zc(stream_id, body, true)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_send_zc<B: Buf>(
+        &mut self, stream_id: u64, buf: B, fin: bool,
+    ) -> Result<usize> {
+        if self.stream_readers.contains_key(&stream_id) {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        let data = stream::BufData::App(Arc::new(buf));
+
+        let shared = Some((&data, 0));
+
+        self.stream_send_internal(stream_id, data.as_slice(), shared, fin)
+    }
+
+    /// Reads data from the readers of all streams that have one.
+    fn fill_stream_readers(&mut self) {
+        if self.stream_readers.is_empty() {
+            return;
+        }
+
+        let ids: Vec<u64> = self.stream_readers.keys().copied().collect();
+
+        for stream_id in ids {
+            self.fill_stream_reader(stream_id);
+        }
+    }
+
+    /// Reads data from a stream's reader, as much as the stream can accept
+    /// within the read ahead limit.
+    ///
+    /// The reader is dropped once it reached its end, failed, or the stream
+    /// can't be written to anymore.
+    fn fill_stream_reader(&mut self, stream_id: u64) {
+        let mut reader = match self.stream_readers.remove(&stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        loop {
+            let (cap, unsent) = match self.streams.get(stream_id) {
+                Some(stream) => match stream.send.cap() {
+                    Ok(cap) => (
+                        cmp::min(cap, self.tx_cap),
+                        (stream.send.off_back() - stream.send.off_front())
+                            as usize,
+                    ),
+
+                    // The peer stopped the stream.
+                    Err(_) => return,
+                },
+
+                // The stream was collected.
+                None => return,
+            };
+
+            if cap == 0 || unsent >= self.stream_read_ahead {
+                break;
+            }
+
+            let len = cmp::min(cap, self.stream_read_ahead - unsent);
+
+            let mut buf = vec![0; len];
+
+            let read = match reader.read(&mut buf) {
+                Ok(v) => v,
+
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+
+                Err(e) => {
+                    trace!(
+                        "{} failed to read data of stream {}: {:?}",
+                        self.trace_id,
+                        stream_id,
+                        e
+                    );
+
+                    self.stream_shutdown(stream_id, Shutdown::Write, 0).ok();
+
+                    return;
+                },
+            };
+
+            let fin = read == 0;
+
+            match self.stream_send_internal(stream_id, &buf[..read], None, fin) {
+                // The stream has enough capacity for all of the data read.
+                Ok(_) if fin => return,
+
+                Ok(_) => (),
+
+                Err(_) => return,
+            }
+        }
+
+        self.stream_readers.insert(stream_id, reader);
+    }
+
+    fn stream_send_internal(
+        &mut self, stream_id: u64, buf: &[u8],
+        shared: Option<(&stream::BufData, usize)>, fin: bool,
+    ) -> Result<usize> {
+        // We can't write on the peer's unidirectional streams.
+        if !stream::is_bidi(stream_id) &&
+            !stream::is_local(stream_id, self.is_server)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        // Mark the connection as blocked if the connection-level flow control
+        // limit doesn't let us buffer all the data.
+        //
         // Note that this is separate from "send capacity" as that also takes
         // congestion control into consideration.
         if self.max_tx_data - self.tx_data < buf.len() as u64 {
@@ -4654,7 +8732,16 @@ impl Connection {
 
         let was_flushable = stream.is_flushable();
 
-        let sent = match stream.send.write(buf, fin) {
+        let was_fin = stream.send.is_fin();
+
+        let written = match shared {
+            Some((shared, start)) =>
+                stream.send.write_data(shared, start, buf.len(), fin),
+
+            None => stream.send.write(buf, fin),
+        };
+
+        let sent = match written {
             Ok(v) => v,
 
             Err(e) => {
@@ -4670,9 +8757,15 @@ impl Connection {
 
         let writable = stream.is_writable();
 
-        let empty_fin = buf.is_empty() && fin;
+        // A FIN without data only needs its own frame when it wasn't written
+        // already, and there is no buffered data left to carry it.
+        let empty_fin = buf.is_empty() &&
+            fin &&
+            !was_fin &&
+            stream.send.off_front() == stream.send.off_back();
 
-        if sent < buf.len() {
+        // Only flow control blocks the stream, not a full send buffer.
+        if sent < buf.len() && stream.send.off_back() == stream.send.max_off() {
             let max_off = stream.send.max_off();
 
             if stream.send.blocked_at() != Some(max_off) {
@@ -4728,8 +8821,14 @@ impl Connection {
     /// on the wire (streams with lower priority are sent first). Streams are
     /// created with a default priority of `127`.
     ///
+    /// Among streams with the same urgency, non-incremental streams are sent
+    /// first, one at a time in order of stream ID. The data of incremental
+    /// streams is then interleaved in a round-robin fashion, one STREAM frame
+    /// per stream at a time.
+    ///
     /// The target stream is created if it did not exist before calling this
-    /// method.
+    /// method. Changing the priority of a stream that has data waiting to be
+    /// sent takes effect immediately.
     pub fn stream_priority(
         &mut self, stream_id: u64, urgency: u8, incremental: bool,
     ) -> Result<()> {
@@ -4747,10 +8846,82 @@ impl Connection {
             return Ok(());
         }
 
+        let prev_urgency = stream.urgency;
+        let prev_incremental = stream.incremental;
+
         stream.urgency = urgency;
         stream.incremental = incremental;
 
-        // TODO: reprioritization
+        // Move the stream to the queue matching its new priority, if it was
+        // waiting to be sent.
+        if self.streams.unqueue_flushable(
+            stream_id,
+            prev_urgency,
+            prev_incremental,
+        ) {
+            self.streams.push_flushable(stream_id, urgency, incremental);
+        }
+
+        Ok(())
+    }
+
+    /// Restricts the data of a stream to the path between `local_addr` and
+    /// `peer_addr` of the given `path`.
+    ///
+    /// With the multipath extension, the data of the stream is then only sent
+    /// on that path, for example to keep some of the traffic away from a
+    /// metered path. If the path is not active anymore, the data of the stream
+    /// is sent on any path. Passing `None` removes the restriction.
+    ///
+    /// This requires the multipath extension to be negotiated, otherwise this
+    /// call returns [`InvalidState`]. If the path does not exist,
+    /// [`InvalidState`] is returned as well.
+    ///
+    /// The target stream is created if it did not exist before calling this
+    /// method.
+    ///
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let stream_id = 0;
+    /// conn.stream_set_path_affinity(stream_id, Some((local, peer)))?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_set_path_affinity(
+        &mut self, stream_id: u64, path: Option<(SocketAddr, SocketAddr)>,
+    ) -> Result<()> {
+        if !self.multipath {
+            return Err(Error::InvalidState);
+        }
+
+        if let Some(addrs) = path {
+            if self.paths.path_id_from_addrs(&addrs).is_none() {
+                return Err(Error::InvalidState);
+            }
+        }
+
+        // Get existing stream or create a new one, but if the stream
+        // has already been closed and collected, ignore the affinity.
+        match self.get_or_create_stream(stream_id, true) {
+            Ok(_) => (),
+
+            Err(Error::Done) => return Ok(()),
+
+            Err(e) => return Err(e),
+        };
+
+        match path {
+            Some(addrs) => self.stream_path_affinity.insert(stream_id, addrs),
+
+            None => self.stream_path_affinity.remove(&stream_id),
+        };
 
         Ok(())
     }
@@ -4933,34 +9104,33 @@ impl Connection {
         None
     }
 
-    /// Returns true if the stream has enough send capacity.
+    /// Returns true if the stream has enough data to read.
     ///
-    /// When `len` more bytes can be buffered into the given stream's send
-    /// buffer, `true` will be returned, `false` otherwise.
+    /// When at least `len` bytes can be read from the given stream, or when
+    /// less data is left to be read but the stream was finished or reset by
+    /// the peer, `true` will be returned, `false` otherwise.
     ///
-    /// In the latter case, if the additional data can't be buffered due to
-    /// flow control limits, the peer will also be notified, and a "low send
-    /// watermark" will be set for the stream, such that it is not going to be
-    /// reported as writable again by [`stream_writable_next()`] until its send
-    /// capacity reaches `len`.
+    /// In addition, `len` is set as the "low receive watermark" of the stream,
+    /// such that it is not going to be reported by [`readable()`] and
+    /// [`stream_readable_next()`] until that much data can be read. This
+    /// avoids waking up the application for every small amount of data that
+    /// is received. Passing a `len` of `1` restores the default behavior.
     ///
     /// If the specified stream doesn't exist (including when it has already
     /// been completed and closed), the [`InvalidStreamState`] error will be
     /// returned.
     ///
-    /// In addition, if the peer has signalled that it doesn't want to receive
-    /// any more data from this stream by sending the `STOP_SENDING` frame, the
-    /// [`StreamStopped`] error will be returned.
-    ///
-    /// [`stream_writable_next()`]: struct.Connection.html#method.stream_writable_next
+    /// [`readable()`]: struct.Connection.html#method.readable
+    /// [`stream_readable_next()`]: struct.Connection.html#method.stream_readable_next
     /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
-    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
-    #[inline]
-    pub fn stream_writable(
+    pub fn stream_readable_threshold(
         &mut self, stream_id: u64, len: usize,
     ) -> Result<bool> {
-        if self.stream_capacity(stream_id)? >= len {
-            return Ok(true);
+        // We can't read on our own unidirectional streams.
+        if !stream::is_bidi(stream_id) &&
+            stream::is_local(stream_id, self.is_server)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
         }
 
         let stream = match self.streams.get_mut(stream_id) {
@@ -4969,13 +9139,58 @@ impl Connection {
             None => return Err(Error::InvalidStreamState(stream_id)),
         };
 
-        stream.send_lowat = cmp::max(1, len);
+        stream.recv_lowat = cmp::max(1, len);
 
-        if self.max_tx_data - self.tx_data < len as u64 {
-            self.blocked_limit = Some(self.max_tx_data);
-        }
+        let readable = stream.is_readable_lowat();
 
-        if stream.send.cap()? < len {
+        self.streams.mark_readable(stream_id, readable);
+
+        Ok(readable)
+    }
+
+    /// Returns true if the stream has enough send capacity.
+    ///
+    /// When `len` more bytes can be buffered into the given stream's send
+    /// buffer, `true` will be returned, `false` otherwise.
+    ///
+    /// In the latter case, if the additional data can't be buffered due to
+    /// flow control limits, the peer will also be notified, and a "low send
+    /// watermark" will be set for the stream, such that it is not going to be
+    /// reported as writable again by [`stream_writable_next()`] until its send
+    /// capacity reaches `len`.
+    ///
+    /// If the specified stream doesn't exist (including when it has already
+    /// been completed and closed), the [`InvalidStreamState`] error will be
+    /// returned.
+    ///
+    /// In addition, if the peer has signalled that it doesn't want to receive
+    /// any more data from this stream by sending the `STOP_SENDING` frame, the
+    /// [`StreamStopped`] error will be returned.
+    ///
+    /// [`stream_writable_next()`]: struct.Connection.html#method.stream_writable_next
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
+    #[inline]
+    pub fn stream_writable(
+        &mut self, stream_id: u64, len: usize,
+    ) -> Result<bool> {
+        if self.stream_capacity(stream_id)? >= len {
+            return Ok(true);
+        }
+
+        let stream = match self.streams.get_mut(stream_id) {
+            Some(v) => v,
+
+            None => return Err(Error::InvalidStreamState(stream_id)),
+        };
+
+        stream.send_lowat = cmp::max(1, len);
+
+        if self.max_tx_data - self.tx_data < len as u64 {
+            self.blocked_limit = Some(self.max_tx_data);
+        }
+
+        if stream.send.max_off() - stream.send.off_back() < len as u64 {
             let max_off = stream.send.max_off();
             if stream.send.blocked_at() != Some(max_off) {
                 stream.send.update_blocked_at(Some(max_off));
@@ -5110,11 +9325,16 @@ impl Connection {
     /// streams are only allowed to buffer outgoing data up to the amount that
     /// the peer allows to send.
     ///
+    /// Streams whose low send watermark was set with [`stream_writable()`]
+    /// are only included once their send capacity reaches it.
+    ///
     /// Note that the iterator will only include streams that were writable at
     /// the time the iterator itself was created (i.e. when `writable()` was
     /// called). To account for newly writable streams, the iterator needs to
     /// be created again.
     ///
+    /// [`stream_writable()`]: struct.Connection.html#method.stream_writable
+    ///
     /// ## Examples:
     ///
     /// ```no_run
@@ -5142,7 +9362,7 @@ impl Connection {
             return StreamIter::default();
         }
 
-        self.streams.writable()
+        self.streams.writable_lowat(self.tx_cap)
     }
 
     /// Returns the maximum possible size of egress UDP payloads.
@@ -5246,6 +9466,7 @@ impl Connection {
     /// }
     /// # Ok::<(), quiche::Error>(())
     /// ```
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
         match self.dgram_recv_queue.pop() {
@@ -5268,6 +9489,7 @@ impl Connection {
     /// `Vec<u8>` instead of copying into the provided buffer.
     ///
     /// [`dgram_recv()`]: struct.Connection.html#method.dgram_recv
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_recv_vec(&mut self) -> Result<Vec<u8>> {
         match self.dgram_recv_queue.pop() {
@@ -5290,6 +9512,7 @@ impl Connection {
     ///
     /// [`Done`]: enum.Error.html#variant.Done
     /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_recv_peek(&self, buf: &mut [u8], len: usize) -> Result<usize> {
         self.dgram_recv_queue.peek_front_bytes(buf, len)
@@ -5302,41 +9525,161 @@ impl Connection {
     }
 
     /// Returns the number of items in the DATAGRAM receive queue.
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_recv_queue_len(&self) -> usize {
         self.dgram_recv_queue.len()
     }
 
     /// Returns the total size of all items in the DATAGRAM receive queue.
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_recv_queue_byte_size(&self) -> usize {
         self.dgram_recv_queue.byte_size()
     }
 
     /// Returns the number of items in the DATAGRAM send queue.
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_send_queue_len(&self) -> usize {
         self.dgram_send_queue.len()
     }
 
     /// Returns the total size of all items in the DATAGRAM send queue.
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_send_queue_byte_size(&self) -> usize {
         self.dgram_send_queue.byte_size()
     }
 
     /// Returns whether or not the DATAGRAM send queue is full.
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn is_dgram_send_queue_full(&self) -> bool {
         self.dgram_send_queue.is_full()
     }
 
+    /// Returns whether the DATAGRAM send queue became writable again.
+    ///
+    /// This returns `true` once after a DATAGRAM was rejected with [`Done`]
+    /// because the send queue was full, as soon as the queue drained to the
+    /// low watermark set with [`set_dgram_send_queue_low_watermark()`]. This
+    /// can be checked after sending packets, instead of polling
+    /// [`dgram_send()`] until it stops returning [`Done`].
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`set_dgram_send_queue_low_watermark()`]:
+    /// struct.Config.html#method.set_dgram_send_queue_low_watermark
+    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut out = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let (write, send_info) = conn.send(&mut out)?;
+    ///
+    /// socket.send_to(&out[..write], &send_info.to).unwrap();
+    ///
+    /// if conn.dgram_send_queue_writable() {
+    ///     // Resume queueing DATAGRAMs.
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[cfg(feature = "datagram")]
+    pub fn dgram_send_queue_writable(&mut self) -> bool {
+        if !self.dgram_send_blocked ||
+            self.dgram_send_queue.len() > self.dgram_send_low_watermark
+        {
+            return false;
+        }
+
+        self.dgram_send_blocked = false;
+
+        true
+    }
+
     /// Returns whether or not the DATAGRAM recv queue is full.
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn is_dgram_recv_queue_full(&self) -> bool {
         self.dgram_recv_queue.is_full()
     }
 
+    /// Returns the value of the given field of the DATAGRAM queues.
+    #[cfg(all(feature = "plugins", feature = "datagram"))]
+    pub(crate) fn dgram_get_field(
+        &self, field: dgram::DatagramField, now: time::Instant,
+    ) -> u64 {
+        let age = |age: Option<time::Duration>| {
+            age.map(|a| a.as_micros() as u64).unwrap_or(0)
+        };
+
+        match field {
+            dgram::DatagramField::SendQueueLen =>
+                self.dgram_send_queue.len() as u64,
+
+            dgram::DatagramField::SendQueueByteSize =>
+                self.dgram_send_queue.byte_size() as u64,
+
+            dgram::DatagramField::SendQueueFrontLen =>
+                self.dgram_send_queue.peek_front_len().unwrap_or(0) as u64,
+
+            dgram::DatagramField::SendQueueFrontAge =>
+                age(self.dgram_send_queue.peek_front_age(now)),
+
+            dgram::DatagramField::RecvQueueLen =>
+                self.dgram_recv_queue.len() as u64,
+
+            dgram::DatagramField::RecvQueueByteSize =>
+                self.dgram_recv_queue.byte_size() as u64,
+
+            dgram::DatagramField::RecvQueueFrontLen =>
+                self.dgram_recv_queue.peek_front_len().unwrap_or(0) as u64,
+
+            dgram::DatagramField::RecvQueueFrontAge =>
+                age(self.dgram_recv_queue.peek_front_age(now)),
+
+            dgram::DatagramField::SendQueueRequeue => 0,
+        }
+    }
+
+    /// Updates the given field of the DATAGRAM queues.
+    ///
+    /// Only the length of the queues can be reduced, which drops their oldest
+    /// DATAGRAMs, and DATAGRAMs can be moved to the back of the send queue.
+    /// [`InvalidState`] is returned for other fields.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    #[cfg(all(feature = "plugins", feature = "datagram"))]
+    pub(crate) fn dgram_set_field(
+        &mut self, field: dgram::DatagramField, v: u64,
+    ) -> Result<()> {
+        let v: usize = v.try_into().map_err(|_| Error::InvalidState)?;
+
+        match field {
+            dgram::DatagramField::SendQueueLen => {
+                self.dgram_send_queue.truncate_front(v);
+            },
+
+            dgram::DatagramField::RecvQueueLen => {
+                self.dgram_recv_queue.truncate_front(v);
+            },
+
+            dgram::DatagramField::SendQueueRequeue =>
+                self.dgram_send_queue.requeue_front(v),
+
+            _ => return Err(Error::InvalidState),
+        }
+
+        Ok(())
+    }
+
     /// Sends data in a DATAGRAM frame.
     ///
     /// [`Done`] is returned if no data was written.
@@ -5369,28 +9712,9 @@ impl Connection {
     /// conn.dgram_send(b"hello")?;
     /// # Ok::<(), quiche::Error>(())
     /// ```
+    #[cfg(feature = "datagram")]
     pub fn dgram_send(&mut self, buf: &[u8]) -> Result<()> {
-        let max_payload_len = match self.dgram_max_writable_len() {
-            Some(v) => v,
-
-            None => return Err(Error::InvalidState),
-        };
-
-        if buf.len() > max_payload_len {
-            return Err(Error::BufferTooShort);
-        }
-
-        self.dgram_send_queue.push(buf.to_vec())?;
-
-        let active_path = self.paths.get_active_mut()?;
-
-        if self.dgram_send_queue.byte_size() >
-            active_path.recovery.cwnd_available()
-        {
-            active_path.recovery.update_app_limited(false);
-        }
-
-        Ok(())
+        self.dgram_send_with(buf, dgram::DEFAULT_URGENCY, None)
     }
 
     /// Sends data in a DATAGRAM frame.
@@ -5399,18 +9723,98 @@ impl Connection {
     /// a slice.
     ///
     /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
+    #[cfg(feature = "datagram")]
     pub fn dgram_send_vec(&mut self, buf: Vec<u8>) -> Result<()> {
+        self.dgram_send_buf(
+            dgram::DatagramBuf::Owned(buf),
+            dgram::DEFAULT_URGENCY,
+            None,
+        )
+    }
+
+    /// Sends data in a DATAGRAM frame.
+    ///
+    /// This is the same as [`dgram_send()`] but takes a shared buffer, which
+    /// is queued without copying its data. This allows sending the same
+    /// payload on many connections at the cost of a single copy, see
+    /// [`Broadcast`].
+    ///
+    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
+    /// [`Broadcast`]: struct.Broadcast.html
+    #[cfg(feature = "datagram")]
+    pub fn dgram_send_shared(&mut self, buf: &Arc<[u8]>) -> Result<()> {
+        self.dgram_send_buf(
+            dgram::DatagramBuf::Shared(buf.clone()),
+            dgram::DEFAULT_URGENCY,
+            None,
+        )
+    }
+
+    /// Sends data in a DATAGRAM frame, with the given urgency and time to
+    /// live.
+    ///
+    /// This is the same as [`dgram_send()`], but queued DATAGRAMs are sent in
+    /// order of `urgency` (lower is better), then in the order they were
+    /// queued. The default urgency is 127.
+    ///
+    /// If `ttl` is set and the DATAGRAM is still queued after this duration,
+    /// it is dropped instead of being sent, so that stale data doesn't use the
+    /// congestion window. Dropped DATAGRAMs are reported in the connection's
+    /// [`Stats`].
+    ///
+    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
+    /// [`Stats`]: struct.Stats.html
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let ttl = std::time::Duration::from_millis(50);
+    /// conn.dgram_send_with(b"hello", 0, Some(ttl))?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[cfg(feature = "datagram")]
+    pub fn dgram_send_with(
+        &mut self, buf: &[u8], urgency: u8, ttl: Option<time::Duration>,
+    ) -> Result<()> {
+        self.dgram_send_buf(dgram::DatagramBuf::Owned(buf.to_vec()), urgency, ttl)
+    }
+
+    #[cfg(feature = "datagram")]
+    fn dgram_send_buf(
+        &mut self, buf: dgram::DatagramBuf, urgency: u8,
+        ttl: Option<time::Duration>,
+    ) -> Result<()> {
         let max_payload_len = match self.dgram_max_writable_len() {
             Some(v) => v,
 
             None => return Err(Error::InvalidState),
         };
 
-        if buf.len() > max_payload_len {
+        if buf.as_ref().len() > max_payload_len {
             return Err(Error::BufferTooShort);
         }
 
-        self.dgram_send_queue.push(buf)?;
+        if self.dgram_send_queue.is_full() {
+            self.plugin_dgram_send_queue_full(buf.as_ref().len());
+        }
+
+        let now = time::Instant::now();
+        let expiry = ttl.map(|ttl| now + ttl);
+
+        let res = self.dgram_send_queue.push_with(buf, urgency, expiry, now);
+
+        // The application will be notified once the queue drains.
+        if res.is_err() {
+            self.dgram_send_blocked = true;
+        }
+
+        res?;
 
         let active_path = self.paths.get_active_mut()?;
 
@@ -5439,6 +9843,7 @@ impl Connection {
     /// conn.dgram_purge_outgoing(&|d: &[u8]| -> bool { d[0] == 0 });
     /// # Ok::<(), quiche::Error>(())
     /// ```
+    #[cfg(feature = "datagram")]
     #[inline]
     pub fn dgram_purge_outgoing<F: Fn(&[u8]) -> bool>(&mut self, f: F) {
         self.dgram_send_queue.purge(f);
@@ -5507,7 +9912,7 @@ impl Connection {
     ///
     /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
     pub fn timeout_instant(&self) -> Option<time::Instant> {
-        if self.is_closed() {
+        if self.is_closed() || self.is_suspended() {
             return None;
         }
 
@@ -5526,7 +9931,28 @@ impl Connection {
                 .iter()
                 .filter_map(|(_, p)| p.recovery.loss_detection_timer())
                 .min();
-            let timers = [self.idle_timer, path_timer];
+            let key_update_timer = self.pkt_num_spaces
+                [packet::Epoch::Application]
+                .key_update
+                .as_ref()
+                .and_then(|ku| ku.timer);
+            #[cfg(feature = "plugins")]
+            let plugin_timer = self.plugin_timers.next_timeout();
+            #[cfg(not(feature = "plugins"))]
+            let plugin_timer = None;
+
+            let handshake_timer =
+                self.handshake_timer.filter(|_| !self.is_established());
+
+            let timers = [
+                self.idle_timer,
+                self.keepalive_timer,
+                handshake_timer,
+                path_timer,
+                plugin_timer,
+                key_update_timer,
+                self.ack_freq_recv.ack_timer(),
+            ];
 
             timers.iter().filter_map(|&x| x).min()
         }
@@ -5554,6 +9980,11 @@ impl Connection {
     ///
     /// If no timeout has occurred it does nothing.
     pub fn on_timeout(&mut self) {
+        // Timers are quiesced while the connection is suspended.
+        if self.is_suspended() {
+            return;
+        }
+
         let now = time::Instant::now();
 
         if let Some(draining_timer) = self.draining_timer {
@@ -5587,6 +10018,51 @@ impl Connection {
             }
         }
 
+        if let Some(timer) = self.handshake_timer {
+            if timer <= now && !self.is_established() {
+                trace!("{} handshake timeout expired", self.trace_id);
+
+                qlog_with!(self.qlog, q, {
+                    q.finish_log().ok();
+                });
+
+                self.closed = true;
+                self.handshake_timed_out = true;
+                return;
+            }
+        }
+
+        if let Some(timer) = self.keepalive_timer {
+            if timer <= now {
+                trace!("{} keep-alive timeout expired", self.trace_id);
+
+                // The timer is re-armed once the PING frame is sent.
+                self.keepalive_timer = None;
+
+                if let Ok(path) = self.paths.get_active_mut() {
+                    path.needs_ack_eliciting = true;
+                }
+            }
+        }
+
+        let app_space = &mut self.pkt_num_spaces[packet::Epoch::Application];
+
+        if let Some(timer) = app_space.key_update.as_ref().and_then(|ku| ku.timer)
+        {
+            if timer <= now {
+                trace!("{} key update timeout expired", self.trace_id);
+
+                // Discard the keys of the previous key phase.
+                app_space.key_update = None;
+            }
+        }
+
+        if self.ack_freq_recv.on_timeout(now) {
+            trace!("{} ack timeout expired", self.trace_id);
+
+            self.pkt_num_spaces[packet::Epoch::Application].ack_elicited = true;
+        }
+
         let handshake_status = self.handshake_status();
 
         for (_, p) in self.paths.iter_mut() {
@@ -5604,6 +10080,13 @@ impl Connection {
                     self.lost_count += lost_packets;
                     self.lost_bytes += lost_bytes as u64;
 
+                    if let Some(metrics) = &self.metrics {
+                        metrics.incr_counter(
+                            Counter::PacketsLost,
+                            lost_packets as u64,
+                        );
+                    }
+
                     qlog_with_type!(QLOG_METRICS, self.qlog, q, {
                         if let Some(ev_data) = p.recovery.maybe_qlog() {
                             q.add_event_data_with_instant(ev_data, now).ok();
@@ -5613,6 +10096,33 @@ impl Connection {
             }
         }
 
+        // Fire the timers requested by plugins.
+        #[cfg(feature = "plugins")]
+        for timer_id in self.plugin_timers.pop_expired(now) {
+            trace!("{} plugin timer {} expired", self.trace_id, timer_id);
+
+            if !self.plugin_call_allowed(now) {
+                continue;
+            }
+
+            let metrics = self.metrics.clone();
+
+            if let Some(ph) =
+                self.get_pluginizable_connection().map(|pc| pc.get_ph_mut())
+            {
+                let op = PluginOp::OnPluginTimeout(timer_id);
+
+                let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+                    plugin::guarded_call(&op, &[], || ph.call(&op, &[]))
+                });
+
+                self.plugin_result(res);
+            }
+
+            #[cfg(feature = "datagram")]
+            self.plugin_dgram_enqueue(now);
+        }
+
         // Notify timeout events to the application.
         self.paths.notify_failed_validations();
 
@@ -5631,6 +10141,82 @@ impl Connection {
         }
     }
 
+    /// Suspends the connection timers.
+    ///
+    /// This is meant to be called when the application is about to be put in
+    /// the background (e.g. on mobile platforms) and won't be able to process
+    /// timeout events for a while. Until [`resume()`] is called,
+    /// [`timeout()`] returns `None` and [`on_timeout()`] does nothing.
+    ///
+    /// The wall-clock time at which the connection was suspended is recorded.
+    /// Calling this method on an already suspended, draining or closed
+    /// connection has no effect.
+    ///
+    /// [`resume()`]: struct.Connection.html#method.resume
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    pub fn suspend(&mut self) {
+        if self.is_suspended() || self.is_closed() || self.is_draining() {
+            return;
+        }
+
+        trace!("{} connection suspended", self.trace_id);
+
+        self.suspended_at = Some(time::SystemTime::now());
+    }
+
+    /// Resumes a connection previously suspended with [`suspend()`].
+    ///
+    /// The idle timeout and the loss detection timers are recomputed from the
+    /// current time, so that the time spent in the background does not
+    /// trigger spurious timeouts. An ack-eliciting packet is also scheduled on
+    /// the active path to probe it, and the application should call
+    /// [`send()`] afterwards.
+    ///
+    /// Returns the wall-clock duration during which the connection was
+    /// suspended, or `None` if it was not suspended.
+    ///
+    /// [`suspend()`]: struct.Connection.html#method.suspend
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn resume(&mut self) -> Option<time::Duration> {
+        let suspended_at = self.suspended_at.take()?;
+
+        // The system clock may have gone backward while suspended.
+        let elapsed = suspended_at.elapsed().unwrap_or_default();
+
+        trace!("{} connection resumed after {:?}", self.trace_id, elapsed);
+
+        let now = time::Instant::now();
+
+        if self.idle_timer.is_some() {
+            self.idle_timer = self.idle_timeout().map(|t| now + t);
+        }
+
+        self.reset_keepalive_timer(now);
+
+        let handshake_status = self.handshake_status();
+
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery.on_resume(handshake_status, now);
+        }
+
+        if let Ok(path) = self.paths.get_active_mut() {
+            path.needs_ack_eliciting = true;
+        }
+
+        Some(elapsed)
+    }
+
+    /// Returns true if the connection is suspended.
+    ///
+    /// See [`suspend()`] for more details.
+    ///
+    /// [`suspend()`]: struct.Connection.html#method.suspend
+    #[inline]
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+    }
+
     /// Requests the stack to perform path validation of the proposed 4-tuple.
     ///
     /// Probing new paths requires spare Connection IDs at both the host and the
@@ -5699,6 +10285,13 @@ impl Connection {
     /// at both sides. If this requirement is not satisfied, this call returns
     /// [`OutOfIdentifiers`].
     ///
+    /// If the path is not validated yet, it is first probed with a
+    /// PATH_CHALLENGE frame, and the connection keeps using the current path
+    /// until the matching PATH_RESPONSE is received. Once the connection
+    /// switches to the new path, its congestion controller is reset as
+    /// described in RFC 9000 Section 9.4, and the Destination Connection ID of
+    /// the old path is retired.
+    ///
     /// Returns the Destination Connection ID associated to that migrated path.
     ///
     /// [`OutOfIdentifiers`]: enum.Error.html#OutOfIdentifiers
@@ -5718,6 +10311,8 @@ impl Connection {
 
             // If it is already active, do nothing.
             if path.active() {
+                self.pending_migration = None;
+
                 return path.active_dcid_seq.ok_or(Error::OutOfIdentifiers);
             }
 
@@ -5759,13 +10354,162 @@ impl Connection {
             (pid, dcid_seq)
         };
 
-        // Change the active path.
-        self.paths.set_active_path(pid)?;
+        // Non-probing packets keep using the current path until the peer
+        // proved it is reachable over the new one.
+        let path = self.paths.get_mut(pid)?;
+
+        if !path.validated() {
+            if !path.under_validation() {
+                path.request_validation();
+            }
+
+            self.pending_migration = Some(pid);
+
+            return Ok(dcid_seq);
+        }
+
+        self.pending_migration = None;
+
+        self.complete_migration(pid)?;
 
         Ok(dcid_seq)
     }
 
-    /// Provides additional source Connection IDs that the peer can use to reach
+    /// Opens an additional path between `local_addr` and `peer_addr`.
+    ///
+    /// This is only possible once the multipath extension has been negotiated
+    /// (see [`is_multipath()`]). Otherwise, this call returns
+    /// [`InvalidState`]. The new path is validated as described in
+    /// [`probe_path()`], and is used alongside the existing ones once the
+    /// validation succeeds.
+    ///
+    /// Returns the Destination Connection ID sequence number associated to that
+    /// path.
+    ///
+    /// [`is_multipath()`]: struct.Connection.html#method.is_multipath
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    /// [`probe_path()`]: struct.Connection.html#method.probe_path
+    pub fn create_path(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> Result<u64> {
+        if !self.multipath {
+            return Err(Error::InvalidState);
+        }
+
+        self.probe_path(local_addr, peer_addr)
+    }
+
+    /// Advertises the status of the path between `local_addr` and `peer_addr`
+    /// to the peer.
+    ///
+    /// Packets are only sent on a [`Standby`] path when no available path can
+    /// be used.
+    ///
+    /// This requires the multipath extension to be negotiated, otherwise this
+    /// call returns [`InvalidState`]. If the path does not exist,
+    /// [`InvalidState`] is returned as well.
+    ///
+    /// [`Standby`]: enum.PathStatus.html#variant.Standby
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    pub fn set_path_status(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+        status: PathStatus,
+    ) -> Result<()> {
+        if !self.multipath {
+            return Err(Error::InvalidState);
+        }
+
+        let pid = self
+            .paths
+            .path_id_from_addrs(&(local_addr, peer_addr))
+            .ok_or(Error::InvalidState)?;
+
+        self.paths.get_mut(pid)?.set_local_status(status);
+
+        Ok(())
+    }
+
+    /// Abandons the path between `local_addr` and `peer_addr`.
+    ///
+    /// A PATH_ABANDON frame carrying `error_code` and `reason` is sent to the
+    /// peer on one of the remaining paths, and the abandoned path is not used
+    /// anymore.
+    ///
+    /// This requires the multipath extension to be negotiated. As the last
+    /// active path cannot be abandoned, [`InvalidState`] is returned if no
+    /// other active path exists, or if the path does not exist.
+    ///
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    pub fn abandon_path(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+        error_code: u64, reason: &[u8],
+    ) -> Result<()> {
+        if !self.multipath {
+            return Err(Error::InvalidState);
+        }
+
+        let pid = self
+            .paths
+            .path_id_from_addrs(&(local_addr, peer_addr))
+            .ok_or(Error::InvalidState)?;
+
+        if !self.paths.iter().any(|(id, p)| id != pid && p.active()) {
+            return Err(Error::InvalidState);
+        }
+
+        let path = self.paths.get_mut(pid)?;
+
+        if path.abandoned() {
+            return Err(Error::InvalidState);
+        }
+
+        path.abandon(error_code, reason.to_vec());
+
+        self.redundant_streams.remove(&pid);
+
+        Ok(())
+    }
+
+    /// Replaces the path scheduler of the connection.
+    ///
+    /// The scheduler selects the path of each packet when the multipath
+    /// extension is in use, instead of the built-in one configured with
+    /// [`set_path_scheduler()`]. A plugin implementing the `select_send_path`
+    /// operation takes precedence over it.
+    ///
+    /// [`set_path_scheduler()`]: struct.Config.html#method.set_path_scheduler
+    pub fn set_path_scheduler(&mut self, scheduler: Box<dyn PathScheduler>) {
+        self.path_scheduler = scheduler;
+    }
+
+    /// Sets the maximum sending rate of each path, in bytes per second.
+    ///
+    /// The pacing rate of the paths is capped to this value, even if the
+    /// congestion controller would allow sending faster, so that per-client
+    /// bandwidth caps can be enforced without an external traffic shaper.
+    /// Packets are paced with this rate even when pacing is disabled with
+    /// [`enable_pacing()`].
+    ///
+    /// Note that, as for pacing, the limit is only enforced if the
+    /// application sends packets at the time given by [`SendInfo::at`].
+    ///
+    /// A value of 0 removes the limit, which is the default.
+    ///
+    /// [`enable_pacing()`]: struct.Config.html#method.enable_pacing
+    /// [`SendInfo::at`]: struct.SendInfo.html#structfield.at
+    pub fn set_max_send_rate(&mut self, v: u64) {
+        let rate = if v == 0 { None } else { Some(v) };
+
+        let now = time::Instant::now();
+
+        self.recovery_config.max_pacing_rate = rate;
+
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery.set_max_pacing_rate(rate, now);
+        }
+    }
+
+    /// Provides additional source Connection IDs that the peer can use to reach
     /// this host.
     ///
     /// This triggers sending NEW_CONNECTION_ID frames if the provided Source
@@ -5792,14 +10536,25 @@ impl Connection {
     /// advertise Source Connection IDs and calling this method returns an
     /// [`InvalidState`].
     ///
+    /// If issuing Connection IDs is rate-limited with [`set_rate_limit()`],
+    /// [`Done`] is returned once the limit is reached.
+    ///
     /// Returns the sequence number associated to the provided Connection ID.
     ///
     /// [`source_cids_left()`]: struct.Connection.html#method.source_cids_left
     /// [`IdLimit`]: enum.Error.html#IdLimit
     /// [`InvalidState`]: enum.Error.html#InvalidState
+    /// [`set_rate_limit()`]: struct.Config.html#method.set_rate_limit
+    /// [`Done`]: enum.Error.html#variant.Done
     pub fn new_source_cid(
         &mut self, scid: &ConnectionId, reset_token: u128, retire_if_needed: bool,
     ) -> Result<u64> {
+        let now = time::Instant::now();
+
+        if !self.rate_limit_allow(RateLimited::NewConnectionId, now) {
+            return Err(Error::Done);
+        }
+
         self.ids.new_scid(
             scid.to_vec().into(),
             Some(reset_token),
@@ -5809,6 +10564,32 @@ impl Connection {
         )
     }
 
+    /// Provides a new source Connection ID created by the configured
+    /// [`ConnectionIdGenerator`], with a random reset token.
+    ///
+    /// This is the same as [`new_source_cid()`], and returns the new
+    /// Connection ID along with its sequence number. The [`InvalidState`]
+    /// error is returned if no generator is configured.
+    ///
+    /// [`ConnectionIdGenerator`]: trait.ConnectionIdGenerator.html
+    /// [`new_source_cid()`]: struct.Connection.html#method.new_source_cid
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn new_generated_source_cid(
+        &mut self, retire_if_needed: bool,
+    ) -> Result<(ConnectionId<'static>, u64)> {
+        let generator = self.cid_generator.clone().ok_or(Error::InvalidState)?;
+
+        let scid = generator.generate();
+
+        let mut reset_token = [0; 16];
+        rand::rand_bytes(&mut reset_token);
+        let reset_token = u128::from_be_bytes(reset_token);
+
+        let seq = self.new_source_cid(&scid, reset_token, retire_if_needed)?;
+
+        Ok((scid, seq))
+    }
+
     /// Returns the number of source Connection IDs that are active. This is
     /// only meaningful if the host uses non-zero length Source Connection IDs.
     pub fn active_source_cids(&self) -> usize {
@@ -5828,9 +10609,17 @@ impl Connection {
     /// Connection IDs, but instead treat this as an untrusted upper bound.
     /// Applications should limit how many outstanding source ConnectionIDs
     /// are simultaneously issued to prevent issuing more than they can handle.
+    ///
+    /// While Connection IDs issued with `retire_if_needed` are waiting for the
+    /// peer to retire older ones, the limit can be temporarily exceeded, and
+    /// this returns 0. Once the peer retired them, which the application learns
+    /// through [`retired_scid_next()`], new ones can be issued again.
+    ///
+    /// [`retired_scid_next()`]: struct.Connection.html#method.retired_scid_next
     #[inline]
     pub fn source_cids_left(&self) -> usize {
-        self.max_active_source_cids() - self.active_source_cids()
+        self.max_active_source_cids()
+            .saturating_sub(self.active_source_cids())
     }
 
     /// Requests the retirement of the destination Connection ID used by the
@@ -5906,6 +10695,131 @@ impl Connection {
         self.paths.pop_event()
     }
 
+    /// Initiates a 1-RTT key update.
+    ///
+    /// Packets sent after this call are protected with the keys of the next
+    /// key phase. A [`LocalInitiated`] event is reported right away, and a
+    /// [`LocalConfirmed`] event once the peer acknowledges a packet protected
+    /// with the new keys.
+    ///
+    /// A new key update can only be initiated once the previous one, initiated
+    /// by either endpoint, has been confirmed, which takes at least one round
+    /// trip. Otherwise this returns [`KeyUpdate`].
+    ///
+    /// Key updates cannot be initiated before the handshake is confirmed, nor
+    /// when the multipath extension is in use. In such cases, this returns
+    /// [`InvalidState`].
+    ///
+    /// [`LocalInitiated`]: enum.KeyUpdateEvent.html#variant.LocalInitiated
+    /// [`LocalConfirmed`]: enum.KeyUpdateEvent.html#variant.LocalConfirmed
+    /// [`KeyUpdate`]: enum.Error.html#variant.KeyUpdate
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn initiate_key_update(&mut self) -> Result<()> {
+        if !self.handshake_confirmed || self.multipath {
+            return Err(Error::InvalidState);
+        }
+
+        let pkt_space = &mut self.pkt_num_spaces[packet::Epoch::Application];
+
+        if pkt_space
+            .key_update
+            .as_ref()
+            .map_or(false, |ku| !ku.update_acked)
+        {
+            return Err(Error::KeyUpdate);
+        }
+
+        let (open_next, seal_next) =
+            match (&pkt_space.crypto_open, &pkt_space.crypto_seal) {
+                (Some(open), Some(seal)) => (
+                    open.derive_next_packet_key()?,
+                    seal.derive_next_packet_key()?,
+                ),
+
+                _ => return Err(Error::InvalidState),
+            };
+
+        trace!("{} initiated key update", self.trace_id);
+
+        let open_prev = pkt_space
+            .crypto_open
+            .replace(open_next)
+            .ok_or(Error::InvalidState)?;
+
+        pkt_space.crypto_seal = Some(seal_next);
+
+        // Until the peer sends a packet protected with the new keys, every
+        // packet using the previous key phase is decrypted with the previous
+        // keys.
+        pkt_space.key_update = Some(packet::KeyUpdate {
+            crypto_open: open_prev,
+            pn_on_update: std::u64::MAX,
+            pn_sent_on_update: pkt_space.next_pkt_num,
+            local: true,
+            update_acked: false,
+            ack_sent: false,
+            timer: None,
+        });
+
+        self.key_phase = !self.key_phase;
+        self.key_update_count += 1;
+        self.key_phase_sent_count = 0;
+        self.key_phase_sent_bytes = 0;
+        self.key_phase_recv_count = 0;
+
+        self.key_update_events.push(KeyUpdateEvent::LocalInitiated);
+
+        Ok(())
+    }
+
+    /// Returns whether enough data was sent with the current 1-RTT keys to
+    /// rotate them automatically.
+    fn is_key_update_due(&self) -> bool {
+        match self.key_update_interval {
+            KeyUpdateInterval::None => false,
+
+            KeyUpdateInterval::Packets(v) => self.key_phase_sent_count >= v,
+
+            KeyUpdateInterval::Bytes(v) => self.key_phase_sent_bytes >= v,
+        }
+    }
+
+    /// Returns whether the current 1-RTT keys protected as many packets as
+    /// the AEAD in use allows.
+    fn is_confidentiality_limit_reached(&self) -> bool {
+        self.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_seal
+            .as_ref()
+            .map_or(false, |seal| {
+                self.key_phase_sent_count >= seal.alg().confidentiality_limit()
+            })
+    }
+
+    /// Processes key update events.
+    ///
+    /// On success it returns a [`KeyUpdateEvent`], or `None` when there are no
+    /// events to report.
+    ///
+    /// [`KeyUpdateEvent`]: enum.KeyUpdateEvent.html
+    pub fn key_update_event_next(&mut self) -> Option<KeyUpdateEvent> {
+        self.key_update_events.pop()
+    }
+
+    /// Processes stream events.
+    ///
+    /// On success it returns a [`StreamEvent`], or `None` when there are no
+    /// events to report. Please refer to [`StreamEvent`] for the exhaustive
+    /// event list.
+    ///
+    /// This reports the changes of the streams' state as they happen, so that
+    /// the application doesn't need to infer them from the errors returned
+    /// when reading from or writing to the streams.
+    ///
+    /// [`StreamEvent`]: enum.StreamEvent.html
+    pub fn stream_event_next(&mut self) -> Option<StreamEvent> {
+        self.stream_events.pop()
+    }
+
     /// Returns a source `ConnectionId` that has been retired.
     ///
     /// On success it returns a [`ConnectionId`], or `None` when there are no
@@ -6012,6 +10926,30 @@ impl Connection {
     /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
     /// [`is_closed()`]: struct.Connection.html#method.is_closed
     pub fn close(&mut self, app: bool, err: u64, reason: &[u8]) -> Result<()> {
+        self.close_internal(app, err, 0, reason)
+    }
+
+    /// Closes the connection with the given transport error, reporting the
+    /// type of the frame that triggered it.
+    ///
+    /// This is the same as [`close()`] with `app` set to `false`, but the
+    /// `CONNECTION_CLOSE` frame sent to the peer also carries `frame_type`,
+    /// which is useful for reporting protocol violations caused by a specific
+    /// frame received from the peer.
+    ///
+    /// Returns [`Done`] if the connection had already been closed.
+    ///
+    /// [`close()`]: struct.Connection.html#method.close
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn close_with_frame_type(
+        &mut self, err: u64, frame_type: u64, reason: &[u8],
+    ) -> Result<()> {
+        self.close_internal(false, err, frame_type, reason)
+    }
+
+    fn close_internal(
+        &mut self, app: bool, err: u64, frame_type: u64, reason: &[u8],
+    ) -> Result<()> {
         if self.is_closed() || self.is_draining() {
             return Err(Error::Done);
         }
@@ -6028,12 +10966,14 @@ impl Connection {
             self.local_error = Some(ConnectionError {
                 is_app: false,
                 error_code: 0x0c,
+                frame_type: 0,
                 reason: vec![],
             });
         } else {
             self.local_error = Some(ConnectionError {
                 is_app: app,
                 error_code: err,
+                frame_type,
                 reason: reason.to_vec(),
             });
         }
@@ -6063,6 +11003,199 @@ impl Connection {
         self.alpn.as_ref()
     }
 
+    /// Returns the list of plugins advertised by the peer.
+    ///
+    /// `None` is returned if the peer's transport parameters have not been
+    /// received yet, or if the peer didn't advertise any plugin.
+    ///
+    /// See [`set_plugin_support()`] for more details.
+    ///
+    /// [`set_plugin_support()`]: struct.Config.html#method.set_plugin_support
+    #[inline]
+    pub fn peer_plugin_support(&self) -> Option<&[Vec<u8>]> {
+        self.peer_transport_params.plugin_support.as_deref()
+    }
+
+    /// Returns the plugins advertised by both endpoints.
+    ///
+    /// The returned list follows the order of the local advertisement, and is
+    /// empty if either endpoint didn't advertise any plugin.
+    pub fn negotiated_plugins(&self) -> Vec<&[u8]> {
+        let (local, peer) = match (
+            &self.local_transport_params.plugin_support,
+            &self.peer_transport_params.plugin_support,
+        ) {
+            (Some(local), Some(peer)) => (local, peer),
+
+            _ => return Vec::new(),
+        };
+
+        local
+            .iter()
+            .filter(|p| peer.contains(p))
+            .map(|p| p.as_slice())
+            .collect()
+    }
+
+    /// Returns the most recent writes to connection and recovery fields
+    /// performed by plugins, oldest first.
+    ///
+    /// Writes are also logged to qlog, if enabled. The number of writes kept
+    /// is bounded by [`set_max_plugin_mutations()`].
+    ///
+    /// [`set_max_plugin_mutations()`]:
+    /// struct.Config.html#method.set_max_plugin_mutations
+    #[cfg(feature = "plugins")]
+    pub fn plugin_mutations(&self) -> impl Iterator<Item = &PluginMutation> {
+        self.plugin_mutations.iter()
+    }
+
+    /// Wraps the connection into a pluginizable connection, and inserts the
+    /// plugins added to its configuration with [`load_plugin_from_file()`].
+    ///
+    /// The connection is then owned by the returned object, and can be
+    /// accessed with its `get_conn()` and `get_conn_mut()` methods. Further
+    /// plugins can be inserted with its plugin handler.
+    ///
+    /// An error is returned if one of the plugins can't be inserted.
+    ///
+    /// [`load_plugin_from_file()`]:
+    /// struct.Config.html#method.load_plugin_from_file
+    #[cfg(feature = "plugins")]
+    pub fn pluginize(self) -> Result<Box<PluginizableConnection<Connection>>> {
+        let files = self.plugin_files.clone();
+
+        let mut pc = PluginizableConnection::new_pluginizable_connection(
+            pluginop::api::exports_func,
+            self,
+        );
+
+        for file in &files {
+            pc.get_conn_mut().insert_plugin(file)?;
+        }
+
+        Ok(pc)
+    }
+
+    /// Inserts the plugin at `path` into a connection returned by
+    /// [`pluginize()`].
+    ///
+    /// The experiments enabled on the connection are then passed to the
+    /// plugins through the `experiments` operation, so plugins should be
+    /// inserted with this method rather than directly with the plugin handler.
+    ///
+    /// An error is returned if the connection wasn't pluginized, or if the
+    /// plugin can't be inserted.
+    ///
+    /// [`pluginize()`]: struct.Connection.html#method.pluginize
+    #[cfg(feature = "plugins")]
+    pub fn insert_plugin<P: AsRef<std::path::Path>>(
+        &mut self, path: P,
+    ) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+
+        let ph = self
+            .get_pluginizable_connection()
+            .map(|pc| pc.get_ph_mut())
+            .ok_or(Error::InvalidState)?;
+
+        if let Err(e) = ph.insert_plugin(&path) {
+            error!("failed to insert plugin {:?}: {:?}", path, e);
+            return Err(Error::InvalidState);
+        }
+
+        self.plugin_experiments();
+
+        Ok(())
+    }
+
+    /// Writes the internal state of the connection's plugins in `out`.
+    ///
+    /// The state is serialized by the plugin implementing the `export_state`
+    /// operation, in a format only it needs to understand. It can be restored
+    /// later with [`import_plugin_state()`], for example when handing the
+    /// connection over to another process, or dumped for debugging.
+    ///
+    /// On success the number of bytes written is returned. If the connection
+    /// has no plugin, or the plugin fails to export its state (e.g. because
+    /// `out` is too short), [`Done`] is returned.
+    ///
+    /// [`import_plugin_state()`]:
+    /// struct.Connection.html#method.import_plugin_state
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn export_plugin_state(&mut self, out: &mut [u8]) -> Result<usize> {
+        #[cfg(feature = "plugins")]
+        {
+            use pluginop::IntoWithPH;
+
+            let ph = self
+                .get_pluginizable_connection()
+                .map(|pc| pc.get_ph_mut())
+                .ok_or(Error::Done)?;
+
+            let mut b = octets::OctetsMut::with_slice(out);
+
+            let op = plugin::export_state_op();
+
+            let params = &[OctetsMutPtr::from(&mut b).into_with_ph(ph)];
+            let res = plugin::guarded_call(&op, params, || ph.call(&op, params));
+
+            self.plugin_result(res).ok_or(Error::Done)?;
+
+            Ok(b.off())
+        }
+
+        #[cfg(not(feature = "plugins"))]
+        {
+            let _ = out;
+
+            Err(Error::Done)
+        }
+    }
+
+    /// Restores the internal state of the connection's plugins.
+    ///
+    /// The state is passed to the plugin implementing the `import_state`
+    /// operation, and is expected to have been written by
+    /// [`export_plugin_state()`] with the same plugins.
+    ///
+    /// If the connection has no plugin, or the plugin fails to import the
+    /// state, [`Done`] is returned.
+    ///
+    /// [`export_plugin_state()`]:
+    /// struct.Connection.html#method.export_plugin_state
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn import_plugin_state(&mut self, state: &[u8]) -> Result<()> {
+        #[cfg(feature = "plugins")]
+        {
+            use octets::OctetsPtr;
+            use pluginop::IntoWithPH;
+
+            let ph = self
+                .get_pluginizable_connection()
+                .map(|pc| pc.get_ph_mut())
+                .ok_or(Error::Done)?;
+
+            let mut b = octets::Octets::with_slice(state);
+
+            let op = plugin::import_state_op();
+
+            let params = &[OctetsPtr::from(&mut b).into_with_ph(ph)];
+            let res = plugin::guarded_call(&op, params, || ph.call(&op, params));
+
+            self.plugin_result(res).ok_or(Error::Done)?;
+
+            Ok(())
+        }
+
+        #[cfg(not(feature = "plugins"))]
+        {
+            let _ = state;
+
+            Err(Error::Done)
+        }
+    }
+
     /// Returns the server name requested by the client.
     #[inline]
     pub fn server_name(&self) -> Option<&str> {
@@ -6081,11 +11214,41 @@ impl Connection {
     /// The certificate at index 0 is the peer's leaf certificate, the other
     /// certificates (if any) are the chain certificate authorities used to
     /// sign the leaf certificate.
+    ///
+    /// On servers, this returns the client's certificate chain when client
+    /// authentication is enabled with [`verify_peer()`] or
+    /// [`verify_peer_required()`].
+    ///
+    /// [`verify_peer()`]: struct.Config.html#method.verify_peer
+    /// [`verify_peer_required()`]:
+    /// struct.Config.html#method.verify_peer_required
     #[inline]
     pub fn peer_cert_chain(&self) -> Option<Vec<&[u8]>> {
         self.handshake.peer_cert_chain()
     }
 
+    /// Derives keying material from the connection's TLS secrets, as defined
+    /// in [RFC 5705] and [RFC 8446].
+    ///
+    /// The `out` buffer is filled with keying material bound to `label` and,
+    /// if any, to `context`, which both endpoints derive identically. As with
+    /// TLS 1.3, no context and an empty context produce the same output.
+    ///
+    /// [`InvalidState`] is returned if the handshake is not completed yet.
+    ///
+    /// [RFC 5705]: https://www.rfc-editor.org/rfc/rfc5705.html
+    /// [RFC 8446]: https://www.rfc-editor.org/rfc/rfc8446.html#section-7.5
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn export_keying_material(
+        &self, label: &[u8], context: Option<&[u8]>, out: &mut [u8],
+    ) -> Result<()> {
+        if !self.handshake_completed {
+            return Err(Error::InvalidState);
+        }
+
+        self.handshake.export_keying_material(label, context, out)
+    }
+
     /// Returns the serialized cryptographic session for the connection.
     ///
     /// This can be used by a client to cache a connection's session, and resume
@@ -6145,14 +11308,81 @@ impl Connection {
         self.handshake.is_resumed()
     }
 
-    /// Returns true if the connection has a pending handshake that has
-    /// progressed enough to send or receive early data.
+    /// Returns true if the multipath extension has been negotiated.
+    ///
+    /// See [`set_multipath()`] for more details.
+    ///
+    /// [`set_multipath()`]: struct.Config.html#method.set_multipath
     #[inline]
-    pub fn is_in_early_data(&self) -> bool {
-        self.handshake.is_in_early_data()
+    pub fn is_multipath(&self) -> bool {
+        self.multipath
     }
 
-    /// Returns whether there is stream or DATAGRAM data available to read.
+    /// Returns the optional extensions negotiated with the peer.
+    ///
+    /// Extensions are negotiated using transport parameters, so none is
+    /// reported before the peer's transport parameters are received.
+    pub fn negotiated_extensions(&self) -> NegotiatedExtensions {
+        NegotiatedExtensions {
+            datagram: self.dgram_enabled() &&
+                self.peer_transport_params.max_datagram_frame_size.is_some(),
+
+            multipath: self.multipath,
+
+            ack_frequency: self.ack_frequency,
+
+            plugins: !self.negotiated_plugins().is_empty(),
+
+            grease_quic_bit: self.grease_quic_bit,
+        }
+    }
+
+    /// Returns the transport parameters advertised by the peer.
+    ///
+    /// `None` is returned if the peer's transport parameters have not been
+    /// received yet.
+    pub fn peer_transport_params(&self) -> Option<PeerTransportParams> {
+        if !self.parsed_peer_transport_params {
+            return None;
+        }
+
+        Some((&self.peer_transport_params).into())
+    }
+
+    /// Returns the value of a custom transport parameter sent by the peer.
+    ///
+    /// `None` is returned if the peer's transport parameters have not been
+    /// received yet, or if the peer didn't send the parameter.
+    ///
+    /// See [`add_custom_transport_param()`] for more details.
+    ///
+    /// [`add_custom_transport_param()`]: struct.Config.html#method.add_custom_transport_param
+    pub fn peer_custom_transport_param(&self, id: u64) -> Option<&[u8]> {
+        self.peer_transport_params
+            .extra_params
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Returns the experiments enabled on the connection.
+    ///
+    /// See [`set_experiments()`] for more details.
+    ///
+    /// [`set_experiments()`]: struct.Config.html#method.set_experiments
+    #[inline]
+    pub fn experiments(&self) -> Experiments {
+        self.experiments
+    }
+
+    /// Returns true if the connection has a pending handshake that has
+    /// progressed enough to send or receive early data.
+    #[inline]
+    pub fn is_in_early_data(&self) -> bool {
+        self.handshake.is_in_early_data()
+    }
+
+    /// Returns whether there is stream or DATAGRAM data available to read.
     #[inline]
     pub fn is_readable(&self) -> bool {
         self.streams.has_readable() || self.dgram_recv_front_len().is_some()
@@ -6210,6 +11440,16 @@ impl Connection {
         self.timed_out
     }
 
+    /// Returns true if the connection was closed because the handshake didn't
+    /// complete within the timeout set with [`set_handshake_timeout()`].
+    ///
+    /// [`set_handshake_timeout()`]:
+    /// struct.Config.html#method.set_handshake_timeout
+    #[inline]
+    pub fn is_handshake_timed_out(&self) -> bool {
+        self.handshake_timed_out
+    }
+
     /// Returns the error received from the peer, if any.
     ///
     /// Note that a `Some` return value does not necessarily imply
@@ -6236,9 +11476,26 @@ impl Connection {
         self.local_error.as_ref()
     }
 
+    /// Returns true if the duplicate STREAM bytes received on the connection
+    /// exceeded the threshold set with [`set_stream_dup_alarm()`].
+    ///
+    /// [`set_stream_dup_alarm()`]:
+    /// struct.Config.html#method.set_stream_dup_alarm
+    #[inline]
+    pub fn stream_dup_alarm(&self) -> bool {
+        self.stream_dup_alarm_raised
+    }
+
     /// Collects and returns statistics about the connection.
     #[inline]
     pub fn stats(&self) -> Stats {
+        let ecn_recv = self.ecn.total_recv_counts();
+
+        #[cfg(feature = "plugins")]
+        let plugin_dropped_events = self.plugin_timers.dropped();
+        #[cfg(not(feature = "plugins"))]
+        let plugin_dropped_events = 0;
+
         Stats {
             recv: self.recv_count,
             sent: self.sent_count,
@@ -6248,6 +11505,7 @@ impl Connection {
             recv_bytes: self.recv_bytes,
             lost_bytes: self.lost_bytes,
             stream_retrans_bytes: self.stream_retrans_bytes,
+            stream_dup_bytes: self.stream_dup_bytes,
             paths_count: self.paths.len(),
             peer_max_idle_timeout: self.peer_transport_params.max_idle_timeout,
             peer_max_udp_payload_size: self
@@ -6282,38 +11540,302 @@ impl Connection {
             peer_max_datagram_frame_size: self
                 .peer_transport_params
                 .max_datagram_frame_size,
+            late_initial: self.late_initial_count,
+            late_handshake: self.late_handshake_count,
+            junk_datagrams: self.junk_datagram_count,
+            junk_bytes: self.junk_bytes,
+            throttled_datagrams: self.throttled_datagram_count,
+            dcids_over_limit: self.ids.dcids_over_limit(),
+            rate_limited: self.rate_limited_count,
+            plugin_failures: self.plugin_failure_count,
+            dgram_expired: self.dgram_expired_count,
+            key_updates: self.key_update_count,
+            key_phase: self.key_phase,
+            key_phase_sent: self.key_phase_sent_count,
+            key_phase_recv: self.key_phase_recv_count,
+            aead_auth_failures: self.aead_auth_fail_count,
+            ack_delay_received: self.ack_delay_received,
+            ack_delay_sent: self.ack_delay_sent,
+            ecn_marked_sent: self.ecn.marked_sent(),
+            ecn_ect0_recv: ecn_recv.ect0_count,
+            ecn_ect1_recv: ecn_recv.ect1_count,
+            ecn_ce_recv: ecn_recv.ecn_ce_count,
+            ecn_ce_reported: self.ecn.total_peer_counts().ecn_ce_count,
+            ecn_capable: self.ecn.is_capable(),
+            dropped_events: self.paths.dropped_events() +
+                self.key_update_events.dropped() +
+                self.stream_events.dropped() +
+                plugin_dropped_events,
         }
     }
 
     /// Collects and returns statistics about each known path for the
     /// connection.
     pub fn path_stats(&self) -> impl Iterator<Item = PathStats> + '_ {
-        self.paths.iter().map(|(_, p)| p.stats())
+        self.paths.iter().map(move |(_, p)| {
+            let mut stats = p.stats();
+
+            if self.is_server && !p.verified_peer_address {
+                stats.anti_amplification_allowance = Some(p.max_send_bytes);
+            }
+
+            stats
+        })
+    }
+
+    /// Switches a client connection to `version`, before its first Initial
+    /// packet is sent.
+    fn set_client_version(&mut self, version: u32) -> Result<()> {
+        self.version = version;
+
+        // Derive Initial secrets based on the new version.
+        let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+            &self.crypto_provider,
+            &self.destination_id(),
+            self.version,
+            self.is_server,
+        )?;
+
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_open = Some(aead_open);
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal = Some(aead_seal);
+
+        self.handshake
+            .use_legacy_codepoint(!version_is_final(self.version));
+
+        // Encode transport parameters again, as the new version might be
+        // using a different format.
+        self.encode_transport_params()
+    }
+
+    /// Applies the hints stored for the server by previous connections, when
+    /// creating a client connection.
+    fn apply_server_hints(&mut self, hints: &ServerHints) -> Result<()> {
+        // Start directly with the version selected by the server last time.
+        if let Some(version) = hints.version {
+            if version != self.version &&
+                self.supported_versions.contains(&version)
+            {
+                trace!("{} using cached version {:x}", self.trace_id, version);
+
+                self.set_client_version(version)?;
+            }
+        }
+
+        // Stop offering the protocols refused by the server, unless it refused
+        // all of them.
+        let protos: Vec<Vec<u8>> = self
+            .application_protos
+            .iter()
+            .filter(|p| !hints.rejected_protos.contains(p))
+            .cloned()
+            .collect();
+
+        if !protos.is_empty() && protos.len() < self.application_protos.len() {
+            let list: Vec<&[u8]> = protos.iter().map(|p| p.as_slice()).collect();
+
+            self.handshake.set_alpn(&list)?;
+            self.application_protos = protos;
+        }
+
+        Ok(())
+    }
+
+    /// Updates the hints stored for the server in the client store, if any.
+    fn update_server_hints<F: FnOnce(&mut ServerHints)>(&self, f: F) {
+        if let Some((store, server)) = &self.client_store {
+            let mut hints = store.get_hints(server).unwrap_or_default();
+
+            f(&mut hints);
+
+            store.put_hints(server, &hints);
+        }
+    }
+
+    /// Returns the version a server connection should be upgraded to through
+    /// compatible version negotiation, given the versions available to the
+    /// client, if any.
+    fn choose_compatible_version(&self, available: &[u32]) -> Option<u32> {
+        let version = self.supported_versions.iter().find(|&&v| {
+            versions_are_compatible(self.version, v) && available.contains(&v)
+        })?;
+
+        // The client's version is preferred, or it doesn't support any other.
+        if *version == self.version {
+            return None;
+        }
+
+        Some(*version)
+    }
+
+    /// Upgrades a server connection to `version`, once the client's first
+    /// Initial packet, sent to `dcid`, was received with a compatible version.
+    ///
+    /// The client's Initial packets of the original version are still accepted
+    /// until it switches to the new version.
+    fn upgrade_server_version(
+        &mut self, version: u32, dcid: &ConnectionId,
+    ) -> Result<()> {
+        trace!(
+            "{} upgrading version from {:x} to {:x}",
+            self.trace_id,
+            self.version,
+            version
+        );
+
+        let (original_open, _) = crypto::derive_initial_key_material(
+            &self.crypto_provider,
+            dcid,
+            self.version,
+            self.is_server,
+        )?;
+
+        let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+            &self.crypto_provider,
+            dcid,
+            version,
+            self.is_server,
+        )?;
+
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_open = Some(aead_open);
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal = Some(aead_seal);
+
+        self.derived_initial_secrets = true;
+
+        self.original_version = Some(self.version);
+        self.original_initial_open = Some(original_open);
+        self.version = version;
+
+        Ok(())
+    }
+
+    /// Upgrades a client connection to the compatible `version` selected by
+    /// the server.
+    fn upgrade_client_version(&mut self, version: u32) -> Result<()> {
+        trace!(
+            "{} upgraded version from {:x} to {:x}",
+            self.trace_id,
+            self.version,
+            version
+        );
+
+        let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+            &self.crypto_provider,
+            &self.destination_id(),
+            version,
+            self.is_server,
+        )?;
+
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_open = Some(aead_open);
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal = Some(aead_seal);
+
+        // The transport parameters were already sent, and keep listing the
+        // original version as the one chosen by the client.
+        self.original_version = Some(self.version);
+        self.version = version;
+
+        Ok(())
+    }
+
+    /// Returns the most preferred of the supported versions that is also
+    /// offered by the peer, if any.
+    fn choose_version(&self, offered: &[u32]) -> Option<u32> {
+        self.supported_versions
+            .iter()
+            .find(|v| offered.contains(v))
+            .copied()
     }
 
     fn encode_transport_params(&mut self) -> Result<()> {
-        let mut raw_params = [0; 128];
+        self.local_transport_params.version_information =
+            Some(VersionInformation {
+                chosen_version: self.version,
+                available_versions: self.supported_versions.clone(),
+            });
+
+        let tp = &self.local_transport_params;
+
+        // The parameters below have a variable size, so the exact room they
+        // take is computed on top of the fixed-size ones.
+        let plugin_support_len = tp.plugin_support.as_ref().map_or(0, |v| {
+            let len = v
+                .iter()
+                .map(|p| octets::varint_len(p.len() as u64) + p.len())
+                .sum();
+
+            TransportParams::encoded_len(PLUGIN_SUPPORT_PARAM_ID, len)
+        });
+
+        let multipath_len = if tp.enable_multipath {
+            TransportParams::encoded_len(ENABLE_MULTIPATH_PARAM_ID, 0)
+        } else {
+            0
+        };
+
+        let grease_quic_bit_len = if tp.grease_quic_bit {
+            TransportParams::encoded_len(GREASE_QUIC_BIT_PARAM_ID, 0)
+        } else {
+            0
+        };
+
+        let ack_freq_len = tp.min_ack_delay.map_or(0, |v| {
+            TransportParams::encoded_len(
+                MIN_ACK_DELAY_PARAM_ID,
+                octets::varint_len(v),
+            )
+        });
+
+        let version_info_len = tp.version_information.as_ref().map_or(0, |v| {
+            TransportParams::encoded_len(
+                VERSION_INFORMATION_PARAM_ID,
+                4 * (v.available_versions.len() + 1),
+            )
+        });
+
+        let custom_len: usize = tp
+            .extra_params
+            .iter()
+            .map(|(id, v)| TransportParams::encoded_len(*id, v.len()))
+            .sum();
 
+        let mut raw_params = vec![
+            0;
+            128 + plugin_support_len +
+                multipath_len +
+                grease_quic_bit_len +
+                ack_freq_len +
+                version_info_len +
+                custom_len
+        ];
+
+        #[cfg(feature = "plugins")]
         let registrations = self
             .get_pluginizable_connection()
             .map(|pc| pc.get_ph().get_registrations().to_vec())
             .unwrap_or(vec![]);
 
+        #[cfg_attr(not(feature = "plugins"), allow(unused_mut))]
         let mut b = TransportParams::encode_internal(
             &self.local_transport_params,
             self.is_server,
             &mut raw_params,
         )?;
 
-        use pluginop::IntoWithPH;
+        #[cfg(feature = "plugins")]
         registrations.iter().for_each(|r| {
+            use pluginop::IntoWithPH;
+
             if let Registration::TransportParameter(tp) = r {
                 if let Some(ph) =
                     self.get_pluginizable_connection().map(|pc| pc.get_ph_mut())
                 {
+                    let op = PluginOp::WriteTransportParameter(*tp);
+
                     let params = &[OctetsMutPtr::from(&mut b).into_with_ph(ph)];
-                    ph.call(&PluginOp::WriteTransportParameter(*tp), params)
-                        .ok();
+                    let res = plugin::guarded_call(&op, params, || {
+                        ph.call(&op, params)
+                    });
+
+                    self.plugin_result(res);
                 }
             }
         });
@@ -6329,8 +11851,48 @@ impl Connection {
     fn parse_peer_transport_params(
         &mut self, peer_params: TransportParams,
     ) -> Result<()> {
+        // The client's chosen version is the one of its first Initial packet,
+        // even if the server then upgraded the connection.
+        let chosen_version = if self.is_server {
+            self.original_version.unwrap_or(self.version)
+        } else {
+            self.version
+        };
+
+        // Validate version_information.
+        match &peer_params.version_information {
+            Some(v) if v.chosen_version != chosen_version =>
+                return Err(Error::VersionNegotiation),
+
+            // After a Version Negotiation packet, make sure the same version
+            // would have been chosen from the server's full list of versions,
+            // to detect a downgrade by an attacker.
+            Some(v) if !self.is_server && self.did_version_negotiation => {
+                let version = self.choose_version(&v.available_versions);
+
+                if version != Some(self.version) {
+                    return Err(Error::VersionNegotiation);
+                }
+            },
+
+            Some(_) => (),
+
+            // version_information must be sent by the server after version
+            // negotiation, unless a draft version was negotiated.
+            None if !self.is_server &&
+                self.did_version_negotiation &&
+                version_is_final(self.version) =>
+                return Err(Error::VersionNegotiation),
+
+            // It must also be sent after a compatible version upgrade.
+            None if self.original_version.is_some() =>
+                return Err(Error::VersionNegotiation),
+
+            None => (),
+        }
+
         if self.version >= PROTOCOL_VERSION_DRAFT28 ||
-            self.version == PROTOCOL_VERSION_V1
+            version_is_final(self.version)
         {
             // Validate initial_source_connection_id.
             match &peer_params.initial_source_connection_id {
@@ -6417,10 +11979,27 @@ impl Connection {
             .recovery
             .update_max_datagram_size(peer_params.max_udp_payload_size as usize);
 
+        active_path.update_max_pmtu(peer_params.max_udp_payload_size as usize);
+
         // Record the max_active_conn_id parameter advertised by the peer.
         self.ids
             .set_source_conn_id_limit(peer_params.active_conn_id_limit);
 
+        // The multipath extension requires non-zero length connection IDs to
+        // identify paths.
+        self.multipath = self.local_transport_params.enable_multipath &&
+            peer_params.enable_multipath &&
+            !self.ids.zero_length_scid() &&
+            !self.ids.zero_length_dcid();
+
+        self.paths.set_multipath(self.multipath);
+
+        self.ack_frequency = peer_params.min_ack_delay.is_some() &&
+            self.local_transport_params.min_ack_delay.is_some();
+
+        self.grease_quic_bit = peer_params.grease_quic_bit &&
+            self.local_transport_params.grease_quic_bit;
+
         self.peer_transport_params = peer_params;
 
         Ok(())
@@ -6430,6 +12009,14 @@ impl Connection {
     ///
     /// If the connection is already established, it does nothing.
     fn do_handshake(&mut self) -> Result<()> {
+        // Session tickets are only received after the handshake. Take the
+        // current session out, so that a new one can be told apart.
+        let prev_session = if self.handshake_completed {
+            self.session.take()
+        } else {
+            None
+        };
+
         let mut ex_data = tls::ExData {
             application_protos: &self.application_protos,
 
@@ -6441,13 +12028,37 @@ impl Connection {
 
             keylog: self.keylog.as_mut(),
 
+            keylog_callback: self.keylog_callback.as_deref(),
+
             trace_id: &self.trace_id,
 
+            key_ring: self.key_ring.as_deref(),
+
+            ticket_keys: self.ticket_keys.as_deref(),
+
+            crypto_provider: &self.crypto_provider,
+
+            cert_verifier: self.cert_verifier.as_deref(),
+
             is_server: self.is_server,
+
+            version: self.version,
         };
 
         if self.handshake_completed {
-            return self.handshake.process_post_handshake(&mut ex_data);
+            let res = self.handshake.process_post_handshake(&mut ex_data);
+
+            match &self.session {
+                // A new session ticket was received.
+                Some(session) =>
+                    if let Some((store, server)) = &self.client_store {
+                        store.put_session(server, session);
+                    },
+
+                None => self.session = prev_session,
+            }
+
+            return res;
         }
 
         match self.handshake.do_handshake(&mut ex_data) {
@@ -6481,6 +12092,33 @@ impl Connection {
             Err(e) => return Err(e),
         };
 
+        // Let the application vet the peer's certificate chain, on top of the
+        // verification performed by the TLS library.
+        if let Some(verifier) = self
+            .cert_verifier
+            .as_ref()
+            .filter(|v| v.mode() == CertVerifyMode::AfterHandshake)
+        {
+            let chain = self.handshake.peer_cert_chain().unwrap_or_default();
+
+            if let Err(e) = verifier.verify(&chain) {
+                // Errors that are not a valid TLS alert are reported as a bad
+                // certificate.
+                let alert = u8::try_from(e)
+                    .unwrap_or(tls::TLS_ALERT_BAD_CERTIFICATE)
+                    .into();
+
+                self.local_error = Some(ConnectionError {
+                    is_app: false,
+                    error_code: tls::TLS_ALERT_ERROR + alert,
+                    frame_type: 0,
+                    reason: Vec::new(),
+                });
+
+                return Err(Error::TlsFail);
+            }
+        }
+
         self.handshake_completed = self.handshake.is_completed();
 
         self.alpn = self.handshake.alpn_protocol().to_vec();
@@ -6500,6 +12138,60 @@ impl Connection {
         // packets anymore, so clear the buffer now.
         if self.handshake_completed {
             self.undecryptable_pkts.clear();
+
+            // Remember the version selected by the server, so the next
+            // connections can skip version negotiation.
+            if self.did_version_negotiation {
+                let version = self.version;
+
+                self.update_server_hints(|hints| hints.version = Some(version));
+            }
+
+            // Give the client a token to validate its address with in future
+            // connections.
+            if let (true, Some(minter)) = (self.is_server, &self.token_minter) {
+                let peer = self.paths.get_active()?.peer_addr();
+
+                match minter.mint(&AddressToken::NewToken, &peer) {
+                    Ok(v) => self.new_token = Some(v),
+
+                    Err(e) => trace!(
+                        "{} failed to mint address validation token: {:?}",
+                        self.trace_id,
+                        e
+                    ),
+                }
+            }
+
+            if let Some(metrics) = &self.metrics {
+                metrics.incr_counter(Counter::HandshakesCompleted, 1);
+                metrics.record_histogram(
+                    Histogram::HandshakeDuration,
+                    self.created.elapsed().as_secs_f64(),
+                );
+            }
+        }
+
+        // When the server rejected early data, the 0-RTT packets sent by the
+        // client were discarded, so their data is sent again in 1-RTT packets.
+        if self.handshake_completed &&
+            !self.is_server &&
+            !self.handshake.early_data_accepted()
+        {
+            let handshake_status = self.handshake_status();
+            let now = time::Instant::now();
+
+            for (_, p) in self.paths.iter_mut() {
+                let rejected = p.recovery.on_0rtt_rejected(handshake_status, now);
+
+                if rejected > 0 {
+                    trace!(
+                        "{} 0-RTT rejected, resending data of {} packets",
+                        self.trace_id,
+                        rejected
+                    );
+                }
+            }
         }
 
         trace!("{} connection established: proto={:?} cipher={:?} curve={:?} sigalg={:?} resumed={} {:?}",
@@ -6582,6 +12274,7 @@ impl Connection {
         let send_path = self.paths.get(send_pid)?;
         if (self.is_established() || self.is_in_early_data()) &&
             (self.should_send_handshake_done() ||
+                self.new_token.is_some() ||
                 self.almost_full ||
                 self.blocked_limit.is_some() ||
                 self.dgram_send_queue.has_pending() ||
@@ -6597,6 +12290,7 @@ impl Connection {
                 self.streams.has_stopped() ||
                 self.ids.has_new_scids() ||
                 self.ids.has_retire_dcids() ||
+                (self.ack_frequency && self.ack_freq_send.has_pending()) ||
                 send_path.needs_ack_eliciting ||
                 send_path.probing_required())
         {
@@ -6626,9 +12320,8 @@ impl Connection {
     }
 
     /// Processes an incoming frame.
-    #[pluginop_result_param(po = "PluginOp::ProcessFrame", param = "ty")]
     fn process_frame_internal(
-        &mut self, ty: u64, frame: frame::Frame, hdr: &packet::Header,
+        &mut self, frame: frame::Frame, hdr: &packet::Header,
         recv_path_id: usize, epoch: packet::Epoch, now: time::Instant,
     ) -> Result<()> {
         trace!("{} rx frm {:?}", self.trace_id, frame);
@@ -6636,10 +12329,12 @@ impl Connection {
         match frame {
             frame::Frame::Padding { .. } => (),
 
-            frame::Frame::Ping => (),
+            frame::Frame::Ping { .. } => (),
 
             frame::Frame::ACK {
-                ranges, ack_delay, ..
+                ranges,
+                ack_delay,
+                ecn_counts,
             } => {
                 let ack_delay = ack_delay
                     .checked_mul(2_u64.pow(
@@ -6647,6 +12342,11 @@ impl Connection {
                     ))
                     .ok_or(Error::InvalidFrame)?;
 
+                if epoch == packet::Epoch::Application {
+                    self.ack_delay_received
+                        .on_sample(time::Duration::from_micros(ack_delay));
+                }
+
                 if epoch == packet::Epoch::Handshake {
                     self.peer_verified_initial_address = true;
                 }
@@ -6663,11 +12363,33 @@ impl Connection {
 
                 let is_app_limited = self.delivery_rate_check_if_app_limited();
 
-                for (_, p) in self.paths.iter_mut() {
+                // With multipath, plain ACK frames only acknowledge the packet
+                // number space of the initial Connection ID.
+                let multipath =
+                    self.multipath && epoch == packet::Epoch::Application;
+
+                // Paths on which ECN-marked packets were newly acknowledged.
+                let mut ecn_pids: SmallVec<[usize; 4]> = SmallVec::new();
+                let mut ecn_marked_acked = 0;
+
+                // Paths on which a new delivery rate sample was generated.
+                let mut sampled_pids: SmallVec<[usize; 4]> = SmallVec::new();
+
+                for (pid, p) in self.paths.iter_mut() {
+                    if multipath &&
+                        matches!(p.active_dcid_seq, Some(seq) if seq != 0)
+                    {
+                        continue;
+                    }
+
                     if is_app_limited {
                         p.recovery.delivery_rate_update_app_limited(true);
                     }
 
+                    let marked_acked = p.recovery.ecn_marked_acked;
+
+                    let samples = p.recovery.delivery_rate_samples();
+
                     let (lost_packets, lost_bytes) = p.recovery.on_ack_received(
                         &ranges,
                         ack_delay,
@@ -6679,6 +12401,76 @@ impl Connection {
 
                     self.lost_count += lost_packets;
                     self.lost_bytes += lost_bytes as u64;
+
+                    if let Some(metrics) = &self.metrics {
+                        metrics.incr_counter(
+                            Counter::PacketsLost,
+                            lost_packets as u64,
+                        );
+                        metrics.record_histogram(
+                            Histogram::Cwnd,
+                            p.recovery.cwnd() as f64,
+                        );
+                    }
+
+                    if p.recovery.ecn_marked_acked > marked_acked {
+                        ecn_marked_acked +=
+                            p.recovery.ecn_marked_acked - marked_acked;
+                        ecn_pids.push(pid);
+                    }
+
+                    if p.recovery.delivery_rate_samples() > samples {
+                        sampled_pids.push(pid);
+                    }
+                }
+
+                for pid in sampled_pids {
+                    self.on_delivery_rate_sample(pid);
+                }
+
+                let ecn_marked_lost: usize = self
+                    .paths
+                    .iter()
+                    .map(|(_, p)| p.recovery.ecn_marked_lost)
+                    .sum();
+
+                // An increase of the ECN-CE count is a congestion signal for
+                // the paths the marked packets were sent on.
+                if self.ecn.on_ack_received(
+                    epoch,
+                    ecn_counts.as_ref(),
+                    ecn_marked_acked,
+                    ecn_marked_lost,
+                ) {
+                    for pid in ecn_pids {
+                        self.paths.get_mut(pid)?.recovery.on_ecn_ce(epoch, now);
+                    }
+                }
+
+                // Acknowledging a packet protected with the current keys
+                // confirms the last key update. The previous keys are kept a
+                // bit longer for reordered packets.
+                if epoch == packet::Epoch::Application {
+                    let pto = self.paths.get(recv_path_id)?.recovery.pto();
+
+                    let ku = self.pkt_num_spaces[epoch].key_update.as_mut();
+
+                    if let Some(ku) = ku {
+                        if !ku.update_acked &&
+                            ranges
+                                .last()
+                                .map_or(false, |pn| pn >= ku.pn_sent_on_update)
+                        {
+                            ku.update_acked = true;
+                            ku.timer = Some(now + (pto * 3));
+
+                            self.key_update_events.push(if ku.local {
+                                KeyUpdateEvent::LocalConfirmed
+                            } else {
+                                KeyUpdateEvent::PeerConfirmed
+                            });
+                        }
+                    }
                 }
 
                 if self.handshake_confirmed {
@@ -6718,7 +12510,9 @@ impl Connection {
                     Err(e) => return Err(e),
                 };
 
-                let was_readable = stream.is_readable();
+                let was_readable = stream.is_readable_lowat();
+
+                let was_reset = stream.recv.is_reset();
 
                 let max_off_delta =
                     stream.recv.reset(error_code, final_size)? as u64;
@@ -6727,8 +12521,16 @@ impl Connection {
                     return Err(Error::FlowControl);
                 }
 
-                if !was_readable && stream.is_readable() {
-                    self.streams.mark_readable(stream_id, true);
+                if !was_reset {
+                    self.stream_events.push(StreamEvent::ResetReceived {
+                        stream_id,
+                        error_code,
+                        final_size,
+                    });
+                }
+
+                if !was_readable && stream.is_readable_lowat() {
+                    self.streams.mark_readable(stream_id, true);
                 }
 
                 self.rx_data += max_off_delta;
@@ -6767,6 +12569,11 @@ impl Connection {
 
                 // Try stopping the stream.
                 if let Ok((final_size, unsent)) = stream.send.stop(error_code) {
+                    self.stream_events.push(StreamEvent::StopSending {
+                        stream_id,
+                        error_code,
+                    });
+
                     // Claw back some flow control allowance from data that was
                     // buffered but not actually sent before the stream was
                     // reset.
@@ -6806,8 +12613,15 @@ impl Connection {
 
             frame::Frame::CryptoHeader { .. } => unreachable!(),
 
-            // TODO: implement stateless retry
-            frame::Frame::NewToken { .. } => (),
+            frame::Frame::NewToken { token } => {
+                if self.is_server {
+                    return Err(Error::InvalidPacket);
+                }
+
+                if let Some((store, server)) = &self.client_store {
+                    store.put_token(server, &token);
+                }
+            },
 
             frame::Frame::Stream { stream_id, data } => {
                 // Peer can't send on our unidirectional streams.
@@ -6832,7 +12646,12 @@ impl Connection {
                 let stream = match self.get_or_create_stream(stream_id, false) {
                     Ok(v) => v,
 
-                    Err(Error::Done) => return Ok(()),
+                    // All of the data of a collected stream was received.
+                    Err(Error::Done) => {
+                        self.on_stream_dup_bytes(data.len() as u64);
+
+                        return Ok(());
+                    },
 
                     Err(e) => return Err(e),
                 };
@@ -6845,16 +12664,28 @@ impl Connection {
                     return Err(Error::FlowControl);
                 }
 
-                let was_readable = stream.is_readable();
+                let was_readable = stream.is_readable_lowat();
 
                 let was_draining = stream.is_draining();
 
+                let was_fin_received = stream.recv.is_fin_received();
+
+                let dup_bytes = stream.recv.dup_bytes();
+
                 stream.recv.write(data)?;
 
-                if !was_readable && stream.is_readable() {
+                let dup_bytes = stream.recv.dup_bytes() - dup_bytes;
+
+                if !was_fin_received && stream.recv.is_fin_received() {
+                    self.stream_events.push(StreamEvent::Finished { stream_id });
+                }
+
+                if !was_readable && stream.is_readable_lowat() {
                     self.streams.mark_readable(stream_id, true);
                 }
 
+                self.on_stream_dup_bytes(dup_bytes);
+
                 self.rx_data += max_off_delta;
 
                 if was_draining {
@@ -7018,23 +12849,61 @@ impl Connection {
             },
 
             frame::Frame::PathChallenge { data } => {
-                self.paths
-                    .get_mut(recv_path_id)?
-                    .on_challenge_received(data);
+                // Don't let the peer make us send PATH_RESPONSE frames at an
+                // arbitrary rate.
+                if self.rate_limit_allow(RateLimited::PathResponse, now) {
+                    self.paths
+                        .get_mut(recv_path_id)?
+                        .on_challenge_received(data);
+                }
             },
 
             frame::Frame::PathResponse { data } => {
                 self.paths.on_response_received(data)?;
+
+                // Completes a migration waiting for the path to be validated.
+                if let Some(pid) = self.pending_migration {
+                    if self.paths.get(pid).map_or(false, |p| p.validated()) {
+                        self.pending_migration = None;
+
+                        self.complete_migration(pid)?;
+                    }
+                }
             },
 
             frame::Frame::ConnectionClose {
-                error_code, reason, ..
+                error_code,
+                frame_type,
+                reason,
             } => {
-                self.peer_error = Some(ConnectionError {
+                let err = ConnectionError {
                     is_app: false,
                     error_code,
+                    frame_type,
                     reason,
-                });
+                };
+
+                // Remember that the server refused the offered protocols, so
+                // the next connections can offer others. Initial packets are
+                // not authenticated, so they could be forged by an attacker
+                // to make the client give up on a protocol.
+                if !self.is_server &&
+                    epoch != packet::Epoch::Initial &&
+                    err.tls_alert() ==
+                        Some(tls::TLS_ALERT_NO_APPLICATION_PROTOCOL)
+                {
+                    let protos = &self.application_protos;
+
+                    self.update_server_hints(|hints| {
+                        for p in protos {
+                            if !hints.rejected_protos.contains(p) {
+                                hints.rejected_protos.push(p.clone());
+                            }
+                        }
+                    });
+                }
+
+                self.peer_error = Some(err);
 
                 let path = self.paths.get_active()?;
                 self.draining_timer = Some(now + (path.recovery.pto() * 3));
@@ -7044,6 +12913,7 @@ impl Connection {
                 self.peer_error = Some(ConnectionError {
                     is_app: true,
                     error_code,
+                    frame_type: 0,
                     reason,
                 });
 
@@ -7081,9 +12951,132 @@ impl Connection {
                 self.dgram_recv_queue.push(data)?;
             },
 
+            frame::Frame::MpAck { .. } |
+            frame::Frame::PathAbandon { .. } |
+            frame::Frame::PathStatus { .. }
+                if !self.multipath =>
+                return Err(Error::InvalidPacket),
+
+            frame::Frame::MpAck {
+                space_identifier,
+                ack_delay,
+                ranges,
+                ..
+            } => {
+                let ack_delay = ack_delay
+                    .checked_mul(2_u64.pow(
+                        self.peer_transport_params.ack_delay_exponent as u32,
+                    ))
+                    .ok_or(Error::InvalidFrame)?;
+
+                self.ack_delay_received
+                    .on_sample(time::Duration::from_micros(ack_delay));
+
+                // The path might have been closed in the meantime.
+                let pid = match self.paths.path_id_from_dcid_seq(space_identifier)
+                {
+                    Some(v) => v,
+
+                    None => return Ok(()),
+                };
+
+                let handshake_status = self.handshake_status();
+
+                let is_app_limited = self.delivery_rate_check_if_app_limited();
+
+                let p = self.paths.get_mut(pid)?;
+
+                if is_app_limited {
+                    p.recovery.delivery_rate_update_app_limited(true);
+                }
+
+                let samples = p.recovery.delivery_rate_samples();
+
+                let (lost_packets, lost_bytes) = p.recovery.on_ack_received(
+                    &ranges,
+                    ack_delay,
+                    packet::Epoch::Application,
+                    handshake_status,
+                    now,
+                    &self.trace_id,
+                )?;
+
+                let sampled = p.recovery.delivery_rate_samples() > samples;
+
+                self.lost_count += lost_packets;
+                self.lost_bytes += lost_bytes as u64;
+
+                if let Some(metrics) = &self.metrics {
+                    metrics
+                        .incr_counter(Counter::PacketsLost, lost_packets as u64);
+                    metrics.record_histogram(
+                        Histogram::Cwnd,
+                        p.recovery.cwnd() as f64,
+                    );
+                }
+
+                if sampled {
+                    self.on_delivery_rate_sample(pid);
+                }
+            },
+
+            frame::Frame::PathAbandon { dcid_seq_num, .. } => {
+                if let Some(pid) = self.paths.path_id_from_dcid_seq(dcid_seq_num)
+                {
+                    self.paths.on_path_abandoned(pid)?;
+
+                    self.redundant_streams.remove(&pid);
+                }
+            },
+
+            frame::Frame::PathStatus {
+                dcid_seq_num,
+                seq_num,
+                status,
+            } => {
+                let status = path::PathStatus::from_wire(status)?;
+
+                if let Some(pid) = self.paths.path_id_from_dcid_seq(dcid_seq_num)
+                {
+                    self.paths.get_mut(pid)?.on_status_received(seq_num, status);
+                }
+            },
+
+            frame::Frame::AckFrequency { .. } | frame::Frame::ImmediateAck
+                if self.local_transport_params.min_ack_delay.is_none() =>
+                return Err(Error::InvalidPacket),
+
+            frame::Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                // The peer can't ask for a delay lower than our min_ack_delay.
+                if Some(request_max_ack_delay) <
+                    self.local_transport_params.min_ack_delay
+                {
+                    return Err(Error::InvalidFrame);
+                }
+
+                let params = ack_freq::AckFrequencyParams {
+                    ack_eliciting_threshold,
+                    max_ack_delay: time::Duration::from_micros(
+                        request_max_ack_delay,
+                    ),
+                    reordering_threshold,
+                };
+
+                self.ack_freq_recv.on_ack_frequency(seq_num, params);
+            },
+
+            frame::Frame::ImmediateAck => self.ack_freq_recv.on_immediate_ack(),
+
             frame::Frame::DatagramHeader { .. } => unreachable!(),
 
-            frame::Frame::Extension { .. } => unreachable!(),
+            // Extension frames are only parsed by plugins, which are then
+            // expected to process them as well.
+            frame::Frame::Extension { .. } => return Err(Error::InvalidFrame),
         }
 
         Ok(())
@@ -7093,31 +13086,99 @@ impl Connection {
         &mut self, frame: frame::Frame, hdr: &packet::Header,
         recv_path_id: usize, epoch: packet::Epoch, now: time::Instant,
     ) -> Result<()> {
-        self.process_frame_internal(
-            frame.ty(),
-            frame,
-            hdr,
-            recv_path_id,
-            epoch,
-            now,
-        )
-    }
+        #[cfg(feature = "plugins")]
+        {
+            use pluginop::IntoWithPH;
+            use pluginop::TryFromWithPH;
+
+            let res =
+                self.plugin_define(PluginOp::ProcessFrame(frame.ty()), |ph| {
+                    Ok(vec![
+                        PluginVal::try_from_with_ph(frame.clone(), ph)?,
+                        hdr.clone().into_with_ph(ph),
+                        epoch.into_with_ph(ph),
+                    ])
+                });
 
-    /// Parses an incoming frame.
-    #[pluginop_result_param(po = "PluginOp::ParseFrame", param = "frame_type")]
-    fn parse_frame_internal(
-        &mut self, frame_type: u64, payload: &mut octets::Octets,
-        pkt: packet::Type,
-    ) -> Result<frame::Frame> {
-        frame::Frame::from_bytes_with_type(frame_type, payload, pkt)
+            if res.is_some() {
+                return Ok(());
+            }
+        }
+
+        let stream_id = match frame {
+            frame::Frame::Stream { stream_id, .. } |
+            frame::Frame::ResetStream { stream_id, .. } |
+            frame::Frame::StopSending { stream_id, .. } |
+            frame::Frame::MaxStreamData { stream_id, .. } => Some(stream_id),
+
+            _ => None,
+        };
+
+        self.process_frame_internal(frame, hdr, recv_path_id, epoch, now)?;
+
+        if let Some(stream_id) = stream_id {
+            self.plugin_stream_state(stream_id, now);
+        }
+
+        Ok(())
     }
 
+    /// Parses an incoming frame.
     fn parse_frame(
         &mut self, payload: &mut octets::Octets, pkt: packet::Type,
     ) -> Result<frame::Frame> {
         let frame_type = payload.get_varint()?;
 
-        self.parse_frame_internal(frame_type, payload, pkt)
+        #[cfg(feature = "plugins")]
+        {
+            use octets::OctetsPtr;
+            use pluginop::IntoWithPH;
+
+            let res =
+                self.plugin_define(PluginOp::ParseFrame(frame_type), |ph| {
+                    Ok(vec![
+                        OctetsPtr::from(&mut *payload).into_with_ph(ph),
+                        pkt.into_with_ph(ph),
+                    ])
+                });
+
+            if let Some(v) = res.and_then(|v| v.into_iter().next()) {
+                return self.plugin_frame(v);
+            }
+        }
+
+        frame::Frame::from_bytes_with_factory(
+            frame_type,
+            payload,
+            pkt,
+            self.buf_factory.as_deref(),
+        )
+    }
+
+    /// Returns the largest packet number received in the given epoch and
+    /// multipath packet number space.
+    fn largest_rx_pkt_num(&self, epoch: packet::Epoch, mp_space_id: u64) -> u64 {
+        if mp_space_id == 0 {
+            return self.pkt_num_spaces[epoch].largest_rx_pkt_num;
+        }
+
+        self.mp_recv_spaces
+            .get(&mp_space_id)
+            .map_or(0, |s| s.largest_rx_pkt_num)
+    }
+
+    /// Returns the packet number space tracking packets received in the given
+    /// epoch and multipath packet number space, creating it if needed.
+    fn recv_pkt_num_space_mut(
+        &mut self, epoch: packet::Epoch, mp_space_id: u64,
+    ) -> &mut packet::PktNumSpace {
+        if mp_space_id == 0 {
+            return &mut self.pkt_num_spaces[epoch];
+        }
+
+        self.mp_recv_spaces
+            .entry(mp_space_id)
+            .or_insert_with(packet::PktNumSpace::new)
     }
 
     /// Drops the keys and recovery state for the given epoch.
@@ -7129,6 +13190,7 @@ impl Connection {
         self.pkt_num_spaces[epoch].crypto_open = None;
         self.pkt_num_spaces[epoch].crypto_seal = None;
         self.pkt_num_spaces[epoch].clear();
+        self.pkt_num_spaces[epoch].keys_discarded = true;
 
         let handshake_status = self.handshake_status();
         for (_, p) in self.paths.iter_mut() {
@@ -7152,6 +13214,39 @@ impl Connection {
         self.flow_control.max_data()
     }
 
+    /// Accounts for duplicate STREAM bytes received, and raises the alarm
+    /// once they exceed the configured threshold.
+    fn on_stream_dup_bytes(&mut self, dup_bytes: u64) {
+        if dup_bytes == 0 {
+            return;
+        }
+
+        self.stream_dup_bytes += dup_bytes;
+
+        let threshold = match self.stream_dup_alarm {
+            Some(v) if !self.stream_dup_alarm_raised => v,
+
+            _ => return,
+        };
+
+        if self.stream_dup_bytes <= threshold {
+            return;
+        }
+
+        self.stream_dup_alarm_raised = true;
+
+        trace!(
+            "{} received {} duplicate stream bytes, above threshold of {}",
+            self.trace_id,
+            self.stream_dup_bytes,
+            threshold
+        );
+
+        if let Some(metrics) = &self.metrics {
+            metrics.incr_counter(Counter::StreamDupAlarms, 1);
+        }
+    }
+
     /// Returns true if the HANDSHAKE_DONE frame needs to be sent.
     fn should_send_handshake_done(&self) -> bool {
         self.is_established() && !self.handshake_done_sent && self.is_server
@@ -7194,6 +13289,24 @@ impl Connection {
         Some(idle_timeout)
     }
 
+    /// Re-arms the keep-alive timer, if keep-alive is enabled.
+    fn reset_keepalive_timer(&mut self, now: time::Instant) {
+        let interval = match self.keepalive_interval {
+            Some(v) if self.is_established() => v,
+
+            _ => return,
+        };
+
+        // Make sure the PING frame is sent before the idle timeout expires.
+        let interval = match self.idle_timeout() {
+            Some(idle_timeout) => cmp::min(interval, idle_timeout / 2),
+
+            None => interval,
+        };
+
+        self.keepalive_timer = Some(now + interval);
+    }
+
     /// Returns the connection's handshake status for use in loss recovery.
     fn handshake_status(&self) -> recovery::HandshakeStatus {
         recovery::HandshakeStatus {
@@ -7345,11 +13458,16 @@ impl Connection {
         let mut path =
             path::Path::new(info.to, info.from, &self.recovery_config, false);
 
+        #[cfg(feature = "plugins")]
         if let Some(pc) = self.pc.as_mut() {
             path.recovery.set_pluginizable_connection(&mut **pc);
         }
 
-        path.max_send_bytes = buf_len * MAX_AMPLIFICATION_FACTOR;
+        path.update_max_pmtu(
+            self.peer_transport_params.max_udp_payload_size as usize,
+        );
+
+        path.max_send_bytes = self.anti_amplification_credit(buf_len);
         path.active_scid_seq = Some(in_scid_seq);
 
         // Automatically probes the new path.
@@ -7367,7 +13485,7 @@ impl Connection {
 
     /// Selects the path on which the next packet must be sent.
     fn get_send_path_id(
-        &self, from: Option<SocketAddr>, to: Option<SocketAddr>,
+        &mut self, from: Option<SocketAddr>, to: Option<SocketAddr>,
     ) -> Result<usize> {
         // A probing packet must be sent, but only if the connection is fully
         // established.
@@ -7384,6 +13502,10 @@ impl Connection {
             if let Some(pid) = probing.next() {
                 return Ok(pid);
             }
+
+            if self.multipath {
+                return self.schedule_send_path(from, to);
+            }
         }
 
         if let Some((pid, p)) = self.paths.get_active_with_pid() {
@@ -7401,2003 +13523,5554 @@ impl Connection {
         Err(Error::InvalidState)
     }
 
-    /// Creates a new client-side path.
-    fn create_path_on_client(
-        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    /// Drops the stream data queued by the redundant path scheduler on paths
+    /// that can't be used anymore, and the data already acknowledged by the
+    /// peer on another path.
+    fn prune_redundant_streams(&mut self) {
+        let paths = &self.paths;
+        let streams = &self.streams;
+
+        self.redundant_streams.retain(|pid, ranges| {
+            if !paths.get(*pid).map_or(false, |p| p.active()) {
+                return false;
+            }
+
+            ranges.retain(|(stream_id, offset, length, _)| {
+                streams
+                    .get(*stream_id)
+                    .map_or(false, |s| !s.send.is_acked(*offset, *length))
+            });
+
+            !ranges.is_empty()
+        });
+    }
+
+    /// Selects the path on which the next packet must be sent when the
+    /// multipath extension is in use, using the configured path scheduler.
+    fn schedule_send_path(
+        &mut self, from: Option<SocketAddr>, to: Option<SocketAddr>,
     ) -> Result<usize> {
-        if self.is_server {
-            return Err(Error::InvalidState);
+        let mut paths: SmallVec<[(usize, PathCandidate); 4]> = self
+            .paths
+            .iter()
+            .filter(|(_, p)| from.is_none() || Some(p.local_addr()) == from)
+            .filter(|(_, p)| to.is_none() || Some(p.peer_addr()) == to)
+            .filter(|(_, p)| p.active())
+            .map(|(pid, p)| {
+                (pid, PathCandidate {
+                    local_addr: p.local_addr(),
+                    peer_addr: p.peer_addr(),
+                    rtt: p.recovery.rtt(),
+                    cwnd_available: p.recovery.cwnd_available(),
+                })
+            })
+            .collect();
+
+        if paths.is_empty() {
+            return Err(Error::Done);
         }
 
-        // If we use zero-length SCID and go over our local active CID limit,
-        // the `insert_path()` call will raise an error.
-        if !self.ids.zero_length_scid() && self.ids.available_scids() == 0 {
-            return Err(Error::OutOfIdentifiers);
+        // Stream data duplicated by a redundant scheduler goes first.
+        self.prune_redundant_streams();
+
+        let redundant = &self.redundant_streams;
+        if let Some((pid, _)) =
+            paths.iter().find(|(pid, _)| redundant.contains_key(pid))
+        {
+            return Ok(*pid);
         }
 
-        // Do we have a spare DCID? If we are using zero-length DCID, just use
-        // the default having sequence 0 (note that if we exceed our local CID
-        // limit, the `insert_path()` call will raise an error.
-        let dcid_seq = if self.ids.zero_length_dcid() {
-            0
-        } else {
-            self.ids
-                .lowest_available_dcid_seq()
-                .ok_or(Error::OutOfIdentifiers)?
+        // Data of streams pinned to a path can only be sent on that path, so
+        // it goes first while its congestion window allows.
+        let streams = &self.streams;
+        self.stream_path_affinity
+            .retain(|stream_id, _| streams.get(*stream_id).is_some());
+
+        let has_pinned_data = |pid: usize| {
+            self.stream_path_affinity.iter().any(|(stream_id, addrs)| {
+                self.paths.path_id_from_addrs(addrs) == Some(pid) &&
+                    self.streams
+                        .get(*stream_id)
+                        .map_or(false, |s| s.is_flushable())
+            })
         };
 
-        let mut path =
-            path::Path::new(local_addr, peer_addr, &self.recovery_config, false);
-        path.active_dcid_seq = Some(dcid_seq);
-        if let Some(pc) = self.pc.as_mut() {
-            path.recovery.set_pluginizable_connection(&mut **pc);
+        if let Some((pid, _)) = paths
+            .iter()
+            .find(|(pid, c)| c.cwnd_available > 0 && has_pinned_data(*pid))
+        {
+            return Ok(*pid);
         }
 
-        let pid = self
-            .paths
-            .insert_path(path, false)
-            .map_err(|_| Error::OutOfIdentifiers)?;
-        self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
+        // Only use standby paths when no other path is available.
+        let is_standby = |pid: usize| {
+            self.paths
+                .get(pid)
+                .map(|p| p.status() == path::PathStatus::Standby)
+                .unwrap_or(false)
+        };
 
-        Ok(pid)
-    }
-}
+        if paths.iter().any(|(pid, _)| !is_standby(*pid)) {
+            paths.retain(|(pid, _)| !is_standby(*pid));
+        }
 
-/// Maps an `Error` to `Error::Done`, or itself.
-///
-/// When a received packet that hasn't yet been authenticated triggers a failure
-/// it should, in most cases, be ignored, instead of raising a connection error,
-/// to avoid potential man-in-the-middle and man-on-the-side attacks.
-///
-/// However, if no other packet was previously received, the connection should
-/// indeed be closed as the received packet might just be network background
-/// noise, and it shouldn't keep resources occupied indefinitely.
-///
-/// This function maps an error to `Error::Done` to ignore a packet failure
-/// without aborting the connection, except when no other packet was previously
-/// received, in which case the error itself is returned, but only on the
-/// server-side as the client will already have armed the idle timer.
-///
-/// This must only be used for errors preceding packet authentication. Failures
-/// happening after a packet has been authenticated should still cause the
-/// connection to be aborted.
-fn drop_pkt_on_err(
-    e: Error, recv_count: usize, is_server: bool, trace_id: &str,
-) -> Error {
-    // On the server, if no other packet has been successfully processed, abort
-    // the connection to avoid keeping the connection open when only junk is
-    // received.
-    if is_server && recv_count == 0 {
-        return e;
-    }
+        let candidates: SmallVec<[PathCandidate; 4]> =
+            paths.iter().map(|(_, c)| *c).collect();
 
-    trace!("{} dropped invalid packet", trace_id);
+        let selected = match self.plugin_select_send_path(&candidates) {
+            Some(i) => Some(i),
 
-    // Ignore other invalid packets that haven't been authenticated to prevent
-    // man-in-the-middle and man-on-the-side attacks.
-    Error::Done
-}
+            None => self.path_scheduler.select(&candidates),
+        };
 
-struct AddrTupleFmt(SocketAddr, SocketAddr);
+        let pid = selected.and_then(|i| paths.get(i)).unwrap_or(&paths[0]).0;
 
-impl std::fmt::Display for AddrTupleFmt {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let AddrTupleFmt(src, dst) = &self;
+        Ok(pid)
+    }
 
-        if src.ip().is_unspecified() || dst.ip().is_unspecified() {
-            return Ok(());
-        }
+    /// Returns true if the data of the given stream must be sent on another
+    /// active path than `send_pid`.
+    fn stream_pinned_to_other_path(
+        &self, stream_id: u64, send_pid: usize,
+    ) -> bool {
+        let pid = match self.stream_path_affinity.get(&stream_id) {
+            Some(addrs) => self.paths.path_id_from_addrs(addrs),
 
-        f.write_fmt(format_args!("src:{src} dst:{dst}"))
+            None => return false,
+        };
+
+        match pid {
+            Some(pid) if pid != send_pid =>
+                self.paths.get(pid).map_or(false, |p| p.active()),
+
+            _ => false,
+        }
     }
-}
 
-/// Statistics about the connection.
-///
-/// A connection's statistics can be collected using the [`stats()`] method.
-///
-/// [`stats()`]: struct.Connection.html#method.stats
-#[derive(Clone, Default)]
-pub struct Stats {
-    /// The number of QUIC packets received.
-    pub recv: usize,
+    /// Lets a plugin select the path of the next packet, if one implements
+    /// the `select_send_path` operation.
+    ///
+    /// The plugin receives the RTT in microseconds and the available
+    /// congestion window of each candidate path, and returns the index of the
+    /// selected one.
+    #[cfg(feature = "plugins")]
+    fn plugin_select_send_path(
+        &mut self, candidates: &[PathCandidate],
+    ) -> Option<usize> {
+        if !self.plugin_call_allowed(time::Instant::now()) {
+            return None;
+        }
 
-    /// The number of QUIC packets sent.
-    pub sent: usize,
+        let metrics = self.metrics.clone();
 
-    /// The number of QUIC packets that were lost.
-    pub lost: usize,
+        let ph = self
+            .get_pluginizable_connection()
+            .map(|pc| pc.get_ph_mut())?;
 
-    /// The number of sent QUIC packets with retransmitted data.
-    pub retrans: usize,
+        let op = plugin::select_send_path_op();
 
-    /// The number of sent bytes.
-    pub sent_bytes: u64,
+        let params: Vec<PluginVal> = candidates
+            .iter()
+            .flat_map(|c| {
+                [
+                    PluginVal::U64(c.rtt.as_micros() as u64),
+                    PluginVal::U64(c.cwnd_available as u64),
+                ]
+            })
+            .collect();
 
-    /// The number of received bytes.
-    pub recv_bytes: u64,
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &params, || ph.call(&op, &params))
+        });
 
-    /// The number of bytes sent lost.
-    pub lost_bytes: u64,
+        match self.plugin_result(res)?.first() {
+            Some(PluginVal::U64(i)) => Some(*i as usize),
 
-    /// The number of stream bytes retransmitted.
-    pub stream_retrans_bytes: u64,
+            _ => None,
+        }
+    }
 
-    /// The number of known paths for the connection.
-    pub paths_count: usize,
+    /// Updates the ACK rate requested from the peer with the ACK frequency
+    /// extension.
+    ///
+    /// By default the Ack-Eliciting Threshold follows the congestion window of
+    /// the given path, but a plugin can select other parameters.
+    fn update_ack_frequency(&mut self, send_pid: usize) -> Result<()> {
+        let recovery = &self.paths.get(send_pid)?.recovery;
 
-    /// The maximum idle timeout.
-    pub peer_max_idle_timeout: u64,
+        let cwnd = recovery.cwnd();
+        let rtt = recovery.rtt();
 
-    /// The maximum UDP payload size.
-    pub peer_max_udp_payload_size: u64,
+        let peer_max_ack_delay =
+            time::Duration::from_millis(self.peer_transport_params.max_ack_delay);
+        let peer_min_ack_delay = time::Duration::from_micros(
+            self.peer_transport_params.min_ack_delay.unwrap_or(0),
+        );
 
-    /// The initial flow control maximum data for the connection.
-    pub peer_initial_max_data: u64,
+        let mut params = ack_freq::AckFrequencyParams::from_cwnd(
+            cwnd,
+            recovery.max_datagram_size(),
+            peer_max_ack_delay,
+            peer_min_ack_delay,
+        );
 
-    /// The initial flow control maximum data for local bidirectional streams.
-    pub peer_initial_max_stream_data_bidi_local: u64,
+        if let Some(v) = self.plugin_ack_frequency(params, cwnd, rtt) {
+            params = v;
+        }
 
-    /// The initial flow control maximum data for remote bidirectional streams.
-    pub peer_initial_max_stream_data_bidi_remote: u64,
+        // The peer can't delay ACKs for less than its min_ack_delay.
+        params.max_ack_delay = cmp::max(params.max_ack_delay, peer_min_ack_delay);
 
-    /// The initial flow control maximum data for unidirectional streams.
-    pub peer_initial_max_stream_data_uni: u64,
+        // Loss detection needs to account for the largest delay the peer can
+        // use.
+        if self.ack_freq_send.requested() != Some(params) {
+            let max_ack_delay =
+                cmp::max(peer_max_ack_delay, params.max_ack_delay);
 
-    /// The initial maximum bidirectional streams.
-    pub peer_initial_max_streams_bidi: u64,
+            self.recovery_config.max_ack_delay = max_ack_delay;
 
-    /// The initial maximum unidirectional streams.
-    pub peer_initial_max_streams_uni: u64,
+            for (_, p) in self.paths.iter_mut() {
+                p.recovery.max_ack_delay = max_ack_delay;
+            }
+        }
 
-    /// The ACK delay exponent.
-    pub peer_ack_delay_exponent: u64,
+        self.ack_freq_send.update(params);
 
-    /// The max ACK delay.
-    pub peer_max_ack_delay: u64,
+        Ok(())
+    }
 
-    /// Whether active migration is disabled.
-    pub peer_disable_active_migration: bool,
+    /// Lets a plugin select the ACK rate requested from the peer, if one
+    /// implements the `ack_frequency` operation.
+    ///
+    /// The plugin receives the congestion window, the RTT in microseconds and
+    /// the parameters selected by default, and returns the Ack-Eliciting
+    /// Threshold, the maximum ACK delay in microseconds and the Reordering
+    /// Threshold to request.
+    #[cfg(feature = "plugins")]
+    fn plugin_ack_frequency(
+        &mut self, params: ack_freq::AckFrequencyParams, cwnd: usize,
+        rtt: time::Duration,
+    ) -> Option<ack_freq::AckFrequencyParams> {
+        if !self.plugin_call_allowed(time::Instant::now()) {
+            return None;
+        }
 
-    /// The active connection ID limit.
-    pub peer_active_conn_id_limit: u64,
+        let metrics = self.metrics.clone();
 
-    /// DATAGRAM frame extension parameter, if any.
-    pub peer_max_datagram_frame_size: Option<u64>,
-}
+        let ph = self
+            .get_pluginizable_connection()
+            .map(|pc| pc.get_ph_mut())?;
 
-impl std::fmt::Debug for Stats {
-    #[inline]
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "recv={} sent={} lost={} retrans={}",
-            self.recv, self.sent, self.lost, self.retrans,
-        )?;
+        let op = plugin::ack_frequency_op();
 
-        write!(
-            f,
-            " sent_bytes={} recv_bytes={} lost_bytes={}",
-            self.sent_bytes, self.recv_bytes, self.lost_bytes,
-        )?;
+        let args = [
+            PluginVal::U64(cwnd as u64),
+            PluginVal::U64(rtt.as_micros() as u64),
+            PluginVal::U64(params.ack_eliciting_threshold),
+            PluginVal::U64(params.max_ack_delay.as_micros() as u64),
+            PluginVal::U64(params.reordering_threshold),
+        ];
 
-        write!(f, " peer_tps={{")?;
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &args, || ph.call(&op, &args))
+        });
 
-        write!(f, " max_idle_timeout={},", self.peer_max_idle_timeout)?;
+        let res = self.plugin_result(res)?;
 
-        write!(
-            f,
-            " max_udp_payload_size={},",
-            self.peer_max_udp_payload_size,
-        )?;
+        let (threshold, delay, reordering) = match &res[..] {
+            [PluginVal::U64(t), PluginVal::U64(d), PluginVal::U64(r), ..] =>
+                (*t, *d, *r),
 
-        write!(f, " initial_max_data={},", self.peer_initial_max_data)?;
+            _ => return None,
+        };
 
-        write!(
-            f,
-            " initial_max_stream_data_bidi_local={},",
-            self.peer_initial_max_stream_data_bidi_local,
-        )?;
+        Some(ack_freq::AckFrequencyParams {
+            ack_eliciting_threshold: threshold,
+            max_ack_delay: time::Duration::from_micros(delay),
+            reordering_threshold: reordering,
+        })
+    }
 
-        write!(
-            f,
-            " initial_max_stream_data_bidi_remote={},",
-            self.peer_initial_max_stream_data_bidi_remote,
-        )?;
+    /// Lets a plugin make room in the full DATAGRAM send queue, if one
+    /// implements the `dgram_send_queue_full` operation.
+    ///
+    /// The plugin receives the size of the DATAGRAM being queued and the state
+    /// of the send queue, and returns how many queued DATAGRAMs to keep and
+    /// to requeue. If it doesn't make room, the new DATAGRAM is rejected.
+    #[cfg(all(feature = "plugins", feature = "datagram"))]
+    fn plugin_dgram_send_queue_full(&mut self, len: usize) {
+        let now = time::Instant::now();
 
-        write!(
-            f,
-            " initial_max_stream_data_uni={},",
-            self.peer_initial_max_stream_data_uni,
-        )?;
+        if !self.plugin_call_allowed(now) {
+            return;
+        }
 
-        write!(
-            f,
-            " initial_max_streams_bidi={},",
-            self.peer_initial_max_streams_bidi,
-        )?;
+        let metrics = self.metrics.clone();
 
-        write!(
-            f,
-            " initial_max_streams_uni={},",
-            self.peer_initial_max_streams_uni,
-        )?;
+        let field = |f| PluginVal::U64(self.dgram_get_field(f, now));
 
-        write!(f, " ack_delay_exponent={},", self.peer_ack_delay_exponent)?;
+        let params = [
+            PluginVal::U64(len as u64),
+            field(dgram::DatagramField::SendQueueLen),
+            field(dgram::DatagramField::SendQueueByteSize),
+            field(dgram::DatagramField::SendQueueFrontLen),
+            field(dgram::DatagramField::SendQueueFrontAge),
+        ];
 
-        write!(f, " max_ack_delay={},", self.peer_max_ack_delay)?;
+        let ph = match self.get_pluginizable_connection() {
+            Some(pc) => pc.get_ph_mut(),
 
-        write!(
-            f,
-            " disable_active_migration={},",
-            self.peer_disable_active_migration,
-        )?;
+            None => return,
+        };
 
-        write!(
-            f,
-            " active_conn_id_limit={},",
-            self.peer_active_conn_id_limit,
-        )?;
+        let op = plugin::dgram_send_queue_full_op();
 
-        write!(
-            f,
-            " max_datagram_frame_size={:?}",
-            self.peer_max_datagram_frame_size,
-        )?;
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &params, || ph.call(&op, &params))
+        });
 
-        write!(f, "}}")
-    }
-}
+        let res = match self.plugin_result(res) {
+            Some(v) => v,
 
-#[derive(Clone, Debug, PartialEq)]
-struct TransportParams {
-    pub original_destination_connection_id: Option<ConnectionId<'static>>,
-    pub max_idle_timeout: u64,
-    pub stateless_reset_token: Option<u128>,
-    pub max_udp_payload_size: u64,
-    pub initial_max_data: u64,
-    pub initial_max_stream_data_bidi_local: u64,
-    pub initial_max_stream_data_bidi_remote: u64,
-    pub initial_max_stream_data_uni: u64,
-    pub initial_max_streams_bidi: u64,
-    pub initial_max_streams_uni: u64,
-    pub ack_delay_exponent: u64,
-    pub max_ack_delay: u64,
-    pub disable_active_migration: bool,
-    // pub preferred_address: ...,
-    pub active_conn_id_limit: u64,
-    pub initial_source_connection_id: Option<ConnectionId<'static>>,
-    pub retry_source_connection_id: Option<ConnectionId<'static>>,
-    pub max_datagram_frame_size: Option<u64>,
-}
+            None => return,
+        };
 
-impl Default for TransportParams {
-    fn default() -> TransportParams {
-        TransportParams {
-            original_destination_connection_id: None,
-            max_idle_timeout: 0,
-            stateless_reset_token: None,
-            max_udp_payload_size: 65527,
-            initial_max_data: 0,
-            initial_max_stream_data_bidi_local: 0,
-            initial_max_stream_data_bidi_remote: 0,
-            initial_max_stream_data_uni: 0,
-            initial_max_streams_bidi: 0,
-            initial_max_streams_uni: 0,
-            ack_delay_exponent: 3,
-            max_ack_delay: 25,
-            disable_active_migration: false,
-            active_conn_id_limit: 2,
-            initial_source_connection_id: None,
-            retry_source_connection_id: None,
-            max_datagram_frame_size: None,
+        if let Some(PluginVal::U64(keep)) = res.first() {
+            let field = dgram::DatagramField::SendQueueLen;
+            self.dgram_set_field(field, *keep).ok();
+        }
+
+        if let Some(PluginVal::U64(requeue)) = res.get(1) {
+            let field = dgram::DatagramField::SendQueueRequeue;
+            self.dgram_set_field(field, *requeue).ok();
         }
     }
-}
 
-impl TransportParams {
-    fn decode(
-        mut conn: Option<&mut Connection>, buf: &[u8], is_server: bool,
-    ) -> Result<TransportParams> {
-        let mut params = octets::Octets::with_slice(buf);
-        let mut seen_params = HashSet::new();
+    /// Queues the DATAGRAM written by a plugin, if one implements the
+    /// `dgram_enqueue` operation.
+    #[cfg(all(feature = "plugins", feature = "datagram"))]
+    fn plugin_dgram_enqueue(&mut self, now: time::Instant) {
+        use pluginop::IntoWithPH;
 
-        let mut tp = TransportParams::default();
+        let max_len = match self.dgram_max_writable_len() {
+            Some(v) => v,
 
-        while params.cap() > 0 {
-            let id = params.get_varint()?;
+            None => return,
+        };
 
-            if seen_params.contains(&id) {
-                return Err(Error::InvalidTransportParam);
-            }
-            seen_params.insert(id);
+        if self.dgram_send_queue.is_full() || !self.plugin_call_allowed(now) {
+            return;
+        }
 
-            let mut val = params.get_bytes_with_varint_length()?;
+        let metrics = self.metrics.clone();
 
-            if let Some(ph) = conn.as_mut().and_then(|c| {
-                c.get_pluginizable_connection().map(|pc| pc.get_ph_mut())
-            }) {
-                use octets::OctetsPtr;
-                use pluginop::IntoWithPH;
-                let params = &[OctetsPtr::from(&mut val).into_with_ph(ph)];
-                ph.call(&PluginOp::DecodeTransportParameter(id), params)
-                    .ok();
-            }
+        let queue_len = self.dgram_send_queue.len() as u64;
 
-            match id {
-                0x0000 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let ph = match self.get_pluginizable_connection() {
+            Some(pc) => pc.get_ph_mut(),
 
-                    tp.original_destination_connection_id =
-                        Some(val.to_vec().into());
-                },
+            None => return,
+        };
 
-                0x0001 => {
-                    tp.max_idle_timeout = val.get_varint()?;
-                },
+        let mut buf = vec![0; max_len];
+        let mut b = octets::OctetsMut::with_slice(&mut buf);
 
-                0x0002 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let op = plugin::dgram_enqueue_op();
+        let params = &[
+            OctetsMutPtr::from(&mut b).into_with_ph(ph),
+            PluginVal::U64(queue_len),
+        ];
 
-                    tp.stateless_reset_token = Some(u128::from_be_bytes(
-                        val.get_bytes(16)?
-                            .to_vec()
-                            .try_into()
-                            .map_err(|_| Error::BufferTooShort)?,
-                    ));
-                },
-
-                0x0003 => {
-                    tp.max_udp_payload_size = val.get_varint()?;
-
-                    if tp.max_udp_payload_size < 1200 {
-                        return Err(Error::InvalidTransportParam);
-                    }
-                },
-
-                0x0004 => {
-                    tp.initial_max_data = val.get_varint()?;
-                },
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, params, || ph.call(&op, params))
+        });
 
-                0x0005 => {
-                    tp.initial_max_stream_data_bidi_local = val.get_varint()?;
-                },
+        let len = b.off();
 
-                0x0006 => {
-                    tp.initial_max_stream_data_bidi_remote = val.get_varint()?;
-                },
+        if self.plugin_result(res).is_none() || len == 0 {
+            return;
+        }
 
-                0x0007 => {
-                    tp.initial_max_stream_data_uni = val.get_varint()?;
-                },
+        buf.truncate(len);
 
-                0x0008 => {
-                    let max = val.get_varint()?;
+        if let Err(e) = self.dgram_send_vec(buf) {
+            trace!("{} plugin DATAGRAM dropped: {:?}", self.trace_id, e);
+        }
+    }
 
-                    if max > MAX_STREAM_ID {
-                        return Err(Error::InvalidTransportParam);
-                    }
+    /// Passes the experiments enabled on the connection to the plugin
+    /// implementing the `experiments` operation, if any.
+    ///
+    /// This is called each time a plugin is inserted, so that plugins inserted
+    /// after the others also receive them.
+    #[cfg(feature = "plugins")]
+    fn plugin_experiments(&mut self) {
+        let metrics = self.metrics.clone();
 
-                    tp.initial_max_streams_bidi = max;
-                },
+        let params = [PluginVal::U64(self.experiments.bits())];
 
-                0x0009 => {
-                    let max = val.get_varint()?;
+        let ph = match self.get_pluginizable_connection() {
+            Some(pc) => pc.get_ph_mut(),
 
-                    if max > MAX_STREAM_ID {
-                        return Err(Error::InvalidTransportParam);
-                    }
+            None => return,
+        };
 
-                    tp.initial_max_streams_uni = max;
-                },
+        let op = plugin::experiments_op();
 
-                0x000a => {
-                    let ack_delay_exponent = val.get_varint()?;
+        // Not all plugins are interested in experiments.
+        if !ph.provides(&op, pluginop::common::Anchor::Define) {
+            return;
+        }
 
-                    if ack_delay_exponent > 20 {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &params, || ph.call(&op, &params))
+        });
 
-                    tp.ack_delay_exponent = ack_delay_exponent;
-                },
+        self.plugin_result(res);
+    }
 
-                0x000b => {
-                    let max_ack_delay = val.get_varint()?;
+    /// Passes the state of a stream updated by the peer to the plugin
+    /// implementing the `stream_state` operation, if any.
+    ///
+    /// The plugin receives the stream ID and the value of each
+    /// [`stream::StreamField`], and can return a new urgency and incremental
+    /// flag to reprioritize the stream.
+    #[cfg(feature = "plugins")]
+    fn plugin_stream_state(&mut self, stream_id: u64, now: time::Instant) {
+        // The stream might have been collected while processing the frame.
+        let params = match self.plugin_stream_state_params(stream_id) {
+            Some(v) => v,
 
-                    if max_ack_delay >= 2_u64.pow(14) {
-                        return Err(Error::InvalidTransportParam);
-                    }
+            None => return,
+        };
 
-                    tp.max_ack_delay = max_ack_delay;
-                },
+        if !self.plugin_call_allowed(now) {
+            return;
+        }
 
-                0x000c => {
-                    tp.disable_active_migration = true;
-                },
+        let metrics = self.metrics.clone();
 
-                0x000d => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let ph = match self.get_pluginizable_connection() {
+            Some(pc) => pc.get_ph_mut(),
 
-                    // TODO: decode preferred_address
-                },
+            None => return,
+        };
 
-                0x000e => {
-                    let limit = val.get_varint()?;
+        let op = plugin::stream_state_op();
 
-                    if limit < 2 {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &params, || ph.call(&op, &params))
+        });
 
-                    tp.active_conn_id_limit = limit;
-                },
+        let res = match self.plugin_result(res) {
+            Some(v) => v,
 
-                0x000f => {
-                    tp.initial_source_connection_id = Some(val.to_vec().into());
-                },
+            None => return,
+        };
 
-                0x00010 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        if let Err(e) = self.plugin_stream_priority(stream_id, &res) {
+            trace!(
+                "{} plugin priority of stream {} ignored: {:?}",
+                self.trace_id,
+                stream_id,
+                e
+            );
+        }
+    }
 
-                    tp.retry_source_connection_id = Some(val.to_vec().into());
-                },
+    /// Returns the parameters of the `stream_state` plugin operation for the
+    /// given stream, or `None` if the stream doesn't exist.
+    #[cfg(feature = "plugins")]
+    fn plugin_stream_state_params(
+        &self, stream_id: u64,
+    ) -> Option<Vec<PluginVal>> {
+        let mut params = vec![PluginVal::U64(stream_id)];
 
-                0x0020 => {
-                    tp.max_datagram_frame_size = Some(val.get_varint()?);
-                },
+        for field in stream::StreamField::ALL {
+            let v = self.streams.get_field(stream_id, field).ok()?;
 
-                // Ignore unknown parameters.
-                _ => (),
-            }
+            params.push(PluginVal::U64(v));
         }
 
-        Ok(tp)
+        Some(params)
     }
 
-    fn encode_param(
-        b: &mut octets::OctetsMut, ty: u64, len: usize,
+    /// Reprioritizes a stream with the urgency and incremental flag returned
+    /// by the `stream_state` plugin operation, if any.
+    #[cfg(feature = "plugins")]
+    fn plugin_stream_priority(
+        &mut self, stream_id: u64, res: &[PluginVal],
     ) -> Result<()> {
-        b.put_varint(ty)?;
-        b.put_varint(len as u64)?;
-
-        Ok(())
-    }
-
-    fn encode_internal<'a>(
-        tp: &TransportParams, is_server: bool, out: &'a mut [u8],
-    ) -> Result<OctetsMut<'a>> {
-        let mut b = octets::OctetsMut::with_slice(out);
+        let (urgency, incremental) = match res {
+            [PluginVal::U64(u), PluginVal::U64(i), ..] => (*u, *i != 0),
 
-        if is_server {
-            if let Some(ref odcid) = tp.original_destination_connection_id {
-                TransportParams::encode_param(&mut b, 0x0000, odcid.len())?;
-                b.put_bytes(odcid)?;
-            }
+            _ => return Ok(()),
         };
 
-        if tp.max_idle_timeout != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0001,
-                octets::varint_len(tp.max_idle_timeout),
-            )?;
-            b.put_varint(tp.max_idle_timeout)?;
-        }
+        let urgency = u8::try_from(urgency).map_err(|_| Error::InvalidState)?;
 
-        if is_server {
-            if let Some(ref token) = tp.stateless_reset_token {
-                TransportParams::encode_param(&mut b, 0x0002, 16)?;
-                b.put_bytes(&token.to_be_bytes())?;
-            }
-        }
+        self.stream_priority(stream_id, urgency, incremental)
+    }
 
-        if tp.max_udp_payload_size != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0003,
-                octets::varint_len(tp.max_udp_payload_size),
-            )?;
-            b.put_varint(tp.max_udp_payload_size)?;
+    /// Lets a plugin select the anti-amplification credit granted for a
+    /// datagram received on a path not validated yet, if one implements the
+    /// `anti_amplification` operation.
+    ///
+    /// The plugin receives the size of the datagram and the credit granted by
+    /// default, and returns the credit to grant.
+    #[cfg(feature = "plugins")]
+    fn plugin_anti_amplification(
+        &mut self, len: usize, credit: usize,
+    ) -> Option<usize> {
+        if !self.plugin_call_allowed(time::Instant::now()) {
+            return None;
         }
 
-        if tp.initial_max_data != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0004,
-                octets::varint_len(tp.initial_max_data),
-            )?;
-            b.put_varint(tp.initial_max_data)?;
-        }
+        let metrics = self.metrics.clone();
 
-        if tp.initial_max_stream_data_bidi_local != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0005,
-                octets::varint_len(tp.initial_max_stream_data_bidi_local),
-            )?;
-            b.put_varint(tp.initial_max_stream_data_bidi_local)?;
-        }
+        let ph = self
+            .get_pluginizable_connection()
+            .map(|pc| pc.get_ph_mut())?;
 
-        if tp.initial_max_stream_data_bidi_remote != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0006,
-                octets::varint_len(tp.initial_max_stream_data_bidi_remote),
-            )?;
-            b.put_varint(tp.initial_max_stream_data_bidi_remote)?;
-        }
+        let op = plugin::anti_amplification_op();
+        let params = [PluginVal::U64(len as u64), PluginVal::U64(credit as u64)];
 
-        if tp.initial_max_stream_data_uni != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0007,
-                octets::varint_len(tp.initial_max_stream_data_uni),
-            )?;
-            b.put_varint(tp.initial_max_stream_data_uni)?;
-        }
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &params, || ph.call(&op, &params))
+        });
 
-        if tp.initial_max_streams_bidi != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0008,
-                octets::varint_len(tp.initial_max_streams_bidi),
-            )?;
-            b.put_varint(tp.initial_max_streams_bidi)?;
-        }
+        let res = self.plugin_result(res)?;
 
-        if tp.initial_max_streams_uni != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0009,
-                octets::varint_len(tp.initial_max_streams_uni),
-            )?;
-            b.put_varint(tp.initial_max_streams_uni)?;
-        }
+        match res.first() {
+            Some(PluginVal::U64(credit)) => Some(*credit as usize),
 
-        if tp.ack_delay_exponent != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x000a,
-                octets::varint_len(tp.ack_delay_exponent),
-            )?;
-            b.put_varint(tp.ack_delay_exponent)?;
+            _ => None,
         }
+    }
 
-        if tp.max_ack_delay != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x000b,
-                octets::varint_len(tp.max_ack_delay),
-            )?;
-            b.put_varint(tp.max_ack_delay)?;
-        }
+    #[cfg(not(feature = "plugins"))]
+    fn plugin_anti_amplification(
+        &mut self, _len: usize, _credit: usize,
+    ) -> Option<usize> {
+        None
+    }
 
-        if tp.disable_active_migration {
-            TransportParams::encode_param(&mut b, 0x000c, 0)?;
-        }
+    /// Reports the latest delivery rate sample of the given path to the
+    /// observer and plugins, if any.
+    fn on_delivery_rate_sample(&mut self, pid: usize) {
+        let (local_addr, peer_addr, sample) = match self.paths.get(pid) {
+            Ok(p) => (
+                p.local_addr(),
+                p.peer_addr(),
+                p.recovery.delivery_rate_sample(),
+            ),
 
-        // TODO: encode preferred_address
+            Err(_) => return,
+        };
 
-        if tp.active_conn_id_limit != 2 {
-            TransportParams::encode_param(
-                &mut b,
-                0x000e,
-                octets::varint_len(tp.active_conn_id_limit),
-            )?;
-            b.put_varint(tp.active_conn_id_limit)?;
+        if let Some(observer) = &self.delivery_rate_observer {
+            observer.on_rate_sample(local_addr, peer_addr, &sample);
         }
 
-        if let Some(scid) = &tp.initial_source_connection_id {
-            TransportParams::encode_param(&mut b, 0x000f, scid.len())?;
-            b.put_bytes(scid)?;
-        }
+        self.plugin_delivery_rate_sample(&sample);
+    }
 
-        if is_server {
-            if let Some(scid) = &tp.retry_source_connection_id {
-                TransportParams::encode_param(&mut b, 0x0010, scid.len())?;
-                b.put_bytes(scid)?;
-            }
+    /// Notifies plugins implementing the `delivery_rate_sample` operation of
+    /// a new delivery rate sample.
+    ///
+    /// The plugin receives the delivery rate in bytes per second, whether the
+    /// sample is application-limited, the sampling interval and the RTT in
+    /// microseconds, and the number of bytes delivered.
+    #[cfg(feature = "plugins")]
+    fn plugin_delivery_rate_sample(&mut self, sample: &DeliveryRateSample) {
+        if !self.plugin_call_allowed(time::Instant::now()) {
+            return;
         }
 
-        if let Some(max_datagram_frame_size) = tp.max_datagram_frame_size {
-            TransportParams::encode_param(
-                &mut b,
-                0x0020,
-                octets::varint_len(max_datagram_frame_size),
-            )?;
-            b.put_varint(max_datagram_frame_size)?;
-        }
+        let metrics = self.metrics.clone();
 
-        Ok(b)
-    }
+        let ph = match self.get_pluginizable_connection() {
+            Some(pc) => pc.get_ph_mut(),
 
-    #[allow(dead_code)]
-    fn encode<'a>(
-        tp: &TransportParams, is_server: bool, out: &'a mut [u8],
-    ) -> Result<&'a mut [u8]> {
-        let b = TransportParams::encode_internal(tp, is_server, out)?;
-
-        let out_len = b.off();
+            None => return,
+        };
 
-        Ok(&mut out[..out_len])
-    }
+        let op = plugin::delivery_rate_sample_op();
+        let params = [
+            PluginVal::U64(sample.delivery_rate),
+            sample.is_app_limited.into(),
+            PluginVal::U64(sample.interval.as_micros() as u64),
+            PluginVal::U64(sample.rtt.as_micros() as u64),
+            PluginVal::U64(sample.delivered as u64),
+        ];
 
-    /// Creates a qlog event for connection transport parameters and TLS fields
-    #[cfg(feature = "qlog")]
-    pub fn to_qlog(
-        &self, owner: TransportOwner, cipher: Option<crypto::Algorithm>,
-    ) -> EventData {
-        let original_destination_connection_id = qlog::HexSlice::maybe_string(
-            self.original_destination_connection_id.as_ref(),
-        );
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &params, || ph.call(&op, &params))
+        });
 
-        let stateless_reset_token = qlog::HexSlice::maybe_string(
-            self.stateless_reset_token.map(|s| s.to_be_bytes()).as_ref(),
-        );
+        self.plugin_result(res);
+    }
 
-        EventData::TransportParametersSet(
-            qlog::events::quic::TransportParametersSet {
-                owner: Some(owner),
-                resumption_allowed: None,
-                early_data_enabled: None,
-                tls_cipher: Some(format!("{cipher:?}")),
-                aead_tag_length: None,
-                original_destination_connection_id,
-                initial_source_connection_id: None,
-                retry_source_connection_id: None,
-                stateless_reset_token,
-                disable_active_migration: Some(self.disable_active_migration),
-                max_idle_timeout: Some(self.max_idle_timeout),
-                max_udp_payload_size: Some(self.max_udp_payload_size as u32),
-                ack_delay_exponent: Some(self.ack_delay_exponent as u16),
-                max_ack_delay: Some(self.max_ack_delay as u16),
-                active_connection_id_limit: Some(
-                    self.active_conn_id_limit as u32,
-                ),
+    #[cfg(not(feature = "plugins"))]
+    fn plugin_delivery_rate_sample(&mut self, _sample: &DeliveryRateSample) {}
 
-                initial_max_data: Some(self.initial_max_data),
-                initial_max_stream_data_bidi_local: Some(
-                    self.initial_max_stream_data_bidi_local,
-                ),
-                initial_max_stream_data_bidi_remote: Some(
-                    self.initial_max_stream_data_bidi_remote,
-                ),
-                initial_max_stream_data_uni: Some(
-                    self.initial_max_stream_data_uni,
-                ),
-                initial_max_streams_bidi: Some(self.initial_max_streams_bidi),
-                initial_max_streams_uni: Some(self.initial_max_streams_uni),
+    /// Returns how many bytes can be sent on a path not validated yet, in
+    /// exchange for a datagram of `len` bytes received on it.
+    fn anti_amplification_credit(&mut self, len: usize) -> usize {
+        let credit = len.saturating_mul(self.anti_amplification_factor);
 
-                preferred_address: None,
-            },
-        )
+        self.plugin_anti_amplification(len, credit)
+            .unwrap_or(credit)
     }
-}
 
-#[doc(hidden)]
-pub mod testing {
-    use super::*;
+    #[cfg(not(feature = "plugins"))]
+    fn plugin_select_send_path(
+        &mut self, _candidates: &[PathCandidate],
+    ) -> Option<usize> {
+        None
+    }
 
-    pub struct Pipe {
-        pub client: Connection,
-        pub server: Connection,
+    #[cfg(not(feature = "plugins"))]
+    fn plugin_ack_frequency(
+        &mut self, _params: ack_freq::AckFrequencyParams, _cwnd: usize,
+        _rtt: time::Duration,
+    ) -> Option<ack_freq::AckFrequencyParams> {
+        None
     }
 
-    impl Pipe {
-        pub fn new() -> Result<Pipe> {
-            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
-            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
-            config.load_priv_key_from_pem_file("examples/cert.key")?;
-            config.set_application_protos(&[b"proto1", b"proto2"])?;
-            config.set_initial_max_data(30);
-            config.set_initial_max_stream_data_bidi_local(15);
-            config.set_initial_max_stream_data_bidi_remote(15);
-            config.set_initial_max_stream_data_uni(10);
-            config.set_initial_max_streams_bidi(3);
-            config.set_initial_max_streams_uni(3);
-            config.set_max_idle_timeout(180_000);
-            config.verify_peer(false);
-            config.set_ack_delay_exponent(8);
+    #[cfg(all(not(feature = "plugins"), feature = "datagram"))]
+    fn plugin_dgram_send_queue_full(&mut self, _len: usize) {}
 
-            Pipe::with_config(&mut config)
-        }
+    #[cfg(not(feature = "plugins"))]
+    fn plugin_stream_state(&mut self, _stream_id: u64, _now: time::Instant) {}
 
-        pub fn client_addr() -> SocketAddr {
-            "127.0.0.1:1234".parse().unwrap()
+    /// Returns whether the given rate-limited behavior is allowed at `now`.
+    fn rate_limit_allow(
+        &mut self, behavior: RateLimited, now: time::Instant,
+    ) -> bool {
+        if self.rate_limits.allow(behavior, now) {
+            return true;
         }
 
-        pub fn server_addr() -> SocketAddr {
-            "127.0.0.1:4321".parse().unwrap()
-        }
+        trace!("{} rate limited {:?}", self.trace_id, behavior);
 
-        pub fn with_config(config: &mut Config) -> Result<Pipe> {
-            let mut client_scid = [0; 16];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        self.rate_limited_count += 1;
 
-            let mut server_scid = [0; 16];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        false
+    }
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    config,
-                )?,
-            })
-        }
+    /// Returns the result of a plugin operation run with
+    /// [`plugin::guarded_call()`].
+    ///
+    /// If it failed, the report is completed with the connection's details and
+    /// passed to the sink registered with [`set_plugin_failure_sink()`], and
+    /// `None` is returned.
+    ///
+    /// [`set_plugin_failure_sink()`]: fn.set_plugin_failure_sink.html
+    #[cfg(feature = "plugins")]
+    fn plugin_result<T>(
+        &mut self, res: std::result::Result<T, plugin::PluginFailureReport>,
+    ) -> Option<T> {
+        let mut report = match res {
+            Ok(v) => return Some(v),
 
-        pub fn with_config_and_scid_lengths(
-            config: &mut Config, client_scid_len: usize, server_scid_len: usize,
-        ) -> Result<Pipe> {
-            let mut client_scid = vec![0; client_scid_len];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+            Err(report) => report,
+        };
 
-            let mut server_scid = vec![0; server_scid_len];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        warn!(
+            "{} plugin operation {} failed: {:?}",
+            self.trace_id, report.operation, report.failure
+        );
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    config,
-                )?,
-            })
+        self.plugin_failure_count += 1;
+
+        if let Some(metrics) = &self.metrics {
+            metrics.incr_counter(Counter::PluginFailures, 1);
         }
 
-        pub fn with_client_config(client_config: &mut Config) -> Result<Pipe> {
-            let mut client_scid = [0; 16];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        report.trace_id = self.trace_id.clone();
+        report.plugins = self
+            .local_transport_params
+            .plugin_support
+            .clone()
+            .unwrap_or_default();
 
-            let mut server_scid = [0; 16];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        plugin::report_failure(&report);
 
-            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
-            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
-            config.load_priv_key_from_pem_file("examples/cert.key")?;
-            config.set_application_protos(&[b"proto1", b"proto2"])?;
-            config.set_initial_max_data(30);
-            config.set_initial_max_stream_data_bidi_local(15);
-            config.set_initial_max_stream_data_bidi_remote(15);
-            config.set_initial_max_streams_bidi(3);
-            config.set_initial_max_streams_uni(3);
-            config.set_ack_delay_exponent(8);
+        None
+    }
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    client_config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    &mut config,
-                )?,
-            })
-        }
+    /// Records a write to a connection or recovery field performed by a
+    /// plugin, and logs it to qlog.
+    #[cfg(feature = "plugins")]
+    fn record_plugin_mutation(
+        &mut self, field: plugin::PluginField, old: Option<PluginVal>,
+        new: PluginVal,
+    ) {
+        let mutation = plugin::PluginMutation {
+            time: time::Instant::now(),
+            operation: plugin::running_op(),
+            field,
+            old,
+            new,
+        };
 
-        pub fn with_server_config(server_config: &mut Config) -> Result<Pipe> {
-            let mut client_scid = [0; 16];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        trace!("{} {}", self.trace_id, mutation);
 
-            let mut server_scid = [0; 16];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        qlog_with_type!(QLOG_MESSAGE, self.qlog, q, {
+            let ev_data = EventData::Message {
+                message: mutation.to_string(),
+            };
 
-            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
-            config.set_application_protos(&[b"proto1", b"proto2"])?;
-            config.set_initial_max_data(30);
-            config.set_initial_max_stream_data_bidi_local(15);
-            config.set_initial_max_stream_data_bidi_remote(15);
-            config.set_initial_max_streams_bidi(3);
-            config.set_initial_max_streams_uni(3);
-            config.set_ack_delay_exponent(8);
+            q.add_event_data_with_instant(ev_data, mutation.time).ok();
+        });
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    &mut config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    server_config,
-                )?,
-            })
+        self.plugin_mutations.push(mutation);
+    }
+
+    /// Runs the plugin operation `op` replacing a built-in behavior of the
+    /// connection, with [`plugin::guarded_call()`].
+    ///
+    /// The parameters are built by `params` with the plugin handler. The values
+    /// returned by the plugin are returned, or `None` if no plugin defines the
+    /// operation, if the parameters can't be passed to plugins or if it failed,
+    /// in which case the built-in behavior applies. Unlike other operations,
+    /// these are part of the protocol itself, so they are not rate limited.
+    #[cfg(feature = "plugins")]
+    fn plugin_define(
+        &mut self, op: PluginOp,
+        params: impl FnOnce(
+            &mut pluginop::handler::PluginHandler<Connection>,
+        ) -> std::result::Result<
+            Vec<PluginVal>,
+            plugin::TryFromCoreQuicheError,
+        >,
+    ) -> Option<Vec<PluginVal>> {
+        let metrics = self.metrics.clone();
+
+        let ph = self
+            .get_pluginizable_connection()
+            .map(|pc| pc.get_ph_mut())?;
+
+        if !ph.provides(&op, pluginop::common::Anchor::Define) {
+            return None;
         }
 
-        pub fn handshake(&mut self) -> Result<()> {
-            while !self.client.is_established() || !self.server.is_established() {
-                let flight = emit_flight(&mut self.client)?;
-                process_flight(&mut self.server, flight)?;
+        let params = params(ph).ok()?;
 
-                let flight = emit_flight(&mut self.server)?;
-                process_flight(&mut self.client, flight)?;
-            }
+        let res = metrics::timed(&metrics, Histogram::PluginCall, || {
+            plugin::guarded_call(&op, &params, || ph.call(&op, &params))
+        });
 
-            Ok(())
-        }
+        self.plugin_result(res)
+    }
 
-        pub fn advance(&mut self) -> Result<()> {
-            let mut client_done = false;
-            let mut server_done = false;
+    /// Converts a frame returned by a plugin.
+    #[cfg(feature = "plugins")]
+    fn plugin_frame(&mut self, v: PluginVal) -> Result<frame::Frame> {
+        use pluginop::TryFromWithPH;
 
-            while !client_done || !server_done {
-                match emit_flight(&mut self.client) {
-                    Ok(flight) => process_flight(&mut self.server, flight)?,
+        let ph = self
+            .get_pluginizable_connection()
+            .map(|pc| pc.get_ph_mut())
+            .ok_or(Error::InvalidFrame)?;
 
-                    Err(Error::Done) => client_done = true,
+        frame::Frame::try_from_with_ph(v, ph).map_err(|_| Error::InvalidFrame)
+    }
 
-                    Err(e) => return Err(e),
-                };
+    /// Returns whether a plugin operation can be called at `now`, if the
+    /// connection has plugins.
+    #[cfg(feature = "plugins")]
+    fn plugin_call_allowed(&mut self, now: time::Instant) -> bool {
+        self.get_pluginizable_connection().is_some() &&
+            self.rate_limit_allow(RateLimited::PluginCall, now)
+    }
 
-                match emit_flight(&mut self.server) {
-                    Ok(flight) => process_flight(&mut self.client, flight)?,
-
-                    Err(Error::Done) => server_done = true,
-
-                    Err(e) => return Err(e),
-                };
-            }
+    /// Switches the active path to the validated path `pid`, as requested by
+    /// [`migrate()`].
+    ///
+    /// [`migrate()`]: struct.Connection.html#method.migrate
+    fn complete_migration(&mut self, pid: usize) -> Result<()> {
+        let old_pid = self.paths.get_active_path_id()?;
 
-            Ok(())
+        if old_pid == pid {
+            return Ok(());
         }
 
-        pub fn client_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-            let server_path = &self.server.paths.get_active().unwrap();
-            let info = RecvInfo {
-                to: server_path.peer_addr(),
-                from: server_path.local_addr(),
-            };
+        self.paths.set_active_path(pid)?;
 
-            self.client.recv(buf, info)
-        }
+        let old_path = self.paths.get_mut(old_pid)?;
+        let old_local_ip = old_path.local_addr().ip();
+        let old_peer_ip = old_path.peer_addr().ip();
 
-        pub fn server_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-            let client_path = &self.client.paths.get_active().unwrap();
-            let info = RecvInfo {
-                to: client_path.peer_addr(),
-                from: client_path.local_addr(),
-            };
+        // The old path is not used anymore, so its Destination Connection ID
+        // can be retired.
+        let old_dcid_seq = if self.ids.zero_length_dcid() {
+            None
+        } else {
+            old_path.active_dcid_seq.take()
+        };
 
-            self.server.recv(buf, info)
+        let path = self.paths.get_mut(pid)?;
+
+        // Unless only the port changed, the congestion state cannot be carried
+        // over to the new path (RFC 9000 Section 9.4).
+        if path.local_addr().ip() != old_local_ip ||
+            path.peer_addr().ip() != old_peer_ip
+        {
+            path.recovery.reset();
         }
 
-        pub fn send_pkt_to_server(
-            &mut self, pkt_type: packet::Type, frames: &[frame::Frame],
-            buf: &mut [u8],
-        ) -> Result<usize> {
-            let written = encode_pkt(&mut self.client, pkt_type, frames, buf)?;
-            recv_send(&mut self.server, buf, written)
+        if let Some(dcid_seq) = old_dcid_seq {
+            if path.active_dcid_seq != Some(dcid_seq) {
+                self.ids.retire_dcid(dcid_seq)?;
+            }
         }
+
+        Ok(())
     }
 
-    pub fn recv_send(
-        conn: &mut Connection, buf: &mut [u8], len: usize,
+    /// Creates a new client-side path.
+    fn create_path_on_client(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
     ) -> Result<usize> {
-        let active_path = conn.paths.get_active()?;
-        let info = RecvInfo {
-            to: active_path.local_addr(),
-            from: active_path.peer_addr(),
-        };
+        if self.is_server {
+            return Err(Error::InvalidState);
+        }
 
-        conn.recv(&mut buf[..len], info)?;
+        // If we use zero-length SCID and go over our local active CID limit,
+        // the `insert_path()` call will raise an error.
+        if !self.ids.zero_length_scid() && self.ids.available_scids() == 0 {
+            return Err(Error::OutOfIdentifiers);
+        }
 
-        let mut off = 0;
+        // Do we have a spare DCID? If we are using zero-length DCID, just use
+        // the default having sequence 0 (note that if we exceed our local CID
+        // limit, the `insert_path()` call will raise an error.
+        let dcid_seq = if self.ids.zero_length_dcid() {
+            0
+        } else {
+            self.ids
+                .lowest_available_dcid_seq()
+                .ok_or(Error::OutOfIdentifiers)?
+        };
 
-        match conn.send(&mut buf[off..]) {
-            Ok((write, _)) => off += write,
+        let mut path =
+            path::Path::new(local_addr, peer_addr, &self.recovery_config, false);
+        path.active_dcid_seq = Some(dcid_seq);
+        #[cfg(feature = "plugins")]
+        if let Some(pc) = self.pc.as_mut() {
+            path.recovery.set_pluginizable_connection(&mut **pc);
+        }
 
-            Err(Error::Done) => (),
+        path.update_max_pmtu(
+            self.peer_transport_params.max_udp_payload_size as usize,
+        );
 
-            Err(e) => return Err(e),
-        }
+        let pid = self
+            .paths
+            .insert_path(path, false)
+            .map_err(|_| Error::OutOfIdentifiers)?;
+        self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
 
-        Ok(off)
+        Ok(pid)
     }
+}
 
-    pub fn process_flight(
-        conn: &mut Connection, flight: Vec<(Vec<u8>, SendInfo)>,
-    ) -> Result<()> {
-        for (mut pkt, si) in flight {
-            let info = RecvInfo {
-                to: si.to,
-                from: si.from,
-            };
-
-            conn.recv(&mut pkt, info)?;
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.add_gauge(Gauge::Connections, -1);
         }
-
-        Ok(())
     }
+}
 
-    pub fn emit_flight_with_max_buffer(
-        conn: &mut Connection, out_size: usize,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        let mut flight = Vec::new();
+/// Maps an `Error` to `Error::Done`, or itself.
+///
+/// When a received packet that hasn't yet been authenticated triggers a failure
+/// it should, in most cases, be ignored, instead of raising a connection error,
+/// to avoid potential man-in-the-middle and man-on-the-side attacks.
+///
+/// However, if no other packet was previously received, the connection should
+/// indeed be closed as the received packet might just be network background
+/// noise, and it shouldn't keep resources occupied indefinitely.
+///
+/// This function maps an error to `Error::Done` to ignore a packet failure
+/// without aborting the connection, except when no other packet was previously
+/// received, in which case the error itself is returned, but only on the
+/// server-side as the client will already have armed the idle timer.
+///
+/// This must only be used for errors preceding packet authentication. Failures
+/// happening after a packet has been authenticated should still cause the
+/// connection to be aborted.
+fn drop_pkt_on_err(
+    e: Error, recv_count: usize, is_server: bool, trace_id: &str,
+) -> Error {
+    // On the server, if no other packet has been successfully processed, abort
+    // the connection to avoid keeping the connection open when only junk is
+    // received.
+    if is_server && recv_count == 0 {
+        return e;
+    }
 
-        loop {
-            let mut out = vec![0u8; out_size];
+    trace!("{} dropped invalid packet", trace_id);
 
-            let info = match conn.send(&mut out) {
-                Ok((written, info)) => {
-                    out.truncate(written);
-                    info
-                },
+    // Ignore other invalid packets that haven't been authenticated to prevent
+    // man-in-the-middle and man-on-the-side attacks.
+    Error::Done
+}
 
-                Err(Error::Done) => break,
+/// Returns the first value returned by a plugin operation, if it has the
+/// expected type.
+#[cfg(feature = "plugins")]
+fn plugin_first<T: std::convert::TryFrom<PluginVal>>(
+    res: Option<Vec<PluginVal>>,
+) -> Option<T> {
+    res?.into_iter().next()?.try_into().ok()
+}
 
-                Err(e) => return Err(e),
-            };
+struct AddrTupleFmt(SocketAddr, SocketAddr);
 
-            flight.push((out, info));
-        }
+impl std::fmt::Display for AddrTupleFmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let AddrTupleFmt(src, dst) = &self;
 
-        if flight.is_empty() {
-            return Err(Error::Done);
+        if src.ip().is_unspecified() || dst.ip().is_unspecified() {
+            return Ok(());
         }
 
-        Ok(flight)
+        f.write_fmt(format_args!("src:{src} dst:{dst}"))
     }
+}
 
-    pub fn emit_flight(
-        conn: &mut Connection,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        emit_flight_with_max_buffer(conn, 65535)
-    }
+/// An event related to 1-RTT key updates.
+///
+/// Key update events can be collected using the [`key_update_event_next()`]
+/// method.
+///
+/// [`key_update_event_next()`]: struct.Connection.html#method.key_update_event_next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyUpdateEvent {
+    /// The local endpoint initiated a key update.
+    LocalInitiated,
 
-    pub fn encode_pkt(
-        conn: &mut Connection, pkt_type: packet::Type, frames: &[frame::Frame],
-        buf: &mut [u8],
-    ) -> Result<usize> {
-        let mut b = octets::OctetsMut::with_slice(buf);
+    /// The peer acknowledged a packet protected with the keys of a key update
+    /// initiated by the local endpoint.
+    LocalConfirmed,
 
-        let epoch = pkt_type.to_epoch()?;
+    /// The peer initiated a key update, and the local endpoint switched to the
+    /// new keys.
+    PeerInitiated,
 
-        let space = &mut conn.pkt_num_spaces[epoch];
+    /// The peer acknowledged a packet protected with the keys of a key update
+    /// it initiated.
+    PeerConfirmed,
+}
 
-        let pn = space.next_pkt_num;
-        let pn_len = 4;
+/// A change of the state of a stream.
+///
+/// Stream events can be collected using the [`stream_event_next()`] method.
+///
+/// [`stream_event_next()`]: struct.Connection.html#method.stream_event_next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// The peer asked to stop sending data on the stream with a
+    /// `STOP_SENDING` frame. The stream was reset in response.
+    StopSending {
+        /// The stream ID.
+        stream_id: u64,
+
+        /// The error code sent by the peer.
+        error_code: u64,
+    },
+
+    /// The peer reset the stream with a `RESET_STREAM` frame.
+    ResetReceived {
+        /// The stream ID.
+        stream_id: u64,
+
+        /// The error code sent by the peer.
+        error_code: u64,
+
+        /// The final size of the stream.
+        final_size: u64,
+    },
+
+    /// All of the data sent by the peer on the stream was received, up to
+    /// the final size. It might not have been read by the application yet.
+    Finished {
+        /// The stream ID.
+        stream_id: u64,
+    },
+
+    /// The peer acknowledged all of the data sent on the stream, up to the
+    /// final size.
+    Acked {
+        /// The stream ID.
+        stream_id: u64,
+    },
+}
 
-        let send_path = conn.paths.get_active()?;
-        let active_dcid_seq = send_path
-            .active_dcid_seq
-            .as_ref()
-            .ok_or(Error::InvalidState)?;
-        let active_scid_seq = send_path
-            .active_scid_seq
-            .as_ref()
-            .ok_or(Error::InvalidState)?;
+/// The optional QUIC extensions negotiated on a connection.
+///
+/// An extension is only reported as negotiated once both endpoints advertised
+/// it in their transport parameters. The negotiated extensions can be
+/// collected using the [`negotiated_extensions()`] method.
+///
+/// [`negotiated_extensions()`]: struct.Connection.html#method.negotiated_extensions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NegotiatedExtensions {
+    /// Whether DATAGRAM frames can be exchanged.
+    pub datagram: bool,
 
-        let hdr = Header {
-            ty: pkt_type,
-            version: conn.version,
-            dcid: ConnectionId::from_ref(
-                conn.ids.get_dcid(*active_dcid_seq)?.cid.as_ref(),
-            ),
-            scid: ConnectionId::from_ref(
-                conn.ids.get_scid(*active_scid_seq)?.cid.as_ref(),
-            ),
-            pkt_num: 0,
-            pkt_num_len: pn_len,
-            token: conn.token.clone(),
-            versions: None,
-            key_phase: false,
-        };
+    /// Whether the multipath extension is used.
+    pub multipath: bool,
 
-        hdr.to_bytes(&mut b)?;
+    /// Whether ACK_FREQUENCY and IMMEDIATE_ACK frames can be exchanged.
+    pub ack_frequency: bool,
 
-        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
+    /// Whether at least one plugin was advertised by both endpoints.
+    pub plugins: bool,
 
-        if pkt_type != packet::Type::Short {
-            let len = pn_len + payload_len + space.crypto_overhead().unwrap();
-            b.put_varint(len as u64)?;
-        }
+    /// Whether the QUIC bit of sent packets is greased.
+    pub grease_quic_bit: bool,
+}
 
-        // Always encode packet number in 4 bytes, to allow encoding packets
-        // with empty payloads.
-        b.put_u32(pn as u32)?;
+/// The transport parameters advertised by the peer.
+///
+/// The peer's transport parameters can be collected using the
+/// [`peer_transport_params()`] method. Parameters that were not sent by the
+/// peer are reported with their default value, as defined by RFC 9000.
+///
+/// When the `serde` feature is enabled, the struct can be serialized, so it
+/// can be logged or stored along with the connection.
+///
+/// [`peer_transport_params()`]: struct.Connection.html#method.peer_transport_params
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PeerTransportParams {
+    /// The `original_destination_connection_id` parameter.
+    pub original_destination_connection_id: Option<Vec<u8>>,
+
+    /// The `max_idle_timeout` parameter, in milliseconds.
+    pub max_idle_timeout: u64,
 
-        let payload_offset = b.off();
+    /// The `stateless_reset_token` parameter.
+    pub stateless_reset_token: Option<u128>,
 
-        for frame in frames {
-            frame.to_bytes(&mut b)?;
-        }
+    /// The `max_udp_payload_size` parameter.
+    pub max_udp_payload_size: u64,
 
-        let aead = match space.crypto_seal {
-            Some(ref v) => v,
-            None => return Err(Error::InvalidState),
-        };
+    /// The `initial_max_data` parameter.
+    pub initial_max_data: u64,
 
-        let written = packet::encrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            payload_offset,
-            None,
-            aead,
-        )?;
+    /// The `initial_max_stream_data_bidi_local` parameter.
+    pub initial_max_stream_data_bidi_local: u64,
 
-        space.next_pkt_num += 1;
+    /// The `initial_max_stream_data_bidi_remote` parameter.
+    pub initial_max_stream_data_bidi_remote: u64,
 
-        Ok(written)
-    }
+    /// The `initial_max_stream_data_uni` parameter.
+    pub initial_max_stream_data_uni: u64,
 
-    pub fn decode_pkt(
-        conn: &mut Connection, buf: &mut [u8], len: usize,
-    ) -> Result<Vec<frame::Frame>> {
-        let mut b = octets::OctetsMut::with_slice(&mut buf[..len]);
+    /// The `initial_max_streams_bidi` parameter.
+    pub initial_max_streams_bidi: u64,
 
-        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
+    /// The `initial_max_streams_uni` parameter.
+    pub initial_max_streams_uni: u64,
 
-        let epoch = hdr.ty.to_epoch()?;
+    /// The `ack_delay_exponent` parameter.
+    pub ack_delay_exponent: u64,
 
-        let aead = conn.pkt_num_spaces[epoch].crypto_open.as_ref().unwrap();
+    /// The `max_ack_delay` parameter, in milliseconds.
+    pub max_ack_delay: u64,
 
-        let payload_len = b.cap();
+    /// Whether the `disable_active_migration` parameter was sent.
+    pub disable_active_migration: bool,
 
-        packet::decrypt_hdr(&mut b, &mut hdr, aead).unwrap();
+    /// The `active_connection_id_limit` parameter.
+    pub active_conn_id_limit: u64,
 
-        let pn = packet::decode_pkt_num(
-            conn.pkt_num_spaces[epoch].largest_rx_pkt_num,
-            hdr.pkt_num,
-            hdr.pkt_num_len,
-        );
+    /// The `initial_source_connection_id` parameter.
+    pub initial_source_connection_id: Option<Vec<u8>>,
 
-        let mut payload =
-            packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len, payload_len, aead)
-                .unwrap();
+    /// The `retry_source_connection_id` parameter.
+    pub retry_source_connection_id: Option<Vec<u8>>,
 
-        let mut frames = Vec::new();
+    /// The `max_datagram_frame_size` parameter.
+    pub max_datagram_frame_size: Option<u64>,
 
-        while payload.cap() > 0 {
-            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
-            frames.push(frame);
-        }
+    /// The names of the plugins advertised by the peer.
+    pub plugin_support: Option<Vec<Vec<u8>>>,
 
-        Ok(frames)
-    }
+    /// Whether the `enable_multipath` parameter was sent.
+    pub enable_multipath: bool,
 
-    pub fn create_cid_and_reset_token(
-        cid_len: usize,
-    ) -> (ConnectionId<'static>, u128) {
-        let mut cid = vec![0; cid_len];
-        rand::rand_bytes(&mut cid[..]);
-        let cid = ConnectionId::from_ref(&cid).into_owned();
+    /// The `min_ack_delay` parameter, in microseconds.
+    pub min_ack_delay: Option<u64>,
 
-        let mut reset_token = [0; 16];
-        rand::rand_bytes(&mut reset_token);
-        let reset_token = u128::from_be_bytes(reset_token);
+    /// Whether the `grease_quic_bit` parameter was sent.
+    pub grease_quic_bit: bool,
 
-        (cid, reset_token)
+    /// The chosen version of the `version_information` parameter.
+    pub chosen_version: Option<u32>,
+
+    /// The available versions of the `version_information` parameter.
+    pub available_versions: Vec<u32>,
+
+    /// The parameters not known to quiche, including GREASE ones, as pairs of
+    /// identifier and raw value, in the order they were received.
+    pub unknown_params: Vec<(u64, Vec<u8>)>,
+}
+
+impl From<&TransportParams> for PeerTransportParams {
+    fn from(tp: &TransportParams) -> Self {
+        PeerTransportParams {
+            original_destination_connection_id: tp
+                .original_destination_connection_id
+                .as_ref()
+                .map(|cid| cid.to_vec()),
+            max_idle_timeout: tp.max_idle_timeout,
+            stateless_reset_token: tp.stateless_reset_token,
+            max_udp_payload_size: tp.max_udp_payload_size,
+            initial_max_data: tp.initial_max_data,
+            initial_max_stream_data_bidi_local: tp
+                .initial_max_stream_data_bidi_local,
+            initial_max_stream_data_bidi_remote: tp
+                .initial_max_stream_data_bidi_remote,
+            initial_max_stream_data_uni: tp.initial_max_stream_data_uni,
+            initial_max_streams_bidi: tp.initial_max_streams_bidi,
+            initial_max_streams_uni: tp.initial_max_streams_uni,
+            ack_delay_exponent: tp.ack_delay_exponent,
+            max_ack_delay: tp.max_ack_delay,
+            disable_active_migration: tp.disable_active_migration,
+            active_conn_id_limit: tp.active_conn_id_limit,
+            initial_source_connection_id: tp
+                .initial_source_connection_id
+                .as_ref()
+                .map(|cid| cid.to_vec()),
+            retry_source_connection_id: tp
+                .retry_source_connection_id
+                .as_ref()
+                .map(|cid| cid.to_vec()),
+            max_datagram_frame_size: tp.max_datagram_frame_size,
+            plugin_support: tp.plugin_support.clone(),
+            enable_multipath: tp.enable_multipath,
+            min_ack_delay: tp.min_ack_delay,
+            grease_quic_bit: tp.grease_quic_bit,
+            chosen_version: tp
+                .version_information
+                .as_ref()
+                .map(|info| info.chosen_version),
+            available_versions: tp
+                .version_information
+                .as_ref()
+                .map(|info| info.available_versions.clone())
+                .unwrap_or_default(),
+            unknown_params: tp.extra_params.clone(),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Statistics about the connection.
+///
+/// A connection's statistics can be collected using the [`stats()`] method.
+///
+/// [`stats()`]: struct.Connection.html#method.stats
+#[derive(Clone, Default)]
+pub struct Stats {
+    /// The number of QUIC packets received.
+    pub recv: usize,
 
-    #[test]
-    fn transport_params() {
-        // Server encodes, client decodes.
-        let tp = TransportParams {
-            original_destination_connection_id: None,
-            max_idle_timeout: 30,
-            stateless_reset_token: Some(u128::from_be_bytes([0xba; 16])),
-            max_udp_payload_size: 23_421,
-            initial_max_data: 424_645_563,
-            initial_max_stream_data_bidi_local: 154_323_123,
-            initial_max_stream_data_bidi_remote: 6_587_456,
-            initial_max_stream_data_uni: 2_461_234,
-            initial_max_streams_bidi: 12_231,
-            initial_max_streams_uni: 18_473,
-            ack_delay_exponent: 20,
-            max_ack_delay: 2_u64.pow(14) - 1,
-            disable_active_migration: true,
-            active_conn_id_limit: 8,
-            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
-            retry_source_connection_id: Some(b"retry".to_vec().into()),
-            max_datagram_frame_size: Some(32),
-        };
+    /// The number of QUIC packets sent.
+    pub sent: usize,
 
-        let mut raw_params = [42; 256];
-        let raw_params =
-            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 94);
+    /// The number of QUIC packets that were lost.
+    pub lost: usize,
 
-        let new_tp = TransportParams::decode(None, raw_params, false).unwrap();
+    /// The number of sent QUIC packets with retransmitted data.
+    pub retrans: usize,
 
-        assert_eq!(new_tp, tp);
+    /// The number of sent bytes.
+    pub sent_bytes: u64,
 
-        // Client encodes, server decodes.
-        let tp = TransportParams {
-            original_destination_connection_id: None,
-            max_idle_timeout: 30,
-            stateless_reset_token: None,
-            max_udp_payload_size: 23_421,
-            initial_max_data: 424_645_563,
-            initial_max_stream_data_bidi_local: 154_323_123,
-            initial_max_stream_data_bidi_remote: 6_587_456,
-            initial_max_stream_data_uni: 2_461_234,
-            initial_max_streams_bidi: 12_231,
-            initial_max_streams_uni: 18_473,
-            ack_delay_exponent: 20,
-            max_ack_delay: 2_u64.pow(14) - 1,
-            disable_active_migration: true,
-            active_conn_id_limit: 8,
-            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
-            retry_source_connection_id: None,
-            max_datagram_frame_size: Some(32),
-        };
+    /// The number of received bytes.
+    pub recv_bytes: u64,
 
-        let mut raw_params = [42; 256];
-        let raw_params =
-            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 69);
+    /// The number of bytes sent lost.
+    pub lost_bytes: u64,
 
-        let new_tp = TransportParams::decode(None, raw_params, true).unwrap();
+    /// The number of stream bytes retransmitted.
+    pub stream_retrans_bytes: u64,
 
-        assert_eq!(new_tp, tp);
-    }
+    /// The number of received stream bytes that had already been received.
+    pub stream_dup_bytes: u64,
 
-    #[test]
-    fn transport_params_forbid_duplicates() {
-        // Given an encoded param.
-        let initial_source_connection_id = b"id";
-        let initial_source_connection_id_raw = [
-            15,
-            initial_source_connection_id.len() as u8,
-            initial_source_connection_id[0],
-            initial_source_connection_id[1],
-        ];
+    /// The number of known paths for the connection.
+    pub paths_count: usize,
 
-        // No error when decoding the param.
-        let tp = TransportParams::decode(
-            None,
-            initial_source_connection_id_raw.as_slice(),
-            true,
-        )
-        .unwrap();
+    /// The maximum idle timeout.
+    pub peer_max_idle_timeout: u64,
 
-        assert_eq!(
-            tp.initial_source_connection_id,
-            Some(initial_source_connection_id.to_vec().into())
-        );
+    /// The maximum UDP payload size.
+    pub peer_max_udp_payload_size: u64,
 
-        // Duplicate the param.
-        let mut raw_params = Vec::new();
-        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
-        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
+    /// The initial flow control maximum data for the connection.
+    pub peer_initial_max_data: u64,
 
-        // Decoding fails.
-        assert_eq!(
-            TransportParams::decode(None, raw_params.as_slice(), true),
-            Err(Error::InvalidTransportParam)
-        );
-    }
+    /// The initial flow control maximum data for local bidirectional streams.
+    pub peer_initial_max_stream_data_bidi_local: u64,
 
-    #[test]
-    fn unknown_version() {
-        let mut config = Config::new(0xbabababa).unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.verify_peer(false);
+    /// The initial flow control maximum data for remote bidirectional streams.
+    pub peer_initial_max_stream_data_bidi_remote: u64,
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Err(Error::UnknownVersion));
-    }
+    /// The initial flow control maximum data for unidirectional streams.
+    pub peer_initial_max_stream_data_uni: u64,
 
-    #[test]
-    fn config_version_reserved() {
-        Config::new(0xbabababa).unwrap();
-        Config::new(0x1a2a3a4a).unwrap();
-    }
+    /// The initial maximum bidirectional streams.
+    pub peer_initial_max_streams_bidi: u64,
 
-    #[test]
-    fn config_version_invalid() {
-        assert_eq!(
-            Config::new(0xb1bababa).err().unwrap(),
-            Error::UnknownVersion
-        );
-    }
+    /// The initial maximum unidirectional streams.
+    pub peer_initial_max_streams_uni: u64,
 
-    #[test]
-    fn version_negotiation() {
-        let mut buf = [0; 65535];
+    /// The ACK delay exponent.
+    pub peer_ack_delay_exponent: u64,
 
-        let mut config = Config::new(0xbabababa).unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.verify_peer(false);
+    /// The max ACK delay.
+    pub peer_max_ack_delay: u64,
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+    /// Whether active migration is disabled.
+    pub peer_disable_active_migration: bool,
 
-        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+    /// The active connection ID limit.
+    pub peer_active_conn_id_limit: u64,
 
-        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
-        len = crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut buf).unwrap();
+    /// DATAGRAM frame extension parameter, if any.
+    pub peer_max_datagram_frame_size: Option<u64>,
 
-        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+    /// The number of Initial packets received after the Initial keys were
+    /// discarded.
+    pub late_initial: usize,
 
-        assert_eq!(pipe.handshake(), Ok(()));
+    /// The number of Handshake packets received after the Handshake keys were
+    /// discarded.
+    pub late_handshake: usize,
 
-        assert_eq!(pipe.client.version, PROTOCOL_VERSION);
-        assert_eq!(pipe.server.version, PROTOCOL_VERSION);
-    }
+    /// The number of received datagrams that didn't contain any valid QUIC
+    /// packet.
+    pub junk_datagrams: usize,
 
-    #[test]
-    fn verify_custom_root() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config.verify_peer(true);
-        config
-            .load_verify_locations_from_file("examples/rootca.crt")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
+    /// The number of bytes of received datagrams that didn't contain any
+    /// valid QUIC packet.
+    pub junk_bytes: u64,
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-    }
+    /// The number of datagrams dropped without being processed because their
+    /// sender sent too many junk datagrams.
+    pub throttled_datagrams: usize,
 
-    #[test]
-    fn missing_initial_source_connection_id() {
-        let mut buf = [0; 65535];
+    /// The number of Connection IDs provided by the peer in excess of the
+    /// advertised `active_connection_id_limit` transport parameter.
+    pub dcids_over_limit: usize,
 
-        let mut pipe = testing::Pipe::new().unwrap();
+    /// The number of actions denied by a rate limit set with
+    /// [`Config::set_rate_limit()`].
+    ///
+    /// [`Config::set_rate_limit()`]: struct.Config.html#method.set_rate_limit
+    pub rate_limited: usize,
 
-        // Reset initial_source_connection_id.
-        pipe.client
-            .local_transport_params
-            .initial_source_connection_id = None;
-        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+    /// The number of plugin operations that failed, because the plugin
+    /// trapped or the host panicked while running them.
+    pub plugin_failures: usize,
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+    /// The number of DATAGRAMs dropped before being sent, because their time
+    /// to live set with [`dgram_send_with()`] elapsed.
+    ///
+    /// [`dgram_send_with()`]: struct.Connection.html#method.dgram_send_with
+    pub dgram_expired: usize,
 
-        // Server rejects transport parameters.
-        assert_eq!(
-            pipe.server_recv(&mut buf[..len]),
-            Err(Error::InvalidTransportParam)
-        );
-    }
+    /// The number of 1-RTT key updates, initiated by either endpoint.
+    pub key_updates: usize,
 
-    #[test]
-    fn invalid_initial_source_connection_id() {
-        let mut buf = [0; 65535];
-
-        let mut pipe = testing::Pipe::new().unwrap();
-
-        // Scramble initial_source_connection_id.
-        pipe.client
-            .local_transport_params
-            .initial_source_connection_id = Some(b"bogus value".to_vec().into());
-        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+    /// The current 1-RTT key phase.
+    pub key_phase: bool,
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+    /// The number of packets sent with the current 1-RTT keys.
+    ///
+    /// Keys are updated automatically before this reaches the confidentiality
+    /// limit of the AEAD in use, or the connection is closed with an
+    /// `AEAD_LIMIT_REACHED` error if that's not possible.
+    pub key_phase_sent: u64,
 
-        // Server rejects transport parameters.
-        assert_eq!(
-            pipe.server_recv(&mut buf[..len]),
-            Err(Error::InvalidTransportParam)
-        );
-    }
+    /// The number of packets received with the current 1-RTT keys.
+    pub key_phase_recv: u64,
 
-    #[test]
-    fn handshake() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    /// The number of received packets that failed authentication, with any
+    /// keys.
+    ///
+    /// The connection is closed with an `AEAD_LIMIT_REACHED` error once this
+    /// reaches the integrity limit of the AEAD in use.
+    pub aead_auth_failures: u64,
 
-        assert_eq!(
-            pipe.client.application_proto(),
-            pipe.server.application_proto()
-        );
+    /// The distribution of ACK delays reported by the peer in the ACK frames
+    /// received for application data.
+    pub ack_delay_received: AckDelayStats,
 
-        assert_eq!(pipe.server.server_name(), Some("quic.tech"));
-    }
+    /// The distribution of ACK delays of the ACK frames sent for application
+    /// data, i.e. the time between receiving the largest acknowledged packet
+    /// and sending the ACK.
+    pub ack_delay_sent: AckDelayStats,
 
-    #[test]
-    fn handshake_done() {
-        let mut pipe = testing::Pipe::new().unwrap();
+    /// The number of QUIC packets sent marked with ECT(0).
+    pub ecn_marked_sent: usize,
 
-        // Disable session tickets on the server (SSL_OP_NO_TICKET) to avoid
-        // triggering 1-RTT packet send with a CRYPTO frame.
-        pipe.server.handshake.set_options(0x0000_4000);
+    /// The number of QUIC packets received marked with ECT(0).
+    pub ecn_ect0_recv: u64,
 
-        assert_eq!(pipe.handshake(), Ok(()));
+    /// The number of QUIC packets received marked with ECT(1).
+    pub ecn_ect1_recv: u64,
 
-        assert!(pipe.server.handshake_done_sent);
-    }
+    /// The number of QUIC packets received marked with ECN-CE.
+    pub ecn_ce_recv: u64,
 
-    #[test]
-    fn handshake_confirmation() {
-        let mut pipe = testing::Pipe::new().unwrap();
+    /// The number of sent QUIC packets the peer reported as marked with
+    /// ECN-CE.
+    pub ecn_ce_reported: u64,
 
-        // Client sends initial flight.
-        let flight = testing::emit_flight(&mut pipe.client).unwrap();
-        testing::process_flight(&mut pipe.server, flight).unwrap();
+    /// Whether the path was validated as supporting ECN.
+    pub ecn_capable: bool,
 
-        // Server sends initial flight.
-        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+    /// The number of events that were not queued for the application because
+    /// too many of them were already queued.
+    pub dropped_events: usize,
+}
 
-        assert!(!pipe.client.is_established());
-        assert!(!pipe.client.handshake_confirmed);
+/// The upper bounds of the [`AckDelayStats`] histogram buckets, except for the
+/// last bucket which has no upper bound.
+///
+/// [`AckDelayStats`]: struct.AckDelayStats.html
+pub const ACK_DELAY_BUCKET_BOUNDS: [time::Duration; 5] = [
+    time::Duration::from_millis(1),
+    time::Duration::from_millis(5),
+    time::Duration::from_millis(10),
+    time::Duration::from_millis(25),
+    time::Duration::from_millis(50),
+];
+
+/// Statistics about a set of ACK delay samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AckDelayStats {
+    /// The number of samples.
+    pub count: u64,
+
+    /// The smallest sample.
+    pub min: time::Duration,
+
+    /// The largest sample.
+    pub max: time::Duration,
+
+    /// The sum of all samples.
+    pub total: time::Duration,
+
+    /// The number of samples in each bucket. A sample lands in the first
+    /// bucket whose bound in [`ACK_DELAY_BUCKET_BOUNDS`] is greater than it,
+    /// or in the last bucket.
+    ///
+    /// [`ACK_DELAY_BUCKET_BOUNDS`]: constant.ACK_DELAY_BUCKET_BOUNDS.html
+    pub buckets: [u64; ACK_DELAY_BUCKET_BOUNDS.len() + 1],
+}
 
-        assert!(!pipe.server.is_established());
-        assert!(!pipe.server.handshake_confirmed);
+impl AckDelayStats {
+    fn on_sample(&mut self, delay: time::Duration) {
+        if self.count == 0 || delay < self.min {
+            self.min = delay;
+        }
 
-        testing::process_flight(&mut pipe.client, flight).unwrap();
+        self.max = cmp::max(self.max, delay);
+        self.total = self.total.saturating_add(delay);
+        self.count += 1;
 
-        // Client sends Handshake packet and completes handshake.
-        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let bucket = ACK_DELAY_BUCKET_BOUNDS
+            .iter()
+            .position(|&b| delay < b)
+            .unwrap_or(ACK_DELAY_BUCKET_BOUNDS.len());
 
-        assert!(pipe.client.is_established());
-        assert!(!pipe.client.handshake_confirmed);
+        self.buckets[bucket] += 1;
+    }
 
-        assert!(!pipe.server.is_established());
-        assert!(!pipe.server.handshake_confirmed);
+    /// Returns the average of the samples, if any.
+    pub fn mean(&self) -> Option<time::Duration> {
+        if self.count == 0 {
+            return None;
+        }
 
-        testing::process_flight(&mut pipe.server, flight).unwrap();
+        let mean = self.total.as_nanos() / self.count as u128;
 
-        // Server completes handshake and sends HANDSHAKE_DONE.
-        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        Some(time::Duration::from_nanos(mean as u64))
+    }
+}
 
-        assert!(pipe.client.is_established());
-        assert!(!pipe.client.handshake_confirmed);
+impl std::fmt::Debug for Stats {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "recv={} sent={} lost={} retrans={}",
+            self.recv, self.sent, self.lost, self.retrans,
+        )?;
 
-        assert!(pipe.server.is_established());
-        assert!(!pipe.server.handshake_confirmed);
+        write!(
+            f,
+            " sent_bytes={} recv_bytes={} lost_bytes={}",
+            self.sent_bytes, self.recv_bytes, self.lost_bytes,
+        )?;
 
-        testing::process_flight(&mut pipe.client, flight).unwrap();
+        write!(f, " peer_tps={{")?;
 
-        // Client acks 1-RTT packet, and confirms handshake.
-        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        write!(f, " max_idle_timeout={},", self.peer_max_idle_timeout)?;
 
-        assert!(pipe.client.is_established());
-        assert!(pipe.client.handshake_confirmed);
+        write!(
+            f,
+            " max_udp_payload_size={},",
+            self.peer_max_udp_payload_size,
+        )?;
 
-        assert!(pipe.server.is_established());
-        assert!(!pipe.server.handshake_confirmed);
+        write!(f, " initial_max_data={},", self.peer_initial_max_data)?;
 
-        testing::process_flight(&mut pipe.server, flight).unwrap();
+        write!(
+            f,
+            " initial_max_stream_data_bidi_local={},",
+            self.peer_initial_max_stream_data_bidi_local,
+        )?;
 
-        // Server handshake is confirmed.
-        assert!(pipe.client.is_established());
-        assert!(pipe.client.handshake_confirmed);
+        write!(
+            f,
+            " initial_max_stream_data_bidi_remote={},",
+            self.peer_initial_max_stream_data_bidi_remote,
+        )?;
 
-        assert!(pipe.server.is_established());
-        assert!(pipe.server.handshake_confirmed);
-    }
+        write!(
+            f,
+            " initial_max_stream_data_uni={},",
+            self.peer_initial_max_stream_data_uni,
+        )?;
 
-    #[test]
-    fn handshake_resumption() {
-        const SESSION_TICKET_KEY: [u8; 48] = [0xa; 48];
+        write!(
+            f,
+            " initial_max_streams_bidi={},",
+            self.peer_initial_max_streams_bidi,
+        )?;
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.set_ticket_key(&SESSION_TICKET_KEY).unwrap();
+        write!(
+            f,
+            " initial_max_streams_uni={},",
+            self.peer_initial_max_streams_uni,
+        )?;
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        write!(f, " ack_delay_exponent={},", self.peer_ack_delay_exponent)?;
 
-        assert_eq!(pipe.client.is_established(), true);
-        assert_eq!(pipe.server.is_established(), true);
+        write!(f, " max_ack_delay={},", self.peer_max_ack_delay)?;
 
-        assert_eq!(pipe.client.is_resumed(), false);
-        assert_eq!(pipe.server.is_resumed(), false);
+        write!(
+            f,
+            " disable_active_migration={},",
+            self.peer_disable_active_migration,
+        )?;
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+        write!(
+            f,
+            " active_conn_id_limit={},",
+            self.peer_active_conn_id_limit,
+        )?;
 
-        // Configure session on new connection and perform handshake.
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.set_ticket_key(&SESSION_TICKET_KEY).unwrap();
+        write!(
+            f,
+            " max_datagram_frame_size={:?}",
+            self.peer_max_datagram_frame_size,
+        )?;
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        write!(f, "}}")
+    }
+}
 
-        assert_eq!(pipe.client.set_session(session), Ok(()));
-        assert_eq!(pipe.handshake(), Ok(()));
+#[derive(Clone, Debug, PartialEq)]
+struct TransportParams {
+    pub original_destination_connection_id: Option<ConnectionId<'static>>,
+    pub max_idle_timeout: u64,
+    pub stateless_reset_token: Option<u128>,
+    pub max_udp_payload_size: u64,
+    pub initial_max_data: u64,
+    pub initial_max_stream_data_bidi_local: u64,
+    pub initial_max_stream_data_bidi_remote: u64,
+    pub initial_max_stream_data_uni: u64,
+    pub initial_max_streams_bidi: u64,
+    pub initial_max_streams_uni: u64,
+    pub ack_delay_exponent: u64,
+    pub max_ack_delay: u64,
+    pub disable_active_migration: bool,
+    // pub preferred_address: ...,
+    pub active_conn_id_limit: u64,
+    pub initial_source_connection_id: Option<ConnectionId<'static>>,
+    pub retry_source_connection_id: Option<ConnectionId<'static>>,
+    pub max_datagram_frame_size: Option<u64>,
+    pub plugin_support: Option<Vec<Vec<u8>>>,
+    pub enable_multipath: bool,
+    pub min_ack_delay: Option<u64>,
+    pub grease_quic_bit: bool,
+    pub version_information: Option<VersionInformation>,
+    pub extra_params: Vec<(u64, Vec<u8>)>,
+}
 
-        assert_eq!(pipe.client.is_established(), true);
-        assert_eq!(pipe.server.is_established(), true);
+/// The `version_information` transport parameter, as defined by RFC 9368.
+#[derive(Clone, Debug, PartialEq)]
+struct VersionInformation {
+    pub chosen_version: u32,
+    pub available_versions: Vec<u32>,
+}
 
-        assert_eq!(pipe.client.is_resumed(), true);
-        assert_eq!(pipe.server.is_resumed(), true);
+impl Default for TransportParams {
+    fn default() -> TransportParams {
+        TransportParams {
+            original_destination_connection_id: None,
+            max_idle_timeout: 0,
+            stateless_reset_token: None,
+            max_udp_payload_size: 65527,
+            initial_max_data: 0,
+            initial_max_stream_data_bidi_local: 0,
+            initial_max_stream_data_bidi_remote: 0,
+            initial_max_stream_data_uni: 0,
+            initial_max_streams_bidi: 0,
+            initial_max_streams_uni: 0,
+            ack_delay_exponent: 3,
+            max_ack_delay: 25,
+            disable_active_migration: false,
+            active_conn_id_limit: 2,
+            initial_source_connection_id: None,
+            retry_source_connection_id: None,
+            max_datagram_frame_size: None,
+            plugin_support: None,
+            enable_multipath: false,
+            min_ack_delay: None,
+            grease_quic_bit: false,
+            version_information: None,
+            extra_params: Vec::new(),
+        }
     }
+}
 
-    #[test]
-    fn handshake_alpn_mismatch() {
-        let mut buf = [0; 65535];
-
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .set_application_protos(&[b"proto3\x06proto4"])
-            .unwrap();
-        config.verify_peer(false);
+impl TransportParams {
+    #[cfg_attr(not(feature = "plugins"), allow(unused_mut, unused_variables))]
+    fn decode(
+        mut conn: Option<&mut Connection>, buf: &[u8], is_server: bool,
+    ) -> Result<TransportParams> {
+        let mut params = octets::Octets::with_slice(buf);
+        let mut seen_params = HashSet::new();
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Err(Error::TlsFail));
+        let mut tp = TransportParams::default();
 
-        assert_eq!(pipe.client.application_proto(), b"");
-        assert_eq!(pipe.server.application_proto(), b"");
+        while params.cap() > 0 {
+            let id = params.get_varint()?;
 
-        // Server should only send one packet in response to ALPN mismatch.
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
-        assert_eq!(len, 1200);
+            if seen_params.contains(&id) {
+                return Err(Error::InvalidTransportParam);
+            }
+            seen_params.insert(id);
 
-        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
-        assert_eq!(pipe.server.sent_count, 1);
-    }
+            let mut val = params.get_bytes_with_varint_length()?;
 
-    #[test]
-    fn handshake_0rtt() {
-        let mut buf = [0; 65535];
+            #[cfg(feature = "plugins")]
+            if let Some(ph) = conn.as_mut().and_then(|c| {
+                c.get_pluginizable_connection().map(|pc| pc.get_ph_mut())
+            }) {
+                use octets::OctetsPtr;
+                use pluginop::IntoWithPH;
+                let op = PluginOp::DecodeTransportParameter(id);
+                let params = &[OctetsPtr::from(&mut val).into_with_ph(ph)];
+                let res =
+                    plugin::guarded_call(&op, params, || ph.call(&op, params));
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
-        config.verify_peer(false);
+                if let Some(c) = conn.as_mut() {
+                    c.plugin_result(res);
+                }
+            }
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+            match id {
+                0x0000 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+                    tp.original_destination_connection_id =
+                        Some(val.to_vec().into());
+                },
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+                0x0001 => {
+                    tp.max_idle_timeout = val.get_varint()?;
+                },
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+                0x0002 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // Client sends 0-RTT packet.
-        let pkt_type = packet::Type::ZeroRTT;
+                    tp.stateless_reset_token = Some(u128::from_be_bytes(
+                        val.get_bytes(16)?
+                            .to_vec()
+                            .try_into()
+                            .map_err(|_| Error::BufferTooShort)?,
+                    ));
+                },
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, true),
-        }];
+                0x0003 => {
+                    tp.max_udp_payload_size = val.get_varint()?;
 
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(1200)
-        );
+                    if tp.max_udp_payload_size < 1200 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+                },
 
-        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+                0x0004 => {
+                    tp.initial_max_data = val.get_varint()?;
+                },
 
-        // 0-RTT stream data is readable.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+                0x0005 => {
+                    tp.initial_max_stream_data_bidi_local = val.get_varint()?;
+                },
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
-        assert_eq!(&b[..5], b"aaaaa");
-    }
+                0x0006 => {
+                    tp.initial_max_stream_data_bidi_remote = val.get_varint()?;
+                },
 
-    #[test]
-    fn handshake_0rtt_reordered() {
-        let mut buf = [0; 65535];
+                0x0007 => {
+                    tp.initial_max_stream_data_uni = val.get_varint()?;
+                },
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
-        config.verify_peer(false);
+                0x0008 => {
+                    let max = val.get_varint()?;
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+                    if max > MAX_STREAM_ID {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+                    tp.initial_max_streams_bidi = max;
+                },
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+                0x0009 => {
+                    let max = val.get_varint()?;
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        let mut initial = buf[..len].to_vec();
+                    if max > MAX_STREAM_ID {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // Client sends 0-RTT packet.
-        let pkt_type = packet::Type::ZeroRTT;
+                    tp.initial_max_streams_uni = max;
+                },
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, true),
-        }];
+                0x000a => {
+                    let ack_delay_exponent = val.get_varint()?;
 
-        let len =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
-        let mut zrtt = buf[..len].to_vec();
+                    if ack_delay_exponent > 20 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // 0-RTT packet is received before the Initial one.
-        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+                    tp.ack_delay_exponent = ack_delay_exponent;
+                },
 
-        assert_eq!(pipe.server.undecryptable_pkts.len(), 1);
-        assert_eq!(pipe.server.undecryptable_pkts[0].0.len(), zrtt.len());
+                0x000b => {
+                    let max_ack_delay = val.get_varint()?;
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), None);
+                    if max_ack_delay >= 2_u64.pow(14) {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // Initial packet is also received.
-        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
+                    tp.max_ack_delay = max_ack_delay;
+                },
 
-        // 0-RTT stream data is readable.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+                0x000c => {
+                    tp.disable_active_migration = true;
+                },
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
-        assert_eq!(&b[..5], b"aaaaa");
-    }
+                0x000d => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-    #[test]
-    fn handshake_0rtt_truncated() {
-        let mut buf = [0; 65535];
+                    // TODO: decode preferred_address
+                },
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
-        config.verify_peer(false);
+                0x000e => {
+                    let limit = val.get_varint()?;
 
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+                    if limit < 2 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+                    tp.active_conn_id_limit = limit;
+                },
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+                0x000f => {
+                    tp.initial_source_connection_id = Some(val.to_vec().into());
+                },
 
-        // Client sends initial flight.
-        pipe.client.send(&mut buf).unwrap();
+                0x00010 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        // Client sends 0-RTT packet.
-        let pkt_type = packet::Type::ZeroRTT;
+                    tp.retry_source_connection_id = Some(val.to_vec().into());
+                },
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, true),
-        }];
+                VERSION_INFORMATION_PARAM_ID => {
+                    if val.cap() < 4 || val.cap() % 4 != 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        let len =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
+                    let chosen_version = val.get_u32()?;
 
-        // Simulate a truncated packet by sending one byte less.
-        let mut zrtt = buf[..len - 1].to_vec();
+                    let mut available_versions = Vec::new();
 
-        // 0-RTT packet is received before the Initial one.
-        assert_eq!(pipe.server_recv(&mut zrtt), Err(Error::InvalidPacket));
+                    while val.cap() > 0 {
+                        available_versions.push(val.get_u32()?);
+                    }
 
-        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+                    if chosen_version == 0 || available_versions.contains(&0) {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        assert!(pipe.server.is_closed());
-    }
+                    tp.version_information = Some(VersionInformation {
+                        chosen_version,
+                        available_versions,
+                    });
+                },
 
-    #[test]
-    /// Tests that a pre-v1 client can connect to a v1-enabled server, by making
-    /// the server downgrade to the pre-v1 version.
-    fn handshake_downgrade_v1() {
-        let mut config = Config::new(PROTOCOL_VERSION_DRAFT29).unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.verify_peer(false);
+                0x0020 => {
+                    tp.max_datagram_frame_size = Some(val.get_varint()?);
+                },
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+                PLUGIN_SUPPORT_PARAM_ID => {
+                    let mut plugins = Vec::new();
 
-        assert_eq!(pipe.client.version, PROTOCOL_VERSION_DRAFT29);
-        assert_eq!(pipe.server.version, PROTOCOL_VERSION_DRAFT29);
-    }
+                    while val.cap() > 0 {
+                        let name = val.get_bytes_with_varint_length()?;
 
-    #[test]
-    fn limit_handshake_data() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert-big.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
+                        if name.cap() == 0 {
+                            return Err(Error::InvalidTransportParam);
+                        }
 
-        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+                        plugins.push(name.to_vec());
+                    }
 
-        let flight = testing::emit_flight(&mut pipe.client).unwrap();
-        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
-        testing::process_flight(&mut pipe.server, flight).unwrap();
+                    tp.plugin_support = Some(plugins);
+                },
 
-        let flight = testing::emit_flight(&mut pipe.server).unwrap();
-        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+                ENABLE_MULTIPATH_PARAM_ID => {
+                    if val.cap() != 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        assert_eq!(server_sent, client_sent * MAX_AMPLIFICATION_FACTOR);
-    }
+                    tp.enable_multipath = true;
+                },
 
-    #[test]
-    fn stream() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+                MIN_ACK_DELAY_PARAM_ID => {
+                    tp.min_ack_delay = Some(val.get_varint()?);
+                },
 
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
+                GREASE_QUIC_BIT_PARAM_ID => {
+                    if val.cap() != 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        assert!(!pipe.server.stream_finished(4));
+                    tp.grease_quic_bit = true;
+                },
 
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+                // Unknown parameters are otherwise ignored, but kept around
+                // so the application can inspect them.
+                _ => tp.extra_params.push((id, val.to_vec())),
+            }
+        }
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
-        assert_eq!(&b[..12], b"hello, world");
+        // The min_ack_delay parameter is in microseconds, while max_ack_delay
+        // is in milliseconds.
+        if tp.min_ack_delay > Some(tp.max_ack_delay * 1000) {
+            return Err(Error::InvalidTransportParam);
+        }
 
-        assert!(pipe.server.stream_finished(4));
+        Ok(tp)
     }
 
-    #[test]
-    fn zero_rtt() {
-        let mut buf = [0; 65535];
-
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_streams_bidi(3);
-        config.enable_early_data();
-        config.verify_peer(false);
-
-        // Perform initial handshake.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    /// Returns the length of a parameter with identifier `ty` and a value of
+    /// `len` bytes, once encoded.
+    fn encoded_len(ty: u64, len: usize) -> usize {
+        octets::varint_len(ty) + octets::varint_len(len as u64) + len
+    }
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+    fn encode_param(
+        b: &mut octets::OctetsMut, ty: u64, len: usize,
+    ) -> Result<()> {
+        b.put_varint(ty)?;
+        b.put_varint(len as u64)?;
 
-        // Configure session on new connection.
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+        Ok(())
+    }
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        let mut initial = buf[..len].to_vec();
+    fn encode_internal<'a>(
+        tp: &TransportParams, is_server: bool, out: &'a mut [u8],
+    ) -> Result<OctetsMut<'a>> {
+        let mut b = octets::OctetsMut::with_slice(out);
 
-        assert_eq!(pipe.client.is_in_early_data(), true);
+        if is_server {
+            if let Some(ref odcid) = tp.original_destination_connection_id {
+                TransportParams::encode_param(&mut b, 0x0000, odcid.len())?;
+                b.put_bytes(odcid)?;
+            }
+        };
 
-        // Client sends 0-RTT data.
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+        if tp.max_idle_timeout != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0001,
+                octets::varint_len(tp.max_idle_timeout),
+            )?;
+            b.put_varint(tp.max_idle_timeout)?;
+        }
 
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
-        let mut zrtt = buf[..len].to_vec();
+        if is_server {
+            if let Some(ref token) = tp.stateless_reset_token {
+                TransportParams::encode_param(&mut b, 0x0002, 16)?;
+                b.put_bytes(&token.to_be_bytes())?;
+            }
+        }
 
-        // Server receives packets.
-        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
-        assert_eq!(pipe.server.is_in_early_data(), true);
+        if tp.max_udp_payload_size != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0003,
+                octets::varint_len(tp.max_udp_payload_size),
+            )?;
+            b.put_varint(tp.max_udp_payload_size)?;
+        }
 
-        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+        if tp.initial_max_data != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0004,
+                octets::varint_len(tp.initial_max_data),
+            )?;
+            b.put_varint(tp.initial_max_data)?;
+        }
 
-        // 0-RTT stream data is readable.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        if tp.initial_max_stream_data_bidi_local != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0005,
+                octets::varint_len(tp.initial_max_stream_data_bidi_local),
+            )?;
+            b.put_varint(tp.initial_max_stream_data_bidi_local)?;
+        }
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
-        assert_eq!(&b[..12], b"hello, world");
-    }
+        if tp.initial_max_stream_data_bidi_remote != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0006,
+                octets::varint_len(tp.initial_max_stream_data_bidi_remote),
+            )?;
+            b.put_varint(tp.initial_max_stream_data_bidi_remote)?;
+        }
 
-    #[test]
-    fn stream_send_on_32bit_arch() {
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(2_u64.pow(32) + 5);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(10);
-        config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(0);
-        config.verify_peer(false);
+        if tp.initial_max_stream_data_uni != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0007,
+                octets::varint_len(tp.initial_max_stream_data_uni),
+            )?;
+            b.put_varint(tp.initial_max_stream_data_uni)?;
+        }
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        if tp.initial_max_streams_bidi != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0008,
+                octets::varint_len(tp.initial_max_streams_bidi),
+            )?;
+            b.put_varint(tp.initial_max_streams_bidi)?;
+        }
 
-        // In 32bit arch, send_capacity() should be min(2^32+5, cwnd),
-        // not min(5, cwnd)
-        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+        if tp.initial_max_streams_uni != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0009,
+                octets::varint_len(tp.initial_max_streams_uni),
+            )?;
+            b.put_varint(tp.initial_max_streams_uni)?;
+        }
 
-        assert_eq!(pipe.advance(), Ok(()));
-
-        assert!(!pipe.server.stream_finished(4));
-    }
+        if tp.ack_delay_exponent != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x000a,
+                octets::varint_len(tp.ack_delay_exponent),
+            )?;
+            b.put_varint(tp.ack_delay_exponent)?;
+        }
 
-    #[test]
-    fn empty_stream_frame() {
-        let mut buf = [0; 65535];
+        if tp.max_ack_delay != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x000b,
+                octets::varint_len(tp.max_ack_delay),
+            )?;
+            b.put_varint(tp.max_ack_delay)?;
+        }
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        if tp.disable_active_migration {
+            TransportParams::encode_param(&mut b, 0x000c, 0)?;
+        }
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"aaaaa", 0, false),
-        }];
+        // TODO: encode preferred_address
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+        if tp.active_conn_id_limit != 2 {
+            TransportParams::encode_param(
+                &mut b,
+                0x000e,
+                octets::varint_len(tp.active_conn_id_limit),
+            )?;
+            b.put_varint(tp.active_conn_id_limit)?;
+        }
 
-        let mut readable = pipe.server.readable();
-        assert_eq!(readable.next(), Some(4));
+        if let Some(scid) = &tp.initial_source_connection_id {
+            TransportParams::encode_param(&mut b, 0x000f, scid.len())?;
+            b.put_bytes(scid)?;
+        }
 
-        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, false)));
+        if is_server {
+            if let Some(scid) = &tp.retry_source_connection_id {
+                TransportParams::encode_param(&mut b, 0x0010, scid.len())?;
+                b.put_bytes(scid)?;
+            }
+        }
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"", 5, true),
-        }];
+        if let Some(info) = &tp.version_information {
+            TransportParams::encode_param(
+                &mut b,
+                VERSION_INFORMATION_PARAM_ID,
+                4 * (info.available_versions.len() + 1),
+            )?;
+            b.put_u32(info.chosen_version)?;
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+            for &v in &info.available_versions {
+                b.put_u32(v)?;
+            }
+        }
 
-        let mut readable = pipe.server.readable();
-        assert_eq!(readable.next(), Some(4));
+        if let Some(max_datagram_frame_size) = tp.max_datagram_frame_size {
+            TransportParams::encode_param(
+                &mut b,
+                0x0020,
+                octets::varint_len(max_datagram_frame_size),
+            )?;
+            b.put_varint(max_datagram_frame_size)?;
+        }
 
-        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((0, true)));
+        if let Some(plugins) = &tp.plugin_support {
+            let len = plugins
+                .iter()
+                .map(|p| octets::varint_len(p.len() as u64) + p.len())
+                .sum();
 
-        let frames = [frame::Frame::Stream {
-            stream_id: 4,
-            data: stream::RangeBuf::from(b"", 15, true),
-        }];
+            TransportParams::encode_param(&mut b, PLUGIN_SUPPORT_PARAM_ID, len)?;
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FinalSize)
-        );
-    }
+            for p in plugins {
+                b.put_varint(p.len() as u64)?;
+                b.put_bytes(p)?;
+            }
+        }
 
-    #[test]
-    /// Tests that receiving a MAX_STREAM_DATA frame for a receive-only
-    /// unidirectional stream is forbidden.
-    fn max_stream_data_receive_uni() {
-        let mut buf = [0; 65535];
+        if tp.enable_multipath {
+            TransportParams::encode_param(&mut b, ENABLE_MULTIPATH_PARAM_ID, 0)?;
+        }
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        if let Some(min_ack_delay) = tp.min_ack_delay {
+            TransportParams::encode_param(
+                &mut b,
+                MIN_ACK_DELAY_PARAM_ID,
+                octets::varint_len(min_ack_delay),
+            )?;
+            b.put_varint(min_ack_delay)?;
+        }
 
-        // Client opens unidirectional stream.
-        assert_eq!(pipe.client.stream_send(2, b"hello", false), Ok(5));
-        assert_eq!(pipe.advance(), Ok(()));
+        if tp.grease_quic_bit {
+            TransportParams::encode_param(&mut b, GREASE_QUIC_BIT_PARAM_ID, 0)?;
+        }
 
-        // Client sends MAX_STREAM_DATA on local unidirectional stream.
-        let frames = [frame::Frame::MaxStreamData {
-            stream_id: 2,
-            max: 1024,
-        }];
+        for (id, v) in &tp.extra_params {
+            TransportParams::encode_param(&mut b, *id, v.len())?;
+            b.put_bytes(v)?;
+        }
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::InvalidStreamState(2)),
-        );
+        Ok(b)
     }
 
-    #[test]
-    fn empty_payload() {
-        let mut buf = [0; 65535];
-
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    /// Returns whether the parameter identifier is handled by quiche.
+    fn is_known_param(id: u64) -> bool {
+        let extensions = [
+            0x0020,
+            PLUGIN_SUPPORT_PARAM_ID,
+            ENABLE_MULTIPATH_PARAM_ID,
+            MIN_ACK_DELAY_PARAM_ID,
+            GREASE_QUIC_BIT_PARAM_ID,
+        ];
 
-        // Send a packet with no frames.
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &[], &mut buf),
-            Err(Error::InvalidPacket)
-        );
+        // All the parameters defined by RFC 9000 have lower identifiers than
+        // version_information.
+        id <= VERSION_INFORMATION_PARAM_ID || extensions.contains(&id)
     }
 
-    #[test]
-    fn min_payload() {
-        let mut buf = [0; 65535];
+    #[allow(dead_code)]
+    fn encode<'a>(
+        tp: &TransportParams, is_server: bool, out: &'a mut [u8],
+    ) -> Result<&'a mut [u8]> {
+        let b = TransportParams::encode_internal(tp, is_server, out)?;
 
-        let mut pipe = testing::Pipe::new().unwrap();
+        let out_len = b.off();
 
-        // Send a non-ack-eliciting packet.
-        let frames = [frame::Frame::Padding { len: 4 }];
+        Ok(&mut out[..out_len])
+    }
 
-        let pkt_type = packet::Type::Initial;
-        let written =
-            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
-                .unwrap();
-        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+    /// Creates a qlog event for connection transport parameters and TLS fields
+    #[cfg(feature = "qlog")]
+    pub fn to_qlog(
+        &self, owner: TransportOwner, cipher: Option<crypto::Algorithm>,
+    ) -> EventData {
+        let original_destination_connection_id = qlog::HexSlice::maybe_string(
+            self.original_destination_connection_id.as_ref(),
+        );
 
-        let initial_path = pipe
-            .server
-            .paths
-            .get_active()
-            .expect("initial path not found");
+        let stateless_reset_token = qlog::HexSlice::maybe_string(
+            self.stateless_reset_token.map(|s| s.to_be_bytes()).as_ref(),
+        );
 
-        assert_eq!(initial_path.max_send_bytes, 195);
+        EventData::TransportParametersSet(
+            qlog::events::quic::TransportParametersSet {
+                owner: Some(owner),
+                resumption_allowed: None,
+                early_data_enabled: None,
+                tls_cipher: Some(format!("{cipher:?}")),
+                aead_tag_length: None,
+                original_destination_connection_id,
+                initial_source_connection_id: None,
+                retry_source_connection_id: None,
+                stateless_reset_token,
+                disable_active_migration: Some(self.disable_active_migration),
+                max_idle_timeout: Some(self.max_idle_timeout),
+                max_udp_payload_size: Some(self.max_udp_payload_size as u32),
+                ack_delay_exponent: Some(self.ack_delay_exponent as u16),
+                max_ack_delay: Some(self.max_ack_delay as u16),
+                active_connection_id_limit: Some(
+                    self.active_conn_id_limit as u32,
+                ),
 
-        // Force server to send a single PING frame.
-        pipe.server
-            .paths
-            .get_active_mut()
-            .expect("no active path")
-            .recovery
-            .loss_probes[packet::Epoch::Initial] = 1;
+                initial_max_data: Some(self.initial_max_data),
+                initial_max_stream_data_bidi_local: Some(
+                    self.initial_max_stream_data_bidi_local,
+                ),
+                initial_max_stream_data_bidi_remote: Some(
+                    self.initial_max_stream_data_bidi_remote,
+                ),
+                initial_max_stream_data_uni: Some(
+                    self.initial_max_stream_data_uni,
+                ),
+                initial_max_streams_bidi: Some(self.initial_max_streams_bidi),
+                initial_max_streams_uni: Some(self.initial_max_streams_uni),
 
-        let initial_path = pipe
-            .server
-            .paths
-            .get_active_mut()
-            .expect("initial path not found");
+                preferred_address: None,
+            },
+        )
+    }
+}
 
-        // Artificially limit the amount of bytes the server can send.
-        initial_path.max_send_bytes = 60;
+#[doc(hidden)]
+pub mod testing {
+    use super::*;
 
-        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+    pub struct Pipe {
+        pub client: Connection,
+        pub server: Connection,
     }
 
-    #[test]
-    fn flow_control_limit() {
-        let mut buf = [0; 65535];
+    impl Pipe {
+        pub fn new() -> Result<Pipe> {
+            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
+            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
+            config.load_priv_key_from_pem_file("examples/cert.key")?;
+            config.set_application_protos(&[b"proto1", b"proto2"])?;
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_stream_data_uni(10);
+            config.set_initial_max_streams_bidi(3);
+            config.set_initial_max_streams_uni(3);
+            config.set_max_idle_timeout(180_000);
+            config.verify_peer(false);
+            config.set_ack_delay_exponent(8);
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+            Pipe::with_config(&mut config)
+        }
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 0,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
-            frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
-            },
+        pub fn client_addr() -> SocketAddr {
+            "127.0.0.1:1234".parse().unwrap()
+        }
+
+        pub fn server_addr() -> SocketAddr {
+            "127.0.0.1:4321".parse().unwrap()
+        }
+
+        pub fn with_config(config: &mut Config) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
+
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
+
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    config,
+                )?,
+            })
+        }
+
+        pub fn with_config_and_scid_lengths(
+            config: &mut Config, client_scid_len: usize, server_scid_len: usize,
+        ) -> Result<Pipe> {
+            let mut client_scid = vec![0; client_scid_len];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
+
+            let mut server_scid = vec![0; server_scid_len];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
+
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    config,
+                )?,
+            })
+        }
+
+        pub fn with_client_config(client_config: &mut Config) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
+
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
+
+            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
+            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
+            config.load_priv_key_from_pem_file("examples/cert.key")?;
+            config.set_application_protos(&[b"proto1", b"proto2"])?;
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.set_initial_max_streams_uni(3);
+            config.set_ack_delay_exponent(8);
+
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    client_config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    &mut config,
+                )?,
+            })
+        }
+
+        pub fn with_server_config(server_config: &mut Config) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
+
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
+
+            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
+            config.set_application_protos(&[b"proto1", b"proto2"])?;
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.set_initial_max_streams_uni(3);
+            config.set_ack_delay_exponent(8);
+
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    &mut config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    server_config,
+                )?,
+            })
+        }
+
+        pub fn with_client_and_server_config(
+            client_config: &mut Config, server_config: &mut Config,
+        ) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
+
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
+
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    client_config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    server_config,
+                )?,
+            })
+        }
+
+        pub fn handshake(&mut self) -> Result<()> {
+            while !self.client.is_established() || !self.server.is_established() {
+                let flight = emit_flight(&mut self.client)?;
+                process_flight(&mut self.server, flight)?;
+
+                let flight = emit_flight(&mut self.server)?;
+                process_flight(&mut self.client, flight)?;
+            }
+
+            Ok(())
+        }
+
+        pub fn advance(&mut self) -> Result<()> {
+            let mut client_done = false;
+            let mut server_done = false;
+
+            while !client_done || !server_done {
+                match emit_flight(&mut self.client) {
+                    Ok(flight) => process_flight(&mut self.server, flight)?,
+
+                    Err(Error::Done) => client_done = true,
+
+                    Err(e) => return Err(e),
+                };
+
+                match emit_flight(&mut self.server) {
+                    Ok(flight) => process_flight(&mut self.client, flight)?,
+
+                    Err(Error::Done) => server_done = true,
+
+                    Err(e) => return Err(e),
+                };
+            }
+
+            Ok(())
+        }
+
+        pub fn client_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let server_path = &self.server.paths.get_active().unwrap();
+            let info = RecvInfo {
+                to: server_path.peer_addr(),
+                from: server_path.local_addr(),
+            };
+
+            self.client.recv(buf, info)
+        }
+
+        pub fn server_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let client_path = &self.client.paths.get_active().unwrap();
+            let info = RecvInfo {
+                to: client_path.peer_addr(),
+                from: client_path.local_addr(),
+            };
+
+            self.server.recv(buf, info)
+        }
+
+        pub fn send_pkt_to_server(
+            &mut self, pkt_type: packet::Type, frames: &[frame::Frame],
+            buf: &mut [u8],
+        ) -> Result<usize> {
+            let written = encode_pkt(&mut self.client, pkt_type, frames, buf)?;
+            recv_send(&mut self.server, buf, written)
+        }
+    }
+
+    pub fn recv_send(
+        conn: &mut Connection, buf: &mut [u8], len: usize,
+    ) -> Result<usize> {
+        let active_path = conn.paths.get_active()?;
+        let info = RecvInfo {
+            to: active_path.local_addr(),
+            from: active_path.peer_addr(),
+        };
+
+        conn.recv(&mut buf[..len], info)?;
+
+        let mut off = 0;
+
+        match conn.send(&mut buf[off..]) {
+            Ok((write, _)) => off += write,
+
+            Err(Error::Done) => (),
+
+            Err(e) => return Err(e),
+        }
+
+        Ok(off)
+    }
+
+    pub fn process_flight(
+        conn: &mut Connection, flight: Vec<(Vec<u8>, SendInfo)>,
+    ) -> Result<()> {
+        for (mut pkt, si) in flight {
+            let info = RecvInfo {
+                to: si.to,
+                from: si.from,
+            };
+
+            conn.recv(&mut pkt, info)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn emit_flight_with_max_buffer(
+        conn: &mut Connection, out_size: usize,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        let mut flight = Vec::new();
+
+        loop {
+            let mut out = vec![0u8; out_size];
+
+            let info = match conn.send(&mut out) {
+                Ok((written, info)) => {
+                    out.truncate(written);
+                    info
+                },
+
+                Err(Error::Done) => break,
+
+                Err(e) => return Err(e),
+            };
+
+            flight.push((out, info));
+        }
+
+        if flight.is_empty() {
+            return Err(Error::Done);
+        }
+
+        Ok(flight)
+    }
+
+    pub fn emit_flight(
+        conn: &mut Connection,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        emit_flight_with_max_buffer(conn, 65535)
+    }
+
+    pub fn encode_pkt(
+        conn: &mut Connection, pkt_type: packet::Type, frames: &[frame::Frame],
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mut b = octets::OctetsMut::with_slice(buf);
+
+        let epoch = pkt_type.to_epoch()?;
+
+        let space = &mut conn.pkt_num_spaces[epoch];
+
+        let pn = space.next_pkt_num;
+        let pn_len = 4;
+
+        let send_path = conn.paths.get_active()?;
+        let active_dcid_seq = send_path
+            .active_dcid_seq
+            .as_ref()
+            .ok_or(Error::InvalidState)?;
+        let active_scid_seq = send_path
+            .active_scid_seq
+            .as_ref()
+            .ok_or(Error::InvalidState)?;
+
+        let hdr = Header {
+            ty: pkt_type,
+            version: conn.version,
+            dcid: ConnectionId::from_ref(
+                conn.ids.get_dcid(*active_dcid_seq)?.cid.as_ref(),
+            ),
+            scid: ConnectionId::from_ref(
+                conn.ids.get_scid(*active_scid_seq)?.cid.as_ref(),
+            ),
+            pkt_num: 0,
+            pkt_num_len: pn_len,
+            token: conn.token.clone(),
+            versions: None,
+            key_phase: conn.key_phase,
+        };
+
+        hdr.to_bytes(&mut b)?;
+
+        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
+
+        if pkt_type != packet::Type::Short {
+            let len = pn_len + payload_len + space.crypto_overhead().unwrap();
+            b.put_varint(len as u64)?;
+        }
+
+        // Always encode packet number in 4 bytes, to allow encoding packets
+        // with empty payloads.
+        b.put_u32(pn as u32)?;
+
+        let payload_offset = b.off();
+
+        for frame in frames {
+            frame.to_bytes(&mut b)?;
+        }
+
+        let aead = match space.crypto_seal {
+            Some(ref v) => v,
+            None => return Err(Error::InvalidState),
+        };
+
+        let written = packet::encrypt_pkt(
+            &mut b,
+            pn,
+            pn_len,
+            payload_len,
+            payload_offset,
+            None,
+            aead,
+        )?;
+
+        space.next_pkt_num += 1;
+
+        Ok(written)
+    }
+
+    pub fn decode_pkt(
+        conn: &mut Connection, buf: &mut [u8], len: usize,
+    ) -> Result<Vec<frame::Frame>> {
+        let mut b = octets::OctetsMut::with_slice(&mut buf[..len]);
+
+        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
+
+        let epoch = hdr.ty.to_epoch()?;
+
+        let aead = conn.pkt_num_spaces[epoch].crypto_open.as_ref().unwrap();
+
+        let payload_len = b.cap();
+
+        packet::decrypt_hdr(&mut b, &mut hdr, aead).unwrap();
+
+        let pn = packet::decode_pkt_num(
+            conn.pkt_num_spaces[epoch].largest_rx_pkt_num,
+            hdr.pkt_num,
+            hdr.pkt_num_len,
+        );
+
+        let mut payload =
+            packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len, payload_len, aead)
+                .unwrap();
+
+        let mut frames = Vec::new();
+
+        while payload.cap() > 0 {
+            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
+            frames.push(frame);
+        }
+
+        Ok(frames)
+    }
+
+    pub fn create_cid_and_reset_token(
+        cid_len: usize,
+    ) -> (ConnectionId<'static>, u128) {
+        let mut cid = vec![0; cid_len];
+        rand::rand_bytes(&mut cid[..]);
+        let cid = ConnectionId::from_ref(&cid).into_owned();
+
+        let mut reset_token = [0; 16];
+        rand::rand_bytes(&mut reset_token);
+        let reset_token = u128::from_be_bytes(reset_token);
+
+        (cid, reset_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_params() {
+        // Server encodes, client decodes.
+        let tp = TransportParams {
+            original_destination_connection_id: None,
+            max_idle_timeout: 30,
+            stateless_reset_token: Some(u128::from_be_bytes([0xba; 16])),
+            max_udp_payload_size: 23_421,
+            initial_max_data: 424_645_563,
+            initial_max_stream_data_bidi_local: 154_323_123,
+            initial_max_stream_data_bidi_remote: 6_587_456,
+            initial_max_stream_data_uni: 2_461_234,
+            initial_max_streams_bidi: 12_231,
+            initial_max_streams_uni: 18_473,
+            ack_delay_exponent: 20,
+            max_ack_delay: 2_u64.pow(14) - 1,
+            disable_active_migration: true,
+            active_conn_id_limit: 8,
+            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
+            retry_source_connection_id: Some(b"retry".to_vec().into()),
+            max_datagram_frame_size: Some(32),
+            plugin_support: None,
+            enable_multipath: false,
+            min_ack_delay: None,
+            grease_quic_bit: false,
+            version_information: None,
+            extra_params: Vec::new(),
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
+        assert_eq!(raw_params.len(), 94);
+
+        let new_tp = TransportParams::decode(None, raw_params, false).unwrap();
+
+        assert_eq!(new_tp, tp);
+
+        // Client encodes, server decodes.
+        let tp = TransportParams {
+            original_destination_connection_id: None,
+            max_idle_timeout: 30,
+            stateless_reset_token: None,
+            max_udp_payload_size: 23_421,
+            initial_max_data: 424_645_563,
+            initial_max_stream_data_bidi_local: 154_323_123,
+            initial_max_stream_data_bidi_remote: 6_587_456,
+            initial_max_stream_data_uni: 2_461_234,
+            initial_max_streams_bidi: 12_231,
+            initial_max_streams_uni: 18_473,
+            ack_delay_exponent: 20,
+            max_ack_delay: 2_u64.pow(14) - 1,
+            disable_active_migration: true,
+            active_conn_id_limit: 8,
+            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
+            retry_source_connection_id: None,
+            max_datagram_frame_size: Some(32),
+            plugin_support: None,
+            enable_multipath: false,
+            min_ack_delay: None,
+            grease_quic_bit: false,
+            version_information: None,
+            extra_params: Vec::new(),
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+        assert_eq!(raw_params.len(), 69);
+
+        let new_tp = TransportParams::decode(None, raw_params, true).unwrap();
+
+        assert_eq!(new_tp, tp);
+    }
+
+    #[test]
+    fn transport_params_plugin_support() {
+        let tp = TransportParams {
+            plugin_support: Some(vec![b"foo".to_vec(), b"quux".to_vec()]),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        let new_tp = TransportParams::decode(None, raw_params, true).unwrap();
+
+        assert_eq!(new_tp, tp);
+
+        // Empty plugin names are rejected.
+        let mut raw_params = [0; 32];
+        let mut b = octets::OctetsMut::with_slice(&mut raw_params);
+        b.put_varint(PLUGIN_SUPPORT_PARAM_ID).unwrap();
+        b.put_varint(1).unwrap();
+        b.put_varint(0).unwrap();
+        let len = b.off();
+
+        assert_eq!(
+            TransportParams::decode(None, &raw_params[..len], true),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn transport_params_min_ack_delay() {
+        let tp = TransportParams {
+            min_ack_delay: Some(1_000),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        let new_tp = TransportParams::decode(None, raw_params, true).unwrap();
+
+        assert_eq!(new_tp, tp);
+
+        // The minimum ACK delay can't exceed the maximum one.
+        let tp = TransportParams {
+            max_ack_delay: 25,
+            min_ack_delay: Some(25_001),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        assert_eq!(
+            TransportParams::decode(None, raw_params, true),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn plugin_support_negotiation() {
+        let mut client_config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        client_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        client_config.verify_peer(false);
+        assert_eq!(
+            client_config.set_plugin_support(&[b"foo", b"bar", b"baz"]),
+            Ok(())
+        );
+        assert_eq!(
+            client_config.set_plugin_support(&[b"foo", b""]),
+            Err(Error::InvalidState)
+        );
+
+        let mut server_config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config.set_plugin_support(&[b"baz", b"foo"]).unwrap();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client.peer_plugin_support(), None);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.peer_plugin_support(),
+            Some(&[b"baz".to_vec(), b"foo".to_vec()][..])
+        );
+        assert_eq!(
+            pipe.server.peer_plugin_support(),
+            Some(&[b"foo".to_vec(), b"bar".to_vec(), b"baz".to_vec()][..])
+        );
+
+        assert_eq!(pipe.client.negotiated_plugins(), vec![b"foo", b"baz"]);
+        assert_eq!(pipe.server.negotiated_plugins(), vec![b"baz", b"foo"]);
+    }
+
+    #[test]
+    fn plugin_state_without_plugins() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut buf = [0; 128];
+        assert_eq!(pipe.client.export_plugin_state(&mut buf), Err(Error::Done));
+        assert_eq!(pipe.client.import_plugin_state(b"state"), Err(Error::Done));
+    }
+
+    #[test]
+    fn transport_params_unknown() {
+        let tp = TransportParams {
+            initial_max_data: 1_000,
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let len = TransportParams::encode(&tp, false, &mut raw_params)
+            .unwrap()
+            .len();
+
+        // Append a GREASE parameter.
+        let mut b = octets::OctetsMut::with_slice(&mut raw_params[len..]);
+        b.put_varint(31 * 5 + 27).unwrap();
+        b.put_varint(3).unwrap();
+        b.put_bytes(b"abc").unwrap();
+        let len = len + b.off();
+
+        let new_tp =
+            TransportParams::decode(None, &raw_params[..len], true).unwrap();
+
+        assert_eq!(new_tp.initial_max_data, 1_000);
+        assert_eq!(new_tp.extra_params, vec![(31 * 5 + 27, b"abc".to_vec())]);
+    }
+
+    #[test]
+    fn peer_transport_params() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_idle_timeout(180_000);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(pipe.client.peer_transport_params(), None);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let params = pipe.client.peer_transport_params().unwrap();
+        assert_eq!(params.initial_max_data, 30);
+        assert_eq!(params.initial_max_streams_bidi, 3);
+        assert_eq!(params.max_idle_timeout, 180_000);
+        assert!(params.stateless_reset_token.is_some());
+        assert_eq!(
+            params.initial_source_connection_id.as_deref(),
+            Some(pipe.server.source_id().as_ref())
+        );
+        assert!(params.unknown_params.is_empty());
+
+        let params = pipe.server.peer_transport_params().unwrap();
+        assert_eq!(params.initial_max_data, 30);
+        assert_eq!(params.stateless_reset_token, None);
+    }
+
+    #[test]
+    fn custom_transport_params() {
+        let mut client_config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        client_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        client_config.verify_peer(false);
+        assert_eq!(
+            client_config.add_custom_transport_param(0x7c5d, b"c"),
+            Ok(())
+        );
+        assert_eq!(
+            client_config.add_custom_transport_param(0x7c5d, b"d"),
+            Err(Error::InvalidState)
+        );
+        assert_eq!(
+            client_config.add_custom_transport_param(0x0004, b""),
+            Err(Error::InvalidState)
+        );
+        assert_eq!(
+            client_config.add_custom_transport_param(1 << 62, b""),
+            Err(Error::InvalidState)
+        );
+
+        let mut server_config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config
+            .add_custom_transport_param(0x7c5e, b"server")
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client.peer_custom_transport_param(0x7c5e), None);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.peer_custom_transport_param(0x7c5e),
+            Some(&b"server"[..])
+        );
+        assert_eq!(pipe.client.peer_custom_transport_param(0x7c5d), None);
+        assert_eq!(
+            pipe.server.peer_custom_transport_param(0x7c5d),
+            Some(&b"c"[..])
+        );
+
+        let params = pipe.server.peer_transport_params().unwrap();
+        assert_eq!(params.unknown_params, vec![(0x7c5d, b"c".to_vec())]);
+    }
+
+    #[test]
+    fn transport_params_forbid_duplicates() {
+        // Given an encoded param.
+        let initial_source_connection_id = b"id";
+        let initial_source_connection_id_raw = [
+            15,
+            initial_source_connection_id.len() as u8,
+            initial_source_connection_id[0],
+            initial_source_connection_id[1],
+        ];
+
+        // No error when decoding the param.
+        let tp = TransportParams::decode(
+            None,
+            initial_source_connection_id_raw.as_slice(),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            tp.initial_source_connection_id,
+            Some(initial_source_connection_id.to_vec().into())
+        );
+
+        // Duplicate the param.
+        let mut raw_params = Vec::new();
+        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
+        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
+
+        // Decoding fails.
+        assert_eq!(
+            TransportParams::decode(None, raw_params.as_slice(), true),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn transport_params_version_information() {
+        let tp = TransportParams {
+            version_information: Some(VersionInformation {
+                chosen_version: PROTOCOL_VERSION_V1,
+                available_versions: SUPPORTED_VERSIONS.to_vec(),
+            }),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        let new_tp = TransportParams::decode(None, raw_params, true).unwrap();
+
+        assert_eq!(new_tp, tp);
+
+        // Versions can't be zero.
+        let tp = TransportParams {
+            version_information: Some(VersionInformation {
+                chosen_version: PROTOCOL_VERSION_V1,
+                available_versions: vec![0],
+            }),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+
+        assert_eq!(
+            TransportParams::decode(None, raw_params, true),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut config = Config::new(0xbabababa).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Err(Error::UnknownVersion));
+    }
+
+    #[test]
+    fn config_version_reserved() {
+        Config::new(0xbabababa).unwrap();
+        Config::new(0x1a2a3a4a).unwrap();
+    }
+
+    #[test]
+    fn config_version_invalid() {
+        assert_eq!(
+            Config::new(0xb1bababa).err().unwrap(),
+            Error::UnknownVersion
+        );
+    }
+
+    #[test]
+    fn config_profiles() {
+        for profile in [
+            ConfigProfile::LowLatency,
+            ConfigProfile::Bulk,
+            ConfigProfile::ConstrainedMemory,
+            ConfigProfile::Interop,
+        ] {
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.verify_peer(false);
+            config.apply_profile(profile);
+
+            assert_eq!(
+                config.cc_algorithm == CongestionControlAlgorithm::BBR,
+                profile == ConfigProfile::LowLatency
+            );
+            assert_eq!(config.pmtud, profile == ConfigProfile::Bulk);
+
+            let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+            assert_eq!(pipe.handshake(), Ok(()));
+
+            let data = [42; 50_000];
+            let mut sent = 0;
+
+            let mut b = [0; 50_000];
+            let mut len = 0;
+            let mut fin = false;
+
+            while !fin {
+                if sent < data.len() {
+                    sent += pipe
+                        .client
+                        .stream_send(0, &data[sent..], true)
+                        .unwrap_or(0);
+                }
+
+                assert_eq!(pipe.advance(), Ok(()));
+
+                while let Ok((read, f)) =
+                    pipe.server.stream_recv(0, &mut b[len..])
+                {
+                    len += read;
+                    fin = f;
+                }
+            }
+
+            assert_eq!(len, 50_000);
+        }
+    }
+
+    #[test]
+    fn version_negotiation() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(0xbabababa).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        len = crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut buf).unwrap();
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn version_negotiation_supported_versions() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        assert_eq!(
+            config.set_supported_versions(&[]),
+            Err(Error::UnknownVersion)
+        );
+        assert_eq!(
+            config.set_supported_versions(&[0xbabababa]),
+            Err(Error::UnknownVersion)
+        );
+
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config
+            .set_supported_versions(&[PROTOCOL_VERSION_DRAFT29])
+            .unwrap();
+        config.verify_peer(false);
+
+        // The server doesn't accept the client's version.
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(
+            pipe.server_recv(&mut buf[..len]),
+            Err(Error::UnknownVersion)
+        );
+
+        // The client switches to the version listed by the server.
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        let len = negotiate_version_with_config(
+            &hdr.scid, &hdr.dcid, &config, &mut buf,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_DRAFT29);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_DRAFT29);
+    }
+
+    #[test]
+    fn version_negotiation_downgrade() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // An attacker only lists a version older than the one the client and
+        // the server would choose.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        let len = packet::negotiate_version(
+            &hdr.scid,
+            &hdr.dcid,
+            &[PROTOCOL_VERSION_DRAFT29],
+            &mut buf,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_DRAFT29);
+
+        // The client detects the downgrade from the server's
+        // version_information.
+        assert_eq!(pipe.handshake(), Err(Error::VersionNegotiation));
+    }
+
+    #[test]
+    fn verify_custom_root() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config.verify_peer(true);
+        config
+            .load_verify_locations_from_file("examples/rootca.crt")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
+
+    #[test]
+    fn verify_client_cert() {
+        let mut client_config = Config::new(PROTOCOL_VERSION).unwrap();
+        client_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        client_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        client_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        client_config.verify_peer(false);
+
+        let mut server_config = Config::new(PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .load_verify_locations_from_file("examples/rootca.crt")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config.verify_peer_required(true);
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let chain = pipe.server.peer_cert_chain().unwrap();
+        assert_eq!(chain.len(), 1);
+        assert_eq!(Some(chain[0]), pipe.server.peer_cert());
+    }
+
+    #[test]
+    fn verify_client_cert_missing() {
+        let mut server_config = Config::new(PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .load_verify_locations_from_file("examples/rootca.crt")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config.verify_peer_required(true);
+
+        let mut pipe =
+            testing::Pipe::with_server_config(&mut server_config).unwrap();
+        assert_eq!(pipe.handshake(), Err(Error::TlsFail));
+
+        let err = pipe.server.local_error().unwrap();
+        assert_eq!(err.tls_alert_description(), Some("certificate_required"));
+        assert_eq!(pipe.server.peer_cert_chain(), None);
+    }
+
+    #[test]
+    fn cert_verifier() {
+        struct TestVerifier {
+            accept: bool,
+
+            chains: std::sync::Mutex<Vec<Vec<Vec<u8>>>>,
+        }
+
+        impl CertVerifier for TestVerifier {
+            fn verify(&self, chain: &[&[u8]]) -> std::result::Result<(), u64> {
+                let chain = chain.iter().map(|c| c.to_vec()).collect();
+                self.chains.lock().unwrap().push(chain);
+
+                // access_denied
+                if self.accept {
+                    Ok(())
+                } else {
+                    Err(49)
+                }
+            }
+        }
+
+        for accept in [true, false] {
+            let verifier = Arc::new(TestVerifier {
+                accept,
+                chains: Default::default(),
+            });
+
+            let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.verify_peer(false);
+            config.set_cert_verifier(Some(verifier.clone()));
+
+            let mut pipe =
+                testing::Pipe::with_client_config(&mut config).unwrap();
+
+            if accept {
+                assert_eq!(pipe.handshake(), Ok(()));
+            } else {
+                assert_eq!(pipe.handshake(), Err(Error::TlsFail));
+
+                let err = pipe.client.local_error().unwrap();
+                assert_eq!(err.tls_alert_description(), Some("access_denied"));
+            }
+
+            // The verifier is called once, with the server's certificate.
+            let chains = verifier.chains.lock().unwrap();
+            assert_eq!(chains.len(), 1);
+            assert_eq!(chains[0], vec![pipe
+                .client
+                .peer_cert()
+                .unwrap()
+                .to_vec()]);
+        }
+    }
+
+    #[test]
+    fn verify_callback() {
+        for accept in [true, false] {
+            let chains = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let cb_chains = chains.clone();
+
+            let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+
+            // No CA is trusted, so the handshake only succeeds if the callback
+            // is used instead of the TLS library's verification.
+            config.set_verify_callback(Box::new(move |chain: &[&[u8]]| {
+                let chain: Vec<Vec<u8>> =
+                    chain.iter().map(|c| c.to_vec()).collect();
+                cb_chains.lock().unwrap().push(chain);
+
+                // access_denied
+                if accept {
+                    Ok(())
+                } else {
+                    Err(49)
+                }
+            }));
+
+            let mut pipe =
+                testing::Pipe::with_client_config(&mut config).unwrap();
+
+            if accept {
+                assert_eq!(pipe.handshake(), Ok(()));
+            } else {
+                assert_eq!(pipe.handshake(), Err(Error::TlsFail));
+
+                let err = pipe.client.local_error().unwrap();
+                assert_eq!(err.tls_alert_description(), Some("access_denied"));
+            }
+
+            // The callback is called once, with the server's certificate.
+            let chains = chains.lock().unwrap();
+            assert_eq!(chains.len(), 1);
+            assert_eq!(chains[0].len(), 1);
+
+            if accept {
+                assert_eq!(
+                    chains[0][0].as_slice(),
+                    pipe.client.peer_cert().unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn missing_initial_source_connection_id() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Reset initial_source_connection_id.
+        pipe.client
+            .local_transport_params
+            .initial_source_connection_id = None;
+        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server rejects transport parameters.
+        assert_eq!(
+            pipe.server_recv(&mut buf[..len]),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn invalid_initial_source_connection_id() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Scramble initial_source_connection_id.
+        pipe.client
+            .local_transport_params
+            .initial_source_connection_id = Some(b"bogus value".to_vec().into());
+        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server rejects transport parameters.
+        assert_eq!(
+            pipe.server_recv(&mut buf[..len]),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn handshake() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.application_proto(),
+            pipe.server.application_proto()
+        );
+
+        assert_eq!(pipe.server.server_name(), Some("quic.tech"));
+    }
+
+    #[test]
+    fn handshake_done() {
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Disable session tickets on the server (SSL_OP_NO_TICKET) to avoid
+        // triggering 1-RTT packet send with a CRYPTO frame.
+        pipe.server.handshake.set_options(0x0000_4000);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.server.handshake_done_sent);
+    }
+
+    #[test]
+    fn handshake_confirmation() {
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Client sends initial flight.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Server sends initial flight.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+        assert!(!pipe.client.is_established());
+        assert!(!pipe.client.handshake_confirmed);
+
+        assert!(!pipe.server.is_established());
+        assert!(!pipe.server.handshake_confirmed);
+
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        // Client sends Handshake packet and completes handshake.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        assert!(pipe.client.is_established());
+        assert!(!pipe.client.handshake_confirmed);
+
+        assert!(!pipe.server.is_established());
+        assert!(!pipe.server.handshake_confirmed);
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Server completes handshake and sends HANDSHAKE_DONE.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+        assert!(pipe.client.is_established());
+        assert!(!pipe.client.handshake_confirmed);
+
+        assert!(pipe.server.is_established());
+        assert!(!pipe.server.handshake_confirmed);
+
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        // Client acks 1-RTT packet, and confirms handshake.
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        assert!(pipe.client.is_established());
+        assert!(pipe.client.handshake_confirmed);
+
+        assert!(pipe.server.is_established());
+        assert!(!pipe.server.handshake_confirmed);
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Server handshake is confirmed.
+        assert!(pipe.client.is_established());
+        assert!(pipe.client.handshake_confirmed);
+
+        assert!(pipe.server.is_established());
+        assert!(pipe.server.handshake_confirmed);
+    }
+
+    #[test]
+    fn handshake_resumption() {
+        const SESSION_TICKET_KEY: [u8; 48] = [0xa; 48];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_ticket_key(&SESSION_TICKET_KEY).unwrap();
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_established(), true);
+        assert_eq!(pipe.server.is_established(), true);
+
+        assert_eq!(pipe.client.is_resumed(), false);
+        assert_eq!(pipe.server.is_resumed(), false);
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // Configure session on new connection and perform handshake.
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_ticket_key(&SESSION_TICKET_KEY).unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_established(), true);
+        assert_eq!(pipe.server.is_established(), true);
+
+        assert_eq!(pipe.client.is_resumed(), true);
+        assert_eq!(pipe.server.is_resumed(), true);
+    }
+
+    #[test]
+    fn handshake_resumption_key_ring() {
+        let server_config = |key_ring: &Arc<KeyRing>| {
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.set_key_ring(Some(key_ring.clone()));
+            config
+        };
+
+        let key_ring = Arc::new(KeyRing::new(
+            time::Duration::from_secs(3600),
+            time::Duration::from_secs(600),
+        ));
+
+        // Perform initial handshake.
+        let mut config = server_config(&key_ring);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.server.is_resumed(), false);
+
+        let session = pipe.client.session().unwrap().to_vec();
+
+        // The ticket is still accepted by another config sharing the ring,
+        // after its key was rotated.
+        key_ring.rotate();
+
+        let mut config = server_config(&key_ring);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(&session), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), true);
+        assert_eq!(pipe.server.is_resumed(), true);
+
+        // The ticket is not accepted with other keys.
+        let other_ring = Arc::new(KeyRing::new(
+            time::Duration::from_secs(3600),
+            time::Duration::from_secs(600),
+        ));
+
+        let mut config = server_config(&other_ring);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(&session), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), false);
+        assert_eq!(pipe.server.is_resumed(), false);
+    }
+
+    #[test]
+    fn handshake_resumption_ticket_keys() {
+        let key = |b: u8| TicketKey::new(&[b; 48]).unwrap();
+
+        let server_config = |keys: &[TicketKey]| {
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.set_ticket_keys(keys).unwrap();
+            config
+        };
+
+        // Perform initial handshake.
+        let mut config = server_config(&[key(1)]);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.server.is_resumed(), false);
+
+        let session = pipe.client.session().unwrap().to_vec();
+
+        // The ticket is still accepted after the encryption key is rotated.
+        assert_eq!(config.rotate_ticket_key(key(2)), Ok(()));
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(&session), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), true);
+        assert_eq!(pipe.server.is_resumed(), true);
+
+        // As well as by other servers, which only use the key to decrypt.
+        let mut config = server_config(&[key(2), key(1)]);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(&session), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), true);
+        assert_eq!(pipe.server.is_resumed(), true);
+
+        // The ticket is not accepted once its key is dropped.
+        let mut config = server_config(&[key(2)]);
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(&session), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), false);
+        assert_eq!(pipe.server.is_resumed(), false);
+
+        // Keys can only be rotated once set.
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        assert_eq!(config.rotate_ticket_key(key(3)), Err(Error::InvalidState));
+    }
+
+    #[test]
+    fn handshake_resumption_client_store() {
+        const SESSION_TICKET_KEY: [u8; 48] = [0xa; 48];
+
+        let store = Arc::new(MemoryClientStore::new());
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_ticket_key(&SESSION_TICKET_KEY).unwrap();
+        config.set_client_store(Some(store.clone()));
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), false);
+
+        // The session was recorded in the store.
+        let session = store.take_session("quic.tech");
+        assert_eq!(session.as_deref(), pipe.client.session());
+
+        // It is only recorded again when a new session ticket is received.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(store.take_session("quic.tech"), None);
+
+        store.put_session("quic.tech", &session.unwrap());
+
+        // The server sends a NEW_TOKEN frame, which is recorded as well.
+        let mut buf = [0; 65535];
+
+        let frames = [frame::Frame::NewToken {
+            token: b"token".to_vec(),
+        }];
+
+        let written = testing::encode_pkt(
+            &mut pipe.server,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client_recv(&mut buf[..written]), Ok(written));
+
+        // A new connection picks up the session and token from the store.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.token, Some(b"token".to_vec()));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), true);
+        assert_eq!(pipe.server.is_resumed(), true);
+
+        // The token was used, so the next connection doesn't reuse it.
+        let pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.token, None);
+    }
+
+    #[test]
+    fn metrics() {
+        #[derive(Default)]
+        struct TestMetrics {
+            counters: std::sync::Mutex<HashMap<Counter, u64>>,
+
+            connections: std::sync::Mutex<i64>,
+
+            histograms: std::sync::Mutex<HashMap<Histogram, usize>>,
+        }
+
+        impl Metrics for TestMetrics {
+            fn incr_counter(&self, counter: Counter, v: u64) {
+                *self.counters.lock().unwrap().entry(counter).or_default() += v;
+            }
+
+            fn add_gauge(&self, gauge: Gauge, delta: i64) {
+                assert_eq!(gauge, Gauge::Connections);
+                *self.connections.lock().unwrap() += delta;
+            }
+
+            fn record_histogram(&self, histogram: Histogram, _v: f64) {
+                let mut histograms = self.histograms.lock().unwrap();
+                *histograms.entry(histogram).or_default() += 1;
+            }
+        }
+
+        let metrics = Arc::new(TestMetrics::default());
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_metrics(Some(metrics.clone()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(*metrics.connections.lock().unwrap(), 2);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let client = pipe.client.stats();
+        let server = pipe.server.stats();
+
+        {
+            let counters = metrics.counters.lock().unwrap();
+
+            assert_eq!(
+                counters[&Counter::PacketsSent],
+                (client.sent + server.sent) as u64
+            );
+            assert_eq!(
+                counters[&Counter::PacketsRecv],
+                (client.recv + server.recv) as u64
+            );
+            assert_eq!(
+                counters[&Counter::BytesSent],
+                client.sent_bytes + server.sent_bytes
+            );
+            assert_eq!(
+                counters[&Counter::BytesRecv],
+                client.recv_bytes + server.recv_bytes
+            );
+            assert_eq!(counters[&Counter::HandshakesCompleted], 2);
+
+            let histograms = metrics.histograms.lock().unwrap();
+
+            assert_eq!(histograms[&Histogram::HandshakeDuration], 2);
+            assert!(histograms[&Histogram::Cwnd] > 0);
+        }
+
+        drop(pipe);
+        assert_eq!(*metrics.connections.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn delivery_rate_observer() {
+        #[derive(Default)]
+        struct TestObserver {
+            samples: std::sync::Mutex<Vec<(SocketAddr, DeliveryRateSample)>>,
+        }
+
+        impl DeliveryRateObserver for TestObserver {
+            fn on_rate_sample(
+                &self, _local_addr: SocketAddr, peer_addr: SocketAddr,
+                sample: &DeliveryRateSample,
+            ) {
+                self.samples.lock().unwrap().push((peer_addr, *sample));
+            }
+        }
+
+        let observer = Arc::new(TestObserver::default());
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_delivery_rate_observer(Some(observer.clone()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let samples = observer.samples.lock().unwrap();
+        assert!(!samples.is_empty());
+
+        // The rate of the latest sample reported for the client is also
+        // exposed in the path's stats.
+        let (_, latest) = samples
+            .iter()
+            .rev()
+            .find(|(peer, _)| *peer == testing::Pipe::server_addr())
+            .unwrap();
+
+        let stats = pipe.client.path_stats().next().unwrap();
+        assert_eq!(stats.delivery_rate, latest.delivery_rate);
+        assert_eq!(
+            stats.delivery_rate_sample.delivery_rate,
+            latest.delivery_rate
+        );
+    }
+
+    #[test]
+    fn custom_crypto_provider() {
+        use std::sync::atomic;
+
+        #[derive(Default)]
+        struct TestProvider {
+            packet_keys: atomic::AtomicUsize,
+        }
+
+        impl CryptoProvider for TestProvider {
+            fn new_packet_key(
+                &self, alg: CryptoAlgorithm, key: &[u8],
+            ) -> Result<Box<dyn PacketKey>> {
+                self.packet_keys.fetch_add(1, atomic::Ordering::Relaxed);
+
+                DefaultCryptoProvider.new_packet_key(alg, key)
+            }
+
+            fn new_header_key(
+                &self, alg: CryptoAlgorithm, key: &[u8],
+            ) -> Result<Box<dyn HeaderKey>> {
+                DefaultCryptoProvider.new_header_key(alg, key)
+            }
+
+            fn hkdf_extract(
+                &self, alg: CryptoAlgorithm, salt: &[u8], ikm: &[u8],
+            ) -> Result<Vec<u8>> {
+                DefaultCryptoProvider.hkdf_extract(alg, salt, ikm)
+            }
+
+            fn hkdf_expand(
+                &self, alg: CryptoAlgorithm, prk: &[u8], info: &[u8],
+                out: &mut [u8],
+            ) -> Result<()> {
+                DefaultCryptoProvider.hkdf_expand(alg, prk, info, out)
+            }
+        }
+
+        let provider = Arc::new(TestProvider::default());
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_crypto_provider(Some(provider.clone()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"hello");
+
+        // The Initial, Handshake and 1-RTT keys of both endpoints.
+        assert_eq!(provider.packet_keys.load(atomic::Ordering::Relaxed), 12);
+    }
+
+    #[test]
+    fn short_packet_padding() {
+        let mut buf = [0; 65535];
+
+        for padding in [
+            ShortPacketPadding::None,
+            ShortPacketPadding::Min(1000),
+            ShortPacketPadding::Bucket(64),
+        ] {
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.verify_peer(false);
+            config.set_short_packet_padding(padding);
+
+            let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+            assert_eq!(pipe.handshake(), Ok(()));
+
+            assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+
+            let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+            match padding {
+                ShortPacketPadding::None => assert!(len < 64),
+
+                ShortPacketPadding::Min(min) => assert_eq!(len, min),
+
+                ShortPacketPadding::Bucket(bucket) => {
+                    assert!(len > 0);
+                    assert_eq!(len % bucket, 0);
+                },
+            }
+
+            // The padded packet is still readable by the peer.
+            assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+            let mut b = [0; 15];
+            assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((1, true)));
+
+            // ACK-only packets are not padded.
+            let (len, _) = pipe.server.send(&mut buf).unwrap();
+            assert!(len < 64);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "datagram")]
+    fn deterministic_frame_order() {
+        let mut buf = [0; 65535];
+
+        for deterministic in [false, true] {
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.enable_dgram(true, 10, 10);
+            config.verify_peer(false);
+            config.set_deterministic_frame_order(deterministic);
+
+            let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+            assert_eq!(pipe.handshake(), Ok(()));
+
+            // The server has a packet to acknowledge.
+            assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+            let (len, _) = pipe.client.send(&mut buf).unwrap();
+            assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+            // Only one DATAGRAM fits in each packet.
+            assert_eq!(pipe.server.dgram_send(&[0; 800]), Ok(()));
+            assert_eq!(pipe.server.dgram_send(&[0; 800]), Ok(()));
+            assert_eq!(pipe.server.stream_send(0, b"world", false), Ok(5));
+
+            let (len, _) = pipe.server.send(&mut buf).unwrap();
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+            assert!(matches!(frames.first(), Some(frame::Frame::ACK { .. })));
+            assert!(matches!(frames.last(), Some(frame::Frame::Datagram { .. })));
+
+            // Without a deterministic order, the next packet carries STREAM
+            // frames instead of the pending DATAGRAM.
+            let (len, _) = pipe.server.send(&mut buf).unwrap();
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+            if deterministic {
+                assert!(matches!(frames[0], frame::Frame::Datagram { .. }));
+            } else {
+                assert!(matches!(frames[0], frame::Frame::Stream { .. }));
+            }
+        }
+    }
+
+    #[test]
+    fn late_initial_packet() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Keep a copy of the client's Initial to replay it later.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut replay = buf[..len].to_vec();
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.server.stats().late_initial, 0);
+
+        // The late packet is dropped, but accounted for.
+        assert_eq!(pipe.server_recv(&mut replay), Ok(len));
+        assert_eq!(pipe.server.stats().late_initial, 1);
+        assert!(!pipe.server.is_closed());
+
+        // Now configure the server to error on late packets.
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_error_on_late_packets(true);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut replay = buf[..len].to_vec();
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.server_recv(&mut replay), Err(Error::InvalidPacket));
+        assert_eq!(pipe.server.stats().late_initial, 1);
+        assert_eq!(
+            pipe.server.local_error(),
+            Some(&ConnectionError {
+                is_app: false,
+                error_code: 0xa,
+                frame_type: 0,
+                reason: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn key_update() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        assert_eq!(
+            pipe.client.key_update_event_next(),
+            Some(KeyUpdateEvent::LocalInitiated)
+        );
+
+        // A new update can't be initiated until this one is confirmed.
+        assert_eq!(pipe.client.initiate_key_update(), Err(Error::KeyUpdate));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 5];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b, b"hello");
+
+        assert_eq!(
+            pipe.server.key_update_event_next(),
+            Some(KeyUpdateEvent::PeerInitiated)
+        );
+        assert_eq!(
+            pipe.client.key_update_event_next(),
+            Some(KeyUpdateEvent::LocalConfirmed)
+        );
+
+        // The server's update is confirmed once the client acknowledges an
+        // ack-eliciting packet protected with the new keys.
+        assert_eq!(pipe.server.key_update_event_next(), None);
+
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b, b"world");
+
+        assert_eq!(
+            pipe.server.key_update_event_next(),
+            Some(KeyUpdateEvent::PeerConfirmed)
+        );
+        assert_eq!(pipe.server.key_update_event_next(), None);
+        assert_eq!(pipe.client.key_update_event_next(), None);
+
+        assert_eq!(pipe.client.stats().key_updates, 1);
+        assert_eq!(pipe.server.stats().key_updates, 1);
+
+        // The server can now initiate the next update.
+        assert_eq!(pipe.server.initiate_key_update(), Ok(()));
+        assert_eq!(pipe.server.stream_send(5, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.key_update_event_next(),
+            Some(KeyUpdateEvent::PeerInitiated)
+        );
+        assert_eq!(pipe.client.stats().key_updates, 2);
+    }
+
+    #[test]
+    fn key_update_interval() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(1000);
+        config.set_initial_max_stream_data_bidi_local(1000);
+        config.set_initial_max_stream_data_bidi_remote(1000);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_key_update_interval(KeyUpdateInterval::Packets(5));
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(!pipe.client.stats().key_phase);
+
+        // The client updates its keys once it sent enough packets.
+        let mut rounds = 0;
+
+        while pipe.client.key_update_event_next().is_none() {
+            assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+            assert_eq!(pipe.advance(), Ok(()));
+
+            rounds += 1;
+            assert!(rounds <= 5);
+        }
+
+        assert_eq!(
+            pipe.server.key_update_event_next(),
+            Some(KeyUpdateEvent::PeerInitiated)
+        );
+
+        assert!(pipe.client.stats().key_phase);
+        assert!(pipe.server.stats().key_phase);
+        assert_eq!(pipe.client.stats().key_updates, 1);
+
+        // The next update only happens after the previous one is confirmed.
+        let mut rounds = 0;
+
+        loop {
+            assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+            assert_eq!(pipe.advance(), Ok(()));
+
+            rounds += 1;
+            assert!(rounds <= 10);
+
+            match pipe.client.key_update_event_next() {
+                Some(KeyUpdateEvent::LocalInitiated) => break,
+
+                Some(ev) => assert_eq!(ev, KeyUpdateEvent::LocalConfirmed),
+
+                None => (),
+            }
+        }
+
+        assert!(!pipe.client.stats().key_phase);
+        assert_eq!(pipe.client.stats().key_updates, 2);
+
+        // The server doesn't rotate its keys on its own.
+        assert_eq!(pipe.server.stats().key_updates, 2);
+    }
+
+    #[test]
+    fn key_update_before_confirmation() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        assert_eq!(
+            pipe.server.key_update_event_next(),
+            Some(KeyUpdateEvent::PeerInitiated)
+        );
+
+        // Force the client to update again, before the server sent an ACK
+        // protected with the new keys.
+        pipe.client.pkt_num_spaces[packet::Epoch::Application]
+            .key_update
+            .as_mut()
+            .unwrap()
+            .update_acked = true;
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, b"b", false), Ok(1));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert_eq!(
+            testing::process_flight(&mut pipe.server, flight),
+            Err(Error::KeyUpdate)
+        );
+
+        assert_eq!(
+            pipe.server.local_error(),
+            Some(&ConnectionError {
+                is_app: false,
+                error_code: 0xe,
+                frame_type: 0,
+                reason: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn key_update_ack_only_receiver() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The server only sends ACK-only packets, which are never
+        // acknowledged, so its side of each update is never confirmed. The
+        // client can still update again once its own packets are
+        // acknowledged.
+        for _ in 0..3 {
+            assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+            assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+            assert_eq!(pipe.advance(), Ok(()));
+
+            assert!(
+                !pipe.server.pkt_num_spaces[packet::Epoch::Application]
+                    .key_update
+                    .as_ref()
+                    .unwrap()
+                    .update_acked
+            );
+        }
+
+        assert_eq!(pipe.server.stats().key_updates, 3);
+        assert!(!pipe.server.is_closed());
+        assert!(!pipe.client.is_closed());
+    }
+
+    #[test]
+    fn aead_confidentiality_limit() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let limit = pipe.client.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_seal
+            .as_ref()
+            .unwrap()
+            .alg()
+            .confidentiality_limit();
+
+        // The keys are updated once they protected as many packets as the
+        // AEAD allows.
+        pipe.client.key_phase_sent_count = limit;
+
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.key_update_event_next(),
+            Some(KeyUpdateEvent::LocalInitiated)
+        );
+        assert_eq!(pipe.client.stats().key_updates, 1);
+        assert!(pipe.client.stats().key_phase_sent < limit);
+        assert_eq!(pipe.client.local_error(), None);
+
+        // The connection is closed when the keys can't be updated.
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        pipe.client.key_phase_sent_count = limit;
+
+        assert_eq!(pipe.client.stream_send(0, b"b", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.local_error(),
+            Some(&ConnectionError {
+                is_app: false,
+                error_code: 0xf,
+                frame_type: 0,
+                reason: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn aead_integrity_limit() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let limit = pipe.server.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_open
+            .as_ref()
+            .unwrap()
+            .alg()
+            .integrity_limit();
+
+        pipe.server.aead_auth_fail_count = limit - 2;
+
+        let forge = |pipe: &mut testing::Pipe| {
+            assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+
+            let (mut pkt, info) =
+                testing::emit_flight(&mut pipe.client).unwrap().remove(0);
+
+            // Corrupt the authentication tag.
+            let last = pkt.len() - 1;
+            pkt[last] ^= 0xff;
+
+            let info = RecvInfo {
+                from: info.from,
+                to: info.to,
+            };
+
+            pipe.server.recv(&mut pkt, info)
+        };
+
+        // Packets failing authentication are dropped until the limit is
+        // reached.
+        assert!(forge(&mut pipe).is_ok());
+        assert_eq!(forge(&mut pipe), Err(Error::AeadLimitReached));
+
+        assert_eq!(pipe.server.stats().aead_auth_failures, limit);
+        assert_eq!(
+            pipe.server.local_error(),
+            Some(&ConnectionError {
+                is_app: false,
+                error_code: 0xf,
+                frame_type: 0,
+                reason: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn max_queued_events() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_max_queued_events(1);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The confirmation of the update doesn't fit in the queue.
+        assert_eq!(pipe.client.stats().dropped_events, 1);
+        assert_eq!(
+            pipe.client.key_update_event_next(),
+            Some(KeyUpdateEvent::LocalInitiated)
+        );
+        assert_eq!(pipe.client.key_update_event_next(), None);
+
+        assert_eq!(pipe.server.stats().dropped_events, 0);
+    }
+
+    #[test]
+    fn junk_datagrams() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Only padding.
+        let mut junk = [0; 100];
+        assert_eq!(pipe.server_recv(&mut junk), Ok(100));
+
+        // Garbage that looks like a short header packet.
+        let mut junk = [0x42; 100];
+        assert_eq!(pipe.server_recv(&mut junk), Ok(100));
+
+        let stats = pipe.server.stats();
+        assert_eq!(stats.junk_datagrams, 2);
+        assert_eq!(stats.junk_bytes, 200);
+        assert_eq!(stats.throttled_datagrams, 0);
+
+        // Valid datagrams are still processed.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.readable().next(), Some(0));
+        assert_eq!(pipe.server.stats().junk_datagrams, 2);
+    }
+
+    #[test]
+    fn stream_dup_bytes() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_stream_dup_alarm(Some(6));
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 0,
+            data: stream::RangeBuf::from(b"hello", 0, false),
+        }];
+
+        assert!(pipe
+            .send_pkt_to_server(packet::Type::Short, &frames, &mut buf)
+            .is_ok());
+        assert_eq!(pipe.server.stats().stream_dup_bytes, 0);
+
+        // The same data is received again.
+        assert!(pipe
+            .send_pkt_to_server(packet::Type::Short, &frames, &mut buf)
+            .is_ok());
+        assert_eq!(pipe.server.stats().stream_dup_bytes, 5);
+        assert!(!pipe.server.stream_dup_alarm());
+
+        // Only the new data counts.
+        let frames = [frame::Frame::Stream {
+            stream_id: 0,
+            data: stream::RangeBuf::from(b"hello, world", 0, false),
+        }];
+
+        assert!(pipe
+            .send_pkt_to_server(packet::Type::Short, &frames, &mut buf)
+            .is_ok());
+        assert_eq!(pipe.server.stats().stream_dup_bytes, 10);
+        assert!(pipe.server.stream_dup_alarm());
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((12, false)));
+        assert_eq!(&b[..12], b"hello, world");
+    }
+
+    #[test]
+    fn junk_datagrams_throttle() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_junk_datagram_policy(JunkDatagramPolicy::Throttle);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Junk from the validated peer address, which could be spoofed, is
+        // only counted.
+        for _ in 0..JUNK_THROTTLE_THRESHOLD {
+            let mut junk = [0x42; 100];
+            assert_eq!(pipe.server_recv(&mut junk), Ok(100));
+        }
+
+        let stats = pipe.server.stats();
+        assert_eq!(stats.junk_datagrams, JUNK_THROTTLE_THRESHOLD);
+        assert_eq!(stats.throttled_datagrams, 0);
+
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.readable().next(), Some(0));
+
+        // Junk from another address gets it throttled.
+        let info = RecvInfo {
+            from: "127.0.0.1:5678".parse().unwrap(),
+            to: testing::Pipe::server_addr(),
+        };
+
+        for _ in 0..JUNK_THROTTLE_THRESHOLD {
+            let mut junk = [0x42; 100];
+            assert_eq!(pipe.server.recv(&mut junk, info), Ok(100));
+        }
+
+        let stats = pipe.server.stats();
+        assert_eq!(stats.junk_datagrams, 2 * JUNK_THROTTLE_THRESHOLD);
+        assert_eq!(stats.throttled_datagrams, 0);
+
+        let mut junk = [0x42; 100];
+        assert_eq!(pipe.server.recv(&mut junk, info), Ok(100));
+
+        let stats = pipe.server.stats();
+        assert_eq!(stats.junk_datagrams, 2 * JUNK_THROTTLE_THRESHOLD);
+        assert_eq!(stats.throttled_datagrams, 1);
+    }
+
+    #[test]
+    fn handshake_alpn_mismatch() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto3\x06proto4"])
+            .unwrap();
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Err(Error::TlsFail));
+
+        assert_eq!(pipe.client.application_proto(), b"");
+        assert_eq!(pipe.server.application_proto(), b"");
+
+        // The server closes the connection with a TLS alert.
+        let err = pipe.server.local_error().unwrap();
+        assert!(err.is_crypto());
+        assert_eq!(err.error_code, 0x178);
+        assert_eq!(err.tls_alert(), Some(120));
+        assert_eq!(err.tls_alert_description(), Some("no_application_protocol"));
+
+        // Server should only send one packet in response to ALPN mismatch.
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        assert_eq!(len, 1200);
+
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+        assert_eq!(pipe.server.sent_count, 1);
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        let err = pipe.client.peer_error().unwrap();
+        assert_eq!(err.tls_alert(), Some(120));
+        assert_eq!(err.tls_alert_description(), Some("no_application_protocol"));
+    }
+
+    #[test]
+    fn client_store_server_hints() {
+        let mut buf = [0; 65535];
+
+        let store = Arc::new(MemoryClientStore::new());
+
+        // The server refuses the only protocol offered by the client.
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config.set_application_protos(&[b"proto3"]).unwrap();
+        config.set_client_store(Some(store.clone()));
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Err(Error::TlsFail));
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        // The refusal was sent in an Initial packet, which could have been
+        // forged, so it is not remembered.
+        assert_eq!(store.get_hints("quic.tech"), None);
+
+        // The refusal is remembered when received in a Handshake packet.
+        let mut server_config = Config::new(PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config.set_application_protos(&[b"proto3"]).unwrap();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut config,
+            &mut server_config,
+        )
+        .unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        let frames = [frame::Frame::ConnectionClose {
+            error_code: tls::TLS_ALERT_ERROR +
+                u64::from(tls::TLS_ALERT_NO_APPLICATION_PROTOCOL),
+            frame_type: 0,
+            reason: Vec::new(),
+        }];
+
+        let len = testing::encode_pkt(
+            &mut pipe.server,
+            packet::Type::Handshake,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(
+            store.get_hints("quic.tech"),
+            Some(ServerHints {
+                version: None,
+                rejected_protos: vec![b"proto3".to_vec()],
+            })
+        );
+
+        // The refused protocol is not offered anymore.
+        config
+            .set_application_protos(&[b"proto3", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.client.application_protos, vec![b"proto2".to_vec()]);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.client.application_proto(), b"proto2");
+
+        // The server doesn't support the client's initial version.
+        let mut server_config = Config::new(PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config
+            .set_supported_versions(&[PROTOCOL_VERSION_DRAFT29])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut config,
+            &mut server_config,
+        )
+        .unwrap();
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        let len = packet::negotiate_version(
+            &hdr.scid,
+            &hdr.dcid,
+            &[PROTOCOL_VERSION_DRAFT29],
+            &mut buf,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let hints = store.get_hints("quic.tech").unwrap();
+        assert_eq!(hints.version, Some(PROTOCOL_VERSION_DRAFT29));
+        assert_eq!(hints.rejected_protos, vec![b"proto3".to_vec()]);
+
+        // The next connection uses the negotiated version right away.
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_DRAFT29);
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_DRAFT29);
+    }
+
+    #[test]
+    fn connection_error_tls_alert() {
+        let err = ConnectionError {
+            is_app: false,
+            error_code: 0x128,
+            frame_type: 0,
+            reason: vec![],
+        };
+        assert!(err.is_crypto());
+        assert_eq!(err.tls_alert(), Some(40));
+        assert_eq!(err.tls_alert_description(), Some("handshake_failure"));
+
+        let err = ConnectionError {
+            is_app: false,
+            error_code: 0x1ff,
+            frame_type: 0,
+            reason: vec![],
+        };
+        assert_eq!(err.tls_alert(), Some(255));
+        assert_eq!(err.tls_alert_description(), None);
+
+        // Transport errors that are not crypto errors.
+        for error_code in [0x1, 0xa, 0x200] {
+            let err = ConnectionError {
+                is_app: false,
+                error_code,
+                frame_type: 0,
+                reason: vec![],
+            };
+            assert!(!err.is_crypto());
+            assert_eq!(err.tls_alert(), None);
+        }
+
+        // Application errors.
+        let err = ConnectionError {
+            is_app: true,
+            error_code: 0x128,
+            frame_type: 0,
+            reason: vec![],
+        };
+        assert!(!err.is_crypto());
+        assert_eq!(err.tls_alert_description(), None);
+    }
+
+    #[test]
+    fn handshake_0rtt() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        // Client sends 0-RTT packet.
+        let pkt_type = packet::Type::ZeroRTT;
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(1200)
+        );
+
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+
+        // 0-RTT stream data is readable.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
+    }
+
+    #[test]
+    fn handshake_0rtt_reordered() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut initial = buf[..len].to_vec();
+
+        // Client sends 0-RTT packet.
+        let pkt_type = packet::Type::ZeroRTT;
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
+
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        let mut zrtt = buf[..len].to_vec();
+
+        // 0-RTT packet is received before the Initial one.
+        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 1);
+        assert_eq!(pipe.server.undecryptable_pkts[0].0.len(), zrtt.len());
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // Initial packet is also received.
+        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
+
+        // 0-RTT stream data is readable.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
+    }
+
+    #[test]
+    fn handshake_0rtt_truncated() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Client sends initial flight.
+        pipe.client.send(&mut buf).unwrap();
+
+        // Client sends 0-RTT packet.
+        let pkt_type = packet::Type::ZeroRTT;
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
+
+        let len =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+
+        // Simulate a truncated packet by sending one byte less.
+        let mut zrtt = buf[..len - 1].to_vec();
+
+        // 0-RTT packet is received before the Initial one.
+        assert_eq!(pipe.server_recv(&mut zrtt), Err(Error::InvalidPacket));
+
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+
+        assert!(pipe.server.is_closed());
+    }
+
+    #[test]
+    /// Tests that a pre-v1 client can connect to a v1-enabled server, by making
+    /// the server downgrade to the pre-v1 version.
+    fn handshake_downgrade_v1() {
+        let mut config = Config::new(PROTOCOL_VERSION_DRAFT29).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_DRAFT29);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_DRAFT29);
+    }
+
+    #[test]
+    fn handshake_v2() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION_V2).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        // The client's first packet uses the v2 Initial packet type.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        assert_eq!(buf[0] & 0x30, 0x10);
+
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        assert_eq!(hdr.ty, packet::Type::Initial);
+        assert_eq!(hdr.version, PROTOCOL_VERSION_V2);
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn handshake_compatible_version_upgrade() {
+        let mut buf = [0; 65535];
+
+        let mut client_config = Config::new(PROTOCOL_VERSION_V1).unwrap();
+        client_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        client_config.verify_peer(false);
+
+        let mut server_config = Config::new(PROTOCOL_VERSION_V1).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config.set_initial_max_data(30);
+        server_config.set_initial_max_stream_data_bidi_remote(15);
+        server_config.set_initial_max_streams_bidi(3);
+
+        // By default v1 is preferred, so the connection is not upgraded.
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V1);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V1);
+
+        // The server prefers v2, which the client supports as well.
+        server_config
+            .set_supported_versions(&[PROTOCOL_VERSION_V2, PROTOCOL_VERSION_V1])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        assert_eq!(hdr.version, PROTOCOL_VERSION_V1);
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        assert_eq!(hdr.version, PROTOCOL_VERSION_V2);
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V2);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert_eq!(r.next(), None);
+
+        // The client doesn't support v2.
+        client_config
+            .set_supported_versions(&[PROTOCOL_VERSION_V1])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V1);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V1);
+    }
+
+    #[test]
+    fn limit_handshake_data() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert-big.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+
+        assert_eq!(server_sent, client_sent * MAX_AMPLIFICATION_FACTOR);
+    }
+
+    #[test]
+    fn limit_handshake_data_anti_amplification_factor() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert-big.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_anti_amplification_factor(2);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        let allowance = |conn: &Connection| {
+            conn.path_stats()
+                .next()
+                .unwrap()
+                .anti_amplification_allowance
+        };
+
+        assert_eq!(allowance(&pipe.server), Some(client_sent * 2));
+        assert_eq!(allowance(&pipe.client), None);
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
+
+        assert_eq!(server_sent, client_sent * 2);
+        assert_eq!(allowance(&pipe.server), Some(0));
+    }
+
+    #[test]
+    fn handshake_priority() {
+        for priority in [true, false] {
+            let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.verify_peer(false);
+            config.enable_handshake_priority(priority);
+
+            let mut pipe =
+                testing::Pipe::with_client_config(&mut config).unwrap();
+
+            let flight = testing::emit_flight(&mut pipe.client).unwrap();
+            testing::process_flight(&mut pipe.server, flight).unwrap();
+
+            let flight = testing::emit_flight(&mut pipe.server).unwrap();
+            testing::process_flight(&mut pipe.client, flight).unwrap();
+
+            // The congestion window of the client is full, e.g. because of
+            // 0-RTT data.
+            let path = pipe.client.paths.get_active_mut().unwrap();
+            path.recovery.bytes_in_flight = path.recovery.cwnd();
+
+            if let Ok(flight) = testing::emit_flight(&mut pipe.client) {
+                testing::process_flight(&mut pipe.server, flight).unwrap();
+            }
+
+            // The client Finished message is only sent when Handshake packets
+            // ignore the congestion window.
+            assert_eq!(pipe.server.is_established(), priority);
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.server.stream_finished(4));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
+        assert_eq!(&b[..12], b"hello, world");
+
+        assert!(pipe.server.stream_finished(4));
+    }
+
+    #[test]
+    fn zero_rtt() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut initial = buf[..len].to_vec();
+
+        assert_eq!(pipe.client.is_in_early_data(), true);
+
+        // Client sends 0-RTT data.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut zrtt = buf[..len].to_vec();
+
+        // Server receives packets.
+        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
+        assert_eq!(pipe.server.is_in_early_data(), true);
+
+        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+
+        // 0-RTT stream data is readable.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
+        assert_eq!(&b[..12], b"hello, world");
+    }
+
+    #[test]
+    fn zero_rtt_rejected() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // The new server doesn't accept early data.
+        let mut server_config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config.set_initial_max_data(30);
+        server_config.set_initial_max_stream_data_bidi_local(15);
+        server_config.set_initial_max_stream_data_bidi_remote(15);
+        server_config.set_initial_max_streams_bidi(3);
+
+        // Configure session on new connection.
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut initial = buf[..len].to_vec();
+
+        assert_eq!(pipe.client.is_in_early_data(), true);
+
+        // Client sends 0-RTT data.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut zrtt = buf[..len].to_vec();
+
+        // Server can't read the 0-RTT data.
+        assert_eq!(pipe.server_recv(&mut initial), Ok(initial.len()));
+        assert_eq!(pipe.server.is_in_early_data(), false);
+
+        assert_eq!(pipe.server_recv(&mut zrtt), Ok(zrtt.len()));
+        assert_eq!(pipe.server.readable().next(), None);
+
+        // Client sends the data again once the handshake completes, without
+        // waiting for it to be declared lost.
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.is_resumed(), false);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((12, true)));
+        assert_eq!(&b[..12], b"hello, world");
+    }
+
+    #[test]
+    fn stream_send_on_32bit_arch() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(2_u64.pow(32) + 5);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(10);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(0);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // In 32bit arch, send_capacity() should be min(2^32+5, cwnd),
+        // not min(5, cwnd)
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.server.stream_finished(4));
+    }
+
+    #[test]
+    fn empty_stream_frame() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, false),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+
+        let mut readable = pipe.server.readable();
+        assert_eq!(readable.next(), Some(4));
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, false)));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"", 5, true),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
+
+        let mut readable = pipe.server.readable();
+        assert_eq!(readable.next(), Some(4));
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((0, true)));
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"", 15, true),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FinalSize)
+        );
+    }
+
+    #[test]
+    /// Tests that receiving a MAX_STREAM_DATA frame for a receive-only
+    /// unidirectional stream is forbidden.
+    fn max_stream_data_receive_uni() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client opens unidirectional stream.
+        assert_eq!(pipe.client.stream_send(2, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client sends MAX_STREAM_DATA on local unidirectional stream.
+        let frames = [frame::Frame::MaxStreamData {
+            stream_id: 2,
+            max: 1024,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidStreamState(2)),
+        );
+    }
+
+    #[test]
+    fn empty_payload() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Send a packet with no frames.
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &[], &mut buf),
+            Err(Error::InvalidPacket)
+        );
+    }
+
+    #[test]
+    fn min_payload() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Send a non-ack-eliciting packet.
+        let frames = [frame::Frame::Padding { len: 4 }];
+
+        let pkt_type = packet::Type::Initial;
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+
+        let initial_path = pipe
+            .server
+            .paths
+            .get_active()
+            .expect("initial path not found");
+
+        assert_eq!(initial_path.max_send_bytes, 195);
+
+        // Force server to send a single PING frame.
+        pipe.server
+            .paths
+            .get_active_mut()
+            .expect("no active path")
+            .recovery
+            .loss_probes[packet::Epoch::Initial] = 1;
+
+        let initial_path = pipe
+            .server
+            .paths
+            .get_active_mut()
+            .expect("initial path not found");
+
+        // Artificially limit the amount of bytes the server can send.
+        initial_path.max_send_bytes = 60;
+
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    fn flow_control_limit() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 0,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"aaaaaaaaaaaaaaa", 0, false),
+            },
             frame::Frame::Stream {
                 stream_id: 8,
                 data: stream::RangeBuf::from(b"a", 0, false),
@@ -10130,6 +19803,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn path_challenge_rate_limited() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_rate_limit(RateLimited::PathResponse, 0, 1);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let pkt_type = packet::Type::Short;
+
+        // The first challenge is answered.
+        let frames = [frame::Frame::PathChallenge { data: [0xba; 8] }];
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+        assert!(frames
+            .iter()
+            .any(|f| *f == frame::Frame::PathResponse { data: [0xba; 8] }));
+
+        // The second one exceeds the limit.
+        let frames = [frame::Frame::PathChallenge { data: [0xbb; 8] }];
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+        assert!(!frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::PathResponse { .. })));
+
+        assert_eq!(pipe.server.stats().rate_limited, 1);
+    }
+
     #[test]
     /// Simulates reception of an early 1-RTT packet on the server, by
     /// delaying the client's Handshake packet that completes the handshake.
@@ -10576,75 +20301,376 @@ mod tests {
 
         assert_eq!(pipe.advance(), Ok(()));
 
-        // Server sends some data, without reading the incoming data, and closes
-        // the stream.
-        assert_eq!(pipe.server.stream_send(4, b"hello, world", true), Ok(12));
-        assert_eq!(pipe.advance(), Ok(()));
+        // Server sends some data, without reading the incoming data, and closes
+        // the stream.
+        assert_eq!(pipe.server.stream_send(4, b"hello, world", true), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Client reads the data.
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((12, true)));
+
+        // Stream is collected on both sides.
+        assert_eq!(pipe.client.streams.len(), 0);
+        assert_eq!(pipe.server.streams.len(), 0);
+
+        assert_eq!(
+            pipe.server.stream_shutdown(4, Shutdown::Read, 0),
+            Err(Error::Done)
+        );
+    }
+
+    #[test]
+    fn stream_shutdown_read_update_max_data() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((1, false)));
+        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Read, 123), Ok(()));
+
+        assert_eq!(pipe.server.rx_data, 1);
+        assert_eq!(pipe.client.tx_data, 1);
+        assert_eq!(pipe.client.max_tx_data, 30);
+
+        assert_eq!(
+            pipe.client
+                .stream_send(0, &buf[..pipe.client.tx_cap], false),
+            Ok(29)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_readable(0), false); // nothing can be consumed
+
+        // The client has increased its tx_data, and server has received it, so
+        // it increases flow control accordingly.
+        assert_eq!(pipe.client.tx_data, 30);
+        assert_eq!(pipe.server.rx_data, 30);
+        assert_eq!(pipe.client.tx_cap, 45);
+    }
+
+    #[test]
+    fn stream_send_reader() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(1_000_000);
+        config.set_initial_max_stream_data_bidi_local(1_000_000);
+        config.set_initial_max_stream_data_bidi_remote(1_000_000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_stream_read_ahead(1000);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+
+        assert_eq!(
+            pipe.client
+                .stream_send_reader(0, std::io::Cursor::new(data.clone())),
+            Ok(())
+        );
+
+        // Only the read ahead is buffered.
+        let stream = pipe.client.streams.get(0).unwrap();
+        assert_eq!(stream.send.off_back(), 1000);
+
+        assert!(pipe.client.stream_reader_pending(0));
+        assert_eq!(
+            pipe.client.stream_send(0, b"hello", false),
+            Err(Error::InvalidStreamState(0))
+        );
+        assert_eq!(
+            pipe.client.stream_send_reader(0, std::io::empty()),
+            Err(Error::InvalidStreamState(0))
+        );
+
+        let mut received = Vec::new();
+        let mut buf = [0; 65535];
+        let mut fin = false;
+
+        while !fin {
+            assert_eq!(pipe.advance(), Ok(()));
+
+            while let Ok((len, f)) = pipe.server.stream_recv(0, &mut buf) {
+                received.extend_from_slice(&buf[..len]);
+                fin = f;
+            }
+
+            let stream = pipe.client.streams.get(0).unwrap();
+            assert!(stream.send.off_back() - stream.send.off_front() <= 1000);
+        }
+
+        assert_eq!(received, data);
+        assert!(!pipe.client.stream_reader_pending(0));
+    }
+
+    #[test]
+    fn stream_send_buffer_limit() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(1_000_000);
+        config.set_initial_max_stream_data_bidi_local(1_000_000);
+        config.set_initial_max_stream_data_bidi_remote(1_000_000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_stream_send_buffer(1000);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let data = [42; 5000];
+
+        // Only the data that fits in the send buffer is written.
+        assert_eq!(pipe.client.stream_send(0, &data, false), Ok(1000));
+        assert_eq!(pipe.client.stream_send(0, &data, false), Err(Error::Done));
+        assert_eq!(pipe.client.stream_capacity(0), Ok(0));
+        assert_eq!(pipe.client.writable().next(), None);
+
+        // A full send buffer doesn't block the stream on flow control.
+        assert_eq!(pipe.client.streams.blocked().len(), 0);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 5000];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((1000, false)));
+
+        // The data was acked, so the stream is writable again.
+        assert_eq!(pipe.client.writable().next(), Some(0));
+        assert_eq!(pipe.client.stream_send(0, &data, true), Ok(1000));
+    }
+
+    #[test]
+    fn stream_recv_buf() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        struct PoolFactory {
+            allocated: AtomicUsize,
+        }
+
+        impl BufFactory for PoolFactory {
+            fn buf_from_slice(&self, data: &[u8]) -> Arc<[u8]> {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+
+                // Fixed-size buffers, larger than the data.
+                let mut buf = vec![0; 1500];
+                buf[..data.len()].copy_from_slice(data);
+                Arc::from(buf)
+            }
+        }
+
+        let factory = Arc::new(PoolFactory {
+            allocated: AtomicUsize::new(0),
+        });
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_buf_factory(factory.clone());
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello, ", false), Ok(7));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(factory.allocated.load(Ordering::Relaxed), 2);
+
+        // Each chunk references the buffer it was received in.
+        let (buf, fin) = pipe.server.stream_recv_buf(0).unwrap();
+        assert_eq!(&buf[..], b"hello, ");
+        assert_eq!(buf.buf().len(), 1500);
+        assert!(!fin);
+
+        let (buf, fin) = pipe.server.stream_recv_buf(0).unwrap();
+        assert_eq!(&buf[..], b"world");
+        assert!(fin);
+
+        assert_eq!(
+            pipe.server.stream_recv_buf(0).map(|(b, f)| (b.to_vec(), f)),
+            Err(Error::Done)
+        );
+    }
+
+    #[test]
+    fn stream_send_zc() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::Ordering;
+
+        struct Body {
+            data: Vec<u8>,
+            dropped: Arc<AtomicBool>,
+        }
+
+        impl AsRef<[u8]> for Body {
+            fn as_ref(&self) -> &[u8] {
+                &self.data
+            }
+        }
+
+        impl Drop for Body {
+            fn drop(&mut self) {
+                self.dropped.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        let body = Body {
+            data: b"hello, world".to_vec(),
+            dropped: dropped.clone(),
+        };
+
+        assert_eq!(pipe.client.stream_send_zc(0, body, true), Ok(12));
+
+        // Only the stream's flow control capacity is written, without fin.
+        assert_eq!(pipe.client.stream_send_zc(4, vec![1; 20], true), Ok(15));
+
+        // The buffer is kept until its data is acknowledged.
+        assert!(!dropped.load(Ordering::SeqCst));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert!(dropped.load(Ordering::SeqCst));
+
+        let mut b = [0; 20];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((12, true)));
+        assert_eq!(&b[..12], b"hello, world");
+
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((15, false)));
+        assert_eq!(&b[..15], &[1; 15]);
+    }
+
+    #[test]
+    fn stream_send_recv_vectored() {
+        use std::io::IoSlice;
+        use std::io::IoSliceMut;
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let bufs = [
+            IoSlice::new(b"hello"),
+            IoSlice::new(b""),
+            IoSlice::new(b", world"),
+        ];
+        assert_eq!(pipe.client.stream_send_vectored(0, &bufs, true), Ok(12));
 
-        // Client reads the data.
-        let mut r = pipe.client.readable();
-        assert_eq!(r.next(), Some(4));
-        assert_eq!(r.next(), None);
+        // Only the stream's flow control capacity is written, without fin.
+        let bufs = [IoSlice::new(&[1; 10]), IoSlice::new(&[2; 10])];
+        assert_eq!(pipe.client.stream_send_vectored(4, &bufs, true), Ok(15));
+        assert_eq!(
+            pipe.client.stream_send_vectored(4, &bufs, true),
+            Err(Error::Done)
+        );
 
-        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((12, true)));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        // Stream is collected on both sides.
-        assert_eq!(pipe.client.streams.len(), 0);
-        assert_eq!(pipe.server.streams.len(), 0);
+        let mut a = [0; 5];
+        let mut b = [0; 20];
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        assert_eq!(
+            pipe.server.stream_recv_vectored(0, &mut bufs),
+            Ok((12, true))
+        );
+        assert_eq!(&a, b"hello");
+        assert_eq!(&b[..7], b", world");
 
+        let mut a = [0; 8];
+        let mut b = [0; 4];
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
         assert_eq!(
-            pipe.server.stream_shutdown(4, Shutdown::Read, 0),
+            pipe.server.stream_recv_vectored(4, &mut bufs),
+            Ok((12, false))
+        );
+        assert_eq!(
+            pipe.server.stream_recv_vectored(4, &mut bufs),
+            Ok((3, false))
+        );
+        assert_eq!(
+            pipe.server.stream_recv_vectored(4, &mut bufs),
             Err(Error::Done)
         );
+        assert_eq!(&a[..3], &[2; 3]);
     }
 
     #[test]
-    fn stream_shutdown_read_update_max_data() {
-        let mut buf = [0; 65535];
+    fn stream_send_reader_error() {
+        struct FailingReader;
 
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(10000);
-        config.set_initial_max_stream_data_bidi_remote(10000);
-        config.set_initial_max_streams_bidi(10);
-        config.verify_peer(false);
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "failed"))
+            }
+        }
 
-        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+        assert_eq!(pipe.client.stream_send_reader(0, FailingReader), Ok(()));
+        assert!(!pipe.client.stream_reader_pending(0));
         assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((1, false)));
-        assert_eq!(pipe.server.stream_shutdown(0, Shutdown::Read, 123), Ok(()));
-
-        assert_eq!(pipe.server.rx_data, 1);
-        assert_eq!(pipe.client.tx_data, 1);
-        assert_eq!(pipe.client.max_tx_data, 30);
-
+        let mut b = [0; 15];
         assert_eq!(
-            pipe.client
-                .stream_send(0, &buf[..pipe.client.tx_cap], false),
-            Ok(29)
+            pipe.server.stream_recv(0, &mut b),
+            Err(Error::StreamReset(0))
         );
-        assert_eq!(pipe.advance(), Ok(()));
-
-        assert_eq!(pipe.server.stream_readable(0), false); // nothing can be consumed
-
-        // The client has increased its tx_data, and server has received it, so
-        // it increases flow control accordingly.
-        assert_eq!(pipe.client.tx_data, 30);
-        assert_eq!(pipe.server.rx_data, 30);
-        assert_eq!(pipe.client.tx_cap, 45);
     }
 
     #[test]
@@ -11035,6 +21061,115 @@ mod tests {
         assert_eq!(w.next(), None);
     }
 
+    #[test]
+    fn stream_writable_lowat() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaaaaaa", false), Ok(10));
+
+        // The stream is only writable once 10 bytes can be written.
+        assert_eq!(pipe.client.stream_writable(0, 10), Ok(false));
+        assert_eq!(pipe.client.writable().next(), None);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((10, false)));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.writable().next(), Some(0));
+        assert_eq!(pipe.client.stream_writable(0, 10), Ok(true));
+    }
+
+    #[test]
+    fn stream_readable_threshold() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The stream is only readable once 10 bytes can be read.
+        assert_eq!(pipe.server.stream_readable_threshold(0, 10), Ok(false));
+        assert_eq!(pipe.server.readable().next(), None);
+        assert_eq!(pipe.server.stream_readable_next(), None);
+
+        assert_eq!(pipe.client.stream_send(0, b"bbbb", false), Ok(4));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.readable().next(), None);
+
+        assert_eq!(pipe.client.stream_send(0, b"c", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.readable().next(), Some(0));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b[..4]), Ok((4, false)));
+        assert_eq!(pipe.server.readable().next(), None);
+
+        // Once the stream is finished, the rest of the data is readable.
+        assert_eq!(pipe.client.stream_send(0, b"d", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.readable().next(), Some(0));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((7, true)));
+        assert_eq!(&b[..7], b"abbbbcd");
+    }
+
+    #[test]
+    fn stream_events() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The server received the whole stream, and the client got the ACK.
+        assert_eq!(
+            pipe.server.stream_event_next(),
+            Some(StreamEvent::Finished { stream_id: 0 })
+        );
+        assert_eq!(pipe.server.stream_event_next(), None);
+
+        assert_eq!(
+            pipe.client.stream_event_next(),
+            Some(StreamEvent::Acked { stream_id: 0 })
+        );
+        assert_eq!(pipe.client.stream_event_next(), None);
+
+        // Reading the data doesn't generate any more events.
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert_eq!(pipe.server.stream_event_next(), None);
+
+        // The server asks the client to stop sending, and the client resets
+        // the stream in response.
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_event_next(),
+            Some(StreamEvent::StopSending {
+                stream_id: 4,
+                error_code: 42,
+            })
+        );
+        assert_eq!(pipe.client.stream_event_next(), None);
+
+        assert_eq!(
+            pipe.server.stream_event_next(),
+            Some(StreamEvent::ResetReceived {
+                stream_id: 4,
+                error_code: 42,
+                final_size: 5,
+            })
+        );
+        assert_eq!(pipe.server.stream_event_next(), None);
+    }
+
     #[test]
     /// Tests that we don't exceed the per-connection flow control limit set by
     /// the peer.
@@ -11491,6 +21626,41 @@ mod tests {
         assert_eq!(r.next(), None);
     }
 
+    #[test]
+    fn stream_finish() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Finish a stream that was never written to.
+        assert_eq!(pipe.client.stream_finish(0), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(0));
+        assert!(r.next().is_none());
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((0, true)));
+
+        // Finishing again doesn't send anything.
+        assert_eq!(pipe.client.stream_finish(0), Ok(()));
+        assert_eq!(testing::emit_flight(&mut pipe.client), Err(Error::Done));
+
+        // Data that is still buffered carries the fin.
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.client.stream_finish(4), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"hello");
+
+        // Can't write after the stream was finished.
+        assert_eq!(
+            pipe.client.stream_send(4, b"world", false),
+            Err(Error::FinalSize)
+        );
+    }
+
     #[test]
     /// Tests that the stream gets created with stream_send() even if there's
     /// no data in the buffer and the fin flag is not set.
@@ -11564,29 +21734,203 @@ mod tests {
         assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(39));
     }
 
-    #[test]
-    fn config_set_cc_algorithm_name() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+    #[test]
+    fn config_set_cc_algorithm_name() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(config.set_cc_algorithm_name("reno"), Ok(()));
+
+        // Unknown name.
+        assert_eq!(
+            config.set_cc_algorithm_name("???"),
+            Err(Error::CongestionControl)
+        );
+    }
+
+    /// A congestion controller with a fixed congestion window.
+    #[derive(Clone)]
+    struct FixedWindow {
+        cwnd: usize,
+    }
+
+    impl CongestionControl for FixedWindow {
+        fn on_init(&mut self, r: &mut Recovery) {
+            r.congestion_window = self.cwnd;
+        }
+
+        fn on_packet_sent(
+            &mut self, r: &mut Recovery, sent_bytes: usize, _now: time::Instant,
+        ) {
+            r.bytes_in_flight += sent_bytes;
+        }
+
+        fn on_packets_acked(
+            &mut self, r: &mut Recovery, packets: &[Acked], _epoch: Epoch,
+            _now: time::Instant,
+        ) {
+            for p in packets {
+                r.bytes_in_flight = r.bytes_in_flight.saturating_sub(p.size);
+            }
+        }
+
+        fn congestion_event(
+            &mut self, _r: &mut Recovery, _lost_bytes: usize,
+            _time_sent: time::Instant, _epoch: Epoch, _now: time::Instant,
+        ) {
+        }
+
+        fn collapse_cwnd(&mut self, _r: &mut Recovery) {}
+
+        fn clone_box(&self) -> Box<dyn CongestionControl> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn custom_congestion_control() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.set_custom_congestion_control(Box::new(FixedWindow {
+            cwnd: 20_000,
+        }));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        for conn in [&pipe.client, &pipe.server] {
+            let recovery = &conn.paths.get_active().unwrap().recovery;
+            assert_eq!(recovery.cwnd(), 20_000);
+        }
+
+        // Selecting a built-in algorithm replaces the custom one.
+        config.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        assert!(config.custom_cc.is_none());
+    }
+
+    #[test]
+    fn peer_cert() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        match pipe.client.peer_cert() {
+            Some(c) => assert_eq!(c.len(), 753),
+
+            None => panic!("missing server certificate"),
+        }
+    }
+
+    #[test]
+    fn export_keying_material() {
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        let mut client_out = [0; 32];
+        assert_eq!(
+            pipe.client
+                .export_keying_material(b"label", None, &mut client_out),
+            Err(Error::InvalidState)
+        );
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut server_out = [0; 32];
+
+        assert_eq!(
+            pipe.client
+                .export_keying_material(b"label", None, &mut client_out),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.server
+                .export_keying_material(b"label", None, &mut server_out),
+            Ok(())
+        );
+        assert_eq!(client_out, server_out);
+        assert_ne!(client_out, [0; 32]);
+
+        // The output depends on the label and the context.
+        let mut out = [0; 32];
+
+        assert_eq!(
+            pipe.client.export_keying_material(b"other", None, &mut out),
+            Ok(())
+        );
+        assert_ne!(out, client_out);
+
+        assert_eq!(
+            pipe.client
+                .export_keying_material(b"label", Some(b"ctx"), &mut out),
+            Ok(())
+        );
+        assert_ne!(out, client_out);
+
+        let mut ctx_out = [0; 32];
 
-        assert_eq!(config.set_cc_algorithm_name("reno"), Ok(()));
+        assert_eq!(
+            pipe.server.export_keying_material(
+                b"label",
+                Some(b"ctx"),
+                &mut ctx_out
+            ),
+            Ok(())
+        );
+        assert_eq!(out, ctx_out);
 
-        // Unknown name.
+        // An empty context is the same as no context in TLS 1.3.
         assert_eq!(
-            config.set_cc_algorithm_name("???"),
-            Err(Error::CongestionControl)
+            pipe.client
+                .export_keying_material(b"label", Some(&[]), &mut out),
+            Ok(())
         );
+        assert_eq!(out, client_out);
     }
 
     #[test]
-    fn peer_cert() {
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    fn keylog_callback() {
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cb_lines = lines.clone();
 
-        match pipe.client.peer_cert() {
-            Some(c) => assert_eq!(c.len(), 753),
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_keylog_callback(Box::new(move |line: &str| {
+            cb_lines.lock().unwrap().push(line.to_string());
+        }));
 
-            None => panic!("missing server certificate"),
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The handshake and application secrets of both directions are
+        // logged by the client.
+        let lines = lines.lock().unwrap();
+
+        for label in [
+            "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+            "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+            "CLIENT_TRAFFIC_SECRET_0",
+            "SERVER_TRAFFIC_SECRET_0",
+        ] {
+            assert!(lines.iter().any(|l| l.starts_with(label)));
         }
+
+        assert!(lines.iter().all(|l| !l.ends_with('\n')));
     }
 
     #[test]
@@ -11679,6 +22023,213 @@ mod tests {
         assert!(pipe.server.is_established());
     }
 
+    #[test]
+    fn retry_minted() {
+        let mut buf = [0; 65535];
+
+        let ring = Arc::new(KeyRing::new(
+            time::Duration::from_secs(60),
+            time::Duration::from_secs(10),
+        ));
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_token_minter(Some(Arc::new(KeyRingTokenMinter::new(
+            ring,
+            time::Duration::from_secs(10),
+            time::Duration::from_secs(3600),
+        ))));
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        // Client sends initial flight.
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server sends Retry packet.
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+
+        let mut scid = [0; MAX_CONN_ID_LEN];
+        rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let from = testing::Pipe::client_addr();
+
+        len = crate::retry_minted(
+            &hdr.scid,
+            &hdr.dcid,
+            &scid,
+            &from,
+            hdr.version,
+            &mut buf,
+            &config,
+        )
+        .unwrap();
+
+        // Client receives Retry and sends new Initial.
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        let token = hdr.token.unwrap();
+
+        // The token is only valid for the client's address.
+        let other = "127.0.0.2:1234".parse().unwrap();
+        assert!(accept_with_token(
+            &scid,
+            &token,
+            testing::Pipe::server_addr(),
+            other,
+            &mut config,
+        )
+        .is_err());
+
+        // Server accepts connection.
+        pipe.server = accept_with_token(
+            &scid,
+            &token,
+            testing::Pipe::server_addr(),
+            from,
+            &mut config,
+        )
+        .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+    }
+
+    #[test]
+    fn generated_source_cids() {
+        let generator = Arc::new(
+            QuicLbGenerator::new(1, &[0x0a, 0x0b], 8, Some(&[0x42; 16])).unwrap(),
+        );
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_connection_id_generator(Some(generator.clone()));
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        pipe.server = accept_generated(
+            None,
+            testing::Pipe::server_addr(),
+            testing::Pipe::client_addr(),
+            &mut config,
+        )
+        .unwrap();
+        assert_eq!(
+            generator.server_id(&pipe.server.source_id()),
+            Some(vec![0x0a, 0x0b])
+        );
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let (scid, seq) = pipe.server.new_generated_source_cid(false).unwrap();
+        assert_eq!(seq, 1);
+        assert_eq!(generator.server_id(&scid), Some(vec![0x0a, 0x0b]));
+
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.available_dcids(), 1);
+
+        // Clients don't have a generator.
+        assert_eq!(
+            pipe.client.new_generated_source_cid(false),
+            Err(Error::InvalidState)
+        );
+    }
+
+    #[test]
+    fn new_token() {
+        let store = Arc::new(MemoryClientStore::new());
+
+        let ring = Arc::new(KeyRing::new(
+            time::Duration::from_secs(60),
+            time::Duration::from_secs(10),
+        ));
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_client_store(Some(store.clone()));
+        config.set_token_minter(Some(Arc::new(KeyRingTokenMinter::new(
+            ring,
+            time::Duration::from_secs(10),
+            time::Duration::from_secs(3600),
+        ))));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The server sent a NEW_TOKEN frame, recorded by the client.
+        assert_eq!(pipe.server.new_token, None);
+
+        let token = store.take_token("quic.tech").unwrap();
+        store.put_token("quic.tech", &token);
+
+        // A new connection validates the client's address with the token.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.token, Some(token.clone()));
+
+        pipe.server = accept_with_token(
+            &ConnectionId::from_ref(&[0xba; 16]),
+            &token,
+            testing::Pipe::server_addr(),
+            testing::Pipe::client_addr(),
+            &mut config,
+        )
+        .unwrap();
+        let path = pipe.server.paths.get_active().unwrap();
+        assert!(path.verified_peer_address);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // A NEW_TOKEN token from another address is ignored, rather than
+        // failing the connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        pipe.server = accept_with_token(
+            &ConnectionId::from_ref(&[0xba; 16]),
+            &token,
+            testing::Pipe::server_addr(),
+            "127.0.0.2:1234".parse().unwrap(),
+            &mut config,
+        )
+        .unwrap();
+        let path = pipe.server.paths.get_active().unwrap();
+        assert!(!path.verified_peer_address);
+    }
+
     #[test]
     fn missing_retry_source_connection_id() {
         let mut buf = [0; 65535];
@@ -12201,7 +22752,7 @@ mod tests {
             let written = testing::encode_pkt(
                 &mut pipe.server,
                 packet::Type::Short,
-                &[frame::Frame::Ping],
+                &[frame::Frame::Ping { mtu_probe: None }],
                 &mut buf,
             )
             .unwrap();
@@ -12389,7 +22940,10 @@ mod tests {
 
         assert_eq!(pipe.server.pkt_num_spaces[epoch].recv_pkt_need_ack.len(), 0);
 
-        let frames = [frame::Frame::Ping, frame::Frame::Padding { len: 3 }];
+        let frames = [
+            frame::Frame::Ping { mtu_probe: None },
+            frame::Frame::Padding { len: 3 },
+        ];
 
         let pkt_type = packet::Type::Short;
 
@@ -12653,9 +23207,6 @@ mod tests {
 
     #[test]
     /// Tests that changing a stream's priority is correctly propagated.
-    ///
-    /// Re-prioritization is not supported, so this should fail.
-    #[should_panic]
     fn stream_reprioritize() {
         let mut buf = [0; 65535];
 
@@ -12772,6 +23323,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
     /// Tests that streams and datagrams are correctly scheduled.
     fn stream_datagram_priority() {
         // Limit 1-RTT packet size to avoid congestion control interference.
@@ -13053,6 +23605,122 @@ mod tests {
         );
     }
 
+    /// Writes a long header packet with the given version and destination
+    /// connection ID, and a garbage payload.
+    fn encode_junk_long_pkt(version: u32, dcid: &[u8], buf: &mut [u8]) -> usize {
+        let mut b = octets::OctetsMut::with_slice(buf);
+
+        let hdr = Header {
+            ty: packet::Type::Handshake,
+            version,
+            dcid: ConnectionId::from_ref(dcid),
+            scid: ConnectionId::from_ref(&[0xba; 8]),
+            pkt_num: 0,
+            pkt_num_len: 1,
+            token: None,
+            versions: None,
+            key_phase: false,
+        };
+
+        hdr.to_bytes(&mut b).unwrap();
+
+        b.put_varint(32).unwrap();
+        b.put_bytes(&[0xab; 32]).unwrap();
+
+        b.off()
+    }
+
+    #[test]
+    fn coalesced_packet_other_version() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let dcid = pipe.server.source_id().into_owned();
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
+
+        // A packet of another supported version is skipped, and the packets
+        // coalesced after it are processed.
+        let mut len =
+            encode_junk_long_pkt(PROTOCOL_VERSION_DRAFT29, &dcid, &mut buf);
+        len += testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf[len..],
+        )
+        .unwrap();
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        // The length of packets of unknown versions can't be parsed, so the
+        // rest of the datagram is dropped.
+        let frames = [frame::Frame::Stream {
+            stream_id: 8,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
+
+        let mut len = encode_junk_long_pkt(0xbabababa, &dcid, &mut buf);
+        len += testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf[len..],
+        )
+        .unwrap();
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert!(!pipe.server.is_closed());
+    }
+
+    #[test]
+    fn coalesced_packet_different_dcid() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let recv_count = pipe.server.stats().recv;
+
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"aaaaa", 0, true),
+        }];
+
+        // The first packet sets the destination connection ID of the datagram,
+        // so the valid packet coalesced after it is ignored.
+        let mut len =
+            encode_junk_long_pkt(PROTOCOL_VERSION, &[0xde; 16], &mut buf);
+        len += testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf[len..],
+        )
+        .unwrap();
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(pipe.server.readable().next(), None);
+        assert_eq!(pipe.server.stats().recv, recv_count);
+
+        assert!(!pipe.server.is_closed());
+    }
+
     #[test]
     fn coalesce_padding_short() {
         let mut buf = [0; 65535];
@@ -13160,7 +23828,7 @@ mod tests {
             pipe.client.paths.get_active_path_id().expect("no active");
         let (ty, len) = pipe
             .client
-            .send_single(&mut buf, active_pid, false)
+            .send_single(&mut buf, active_pid, false, None)
             .unwrap();
         assert_eq!(ty, Type::Initial);
 
@@ -13169,7 +23837,7 @@ mod tests {
         // Client sends Handshake packet.
         let (ty, len) = pipe
             .client
-            .send_single(&mut buf, active_pid, false)
+            .send_single(&mut buf, active_pid, false, None)
             .unwrap();
         assert_eq!(ty, Type::Handshake);
 
@@ -13184,6 +23852,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
     fn dgram_send_fails_invalidstate() {
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
@@ -13195,6 +23864,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
     fn dgram_send_app_limited() {
         let mut buf = [0; 65535];
         let send_buf = [0xcf; 1000];
@@ -13268,6 +23938,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
     fn dgram_single_datagram() {
         let mut buf = [0; 65535];
 
@@ -13305,6 +23976,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
     fn dgram_multiple_datagrams() {
         let mut buf = [0; 65535];
 
@@ -13379,6 +24051,99 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
+    fn dgram_send_with_urgency_and_ttl() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_dgram(true, 10, 10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let ttl = time::Duration::from_millis(1);
+
+        assert_eq!(pipe.client.dgram_send(b"hello, world"), Ok(()));
+        assert_eq!(pipe.client.dgram_send_with(b"stale", 0, Some(ttl)), Ok(()));
+        assert_eq!(pipe.client.dgram_send_with(b"urgent", 0, None), Ok(()));
+        assert_eq!(pipe.client.dgram_send_queue_len(), 3);
+
+        std::thread::sleep(ttl * 2);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.dgram_send_queue_len(), 0);
+        assert_eq!(pipe.client.stats().dgram_expired, 1);
+
+        // The urgent DATAGRAM is sent first, and the expired one is dropped.
+        assert_eq!(pipe.server.dgram_recv(&mut buf), Ok(6));
+        assert_eq!(&buf[..6], b"urgent");
+
+        assert_eq!(pipe.server.dgram_recv(&mut buf), Ok(12));
+        assert_eq!(&buf[..12], b"hello, world");
+
+        assert_eq!(pipe.server.dgram_recv(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    #[cfg(feature = "datagram")]
+    fn dgram_send_queue_writable() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_dgram(true, 10, 3);
+        config.set_dgram_send_queue_low_watermark(1);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The queue is not reported as writable until it was full.
+        assert!(!pipe.client.dgram_send_queue_writable());
+
+        assert_eq!(pipe.client.dgram_send(b"a"), Ok(()));
+        assert_eq!(pipe.client.dgram_send(b"b"), Ok(()));
+        assert_eq!(pipe.client.dgram_send(b"c"), Ok(()));
+        assert_eq!(pipe.client.dgram_send(b"d"), Err(Error::Done));
+        assert!(!pipe.client.dgram_send_queue_writable());
+
+        // Still above the low watermark.
+        pipe.client.dgram_purge_outgoing(|d: &[u8]| d == b"a");
+        assert!(!pipe.client.dgram_send_queue_writable());
+
+        pipe.client.dgram_purge_outgoing(|d: &[u8]| d == b"b");
+        assert!(pipe.client.dgram_send_queue_writable());
+
+        // The notification is only reported once.
+        assert!(!pipe.client.dgram_send_queue_writable());
+
+        assert_eq!(pipe.advance(), Ok(()));
+        assert!(!pipe.client.dgram_send_queue_writable());
+    }
+
+    #[test]
+    #[cfg(feature = "datagram")]
     fn dgram_send_queue_overflow() {
         let mut buf = [0; 65535];
 
@@ -13425,6 +24190,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
     fn dgram_recv_queue_overflow() {
         let mut buf = [0; 65535];
 
@@ -13472,6 +24238,290 @@ mod tests {
     }
 
     #[test]
+    fn ack_frequency() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_ack_frequency(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.client.ack_frequency);
+        assert!(pipe.server.ack_frequency);
+
+        // Both endpoints request an ACK rate.
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(pipe.client.ack_freq_recv.is_active());
+        assert!(pipe.server.ack_freq_recv.is_active());
+
+        let requested = pipe.client.ack_freq_send.requested().unwrap();
+        assert!(requested.ack_eliciting_threshold >= 2);
+
+        // The ACK of a single ack-eliciting packet is delayed.
+        let frames = [frame::Frame::Ping { mtu_probe: None }];
+        let pkt_type = packet::Type::Short;
+        assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
+        assert!(pipe.server.ack_freq_recv.ack_timer().is_some());
+
+        // Unless an IMMEDIATE_ACK frame is received.
+        let frames = [frame::Frame::ImmediateAck];
+        let written = pipe.send_pkt_to_server(pkt_type, &frames, &mut buf);
+        assert!(written.unwrap() > 0);
+        assert_eq!(pipe.server.ack_freq_recv.ack_timer(), None);
+    }
+
+    #[test]
+    fn experiments() {
+        let mut experiments = Experiments::default();
+        experiments.set(0, true);
+        experiments.set(63, true);
+        experiments.set(64, true);
+        assert_eq!(experiments.bits(), 1 | 1 << 63);
+
+        experiments.set(63, false);
+        assert!(experiments.is_set(0));
+        assert!(!experiments.is_set(63));
+        assert!(!experiments.is_set(64));
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_experiments(Experiments::from_bits(0b101));
+
+        let pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert!(pipe.client.experiments().is_set(2));
+        assert!(!pipe.client.experiments().is_set(1));
+        assert_eq!(pipe.server.experiments().bits(), 0b101);
+    }
+
+    #[test]
+    #[cfg(feature = "datagram")]
+    fn negotiated_extensions() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.enable_dgram(true, 10, 10);
+        config.enable_ack_frequency(true);
+        config.set_plugin_support(&[b"plugin1"]).unwrap();
+        config.grease_quic_bit(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(
+            pipe.client.negotiated_extensions(),
+            NegotiatedExtensions::default()
+        );
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let negotiated = NegotiatedExtensions {
+            datagram: true,
+            multipath: false,
+            ack_frequency: true,
+            plugins: true,
+            grease_quic_bit: true,
+        };
+
+        assert_eq!(pipe.client.negotiated_extensions(), negotiated);
+        assert_eq!(pipe.server.negotiated_extensions(), negotiated);
+
+        // Extensions enabled by a single endpoint are not negotiated.
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.negotiated_extensions(),
+            NegotiatedExtensions::default()
+        );
+        assert_eq!(
+            pipe.server.negotiated_extensions(),
+            NegotiatedExtensions::default()
+        );
+    }
+
+    #[test]
+    fn grease_quic_bit() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.grease_quic_bit(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.server.peer_transport_params().unwrap().grease_quic_bit);
+        assert!(pipe.client.grease_quic_bit);
+
+        let mut cleared = 0;
+
+        for _ in 0..64 {
+            assert_eq!(pipe.client.send_ack_eliciting(), Ok(()));
+
+            let (len, info) = pipe.client.send(&mut buf).unwrap();
+
+            if buf[0] & packet::FIXED_BIT == 0 {
+                cleared += 1;
+            }
+
+            // Packets with the QUIC bit cleared are still processed.
+            let info = RecvInfo {
+                from: info.from,
+                to: info.to,
+            };
+            assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+        }
+
+        assert!(cleared > 0 && cleared < 64);
+
+        // The QUIC bit is not greased unless both endpoints enable it.
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.client.peer_transport_params().unwrap().grease_quic_bit);
+        assert!(!pipe.server.grease_quic_bit);
+        assert!(!pipe.client.grease_quic_bit);
+    }
+
+    #[test]
+    fn ack_frequency_not_negotiated() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(!pipe.client.ack_frequency);
+        assert!(!pipe.server.ack_frequency);
+
+        let frames = [frame::Frame::AckFrequency {
+            seq_num: 0,
+            ack_eliciting_threshold: 10,
+            request_max_ack_delay: 25_000,
+            reordering_threshold: 1,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidPacket)
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "plugins", feature = "datagram"))]
+    fn dgram_queue_fields() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_dgram(true, 10, 10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let now = time::Instant::now();
+
+        assert_eq!(pipe.client.dgram_send(b"hello, world"), Ok(()));
+        assert_eq!(pipe.client.dgram_send(b"ciao, mondo"), Ok(()));
+        assert_eq!(pipe.client.dgram_send(b"hola"), Ok(()));
+
+        let field = dgram::DatagramField::SendQueueLen;
+        assert_eq!(pipe.client.dgram_get_field(field, now), 3);
+
+        let field = dgram::DatagramField::SendQueueByteSize;
+        assert_eq!(pipe.client.dgram_get_field(field, now), 27);
+
+        let field = dgram::DatagramField::SendQueueFrontLen;
+        assert_eq!(pipe.client.dgram_get_field(field, now), 12);
+
+        let field = dgram::DatagramField::RecvQueueFrontAge;
+        assert_eq!(pipe.client.dgram_get_field(field, now), 0);
+
+        // Move the oldest DATAGRAM to the back of the queue.
+        let field = dgram::DatagramField::SendQueueRequeue;
+        assert_eq!(pipe.client.dgram_set_field(field, 1), Ok(()));
+
+        let field = dgram::DatagramField::SendQueueFrontLen;
+        assert_eq!(pipe.client.dgram_get_field(field, now), 11);
+
+        // Drop the oldest DATAGRAM.
+        let field = dgram::DatagramField::SendQueueLen;
+        assert_eq!(pipe.client.dgram_set_field(field, 2), Ok(()));
+        assert_eq!(pipe.client.dgram_send_queue_byte_size(), 16);
+
+        let field = dgram::DatagramField::SendQueueByteSize;
+        assert_eq!(
+            pipe.client.dgram_set_field(field, 0),
+            Err(Error::InvalidState)
+        );
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.dgram_recv(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"hola");
+
+        assert_eq!(pipe.server.dgram_recv(&mut buf), Ok(12));
+        assert_eq!(&buf[..12], b"hello, world");
+
+        assert_eq!(pipe.server.dgram_recv(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    #[cfg(feature = "datagram")]
     fn dgram_send_max_size() {
         let mut buf = [0; MAX_DGRAM_FRAME_SIZE as usize];
 
@@ -13522,6 +24572,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "datagram")]
     /// Tests is_readable check.
     fn is_readable() {
         let mut buf = [0; 65535];
@@ -13660,6 +24711,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn close_with_frame_type() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Report a protocol violation triggered by a STREAM frame.
+        assert_eq!(
+            pipe.client.close_with_frame_type(0xa, 0x0a, b"bad stream"),
+            Ok(())
+        );
+
+        assert_eq!(
+            pipe.client.close_with_frame_type(0x7, 0x08, b"bad stream"),
+            Err(Error::Done)
+        );
+
+        let err = ConnectionError {
+            is_app: false,
+            error_code: 0xa,
+            frame_type: 0x0a,
+            reason: b"bad stream".to_vec(),
+        };
+
+        assert_eq!(pipe.client.local_error(), Some(&err));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.peer_error(), Some(&err));
+        assert!(pipe.server.is_draining());
+    }
+
     #[test]
     fn app_close_by_server_during_handshake_private_key_failure() {
         let mut pipe = testing::Pipe::new().unwrap();
@@ -13704,6 +24786,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: false,
                 error_code: 0x01,
+                frame_type: 0,
                 reason: vec![],
             })
         );
@@ -13712,6 +24795,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: false,
                 error_code: 0x01,
+                frame_type: 0,
                 reason: vec![],
             })
         );
@@ -13754,6 +24838,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: false,
                 error_code: 0x0c,
+                frame_type: 0,
                 reason: vec![],
             })
         );
@@ -13762,6 +24847,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: false,
                 error_code: 0x0c,
+                frame_type: 0,
                 reason: vec![],
             })
         );
@@ -13808,6 +24894,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: true,
                 error_code: 123,
+                frame_type: 0,
                 reason: b"Invalid authentication".to_vec()
             })
         );
@@ -13816,6 +24903,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: true,
                 error_code: 123,
+                frame_type: 0,
                 reason: b"Invalid authentication".to_vec()
             })
         );
@@ -13834,6 +24922,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: false,
                 error_code: 0x1234u64,
+                frame_type: 0,
                 reason: b"hello?".to_vec()
             })
         );
@@ -13852,6 +24941,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: true,
                 error_code: 0x1234u64,
+                frame_type: 0,
                 reason: b"hello!".to_vec()
             })
         );
@@ -13871,6 +24961,7 @@ mod tests {
             Some(&ConnectionError {
                 is_app: true,
                 error_code: 0x1234u64,
+                frame_type: 0,
                 reason: b"hello!".to_vec()
             })
         );
@@ -14030,7 +25121,7 @@ mod tests {
         assert_eq!(pipe.advance(), Ok(()));
     }
 
-    #[cfg(feature = "boringssl-boring-crate")]
+    #[cfg(all(feature = "boringssl-boring-crate", not(feature = "rustls")))]
     #[test]
     fn user_provided_boring_ctx() -> Result<()> {
         // Manually construct boring ssl ctx for server
@@ -14222,6 +25313,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn connection_id_limit_policy() {
+        let mut buf = [0; 65535];
+
+        for policy in [
+            ConnectionIdLimitPolicy::Close,
+            ConnectionIdLimitPolicy::Retire,
+        ] {
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.verify_peer(false);
+            config.set_connection_id_limit_policy(policy);
+
+            let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+            assert_eq!(pipe.handshake(), Ok(()));
+
+            // The client provides two more Connection IDs, while the server
+            // only allows one more.
+            let frames: Vec<_> = (1..3)
+                .map(|seq_num| {
+                    let (cid, reset_token) =
+                        testing::create_cid_and_reset_token(16);
+
+                    frame::Frame::NewConnectionId {
+                        seq_num,
+                        retire_prior_to: 0,
+                        conn_id: cid.to_vec(),
+                        reset_token: reset_token.to_be_bytes(),
+                    }
+                })
+                .collect();
+
+            let res =
+                pipe.send_pkt_to_server(packet::Type::Short, &frames, &mut buf);
+
+            assert_eq!(pipe.server.stats().dcids_over_limit, 1);
+
+            if policy == ConnectionIdLimitPolicy::Close {
+                assert_eq!(res, Err(Error::IdLimit));
+                assert_eq!(
+                    pipe.server.local_error(),
+                    Some(&ConnectionError {
+                        is_app: false,
+                        error_code: 0x9,
+                        frame_type: 0,
+                        reason: vec![],
+                    })
+                );
+            } else {
+                assert!(res.is_ok());
+                assert!(!pipe.server.is_closed());
+                assert_eq!(pipe.server.available_dcids(), 1);
+
+                // The extra Connection ID is retired.
+                assert_eq!(pipe.server.ids.next_retire_dcid_seq(), Some(2));
+            }
+        }
+    }
+
     #[test]
     /// Exercices the handling of NEW_CONNECTION_ID and RETIRE_CONNECTION_ID
     /// frames.
@@ -14275,6 +25433,10 @@ mod tests {
             Ok(2)
         );
 
+        // The limit is exceeded until the server retires the oldest CID.
+        assert_eq!(pipe.client.active_source_cids(), 3);
+        assert_eq!(pipe.client.source_cids_left(), 0);
+
         // Let exchange packets over the connection.
         assert_eq!(pipe.advance(), Ok(()));
 
@@ -14570,30 +25732,386 @@ mod tests {
 
         assert_eq!(pipe.advance(), Ok(()));
 
-        // The path should be validated at some point.
+        // The path should be validated at some point.
+        assert_eq!(
+            pipe.client.path_event_next(),
+            Some(PathEvent::Validated(client_addr_2, server_addr)),
+        );
+        assert_eq!(pipe.client.path_event_next(), None);
+
+        // The server should be notified of this new path.
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2)),
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_2)),
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+
+        // The server can later probe the path again.
+        assert_eq!(pipe.server.probe_path(server_addr, client_addr_2), Ok(1));
+
+        // This should not trigger any event at client side.
+        assert_eq!(pipe.client.path_event_next(), None);
+        assert_eq!(pipe.server.path_event_next(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "multipath")]
+    fn multipath() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(2);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_multipath(true);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        assert!(pipe.client.is_multipath());
+        assert!(pipe.server.is_multipath());
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr = testing::Pipe::client_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        assert_eq!(pipe.client.create_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.path_event_next(),
+            Some(PathEvent::Validated(client_addr_2, server_addr)),
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2)),
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_2)),
+        );
+
+        // Both paths are used at the same time.
+        assert_eq!(
+            pipe.client.paths.iter().filter(|(_, p)| p.active()).count(),
+            2
+        );
+        assert_eq!(
+            pipe.server.paths.iter().filter(|(_, p)| p.active()).count(),
+            2
+        );
+
+        // Data still flows, and is acknowledged on both paths.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut buf = [0; 1];
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((1, true)));
+
+        // The client puts the second path on standby.
+        assert_eq!(
+            pipe.client.set_path_status(
+                client_addr_2,
+                server_addr,
+                PathStatus::Standby
+            ),
+            Ok(())
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let pid = pipe
+            .server
+            .paths
+            .path_id_from_addrs(&(server_addr, client_addr_2))
+            .unwrap();
+        assert_eq!(
+            pipe.server.paths.get(pid).unwrap().status(),
+            PathStatus::Standby
+        );
+
+        // The client then abandons it.
+        assert_eq!(
+            pipe.client
+                .abandon_path(client_addr_2, server_addr, 1, b"bye"),
+            Ok(())
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Closed(server_addr, client_addr_2)),
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+        assert!(pipe.server.paths.get(pid).unwrap().abandoned());
+
+        // The last active path cannot be abandoned.
+        assert_eq!(
+            pipe.client
+                .abandon_path(client_addr, server_addr, 1, b"bye"),
+            Err(Error::InvalidState)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "multipath")]
+    fn multipath_not_negotiated() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_multipath(true);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Only the client enabled the extension.
+        assert!(!pipe.client.is_multipath());
+        assert!(!pipe.server.is_multipath());
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        assert_eq!(
+            pipe.client.create_path(client_addr_2, server_addr),
+            Err(Error::InvalidState)
+        );
+        assert_eq!(
+            pipe.client.stream_set_path_affinity(
+                0,
+                Some((testing::Pipe::client_addr(), server_addr))
+            ),
+            Err(Error::InvalidState)
+        );
+
+        // Receiving multipath frames is a protocol violation.
+        let frames = [frame::Frame::PathStatus {
+            dcid_seq_num: 0,
+            seq_num: 1,
+            status: 1,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut [0; 1350]),
+            Err(Error::InvalidPacket)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "multipath")]
+    fn multipath_redundant_scheduler() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(2);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_multipath(true);
+        config.set_path_scheduler(PathSchedulerAlgorithm::Redundant);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        assert_eq!(pipe.client.create_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+
+        let mut buf = [0; 65535];
+
+        // The stream data is sent on one path, and then duplicated on the
+        // other one.
+        let (len, first) = pipe.client.send(&mut buf).unwrap();
+        assert!(!pipe.client.redundant_streams.is_empty());
+
+        let info = RecvInfo {
+            from: first.from,
+            to: first.to,
+        };
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        let (len, second) = pipe.client.send(&mut buf).unwrap();
+        assert!(pipe.client.redundant_streams.is_empty());
+        assert_ne!(first.from, second.from);
+
+        let info = RecvInfo {
+            from: second.from,
+            to: second.to,
+        };
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The data is only delivered once.
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"hello");
+
+        // Data acknowledged on one path isn't sent again on the other one.
+        assert_eq!(pipe.client.stream_send(4, b"world", true), Ok(5));
+
+        let (len, first) = pipe.client.send(&mut buf).unwrap();
+        assert!(!pipe.client.redundant_streams.is_empty());
+
+        let info = RecvInfo {
+            from: first.from,
+            to: first.to,
+        };
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        let (len, ack) = pipe.server.send(&mut buf).unwrap();
+        let info = RecvInfo {
+            from: ack.from,
+            to: ack.to,
+        };
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
+
+        pipe.client.prune_redundant_streams();
+        assert!(pipe.client.redundant_streams.is_empty());
+
+        // Data queued on an abandoned path is dropped.
+        assert_eq!(pipe.client.stream_send(8, b"again", true), Ok(5));
+
+        pipe.client.send(&mut buf).unwrap();
+
+        let pid = *pipe.client.redundant_streams.keys().next().unwrap();
+        let path = pipe.client.paths.get(pid).unwrap();
+        let (local_addr, peer_addr) = (path.local_addr(), path.peer_addr());
+
+        assert_eq!(
+            pipe.client.abandon_path(local_addr, peer_addr, 0, b""),
+            Ok(())
+        );
+        assert!(pipe.client.redundant_streams.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "multipath")]
+    fn multipath_stream_path_affinity() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(2);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_multipath(true);
+        config.set_path_scheduler(PathSchedulerAlgorithm::RoundRobin);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr = testing::Pipe::client_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+        let client_addr_3 = "127.0.0.1:9012".parse().unwrap();
+
+        assert_eq!(pipe.client.create_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The path must exist.
         assert_eq!(
-            pipe.client.path_event_next(),
-            Some(PathEvent::Validated(client_addr_2, server_addr)),
+            pipe.client
+                .stream_set_path_affinity(0, Some((client_addr_3, server_addr))),
+            Err(Error::InvalidState)
         );
-        assert_eq!(pipe.client.path_event_next(), None);
 
-        // The server should be notified of this new path.
+        let second_path = Some((client_addr_2, server_addr));
+        assert_eq!(pipe.client.stream_set_path_affinity(0, second_path), Ok(()));
         assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::New(server_addr, client_addr_2)),
+            pipe.client
+                .stream_set_path_affinity(4, Some((client_addr, server_addr))),
+            Ok(())
         );
+
+        let mut buf = [0; 65535];
+        let mut b = [0; 15];
+
+        // The data of each stream is only sent on its own path.
+        for _ in 0..3 {
+            assert_eq!(pipe.client.stream_send(0, b"a", false), Ok(1));
+
+            let (len, info) = pipe.client.send(&mut buf).unwrap();
+            assert_eq!(info.from, client_addr_2);
+
+            let info = RecvInfo {
+                from: info.from,
+                to: info.to,
+            };
+            assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+            assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((1, false)));
+
+            assert_eq!(pipe.client.stream_send(4, b"b", false), Ok(1));
+
+            let (len, info) = pipe.client.send(&mut buf).unwrap();
+            assert_eq!(info.from, client_addr);
+
+            let info = RecvInfo {
+                from: info.from,
+                to: info.to,
+            };
+            assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+            assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((1, false)));
+
+            assert_eq!(pipe.advance(), Ok(()));
+        }
+
+        // Once the second path is abandoned, its streams use the other one.
         assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::Validated(server_addr, client_addr_2)),
+            pipe.client
+                .abandon_path(client_addr_2, server_addr, 1, b"bye"),
+            Ok(())
         );
-        assert_eq!(pipe.server.path_event_next(), None);
+        assert_eq!(pipe.advance(), Ok(()));
 
-        // The server can later probe the path again.
-        assert_eq!(pipe.server.probe_path(server_addr, client_addr_2), Ok(1));
+        assert_eq!(pipe.client.stream_send(0, b"c", true), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((1, true)));
+        assert_eq!(&b[..1], b"c");
 
-        // This should not trigger any event at client side.
-        assert_eq!(pipe.client.path_event_next(), None);
-        assert_eq!(pipe.server.path_event_next(), None);
+        // The affinity can be removed.
+        assert_eq!(pipe.client.stream_set_path_affinity(4, None), Ok(()));
+        assert!(pipe.client.stream_path_affinity.get(&4).is_none());
     }
 
     #[test]
@@ -14797,6 +26315,170 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pmtud() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(10000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_send_udp_payload_size(1500);
+        config.set_max_recv_udp_payload_size(1400);
+        config.verify_peer(false);
+        config.discover_pmtu(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(
+            pipe.client.path_stats().next().unwrap().pmtu,
+            MIN_CLIENT_INITIAL_LEN
+        );
+
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Probes up to the peer's maximum UDP payload size went through.
+        assert_eq!(pipe.client.path_stats().next().unwrap().pmtu, 1400);
+        assert_eq!(pipe.server.path_stats().next().unwrap().pmtu, 1400);
+        assert_eq!(pipe.client.max_send_udp_payload_size(), 1400);
+
+        assert_eq!(pipe.client.stream_send(0, &[0; 5000], true), Ok(5000));
+
+        let mut buf = [0; 65535];
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(len, 1400);
+    }
+
+    #[test]
+    fn ecn() {
+        // Delivers the flights of the pipe's endpoints along with their ECN
+        // codepoint, optionally re-marking ECT(0) packets as congested.
+        fn advance_with_ecn(pipe: &mut testing::Pipe, ce: bool) -> Result<()> {
+            loop {
+                let mut done = true;
+
+                for (from_client, flight) in [
+                    (true, testing::emit_flight(&mut pipe.client)),
+                    (false, testing::emit_flight(&mut pipe.server)),
+                ] {
+                    let flight = match flight {
+                        Ok(v) => v,
+
+                        Err(Error::Done) => continue,
+
+                        Err(e) => return Err(e),
+                    };
+
+                    done = false;
+
+                    let conn = if from_client {
+                        &mut pipe.server
+                    } else {
+                        &mut pipe.client
+                    };
+
+                    for (mut pkt, si) in flight {
+                        let info = RecvInfo {
+                            to: si.to,
+                            from: si.from,
+                        };
+
+                        let ecn = match si.ecn {
+                            EcnCodepoint::Ect0 if ce => EcnCodepoint::Ce,
+
+                            ecn => ecn,
+                        };
+
+                        conn.recv_with_ecn(&mut pkt, info, ecn)?;
+                    }
+                }
+
+                if done {
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(10000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config.enable_ecn(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(advance_with_ecn(&mut pipe, false), Ok(()));
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+
+        for conn in [&pipe.client, &pipe.server] {
+            let stats = conn.stats();
+            assert!(stats.ecn_capable);
+            assert!(stats.ecn_marked_sent > 0);
+            assert!(stats.ecn_ect0_recv > 0);
+            assert_eq!(stats.ecn_ce_recv, 0);
+        }
+
+        let cwnd = pipe.client.paths.get_active().unwrap().recovery.cwnd();
+
+        // The path marks the packets as congested.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(advance_with_ecn(&mut pipe, true), Ok(()));
+
+        assert_eq!(pipe.server.stats().ecn_ce_recv, 1);
+        assert_eq!(pipe.client.stats().ecn_ce_reported, 1);
+        assert!(pipe.client.stats().ecn_capable);
+        assert!(pipe.client.paths.get_active().unwrap().recovery.cwnd() < cwnd);
+    }
+
+    #[test]
+    fn ecn_not_supported() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_ecn(true);
+
+        // The marks of the packets are not reported to the endpoints.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.client.stats().ecn_capable);
+
+        let mut buf = [0; 65535];
+        assert_eq!(pipe.client.send_ack_eliciting(), Ok(()));
+        let (_, info) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(info.ecn, EcnCodepoint::NotEct);
+    }
+
     #[test]
     fn path_probing_dos() {
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -14889,6 +26571,230 @@ mod tests {
         );
     }
 
+    #[test]
+    fn send_vectored() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+
+        // The packet is written across the buffers.
+        let mut header = [0; 16];
+        let mut payload = [0; 1500];
+        let mut bufs = [
+            std::io::IoSliceMut::new(&mut header),
+            std::io::IoSliceMut::new(&mut payload),
+        ];
+
+        let (len, info) = pipe.client.send_vectored(&mut bufs).unwrap();
+        assert!(len > header.len());
+        assert_eq!(info.to, testing::Pipe::server_addr());
+
+        let mut pkt = header.to_vec();
+        pkt.extend_from_slice(&payload[..len - header.len()]);
+
+        assert_eq!(pipe.server_recv(&mut pkt), Ok(len));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, false)));
+        assert_eq!(&b[..5], b"hello");
+
+        // The packet is written in place in the first buffer.
+        assert_eq!(pipe.client.stream_send(4, b"world", true), Ok(5));
+
+        let mut first = [0; 1500];
+        let mut second = [0; 1500];
+        let mut bufs = [
+            std::io::IoSliceMut::new(&mut first),
+            std::io::IoSliceMut::new(&mut second),
+        ];
+
+        let (len, _) = pipe.client.send_vectored(&mut bufs).unwrap();
+        assert!(len <= first.len());
+        assert_eq!(second, [0; 1500]);
+
+        assert_eq!(pipe.server_recv(&mut first[..len]), Ok(len));
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"world");
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut bufs = [std::io::IoSliceMut::new(&mut first)];
+        assert_eq!(pipe.client.send_vectored(&mut bufs), Err(Error::Done));
+    }
+
+    #[test]
+    fn send_batch() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let data = [42; 5000];
+        assert_eq!(pipe.client.stream_send(4, &data, true), Ok(5000));
+
+        let mut out = [0; 65535];
+
+        let (len, segment_size, info) = pipe.client.send_batch(&mut out).unwrap();
+        assert_eq!(segment_size, pipe.client.max_send_udp_payload_size());
+        assert!(len > 4 * segment_size);
+        assert_eq!(info.to, testing::Pipe::server_addr());
+
+        let mut segments = [RecvSegment {
+            buf: &mut out[..len],
+            segment_size,
+            info: RecvInfo {
+                from: info.from,
+                to: info.to,
+            },
+        }];
+
+        assert_eq!(pipe.server.recv_batch(&mut segments), Ok(len));
+
+        let mut b = [0; 5000];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5000, true)));
+        assert_eq!(b, data);
+
+        // The batch stops when the next datagram doesn't fit.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_send(8, &data, true), Ok(5000));
+
+        let (len, segment_size, _) =
+            pipe.client.send_batch(&mut out[..2500]).unwrap();
+        assert_eq!(len, 2 * segment_size);
+
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.send_batch(&mut out), Err(Error::Done));
+    }
+
+    #[test]
+    fn recv_batch() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let data = [42; 5000];
+        assert_eq!(pipe.client.stream_send(4, &data, true), Ok(5000));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert!(flight.len() > 2);
+
+        // Coalesce the datagrams as GRO would, with all datagrams but the
+        // last one having the same size.
+        let segment_size = flight[0].0.len();
+        let (last, rest) = flight.split_last().unwrap();
+        assert!(rest.iter().all(|(pkt, _)| pkt.len() == segment_size));
+
+        let mut buf: Vec<u8> =
+            flight.iter().flat_map(|(pkt, _)| pkt.clone()).collect();
+        let len = buf.len();
+
+        let mut segments = [RecvSegment {
+            buf: &mut buf,
+            segment_size,
+            info: RecvInfo {
+                from: last.1.from,
+                to: last.1.to,
+            },
+        }];
+
+        assert_eq!(pipe.server.recv_batch(&mut segments), Ok(len));
+
+        let mut b = [0; 5000];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5000, true)));
+        assert_eq!(b, data);
+
+        // A segment without segment size is a single datagram.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
+
+        let (mut pkt, info) =
+            testing::emit_flight(&mut pipe.client).unwrap().remove(0);
+        let len = pkt.len();
+
+        let mut segments = [RecvSegment {
+            buf: &mut pkt,
+            segment_size: 0,
+            info: RecvInfo {
+                from: info.from,
+                to: info.to,
+            },
+        }];
+
+        assert_eq!(pipe.server.recv_batch(&mut segments), Ok(len));
+        assert_eq!(pipe.server.stream_recv(8, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"hello");
+    }
+
+    #[test]
+    fn recv_with_header() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+
+        let (mut pkt, info) =
+            testing::emit_flight(&mut pipe.client).unwrap().remove(0);
+        let len = pkt.len();
+
+        let hdr = Header::from_slice(&mut pkt, 16).unwrap();
+        assert_eq!(hdr.ty, packet::Type::Short);
+
+        let info = RecvInfo {
+            from: info.from,
+            to: info.to,
+        };
+
+        assert_eq!(pipe.server.recv_with_header(&mut pkt, hdr, info), Ok(len));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, false)));
+        assert_eq!(&b[..5], b"hello");
+
+        // A header that doesn't match the packet is parsed again.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.stream_send(0, b"world", true), Ok(5));
+
+        let (mut pkt, _) =
+            testing::emit_flight(&mut pipe.client).unwrap().remove(0);
+        let len = pkt.len();
+
+        let hdr = Header::from_slice(&mut pkt, 8).unwrap();
+
+        assert_eq!(pipe.server.recv_with_header(&mut pkt, hdr, info), Ok(len));
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"world");
+    }
+
     #[test]
     fn send_on_path_test() {
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -15143,445 +27049,1099 @@ mod tests {
             server_addr
         );
         assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::New(server_addr, client_addr_3))
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_3))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_3))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::PeerMigrated(server_addr, client_addr_3))
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .expect("no active")
+                .local_addr(),
+            server_addr
+        );
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .expect("no active")
+                .peer_addr(),
+            client_addr_3
+        );
+
+        // Case 3: the client tries to migrate on the current active path.
+        // This is not an error, but it triggers nothing.
+        assert_eq!(pipe.client.migrate(client_addr_3, server_addr), Ok(2));
+        assert_eq!(pipe.client.stream_send(8, b"data", true), Ok(4));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.path_event_next(), None);
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .local_addr(),
+            client_addr_3
+        );
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .peer_addr(),
+            server_addr
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .expect("no active")
+                .local_addr(),
+            server_addr
+        );
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .expect("no active")
+                .peer_addr(),
+            client_addr_3
+        );
+
+        // Case 4: the client tries to migrate on a path that was not previously
+        // validated, and has no spare SCIDs/DCIDs. Prevent active migration.
+        assert_eq!(
+            pipe.client.migrate(client_addr_4, server_addr),
+            Err(Error::OutOfIdentifiers)
+        );
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .local_addr(),
+            client_addr_3
+        );
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .peer_addr(),
+            server_addr
+        );
+    }
+
+    #[test]
+    fn connection_migration_waits_for_validation() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(2);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(10);
+        config.set_initial_max_streams_bidi(3);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr = testing::Pipe::client_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        assert_eq!(pipe.client.migrate(client_addr_2, server_addr), Ok(1));
+
+        // The current path is used until the new one is validated.
+        let path = pipe.client.paths.get_active().expect("no active");
+        assert_eq!(path.local_addr(), client_addr);
+        assert_eq!(
+            pipe.client.is_path_validated(client_addr_2, server_addr),
+            Ok(false)
+        );
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.path_event_next(),
+            Some(PathEvent::Validated(client_addr_2, server_addr))
+        );
+        assert_eq!(pipe.client.path_event_next(), None);
+
+        let path = pipe.client.paths.get_active().expect("no active");
+        assert_eq!(path.local_addr(), client_addr_2);
+        assert_eq!(path.active_dcid_seq, Some(1));
+
+        // The Destination Connection ID of the old path was retired.
+        let pid = pipe
+            .client
+            .paths
+            .path_id_from_addrs(&(client_addr, server_addr))
+            .expect("no path");
+        assert_eq!(pipe.client.paths.get(pid).unwrap().active_dcid_seq, None);
+        assert!(pipe.client.ids.get_dcid(0).is_err());
+
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::PeerMigrated(server_addr, client_addr_2))
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+    }
+
+    #[test]
+    fn connection_migration_zero_length_cid() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(2);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(10);
+        config.set_initial_max_streams_bidi(3);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 0, 16, 1);
+
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        // The client migrates on a path that was not previously
+        // validated, and has spare SCIDs/DCIDs to do so.
+        assert_eq!(pipe.client.migrate(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.client.stream_send(4, b"data", true), Ok(4));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .local_addr(),
+            client_addr_2
+        );
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active()
+                .expect("no active")
+                .peer_addr(),
+            server_addr
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::PeerMigrated(server_addr, client_addr_2))
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .expect("no active")
+                .local_addr(),
+            server_addr
+        );
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .expect("no active")
+                .peer_addr(),
+            client_addr_2
+        );
+    }
+
+    #[test]
+    fn connection_migration_reordered_non_probing() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(2);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(10);
+        config.set_initial_max_streams_bidi(3);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(
+            pipe.client.path_event_next(),
+            Some(PathEvent::Validated(client_addr_2, server_addr))
         );
+        assert_eq!(pipe.client.path_event_next(), None);
         assert_eq!(
             pipe.server.path_event_next(),
-            Some(PathEvent::Validated(server_addr, client_addr_3))
+            Some(PathEvent::New(server_addr, client_addr_2))
         );
         assert_eq!(
             pipe.server.path_event_next(),
-            Some(PathEvent::PeerMigrated(server_addr, client_addr_3))
+            Some(PathEvent::Validated(server_addr, client_addr_2))
         );
         assert_eq!(pipe.server.path_event_next(), None);
+
+        // A first flight sent from secondary address.
+        assert_eq!(pipe.client.stream_send(0, b"data", true), Ok(4));
+        let mut first = testing::emit_flight(&mut pipe.client).unwrap();
+        first.iter_mut().for_each(|(_, si)| si.from = client_addr_2);
+        // A second one, but sent from the original one.
+        assert_eq!(pipe.client.stream_send(4, b"data", true), Ok(4));
+        let second = testing::emit_flight(&mut pipe.client).unwrap();
+        // Second flight is received before first one.
+        assert_eq!(testing::process_flight(&mut pipe.server, second), Ok(()));
+        assert_eq!(testing::process_flight(&mut pipe.server, first), Ok(()));
+
+        // Server does not perform connection migration because of packet
+        // reordering.
+        assert_eq!(pipe.server.path_event_next(), None);
         assert_eq!(
             pipe.server
                 .paths
                 .get_active()
                 .expect("no active")
-                .local_addr(),
-            server_addr
+                .peer_addr(),
+            client_addr
         );
+    }
+
+    #[test]
+    fn resilience_against_migration_attack() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(3);
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(2);
+
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
+        let spoofed_client_addr = "127.0.0.1:6666".parse().unwrap();
+
+        const DATA_BYTES: usize = 24000;
+        let buf = [42; DATA_BYTES];
+        let mut recv_buf = [0; DATA_BYTES];
+        assert_eq!(pipe.server.stream_send(1, &buf, true), Ok(12000));
         assert_eq!(
-            pipe.server
-                .paths
-                .get_active()
-                .expect("no active")
-                .peer_addr(),
-            client_addr_3
+            testing::process_flight(
+                &mut pipe.client,
+                testing::emit_flight(&mut pipe.server).unwrap()
+            ),
+            Ok(())
         );
+        let (rcv_data_1, _) = pipe.client.stream_recv(1, &mut recv_buf).unwrap();
 
-        // Case 3: the client tries to migrate on the current active path.
-        // This is not an error, but it triggers nothing.
-        assert_eq!(pipe.client.migrate(client_addr_3, server_addr), Ok(2));
-        assert_eq!(pipe.client.stream_send(8, b"data", true), Ok(4));
-        assert_eq!(pipe.advance(), Ok(()));
-        assert_eq!(pipe.client.path_event_next(), None);
+        // Fake the source address of client.
+        let mut faked_addr_flight =
+            testing::emit_flight(&mut pipe.client).unwrap();
+        faked_addr_flight
+            .iter_mut()
+            .for_each(|(_, si)| si.from = spoofed_client_addr);
         assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .local_addr(),
-            client_addr_3
+            testing::process_flight(&mut pipe.server, faked_addr_flight),
+            Ok(())
+        );
+        assert_eq!(pipe.server.stream_send(1, &buf[12000..], true), Ok(12000));
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::ReusedSourceConnectionId(
+                0,
+                (server_addr, client_addr),
+                (server_addr, spoofed_client_addr)
+            ))
         );
         assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .peer_addr(),
-            server_addr
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, spoofed_client_addr))
         );
-        assert_eq!(pipe.server.path_event_next(), None);
+
         assert_eq!(
-            pipe.server
-                .paths
-                .get_active()
-                .expect("no active")
-                .local_addr(),
-            server_addr
+            pipe.server.is_path_validated(server_addr, client_addr),
+            Ok(true)
         );
         assert_eq!(
             pipe.server
-                .paths
-                .get_active()
-                .expect("no active")
-                .peer_addr(),
-            client_addr_3
+                .is_path_validated(server_addr, spoofed_client_addr),
+            Ok(false)
         );
 
-        // Case 4: the client tries to migrate on a path that was not previously
-        // validated, and has no spare SCIDs/DCIDs. Prevent active migration.
+        // The client creates the PATH CHALLENGE, but it is always lost.
+        testing::emit_flight(&mut pipe.server).unwrap();
+
+        // Wait until probing timer expires. Since the RTT is very low,
+        // wait a bit more.
+        let probed_pid = pipe
+            .server
+            .paths
+            .path_id_from_addrs(&(server_addr, spoofed_client_addr))
+            .unwrap();
+        let probe_instant = pipe
+            .server
+            .paths
+            .get(probed_pid)
+            .unwrap()
+            .recovery
+            .loss_detection_timer()
+            .unwrap();
+        let timer = probe_instant.duration_since(time::Instant::now());
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.server.on_timeout();
+
+        // Because of the small ACK size, the server cannot send more to the
+        // client. Fallback on the previous active path.
         assert_eq!(
-            pipe.client.migrate(client_addr_4, server_addr),
-            Err(Error::OutOfIdentifiers)
+            pipe.server.path_event_next(),
+            Some(PathEvent::FailedValidation(
+                server_addr,
+                spoofed_client_addr
+            ))
         );
+
         assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .local_addr(),
-            client_addr_3
+            pipe.server.is_path_validated(server_addr, client_addr),
+            Ok(true)
         );
         assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .peer_addr(),
-            server_addr
+            pipe.server
+                .is_path_validated(server_addr, spoofed_client_addr),
+            Ok(false)
+        );
+
+        let server_active_path = pipe.server.paths.get_active().unwrap();
+        assert_eq!(server_active_path.local_addr(), server_addr);
+        assert_eq!(server_active_path.peer_addr(), client_addr);
+        assert_eq!(pipe.advance(), Ok(()));
+        let (rcv_data_2, fin) =
+            pipe.client.stream_recv(1, &mut recv_buf).unwrap();
+        assert_eq!(fin, true);
+        assert_eq!(rcv_data_1 + rcv_data_2, DATA_BYTES);
+    }
+
+    #[test]
+    fn consecutive_non_ack_eliciting() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends a bunch of PING frames, causing server to ACK (ACKs aren't
+        // ack-eliciting)
+        let frames = [frame::Frame::Ping { mtu_probe: None }];
+        let pkt_type = packet::Type::Short;
+        for _ in 0..24 {
+            let len = pipe
+                .send_pkt_to_server(pkt_type, &frames, &mut buf)
+                .unwrap();
+            assert!(len > 0);
+
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+            assert!(
+                frames
+                    .iter()
+                    .all(|frame| matches!(frame, frame::Frame::ACK { .. })),
+                "ACK only"
+            );
+        }
+
+        // After 24 non-ack-eliciting, an ACK is explicitly elicited with a PING
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+        assert!(len > 0);
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+        assert!(
+            frames
+                .iter()
+                .any(|frame| matches!(frame, frame::Frame::Ping { .. })),
+            "found a PING"
         );
     }
 
     #[test]
-    fn connection_migration_zero_length_cid() {
+    fn max_send_rate() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let pacing_rate = |conn: &Connection| {
+            conn.paths.get_active().unwrap().recovery.pacer.rate()
+        };
+
+        pipe.server.set_max_send_rate(10_000);
+        assert_eq!(pacing_rate(&pipe.server), 10_000);
+
+        // The congestion controller allows sending faster, but the pacing
+        // rate stays capped.
+        assert_eq!(pipe.server.stream_send(1, b"hello", false), Ok(5));
+        pipe.server.send(&mut buf).unwrap();
+
+        assert_eq!(pacing_rate(&pipe.server), 10_000);
+
+        // Removing the limit restores the congestion controller's rate.
+        pipe.server.set_max_send_rate(0);
+
+        assert_eq!(pipe.server.stream_send(1, b"world", false), Ok(5));
+        pipe.server.send(&mut buf).unwrap();
+
+        assert!(pacing_rate(&pipe.server) > 10_000);
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_max_idle_timeout(180_000);
+        config.verify_peer(false);
+        config.set_handshake_timeout(time::Duration::from_millis(50));
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        // The client's Initial packets are lost.
+        testing::emit_flight(&mut pipe.client).unwrap();
+
+        let timer = pipe.client.timeout().unwrap();
+        assert!(timer <= time::Duration::from_millis(50));
+
+        std::thread::sleep(time::Duration::from_millis(51));
+
+        pipe.client.on_timeout();
+
+        assert!(pipe.client.is_closed());
+        assert!(pipe.client.is_handshake_timed_out());
+        assert!(!pipe.client.is_timed_out());
+
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::HandshakeTimeout));
+
+        // Established connections are not affected.
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        std::thread::sleep(time::Duration::from_millis(51));
+
+        pipe.client.on_timeout();
+
+        assert!(!pipe.client.is_closed());
+        assert!(!pipe.client.is_handshake_timed_out());
+    }
+
+    #[test]
+    fn keepalive() {
+        let mut buf = [0; 65535];
+
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
+        config.set_max_idle_timeout(180_000);
         config.verify_peer(false);
-        config.set_active_connection_id_limit(2);
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(10);
-        config.set_initial_max_streams_bidi(3);
+        config.enable_keepalive(time::Duration::from_millis(50));
 
-        let mut pipe = pipe_with_exchanged_cids(&mut config, 0, 16, 1);
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let server_addr = testing::Pipe::server_addr();
-        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+        // Only the client has keep-alive enabled.
+        assert!(pipe.client.keepalive_timer.is_some());
+        assert!(pipe.server.keepalive_timer.is_none());
 
-        // The client migrates on a path that was not previously
-        // validated, and has spare SCIDs/DCIDs to do so.
-        assert_eq!(pipe.client.migrate(client_addr_2, server_addr), Ok(1));
-        assert_eq!(pipe.client.stream_send(4, b"data", true), Ok(4));
-        assert_eq!(pipe.advance(), Ok(()));
-        assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .local_addr(),
-            client_addr_2
-        );
-        assert_eq!(
-            pipe.client
-                .paths
-                .get_active()
-                .expect("no active")
-                .peer_addr(),
-            server_addr
-        );
-        assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::New(server_addr, client_addr_2))
-        );
+        let timer = pipe.client.timeout().unwrap();
+        assert!(timer <= time::Duration::from_millis(50));
+
+        std::thread::sleep(time::Duration::from_millis(51));
+
+        pipe.client.on_timeout();
+        assert!(pipe.client.keepalive_timer.is_none());
+
+        // The client sends a PING frame, and re-arms the timer.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
+
+        assert!(frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::Ping { .. })));
+
+        assert!(pipe.client.keepalive_timer.is_some());
+        assert!(!pipe.client.is_closed());
+    }
+
+    #[test]
+    fn send_ack_eliciting_causes_ping() {
+        // First establish a connection
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Queue a PING frame
+        pipe.server.send_ack_eliciting().unwrap();
+
+        // Make sure ping is sent
+        let mut buf = [0; 1500];
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+        let mut iter = frames.iter();
+
+        assert_eq!(iter.next(), Some(&frame::Frame::Ping { mtu_probe: None }));
+    }
+
+    #[test]
+    fn send_ack_eliciting_no_ping() {
+        // First establish a connection
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Queue a PING frame
+        pipe.server.send_ack_eliciting().unwrap();
+
+        // Send a stream frame, which is ACK-eliciting to make sure the ping is
+        // not sent
+        assert_eq!(pipe.server.stream_send(1, b"a", false), Ok(1));
+
+        // Make sure ping is not sent
+        let mut buf = [0; 1500];
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+        let mut iter = frames.iter();
+
+        assert!(matches!(
+            iter.next(),
+            Some(&frame::Frame::Stream {
+                stream_id: 1,
+                data: _
+            })
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn recv_with_budget() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        // Server sends coalesced Initial and Handshake packets.
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let server_path = pipe.server.paths.get_active().unwrap();
+        let info = RecvInfo {
+            to: server_path.peer_addr(),
+            from: server_path.local_addr(),
+        };
+
+        let budget = RecvBudget {
+            max_packets: Some(1),
+            max_frames: None,
+        };
+
+        // Only the Initial packet is processed.
+        let (read, cursor) = pipe
+            .client
+            .recv_with_budget(&mut buf[..len], info, budget, None)
+            .unwrap();
+        assert!(cursor.is_some());
+        assert!(read < len);
+
+        // Datagrams received in the meantime are accounted for on their own.
+        let mut junk = [0; 100];
         assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::Validated(server_addr, client_addr_2))
+            pipe.client.recv_with_budget(&mut junk, info, budget, None),
+            Ok((100, None))
         );
+        assert_eq!(pipe.client.stats().junk_datagrams, 1);
+
+        // The remainder of the datagram is processed on the next call.
         assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::PeerMigrated(server_addr, client_addr_2))
+            pipe.client.recv_with_budget(
+                &mut buf[read..len],
+                info,
+                budget,
+                cursor
+            ),
+            Ok((len - read, None))
         );
-        assert_eq!(pipe.server.path_event_next(), None);
+
+        // A frame budget of zero doesn't allow processing anything.
+        let budget = RecvBudget {
+            max_packets: None,
+            max_frames: Some(0),
+        };
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let client_path = pipe.client.paths.get_active().unwrap();
+        let info = RecvInfo {
+            to: client_path.peer_addr(),
+            from: client_path.local_addr(),
+        };
+
         assert_eq!(
             pipe.server
-                .paths
-                .get_active()
-                .expect("no active")
-                .local_addr(),
-            server_addr
+                .recv_with_budget(&mut buf[..len], info, budget, None),
+            Err(Error::Done)
+        );
+
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
+
+    #[test]
+    fn ack_delay_stats() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let client_acks_sent = pipe.client.stats().ack_delay_sent.count;
+        let server_acks_received = pipe.server.stats().ack_delay_received.count;
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((5, true)));
+        assert_eq!(pipe.server.stream_send(0, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let client_stats = pipe.client.stats();
+        let server_stats = pipe.server.stats();
+
+        // The client acknowledged the response.
+        assert!(client_stats.ack_delay_sent.count > client_acks_sent);
+        assert!(server_stats.ack_delay_received.count > server_acks_received);
+
+        let s = &server_stats.ack_delay_received;
+        assert_eq!(s.buckets.iter().sum::<u64>(), s.count);
+        assert!(s.min <= s.max);
+        assert!(s.mean().unwrap() <= s.max);
+
+        assert_eq!(AckDelayStats::default().mean(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "plugins")]
+    fn plugin_timers() {
+        let now = time::Instant::now();
+        let later = now + time::Duration::from_secs(1);
+
+        let mut timers = plugin::PluginTimers::new(DEFAULT_MAX_QUEUED_EVENTS);
+        assert_eq!(timers.next_timeout(), None);
+
+        timers.set(1, 42, later);
+        timers.set(2, 43, now);
+        assert_eq!(timers.next_timeout(), Some(now));
+
+        // Rearming a timer replaces its deadline.
+        timers.set(2, 43, later + time::Duration::from_secs(1));
+        assert_eq!(timers.next_timeout(), Some(later));
+
+        assert!(timers.cancel(1));
+        assert!(!timers.cancel(1));
+        assert_eq!(timers.len(), 1);
+
+        timers.set(3, 44, now);
+        timers.set(4, 45, now - time::Duration::from_millis(1));
+        assert_eq!(timers.pop_expired(now), vec![45, 44]);
+        assert_eq!(timers.len(), 1);
+
+        // Timers beyond the limit are not armed, but can still be rearmed.
+        let mut timers = plugin::PluginTimers::new(1);
+        assert!(timers.set(1, 42, later));
+        assert!(!timers.set(2, 43, now));
+        assert!(timers.set(1, 42, now));
+        assert_eq!(timers.next_timeout(), Some(now));
+        assert_eq!(timers.dropped(), 1);
+
+        // Plugin timers are merged into the connection's timers.
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        pipe.client.plugin_timers.set(1, 42, now);
+        assert!(pipe.client.timeout_instant().unwrap() <= now);
+
+        pipe.client.plugin_timers.set(
+            2,
+            43,
+            now + time::Duration::from_secs(300),
         );
+
+        // Expired timers are disarmed when firing.
+        pipe.client.on_timeout();
+        assert_eq!(pipe.client.plugin_timers.len(), 1);
+        assert!(!pipe.client.is_closed());
+    }
+
+    #[test]
+    #[cfg(feature = "plugins")]
+    fn plugin_failure_report() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+        fn sink(report: &PluginFailureReport) {
+            assert_eq!(report.operation, "export_state");
+            assert!(!report.trace_id.is_empty());
+
+            REPORTS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let op = plugin::export_state_op();
+        let params = [PluginVal::U64(42)];
+
+        // Successful operations are not reported.
+        let res = plugin::guarded_call(&op, &params, || Ok::<_, ()>(1));
+        assert_eq!(pipe.client.plugin_result(res), Some(1));
+
+        set_plugin_failure_sink(Some(sink));
+
+        // Plugin trap.
+        let res = plugin::guarded_call(&op, &params, || Err::<(), _>("trap"));
+
+        let report = res.as_ref().unwrap_err();
+        assert_eq!(report.failure, PluginFailure::Trap("\"trap\"".to_string()));
+        assert_eq!(report.inputs, postcard::to_allocvec(&params[..]).unwrap());
+
+        assert_eq!(pipe.client.plugin_result(res), None);
+
+        // Host panic.
+        let res = plugin::guarded_call(&op, &params, || -> Result<()> {
+            panic!("conversion failed")
+        });
+
         assert_eq!(
-            pipe.server
-                .paths
-                .get_active()
-                .expect("no active")
-                .peer_addr(),
-            client_addr_2
+            res.as_ref().unwrap_err().failure,
+            PluginFailure::Panic("conversion failed".to_string())
         );
+
+        assert_eq!(pipe.client.plugin_result(res), None);
+
+        set_plugin_failure_sink(None);
+
+        assert_eq!(REPORTS.load(Ordering::SeqCst), 2);
+        assert_eq!(pipe.client.stats().plugin_failures, 2);
+        assert!(!pipe.client.is_closed());
     }
 
     #[test]
-    fn connection_migration_reordered_non_probing() {
-        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.verify_peer(false);
-        config.set_active_connection_id_limit(2);
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(10);
-        config.set_initial_max_streams_bidi(3);
+    #[cfg(feature = "plugins")]
+    fn plugin_stream_state() {
+        use stream::StreamField;
 
-        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let client_addr = testing::Pipe::client_addr();
-        let server_addr = testing::Pipe::server_addr();
-        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+        assert_eq!(pipe.server.plugin_stream_state_params(0), None);
 
-        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
         assert_eq!(pipe.advance(), Ok(()));
+
+        let params = pipe.server.plugin_stream_state_params(0).unwrap();
+        assert_eq!(params.len(), StreamField::ALL.len() + 1);
+        assert_eq!(params[0], PluginVal::U64(0));
+
+        let field = |f| {
+            let i = StreamField::ALL.iter().position(|v| *v == f).unwrap();
+            params[i + 1].clone()
+        };
+
+        assert_eq!(field(StreamField::RecvOffset), PluginVal::U64(0));
+        assert_eq!(field(StreamField::RecvMaxOffset), PluginVal::U64(5));
+        assert_eq!(field(StreamField::RecvFin), PluginVal::U64(0));
+
+        // Plugins can reprioritize the stream.
+        let res = [PluginVal::U64(2), PluginVal::U64(0)];
+        assert_eq!(pipe.server.plugin_stream_priority(0, &res), Ok(()));
         assert_eq!(
-            pipe.client.path_event_next(),
-            Some(PathEvent::Validated(client_addr_2, server_addr))
+            pipe.server.streams.get_field(0, StreamField::Urgency),
+            Ok(2)
         );
-        assert_eq!(pipe.client.path_event_next(), None);
         assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::New(server_addr, client_addr_2))
+            pipe.server.streams.get_field(0, StreamField::Incremental),
+            Ok(0)
         );
+
+        let res = [PluginVal::U64(256), PluginVal::U64(1)];
         assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::Validated(server_addr, client_addr_2))
+            pipe.server.plugin_stream_priority(0, &res),
+            Err(Error::InvalidState)
         );
-        assert_eq!(pipe.server.path_event_next(), None);
-
-        // A first flight sent from secondary address.
-        assert_eq!(pipe.client.stream_send(0, b"data", true), Ok(4));
-        let mut first = testing::emit_flight(&mut pipe.client).unwrap();
-        first.iter_mut().for_each(|(_, si)| si.from = client_addr_2);
-        // A second one, but sent from the original one.
-        assert_eq!(pipe.client.stream_send(4, b"data", true), Ok(4));
-        let second = testing::emit_flight(&mut pipe.client).unwrap();
-        // Second flight is received before first one.
-        assert_eq!(testing::process_flight(&mut pipe.server, second), Ok(()));
-        assert_eq!(testing::process_flight(&mut pipe.server, first), Ok(()));
 
-        // Server does not perform connection migration because of packet
-        // reordering.
-        assert_eq!(pipe.server.path_event_next(), None);
+        // The priority is left unchanged when the plugin returns nothing.
+        assert_eq!(pipe.server.plugin_stream_priority(0, &[]), Ok(()));
         assert_eq!(
-            pipe.server
-                .paths
-                .get_active()
-                .expect("no active")
-                .peer_addr(),
-            client_addr
+            pipe.server.streams.get_field(0, StreamField::Urgency),
+            Ok(2)
         );
     }
 
     #[test]
-    fn resilience_against_migration_attack() {
+    #[cfg(feature = "plugins")]
+    fn plugin_mutations() {
+        use pluginop::api::ConnectionToPlugin;
+        use pluginop::common::quic::ConnectionField;
+
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
         config
             .set_application_protos(&[b"proto1", b"proto2"])
             .unwrap();
+        config.set_max_plugin_mutations(2);
         config.verify_peer(false);
-        config.set_active_connection_id_limit(3);
-        config.set_initial_max_data(100000);
-        config.set_initial_max_stream_data_bidi_local(100000);
-        config.set_initial_max_stream_data_bidi_remote(100000);
-        config.set_initial_max_streams_bidi(2);
 
-        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 1);
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let client_addr = testing::Pipe::client_addr();
-        let server_addr = testing::Pipe::server_addr();
-        let spoofed_client_addr = "127.0.0.1:6666".parse().unwrap();
+        assert_eq!(pipe.client.plugin_mutations().count(), 0);
 
-        const DATA_BYTES: usize = 24000;
-        let buf = [42; DATA_BYTES];
-        let mut recv_buf = [0; DATA_BYTES];
-        assert_eq!(pipe.server.stream_send(1, &buf, true), Ok(12000));
-        assert_eq!(
-            testing::process_flight(
-                &mut pipe.client,
-                testing::emit_flight(&mut pipe.server).unwrap()
-            ),
-            Ok(())
-        );
-        let (rcv_data_1, _) = pipe.client.stream_recv(1, &mut recv_buf).unwrap();
+        let op = plugin::export_state_op();
 
-        // Fake the source address of client.
-        let mut faked_addr_flight =
-            testing::emit_flight(&mut pipe.client).unwrap();
-        faked_addr_flight
-            .iter_mut()
-            .for_each(|(_, si)| si.from = spoofed_client_addr);
-        assert_eq!(
-            testing::process_flight(&mut pipe.server, faked_addr_flight),
-            Ok(())
-        );
-        assert_eq!(pipe.server.stream_send(1, &buf[12000..], true), Ok(12000));
-        assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::ReusedSourceConnectionId(
-                0,
-                (server_addr, client_addr),
-                (server_addr, spoofed_client_addr)
-            ))
-        );
-        assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::New(server_addr, spoofed_client_addr))
-        );
+        for max in [100, 200] {
+            let v = postcard::to_allocvec(&PluginVal::U64(max)).unwrap();
+
+            assert!(pipe
+                .client
+                .set_connection(ConnectionField::MaxTxData, &v)
+                .is_ok());
+        }
+
+        // Writes performed while running an operation are attributed to it.
+        let v = postcard::to_allocvec(&PluginVal::U64(300)).unwrap();
+        let res = plugin::guarded_call(&op, &[], || {
+            pipe.client.set_connection(ConnectionField::MaxTxData, &v)
+        });
+        assert!(res.is_ok());
+        assert_eq!(plugin::running_op(), None);
+
+        assert_eq!(pipe.client.max_tx_data, 300);
+
+        // Only the most recent writes are kept.
+        let mutations: Vec<_> = pipe.client.plugin_mutations().collect();
+        assert_eq!(mutations.len(), 2);
+
+        assert!(matches!(
+            mutations[0].field,
+            PluginField::Connection(ConnectionField::MaxTxData)
+        ));
+        assert_eq!(mutations[0].operation, None);
+        assert_eq!(mutations[0].old, Some(PluginVal::U64(100)));
+        assert_eq!(mutations[0].new, PluginVal::U64(200));
+
+        assert_eq!(mutations[1].operation, Some(op));
+        assert_eq!(mutations[1].old, Some(PluginVal::U64(200)));
+        assert_eq!(mutations[1].new, PluginVal::U64(300));
+        assert!(mutations[0].time <= mutations[1].time);
+    }
+
+    #[test]
+    #[cfg(feature = "plugins")]
+    fn load_plugin_from_file() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
 
         assert_eq!(
-            pipe.server.is_path_validated(server_addr, client_addr),
-            Ok(true)
+            config.load_plugin_from_file("examples/missing.wasm"),
+            Err(Error::InvalidState)
         );
+        assert!(config.plugin_files.is_empty());
+
+        // Files that aren't WebAssembly modules are rejected.
         assert_eq!(
-            pipe.server
-                .is_path_validated(server_addr, spoofed_client_addr),
-            Ok(false)
+            config.load_plugin_from_file("examples/cert.crt"),
+            Err(Error::InvalidState)
         );
+        assert!(config.plugin_files.is_empty());
 
-        // The client creates the PATH CHALLENGE, but it is always lost.
-        testing::emit_flight(&mut pipe.server).unwrap();
+        // An empty module, followed by a custom section.
+        let module = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03,
+            b'f', b'o', b'o',
+        ];
 
-        // Wait until probing timer expires. Since the RTT is very low,
-        // wait a bit more.
-        let probed_pid = pipe
-            .server
-            .paths
-            .path_id_from_addrs(&(server_addr, spoofed_client_addr))
-            .unwrap();
-        let probe_instant = pipe
-            .server
-            .paths
-            .get(probed_pid)
-            .unwrap()
-            .recovery
-            .loss_detection_timer()
-            .unwrap();
-        let timer = probe_instant.duration_since(time::Instant::now());
-        std::thread::sleep(timer + time::Duration::from_millis(1));
+        let dir = std::env::temp_dir();
 
-        pipe.server.on_timeout();
+        let valid = dir.join(format!("quiche-{}-valid.wasm", std::process::id()));
+        std::fs::write(&valid, module).unwrap();
 
-        // Because of the small ACK size, the server cannot send more to the
-        // client. Fallback on the previous active path.
-        assert_eq!(
-            pipe.server.path_event_next(),
-            Some(PathEvent::FailedValidation(
-                server_addr,
-                spoofed_client_addr
-            ))
-        );
+        // The section is truncated.
+        let truncated =
+            dir.join(format!("quiche-{}-truncated.wasm", std::process::id()));
+        std::fs::write(&truncated, &module[..module.len() - 1]).unwrap();
 
         assert_eq!(
-            pipe.server.is_path_validated(server_addr, client_addr),
-            Ok(true)
+            config.load_plugin_from_file(valid.to_str().unwrap()),
+            Ok(())
         );
         assert_eq!(
-            pipe.server
-                .is_path_validated(server_addr, spoofed_client_addr),
-            Ok(false)
+            config.load_plugin_from_file(truncated.to_str().unwrap()),
+            Err(Error::InvalidState)
         );
+        assert_eq!(config.plugin_files, vec![valid.clone()]);
 
-        let server_active_path = pipe.server.paths.get_active().unwrap();
-        assert_eq!(server_active_path.local_addr(), server_addr);
-        assert_eq!(server_active_path.peer_addr(), client_addr);
-        assert_eq!(pipe.advance(), Ok(()));
-        let (rcv_data_2, fin) =
-            pipe.client.stream_recv(1, &mut recv_buf).unwrap();
-        assert_eq!(fin, true);
-        assert_eq!(rcv_data_1 + rcv_data_2, DATA_BYTES);
+        std::fs::remove_file(valid).ok();
+        std::fs::remove_file(truncated).ok();
     }
 
     #[test]
-    fn consecutive_non_ack_eliciting() {
-        let mut buf = [0; 65535];
+    #[cfg(feature = "plugins")]
+    fn insert_plugin() {
+        // An empty module.
+        let module = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
 
-        let mut pipe = testing::Pipe::new().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        let path = std::env::temp_dir()
+            .join(format!("quiche-{}-insert.wasm", std::process::id()));
+        std::fs::write(&path, module).unwrap();
 
-        // Client sends a bunch of PING frames, causing server to ACK (ACKs aren't
-        // ack-eliciting)
-        let frames = [frame::Frame::Ping];
-        let pkt_type = packet::Type::Short;
-        for _ in 0..24 {
-            let len = pipe
-                .send_pkt_to_server(pkt_type, &frames, &mut buf)
-                .unwrap();
-            assert!(len > 0);
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_experiments(Experiments::from_bits(0b101));
 
-            let frames =
-                testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
-            assert!(
-                frames
-                    .iter()
-                    .all(|frame| matches!(frame, frame::Frame::ACK { .. })),
-                "ACK only"
-            );
-        }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-        // After 24 non-ack-eliciting, an ACK is explicitly elicited with a PING
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
-            .unwrap();
-        assert!(len > 0);
+        // Only pluginized connections can have plugins.
+        assert_eq!(pipe.server.insert_plugin(&path), Err(Error::InvalidState));
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
-        assert!(
-            frames
-                .iter()
-                .any(|frame| matches!(frame, frame::Frame::Ping)),
-            "found a PING"
+        // Plugins can be inserted after `pluginize()`, even when the
+        // configuration had none.
+        let mut pc = pipe.client.pluginize().unwrap();
+        let conn = pc.get_conn_mut();
+
+        assert_eq!(
+            conn.insert_plugin("examples/missing.wasm"),
+            Err(Error::InvalidState)
         );
+        assert_eq!(conn.insert_plugin(&path), Ok(()));
+
+        // The plugin doesn't implement the `experiments` operation, which is
+        // not a failure.
+        assert_eq!(conn.experiments().bits(), 0b101);
+        assert_eq!(conn.stats().plugin_failures, 0);
+
+        std::fs::remove_file(path).ok();
     }
 
     #[test]
-    fn send_ack_eliciting_causes_ping() {
-        // First establish a connection
+    fn suspend_quiesces_timers() {
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Queue a PING frame
-        pipe.server.send_ack_eliciting().unwrap();
+        assert!(pipe.client.timeout().is_some());
 
-        // Make sure ping is sent
-        let mut buf = [0; 1500];
-        let (len, _) = pipe.server.send(&mut buf).unwrap();
+        pipe.client.suspend();
+        assert!(pipe.client.is_suspended());
+        assert_eq!(pipe.client.timeout(), None);
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
-        let mut iter = frames.iter();
+        // Timers are not processed while suspended.
+        pipe.client.on_timeout();
+        assert!(!pipe.client.is_closed());
+
+        assert!(pipe.client.resume().is_some());
+        assert!(!pipe.client.is_suspended());
+        assert!(pipe.client.timeout().is_some());
 
-        assert_eq!(iter.next(), Some(&frame::Frame::Ping));
+        // Resuming twice has no effect.
+        assert_eq!(pipe.client.resume(), None);
     }
 
     #[test]
-    fn send_ack_eliciting_no_ping() {
-        // First establish a connection
+    fn resume_probes_path() {
         let mut pipe = testing::Pipe::new().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        // Queue a PING frame
-        pipe.server.send_ack_eliciting().unwrap();
-
-        // Send a stream frame, which is ACK-eliciting to make sure the ping is
-        // not sent
-        assert_eq!(pipe.server.stream_send(1, b"a", false), Ok(1));
+        pipe.server.suspend();
+        assert!(pipe.server.resume().is_some());
 
-        // Make sure ping is not sent
+        // The active path is probed with an ack-eliciting packet.
         let mut buf = [0; 1500];
         let (len, _) = pipe.server.send(&mut buf).unwrap();
 
@@ -15589,14 +28149,7 @@ mod tests {
             testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
         let mut iter = frames.iter();
 
-        assert!(matches!(
-            iter.next(),
-            Some(&frame::Frame::Stream {
-                stream_id: 1,
-                data: _
-            })
-        ));
-        assert!(iter.next().is_none());
+        assert_eq!(iter.next(), Some(&frame::Frame::Ping { mtu_probe: None }));
     }
 
     /// Tests that streams do not keep being "writable" after being collected
@@ -15792,32 +28345,129 @@ mod tests {
     }
 }
 
+pub use crate::broadcast::Broadcast;
+
+pub use crate::cidgen::ConnectionIdGenerator;
+pub use crate::cidgen::QuicLbGenerator;
+
+pub use crate::client_hello::ClientHello;
+
+pub use crate::crypto::Algorithm as CryptoAlgorithm;
+pub use crate::crypto::CryptoProvider;
+pub use crate::crypto::DefaultCryptoProvider;
+pub use crate::crypto::HeaderKey;
+pub use crate::crypto::PacketKey;
+
+pub use crate::ecn::EcnCodepoint;
+
+pub use crate::endpoint::ConnectionHandle;
+pub use crate::endpoint::Endpoint;
+pub use crate::endpoint::EndpointEvent;
+
+pub use crate::keyring::KeyRing;
+pub use crate::keyring::TicketKey;
+
+pub use crate::metrics::Counter;
+pub use crate::metrics::Gauge;
+pub use crate::metrics::Histogram;
+pub use crate::metrics::Metrics;
+
 pub use crate::packet::ConnectionId;
 pub use crate::packet::Header;
 pub use crate::packet::Type;
 
 pub use crate::path::PathEvent;
 pub use crate::path::PathStats;
+pub use crate::path::PathStatus;
 pub use crate::path::SocketAddrIter;
 
+#[cfg(feature = "plugins")]
+pub use crate::plugin::set_plugin_failure_sink;
+#[cfg(feature = "plugins")]
+pub use crate::plugin::PluginFailure;
+#[cfg(feature = "plugins")]
+pub use crate::plugin::PluginFailureReport;
+#[cfg(feature = "plugins")]
+pub use crate::plugin::PluginFailureSink;
+#[cfg(feature = "plugins")]
+pub use crate::plugin::PluginField;
+#[cfg(feature = "plugins")]
+pub use crate::plugin::PluginMutation;
+
+#[cfg(feature = "plugins")]
+pub use pluginop::PluginizableConnection;
+
+pub use crate::race::ConnectionRace;
+
+pub use crate::ratelimit::RateLimited;
+pub use crate::ratelimit::SourceOverflowCallback;
+pub use crate::ratelimit::SourceRateLimiter;
+pub use crate::ratelimit::TokenBucket;
+
+pub use crate::packet::Epoch;
+
+pub use crate::recovery::Acked;
+pub use crate::recovery::CongestionControl;
 pub use crate::recovery::CongestionControlAlgorithm;
+pub use crate::recovery::DeliveryRateObserver;
+pub use crate::recovery::DeliveryRateSample;
+pub use crate::recovery::Recovery;
 
+pub use crate::scheduler::PathCandidate;
+pub use crate::scheduler::PathScheduler;
+pub use crate::scheduler::PathSchedulerAlgorithm;
+
+pub use crate::store::ClientStore;
+pub use crate::store::MemoryClientStore;
+pub use crate::store::ServerHints;
+
+pub use crate::stream::Buf;
+pub use crate::stream::BufFactory;
+pub use crate::stream::StreamBuf;
 pub use crate::stream::StreamIter;
 
+pub use crate::tls::CertVerifier;
+pub use crate::tls::CertVerifyMode;
+
+pub use crate::token::AddressToken;
+pub use crate::token::KeyRingTokenMinter;
+pub use crate::token::TokenMinter;
+
+mod ack_freq;
+mod broadcast;
 mod cid;
+mod cidgen;
+mod client_hello;
 mod crypto;
+#[cfg_attr(not(feature = "datagram"), allow(dead_code))]
 mod dgram;
+pub mod driver;
+mod ecn;
+mod endpoint;
+mod events;
 #[cfg(feature = "ffi")]
 mod ffi;
 mod flowcontrol;
 mod frame;
+#[cfg(feature = "h3")]
 pub mod h3;
+mod keyring;
+mod metrics;
 mod minmax;
+pub mod msg;
 mod packet;
 mod path;
+#[cfg(feature = "plugins")]
 mod plugin;
+mod race;
 mod rand;
 mod ranges;
+mod ratelimit;
 mod recovery;
+mod scheduler;
+mod store;
 mod stream;
 mod tls;
+mod token;
+#[cfg(feature = "tokio")]
+pub mod tokio;
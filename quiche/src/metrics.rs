@@ -0,0 +1,134 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::sync::Arc;
+use std::time;
+
+/// A counter reported to [`Metrics`].
+///
+/// [`Metrics`]: trait.Metrics.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Counter {
+    /// QUIC packets sent.
+    PacketsSent,
+
+    /// QUIC packets received and successfully processed.
+    PacketsRecv,
+
+    /// QUIC packets declared lost.
+    PacketsLost,
+
+    /// Bytes sent in QUIC packets.
+    BytesSent,
+
+    /// Bytes received in QUIC packets.
+    BytesRecv,
+
+    /// Handshakes completed.
+    HandshakesCompleted,
+
+    /// Connections that received more duplicate STREAM bytes than the
+    /// threshold set with [`Config::set_stream_dup_alarm()`].
+    ///
+    /// [`Config::set_stream_dup_alarm()`]:
+    /// struct.Config.html#method.set_stream_dup_alarm
+    StreamDupAlarms,
+
+    /// Plugin operations that failed, because the plugin trapped or the host
+    /// panicked while running them.
+    PluginFailures,
+}
+
+/// A gauge reported to [`Metrics`].
+///
+/// [`Metrics`]: trait.Metrics.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Gauge {
+    /// Connections currently allocated.
+    Connections,
+}
+
+/// A histogram reported to [`Metrics`].
+///
+/// [`Metrics`]: trait.Metrics.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Histogram {
+    /// Time from the creation of a connection to the completion of its
+    /// handshake, in seconds.
+    HandshakeDuration,
+
+    /// Congestion window of a path, in bytes, sampled when an ACK is
+    /// received on it.
+    Cwnd,
+
+    /// Time spent in a call to a plugin operation, in seconds.
+    PluginCall,
+}
+
+/// A sink for the telemetry of all the connections of an endpoint.
+///
+/// Applications implement this trait once, for example on top of a
+/// Prometheus or OpenTelemetry exporter, and set it on the [`Config`] of their
+/// connections with [`set_metrics()`]. Connections then report their events
+/// as they happen, so there's no need to poll the [`stats()`] of each of them.
+///
+/// Methods are called from the thread driving the connection, so they should
+/// be cheap. All of them do nothing by default.
+///
+/// [`Config`]: struct.Config.html
+/// [`set_metrics()`]: struct.Config.html#method.set_metrics
+/// [`stats()`]: struct.Connection.html#method.stats
+pub trait Metrics: Send + Sync {
+    /// Increments `counter` by `v`.
+    fn incr_counter(&self, _counter: Counter, _v: u64) {}
+
+    /// Adds `delta` to `gauge`.
+    fn add_gauge(&self, _gauge: Gauge, _delta: i64) {}
+
+    /// Records the value `v` in `histogram`.
+    fn record_histogram(&self, _histogram: Histogram, _v: f64) {}
+}
+
+/// Runs `f`, and records the time it took in `histogram`.
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+pub(crate) fn timed<T>(
+    metrics: &Option<Arc<dyn Metrics>>, histogram: Histogram,
+    f: impl FnOnce() -> T,
+) -> T {
+    let metrics = match metrics {
+        Some(v) => v,
+
+        None => return f(),
+    };
+
+    let start = time::Instant::now();
+
+    let res = f();
+
+    metrics.record_histogram(histogram, start.elapsed().as_secs_f64());
+
+    res
+}
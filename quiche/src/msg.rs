@@ -0,0 +1,502 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Length-prefixed message framing over streams.
+//!
+//! This module provides message-oriented send and receive operations on top
+//! of QUIC streams. Each message is written on the stream prefixed by its
+//! length, encoded as a QUIC variable-length integer.
+//!
+//! A [`Framer`] keeps track of partially written and partially received
+//! messages for all the streams of a connection:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+//! let mut framer = quiche::msg::Framer::new(64 * 1024);
+//!
+//! framer.send(&mut conn, 0, b"hello", false)?;
+//!
+//! for stream_id in conn.readable() {
+//!     while let Ok(msg) = framer.recv(&mut conn, stream_id) {
+//!         println!("Got message of {} bytes", msg.len());
+//!     }
+//! }
+//!
+//! for stream_id in conn.writable() {
+//!     framer.flush(&mut conn, stream_id).ok();
+//! }
+//! # Ok::<(), quiche::msg::Error>(())
+//! ```
+//!
+//! [`Framer`]: struct.Framer.html
+
+use std::collections::HashMap;
+
+/// A specialized [`Result`] type for message framing operations.
+///
+/// This type is used throughout the message framing module for any operation
+/// that can produce an error.
+///
+/// [`Result`]: https://doc.rust-lang.org/std/result/enum.Result.html
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A message framing error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// There is no more work to do.
+    Done,
+
+    /// The message is larger than the maximum message size.
+    MessageTooLarge,
+
+    /// The stream was finished in the middle of a message.
+    Truncated,
+
+    /// Error originated from the transport layer.
+    TransportError(crate::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+impl std::convert::From<crate::Error> for Error {
+    fn from(err: crate::Error) -> Self {
+        match err {
+            crate::Error::Done => Error::Done,
+
+            _ => Error::TransportError(err),
+        }
+    }
+}
+
+/// The largest length prefix, in bytes.
+const MAX_PREFIX_LEN: usize = 8;
+
+/// The default number of messages of the maximum size that can be buffered
+/// for a stream.
+const DEFAULT_MAX_BUFFERED_MESSAGES: usize = 4;
+
+/// Sends and receives length-prefixed messages on the streams of a
+/// connection.
+pub struct Framer {
+    max_message_size: usize,
+
+    max_buffered: usize,
+
+    send: HashMap<u64, SendState>,
+
+    recv: HashMap<u64, RecvState>,
+}
+
+/// Encoded data not yet accepted by a stream.
+#[derive(Default)]
+struct SendState {
+    buf: Vec<u8>,
+
+    fin: bool,
+}
+
+/// The message being received on a stream.
+#[derive(Default)]
+struct RecvState {
+    /// The bytes of the length prefix until it is complete, then the bytes
+    /// of the message.
+    buf: Vec<u8>,
+
+    /// The length of the message, once its prefix has been received.
+    len: Option<usize>,
+
+    /// Whether the stream was finished.
+    fin: bool,
+}
+
+impl Framer {
+    /// Creates a new framer rejecting messages larger than
+    /// `max_message_size` bytes.
+    ///
+    /// Up to 4 times `max_message_size` bytes can be buffered for each stream
+    /// by default, see [`set_max_buffered()`].
+    ///
+    /// [`set_max_buffered()`]: struct.Framer.html#method.set_max_buffered
+    pub fn new(max_message_size: usize) -> Framer {
+        Framer {
+            max_message_size,
+
+            max_buffered: max_message_size
+                .saturating_mul(DEFAULT_MAX_BUFFERED_MESSAGES),
+
+            send: HashMap::new(),
+
+            recv: HashMap::new(),
+        }
+    }
+
+    /// Sets the maximum number of bytes buffered for each stream, including
+    /// length prefixes.
+    ///
+    /// A message is always accepted when nothing is buffered for the stream,
+    /// even if it is larger than this limit.
+    pub fn set_max_buffered(&mut self, v: usize) {
+        self.max_buffered = v;
+    }
+
+    /// Sends a message on a stream.
+    ///
+    /// The message is buffered until the stream has enough capacity to accept
+    /// it, so it is never partially lost. Buffered data is written again by
+    /// [`flush()`], which should be called when the stream becomes writable.
+    ///
+    /// If buffering the message would exceed the limit set with
+    /// [`set_max_buffered()`], nothing is sent and [`Done`] is returned. The
+    /// message can be sent again once [`flush()`] has written enough of the
+    /// buffered data.
+    ///
+    /// When `fin` is true, the stream is finished after the message. Sending
+    /// more messages on it then returns a [`TransportError`].
+    ///
+    /// [`flush()`]: struct.Framer.html#method.flush
+    /// [`set_max_buffered()`]: struct.Framer.html#method.set_max_buffered
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`TransportError`]: enum.Error.html#variant.TransportError
+    pub fn send(
+        &mut self, conn: &mut crate::Connection, stream_id: u64, msg: &[u8],
+        fin: bool,
+    ) -> Result<()> {
+        if msg.len() > self.max_message_size {
+            return Err(Error::MessageTooLarge);
+        }
+
+        let state = self.send.entry(stream_id).or_default();
+
+        if state.fin {
+            return Err(Error::TransportError(crate::Error::InvalidStreamState(
+                stream_id,
+            )));
+        }
+
+        let mut prefix = [0; MAX_PREFIX_LEN];
+        let prefix_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut prefix);
+            b.put_varint(msg.len() as u64)
+                .map_err(|_| Error::MessageTooLarge)?;
+            b.off()
+        };
+
+        if !state.buf.is_empty() &&
+            state.buf.len() + prefix_len + msg.len() > self.max_buffered
+        {
+            return Err(Error::Done);
+        }
+
+        state.buf.extend_from_slice(&prefix[..prefix_len]);
+        state.buf.extend_from_slice(msg);
+        state.fin = fin;
+
+        match self.flush(conn, stream_id) {
+            Ok(()) | Err(Error::Done) => Ok(()),
+
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the data buffered for a stream.
+    ///
+    /// On success all the buffered data was accepted by the stream. If some
+    /// data is still buffered, [`Done`] is returned.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn flush(
+        &mut self, conn: &mut crate::Connection, stream_id: u64,
+    ) -> Result<()> {
+        let state = match self.send.get_mut(&stream_id) {
+            Some(v) => v,
+
+            None => return Ok(()),
+        };
+
+        let written = match conn.stream_send(stream_id, &state.buf, state.fin) {
+            Ok(v) => v,
+
+            Err(crate::Error::Done) => 0,
+
+            Err(e) => {
+                self.send.remove(&stream_id);
+
+                return Err(e.into());
+            },
+        };
+
+        state.buf.drain(..written);
+
+        if !state.buf.is_empty() {
+            return Err(Error::Done);
+        }
+
+        // The stream is now finished.
+        if state.fin {
+            self.send.remove(&stream_id);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes buffered for a stream.
+    pub fn pending(&self, stream_id: u64) -> usize {
+        self.send.get(&stream_id).map(|s| s.buf.len()).unwrap_or(0)
+    }
+
+    /// Receives the next complete message from a stream.
+    ///
+    /// Only the bytes of the current message are read from the stream, so
+    /// messages can be received from several calls across partial reads.
+    ///
+    /// If no complete message is available, [`Done`] is returned. This is
+    /// also the case once the stream is finished after the last message. If
+    /// the stream is finished in the middle of a message, [`Truncated`] is
+    /// returned instead, and if the peer announces a message larger than the
+    /// maximum message size, [`MessageTooLarge`] is returned.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`Truncated`]: enum.Error.html#variant.Truncated
+    /// [`MessageTooLarge`]: enum.Error.html#variant.MessageTooLarge
+    pub fn recv(
+        &mut self, conn: &mut crate::Connection, stream_id: u64,
+    ) -> Result<Vec<u8>> {
+        let state = self.recv.entry(stream_id).or_default();
+
+        loop {
+            let needed = match state.len {
+                Some(len) => len - state.buf.len(),
+
+                None if state.buf.is_empty() => 1,
+
+                None => octets::varint_parse_len(state.buf[0]) - state.buf.len(),
+            };
+
+            if needed == 0 {
+                if state.len.take().is_some() {
+                    return Ok(std::mem::take(&mut state.buf));
+                }
+
+                let len = octets::Octets::with_slice(&state.buf)
+                    .get_varint()
+                    .map_err(|_| Error::Truncated)?;
+
+                if len > self.max_message_size as u64 {
+                    self.recv.remove(&stream_id);
+
+                    return Err(Error::MessageTooLarge);
+                }
+
+                state.len = Some(len as usize);
+                state.buf.clear();
+
+                continue;
+            }
+
+            if state.fin {
+                let truncated = state.len.is_some() || !state.buf.is_empty();
+
+                self.recv.remove(&stream_id);
+
+                if truncated {
+                    return Err(Error::Truncated);
+                }
+
+                return Err(Error::Done);
+            }
+
+            let off = state.buf.len();
+            state.buf.resize(off + needed, 0);
+
+            match conn.stream_recv(stream_id, &mut state.buf[off..]) {
+                Ok((read, fin)) => {
+                    state.buf.truncate(off + read);
+                    state.fin = fin;
+                },
+
+                Err(e) => {
+                    state.buf.truncate(off);
+
+                    return Err(e.into());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    #[test]
+    fn send_recv() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut client = Framer::new(100);
+        let mut server = Framer::new(100);
+
+        assert_eq!(client.send(&mut pipe.client, 0, b"hello", false), Ok(()));
+        assert_eq!(client.send(&mut pipe.client, 0, b"", false), Ok(()));
+        assert_eq!(client.send(&mut pipe.client, 0, b"world", true), Ok(()));
+        assert_eq!(client.pending(0), 0);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(server.recv(&mut pipe.server, 0), Ok(b"hello".to_vec()));
+        assert_eq!(server.recv(&mut pipe.server, 0), Ok(b"".to_vec()));
+        assert_eq!(server.recv(&mut pipe.server, 0), Ok(b"world".to_vec()));
+        assert_eq!(server.recv(&mut pipe.server, 0), Err(Error::Done));
+    }
+
+    #[test]
+    fn partial_write_and_read() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut client = Framer::new(100);
+        let mut server = Framer::new(100);
+
+        // The stream flow control limit is 15 bytes, so the second message is
+        // only partially written.
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"0123456789", false),
+            Ok(())
+        );
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"abcdefghij", false),
+            Ok(())
+        );
+        assert_eq!(client.pending(0), 7);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(server.recv(&mut pipe.server, 0), Ok(b"0123456789".to_vec()));
+        assert_eq!(server.recv(&mut pipe.server, 0), Err(Error::Done));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The rest of the message is written once the stream has capacity.
+        assert_eq!(client.flush(&mut pipe.client, 0), Ok(()));
+        assert_eq!(client.pending(0), 0);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(server.recv(&mut pipe.server, 0), Ok(b"abcdefghij".to_vec()));
+    }
+
+    #[test]
+    fn max_buffered() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut client = Framer::new(10);
+        client.set_max_buffered(20);
+
+        // The stream flow control limit is 15 bytes, so 7 bytes of the second
+        // message are buffered.
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"0123456789", false),
+            Ok(())
+        );
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"abcdefghij", false),
+            Ok(())
+        );
+        assert_eq!(client.pending(0), 7);
+
+        // Messages are buffered up to the limit.
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"klmnopqrs", false),
+            Ok(())
+        );
+        assert_eq!(client.pending(0), 17);
+
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"tuvw", false),
+            Err(Error::Done)
+        );
+        assert_eq!(client.pending(0), 17);
+
+        // Other streams have their own limit.
+        assert_eq!(
+            client.send(&mut pipe.client, 4, b"0123456789", false),
+            Ok(())
+        );
+        assert_eq!(client.pending(4), 0);
+    }
+
+    #[test]
+    fn message_too_large() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut client = Framer::new(10);
+        let mut server = Framer::new(5);
+
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"0123456789a", false),
+            Err(Error::MessageTooLarge)
+        );
+
+        assert_eq!(
+            client.send(&mut pipe.client, 0, b"0123456789", false),
+            Ok(())
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            server.recv(&mut pipe.server, 0),
+            Err(Error::MessageTooLarge)
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut server = Framer::new(100);
+
+        // A 5 bytes message with only 3 bytes before the end of the stream.
+        assert_eq!(pipe.client.stream_send(0, b"\x05abc", true), Ok(4));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(server.recv(&mut pipe.server, 0), Err(Error::Truncated));
+    }
+}
@@ -27,6 +27,7 @@
 use std::fmt::Display;
 use std::ops::Index;
 use std::ops::IndexMut;
+use std::ops::Range;
 use std::ops::RangeInclusive;
 use std::time;
 
@@ -41,7 +42,7 @@ use crate::ranges;
 use crate::stream;
 
 const FORM_BIT: u8 = 0x80;
-const FIXED_BIT: u8 = 0x40;
+pub(crate) const FIXED_BIT: u8 = 0x40;
 const KEY_PHASE_BIT: u8 = 0x04;
 
 const TYPE_MASK: u8 = 0x30;
@@ -53,10 +54,15 @@ pub const MAX_PKT_NUM_LEN: usize = 4;
 
 const SAMPLE_LEN: usize = 16;
 
+/// A packet number space.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum Epoch {
+    /// The Initial packet number space.
     Initial     = 0,
+    /// The Handshake packet number space.
     Handshake   = 1,
+    /// The Application Data packet number space, used by 0-RTT and 1-RTT
+    /// packets.
     Application = 2,
 }
 
@@ -66,11 +72,11 @@ static EPOCHS: [Epoch; 3] =
 impl Epoch {
     /// Returns an ordered slice containing the `Epoch`s that fit in the
     /// provided `range`.
-    pub fn epochs(range: RangeInclusive<Epoch>) -> &'static [Epoch] {
+    pub(crate) fn epochs(range: RangeInclusive<Epoch>) -> &'static [Epoch] {
         &EPOCHS[*range.start() as usize..=*range.end() as usize]
     }
 
-    pub const fn count() -> usize {
+    pub(crate) const fn count() -> usize {
         3
     }
 }
@@ -370,6 +376,15 @@ impl<'a> Header<'a> {
 
         let ty = if version == 0 {
             Type::VersionNegotiation
+        } else if version == crate::PROTOCOL_VERSION_V2 {
+            // QUIC v2 uses different codepoints for long header packets.
+            match (first & TYPE_MASK) >> 4 {
+                0x01 => Type::Initial,
+                0x02 => Type::ZeroRTT,
+                0x03 => Type::Handshake,
+                0x00 => Type::Retry,
+                _ => return Err(Error::InvalidPacket),
+            }
         } else {
             match (first & TYPE_MASK) >> 4 {
                 0x00 => Type::Initial,
@@ -473,6 +488,13 @@ impl<'a> Header<'a> {
             _ => return Err(Error::InvalidPacket),
         };
 
+        // QUIC v2 uses different codepoints for long header packets.
+        let ty = if self.version == crate::PROTOCOL_VERSION_V2 {
+            (ty + 1) & 0x03
+        } else {
+            ty
+        };
+
         first |= FORM_BIT | FIXED_BIT | (ty << 4);
         Ok(first)
     }
@@ -528,7 +550,7 @@ impl<'a> Header<'a> {
     /// Returns true if the packet has a long header.
     ///
     /// The `b` parameter represents the first byte of the QUIC header.
-    fn is_long(b: u8) -> bool {
+    pub(crate) fn is_long(b: u8) -> bool {
         b & FORM_BIT != 0
     }
 }
@@ -566,6 +588,54 @@ impl<'a> std::fmt::Debug for Header<'a> {
     }
 }
 
+/// Returns the position of the destination connection ID of the packet at
+/// the start of `buf`, without parsing the rest of its header.
+pub fn dcid_range(buf: &[u8], dcid_len: usize) -> Result<Range<usize>> {
+    let mut b = octets::Octets::with_slice(buf);
+
+    let first = b.get_u8()?;
+
+    let len = if Header::is_long(first) {
+        // Skip the version.
+        b.skip(4)?;
+
+        b.get_u8()? as usize
+    } else {
+        dcid_len
+    };
+
+    let start = b.off();
+
+    b.skip(len)?;
+
+    Ok(start..b.off())
+}
+
+/// Returns the length of the long header packet at the start of `buf`, so
+/// that it can be skipped without being processed.
+///
+/// The length is only known for packets of a supported version that carry an
+/// explicit length, `Error::Done` is returned for other packets.
+pub fn skip_len(buf: &mut [u8]) -> Result<usize> {
+    let mut b = octets::OctetsMut::with_slice(buf);
+
+    let hdr = Header::from_bytes(&mut b, 0)?;
+
+    if !matches!(hdr.ty, Type::Initial | Type::ZeroRTT | Type::Handshake) ||
+        !crate::version_is_supported(hdr.version)
+    {
+        return Err(Error::Done);
+    }
+
+    let len = b.get_varint()? as usize;
+
+    if len > b.cap() {
+        return Err(Error::InvalidPacket);
+    }
+
+    Ok(b.off() + len)
+}
+
 pub fn pkt_num_len(pn: u64) -> Result<usize> {
     let len = if pn < u64::from(std::u8::MAX) {
         1
@@ -660,6 +730,15 @@ pub fn decode_pkt_num(largest_pn: u64, truncated_pn: u64, pn_len: usize) -> u64
 pub fn decrypt_pkt<'a>(
     b: &'a mut octets::OctetsMut, pn: u64, pn_len: usize, payload_len: usize,
     aead: &crypto::Open,
+) -> Result<octets::Octets<'a>> {
+    decrypt_pkt_on_path(b, 0, pn, pn_len, payload_len, aead)
+}
+
+/// Decrypts a packet received on the path identified by `path_id`, as defined
+/// by the multipath extension.
+pub fn decrypt_pkt_on_path<'a>(
+    b: &'a mut octets::OctetsMut, path_id: u32, pn: u64, pn_len: usize,
+    payload_len: usize, aead: &crypto::Open,
 ) -> Result<octets::Octets<'a>> {
     let payload_offset = b.off();
 
@@ -671,8 +750,12 @@ pub fn decrypt_pkt<'a>(
 
     let mut ciphertext = payload.peek_bytes_mut(payload_len)?;
 
-    let payload_len =
-        aead.open_with_u64_counter(pn, header.as_ref(), ciphertext.as_mut())?;
+    let payload_len = aead.open_with_path_and_u64_counter(
+        path_id,
+        pn,
+        header.as_ref(),
+        ciphertext.as_mut(),
+    )?;
 
     Ok(b.get_bytes(payload_len)?)
 }
@@ -706,10 +789,31 @@ pub fn encrypt_hdr(
 pub fn encrypt_pkt(
     b: &mut octets::OctetsMut, pn: u64, pn_len: usize, payload_len: usize,
     payload_offset: usize, extra_in: Option<&[u8]>, aead: &crypto::Seal,
+) -> Result<usize> {
+    encrypt_pkt_on_path(
+        b,
+        0,
+        pn,
+        pn_len,
+        payload_len,
+        payload_offset,
+        extra_in,
+        aead,
+    )
+}
+
+/// Encrypts a packet to be sent on the path identified by `path_id`, as
+/// defined by the multipath extension.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_pkt_on_path(
+    b: &mut octets::OctetsMut, path_id: u32, pn: u64, pn_len: usize,
+    payload_len: usize, payload_offset: usize, extra_in: Option<&[u8]>,
+    aead: &crypto::Seal,
 ) -> Result<usize> {
     let (mut header, mut payload) = b.split_at(payload_offset)?;
 
-    let ciphertext_len = aead.seal_with_u64_counter(
+    let ciphertext_len = aead.seal_with_path_and_u64_counter(
+        path_id,
         pn,
         header.as_ref(),
         payload.as_mut(),
@@ -741,7 +845,7 @@ pub fn encode_pkt_num(pn: u64, b: &mut octets::OctetsMut) -> Result<()> {
 }
 
 pub fn negotiate_version(
-    scid: &[u8], dcid: &[u8], out: &mut [u8],
+    scid: &[u8], dcid: &[u8], versions: &[u32], out: &mut [u8],
 ) -> Result<usize> {
     let mut b = octets::OctetsMut::with_slice(out);
 
@@ -754,10 +858,10 @@ pub fn negotiate_version(
     b.put_bytes(scid)?;
     b.put_u8(dcid.len() as u8)?;
     b.put_bytes(dcid)?;
-    b.put_u32(crate::PROTOCOL_VERSION_V1)?;
-    b.put_u32(crate::PROTOCOL_VERSION_DRAFT29)?;
-    b.put_u32(crate::PROTOCOL_VERSION_DRAFT28)?;
-    b.put_u32(crate::PROTOCOL_VERSION_DRAFT27)?;
+
+    for &v in versions {
+        b.put_u32(v)?;
+    }
 
     Ok(b.off())
 }
@@ -819,6 +923,15 @@ fn compute_retry_integrity_tag(
         0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
     ];
 
+    const RETRY_INTEGRITY_KEY_V2: [u8; 16] = [
+        0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce,
+        0xad, 0x7c, 0xcc, 0x92,
+    ];
+
+    const RETRY_INTEGRITY_NONCE_V2: [u8; aead::NONCE_LEN] = [
+        0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a,
+    ];
+
     const RETRY_INTEGRITY_KEY_DRAFT29: [u8; 16] = [
         0xcc, 0xce, 0x18, 0x7e, 0xd0, 0x9a, 0x09, 0xd0, 0x57, 0x28, 0x15, 0x5a,
         0x6c, 0xb9, 0x6b, 0xe1,
@@ -844,6 +957,9 @@ fn compute_retry_integrity_tag(
         crate::PROTOCOL_VERSION_DRAFT29 =>
             (&RETRY_INTEGRITY_KEY_DRAFT29, RETRY_INTEGRITY_NONCE_DRAFT29),
 
+        crate::PROTOCOL_VERSION_V2 =>
+            (&RETRY_INTEGRITY_KEY_V2, RETRY_INTEGRITY_NONCE_V2),
+
         _ => (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
     };
 
@@ -870,6 +986,35 @@ fn compute_retry_integrity_tag(
         .map_err(|_| Error::CryptoFail)
 }
 
+/// State of the most recent 1-RTT key update.
+pub struct KeyUpdate {
+    /// The packet protection key of the previous key phase, kept to decrypt
+    /// reordered packets.
+    pub crypto_open: crypto::Open,
+
+    /// The lowest packet number received with the current keys. Packets with
+    /// the previous key phase and a lower packet number are decrypted with
+    /// `crypto_open`.
+    pub pn_on_update: u64,
+
+    /// The first packet number sent with the current keys.
+    pub pn_sent_on_update: u64,
+
+    /// Whether the update was initiated by the local endpoint.
+    pub local: bool,
+
+    /// Whether a packet sent with the current keys was acknowledged, which
+    /// confirms the update.
+    pub update_acked: bool,
+
+    /// Whether an ACK frame was sent with the current keys. Until then, the
+    /// peer must not update its keys again.
+    pub ack_sent: bool,
+
+    /// When the previous keys are discarded, once the update is confirmed.
+    pub timer: Option<time::Instant>,
+}
+
 pub struct PktNumSpace {
     pub largest_rx_pkt_num: u64,
 
@@ -891,7 +1036,11 @@ pub struct PktNumSpace {
     pub crypto_0rtt_open: Option<crypto::Open>,
     pub crypto_0rtt_seal: Option<crypto::Seal>,
 
+    pub key_update: Option<KeyUpdate>,
+
     pub crypto_stream: stream::Stream,
+
+    pub keys_discarded: bool,
 }
 
 impl PktNumSpace {
@@ -917,6 +1066,8 @@ impl PktNumSpace {
             crypto_0rtt_open: None,
             crypto_0rtt_seal: None,
 
+            key_update: None,
+
             crypto_stream: stream::Stream::new(
                 std::u64::MAX,
                 std::u64::MAX,
@@ -924,6 +1075,8 @@ impl PktNumSpace {
                 true,
                 stream::MAX_STREAM_WINDOW,
             ),
+
+            keys_discarded: false,
         }
     }
 
@@ -1056,6 +1209,39 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn long_header_v2() {
+        let types = [
+            (Type::Initial, 0x10, Some(vec![])),
+            (Type::ZeroRTT, 0x20, None),
+            (Type::Handshake, 0x30, None),
+        ];
+
+        for (ty, bits, token) in types {
+            let hdr = Header {
+                ty,
+                version: crate::PROTOCOL_VERSION_V2,
+                dcid: vec![0xba, 0xba, 0xba, 0xba].into(),
+                scid: vec![0xbb, 0xbb, 0xbb, 0xbb].into(),
+                pkt_num: 0,
+                pkt_num_len: 0,
+                token,
+                versions: None,
+                key_phase: false,
+            };
+
+            let mut d = [0; 50];
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            assert!(hdr.to_bytes(&mut b).is_ok());
+
+            assert_eq!(d[0] & TYPE_MASK, bits);
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            assert_eq!(Header::from_bytes(&mut b, 4).unwrap(), hdr);
+        }
+    }
+
     #[test]
     fn initial_v1_dcid_too_long() {
         let hdr = Header {
@@ -1310,9 +1496,13 @@ mod tests {
 
         let payload_len = b.get_varint().unwrap() as usize;
 
-        let (aead, _) =
-            crypto::derive_initial_key_material(dcid, hdr.version, is_server)
-                .unwrap();
+        let (aead, _) = crypto::derive_initial_key_material(
+            &crypto::default_provider(),
+            dcid,
+            hdr.version,
+            is_server,
+        )
+        .unwrap();
 
         decrypt_hdr(&mut b, &mut hdr, &aead).unwrap();
         assert_eq!(hdr.pkt_num_len, expected_pn_len);
@@ -1879,7 +2069,13 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Open::from_secret(alg, &secret).unwrap();
+        let aead = crypto::Open::from_secret(
+            &crypto::default_provider(),
+            alg,
+            crate::PROTOCOL_VERSION_V1,
+            &secret,
+        )
+        .unwrap();
 
         let mut hdr = Header::from_bytes(&mut b, 0).unwrap();
         assert_eq!(hdr.ty, Type::Short);
@@ -1913,9 +2109,13 @@ mod tests {
 
         b.put_bytes(header).unwrap();
 
-        let (_, aead) =
-            crypto::derive_initial_key_material(dcid, hdr.version, is_server)
-                .unwrap();
+        let (_, aead) = crypto::derive_initial_key_material(
+            &crypto::default_provider(),
+            dcid,
+            hdr.version,
+            is_server,
+        )
+        .unwrap();
 
         let payload_len = frames.len();
 
@@ -2811,7 +3011,13 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Seal::from_secret(alg, &secret).unwrap();
+        let aead = crypto::Seal::from_secret(
+            &crypto::default_provider(),
+            alg,
+            crate::PROTOCOL_VERSION_V1,
+            &secret,
+        )
+        .unwrap();
 
         let pn = 654_360_564;
         let pn_len = 3;
@@ -2862,8 +3068,13 @@ mod tests {
 
         let payload_len = b.get_varint().unwrap() as usize;
 
-        let (aead, _) =
-            crypto::derive_initial_key_material(b"", hdr.version, true).unwrap();
+        let (aead, _) = crypto::derive_initial_key_material(
+            &crypto::default_provider(),
+            b"",
+            hdr.version,
+            true,
+        )
+        .unwrap();
 
         assert_eq!(
             decrypt_pkt(&mut b, 0, 1, payload_len, &aead),
@@ -2895,8 +3106,13 @@ mod tests {
         // No space for decryption.
         let payload_len = 1;
 
-        let (aead, _) =
-            crypto::derive_initial_key_material(b"", hdr.version, true).unwrap();
+        let (aead, _) = crypto::derive_initial_key_material(
+            &crypto::default_provider(),
+            b"",
+            hdr.version,
+            true,
+        )
+        .unwrap();
 
         assert_eq!(
             decrypt_pkt(&mut b, 0, 1, payload_len, &aead),
@@ -35,9 +35,27 @@ use slab::Slab;
 use crate::Error;
 use crate::Result;
 
+use crate::events::EventQueue;
 use crate::recovery;
+use crate::recovery::DeliveryRateSample;
 use crate::recovery::HandshakeStatus;
 
+// The number of lost probes of a given size after which the size is deemed
+// not supported by the path (MAX_PROBES in RFC 8899).
+const PMTUD_MAX_PROBES: usize = 3;
+
+// The search for the PMTU stops when the largest size known to work and the
+// smallest size known to fail are this close.
+const PMTUD_SEARCH_GRANULARITY: usize = 16;
+
+// How long to wait after the search for the PMTU completed before searching
+// for a larger PMTU (PMTU_RAISE_TIMER in RFC 8899).
+const PMTUD_RAISE_TIMER: time::Duration = time::Duration::from_secs(600);
+
+// The number of consecutive lost packets larger than the minimum QUIC packet
+// size after which the PMTU is deemed to have decreased.
+const PMTUD_BLACK_HOLE_THRESHOLD: usize = 6;
+
 /// The different states of the path validation.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PathState {
@@ -70,6 +88,38 @@ impl PathState {
     }
 }
 
+/// The status of a path, as advertised by the multipath extension.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub enum PathStatus {
+    /// The path should only be used to send non-probing packets if no other
+    /// path is available.
+    Standby,
+
+    /// The path can be used to send non-probing packets.
+    Available,
+}
+
+impl PathStatus {
+    pub(crate) fn from_wire(v: u64) -> Result<PathStatus> {
+        match v {
+            1 => Ok(PathStatus::Standby),
+
+            2 => Ok(PathStatus::Available),
+
+            _ => Err(Error::InvalidFrame),
+        }
+    }
+
+    pub(crate) fn to_wire(self) -> u64 {
+        match self {
+            PathStatus::Standby => 1,
+
+            PathStatus::Available => 2,
+        }
+    }
+}
+
 /// A path-specific event.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PathEvent {
@@ -190,6 +240,30 @@ pub struct Path {
 
     /// Whether or not we should force eliciting of an ACK (e.g. via PING frame)
     pub needs_ack_eliciting: bool,
+
+    /// The status advertised to the peer with the multipath extension.
+    local_status: PathStatus,
+
+    /// The sequence number of the last advertised local status.
+    local_status_seq: u64,
+
+    /// Whether the local status needs to be advertised.
+    local_status_pending: bool,
+
+    /// The status advertised by the peer with the multipath extension.
+    peer_status: PathStatus,
+
+    /// The sequence number of the last status received from the peer.
+    peer_status_seq: Option<u64>,
+
+    /// Whether the path has been abandoned by either endpoint.
+    abandoned: bool,
+
+    /// The error code and reason of the PATH_ABANDON frame to be sent, if any.
+    abandon_pending: Option<(u64, Vec<u8>)>,
+
+    /// The path MTU discovery state.
+    pub(crate) pmtud: Pmtud,
 }
 
 impl Path {
@@ -207,6 +281,11 @@ impl Path {
 
         let recovery = recovery::Recovery::new_with_config(recovery_config);
 
+        let pmtud = Pmtud::new(
+            recovery_config.pmtud,
+            recovery_config.max_send_udp_payload_size,
+        );
+
         Self {
             local_addr,
             peer_addr,
@@ -233,6 +312,14 @@ impl Path {
             failure_notified: false,
             migrating: false,
             needs_ack_eliciting: false,
+            local_status: PathStatus::Available,
+            local_status_seq: 0,
+            local_status_pending: false,
+            peer_status: PathStatus::Available,
+            peer_status_seq: None,
+            abandoned: false,
+            abandon_pending: None,
+            pmtud,
         }
     }
 
@@ -248,10 +335,10 @@ impl Path {
         self.peer_addr
     }
 
-    /// Returns whether the path is working (i.e., not failed).
+    /// Returns whether the path is working (i.e., not failed nor abandoned).
     #[inline]
     fn working(&self) -> bool {
-        self.state > PathState::Failed
+        self.state > PathState::Failed && !self.abandoned
     }
 
     /// Returns whether the path is active.
@@ -265,7 +352,8 @@ impl Path {
     pub fn usable(&self) -> bool {
         self.active() ||
             (self.state == PathState::Validated &&
-                self.active_dcid_seq.is_some())
+                self.active_dcid_seq.is_some() &&
+                !self.abandoned)
     }
 
     /// Returns whether the path is unused.
@@ -387,6 +475,83 @@ impl Path {
         self.received_challenges.pop_front()
     }
 
+    /// Returns the status of the path used to schedule packets.
+    ///
+    /// The path is on standby if any of the endpoints advertised it as such.
+    #[inline]
+    pub fn status(&self) -> PathStatus {
+        if self.local_status == PathStatus::Standby ||
+            self.peer_status == PathStatus::Standby
+        {
+            return PathStatus::Standby;
+        }
+
+        PathStatus::Available
+    }
+
+    /// Sets the status to be advertised to the peer.
+    pub fn set_local_status(&mut self, status: PathStatus) {
+        self.local_status = status;
+        self.local_status_seq += 1;
+        self.local_status_pending = true;
+    }
+
+    /// Returns the sequence number and the value of the local status if it
+    /// needs to be advertised.
+    #[inline]
+    pub fn local_status_to_send(&self) -> Option<(u64, PathStatus)> {
+        if !self.local_status_pending || self.abandoned {
+            return None;
+        }
+
+        Some((self.local_status_seq, self.local_status))
+    }
+
+    /// Handles the sending of a PATH_STATUS frame.
+    ///
+    /// When `lost` is `true`, the frame is scheduled for retransmission if it
+    /// still carries the latest local status.
+    pub fn on_local_status_sent(&mut self, seq_num: u64, lost: bool) {
+        if seq_num == self.local_status_seq {
+            self.local_status_pending = lost;
+        }
+    }
+
+    /// Handles incoming PATH_STATUS frames. Outdated ones are ignored.
+    pub fn on_status_received(&mut self, seq_num: u64, status: PathStatus) {
+        if self.peer_status_seq.map_or(true, |s| seq_num > s) {
+            self.peer_status = status;
+            self.peer_status_seq = Some(seq_num);
+        }
+    }
+
+    /// Returns whether the path has been abandoned.
+    #[inline]
+    pub fn abandoned(&self) -> bool {
+        self.abandoned
+    }
+
+    /// Abandons the path locally and schedules a PATH_ABANDON frame carrying
+    /// the provided `error_code` and `reason`.
+    pub fn abandon(&mut self, error_code: u64, reason: Vec<u8>) {
+        self.on_abandoned();
+        self.abandon_pending = Some((error_code, reason));
+    }
+
+    /// Returns the PATH_ABANDON error code and reason to be sent, if any.
+    #[inline]
+    pub fn pop_abandon_to_send(&mut self) -> Option<(u64, Vec<u8>)> {
+        self.abandon_pending.take()
+    }
+
+    /// Marks the path as abandoned. An abandoned path is never used again.
+    pub fn on_abandoned(&mut self) {
+        self.abandoned = true;
+        self.active = false;
+        self.challenge_requested = false;
+        self.received_challenges.clear();
+    }
+
     pub fn on_loss_detection_timeout(
         &mut self, handshake_status: HandshakeStatus, now: time::Instant,
         is_server: bool, trace_id: &str,
@@ -453,14 +618,177 @@ impl Path {
             lost: self.recovery.lost_count,
             retrans: self.retrans_count,
             rtt: self.recovery.rtt(),
+            latest_rtt: self.recovery.latest_rtt(),
+            rttvar: self.recovery.rttvar(),
+            min_rtt: self.recovery.min_rtt(),
+            min_rtt_refreshes: self.recovery.min_rtt_refreshes(),
             cwnd: self.recovery.cwnd(),
+            bytes_in_flight: self.recovery.bytes_in_flight,
+            cwnd_limited: self.recovery.cwnd_limited_count(),
+            pto_count: self.recovery.pto_count(),
+            pacing_rate: self.recovery.pacing_rate(),
+            persistent_congestion: self.recovery.persistent_congestion_count(),
+            loss_bursts: self.recovery.loss_bursts(),
             sent_bytes: self.sent_bytes,
             recv_bytes: self.recv_bytes,
             lost_bytes: self.recovery.bytes_lost,
             stream_retrans_bytes: self.stream_retrans_bytes,
             pmtu: self.recovery.max_datagram_size(),
             delivery_rate: self.recovery.delivery_rate(),
+            delivery_rate_sample: self.recovery.delivery_rate_sample(),
+            anti_amplification_allowance: None,
+        }
+    }
+
+    /// Limits the size of the PMTU probes to the given size, e.g. as
+    /// advertised by the peer.
+    pub fn update_max_pmtu(&mut self, max: usize) {
+        self.pmtud.update_max(max);
+    }
+
+    /// Updates the size of the packets sent on the path to the discovered
+    /// PMTU, and returns the size of the PMTU probe to send, if any.
+    ///
+    /// When the PMTU decreased, the size of the packets sent on the path falls
+    /// back to the minimum QUIC packet size and the search starts again.
+    pub fn update_pmtu(&mut self, now: time::Instant) -> Option<usize> {
+        if !self.pmtud.enabled {
+            return None;
+        }
+
+        if self.recovery.large_pkts_lost >= PMTUD_BLACK_HOLE_THRESHOLD {
+            self.recovery.large_pkts_lost = 0;
+            self.pmtud.on_black_hole();
+        }
+
+        if self.recovery.max_datagram_size() != self.pmtud.current {
+            self.recovery.set_max_datagram_size(self.pmtud.current);
+        }
+
+        if !self.active() || !self.validated() {
+            return None;
+        }
+
+        self.pmtud.probe_size(now)
+    }
+}
+
+/// Datagram Packetization Layer Path MTU Discovery (DPLPMTUD) state, as
+/// described in RFC 8899.
+///
+/// The search starts from the minimum QUIC packet size, first probing the
+/// largest allowed size, then doing a binary search between the largest size
+/// known to work and the smallest size known to fail.
+pub(crate) struct Pmtud {
+    /// Whether PMTU discovery is enabled.
+    enabled: bool,
+
+    /// The largest size known to be supported by the path.
+    current: usize,
+
+    /// The largest size that can be probed.
+    max: usize,
+
+    /// The smallest size known not to be supported by the path, if any.
+    failed: Option<usize>,
+
+    /// The size of the probe in flight, if any.
+    in_flight: Option<usize>,
+
+    /// The number of lost probes of the next probe size.
+    lost_probes: usize,
+
+    /// When to search for a larger PMTU again, once the search completed.
+    raise_time: Option<time::Instant>,
+}
+
+impl Pmtud {
+    fn new(enabled: bool, max: usize) -> Self {
+        Pmtud {
+            enabled,
+            current: crate::MIN_CLIENT_INITIAL_LEN,
+            max,
+            failed: None,
+            in_flight: None,
+            lost_probes: 0,
+            raise_time: None,
+        }
+    }
+
+    fn update_max(&mut self, max: usize) {
+        self.max = std::cmp::min(self.max, max);
+    }
+
+    /// Returns the size of the next probe, if the search isn't complete.
+    fn next_probe_size(&self) -> Option<usize> {
+        let high = self.failed.unwrap_or(self.max + 1);
+
+        if high.saturating_sub(self.current) <= PMTUD_SEARCH_GRANULARITY {
+            return None;
+        }
+
+        match self.failed {
+            Some(failed) => Some((self.current + failed) / 2),
+
+            None => Some(self.max),
+        }
+    }
+
+    fn probe_size(&mut self, now: time::Instant) -> Option<usize> {
+        if self.in_flight.is_some() {
+            return None;
+        }
+
+        if let Some(raise_time) = self.raise_time {
+            if now < raise_time {
+                return None;
+            }
+
+            // Sizes that failed before might be supported now.
+            self.raise_time = None;
+            self.failed = None;
+        }
+
+        let size = self.next_probe_size();
+
+        if size.is_none() {
+            self.raise_time = Some(now + PMTUD_RAISE_TIMER);
+        }
+
+        size
+    }
+
+    pub(crate) fn on_probe_sent(&mut self, size: usize) {
+        self.in_flight = Some(size);
+    }
+
+    pub(crate) fn on_probe_acked(&mut self, size: usize) {
+        self.in_flight = None;
+        self.lost_probes = 0;
+
+        self.current = std::cmp::max(self.current, size);
+    }
+
+    pub(crate) fn on_probe_lost(&mut self, size: usize) {
+        self.in_flight = None;
+        self.lost_probes += 1;
+
+        if self.lost_probes >= PMTUD_MAX_PROBES {
+            self.lost_probes = 0;
+
+            let failed = self.failed.map_or(size, |f| std::cmp::min(f, size));
+            self.failed = Some(failed);
+        }
+    }
+
+    fn on_black_hole(&mut self) {
+        if self.current > crate::MIN_CLIENT_INITIAL_LEN {
+            self.failed = Some(self.current);
         }
+
+        self.current = crate::MIN_CLIENT_INITIAL_LEN;
+        self.lost_probes = 0;
+        self.raise_time = None;
     }
 }
 
@@ -500,10 +828,14 @@ pub struct PathMap {
     addrs_to_paths: BTreeMap<(SocketAddr, SocketAddr), usize>,
 
     /// Path-specific events to be notified to the application.
-    events: VecDeque<PathEvent>,
+    events: EventQueue<PathEvent>,
 
     /// Whether this manager serves a connection as a server.
     is_server: bool,
+
+    /// Whether several paths can be active at the same time, as allowed by the
+    /// multipath extension.
+    multipath: bool,
 }
 
 impl PathMap {
@@ -528,8 +860,9 @@ impl PathMap {
             paths,
             max_concurrent_paths,
             addrs_to_paths,
-            events: VecDeque::new(),
+            events: EventQueue::new(crate::DEFAULT_MAX_QUEUED_EVENTS),
             is_server,
+            multipath: false,
         }
     }
 
@@ -675,12 +1008,12 @@ impl PathMap {
 
     /// Notifies a path event to the application served by the connection.
     pub fn notify_event(&mut self, ev: PathEvent) {
-        self.events.push_back(ev);
+        self.events.push(ev);
     }
 
     /// Gets the first path event to be notified to the application.
     pub fn pop_event(&mut self) -> Option<PathEvent> {
-        self.events.pop_front()
+        self.events.pop()
     }
 
     /// Notifies all failed validations to the application.
@@ -691,10 +1024,8 @@ impl PathMap {
             .filter(|(_, p)| p.validation_failed() && !p.failure_notified);
 
         for (_, p) in validation_failed {
-            self.events.push_back(PathEvent::FailedValidation(
-                p.local_addr,
-                p.peer_addr,
-            ));
+            self.events
+                .push(PathEvent::FailedValidation(p.local_addr, p.peer_addr));
 
             p.failure_notified = true;
         }
@@ -709,9 +1040,56 @@ impl PathMap {
             .map(|(pid, _)| pid)
     }
 
+    /// Sets whether several paths can be active at the same time.
+    ///
+    /// When enabled, paths become active as soon as they are validated.
+    pub fn set_multipath(&mut self, v: bool) {
+        self.multipath = v;
+    }
+
+    /// Sets the maximum number of path events queued for the application.
+    pub fn set_max_events(&mut self, v: usize) {
+        self.events.set_max_len(v);
+    }
+
+    /// Returns the number of path events dropped because too many of them
+    /// were queued.
+    pub fn dropped_events(&self) -> usize {
+        self.events.dropped()
+    }
+
+    /// Returns the identifier of the path whose Destination Connection ID has
+    /// the provided sequence number, if any.
+    pub fn path_id_from_dcid_seq(&self, dcid_seq: u64) -> Option<usize> {
+        self.paths
+            .iter()
+            .find(|(_, p)| p.active_dcid_seq == Some(dcid_seq))
+            .map(|(pid, _)| pid)
+    }
+
+    /// Handles an incoming PATH_ABANDON frame for the path identified by
+    /// `path_id`, and notifies the application that it got closed.
+    pub fn on_path_abandoned(&mut self, path_id: usize) -> Result<()> {
+        let path = self.get_mut(path_id)?;
+
+        if path.abandoned {
+            return Ok(());
+        }
+
+        path.on_abandoned();
+
+        let local_addr = path.local_addr;
+        let peer_addr = path.peer_addr;
+
+        self.notify_event(PathEvent::Closed(local_addr, peer_addr));
+
+        Ok(())
+    }
+
     /// Handles incoming PATH_RESPONSE data.
     pub fn on_response_received(&mut self, data: [u8; 8]) -> Result<()> {
         let active_pid = self.get_active_path_id()?;
+        let multipath = self.multipath;
 
         let challenge_pending =
             self.iter_mut().find(|(_, p)| p.has_pending_challenge(data));
@@ -724,6 +1102,12 @@ impl PathMap {
 
                 p.migrating = false;
 
+                // With the multipath extension, validated paths are used
+                // alongside the other active paths.
+                if multipath && !p.abandoned {
+                    p.active = true;
+                }
+
                 // Notifies the application.
                 self.notify_event(PathEvent::Validated(local_addr, peer_addr));
 
@@ -835,9 +1219,45 @@ pub struct PathStats {
     /// The estimated round-trip time of the connection.
     pub rtt: time::Duration,
 
+    /// The most recent round-trip time sample of the path.
+    pub latest_rtt: time::Duration,
+
+    /// The round-trip time variation of the path.
+    pub rttvar: time::Duration,
+
+    /// The minimum round-trip time observed on the path, within the window
+    /// set with [`set_min_rtt_expiry()`].
+    ///
+    /// [`set_min_rtt_expiry()`]: struct.Config.html#method.set_min_rtt_expiry
+    pub min_rtt: time::Duration,
+
+    /// The number of times the minimum round-trip time was refreshed after
+    /// older samples expired.
+    pub min_rtt_refreshes: u64,
+
     /// The size of the connection's congestion window in bytes.
     pub cwnd: usize,
 
+    /// The number of bytes sent and not yet acknowledged or declared lost.
+    pub bytes_in_flight: usize,
+
+    /// The number of times sending became limited by the congestion window.
+    pub cwnd_limited: u64,
+
+    /// The number of consecutive probe timeouts (PTO) that fired without
+    /// receiving an acknowledgment.
+    pub pto_count: u32,
+
+    /// The current pacing rate in bytes/s.
+    pub pacing_rate: u64,
+
+    /// The number of persistent congestion episodes, after which the
+    /// congestion window collapsed to its minimum.
+    pub persistent_congestion: u64,
+
+    /// The number of bursts of two or more consecutive packets declared lost.
+    pub loss_bursts: u64,
+
     /// The number of sent bytes.
     pub sent_bytes: u64,
 
@@ -862,6 +1282,17 @@ pub struct PathStats {
     /// [`SendInfo.at`]: struct.SendInfo.html#structfield.at
     /// [Pacing]: index.html#pacing
     pub delivery_rate: u64,
+
+    /// The most recent delivery rate sample, including whether it was taken
+    /// while application-limited and the interval it was measured over.
+    pub delivery_rate_sample: DeliveryRateSample,
+
+    /// The number of bytes that can still be sent on the path before the
+    /// peer's address is validated, when the anti-amplification limit
+    /// applies.
+    ///
+    /// This is `None` once the address is validated, and on clients.
+    pub anti_amplification_allowance: Option<usize>,
 }
 
 impl std::fmt::Debug for PathStats {
@@ -893,7 +1324,37 @@ impl std::fmt::Debug for PathStats {
             f,
             " stream_retrans_bytes={} pmtu={} delivery_rate={}",
             self.stream_retrans_bytes, self.pmtu, self.delivery_rate,
-        )
+        )?;
+
+        write!(
+            f,
+            " min_rtt={:?} min_rtt_refreshes={}",
+            self.min_rtt, self.min_rtt_refreshes,
+        )?;
+
+        write!(
+            f,
+            " latest_rtt={:?} rttvar={:?} pto_count={}",
+            self.latest_rtt, self.rttvar, self.pto_count,
+        )?;
+
+        write!(
+            f,
+            " bytes_in_flight={} pacing_rate={} cwnd_limited={}",
+            self.bytes_in_flight, self.pacing_rate, self.cwnd_limited,
+        )?;
+
+        write!(
+            f,
+            " persistent_congestion={} loss_bursts={}",
+            self.persistent_congestion, self.loss_bursts,
+        )?;
+
+        if let Some(allowance) = self.anti_amplification_allowance {
+            write!(f, " anti_amplification_allowance={allowance}")?;
+        }
+
+        Ok(())
     }
 }
 
@@ -1059,4 +1520,86 @@ mod tests {
             0
         );
     }
+
+    #[test]
+    fn pmtud_search() {
+        let mut pmtud = Pmtud::new(true, 1500);
+        let now = time::Instant::now();
+
+        // The largest allowed size is probed first, one probe at a time.
+        assert_eq!(pmtud.probe_size(now), Some(1500));
+        pmtud.on_probe_sent(1500);
+        assert_eq!(pmtud.probe_size(now), None);
+        pmtud.on_probe_lost(1500);
+
+        // It is only deemed unsupported after several lost probes.
+        for _ in 1..PMTUD_MAX_PROBES {
+            assert_eq!(pmtud.probe_size(now), Some(1500));
+            pmtud.on_probe_sent(1500);
+            pmtud.on_probe_lost(1500);
+        }
+
+        // Binary search between the known working and failed sizes.
+        assert_eq!(pmtud.probe_size(now), Some(1350));
+        pmtud.on_probe_sent(1350);
+        pmtud.on_probe_acked(1350);
+        assert_eq!(pmtud.current, 1350);
+
+        for _ in 0..PMTUD_MAX_PROBES {
+            assert_eq!(pmtud.probe_size(now), Some(1425));
+            pmtud.on_probe_sent(1425);
+            pmtud.on_probe_lost(1425);
+        }
+
+        assert_eq!(pmtud.probe_size(now), Some(1387));
+        pmtud.on_probe_sent(1387);
+        pmtud.on_probe_acked(1387);
+
+        assert_eq!(pmtud.probe_size(now), Some(1406));
+        pmtud.on_probe_sent(1406);
+        pmtud.on_probe_acked(1406);
+
+        assert_eq!(pmtud.probe_size(now), Some(1415));
+        pmtud.on_probe_sent(1415);
+        pmtud.on_probe_acked(1415);
+
+        // The search is complete.
+        assert_eq!(pmtud.probe_size(now), None);
+        assert_eq!(pmtud.current, 1415);
+
+        // Until the raise timer expires.
+        assert_eq!(pmtud.probe_size(now + PMTUD_RAISE_TIMER), Some(1500));
+    }
+
+    #[test]
+    fn pmtud_black_hole() {
+        let config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        let mut recovery_config = RecoveryConfig::from_config(&config);
+        recovery_config.pmtud = true;
+        recovery_config.max_send_udp_payload_size = 1500;
+
+        let client_addr = "127.0.0.1:1234".parse().unwrap();
+        let server_addr = "127.0.0.1:4321".parse().unwrap();
+
+        let mut path =
+            Path::new(client_addr, server_addr, &recovery_config, true);
+        path.active = true;
+
+        let now = time::Instant::now();
+
+        assert_eq!(path.recovery.max_datagram_size(), MIN_CLIENT_INITIAL_LEN);
+
+        assert_eq!(path.update_pmtu(now), Some(1500));
+        path.pmtud.on_probe_sent(1500);
+        path.pmtud.on_probe_acked(1500);
+
+        assert_eq!(path.update_pmtu(now), None);
+        assert_eq!(path.recovery.max_datagram_size(), 1500);
+
+        // Large packets stop going through.
+        path.recovery.large_pkts_lost = PMTUD_BLACK_HOLE_THRESHOLD;
+
+        assert_eq!(path.update_pmtu(now), Some(1350));
+        assert_eq!(path.recovery.max_datagram_size(), MIN_CLIENT_INITIAL_LEN);
+    }
 }
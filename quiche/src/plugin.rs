@@ -1,13 +1,21 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
 use std::convert::TryInto;
+use std::fmt;
+use std::panic;
+use std::ptr;
+use std::sync::atomic;
+use std::sync::atomic::AtomicPtr;
+use std::time;
 
 use pluginop::api::CTPError;
 use pluginop::api::ConnectionToPlugin;
 use pluginop::api::ToPluginizableConnection;
 use pluginop::common::quic;
 use pluginop::common::quic::ACKFrame;
-use pluginop::common::quic::ConnectionCloseFrame;
 use pluginop::common::quic::ConnectionField;
-use pluginop::common::quic::CryptoFrame;
 use pluginop::common::quic::DataBlockedFrame;
 use pluginop::common::quic::ExtensionFrame;
 use pluginop::common::quic::HandshakeDoneFrame;
@@ -24,9 +32,9 @@ use pluginop::common::quic::ResetStreamFrame;
 use pluginop::common::quic::RetireConnectionIdFrame;
 use pluginop::common::quic::StopSendingFrame;
 use pluginop::common::quic::StreamDataBlockedFrame;
-use pluginop::common::quic::StreamFrame;
 use pluginop::common::quic::StreamsBlockedFrame;
 use pluginop::common::Bytes;
+use pluginop::common::PluginOp;
 use pluginop::common::PluginVal;
 use pluginop::FromWithPH;
 use pluginop::ParentReferencer;
@@ -38,9 +46,22 @@ use crate::packet;
 
 impl pluginop::api::ConnectionToPlugin for crate::Connection {
     fn get_recovery<'a>(
-        &self, _: RecoveryField, _: &'a mut [u8],
+        &self, field: RecoveryField, w: &'a mut [u8],
     ) -> postcard::Result<&'a mut [u8]> {
-        todo!("find the right recovery")
+        let recovery = match self.paths.get_active() {
+            Ok(p) => &p.recovery,
+
+            Err(_) => return Err(postcard::Error::SerdeSerCustom),
+        };
+
+        let pv: PluginVal = match field {
+            RecoveryField::CongestionWindow => recovery.congestion_window.into(),
+            RecoveryField::Ssthresh => recovery.ssthresh.into(),
+            RecoveryField::MinRtt => recovery.min_rtt().into(),
+            // The other fields can't be read yet.
+            _ => return Err(postcard::Error::SerdeSerCustom),
+        };
+        postcard::to_slice(&pv, w)
     }
 
     fn set_recovery(
@@ -51,15 +72,34 @@ impl pluginop::api::ConnectionToPlugin for crate::Connection {
         warn!("Assuming recovery of default active path");
         if let Ok(p) = self.paths.get_active_mut() {
             let recovery = &mut p.recovery;
-            match field {
-                RecoveryField::CongestionWindow =>
+            let (old, new): (PluginVal, PluginVal) = match &field {
+                RecoveryField::CongestionWindow => {
+                    let old = recovery.congestion_window;
                     recovery.congestion_window =
-                        pv.try_into().map_err(|_| CTPError::BadType)?,
-                RecoveryField::Ssthresh =>
+                        pv.try_into().map_err(|_| CTPError::BadType)?;
+                    (old.into(), recovery.congestion_window.into())
+                },
+                RecoveryField::Ssthresh => {
+                    let old = recovery.ssthresh;
                     recovery.ssthresh =
-                        pv.try_into().map_err(|_| CTPError::BadType)?,
-                rf => todo!("cannot set recovery field yet: {rf:?}"),
+                        pv.try_into().map_err(|_| CTPError::BadType)?;
+                    (old.into(), recovery.ssthresh.into())
+                },
+                RecoveryField::MinRtt => {
+                    let old = recovery.min_rtt();
+                    let rtt: time::Duration =
+                        pv.try_into().map_err(|_| CTPError::BadType)?;
+                    recovery.refresh_min_rtt(rtt, time::Instant::now());
+                    (old.into(), rtt.into())
+                },
+                // The other fields can't be written yet.
+                _ => return Err(CTPError::BadType),
             };
+            self.record_plugin_mutation(
+                PluginField::Recovery(field),
+                Some(old),
+                new,
+            );
         }
         Ok(())
     }
@@ -90,20 +130,578 @@ impl pluginop::api::ConnectionToPlugin for crate::Connection {
         postcard::to_slice(&pv, w)
     }
 
+    fn set_timer(
+        &mut self, ts: u64, id: u64, timer_id: u64,
+    ) -> std::result::Result<(), CTPError> {
+        // Plugins express deadlines as microseconds since the UNIX epoch.
+        let deadline = time::UNIX_EPOCH + time::Duration::from_micros(ts);
+        let delay = deadline
+            .duration_since(time::SystemTime::now())
+            .unwrap_or_default();
+
+        // Timers beyond the configured limit are dropped, and reported in the
+        // connection's statistics.
+        self.plugin_timers
+            .set(id, timer_id, time::Instant::now() + delay);
+
+        Ok(())
+    }
+
+    fn cancel_timer(&mut self, id: u64) -> std::result::Result<(), CTPError> {
+        self.plugin_timers.cancel(id);
+
+        Ok(())
+    }
+
     fn set_connection(
         &mut self, field: ConnectionField, r: &[u8],
     ) -> std::result::Result<(), CTPError> {
         let pv: PluginVal =
             postcard::from_bytes(r).map_err(|_| CTPError::SerializeError)?;
-        match field {
-            ConnectionField::MaxTxData =>
-                self.max_tx_data = pv.try_into().map_err(|_| CTPError::BadType)?,
+        let (old, new): (Option<PluginVal>, PluginVal) = match &field {
+            ConnectionField::MaxTxData => {
+                let old = self.max_tx_data;
+                self.max_tx_data =
+                    pv.try_into().map_err(|_| CTPError::BadType)?;
+                (Some(old.into()), self.max_tx_data.into())
+            },
             _ => todo!(),
         };
+        self.record_plugin_mutation(PluginField::Connection(field), old, new);
         Ok(())
     }
 }
 
+/// Timers requested by plugins.
+///
+/// Each timer is identified by an `id` chosen by the plugin that registered
+/// it, and carries a `timer_id` passed back to the plugin when it fires.
+/// Registering a timer with an existing `id` rearms it.
+pub struct PluginTimers {
+    timers: BTreeMap<u64, (time::Instant, u64)>,
+
+    /// The maximum number of armed timers.
+    max_len: usize,
+
+    /// The number of timers that were not armed because too many of them
+    /// already were.
+    dropped: usize,
+}
+
+impl PluginTimers {
+    pub fn new(max_len: usize) -> Self {
+        PluginTimers {
+            timers: BTreeMap::new(),
+            max_len,
+            dropped: 0,
+        }
+    }
+
+    /// Arms the timer `id` to fire at `at`, unless too many timers are already
+    /// armed. Returns whether the timer was armed.
+    pub fn set(&mut self, id: u64, timer_id: u64, at: time::Instant) -> bool {
+        if self.timers.len() >= self.max_len && !self.timers.contains_key(&id) {
+            self.dropped += 1;
+            return false;
+        }
+
+        self.timers.insert(id, (at, timer_id));
+
+        true
+    }
+
+    /// Disarms the timer `id`, returning whether it was armed.
+    pub fn cancel(&mut self, id: u64) -> bool {
+        self.timers.remove(&id).is_some()
+    }
+
+    /// Returns the earliest deadline among armed timers.
+    pub fn next_timeout(&self) -> Option<time::Instant> {
+        self.timers.values().map(|(at, _)| *at).min()
+    }
+
+    /// Disarms the timers that expired at `now`, and returns their
+    /// `timer_id`s ordered by deadline.
+    pub fn pop_expired(&mut self, now: time::Instant) -> Vec<u64> {
+        let mut expired: Vec<(time::Instant, u64)> = self
+            .timers
+            .values()
+            .filter(|(at, _)| *at <= now)
+            .copied()
+            .collect();
+
+        self.timers.retain(|_, (at, _)| *at > now);
+
+        expired.sort();
+
+        expired.into_iter().map(|(_, timer_id)| timer_id).collect()
+    }
+
+    /// Returns the number of armed timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns the number of timers that were not armed because too many of
+    /// them already were.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// The default maximum number of plugin mutations kept by a connection.
+pub const DEFAULT_MAX_PLUGIN_MUTATIONS: usize = 128;
+
+/// A write to a connection or recovery field performed by a plugin.
+///
+/// They are returned by [`Connection::plugin_mutations()`]. The values are
+/// kept as exchanged with the plugin, and are only formatted when the
+/// mutation is displayed.
+///
+/// [`Connection::plugin_mutations()`]:
+/// struct.Connection.html#method.plugin_mutations
+#[derive(Clone, Debug)]
+pub struct PluginMutation {
+    /// The time at which the field was written.
+    pub time: time::Instant,
+
+    /// The plugin operation during which the field was written, which
+    /// identifies the plugin implementing it. It is `None` if the field was
+    /// written outside of a plugin operation.
+    pub operation: Option<PluginOp>,
+
+    /// The field that was written.
+    pub field: PluginField,
+
+    /// The value of the field before it was written, if it could be read.
+    pub old: Option<PluginVal>,
+
+    /// The value written.
+    pub new: PluginVal,
+}
+
+impl PluginMutation {
+    /// Returns the printable name of the operation that wrote the field.
+    pub fn operation_name(&self) -> Option<String> {
+        self.operation.as_ref().map(op_name)
+    }
+}
+
+impl fmt::Display for PluginMutation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = self.operation_name();
+
+        write!(
+            f,
+            "plugin operation {} set {:?} from ",
+            op.as_deref().unwrap_or("unknown"),
+            self.field
+        )?;
+
+        match &self.old {
+            Some(old) => write!(f, "{old:?}")?,
+
+            None => write!(f, "unknown")?,
+        }
+
+        write!(f, " to {:?}", self.new)
+    }
+}
+
+/// A connection or recovery field written by a plugin.
+#[derive(Clone, Debug)]
+pub enum PluginField {
+    /// A field of the recovery of the active path.
+    Recovery(RecoveryField),
+
+    /// A field of the connection.
+    Connection(ConnectionField),
+}
+
+/// The most recent plugin mutations of a connection.
+///
+/// Once `max_len` mutations are kept, the oldest one is dropped for each new
+/// mutation.
+pub struct PluginAuditLog {
+    mutations: VecDeque<PluginMutation>,
+
+    /// The maximum number of mutations kept.
+    max_len: usize,
+}
+
+impl PluginAuditLog {
+    pub fn new(max_len: usize) -> Self {
+        PluginAuditLog {
+            mutations: VecDeque::new(),
+            max_len,
+        }
+    }
+
+    /// Records a mutation, dropping the oldest one if the log is full.
+    pub fn push(&mut self, mutation: PluginMutation) {
+        if self.max_len == 0 {
+            return;
+        }
+
+        if self.mutations.len() >= self.max_len {
+            self.mutations.pop_front();
+        }
+
+        self.mutations.push_back(mutation);
+    }
+
+    /// Returns the recorded mutations, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &PluginMutation> {
+        self.mutations.iter()
+    }
+}
+
+/// The reason why a plugin operation failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PluginFailure {
+    /// The plugin trapped or returned an error, described by the message.
+    Trap(String),
+
+    /// The host panicked while running the operation, e.g. when converting
+    /// the values exchanged with the plugin. The panic message is given if it
+    /// is a string.
+    Panic(String),
+}
+
+/// A report describing a failed plugin operation.
+///
+/// Reports are passed to the sink registered with
+/// [`set_plugin_failure_sink()`] before the connection recovers from the
+/// failure, so that plugin failures can be triaged without core dumps.
+///
+/// [`set_plugin_failure_sink()`]: fn.set_plugin_failure_sink.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginFailureReport {
+    /// The trace ID of the connection.
+    pub trace_id: String,
+
+    /// The plugins advertised by the connection with
+    /// [`Config::set_plugin_support()`], one of which implements the failed
+    /// operation.
+    ///
+    /// [`Config::set_plugin_support()`]:
+    /// struct.Config.html#method.set_plugin_support
+    pub plugins: Vec<Vec<u8>>,
+
+    /// The name of the failed operation.
+    pub operation: String,
+
+    /// The inputs of the operation, serialized with `postcard`.
+    pub inputs: Vec<u8>,
+
+    /// The reason of the failure.
+    pub failure: PluginFailure,
+}
+
+/// A function receiving the reports of failed plugin operations.
+pub type PluginFailureSink = fn(&PluginFailureReport);
+
+/// The registered sink. It is stored as a raw function pointer, so that it can
+/// be loaded without locking or allocating, including from a panic hook.
+static FAILURE_SINK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Registers the function receiving the reports of failed plugin operations,
+/// for all connections.
+///
+/// Passing `None` unregisters the current sink. The sink is called on the
+/// thread running the failed operation, and should not block.
+pub fn set_plugin_failure_sink(sink: Option<PluginFailureSink>) {
+    let sink = sink.map_or(ptr::null_mut(), |f| f as *mut ());
+
+    FAILURE_SINK.store(sink, atomic::Ordering::Release);
+}
+
+/// Passes `report` to the registered sink, if any.
+pub fn report_failure(report: &PluginFailureReport) {
+    let sink = FAILURE_SINK.load(atomic::Ordering::Acquire);
+
+    if sink.is_null() {
+        return;
+    }
+
+    // Safety: only `PluginFailureSink` function pointers are ever stored.
+    let sink: PluginFailureSink = unsafe { std::mem::transmute(sink) };
+
+    sink(report);
+}
+
+/// Runs the plugin operation `op` with `call`, catching traps as well as
+/// panics of the host.
+///
+/// On failure, a report is returned with the operation and its inputs. The
+/// fields describing the connection are left empty for the caller to fill.
+pub fn guarded_call<T, E: fmt::Debug>(
+    op: &PluginOp, params: &[PluginVal],
+    call: impl FnOnce() -> std::result::Result<T, E>,
+) -> std::result::Result<T, PluginFailureReport> {
+    // Operations can be nested when a plugin calls back into the host, so the
+    // outer operation is restored once this one returns.
+    let outer = RUNNING_OP.with(|r| r.replace(Some(op.clone())));
+
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(call));
+
+    RUNNING_OP.with(|r| *r.borrow_mut() = outer);
+
+    let failure = match res {
+        Ok(Ok(v)) => return Ok(v),
+
+        Ok(Err(e)) => PluginFailure::Trap(format!("{e:?}")),
+
+        Err(payload) => PluginFailure::Panic(panic_message(&*payload)),
+    };
+
+    Err(PluginFailureReport {
+        trace_id: String::new(),
+        plugins: Vec::new(),
+        operation: op_name(op),
+        inputs: postcard::to_allocvec(params).unwrap_or_default(),
+        failure,
+    })
+}
+
+thread_local! {
+    /// The plugin operation being run by [`guarded_call()`] on this thread.
+    static RUNNING_OP: RefCell<Option<PluginOp>> = RefCell::new(None);
+}
+
+/// Returns the plugin operation being run on this thread, if any.
+pub fn running_op() -> Option<PluginOp> {
+    RUNNING_OP.with(|r| r.borrow().clone())
+}
+
+/// Returns a printable name for the plugin operation `op`.
+fn op_name(op: &PluginOp) -> String {
+    match op {
+        PluginOp::Other(name) => {
+            let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+            String::from_utf8_lossy(&name[..len]).into_owned()
+        },
+
+        op => format!("{op:?}"),
+    }
+}
+
+/// The preamble of WebAssembly binary modules: the magic number followed by
+/// the format version.
+const WASM_PREAMBLE: &[u8] = b"\0asm\x01\x00\x00\x00";
+
+/// Returns whether `buf` is a well-formed WebAssembly binary module.
+///
+/// Only the preamble and the framing of sections are checked, which is enough
+/// to reject files that aren't modules or are truncated. The contents of the
+/// sections are only validated when the plugin is inserted.
+pub fn is_wasm_module(buf: &[u8]) -> bool {
+    let mut b = match buf.strip_prefix(WASM_PREAMBLE) {
+        Some(v) => v,
+
+        None => return false,
+    };
+
+    while let Some((&id, rest)) = b.split_first() {
+        // Section IDs above 12 are not defined.
+        if id > 12 {
+            return false;
+        }
+
+        let (len, rest) = match read_leb128_u32(rest) {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        if rest.len() < len as usize {
+            return false;
+        }
+
+        b = &rest[len as usize..];
+    }
+
+    true
+}
+
+/// Decodes an unsigned LEB128 32-bit integer, returning it along with the
+/// remaining bytes.
+fn read_leb128_u32(buf: &[u8]) -> Option<(u32, &[u8])> {
+    let mut v: u32 = 0;
+
+    // A 32-bit integer is encoded on at most 5 bytes.
+    for (i, &byte) in buf.iter().take(5).enumerate() {
+        // The last byte only holds the 4 remaining bits.
+        if i == 4 && byte > 0x0f {
+            return None;
+        }
+
+        v |= u32::from(byte & 0x7f) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Some((v, &buf[i + 1..]));
+        }
+    }
+
+    None
+}
+
+/// Returns the message of a panic, if it is a string.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        return msg.to_string();
+    }
+
+    payload
+        .downcast_ref::<String>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Name of the plugin operation selecting the path of the next packet with
+/// the multipath extension.
+const SELECT_SEND_PATH: &[u8] = b"select_send_path";
+
+/// Name of the plugin operation called when the DATAGRAM send queue is full.
+#[cfg(feature = "datagram")]
+const DGRAM_SEND_QUEUE_FULL: &[u8] = b"dgram_send_queue_full";
+
+/// Name of the plugin operation queuing DATAGRAMs on behalf of a plugin.
+#[cfg(feature = "datagram")]
+const DGRAM_ENQUEUE: &[u8] = b"dgram_enqueue";
+
+/// Name of the plugin operation selecting the ACK rate requested from the peer
+/// with the ACK frequency extension.
+const ACK_FREQUENCY: &[u8] = b"ack_frequency";
+
+/// Name of the plugin operation selecting the anti-amplification credit
+/// granted for datagrams received on paths not validated yet.
+const ANTI_AMPLIFICATION: &[u8] = b"anti_amplification";
+
+/// Name of the plugin operation notified of new delivery rate samples.
+const DELIVERY_RATE_SAMPLE: &[u8] = b"delivery_rate_sample";
+
+/// Name of the plugin operation serializing the state of a plugin.
+const EXPORT_STATE: &[u8] = b"export_state";
+
+/// Name of the plugin operation restoring the state of a plugin.
+const IMPORT_STATE: &[u8] = b"import_state";
+
+/// Name of the plugin operation receiving the experiments enabled on the
+/// connection.
+const EXPERIMENTS: &[u8] = b"experiments";
+
+/// Name of the plugin operation notified of stream state updated by the peer.
+const STREAM_STATE: &[u8] = b"stream_state";
+
+/// Returns the plugin operation with the given name.
+fn other_op(op_name: &[u8]) -> PluginOp {
+    let mut name = [0; 32];
+    name[..op_name.len()].copy_from_slice(op_name);
+
+    PluginOp::Other(name)
+}
+
+/// Returns the plugin operation selecting the path of the next packet.
+///
+/// It is called with the RTT in microseconds and the available congestion
+/// window of each candidate path, and returns the index of the selected path
+/// as its first value.
+pub fn select_send_path_op() -> PluginOp {
+    other_op(SELECT_SEND_PATH)
+}
+
+/// Returns the plugin operation called when the application queues a
+/// DATAGRAM while the send queue is full.
+///
+/// It is called with the size of the new DATAGRAM, and the number of queued
+/// DATAGRAMs, their total size, and the size and age in microseconds of the
+/// oldest one. It can return the number of queued DATAGRAMs to keep, which
+/// drops the oldest ones to make room, and as its second value the number of
+/// DATAGRAMs to move from the front of the queue to its back.
+#[cfg(feature = "datagram")]
+pub fn dgram_send_queue_full_op() -> PluginOp {
+    other_op(DGRAM_SEND_QUEUE_FULL)
+}
+
+/// Returns the plugin operation queuing DATAGRAMs on behalf of a plugin.
+///
+/// It is called after a timer set by a plugin fires, with a buffer as large
+/// as the largest DATAGRAM the peer accepts and the number of queued
+/// DATAGRAMs. Anything the plugin writes in the buffer is queued as a new
+/// DATAGRAM.
+#[cfg(feature = "datagram")]
+pub fn dgram_enqueue_op() -> PluginOp {
+    other_op(DGRAM_ENQUEUE)
+}
+
+/// Returns the plugin operation selecting the ACK rate requested from the
+/// peer.
+///
+/// It is called with the congestion window, the RTT in microseconds, and the
+/// Ack-Eliciting Threshold, maximum ACK delay in microseconds and Reordering
+/// Threshold selected by default. It returns the values of these three
+/// parameters to request.
+pub fn ack_frequency_op() -> PluginOp {
+    other_op(ACK_FREQUENCY)
+}
+
+/// Returns the plugin operation selecting the anti-amplification credit of a
+/// path.
+///
+/// It is called with the size of a datagram received on a path whose peer
+/// address is not validated yet, and the number of bytes allowed to be sent
+/// in exchange by default. It returns the number of bytes to allow as its
+/// first value.
+pub fn anti_amplification_op() -> PluginOp {
+    other_op(ANTI_AMPLIFICATION)
+}
+
+/// Returns the plugin operation notified of new delivery rate samples.
+///
+/// It is called with the delivery rate in bytes per second, whether the
+/// sample is application-limited, the sampling interval and RTT in
+/// microseconds, and the number of bytes delivered during the interval.
+pub fn delivery_rate_sample_op() -> PluginOp {
+    other_op(DELIVERY_RATE_SAMPLE)
+}
+
+/// Returns the plugin operation serializing the state of a plugin.
+///
+/// It is called with a buffer, in which the plugin writes its state.
+pub fn export_state_op() -> PluginOp {
+    other_op(EXPORT_STATE)
+}
+
+/// Returns the plugin operation restoring the state of a plugin.
+///
+/// It is called with a buffer holding a state previously written by the
+/// `export_state` operation.
+pub fn import_state_op() -> PluginOp {
+    other_op(IMPORT_STATE)
+}
+
+/// Returns the plugin operation receiving the experiments enabled on the
+/// connection.
+///
+/// It is called each time a plugin is inserted by `pluginize()` or
+/// `insert_plugin()`, with the bit representation of the experiments set with
+/// `set_experiments()`.
+pub fn experiments_op() -> PluginOp {
+    other_op(EXPERIMENTS)
+}
+
+/// Returns the plugin operation notified of stream state updated by the peer.
+///
+/// It is called after a STREAM, RESET_STREAM, STOP_SENDING or MAX_STREAM_DATA
+/// frame is processed, with the stream ID and the value of each field of
+/// `StreamField::ALL`, booleans being passed as `0` or `1`. It can return a
+/// new urgency and incremental flag to reprioritize the stream.
+pub fn stream_state_op() -> PluginOp {
+    other_op(STREAM_STATE)
+}
+
 impl ToPluginizableConnection<crate::Connection> for crate::Connection {
     fn set_pluginizable_connection(
         &mut self, pc: *mut PluginizableConnection<Self>,
@@ -122,15 +720,17 @@ impl ToPluginizableConnection<crate::Connection> for crate::Connection {
     }
 }
 
-impl<CTP: ConnectionToPlugin> FromWithPH<frame::Frame, CTP> for PluginVal {
-    fn from_with_ph(
-        value: frame::Frame, _ph: &mut pluginop::handler::PluginHandler<CTP>,
-    ) -> Self {
+impl<CTP: ConnectionToPlugin> TryFromWithPH<frame::Frame, CTP> for PluginVal {
+    type Error = TryFromCoreQuicheError;
+
+    fn try_from_with_ph(
+        value: frame::Frame, _ph: &pluginop::handler::PluginHandler<CTP>,
+    ) -> Result<Self, Self::Error> {
         let frame = match value {
             frame::Frame::Padding { len } =>
                 quic::Frame::Padding(PaddingFrame { length: len as u64 }),
 
-            frame::Frame::Ping => quic::Frame::Ping(PingFrame),
+            frame::Frame::Ping { .. } => quic::Frame::Ping(PingFrame),
 
             frame::Frame::ACK {
                 ack_delay,
@@ -163,7 +763,6 @@ impl<CTP: ConnectionToPlugin> FromWithPH<frame::Frame, CTP> for PluginVal {
                     ectce_count: e.ecn_ce_count,
                 });
 
-                #[allow(unreachable_code)]
                 quic::Frame::ACK(ACKFrame {
                     largest_acknowledged,
                     ack_delay,
@@ -200,47 +799,6 @@ impl<CTP: ConnectionToPlugin> FromWithPH<frame::Frame, CTP> for PluginVal {
                 application_protocol_error_code: error_code,
             }),
 
-            #[allow(unreachable_code)]
-            frame::Frame::Crypto { data } => quic::Frame::Crypto(CryptoFrame {
-                offset: data.off(),
-                length: data.len() as u64,
-                crypto_data: todo!(),
-            }),
-
-            #[allow(unreachable_code)]
-            frame::Frame::CryptoHeader { offset, length } =>
-                quic::Frame::Crypto(CryptoFrame {
-                    offset,
-                    length: length as u64,
-                    crypto_data: todo!(),
-                }),
-
-            frame::Frame::NewToken { .. } => todo!(),
-
-            #[allow(unreachable_code)]
-            frame::Frame::Stream { stream_id, data } =>
-                quic::Frame::Stream(StreamFrame {
-                    stream_id,
-                    offset: Some(data.off()),
-                    length: Some(data.len() as u64),
-                    fin: data.fin(),
-                    stream_data: todo!(),
-                }),
-
-            #[allow(unreachable_code)]
-            frame::Frame::StreamHeader {
-                stream_id,
-                offset,
-                length,
-                fin,
-            } => quic::Frame::Stream(StreamFrame {
-                stream_id,
-                offset: Some(offset),
-                length: Some(length as u64),
-                fin,
-                stream_data: todo!(),
-            }),
-
             frame::Frame::MaxData { max } =>
                 quic::Frame::MaxData(MaxDataFrame { maximum_data: max }),
 
@@ -285,20 +843,6 @@ impl<CTP: ConnectionToPlugin> FromWithPH<frame::Frame, CTP> for PluginVal {
                     maximum_streams: limit,
                 }),
 
-            #[allow(unreachable_code)]
-            frame::Frame::NewConnectionId {
-                seq_num,
-                retire_prior_to,
-                conn_id,
-                ..
-            } => quic::Frame::NewConnectionId(quic::NewConnectionIdFrame {
-                sequence_number: seq_num,
-                retire_prior_to,
-                length: conn_id.len() as u8,
-                connection_id: todo!(),
-                stateless_reset_token: todo!(),
-            }),
-
             frame::Frame::RetireConnectionId { seq_num } =>
                 quic::Frame::RetireConnectionId(RetireConnectionIdFrame {
                     sequence_number: seq_num,
@@ -314,57 +858,44 @@ impl<CTP: ConnectionToPlugin> FromWithPH<frame::Frame, CTP> for PluginVal {
                     data: u64::from_be_bytes(data),
                 }),
 
-            #[allow(unreachable_code)]
-            frame::Frame::ConnectionClose {
-                error_code,
-                frame_type,
-                reason,
-            } => quic::Frame::ConnectionClose(ConnectionCloseFrame {
-                error_code,
-                frame_type: Some(frame_type),
-                reason_phrase_length: reason.len() as u64,
-                reason_phrase: todo!(),
-            }),
-
-            #[allow(unreachable_code)]
-            frame::Frame::ApplicationClose { error_code, reason } =>
-                quic::Frame::ConnectionClose(ConnectionCloseFrame {
-                    error_code,
-                    frame_type: None,
-                    reason_phrase_length: reason.len() as u64,
-                    reason_phrase: todo!(),
-                }),
-
             frame::Frame::HandshakeDone =>
                 quic::Frame::HandshakeDone(HandshakeDoneFrame),
 
-            #[allow(unreachable_code)]
-            frame::Frame::Datagram { .. } =>
-                quic::Frame::Extension(ExtensionFrame {
-                    frame_type: 0x30,
-                    tag: todo!(),
-                }),
-
-            #[allow(unreachable_code)]
-            frame::Frame::DatagramHeader { .. } =>
-                quic::Frame::Extension(ExtensionFrame {
-                    frame_type: 0x30,
-                    tag: todo!(),
-                }),
-
             frame::Frame::Extension { ty, tag } =>
                 quic::Frame::Extension(ExtensionFrame {
                     frame_type: ty,
                     tag,
                 }),
+
+            // The data carried by these frames can't be passed to plugins yet.
+            frame::Frame::Crypto { .. } |
+            frame::Frame::CryptoHeader { .. } |
+            frame::Frame::NewToken { .. } |
+            frame::Frame::Stream { .. } |
+            frame::Frame::StreamHeader { .. } |
+            frame::Frame::NewConnectionId { .. } |
+            frame::Frame::ConnectionClose { .. } |
+            frame::Frame::ApplicationClose { .. } |
+            frame::Frame::Datagram { .. } |
+            frame::Frame::DatagramHeader { .. } |
+            frame::Frame::MpAck { .. } |
+            frame::Frame::PathAbandon { .. } |
+            frame::Frame::PathStatus { .. } |
+            frame::Frame::AckFrequency { .. } |
+            frame::Frame::ImmediateAck =>
+                return Err(TryFromCoreQuicheError::UnsupportedFrame),
         };
-        PluginVal::QUIC(quic::QVal::Frame(frame))
+
+        Ok(PluginVal::QUIC(quic::QVal::Frame(frame)))
     }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum TryFromCoreQuicheError {
     BadFrame,
+
+    /// The frame can't be converted yet.
+    UnsupportedFrame,
 }
 
 impl<CTP: ConnectionToPlugin> TryFromWithPH<PluginVal, CTP> for frame::Frame {
@@ -382,8 +913,9 @@ impl<CTP: ConnectionToPlugin> TryFromWithPH<PluginVal, CTP> for frame::Frame {
             quic::Frame::Padding(p) => frame::Frame::Padding {
                 len: p.length as usize,
             },
-            quic::Frame::Ping(_) => frame::Frame::Ping,
-            quic::Frame::ACK(_) => todo!("ack"),
+            quic::Frame::Ping(_) => frame::Frame::Ping { mtu_probe: None },
+            quic::Frame::ACK(_) =>
+                return Err(TryFromCoreQuicheError::UnsupportedFrame),
             quic::Frame::ResetStream(rs) => frame::Frame::ResetStream {
                 stream_id: rs.stream_id,
                 error_code: rs.application_protocol_error_code,
@@ -393,9 +925,12 @@ impl<CTP: ConnectionToPlugin> TryFromWithPH<PluginVal, CTP> for frame::Frame {
                 stream_id: ss.stream_id,
                 error_code: ss.application_protocol_error_code,
             },
-            quic::Frame::Crypto(_) => todo!("crypto"),
-            quic::Frame::NewToken(_) => todo!("new token"),
-            quic::Frame::Stream(_) => todo!("stream"),
+            quic::Frame::Crypto(_) =>
+                return Err(TryFromCoreQuicheError::UnsupportedFrame),
+            quic::Frame::NewToken(_) =>
+                return Err(TryFromCoreQuicheError::UnsupportedFrame),
+            quic::Frame::Stream(_) =>
+                return Err(TryFromCoreQuicheError::UnsupportedFrame),
             quic::Frame::MaxData(md) => frame::Frame::MaxData {
                 max: md.maximum_data,
             },
@@ -431,7 +966,8 @@ impl<CTP: ConnectionToPlugin> TryFromWithPH<PluginVal, CTP> for frame::Frame {
                         limit: sb.maximum_streams,
                     }
                 },
-            quic::Frame::NewConnectionId(_) => todo!("new cid"),
+            quic::Frame::NewConnectionId(_) =>
+                return Err(TryFromCoreQuicheError::UnsupportedFrame),
             quic::Frame::RetireConnectionId(rc) =>
                 frame::Frame::RetireConnectionId {
                     seq_num: rc.sequence_number,
@@ -442,13 +978,14 @@ impl<CTP: ConnectionToPlugin> TryFromWithPH<PluginVal, CTP> for frame::Frame {
             quic::Frame::PathResponse(pr) => frame::Frame::PathResponse {
                 data: pr.data.to_be_bytes(),
             },
-            quic::Frame::ConnectionClose(_) => todo!("connection close"),
+            quic::Frame::ConnectionClose(_) =>
+                return Err(TryFromCoreQuicheError::UnsupportedFrame),
             quic::Frame::HandshakeDone(_) => frame::Frame::HandshakeDone,
             quic::Frame::Extension(e) => frame::Frame::Extension {
                 ty: e.frame_type,
                 tag: e.tag,
             },
-            f => todo!("unknown frame {f:?}"),
+            _ => return Err(TryFromCoreQuicheError::UnsupportedFrame),
         };
         Ok(quiche_frame)
     }
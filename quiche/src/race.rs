@@ -0,0 +1,406 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::net::SocketAddr;
+use std::time;
+
+use crate::rand;
+use crate::Config;
+use crate::Connection;
+use crate::ConnectionId;
+use crate::Error;
+use crate::RecvInfo;
+use crate::Result;
+use crate::SendInfo;
+
+/// A connection attempt to one of the endpoints of a race.
+struct Candidate {
+    peer: SocketAddr,
+
+    conn: Connection,
+
+    /// Whether the connection started sending packets.
+    started: bool,
+}
+
+/// Client connections raced over several endpoints of the same server.
+///
+/// A connection is created to each endpoint. The first one starts right
+/// away, and each of the following ones starts `stagger` later than the
+/// previous one, or as soon as all the previous ones failed. The first
+/// connection to complete its handshake wins the race, and the others are
+/// closed.
+///
+/// A short `stagger` races the endpoints against each other, while a long one
+/// only fails over to the next endpoint when the previous ones failed.
+///
+/// All the connections are created with the same [`Config`]. When it has a
+/// [`ClientStore`], the address validation token and TLS session stored for
+/// the server name are only used by the first connection, as they can only be
+/// used once.
+///
+/// [`Config`]: struct.Config.html
+/// [`ClientStore`]: trait.ClientStore.html
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+/// # let local = "127.0.0.1:1234".parse().unwrap();
+/// let peers = [
+///     "192.0.2.1:443".parse().unwrap(),
+///     "[2001:db8::1]:443".parse().unwrap(),
+/// ];
+/// let stagger = std::time::Duration::from_millis(250);
+///
+/// let mut race = quiche::ConnectionRace::new(
+///     Some("quic.tech"),
+///     local,
+///     &peers,
+///     stagger,
+///     &mut config,
+/// )?;
+///
+/// // Drive the race with send(), recv(), timeout() and on_timeout(), until
+/// // an endpoint wins.
+/// if let Some(peer) = race.winner() {
+///     println!("connected to {}", peer);
+///
+///     let conn = race.into_connection()?;
+/// }
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub struct ConnectionRace {
+    candidates: Vec<Candidate>,
+
+    stagger: time::Duration,
+
+    created: time::Instant,
+
+    winner: Option<usize>,
+}
+
+impl ConnectionRace {
+    /// Creates a connection to each of the `peers`, in order of preference.
+    ///
+    /// The connections use random Source Connection IDs, and are otherwise
+    /// created like with [`connect()`]. If `peers` is empty, [`InvalidState`]
+    /// is returned.
+    ///
+    /// [`connect()`]: fn.connect.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn new(
+        server_name: Option<&str>, local: SocketAddr, peers: &[SocketAddr],
+        stagger: time::Duration, config: &mut Config,
+    ) -> Result<ConnectionRace> {
+        if peers.is_empty() {
+            return Err(Error::InvalidState);
+        }
+
+        let mut candidates = Vec::with_capacity(peers.len());
+
+        for &peer in peers {
+            let mut scid = [0; crate::MAX_CONN_ID_LEN];
+            rand::rand_bytes(&mut scid[..]);
+            let scid = ConnectionId::from_ref(&scid);
+
+            let conn = crate::connect(server_name, &scid, local, peer, config)?;
+
+            candidates.push(Candidate {
+                peer,
+                conn,
+                started: false,
+            });
+        }
+
+        Ok(ConnectionRace {
+            candidates,
+            stagger,
+            created: time::Instant::now(),
+            winner: None,
+        })
+    }
+
+    /// Processes QUIC packets received from the peer.
+    ///
+    /// The packets are passed to the connection to the endpoint they were
+    /// received from. If there is none, [`Done`] is returned. Otherwise, the
+    /// result of [`Connection::recv()`] is returned.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`Connection::recv()`]: struct.Connection.html#method.recv
+    pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
+        let i = self
+            .candidates
+            .iter()
+            .position(|c| c.started && c.peer == info.from)
+            .ok_or(Error::Done)?;
+
+        let res = self.candidates[i].conn.recv(buf, info);
+
+        if self.winner.is_none() && self.candidates[i].conn.is_established() {
+            trace!("race won by {}", self.candidates[i].peer);
+
+            self.winner = Some(i);
+
+            for (j, c) in self.candidates.iter_mut().enumerate() {
+                if j != i && c.started {
+                    c.conn.close(false, 0x0, b"").ok();
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Writes a single QUIC packet to be sent to one of the endpoints.
+    ///
+    /// Packets are written for the connections that already started, in
+    /// order of preference. Connections that fail are skipped, and [`Done`]
+    /// is returned when there is nothing to write.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn send(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        let now = time::Instant::now();
+
+        for i in 0..self.candidates.len() {
+            if !self.is_started(i, now) {
+                continue;
+            }
+
+            let c = &mut self.candidates[i];
+            c.started = true;
+
+            match c.conn.send(out) {
+                Ok(v) => return Ok(v),
+
+                Err(Error::Done) => (),
+
+                Err(e) => trace!("race candidate {} failed: {:?}", c.peer, e),
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Returns the amount of time until the next timeout event.
+    ///
+    /// This includes the timeouts of the connections that already started,
+    /// and the start of the next connection.
+    pub fn timeout(&self) -> Option<time::Duration> {
+        let now = time::Instant::now();
+
+        (0..self.candidates.len())
+            .filter_map(|i| {
+                if self.is_started(i, now) {
+                    self.candidates[i].conn.timeout()
+                } else if self.winner.is_none() {
+                    Some(self.start_time(i).saturating_duration_since(now))
+                } else {
+                    None
+                }
+            })
+            .min()
+    }
+
+    /// Processes a timeout event.
+    pub fn on_timeout(&mut self) {
+        for c in self.candidates.iter_mut().filter(|c| c.started) {
+            c.conn.on_timeout();
+        }
+    }
+
+    /// Returns the endpoint the winning connection is established with, if
+    /// any.
+    pub fn winner(&self) -> Option<SocketAddr> {
+        self.winner.map(|i| self.candidates[i].peer)
+    }
+
+    /// Returns true if all the connections failed.
+    pub fn is_failed(&self) -> bool {
+        self.candidates.iter().all(|c| c.conn.is_closed())
+    }
+
+    /// Returns the winning connection, dropping the others.
+    ///
+    /// If no endpoint won the race yet, [`InvalidState`] is returned.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn into_connection(mut self) -> Result<Connection> {
+        let i = self.winner.ok_or(Error::InvalidState)?;
+
+        Ok(self.candidates.swap_remove(i).conn)
+    }
+
+    /// Returns the time at which the connection `i` starts, unless the
+    /// previous ones failed earlier.
+    fn start_time(&self, i: usize) -> time::Instant {
+        self.created + self.stagger * i as u32
+    }
+
+    /// Returns whether the connection `i` is allowed to send packets.
+    fn is_started(&self, i: usize, now: time::Instant) -> bool {
+        let c = &self.candidates[i];
+
+        if c.started || Some(i) == self.winner {
+            return true;
+        }
+
+        // Once the race is won, connections that didn't start never do.
+        if self.winner.is_some() {
+            return false;
+        }
+
+        now >= self.start_time(i) ||
+            self.candidates[..i].iter().all(|c| c.conn.is_closed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    fn config() -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+
+        config
+    }
+
+    #[test]
+    fn race() {
+        let mut config = config();
+
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
+        let unreachable = "127.0.0.1:9999".parse().unwrap();
+
+        let mut race = ConnectionRace::new(
+            Some("quic.tech"),
+            client_addr,
+            &[unreachable, server_addr],
+            time::Duration::ZERO,
+            &mut config,
+        )
+        .unwrap();
+
+        let mut server = None;
+        let mut buf = [0; 65535];
+
+        while race.winner().is_none() {
+            let mut sent = false;
+
+            while let Ok((len, info)) = race.send(&mut buf) {
+                sent = true;
+
+                // Packets sent to the unreachable endpoint are lost.
+                if info.to != server_addr {
+                    continue;
+                }
+
+                let server = server.get_or_insert_with(|| {
+                    let scid = ConnectionId::from_ref(&[0xba; 16]);
+                    crate::accept(
+                        &scid,
+                        None,
+                        server_addr,
+                        client_addr,
+                        &mut config,
+                    )
+                    .unwrap()
+                });
+
+                let info = RecvInfo {
+                    from: client_addr,
+                    to: server_addr,
+                };
+                server.recv(&mut buf[..len], info).unwrap();
+            }
+
+            let server = server.as_mut().unwrap();
+
+            while let Ok((len, info)) = server.send(&mut buf) {
+                sent = true;
+
+                let info = RecvInfo {
+                    from: info.from,
+                    to: info.to,
+                };
+                race.recv(&mut buf[..len], info).unwrap();
+            }
+
+            assert!(sent);
+        }
+
+        assert_eq!(race.winner(), Some(server_addr));
+        assert!(!race.is_failed());
+
+        let conn = race.into_connection().unwrap();
+        assert!(conn.is_established());
+        assert_eq!(conn.paths.get_active().unwrap().peer_addr(), server_addr);
+    }
+
+    #[test]
+    fn stagger() {
+        let mut config = config();
+
+        let client_addr = testing::Pipe::client_addr();
+        let server_addr = testing::Pipe::server_addr();
+        let fallback = "127.0.0.1:9999".parse().unwrap();
+        let stagger = time::Duration::from_secs(3600);
+
+        let mut race = ConnectionRace::new(
+            None,
+            client_addr,
+            &[server_addr, fallback],
+            stagger,
+            &mut config,
+        )
+        .unwrap();
+
+        let mut buf = [0; 65535];
+
+        // Only the preferred endpoint is tried until the stagger delay.
+        while let Ok((_, info)) = race.send(&mut buf) {
+            assert_eq!(info.to, server_addr);
+        }
+
+        assert!(race.timeout().unwrap() <= stagger);
+        assert_eq!(race.winner(), None);
+        assert_eq!(race.into_connection().err(), Some(Error::InvalidState));
+    }
+}
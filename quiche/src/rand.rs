@@ -24,12 +24,23 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+#[cfg(not(feature = "rustls"))]
 pub fn rand_bytes(buf: &mut [u8]) {
     unsafe {
         RAND_bytes(buf.as_mut_ptr(), buf.len());
     }
 }
 
+#[cfg(feature = "rustls")]
+pub fn rand_bytes(buf: &mut [u8]) {
+    use ring::rand::SecureRandom;
+
+    // As with BoringSSL, failing to get random bytes is fatal.
+    ring::rand::SystemRandom::new()
+        .fill(buf)
+        .expect("failed to generate random bytes");
+}
+
 pub fn rand_u8() -> u8 {
     let mut buf = [0; 1];
 
@@ -59,6 +70,7 @@ pub fn rand_u64_uniform(max: u64) -> u64 {
     r / chunk_size
 }
 
+#[cfg(not(feature = "rustls"))]
 extern {
     fn RAND_bytes(buf: *mut u8, len: libc::size_t) -> libc::c_int;
 }
@@ -0,0 +1,536 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::net::IpAddr;
+use std::time;
+
+use std::collections::HashMap;
+
+/// A behavior that can be rate-limited with [`Config::set_rate_limit()`].
+///
+/// [`Config::set_rate_limit()`]: struct.Config.html#method.set_rate_limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RateLimited {
+    /// Retry packets sent by the application, checked endpoint-wide with
+    /// [`Config::rate_limit_allow()`].
+    ///
+    /// [`Config::rate_limit_allow()`]:
+    /// struct.Config.html#method.rate_limit_allow
+    Retry,
+
+    /// Address validation tokens issued by the application, checked
+    /// endpoint-wide with [`Config::rate_limit_allow()`].
+    ///
+    /// [`Config::rate_limit_allow()`]:
+    /// struct.Config.html#method.rate_limit_allow
+    NewToken,
+
+    /// Source Connection IDs provided to the peer of a connection with
+    /// [`new_source_cid()`].
+    ///
+    /// [`new_source_cid()`]: struct.Connection.html#method.new_source_cid
+    NewConnectionId,
+
+    /// PATH_RESPONSE frames sent in response to the PATH_CHALLENGE frames
+    /// received on a connection.
+    PathResponse,
+
+    /// Calls to the operations implemented by the plugins of a connection.
+    PluginCall,
+}
+
+impl RateLimited {
+    const COUNT: usize = 5;
+
+    fn index(self) -> usize {
+        match self {
+            RateLimited::Retry => 0,
+            RateLimited::NewToken => 1,
+            RateLimited::NewConnectionId => 2,
+            RateLimited::PathResponse => 3,
+            RateLimited::PluginCall => 4,
+        }
+    }
+}
+
+/// A token bucket limiting how often an action can be performed.
+///
+/// The bucket holds up to `burst` tokens, and is refilled at `rate` tokens
+/// per second. Each action consumes one token, and is denied when the bucket
+/// is empty.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    rate: u64,
+
+    burst: u64,
+
+    tokens: f64,
+
+    last_refill: Option<time::Instant>,
+}
+
+impl TokenBucket {
+    /// Creates a full token bucket.
+    pub fn new(rate: u64, burst: u64) -> TokenBucket {
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst as f64,
+            last_refill: None,
+        }
+    }
+
+    /// Consumes a token if one is available at `now`.
+    ///
+    /// Returns `true` if the action is allowed.
+    pub fn try_acquire(&mut self, now: time::Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+
+        true
+    }
+
+    /// Returns the number of whole tokens available at `now`.
+    pub fn available(&mut self, now: time::Instant) -> u64 {
+        self.refill(now);
+
+        self.tokens as u64
+    }
+
+    fn refill(&mut self, now: time::Instant) {
+        if let Some(last_refill) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last_refill);
+
+            self.tokens = f64::min(
+                self.tokens + elapsed.as_secs_f64() * self.rate as f64,
+                self.burst as f64,
+            );
+        }
+
+        self.last_refill = Some(now);
+    }
+}
+
+/// The default maximum number of sources tracked by a [`SourceRateLimiter`].
+///
+/// [`SourceRateLimiter`]: struct.SourceRateLimiter.html
+pub const DEFAULT_MAX_SOURCES: usize = 65536;
+
+/// How many tracked sources are inspected to find one to forget, when a new
+/// source needs to be tracked.
+const EVICTION_SCAN_LEN: usize = 16;
+
+/// A callback invoked when a source starts exceeding its rate limit.
+pub type SourceOverflowCallback = Box<dyn FnMut(IpAddr) + Send>;
+
+/// Per-source state of a [`SourceRateLimiter`].
+struct Source {
+    addr: IpAddr,
+
+    bucket: TokenBucket,
+
+    /// Whether the last datagram from the source was dropped.
+    limited: bool,
+
+    /// Whether a datagram was received from the source since the eviction
+    /// hand last went past it.
+    referenced: bool,
+}
+
+/// Limits the rate at which UDP datagrams are accepted from each source IP
+/// address.
+///
+/// This is meant to be used by the application's listener, before datagrams
+/// are parsed and dispatched to connections, so that a single source can't
+/// monopolize the receive path:
+///
+/// ```
+/// # let mut buf = [0; 512];
+/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut limiter = quiche::SourceRateLimiter::new(1000, 100);
+///
+/// loop {
+///     let (_len, from) = socket.recv_from(&mut buf).unwrap();
+///
+///     if !limiter.allow(from.ip(), std::time::Instant::now()) {
+///         continue;
+///     }
+///
+///     // Parse the header and dispatch the datagram...
+/// # break;
+/// }
+/// ```
+///
+/// Once the number of tracked sources reaches its maximum, a few sources are
+/// inspected in turn, like a clock, to make room for a new one: the first
+/// idle source, whose bucket is full, or else the first one that didn't send
+/// anything since it was last inspected, is forgotten. If none can be
+/// forgotten, the new source is not tracked, and its datagrams are charged to
+/// a bucket shared by all untracked sources. This keeps the cost of each
+/// datagram bounded, and prevents spoofed source addresses from bypassing the
+/// limiter.
+pub struct SourceRateLimiter {
+    rate: u64,
+
+    burst: u64,
+
+    /// Indexes of the tracked sources in `slots`.
+    index: HashMap<IpAddr, usize>,
+
+    slots: Vec<Source>,
+
+    /// The next slot inspected to make room for a new source.
+    hand: usize,
+
+    /// The bucket shared by the sources that couldn't be tracked.
+    overflow: Source,
+
+    max_sources: usize,
+
+    on_overflow: Option<SourceOverflowCallback>,
+
+    dropped: u64,
+}
+
+impl SourceRateLimiter {
+    /// Creates a limiter accepting up to `rate` datagrams per second from
+    /// each source, with bursts of up to `burst` datagrams.
+    pub fn new(rate: u64, burst: u64) -> SourceRateLimiter {
+        SourceRateLimiter {
+            rate,
+            burst,
+            index: HashMap::new(),
+            slots: Vec::new(),
+            hand: 0,
+            overflow: Source::new(IpAddr::from([0, 0, 0, 0]), rate, burst),
+            max_sources: DEFAULT_MAX_SOURCES,
+            on_overflow: None,
+            dropped: 0,
+        }
+    }
+
+    /// Sets the maximum number of sources tracked at once.
+    ///
+    /// The default value is 65536.
+    pub fn set_max_sources(&mut self, v: usize) {
+        self.max_sources = v;
+    }
+
+    /// Sets the callback invoked with the address of a source when it starts
+    /// exceeding its rate limit.
+    ///
+    /// The callback is not invoked again for the same source until one of its
+    /// datagrams is accepted.
+    pub fn set_overflow_callback(&mut self, cb: Option<SourceOverflowCallback>) {
+        self.on_overflow = cb;
+    }
+
+    /// Returns whether a datagram received from `from` at `now` should be
+    /// processed.
+    pub fn allow(&mut self, from: IpAddr, now: time::Instant) -> bool {
+        let slot = match self.index.get(&from) {
+            Some(&i) => Some(i),
+
+            None => self.track(from, now),
+        };
+
+        let source = match slot {
+            Some(i) => &mut self.slots[i],
+
+            None => &mut self.overflow,
+        };
+
+        source.referenced = true;
+
+        if source.bucket.try_acquire(now) {
+            source.limited = false;
+
+            return true;
+        }
+
+        self.dropped += 1;
+
+        if !source.limited {
+            source.limited = true;
+
+            if let Some(cb) = &mut self.on_overflow {
+                cb(from);
+            }
+        }
+
+        false
+    }
+
+    /// Returns the number of datagrams dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Returns the number of sources currently tracked.
+    pub fn sources(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Starts tracking `addr`, and returns its slot.
+    ///
+    /// If the maximum number of sources is reached, the slot of a forgotten
+    /// source is reused. `None` is returned if no source can be forgotten.
+    fn track(&mut self, addr: IpAddr, now: time::Instant) -> Option<usize> {
+        if self.slots.len() < self.max_sources {
+            self.slots.push(Source::new(addr, self.rate, self.burst));
+            self.index.insert(addr, self.slots.len() - 1);
+
+            return Some(self.slots.len() - 1);
+        }
+
+        let i = self.find_evictable(now)?;
+
+        self.index.remove(&self.slots[i].addr);
+        self.index.insert(addr, i);
+
+        self.slots[i] = Source::new(addr, self.rate, self.burst);
+
+        Some(i)
+    }
+
+    /// Inspects the next few slots, and returns the first one whose source is
+    /// idle at `now` or else wasn't referenced since last inspected.
+    fn find_evictable(&mut self, now: time::Instant) -> Option<usize> {
+        let mut unreferenced = None;
+
+        for _ in 0..EVICTION_SCAN_LEN.min(self.slots.len()) {
+            let i = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+
+            let source = &mut self.slots[i];
+
+            if source.bucket.available(now) >= self.burst {
+                return Some(i);
+            }
+
+            if !source.referenced && unreferenced.is_none() {
+                unreferenced = Some(i);
+            }
+
+            source.referenced = false;
+        }
+
+        unreferenced
+    }
+}
+
+impl Source {
+    fn new(addr: IpAddr, rate: u64, burst: u64) -> Source {
+        Source {
+            addr,
+            bucket: TokenBucket::new(rate, burst),
+            limited: false,
+            referenced: false,
+        }
+    }
+}
+
+impl std::fmt::Debug for SourceRateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SourceRateLimiter")
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .field("sources", &self.index.len())
+            .field("max_sources", &self.max_sources)
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+/// The token buckets of the rate-limited behaviors.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RateLimits {
+    buckets: [Option<TokenBucket>; RateLimited::COUNT],
+}
+
+impl RateLimits {
+    /// Limits `behavior` to `rate` actions per second, with bursts of up to
+    /// `burst` actions. A `burst` of 0 removes the limit.
+    pub fn set(&mut self, behavior: RateLimited, rate: u64, burst: u64) {
+        self.buckets[behavior.index()] = if burst > 0 {
+            Some(TokenBucket::new(rate, burst))
+        } else {
+            None
+        };
+    }
+
+    /// Returns whether `behavior` is allowed at `now`, consuming a token if
+    /// it is limited.
+    pub fn allow(&mut self, behavior: RateLimited, now: time::Instant) -> bool {
+        match &mut self.buckets[behavior.index()] {
+            Some(bucket) => bucket.try_acquire(now),
+
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let mut now = time::Instant::now();
+
+        let mut bucket = TokenBucket::new(10, 3);
+        assert_eq!(bucket.available(now), 3);
+
+        // The burst is allowed right away.
+        assert!(bucket.try_acquire(now));
+        assert!(bucket.try_acquire(now));
+        assert!(bucket.try_acquire(now));
+        assert!(!bucket.try_acquire(now));
+
+        // One token is refilled every 100ms.
+        now += time::Duration::from_millis(50);
+        assert!(!bucket.try_acquire(now));
+
+        now += time::Duration::from_millis(60);
+        assert!(bucket.try_acquire(now));
+        assert!(!bucket.try_acquire(now));
+
+        // The bucket never holds more than the burst.
+        now += time::Duration::from_secs(10);
+        assert_eq!(bucket.available(now), 3);
+    }
+
+    #[test]
+    fn rate_limits() {
+        let now = time::Instant::now();
+
+        let mut limits = RateLimits::default();
+        assert!(limits.allow(RateLimited::PathResponse, now));
+
+        limits.set(RateLimited::PathResponse, 1, 1);
+        assert!(limits.allow(RateLimited::PathResponse, now));
+        assert!(!limits.allow(RateLimited::PathResponse, now));
+
+        // Other behaviors are not affected.
+        assert!(limits.allow(RateLimited::PluginCall, now));
+
+        // A burst of 0 removes the limit.
+        limits.set(RateLimited::PathResponse, 1, 0);
+        assert!(limits.allow(RateLimited::PathResponse, now));
+    }
+
+    #[test]
+    fn source_rate_limiter() {
+        use std::sync::Arc;
+        use std::sync::Mutex;
+
+        let mut now = time::Instant::now();
+
+        let a: IpAddr = "127.0.0.1".parse().unwrap();
+        let b: IpAddr = "127.0.0.2".parse().unwrap();
+        let c: IpAddr = "127.0.0.3".parse().unwrap();
+        let d: IpAddr = "127.0.0.4".parse().unwrap();
+
+        let overflows = Arc::new(Mutex::new(Vec::new()));
+        let overflows_cb = overflows.clone();
+
+        let mut limiter = SourceRateLimiter::new(10, 2);
+        limiter.set_max_sources(2);
+        limiter.set_overflow_callback(Some(Box::new(move |from| {
+            overflows_cb.lock().unwrap().push(from);
+        })));
+
+        assert!(limiter.allow(a, now));
+        assert!(limiter.allow(a, now));
+        assert!(!limiter.allow(a, now));
+        assert!(!limiter.allow(a, now));
+        assert_eq!(limiter.dropped(), 2);
+
+        // The callback is only invoked once per overflow.
+        assert_eq!(*overflows.lock().unwrap(), vec![a]);
+
+        // Other sources are not affected.
+        assert!(limiter.allow(b, now));
+
+        // While tracked sources keep sending, new sources aren't tracked and
+        // share a single bucket.
+        assert!(limiter.allow(c, now));
+        assert!(!limiter.allow(a, now));
+        assert!(limiter.allow(b, now));
+
+        assert!(limiter.allow(c, now));
+        assert!(!limiter.allow(a, now));
+        assert!(!limiter.allow(b, now));
+
+        assert!(!limiter.allow(d, now));
+        assert_eq!(limiter.sources(), 2);
+        assert_eq!(limiter.dropped(), 6);
+        assert_eq!(*overflows.lock().unwrap(), vec![a, b, d]);
+
+        // Once refilled, idle sources are forgotten to make room.
+        now += time::Duration::from_secs(1);
+        assert!(limiter.allow(c, now));
+        assert_eq!(limiter.sources(), 2);
+
+        assert!(limiter.allow(a, now));
+        assert!(limiter.allow(a, now));
+        assert!(!limiter.allow(a, now));
+        assert_eq!(*overflows.lock().unwrap(), vec![a, b, d, a]);
+    }
+
+    #[test]
+    fn source_rate_limiter_eviction() {
+        let now = time::Instant::now();
+
+        let a: IpAddr = "127.0.0.1".parse().unwrap();
+        let b: IpAddr = "127.0.0.2".parse().unwrap();
+        let c: IpAddr = "127.0.0.3".parse().unwrap();
+
+        let mut limiter = SourceRateLimiter::new(10, 2);
+        limiter.set_max_sources(2);
+
+        assert!(limiter.allow(a, now));
+        assert!(limiter.allow(b, now));
+
+        // Neither source is idle, and both were referenced: c isn't tracked,
+        // but the eviction hand went past a and b.
+        assert!(limiter.allow(c, now));
+        assert_eq!(limiter.sources(), 2);
+
+        // Only b is referenced again, so a is forgotten to make room for c.
+        assert!(limiter.allow(b, now));
+        assert!(limiter.allow(c, now));
+        assert_eq!(limiter.sources(), 2);
+
+        // a starts over with a full bucket.
+        assert!(limiter.allow(a, now));
+    }
+}
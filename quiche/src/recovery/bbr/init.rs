@@ -65,6 +65,7 @@ fn bbr_init_pacing_rate(r: &mut Recovery) {
 
     let srtt = r
         .smoothed_rtt
+        .or(r.initial_rtt)
         .unwrap_or_else(|| Duration::from_millis(1))
         .as_secs_f64();
 
@@ -36,18 +36,65 @@ use crate::recovery::*;
 use std::time::Duration;
 use std::time::Instant;
 
-pub static BBR: CongestionControlOps = CongestionControlOps {
-    on_init,
-    reset,
-    on_packet_sent,
-    on_packets_acked,
-    congestion_event,
-    collapse_cwnd,
-    checkpoint,
-    rollback,
-    has_custom_pacing,
-    debug_fmt,
-};
+/// BBR congestion control.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bbr;
+
+impl CongestionControl for Bbr {
+    fn on_init(&mut self, r: &mut Recovery) {
+        on_init(r)
+    }
+
+    fn reset(&mut self, r: &mut Recovery) {
+        reset(r)
+    }
+
+    fn on_packet_sent(
+        &mut self, r: &mut Recovery, sent_bytes: usize, now: Instant,
+    ) {
+        on_packet_sent(r, sent_bytes, now)
+    }
+
+    fn on_packets_acked(
+        &mut self, r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        on_packets_acked(r, packets, epoch, now)
+    }
+
+    fn congestion_event(
+        &mut self, r: &mut Recovery, lost_bytes: usize, time_sent: Instant,
+        epoch: packet::Epoch, now: Instant,
+    ) {
+        congestion_event(r, lost_bytes, time_sent, epoch, now)
+    }
+
+    fn collapse_cwnd(&mut self, r: &mut Recovery) {
+        collapse_cwnd(r)
+    }
+
+    fn checkpoint(&mut self, r: &mut Recovery) {
+        checkpoint(r)
+    }
+
+    fn rollback(&mut self, r: &mut Recovery) -> bool {
+        rollback(r)
+    }
+
+    fn has_custom_pacing(&self) -> bool {
+        has_custom_pacing()
+    }
+
+    fn debug_fmt(
+        &self, r: &Recovery, f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        debug_fmt(r, f)
+    }
+
+    fn clone_box(&self) -> Box<dyn CongestionControl> {
+        Box::new(*self)
+    }
+}
 
 /// A constant specifying the length of the BBR.BtlBw max filter window for
 /// BBR.BtlBwFilter, BtlBwFilterLen is 10 packet-timed round trips.
@@ -424,6 +471,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -491,6 +539,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -557,6 +606,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -605,6 +655,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -676,6 +727,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -746,6 +798,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -797,6 +850,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -103,10 +103,11 @@ fn bbr_update_round(r: &mut Recovery, packet: &Acked) {
 
 // 4.1.2.3. Updating the BBR.RTprop Min Filter
 fn bbr_update_rtprop(r: &mut Recovery, now: Instant) {
+    let filter_len = cmp::min(RTPROP_FILTER_LEN, r.min_rtt_expiry);
     let bbr = &mut r.bbr_state;
     let rs_rtt = r.delivery_rate.sample_rtt();
 
-    bbr.rtprop_expired = now > bbr.rtprop_stamp + RTPROP_FILTER_LEN;
+    bbr.rtprop_expired = now > bbr.rtprop_stamp + filter_len;
 
     if !rs_rtt.is_zero() && (rs_rtt <= bbr.rtprop || bbr.rtprop_expired) {
         bbr.rtprop = rs_rtt;
@@ -361,6 +362,14 @@ fn bbr_handle_probe_rtt(r: &mut Recovery, now: Instant) {
         if r.bbr_state.probe_rtt_round_done && now > probe_rtt_done_stamp {
             r.bbr_state.rtprop_stamp = now;
 
+            // Also refresh the path's minimum RTT from the samples taken
+            // while the queue was drained.
+            let rtprop = r.bbr_state.rtprop;
+
+            if !rtprop.is_zero() && rtprop != Duration::MAX {
+                r.refresh_min_rtt(rtprop, now);
+            }
+
             bbr_restore_cwnd(r);
             bbr_exit_probe_rtt(r, now);
         }
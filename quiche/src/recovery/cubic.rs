@@ -41,28 +41,100 @@ use crate::recovery;
 use crate::recovery::reno;
 
 use crate::recovery::Acked;
-use crate::recovery::CongestionControlOps;
+use crate::recovery::CongestionControl;
 use crate::recovery::Recovery;
 
-pub static CUBIC: CongestionControlOps = CongestionControlOps {
-    on_init,
-    reset,
-    on_packet_sent,
-    on_packets_acked,
-    congestion_event,
-    collapse_cwnd,
-    checkpoint,
-    rollback,
-    has_custom_pacing,
-    debug_fmt,
-};
+/// CUBIC congestion control.
+#[derive(Clone, Copy, Debug)]
+pub struct Cubic {
+    /// The multiplicative window decrease factor.
+    beta: f64,
+
+    /// The constant that determines the aggressiveness of the window growth.
+    c: f64,
+}
+
+impl Cubic {
+    pub fn new(beta: f64, c: f64) -> Self {
+        Cubic { beta, c }
+    }
+
+    /// Value of alpha_aimd in the beginning of congestion avoidance, which
+    /// makes CUBIC as fair as Reno in the AIMD friendly region.
+    fn alpha_aimd(&self) -> f64 {
+        3.0 * (1.0 - self.beta) / (1.0 + self.beta)
+    }
+}
+
+impl Default for Cubic {
+    fn default() -> Self {
+        Cubic::new(BETA_CUBIC, C)
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn on_init(&mut self, r: &mut Recovery) {
+        on_init(r)
+    }
+
+    fn reset(&mut self, r: &mut Recovery) {
+        reset(r)
+    }
+
+    fn on_packet_sent(
+        &mut self, r: &mut Recovery, sent_bytes: usize, now: Instant,
+    ) {
+        on_packet_sent(r, sent_bytes, now)
+    }
+
+    fn on_packets_acked(
+        &mut self, r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        on_packets_acked(self, r, packets, epoch, now)
+    }
+
+    fn congestion_event(
+        &mut self, r: &mut Recovery, lost_bytes: usize, time_sent: Instant,
+        epoch: packet::Epoch, now: Instant,
+    ) {
+        congestion_event(self, r, lost_bytes, time_sent, epoch, now)
+    }
+
+    fn collapse_cwnd(&mut self, r: &mut Recovery) {
+        collapse_cwnd(self, r)
+    }
+
+    fn checkpoint(&mut self, r: &mut Recovery) {
+        checkpoint(r)
+    }
+
+    fn rollback(&mut self, r: &mut Recovery) -> bool {
+        rollback(r)
+    }
+
+    fn has_custom_pacing(&self) -> bool {
+        has_custom_pacing()
+    }
+
+    fn debug_fmt(
+        &self, r: &Recovery, f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        debug_fmt(r, f)
+    }
+
+    fn clone_box(&self) -> Box<dyn CongestionControl> {
+        Box::new(*self)
+    }
+}
 
 /// CUBIC Constants.
 ///
-/// These are recommended value in RFC8312.
-const BETA_CUBIC: f64 = 0.7;
+/// These are recommended value in RFC8312, and are used unless others are set
+/// with `Config::set_cubic_beta()` and `Config::set_cubic_c()`.
+pub const BETA_CUBIC: f64 = 0.7;
 
-const C: f64 = 0.4;
+pub const C: f64 = 0.4;
 
 /// Threshold for rolling back state, as percentage of lost packets relative to
 /// cwnd.
@@ -71,9 +143,6 @@ const ROLLBACK_THRESHOLD_PERCENT: usize = 20;
 /// Minimum threshold for rolling back state, as number of packets.
 const MIN_ROLLBACK_THRESHOLD: usize = 2;
 
-/// Default value of alpha_aimd in the beginning of congestion avoidance.
-const ALPHA_AIMD: f64 = 3.0 * (1.0 - BETA_CUBIC) / (1.0 + BETA_CUBIC);
-
 /// CUBIC State Variables.
 ///
 /// We need to keep those variables across the connection.
@@ -123,18 +192,18 @@ struct PriorState {
 /// Unit of t (duration) and RTT are based on seconds (f64).
 impl State {
     // K = cubic_root ((w_max - cwnd) / C) (Eq. 2)
-    fn cubic_k(&self, cwnd: usize, max_datagram_size: usize) -> f64 {
+    fn cubic_k(&self, c: f64, cwnd: usize, max_datagram_size: usize) -> f64 {
         let w_max = self.w_max / max_datagram_size as f64;
         let cwnd = cwnd as f64 / max_datagram_size as f64;
 
-        libm::cbrt((w_max - cwnd) / C)
+        libm::cbrt((w_max - cwnd) / c)
     }
 
     // W_cubic(t) = C * (t - K)^3 + w_max (Eq. 1)
-    fn w_cubic(&self, t: Duration, max_datagram_size: usize) -> f64 {
+    fn w_cubic(&self, c: f64, t: Duration, max_datagram_size: usize) -> f64 {
         let w_max = self.w_max / max_datagram_size as f64;
 
-        (C * (t.as_secs_f64() - self.k).powi(3) + w_max) *
+        (c * (t.as_secs_f64() - self.k).powi(3) + w_max) *
             max_datagram_size as f64
     }
 
@@ -152,7 +221,7 @@ fn reset(r: &mut Recovery) {
     r.cubic_state = State::default();
 }
 
-fn collapse_cwnd(r: &mut Recovery) {
+fn collapse_cwnd(cc: &Cubic, r: &mut Recovery) {
     let cubic = &mut r.cubic_state;
 
     r.congestion_recovery_start_time = None;
@@ -160,7 +229,7 @@ fn collapse_cwnd(r: &mut Recovery) {
     cubic.w_max = r.congestion_window as f64;
 
     // 4.7 Timeout - reduce ssthresh based on BETA_CUBIC
-    r.ssthresh = (r.congestion_window as f64 * BETA_CUBIC) as usize;
+    r.ssthresh = (r.congestion_window as f64 * cc.beta) as usize;
     r.ssthresh = cmp::max(
         r.ssthresh,
         r.max_datagram_size * recovery::MINIMUM_WINDOW_PACKETS,
@@ -197,15 +266,17 @@ fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, now: Instant) {
 }
 
 fn on_packets_acked(
-    r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch, now: Instant,
+    cc: &Cubic, r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch,
+    now: Instant,
 ) {
     for pkt in packets {
-        on_packet_acked(r, pkt, epoch, now);
+        on_packet_acked(cc, r, pkt, epoch, now);
     }
 }
 
 fn on_packet_acked(
-    r: &mut Recovery, packet: &Acked, epoch: packet::Epoch, now: Instant,
+    cc: &Cubic, r: &mut Recovery, packet: &Acked, epoch: packet::Epoch,
+    now: Instant,
 ) {
     let in_congestion_recovery = r.in_congestion_recovery(packet.time_sent);
 
@@ -282,7 +353,7 @@ fn on_packet_acked(
                 r.cubic_state.k = 0.0;
 
                 r.cubic_state.w_est = r.congestion_window as f64;
-                r.cubic_state.alpha_aimd = ALPHA_AIMD;
+                r.cubic_state.alpha_aimd = cc.alpha_aimd();
             }
         } else {
             match r.congestion_recovery_start_time {
@@ -297,7 +368,7 @@ fn on_packet_acked(
                     r.cubic_state.k = 0.0;
 
                     r.cubic_state.w_est = r.congestion_window as f64;
-                    r.cubic_state.alpha_aimd = ALPHA_AIMD;
+                    r.cubic_state.alpha_aimd = cc.alpha_aimd();
                 },
             }
         }
@@ -305,7 +376,9 @@ fn on_packet_acked(
         let t = now.saturating_duration_since(ca_start_time);
 
         // target = w_cubic(t + rtt)
-        let target = r.cubic_state.w_cubic(t + r.min_rtt, r.max_datagram_size);
+        let target =
+            r.cubic_state
+                .w_cubic(cc.c, t + r.min_rtt, r.max_datagram_size);
 
         // Clipping target to [cwnd, 1.5 x cwnd]
         let target = f64::max(target, r.congestion_window as f64);
@@ -325,7 +398,9 @@ fn on_packet_acked(
 
         let mut cubic_cwnd = r.congestion_window;
 
-        if r.cubic_state.w_cubic(t, r.max_datagram_size) < r.cubic_state.w_est {
+        if r.cubic_state.w_cubic(cc.c, t, r.max_datagram_size) <
+            r.cubic_state.w_est
+        {
             // AIMD friendly region (W_cubic(t) < W_est)
             cubic_cwnd = cmp::max(cubic_cwnd, r.cubic_state.w_est as usize);
         } else {
@@ -347,7 +422,7 @@ fn on_packet_acked(
 }
 
 fn congestion_event(
-    r: &mut Recovery, _lost_bytes: usize, time_sent: Instant,
+    cc: &Cubic, r: &mut Recovery, _lost_bytes: usize, time_sent: Instant,
     epoch: packet::Epoch, now: Instant,
 ) {
     let in_congestion_recovery = r.in_congestion_recovery(time_sent);
@@ -357,15 +432,17 @@ fn congestion_event(
     if !in_congestion_recovery {
         r.congestion_recovery_start_time = Some(now);
 
-        // Fast convergence
+        // Fast convergence: when the window did not grow back to w_max since
+        // the last congestion event, another flow is likely competing for the
+        // bandwidth, so w_max is further reduced to release it faster.
         if (r.congestion_window as f64) < r.cubic_state.w_max {
             r.cubic_state.w_max =
-                r.congestion_window as f64 * (1.0 + BETA_CUBIC) / 2.0;
+                r.congestion_window as f64 * (1.0 + cc.beta) / 2.0;
         } else {
             r.cubic_state.w_max = r.congestion_window as f64;
         }
 
-        r.ssthresh = (r.congestion_window as f64 * BETA_CUBIC) as usize;
+        r.ssthresh = (r.congestion_window as f64 * cc.beta) as usize;
         r.ssthresh = cmp::max(
             r.ssthresh,
             r.max_datagram_size * recovery::MINIMUM_WINDOW_PACKETS,
@@ -376,14 +453,14 @@ fn congestion_event(
             0.0
         } else {
             r.cubic_state
-                .cubic_k(r.congestion_window, r.max_datagram_size)
+                .cubic_k(cc.c, r.congestion_window, r.max_datagram_size)
         };
 
         r.cubic_state.cwnd_inc =
-            (r.cubic_state.cwnd_inc as f64 * BETA_CUBIC) as usize;
+            (r.cubic_state.cwnd_inc as f64 * cc.beta) as usize;
 
         r.cubic_state.w_est = r.congestion_window as f64;
-        r.cubic_state.alpha_aimd = ALPHA_AIMD;
+        r.cubic_state.alpha_aimd = cc.alpha_aimd();
 
         if r.hystart.in_css(epoch) {
             r.hystart.congestion_event();
@@ -485,6 +562,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -533,6 +611,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -602,6 +681,38 @@ mod tests {
         assert_eq!(prev_cwnd as f64 * BETA_CUBIC, r.cwnd() as f64);
     }
 
+    #[test]
+    fn cubic_congestion_event_custom_beta() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::CUBIC);
+        assert_eq!(
+            cfg.set_cubic_beta(1.0),
+            Err(crate::Error::CongestionControl)
+        );
+        assert_eq!(cfg.set_cubic_c(0.0), Err(crate::Error::CongestionControl));
+        assert_eq!(cfg.set_cubic_beta(0.8), Ok(()));
+        assert_eq!(cfg.set_cubic_c(0.8), Ok(()));
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+        let prev_cwnd = r.cwnd();
+
+        r.congestion_event(
+            r.max_datagram_size,
+            now,
+            packet::Epoch::Application,
+            now,
+        );
+
+        assert_eq!((prev_cwnd as f64 * 0.8) as usize, r.cwnd());
+        assert_eq!(r.cubic_state.alpha_aimd, 3.0 * 0.2 / 1.8);
+
+        // K is computed with the custom C.
+        let w_max = prev_cwnd as f64 / r.max_datagram_size as f64;
+        let cwnd = r.cwnd() as f64 / r.max_datagram_size as f64;
+        assert_eq!(r.cubic_state.k, libm::cbrt((w_max - cwnd) / 0.8));
+    }
+
     #[test]
     fn cubic_congestion_avoidance() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -731,6 +842,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         // 1st round.
@@ -879,6 +991,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         // 1st round.
@@ -29,6 +29,7 @@
 //! This implements the algorithm for estimating delivery rate as described in
 //! <https://tools.ietf.org/html/draft-cheng-iccrg-delivery-rate-estimation-01>
 
+use std::net::SocketAddr;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -54,6 +55,9 @@ pub struct Rate {
 
     // Sample of rate estimation.
     rate_sample: RateSample,
+
+    // Number of rate samples generated.
+    samples: u64,
 }
 
 impl Default for Rate {
@@ -74,6 +78,8 @@ impl Default for Rate {
             largest_acked: 0,
 
             rate_sample: RateSample::default(),
+
+            samples: 0,
         }
     }
 }
@@ -148,6 +154,8 @@ impl Rate {
                 self.rate_sample.delivery_rate =
                     (self.rate_sample.delivered as f64 / interval.as_secs_f64())
                         as u64;
+
+                self.samples += 1;
             }
         }
     }
@@ -175,6 +183,60 @@ impl Rate {
     pub fn sample_is_app_limited(&self) -> bool {
         self.rate_sample.is_app_limited
     }
+
+    /// Returns the number of rate samples generated so far.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Returns the most recent rate sample.
+    pub fn sample(&self) -> DeliveryRateSample {
+        DeliveryRateSample {
+            delivery_rate: self.rate_sample.delivery_rate,
+            is_app_limited: self.rate_sample.is_app_limited,
+            interval: self.rate_sample.interval,
+            delivered: self.rate_sample.delivered,
+            rtt: self.rate_sample.rtt,
+        }
+    }
+}
+
+/// A delivery rate sample, generated when packets are acknowledged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeliveryRateSample {
+    /// The estimated delivery rate, in bytes per second.
+    pub delivery_rate: u64,
+
+    /// Whether the sample was taken while the sender was application-limited,
+    /// in which case the rate underestimates the path's capacity.
+    pub is_app_limited: bool,
+
+    /// The interval over which the rate was measured.
+    pub interval: Duration,
+
+    /// The number of bytes delivered during the interval.
+    pub delivered: usize,
+
+    /// The RTT of the most recently acknowledged packet.
+    pub rtt: Duration,
+}
+
+/// Receives the delivery rate samples of connections.
+///
+/// This can be set with [`Config::set_delivery_rate_observer()`], to
+/// implement BBR-style bandwidth probing or adaptive bitrate logic in the
+/// application. The observer is called synchronously when processing ACK
+/// frames, so it should be cheap.
+///
+/// [`Config::set_delivery_rate_observer()`]:
+/// struct.Config.html#method.set_delivery_rate_observer
+pub trait DeliveryRateObserver: Send + Sync {
+    /// Called when a new rate sample is generated for the path between
+    /// `local_addr` and `peer_addr`.
+    fn on_rate_sample(
+        &self, local_addr: SocketAddr, peer_addr: SocketAddr,
+        sample: &DeliveryRateSample,
+    );
 }
 
 #[derive(Default, Debug)]
@@ -230,6 +292,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -294,6 +357,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -333,6 +397,7 @@ mod tests {
                 first_sent_time: now,
                 is_app_limited: false,
                 has_data: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -41,10 +41,15 @@ use crate::minmax;
 use crate::packet;
 use crate::ranges;
 
+#[cfg(feature = "plugins")]
 use pluginop::api::ToPluginizableConnection;
+#[cfg(feature = "plugins")]
 use pluginop::common::PluginOp;
+#[cfg(feature = "plugins")]
 use pluginop::pluginop_macro::pluginop;
+#[cfg(feature = "plugins")]
 use pluginop::ParentReferencer;
+#[cfg(feature = "plugins")]
 use pluginop::PluginizableConnection;
 #[cfg(feature = "qlog")]
 use qlog::events::EventData;
@@ -64,7 +69,7 @@ const INITIAL_RTT: Duration = Duration::from_millis(333);
 
 const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
 
-const RTT_WINDOW: Duration = Duration::from_secs(300);
+pub(crate) const RTT_WINDOW: Duration = Duration::from_secs(300);
 
 const MAX_PTO_PROBES_COUNT: usize = 2;
 
@@ -81,8 +86,14 @@ const PACING_MULTIPLIER: f64 = 1.25;
 // an ACK.
 pub(super) const MAX_OUTSTANDING_NON_ACK_ELICITING: usize = 24;
 
+/// The loss recovery and congestion control state of a path.
+///
+/// This is passed to the methods of [`CongestionControl`].
+///
+/// [`CongestionControl`]: trait.CongestionControl.html
 pub struct Recovery {
     /// The pluginized connection.
+    #[cfg(feature = "plugins")]
     pc: Option<ParentReferencer<PluginizableConnection<crate::Connection>>>,
 
     loss_detection_timer: Option<Instant>,
@@ -100,27 +111,62 @@ pub struct Recovery {
 
     smoothed_rtt: Option<Duration>,
 
+    /// The RTT estimate provided by the application, used until an RTT sample
+    /// is taken.
+    initial_rtt: Option<Duration>,
+
     rttvar: Duration,
 
     minmax_filter: minmax::Minmax<Duration>,
 
     min_rtt: Duration,
 
-    pub max_ack_delay: Duration,
+    /// How long an RTT sample is considered for the minimum RTT.
+    min_rtt_expiry: Duration,
+
+    /// The number of times the minimum RTT increased after older samples
+    /// expired.
+    min_rtt_refreshes: u64,
+
+    /// The number of times the sender became limited by the congestion
+    /// window.
+    cwnd_limited_count: u64,
+
+    /// The number of persistent congestion episodes.
+    persistent_congestion_count: u64,
+
+    /// The number of bursts of consecutive packets declared lost.
+    loss_bursts: u64,
+
+    pub(crate) max_ack_delay: Duration,
 
     loss_time: [Option<Instant>; packet::Epoch::count()],
 
     sent: [VecDeque<Sent>; packet::Epoch::count()],
 
-    pub lost: [Vec<frame::Frame>; packet::Epoch::count()],
+    pub(crate) lost: [Vec<frame::Frame>; packet::Epoch::count()],
+
+    pub(crate) acked: [Vec<frame::Frame>; packet::Epoch::count()],
+
+    pub(crate) lost_count: usize,
 
-    pub acked: [Vec<frame::Frame>; packet::Epoch::count()],
+    pub(crate) lost_spurious_count: usize,
 
-    pub lost_count: usize,
+    pub(crate) loss_probes: [usize; packet::Epoch::count()],
 
-    pub lost_spurious_count: usize,
+    /// The number of consecutive lost packets larger than the minimum QUIC
+    /// packet size, used to detect PMTU black holes.
+    pub(crate) large_pkts_lost: usize,
 
-    pub loss_probes: [usize; packet::Epoch::count()],
+    /// The number of packets marked with an ECN codepoint that were
+    /// acknowledged.
+    pub(crate) ecn_marked_acked: usize,
+
+    /// The number of packets marked with an ECN codepoint that were lost.
+    pub(crate) ecn_marked_lost: usize,
+
+    /// When the largest packet acknowledged by the last ACK frame was sent.
+    largest_newly_acked_time_sent: Option<Instant>,
 
     in_flight_count: [usize; packet::Epoch::count()],
 
@@ -132,13 +178,17 @@ pub struct Recovery {
 
     time_thresh: f64,
 
-    // Congestion control.
-    cc_ops: &'static CongestionControlOps,
+    // Congestion control. This is only `None` while one of its methods is
+    // being called.
+    cc: Option<Box<dyn CongestionControl>>,
 
+    /// The congestion window, in bytes.
     pub congestion_window: usize,
 
-    bytes_in_flight: usize,
+    /// The number of bytes sent and not yet acknowledged or declared lost.
+    pub bytes_in_flight: usize,
 
+    /// The slow start threshold, in bytes.
     pub ssthresh: usize,
 
     bytes_acked_sl: usize,
@@ -147,9 +197,10 @@ pub struct Recovery {
 
     bytes_sent: usize,
 
-    pub bytes_lost: u64,
+    pub(crate) bytes_lost: u64,
 
-    congestion_recovery_start_time: Option<Instant>,
+    /// The time at which the current congestion recovery period started.
+    pub congestion_recovery_start_time: Option<Instant>,
 
     max_datagram_size: usize,
 
@@ -159,7 +210,10 @@ pub struct Recovery {
     hystart: hystart::Hystart,
 
     // Pacing.
-    pub pacer: pacer::Pacer,
+    pub(crate) pacer: pacer::Pacer,
+
+    /// The maximum pacing rate, in bytes per second.
+    max_pacing_rate: Option<u64>,
 
     // RFC6937 PRR.
     prr: prr::PRR,
@@ -179,31 +233,68 @@ pub struct Recovery {
 }
 
 pub struct RecoveryConfig {
-    max_send_udp_payload_size: usize,
+    pub(crate) max_send_udp_payload_size: usize,
     pub max_ack_delay: Duration,
-    cc_ops: &'static CongestionControlOps,
+    cc: Box<dyn CongestionControl>,
     hystart: bool,
     pacing: bool,
+    pacing_granularity: Option<Duration>,
+    initial_rtt: Option<Duration>,
+    min_rtt_expiry: Duration,
+    pub(crate) pmtud: bool,
+    pub(crate) max_pacing_rate: Option<u64>,
 }
 
 impl RecoveryConfig {
     pub fn from_config(config: &Config) -> Self {
+        let cc = match &config.custom_cc {
+            Some(cc) => cc.clone_box(),
+
+            None => match config.cc_algorithm {
+                CongestionControlAlgorithm::CUBIC => {
+                    let (beta, c) = (config.cubic_beta, config.cubic_c);
+
+                    Box::new(cubic::Cubic::new(beta, c))
+                },
+
+                algo => algo.into(),
+            },
+        };
+
         Self {
             max_send_udp_payload_size: config.max_send_udp_payload_size,
             max_ack_delay: Duration::ZERO,
-            cc_ops: config.cc_algorithm.into(),
+            cc,
             hystart: config.hystart,
             pacing: config.pacing,
+            pacing_granularity: config.pacing_granularity,
+            initial_rtt: config.initial_rtt,
+            min_rtt_expiry: config.min_rtt_expiry,
+            pmtud: config.pmtud,
+            max_pacing_rate: None,
         }
     }
 }
 
 impl Recovery {
-    pub fn new_with_config(recovery_config: &RecoveryConfig) -> Self {
+    pub(crate) fn new_with_config(recovery_config: &RecoveryConfig) -> Self {
+        // With PMTU discovery, packets larger than the minimum QUIC packet
+        // size are only sent once the path is known to support them.
+        let max_datagram_size = if recovery_config.pmtud {
+            crate::MIN_CLIENT_INITIAL_LEN
+        } else {
+            recovery_config.max_send_udp_payload_size
+        };
+
         let initial_congestion_window =
-            recovery_config.max_send_udp_payload_size * INITIAL_WINDOW_PACKETS;
+            max_datagram_size * INITIAL_WINDOW_PACKETS;
+
+        let initial_rtt = recovery_config
+            .initial_rtt
+            .map(|rtt| cmp::max(rtt, GRANULARITY));
 
         Recovery {
+            #[cfg(feature = "plugins")]
             pc: None,
 
             loss_detection_timer: None,
@@ -218,17 +309,29 @@ impl Recovery {
 
             latest_rtt: Duration::ZERO,
 
-            // This field should be initialized to `INITIAL_RTT` for the initial
-            // PTO calculation, but it also needs to be an `Option` to track
-            // whether any RTT sample was received, so the initial value is
-            // handled by the `rtt()` method instead.
+            // This field should be initialized to the initial RTT for the
+            // initial PTO calculation, but it also needs to be an `Option` to
+            // track whether any RTT sample was received, so the initial value
+            // is handled by the `rtt()` method instead.
             smoothed_rtt: None,
 
+            initial_rtt,
+
             minmax_filter: minmax::Minmax::new(Duration::ZERO),
 
             min_rtt: Duration::ZERO,
 
-            rttvar: INITIAL_RTT / 2,
+            min_rtt_expiry: recovery_config.min_rtt_expiry,
+
+            min_rtt_refreshes: 0,
+
+            cwnd_limited_count: 0,
+
+            persistent_congestion_count: 0,
+
+            loss_bursts: 0,
+
+            rttvar: initial_rtt.unwrap_or(INITIAL_RTT) / 2,
 
             max_ack_delay: recovery_config.max_ack_delay,
 
@@ -245,6 +348,14 @@ impl Recovery {
 
             loss_probes: [0; packet::Epoch::count()],
 
+            large_pkts_lost: 0,
+
+            ecn_marked_acked: 0,
+
+            ecn_marked_lost: 0,
+
+            largest_newly_acked_time_sent: None,
+
             in_flight_count: [0; packet::Epoch::count()],
 
             congestion_window: initial_congestion_window,
@@ -267,9 +378,9 @@ impl Recovery {
 
             congestion_recovery_start_time: None,
 
-            max_datagram_size: recovery_config.max_send_udp_payload_size,
+            max_datagram_size,
 
-            cc_ops: recovery_config.cc_ops,
+            cc: Some(recovery_config.cc.clone_box()),
 
             delivery_rate: delivery_rate::Rate::default(),
 
@@ -281,11 +392,14 @@ impl Recovery {
 
             pacer: pacer::Pacer::new(
                 recovery_config.pacing,
+                recovery_config.pacing_granularity,
                 initial_congestion_window,
                 0,
-                recovery_config.max_send_udp_payload_size,
+                max_datagram_size,
             ),
 
+            max_pacing_rate: recovery_config.max_pacing_rate,
+
             prr: prr::PRR::default(),
 
             send_quantum: initial_congestion_window,
@@ -299,33 +413,47 @@ impl Recovery {
         }
     }
 
-    pub fn new(config: &Config) -> Self {
+    pub(crate) fn new(config: &Config) -> Self {
         Self::new_with_config(&RecoveryConfig::from_config(config))
     }
 
-    pub fn on_init(&mut self) {
-        (self.cc_ops.on_init)(self);
+    pub(crate) fn on_init(&mut self) {
+        self.with_cc(|cc, r| cc.on_init(r));
+    }
+
+    /// Calls `f` with the congestion control algorithm, which is temporarily
+    /// taken out of `self` so that it can modify the recovery state.
+    fn with_cc<T>(
+        &mut self, f: impl FnOnce(&mut dyn CongestionControl, &mut Self) -> T,
+    ) -> T {
+        let mut cc = self.cc.take().expect("congestion control in use");
+
+        let v = f(cc.as_mut(), self);
+
+        self.cc = Some(cc);
+
+        v
     }
 
-    pub fn reset(&mut self) {
+    pub(crate) fn reset(&mut self) {
         self.congestion_window = self.max_datagram_size * INITIAL_WINDOW_PACKETS;
         self.in_flight_count = [0; packet::Epoch::count()];
         self.congestion_recovery_start_time = None;
         self.ssthresh = std::usize::MAX;
-        (self.cc_ops.reset)(self);
+        self.with_cc(|cc, r| cc.reset(r));
         self.hystart.reset();
         self.prr = prr::PRR::default();
     }
 
     /// Returns whether or not we should elicit an ACK even if we wouldn't
     /// otherwise have constructed an ACK eliciting packet.
-    pub fn should_elicit_ack(&self, epoch: packet::Epoch) -> bool {
+    pub(crate) fn should_elicit_ack(&self, epoch: packet::Epoch) -> bool {
         self.loss_probes[epoch] > 0 ||
             self.outstanding_non_ack_eliciting >=
                 MAX_OUTSTANDING_NON_ACK_ELICITING
     }
 
-    pub fn on_packet_sent(
+    pub(crate) fn on_packet_sent(
         &mut self, mut pkt: Sent, epoch: packet::Epoch,
         handshake_status: HandshakeStatus, now: Instant, trace_id: &str,
     ) {
@@ -350,6 +478,12 @@ impl Recovery {
 
             self.in_flight_count[epoch] += 1;
 
+            if self.bytes_in_flight < self.congestion_window &&
+                self.bytes_in_flight + sent_bytes >= self.congestion_window
+            {
+                self.cwnd_limited_count += 1;
+            }
+
             self.update_app_limited(
                 (self.bytes_in_flight + sent_bytes) < self.congestion_window,
             );
@@ -370,8 +504,14 @@ impl Recovery {
         }
 
         // Pacing: Set the pacing rate if CC doesn't do its own.
-        if !(self.cc_ops.has_custom_pacing)() {
-            if let Some(srtt) = self.smoothed_rtt {
+        let custom_pacing = self
+            .cc
+            .as_ref()
+            .map(|cc| cc.has_custom_pacing())
+            .unwrap_or(false);
+
+        if !custom_pacing {
+            if let Some(srtt) = self.smoothed_rtt.or(self.initial_rtt) {
                 let rate = PACING_MULTIPLIER * self.congestion_window as f64 /
                     srtt.as_secs_f64();
                 self.set_pacing_rate(rate as u64, now);
@@ -393,14 +533,36 @@ impl Recovery {
     }
 
     fn on_packet_sent_cc(&mut self, sent_bytes: usize, now: Instant) {
-        (self.cc_ops.on_packet_sent)(self, sent_bytes, now);
+        self.with_cc(|cc, r| cc.on_packet_sent(r, sent_bytes, now));
     }
 
+    /// Sets the pacing rate, in bytes per second.
+    ///
+    /// The rate is capped to the maximum pacing rate, if any.
     pub fn set_pacing_rate(&mut self, rate: u64, now: Instant) {
+        let rate = match self.max_pacing_rate {
+            // A zero rate disables pacing, so use the maximum rate instead.
+            Some(max) if rate == 0 => max,
+
+            Some(max) => cmp::min(rate, max),
+
+            None => rate,
+        };
+
         self.pacer.update(self.send_quantum, rate, now);
     }
 
-    pub fn get_packet_send_time(&self) -> Instant {
+    /// Sets the maximum pacing rate, in bytes per second.
+    ///
+    /// Packets are paced at this rate at most, even when pacing is disabled,
+    /// or when the congestion controller allows a higher rate.
+    pub fn set_max_pacing_rate(&mut self, rate: Option<u64>, now: Instant) {
+        self.max_pacing_rate = rate;
+
+        self.set_pacing_rate(self.pacer.rate(), now);
+    }
+
+    pub(crate) fn get_packet_send_time(&self) -> Instant {
         self.pacer.next_time()
     }
 
@@ -417,7 +579,10 @@ impl Recovery {
         let in_initcwnd =
             self.bytes_sent < self.max_datagram_size * INITIAL_WINDOW_PACKETS;
 
-        let sent_bytes = if !self.pacer.enabled() || !is_app || in_initcwnd {
+        // A maximum pacing rate is enforced regardless of these.
+        let paced = self.pacer.enabled() && is_app && !in_initcwnd;
+
+        let sent_bytes = if !paced && self.max_pacing_rate.is_none() {
             0
         } else {
             packet_size
@@ -426,7 +591,7 @@ impl Recovery {
         self.pacer.send(sent_bytes, now);
     }
 
-    pub fn on_ack_received(
+    pub(crate) fn on_ack_received(
         &mut self, ranges: &ranges::RangeSet, ack_delay: u64,
         epoch: packet::Epoch, handshake_status: HandshakeStatus, now: Instant,
         trace_id: &str,
@@ -512,6 +677,14 @@ impl Recovery {
                 largest_newly_acked_pkt_num = unacked.pkt_num;
                 largest_newly_acked_sent_time = unacked.time_sent;
 
+                if unacked.size > crate::MIN_CLIENT_INITIAL_LEN {
+                    self.large_pkts_lost = 0;
+                }
+
+                if unacked.ecn_marked {
+                    self.ecn_marked_acked += 1;
+                }
+
                 self.acked[epoch].extend(unacked.frames.drain(..));
 
                 if unacked.in_flight {
@@ -543,13 +716,16 @@ impl Recovery {
 
         // Undo congestion window update.
         if undo_cwnd {
-            (self.cc_ops.rollback)(self);
+            self.with_cc(|cc, r| cc.rollback(r));
         }
 
         if newly_acked.is_empty() {
+            self.largest_newly_acked_time_sent = None;
             return Ok((0, 0));
         }
 
+        self.largest_newly_acked_time_sent = Some(largest_newly_acked_sent_time);
+
         if largest_newly_acked_pkt_num == largest_acked && has_ack_eliciting {
             // The packet's sent time could be in the future if pacing is used
             // and the network has a very short RTT.
@@ -584,7 +760,7 @@ impl Recovery {
         Ok((lost_packets, lost_bytes))
     }
 
-    pub fn on_loss_detection_timeout(
+    pub(crate) fn on_loss_detection_timeout(
         &mut self, handshake_status: HandshakeStatus, now: Instant,
         trace_id: &str,
     ) -> (usize, usize) {
@@ -650,7 +826,7 @@ impl Recovery {
         (0, 0)
     }
 
-    pub fn on_pkt_num_space_discarded(
+    pub(crate) fn on_pkt_num_space_discarded(
         &mut self, epoch: packet::Epoch, handshake_status: HandshakeStatus,
         now: Instant,
     ) {
@@ -675,14 +851,80 @@ impl Recovery {
         self.set_loss_detection_timer(handshake_status, now);
     }
 
-    pub fn loss_detection_timer(&self) -> Option<Instant> {
+    /// Reschedules the frames sent in 0-RTT packets after the server rejected
+    /// early data, and returns the number of affected packets.
+    ///
+    /// The packets are removed from the bytes in flight without triggering a
+    /// congestion event, as they were discarded by the server rather than lost
+    /// in the network.
+    pub(crate) fn on_0rtt_rejected(
+        &mut self, handshake_status: HandshakeStatus, now: Instant,
+    ) -> usize {
+        let epoch = packet::Epoch::Application;
+
+        let mut rejected = 0;
+
+        let unacked_iter = self.sent[epoch]
+            .iter_mut()
+            .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
+
+        for unacked in unacked_iter {
+            if unacked.in_flight {
+                self.bytes_in_flight =
+                    self.bytes_in_flight.saturating_sub(unacked.size);
+            }
+
+            self.lost[epoch].extend(unacked.frames.drain(..));
+
+            unacked.time_lost = Some(now);
+
+            rejected += 1;
+        }
+
+        self.time_of_last_sent_ack_eliciting_pkt[epoch] = None;
+        self.loss_time[epoch] = None;
+        self.in_flight_count[epoch] = 0;
+
+        self.set_loss_detection_timer(handshake_status, now);
+
+        rejected
+    }
+
+    /// Re-arms the loss detection timer after the connection was suspended.
+    ///
+    /// Packets sent before the suspension are considered as sent at `now`, so
+    /// that the PTO is computed from the resumption point rather than firing
+    /// right away. Pending time threshold losses are left to be detected when
+    /// the next ACK is received.
+    pub(crate) fn on_resume(
+        &mut self, handshake_status: HandshakeStatus, now: Instant,
+    ) {
+        self.pto_count = 0;
+
+        for &e in packet::Epoch::epochs(
+            packet::Epoch::Initial..=packet::Epoch::Application,
+        ) {
+            if self.time_of_last_sent_ack_eliciting_pkt[e].is_some() {
+                self.time_of_last_sent_ack_eliciting_pkt[e] = Some(now);
+            }
+
+            self.loss_time[e] = None;
+        }
+
+        self.set_loss_detection_timer(handshake_status, now);
+    }
+
+    pub(crate) fn loss_detection_timer(&self) -> Option<Instant> {
         self.loss_detection_timer
     }
 
+    /// Returns the congestion window, in bytes.
     pub fn cwnd(&self) -> usize {
         self.congestion_window
     }
 
+    /// Returns the number of bytes that can be sent according to the
+    /// congestion window.
     pub fn cwnd_available(&self) -> usize {
         // Ignore cwnd when sending probe packets.
         if self.loss_probes.iter().any(|&x| x > 0) {
@@ -694,26 +936,105 @@ impl Recovery {
             self.prr.snd_cnt
     }
 
+    /// Returns the smoothed RTT.
     pub fn rtt(&self) -> Duration {
-        self.smoothed_rtt.unwrap_or(INITIAL_RTT)
+        self.smoothed_rtt
+            .or(self.initial_rtt)
+            .unwrap_or(INITIAL_RTT)
+    }
+
+    /// Returns the minimum RTT observed.
+    pub fn min_rtt(&self) -> Duration {
+        self.min_rtt
+    }
+
+    /// Returns the number of times the minimum RTT was refreshed.
+    pub fn min_rtt_refreshes(&self) -> u64 {
+        self.min_rtt_refreshes
+    }
+
+    /// Returns the most recent RTT sample.
+    pub fn latest_rtt(&self) -> Duration {
+        self.latest_rtt
+    }
+
+    /// Returns the RTT variation.
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// Returns the number of consecutive probe timeouts.
+    pub fn pto_count(&self) -> u32 {
+        self.pto_count
+    }
+
+    /// Returns the current pacing rate, in bytes per second.
+    pub fn pacing_rate(&self) -> u64 {
+        self.pacer.rate()
+    }
+
+    /// Returns the number of times the sender became limited by the
+    /// congestion window.
+    pub fn cwnd_limited_count(&self) -> u64 {
+        self.cwnd_limited_count
+    }
+
+    /// Returns the number of persistent congestion episodes.
+    pub fn persistent_congestion_count(&self) -> u64 {
+        self.persistent_congestion_count
+    }
+
+    /// Returns the number of bursts of consecutive packets declared lost.
+    pub fn loss_bursts(&self) -> u64 {
+        self.loss_bursts
+    }
+
+    /// Replaces the minimum RTT with the given sample, discarding older ones.
+    pub(crate) fn refresh_min_rtt(&mut self, rtt: Duration, now: Instant) {
+        if rtt > self.min_rtt {
+            self.min_rtt_refreshes += 1;
+        }
+
+        self.min_rtt = self.minmax_filter.reset(now, rtt);
     }
 
+    /// Returns the probe timeout duration.
     pub fn pto(&self) -> Duration {
         self.rtt() + cmp::max(self.rttvar * 4, GRANULARITY)
     }
 
+    /// Returns the most recent delivery rate sample, in bytes per second.
     pub fn delivery_rate(&self) -> u64 {
         self.delivery_rate.sample_delivery_rate()
     }
 
+    /// Returns the most recent delivery rate sample.
+    pub fn delivery_rate_sample(&self) -> DeliveryRateSample {
+        self.delivery_rate.sample()
+    }
+
+    /// Returns the number of delivery rate samples generated so far.
+    pub(crate) fn delivery_rate_samples(&self) -> u64 {
+        self.delivery_rate.samples()
+    }
+
+    /// Returns the maximum size of the packets sent on the path.
     pub fn max_datagram_size(&self) -> usize {
         self.max_datagram_size
     }
 
-    pub fn update_max_datagram_size(&mut self, new_max_datagram_size: usize) {
+    pub(crate) fn update_max_datagram_size(
+        &mut self, new_max_datagram_size: usize,
+    ) {
         let max_datagram_size =
             cmp::min(self.max_datagram_size, new_max_datagram_size);
 
+        self.set_max_datagram_size(max_datagram_size);
+    }
+
+    /// Sets the maximum size of the packets sent on the path, e.g. after the
+    /// path MTU changed.
+    pub(crate) fn set_max_datagram_size(&mut self, max_datagram_size: usize) {
         // Update cwnd if it hasn't been updated yet.
         if self.congestion_window ==
             self.max_datagram_size * INITIAL_WINDOW_PACKETS
@@ -723,6 +1044,7 @@ impl Recovery {
 
         self.pacer = pacer::Pacer::new(
             self.pacer.enabled(),
+            self.pacer.granularity(),
             self.congestion_window,
             0,
             max_datagram_size,
@@ -731,7 +1053,7 @@ impl Recovery {
         self.max_datagram_size = max_datagram_size;
     }
 
-    #[pluginop(po = "PluginOp::UpdateRtt")]
+    #[cfg_attr(feature = "plugins", pluginop(po = "PluginOp::UpdateRtt"))]
     fn update_rtt(
         &mut self, latest_rtt: Duration, ack_delay: Duration, now: Instant,
     ) {
@@ -748,8 +1070,18 @@ impl Recovery {
             },
 
             Some(srtt) => {
-                self.min_rtt =
-                    self.minmax_filter.running_min(RTT_WINDOW, now, latest_rtt);
+                let min_rtt = self.minmax_filter.running_min(
+                    self.min_rtt_expiry,
+                    now,
+                    latest_rtt,
+                );
+
+                // The minimum RTT can only increase once older samples expired.
+                if min_rtt > self.min_rtt {
+                    self.min_rtt_refreshes += 1;
+                }
+
+                self.min_rtt = min_rtt;
 
                 let ack_delay = cmp::min(self.max_ack_delay, ack_delay);
 
@@ -878,6 +1210,10 @@ impl Recovery {
 
         let mut largest_lost_pkt = None;
 
+        // The packet number of the previous packet declared lost, and whether
+        // it was part of a burst.
+        let mut prev_lost: Option<(u64, bool)> = None;
+
         let unacked_iter = self.sent[epoch]
             .iter_mut()
             // Skip packets that follow the largest acked packet.
@@ -890,16 +1226,34 @@ impl Recovery {
             if unacked.time_sent <= lost_send_time ||
                 largest_acked >= unacked.pkt_num + self.pkt_thresh
             {
+                let is_pmtu_probe = unacked.frames.iter().any(|f| {
+                    matches!(f, frame::Frame::Ping { mtu_probe: Some(_) })
+                });
+
                 self.lost[epoch].extend(unacked.frames.drain(..));
 
                 unacked.time_lost = Some(now);
 
-                if unacked.in_flight {
-                    lost_bytes += unacked.size;
+                if unacked.ecn_marked {
+                    self.ecn_marked_lost += 1;
+                }
 
-                    // Frames have already been removed from the packet, so
-                    // cloning the whole packet should be relatively cheap.
-                    largest_lost_pkt = Some(unacked.clone());
+                if unacked.in_flight {
+                    // The loss of a PMTU probe isn't a sign of congestion.
+                    if is_pmtu_probe {
+                        self.bytes_in_flight =
+                            self.bytes_in_flight.saturating_sub(unacked.size);
+                    } else {
+                        lost_bytes += unacked.size;
+
+                        if unacked.size > crate::MIN_CLIENT_INITIAL_LEN {
+                            self.large_pkts_lost += 1;
+                        }
+
+                        // Frames have already been removed from the packet, so
+                        // cloning the whole packet should be relatively cheap.
+                        largest_lost_pkt = Some(unacked.clone());
+                    }
 
                     self.in_flight_count[epoch] =
                         self.in_flight_count[epoch].saturating_sub(1);
@@ -912,6 +1266,18 @@ impl Recovery {
                     );
                 }
 
+                let in_burst = matches!(
+                    prev_lost,
+                    Some((pkt_num, _)) if pkt_num + 1 == unacked.pkt_num
+                );
+
+                // Only count the start of each burst.
+                if in_burst && !prev_lost.map_or(false, |(_, v)| v) {
+                    self.loss_bursts += 1;
+                }
+
+                prev_lost = Some((unacked.pkt_num, in_burst));
+
                 lost_packets += 1;
                 self.lost_count += 1;
             } else {
@@ -980,10 +1346,12 @@ impl Recovery {
         self.delivery_rate.generate_rate_sample(self.min_rtt);
 
         // Call congestion control hooks.
-        (self.cc_ops.on_packets_acked)(self, &acked, epoch, now);
+        self.with_cc(|cc, r| cc.on_packets_acked(r, &acked, epoch, now));
     }
 
-    fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
+    /// Returns whether a packet sent at `sent_time` was sent before the start
+    /// of the current congestion recovery period.
+    pub fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
         match self.congestion_recovery_start_time {
             Some(congestion_recovery_start_time) =>
                 sent_time <= congestion_recovery_start_time,
@@ -1008,39 +1376,53 @@ impl Recovery {
         self.congestion_event(lost_bytes, largest_lost_pkt.time_sent, epoch, now);
 
         if self.in_persistent_congestion(largest_lost_pkt.pkt_num) {
+            self.persistent_congestion_count += 1;
+
             self.collapse_cwnd();
         }
     }
 
+    /// Handles an increase of the number of packets the peer reported as
+    /// marked ECN-CE, which signals congestion like a lost packet does.
+    pub(crate) fn on_ecn_ce(&mut self, epoch: packet::Epoch, now: Instant) {
+        if let Some(time_sent) = self.largest_newly_acked_time_sent {
+            self.congestion_event(0, time_sent, epoch, now);
+        }
+    }
+
     fn congestion_event(
         &mut self, lost_bytes: usize, time_sent: Instant, epoch: packet::Epoch,
         now: Instant,
     ) {
         if !self.in_congestion_recovery(time_sent) {
-            (self.cc_ops.checkpoint)(self);
+            self.with_cc(|cc, r| cc.checkpoint(r));
         }
 
-        (self.cc_ops.congestion_event)(self, lost_bytes, time_sent, epoch, now);
+        self.with_cc(|cc, r| {
+            cc.congestion_event(r, lost_bytes, time_sent, epoch, now)
+        });
     }
 
     fn collapse_cwnd(&mut self) {
-        (self.cc_ops.collapse_cwnd)(self);
+        self.with_cc(|cc, r| cc.collapse_cwnd(r));
     }
 
-    pub fn update_app_limited(&mut self, v: bool) {
+    pub(crate) fn update_app_limited(&mut self, v: bool) {
         self.app_limited = v;
     }
 
+    /// Returns whether sending is limited by the application rather than by
+    /// the congestion window.
     pub fn app_limited(&self) -> bool {
         self.app_limited
     }
 
-    pub fn delivery_rate_update_app_limited(&mut self, v: bool) {
+    pub(crate) fn delivery_rate_update_app_limited(&mut self, v: bool) {
         self.delivery_rate.update_app_limited(v);
     }
 
     #[cfg(feature = "qlog")]
-    pub fn maybe_qlog(&mut self) -> Option<EventData> {
+    pub(crate) fn maybe_qlog(&mut self) -> Option<EventData> {
         let qlog_metrics = QlogMetrics {
             min_rtt: self.min_rtt,
             smoothed_rtt: self.rtt(),
@@ -1055,11 +1437,12 @@ impl Recovery {
         self.qlog_metrics.maybe_update(qlog_metrics)
     }
 
-    pub fn send_quantum(&self) -> usize {
+    pub(crate) fn send_quantum(&self) -> usize {
         self.send_quantum
     }
 }
 
+#[cfg(feature = "plugins")]
 impl ToPluginizableConnection<crate::Connection> for Recovery {
     fn set_pluginizable_connection(
         &mut self, pc: *mut PluginizableConnection<crate::Connection>,
@@ -1106,46 +1489,94 @@ impl FromStr for CongestionControlAlgorithm {
     }
 }
 
-pub struct CongestionControlOps {
-    pub on_init: fn(r: &mut Recovery),
-
-    pub reset: fn(r: &mut Recovery),
-
-    pub on_packet_sent: fn(r: &mut Recovery, sent_bytes: usize, now: Instant),
-
-    pub on_packets_acked: fn(
-        r: &mut Recovery,
-        packets: &[Acked],
-        epoch: packet::Epoch,
+/// A congestion control algorithm.
+///
+/// The built-in algorithms selected with [`set_cc_algorithm()`] implement
+/// this trait, and applications can provide their own implementation with
+/// [`set_custom_congestion_control()`].
+///
+/// The methods of the trait are called with the [`Recovery`] state of the
+/// path, whose congestion window, slow start threshold and bytes in flight
+/// are maintained by the algorithm: it must add the size of sent packets to
+/// the bytes in flight, and remove the size of acknowledged packets from it.
+/// The size of lost packets is removed by the caller.
+///
+/// [`set_cc_algorithm()`]: struct.Config.html#method.set_cc_algorithm
+/// [`set_custom_congestion_control()`]:
+/// struct.Config.html#method.set_custom_congestion_control
+/// [`Recovery`]: struct.Recovery.html
+pub trait CongestionControl: Send + Sync {
+    /// Called when the recovery state of a path is created.
+    fn on_init(&mut self, _r: &mut Recovery) {}
+
+    /// Called when the recovery state of a path is reset.
+    fn reset(&mut self, _r: &mut Recovery) {}
+
+    /// Called when a packet counting towards bytes in flight is sent.
+    fn on_packet_sent(
+        &mut self, r: &mut Recovery, sent_bytes: usize, now: Instant,
+    );
+
+    /// Called with the packets newly acknowledged by an ACK frame.
+    fn on_packets_acked(
+        &mut self, r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch,
         now: Instant,
-    ),
+    );
 
-    pub congestion_event: fn(
-        r: &mut Recovery,
-        lost_bytes: usize,
-        time_sent: Instant,
-        epoch: packet::Epoch,
-        now: Instant,
-    ),
+    /// Called when packets are declared lost, with the time the most recent
+    /// of them was sent.
+    fn congestion_event(
+        &mut self, r: &mut Recovery, lost_bytes: usize, time_sent: Instant,
+        epoch: packet::Epoch, now: Instant,
+    );
 
-    pub collapse_cwnd: fn(r: &mut Recovery),
+    /// Called when persistent congestion is detected.
+    fn collapse_cwnd(&mut self, r: &mut Recovery);
 
-    pub checkpoint: fn(r: &mut Recovery),
+    /// Saves the state of the algorithm before a congestion event that starts
+    /// a new recovery period.
+    fn checkpoint(&mut self, _r: &mut Recovery) {}
+
+    /// Restores the state saved by [`checkpoint()`], when the congestion event
+    /// turned out to be spurious. Returns whether the state was restored.
+    ///
+    /// [`checkpoint()`]: trait.CongestionControl.html#method.checkpoint
+    fn rollback(&mut self, _r: &mut Recovery) -> bool {
+        false
+    }
 
-    pub rollback: fn(r: &mut Recovery) -> bool,
+    /// Returns whether the algorithm sets the pacing rate itself, using
+    /// [`Recovery::set_pacing_rate()`].
+    ///
+    /// [`Recovery::set_pacing_rate()`]:
+    /// struct.Recovery.html#method.set_pacing_rate
+    fn has_custom_pacing(&self) -> bool {
+        false
+    }
 
-    pub has_custom_pacing: fn() -> bool,
+    /// Formats the state of the algorithm for debug logging.
+    fn debug_fmt(
+        &self, _r: &Recovery, _f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        Ok(())
+    }
 
-    pub debug_fmt:
-        fn(r: &Recovery, formatter: &mut std::fmt::Formatter) -> std::fmt::Result,
+    /// Returns a copy of the algorithm.
+    ///
+    /// Each path of each connection uses its own copy of the instance set
+    /// on the [`Config`].
+    ///
+    /// [`Config`]: struct.Config.html
+    fn clone_box(&self) -> Box<dyn CongestionControl>;
 }
 
-impl From<CongestionControlAlgorithm> for &'static CongestionControlOps {
+impl From<CongestionControlAlgorithm> for Box<dyn CongestionControl> {
     fn from(algo: CongestionControlAlgorithm) -> Self {
         match algo {
-            CongestionControlAlgorithm::Reno => &reno::RENO,
-            CongestionControlAlgorithm::CUBIC => &cubic::CUBIC,
-            CongestionControlAlgorithm::BBR => &bbr::BBR,
+            CongestionControlAlgorithm::Reno => Box::new(reno::Reno),
+            CongestionControlAlgorithm::CUBIC =>
+                Box::new(cubic::Cubic::default()),
+            CongestionControlAlgorithm::BBR => Box::new(bbr::Bbr),
         }
     }
 }
@@ -1192,7 +1623,9 @@ impl std::fmt::Debug for Recovery {
         }
 
         // CC-specific debug info
-        (self.cc_ops.debug_fmt)(self, f)?;
+        if let Some(cc) = &self.cc {
+            cc.debug_fmt(self, f)?;
+        }
 
         Ok(())
     }
@@ -1225,6 +1658,8 @@ pub struct Sent {
     pub is_app_limited: bool,
 
     pub has_data: bool,
+
+    pub ecn_marked: bool,
 }
 
 impl std::fmt::Debug for Sent {
@@ -1242,22 +1677,33 @@ impl std::fmt::Debug for Sent {
     }
 }
 
+/// A newly acknowledged packet.
 #[derive(Clone)]
 pub struct Acked {
+    /// The packet number.
     pub pkt_num: u64,
 
+    /// The time the packet was sent.
     pub time_sent: Instant,
 
+    /// The size of the packet in bytes, or zero if it did not count towards
+    /// bytes in flight.
     pub size: usize,
 
+    /// The time elapsed between sending the packet and its acknowledgement.
     pub rtt: Duration,
 
+    /// The number of bytes delivered when the packet was sent.
     pub delivered: usize,
 
+    /// The time at which `delivered` was last updated when the packet was
+    /// sent.
     pub delivered_time: Instant,
 
+    /// The send time of the first packet of the flight the packet belongs to.
     pub first_sent_time: Instant,
 
+    /// Whether sending was application limited when the packet was sent.
     pub is_app_limited: bool,
 }
 
@@ -1462,6 +1908,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1488,6 +1935,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1514,6 +1962,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1540,6 +1989,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1598,6 +2048,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1624,6 +2075,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1660,6 +2112,7 @@ mod tests {
         assert_eq!(r.bytes_in_flight, 0);
 
         assert_eq!(r.lost_count, 2);
+        assert_eq!(r.loss_bursts(), 1);
 
         // Wait 1 RTT.
         now += r.rtt();
@@ -1695,6 +2148,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1721,6 +2175,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1747,6 +2202,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1773,6 +2229,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1820,6 +2277,7 @@ mod tests {
         assert_eq!(r.bytes_in_flight, 0);
 
         assert_eq!(r.lost_count, 1);
+        assert_eq!(r.loss_bursts(), 0);
 
         // Wait 1 RTT.
         now += r.rtt();
@@ -1855,6 +2313,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1881,6 +2340,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1907,6 +2367,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1933,6 +2394,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2027,6 +2489,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2084,6 +2547,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2115,6 +2579,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2143,6 +2608,7 @@ mod tests {
             first_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2167,11 +2633,93 @@ mod tests {
             now + Duration::from_secs_f64(12000.0 / pacing_rate as f64)
         );
     }
+
+    #[test]
+    fn initial_rtt() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+
+        let r = Recovery::new(&cfg);
+
+        assert_eq!(r.rtt(), INITIAL_RTT);
+        assert_eq!(r.pto(), INITIAL_RTT * 3);
+
+        cfg.set_initial_rtt(Duration::from_millis(20));
+
+        let mut r = Recovery::new(&cfg);
+
+        assert_eq!(r.rtt(), Duration::from_millis(20));
+        assert_eq!(r.pto(), Duration::from_millis(60));
+
+        let now = Instant::now();
+
+        let p = Sent {
+            pkt_num: 0,
+            frames: smallvec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1200,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            has_data: false,
+            ecn_marked: false,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+
+        // Packets are paced from the start.
+        let pacing_rate =
+            (r.congestion_window as f64 * PACING_MULTIPLIER / 0.02) as u64;
+        assert_eq!(r.pacer.rate(), pacing_rate);
+    }
+
+    #[test]
+    fn min_rtt_expiry() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+        cfg.set_min_rtt_expiry(Duration::from_secs(1));
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        r.update_rtt(Duration::from_millis(50), Duration::ZERO, now);
+        assert_eq!(r.min_rtt(), Duration::from_millis(50));
+
+        now += Duration::from_millis(100);
+        r.update_rtt(Duration::from_millis(100), Duration::ZERO, now);
+        assert_eq!(r.min_rtt(), Duration::from_millis(50));
+        assert_eq!(r.min_rtt_refreshes(), 0);
+
+        // The first sample expired, so the route change is reflected.
+        now += Duration::from_secs(2);
+        r.update_rtt(Duration::from_millis(100), Duration::ZERO, now);
+        assert_eq!(r.min_rtt(), Duration::from_millis(100));
+        assert_eq!(r.min_rtt_refreshes(), 1);
+
+        r.refresh_min_rtt(Duration::from_millis(80), now);
+        assert_eq!(r.min_rtt(), Duration::from_millis(80));
+        assert_eq!(r.min_rtt_refreshes(), 1);
+    }
 }
 
 mod bbr;
-mod cubic;
+pub(crate) mod cubic;
 mod delivery_rate;
+
+pub use delivery_rate::DeliveryRateObserver;
+pub use delivery_rate::DeliveryRateSample;
 mod hystart;
 mod pacer;
 mod prr;
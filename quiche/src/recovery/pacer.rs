@@ -69,11 +69,16 @@ pub struct Pacer {
 
     /// Interval to be added in next burst.
     iv: Duration,
+
+    /// Maximum duration of a burst at the pacing rate, if bursts are limited
+    /// further than by the bucket capacity.
+    granularity: Option<Duration>,
 }
 
 impl Pacer {
     pub fn new(
-        enabled: bool, capacity: usize, rate: u64, max_datagram_size: usize,
+        enabled: bool, granularity: Option<Duration>, capacity: usize, rate: u64,
+        max_datagram_size: usize,
     ) -> Self {
         // Round capacity to MSS.
         let capacity = capacity / max_datagram_size * max_datagram_size;
@@ -96,6 +101,8 @@ impl Pacer {
             last_packet_size: None,
 
             iv: Duration::ZERO,
+
+            granularity,
         }
     }
 
@@ -109,8 +116,23 @@ impl Pacer {
         self.rate
     }
 
+    /// Returns the maximum duration of a burst, if configured.
+    pub fn granularity(&self) -> Option<Duration> {
+        self.granularity
+    }
+
     /// Updates the bucket capacity or pacing_rate.
     pub fn update(&mut self, capacity: usize, rate: u64, now: Instant) {
+        // With a granularity, bursts are limited to the bytes sent during that
+        // time at the pacing rate, down to a single packet.
+        let capacity = match self.granularity {
+            Some(v) if rate > 0 => capacity
+                .min((rate as f64 * v.as_secs_f64()) as usize)
+                .max(self.max_datagram_size),
+
+            _ => capacity,
+        };
+
         let capacity = capacity / self.max_datagram_size * self.max_datagram_size;
 
         if self.capacity != capacity {
@@ -197,7 +219,7 @@ mod tests {
         let max_burst = datagram_size * 10;
         let pacing_rate = 100_000;
 
-        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size);
+        let mut p = Pacer::new(true, None, max_burst, pacing_rate, datagram_size);
 
         let now = Instant::now();
 
@@ -230,7 +252,7 @@ mod tests {
         let max_burst = datagram_size * 10;
         let pacing_rate = 100_000;
 
-        let mut p = Pacer::new(true, max_burst, pacing_rate, datagram_size);
+        let mut p = Pacer::new(true, None, max_burst, pacing_rate, datagram_size);
 
         let now = Instant::now();
 
@@ -247,4 +269,51 @@ mod tests {
 
         assert_eq!(p.next_time(), now);
     }
+    #[test]
+    fn pacer_granularity() {
+        let datagram_size = 1200;
+        let max_burst = datagram_size * 10;
+        let pacing_rate = 1_200_000;
+
+        // Each packet is released at its own time.
+        let mut p = Pacer::new(
+            true,
+            Some(Duration::ZERO),
+            max_burst,
+            pacing_rate,
+            datagram_size,
+        );
+
+        let now = Instant::now();
+        p.update(max_burst, pacing_rate, now);
+
+        p.send(datagram_size, now);
+        assert_eq!(p.next_time(), now);
+
+        p.send(datagram_size, now);
+        assert_eq!(p.next_time(), now + Duration::from_millis(1));
+
+        p.send(datagram_size, now);
+        assert_eq!(p.next_time(), now + Duration::from_millis(2));
+
+        // Bursts last up to 3ms at the pacing rate.
+        let mut p = Pacer::new(
+            true,
+            Some(Duration::from_millis(3)),
+            max_burst,
+            pacing_rate,
+            datagram_size,
+        );
+
+        let now = Instant::now();
+        p.update(max_burst, pacing_rate, now);
+
+        for _ in 0..3 {
+            p.send(datagram_size, now);
+            assert_eq!(p.next_time(), now);
+        }
+
+        p.send(datagram_size, now);
+        assert_eq!(p.next_time(), now + Duration::from_millis(3));
+    }
 }
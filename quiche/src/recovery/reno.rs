@@ -35,21 +35,68 @@ use crate::packet;
 use crate::recovery;
 
 use crate::recovery::Acked;
-use crate::recovery::CongestionControlOps;
+use crate::recovery::CongestionControl;
 use crate::recovery::Recovery;
 
-pub static RENO: CongestionControlOps = CongestionControlOps {
-    on_init,
-    reset,
-    on_packet_sent,
-    on_packets_acked,
-    congestion_event,
-    collapse_cwnd,
-    checkpoint,
-    rollback,
-    has_custom_pacing,
-    debug_fmt,
-};
+/// Reno congestion control.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reno;
+
+impl CongestionControl for Reno {
+    fn on_init(&mut self, r: &mut Recovery) {
+        on_init(r)
+    }
+
+    fn reset(&mut self, r: &mut Recovery) {
+        reset(r)
+    }
+
+    fn on_packet_sent(
+        &mut self, r: &mut Recovery, sent_bytes: usize, now: Instant,
+    ) {
+        on_packet_sent(r, sent_bytes, now)
+    }
+
+    fn on_packets_acked(
+        &mut self, r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        on_packets_acked(r, packets, epoch, now)
+    }
+
+    fn congestion_event(
+        &mut self, r: &mut Recovery, lost_bytes: usize, time_sent: Instant,
+        epoch: packet::Epoch, now: Instant,
+    ) {
+        congestion_event(r, lost_bytes, time_sent, epoch, now)
+    }
+
+    fn collapse_cwnd(&mut self, r: &mut Recovery) {
+        collapse_cwnd(r)
+    }
+
+    fn checkpoint(&mut self, r: &mut Recovery) {
+        checkpoint(r)
+    }
+
+    fn rollback(&mut self, r: &mut Recovery) -> bool {
+        rollback(r)
+    }
+
+    fn has_custom_pacing(&self) -> bool {
+        has_custom_pacing()
+    }
+
+    fn debug_fmt(
+        &self, r: &Recovery, f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        debug_fmt(r, f)
+    }
+
+    fn clone_box(&self) -> Box<dyn CongestionControl> {
+        Box::new(*self)
+    }
+}
 
 pub fn on_init(_r: &mut Recovery) {}
 
@@ -124,8 +171,9 @@ fn congestion_event(
             r.max_datagram_size * recovery::MINIMUM_WINDOW_PACKETS,
         );
 
-        r.bytes_acked_ca = (r.congestion_window as f64 *
-            recovery::LOSS_REDUCTION_FACTOR) as usize;
+        // The window only grows again once a full window of data sent after
+        // the congestion event is acknowledged.
+        r.bytes_acked_ca = 0;
 
         r.ssthresh = r.congestion_window;
 
@@ -214,6 +262,7 @@ mod tests {
             first_sent_time: std::time::Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -263,6 +312,7 @@ mod tests {
             first_sent_time: std::time::Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -380,4 +430,48 @@ mod tests {
         // After acking more than cwnd, expect cwnd increased by MSS
         assert_eq!(r.cwnd(), cur_cwnd + r.max_datagram_size);
     }
+
+    #[test]
+    fn reno_congestion_avoidance_after_recovery() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+
+        // Fill up bytes_in_flight to avoid app_limited=true
+        r.on_packet_sent_cc(20000, now);
+
+        r.congestion_event(
+            r.max_datagram_size,
+            now,
+            packet::Epoch::Application,
+            now,
+        );
+
+        let cur_cwnd = r.cwnd();
+        let rtt = Duration::from_millis(100);
+
+        let ack = |size| {
+            vec![Acked {
+                pkt_num: 0,
+                // To exit from recovery
+                time_sent: now + rtt,
+                size,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                rtt: Duration::ZERO,
+            }]
+        };
+
+        // Acking half of the window doesn't increase cwnd.
+        r.on_packets_acked(ack(cur_cwnd / 2), packet::Epoch::Application, now);
+        assert_eq!(r.cwnd(), cur_cwnd);
+
+        // Acking the rest of the window does.
+        r.on_packets_acked(ack(cur_cwnd / 2), packet::Epoch::Application, now);
+        assert_eq!(r.cwnd(), cur_cwnd + r.max_datagram_size);
+    }
 }
@@ -0,0 +1,248 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A path that a [`PathScheduler`] can send the next packet on.
+///
+/// [`PathScheduler`]: trait.PathScheduler.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathCandidate {
+    /// The local address of the path.
+    pub local_addr: SocketAddr,
+
+    /// The remote address of the path.
+    pub peer_addr: SocketAddr,
+
+    /// The estimated round-trip time of the path.
+    pub rtt: Duration,
+
+    /// The number of bytes that can be sent on the path according to its
+    /// congestion window.
+    pub cwnd_available: usize,
+}
+
+/// Selects the path on which the next packet is sent when the multipath
+/// extension is in use.
+///
+/// Only active paths are passed to the scheduler. Paths that the local
+/// endpoint or the peer marked as standby are left out, unless no other path
+/// is active.
+pub trait PathScheduler: Send {
+    /// Returns the index in `paths` of the path to send the next packet on.
+    ///
+    /// `paths` is never empty. Returning `None` or an out of range index
+    /// selects the first path.
+    fn select(&mut self, paths: &[PathCandidate]) -> Option<usize>;
+
+    /// Returns whether the stream data sent on one path must be sent again on
+    /// each of the other active paths.
+    fn redundant(&self) -> bool {
+        false
+    }
+}
+
+/// Available path schedulers.
+///
+/// This enum provides the currently available list of built-in path
+/// schedulers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub enum PathSchedulerAlgorithm {
+    /// Sends on the path with the lowest RTT that has congestion window
+    /// available (default). `lowest-rtt` in a string form.
+    LowestRtt  = 0,
+    /// Cycles through the paths that have congestion window available.
+    /// `round-robin` in a string form.
+    RoundRobin = 1,
+    /// Cycles through the paths like `RoundRobin`, and duplicates stream data
+    /// on all paths. `redundant` in a string form.
+    Redundant  = 2,
+}
+
+impl FromStr for PathSchedulerAlgorithm {
+    type Err = crate::Error;
+
+    /// Converts a string to `PathSchedulerAlgorithm`.
+    ///
+    /// If `name` is not valid, `Error::InvalidState` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "lowest-rtt" => Ok(PathSchedulerAlgorithm::LowestRtt),
+            "round-robin" => Ok(PathSchedulerAlgorithm::RoundRobin),
+            "redundant" => Ok(PathSchedulerAlgorithm::Redundant),
+
+            _ => Err(crate::Error::InvalidState),
+        }
+    }
+}
+
+/// Creates a new instance of the built-in scheduler `algo`.
+pub fn new_scheduler(algo: PathSchedulerAlgorithm) -> Box<dyn PathScheduler> {
+    match algo {
+        PathSchedulerAlgorithm::LowestRtt => Box::new(LowestRtt),
+
+        PathSchedulerAlgorithm::RoundRobin => Box::new(RoundRobin::default()),
+
+        PathSchedulerAlgorithm::Redundant => Box::new(Redundant::default()),
+    }
+}
+
+/// Sends on the path with the lowest RTT, preferring paths that have
+/// congestion window available.
+struct LowestRtt;
+
+impl PathScheduler for LowestRtt {
+    fn select(&mut self, paths: &[PathCandidate]) -> Option<usize> {
+        paths
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, p)| (p.cwnd_available == 0, p.rtt))
+            .map(|(i, _)| i)
+    }
+}
+
+/// Cycles through the paths, skipping those without congestion window
+/// available.
+#[derive(Default)]
+struct RoundRobin {
+    next: usize,
+}
+
+impl PathScheduler for RoundRobin {
+    fn select(&mut self, paths: &[PathCandidate]) -> Option<usize> {
+        let start = self.next % paths.len();
+
+        let selected = (0..paths.len())
+            .map(|i| (start + i) % paths.len())
+            .find(|&i| paths[i].cwnd_available > 0)
+            .unwrap_or(start);
+
+        self.next = selected + 1;
+
+        Some(selected)
+    }
+}
+
+/// Cycles through the paths like `RoundRobin`, and duplicates stream data on
+/// all paths.
+#[derive(Default)]
+struct Redundant {
+    round_robin: RoundRobin,
+}
+
+impl PathScheduler for Redundant {
+    fn select(&mut self, paths: &[PathCandidate]) -> Option<usize> {
+        self.round_robin.select(paths)
+    }
+
+    fn redundant(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(port: u16, rtt_ms: u64, cwnd_available: usize) -> PathCandidate {
+        PathCandidate {
+            local_addr: format!("127.0.0.1:{port}").parse().unwrap(),
+            peer_addr: "127.0.0.1:4433".parse().unwrap(),
+            rtt: Duration::from_millis(rtt_ms),
+            cwnd_available,
+        }
+    }
+
+    #[test]
+    fn lowest_rtt() {
+        let mut s = new_scheduler(PathSchedulerAlgorithm::LowestRtt);
+
+        let paths = [candidate(1, 50, 1000), candidate(2, 10, 1000)];
+        assert_eq!(s.select(&paths), Some(1));
+        assert_eq!(s.select(&paths), Some(1));
+
+        // The fastest path is congestion limited.
+        let paths = [candidate(1, 50, 1000), candidate(2, 10, 0)];
+        assert_eq!(s.select(&paths), Some(0));
+
+        assert!(!s.redundant());
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut s = new_scheduler(PathSchedulerAlgorithm::RoundRobin);
+
+        let paths = [
+            candidate(1, 10, 1000),
+            candidate(2, 10, 1000),
+            candidate(3, 10, 1000),
+        ];
+        assert_eq!(s.select(&paths), Some(0));
+        assert_eq!(s.select(&paths), Some(1));
+        assert_eq!(s.select(&paths), Some(2));
+        assert_eq!(s.select(&paths), Some(0));
+
+        // Congestion limited paths are skipped.
+        let paths = [
+            candidate(1, 10, 1000),
+            candidate(2, 10, 0),
+            candidate(3, 10, 1000),
+        ];
+        assert_eq!(s.select(&paths), Some(2));
+
+        // Unless all of them are.
+        let paths = [candidate(1, 10, 0), candidate(2, 10, 0)];
+        assert_eq!(s.select(&paths), Some(1));
+
+        assert!(!s.redundant());
+    }
+
+    #[test]
+    fn redundant() {
+        let mut s = new_scheduler(PathSchedulerAlgorithm::Redundant);
+
+        let paths = [candidate(1, 10, 1000), candidate(2, 50, 1000)];
+        assert_eq!(s.select(&paths), Some(0));
+        assert_eq!(s.select(&paths), Some(1));
+
+        assert!(s.redundant());
+    }
+
+    #[test]
+    fn algorithm_from_str() {
+        assert_eq!(
+            PathSchedulerAlgorithm::from_str("round-robin"),
+            Ok(PathSchedulerAlgorithm::RoundRobin)
+        );
+        assert_eq!(
+            PathSchedulerAlgorithm::from_str("fastest"),
+            Err(crate::Error::InvalidState)
+        );
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time;
+
+/// How long a `MemoryClientStore` keeps server hints by default.
+const DEFAULT_HINTS_TTL: time::Duration = time::Duration::from_secs(3600);
+
+/// Connection parameters learned from previous connections to a server.
+///
+/// They let the next connections skip a version negotiation round trip, or
+/// avoid offering application protocols the server is known to refuse.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerHints {
+    /// The version the server selected with version negotiation.
+    pub version: Option<u32>,
+
+    /// The application protocols the server refused, which are not offered
+    /// anymore, unless no other protocol is left.
+    pub rejected_protos: Vec<Vec<u8>>,
+}
+
+/// A client-side cache of address validation tokens, TLS sessions and
+/// [`ServerHints`].
+///
+/// Entries are keyed by server identity, which is the server name passed to
+/// [`connect()`], or the textual representation of the peer address if no
+/// server name was provided.
+///
+/// Client connections created with a [`Config`] configured with
+/// [`set_client_store()`] look up the store when they are created, and
+/// populate it when the server sends a NEW_TOKEN frame or a new session
+/// ticket. Applications can implement this trait to persist those across
+/// process restarts (e.g. on disk or in a platform keychain).
+///
+/// Tokens and sessions are taken out of the store by the connection that
+/// uses them, as reusing them would let the server link connections together
+/// (see [RFC 9000 Section 9.5] and [RFC 8446 Appendix C.4]).
+///
+/// [RFC 9000 Section 9.5]: https://www.rfc-editor.org/rfc/rfc9000#section-9.5
+/// [RFC 8446 Appendix C.4]: https://www.rfc-editor.org/rfc/rfc8446#appendix-C.4
+///
+/// [`connect()`]: fn.connect.html
+/// [`Config`]: struct.Config.html
+/// [`set_client_store()`]: struct.Config.html#method.set_client_store
+/// [`ServerHints`]: struct.ServerHints.html
+pub trait ClientStore: Send + Sync {
+    /// Removes and returns the address validation token stored for `server`,
+    /// if any.
+    fn take_token(&self, server: &str) -> Option<Vec<u8>>;
+
+    /// Stores the address validation token received from `server`.
+    fn put_token(&self, server: &str, token: &[u8]);
+
+    /// Removes and returns the serialized session stored for `server`, if
+    /// any.
+    ///
+    /// The returned value is passed as-is to [`set_session()`].
+    ///
+    /// [`set_session()`]: struct.Connection.html#method.set_session
+    fn take_session(&self, server: &str) -> Option<Vec<u8>>;
+
+    /// Stores the serialized session received from `server`, as returned by
+    /// [`session()`].
+    ///
+    /// [`session()`]: struct.Connection.html#method.session
+    fn put_session(&self, server: &str, session: &[u8]);
+
+    /// Returns the hints stored for `server`, if any.
+    ///
+    /// The default implementation doesn't store hints.
+    fn get_hints(&self, server: &str) -> Option<ServerHints> {
+        let _ = server;
+
+        None
+    }
+
+    /// Stores the hints learned about `server`, replacing previous ones.
+    ///
+    /// Hints are only useful for a limited time, as servers get upgraded, so
+    /// implementations are expected to expire them.
+    fn put_hints(&self, server: &str, hints: &ServerHints) {
+        let _ = (server, hints);
+    }
+}
+
+/// An in-memory [`ClientStore`].
+///
+/// Entries are kept for the lifetime of the store and are not persisted,
+/// except for server hints which expire after a time to live.
+///
+/// [`ClientStore`]: trait.ClientStore.html
+pub struct MemoryClientStore {
+    tokens: Mutex<HashMap<String, Vec<u8>>>,
+
+    sessions: Mutex<HashMap<String, Vec<u8>>>,
+
+    hints: Mutex<HashMap<String, (ServerHints, time::Instant)>>,
+
+    hints_ttl: time::Duration,
+}
+
+impl MemoryClientStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::with_hints_ttl(DEFAULT_HINTS_TTL)
+    }
+
+    /// Creates an empty store, which keeps server hints for `ttl`.
+    pub fn with_hints_ttl(ttl: time::Duration) -> Self {
+        MemoryClientStore {
+            tokens: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            hints: Mutex::new(HashMap::new()),
+            hints_ttl: ttl,
+        }
+    }
+}
+
+impl Default for MemoryClientStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientStore for MemoryClientStore {
+    fn take_token(&self, server: &str) -> Option<Vec<u8>> {
+        self.tokens.lock().ok()?.remove(server)
+    }
+
+    fn put_token(&self, server: &str, token: &[u8]) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.insert(server.to_string(), token.to_vec());
+        }
+    }
+
+    fn take_session(&self, server: &str) -> Option<Vec<u8>> {
+        self.sessions.lock().ok()?.remove(server)
+    }
+
+    fn put_session(&self, server: &str, session: &[u8]) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.insert(server.to_string(), session.to_vec());
+        }
+    }
+
+    fn get_hints(&self, server: &str) -> Option<ServerHints> {
+        let mut hints = self.hints.lock().ok()?;
+
+        match hints.get(server) {
+            Some((v, at)) if at.elapsed() < self.hints_ttl => Some(v.clone()),
+
+            Some(_) => {
+                hints.remove(server);
+                None
+            },
+
+            None => None,
+        }
+    }
+
+    fn put_hints(&self, server: &str, hints: &ServerHints) {
+        if let Ok(mut all) = self.hints.lock() {
+            all.insert(server.to_string(), (hints.clone(), time::Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryClientStore::new();
+
+        assert_eq!(store.take_token("quic.tech"), None);
+        assert_eq!(store.take_session("quic.tech"), None);
+
+        store.put_token("quic.tech", b"token");
+        store.put_session("quic.tech", b"session");
+
+        assert_eq!(store.take_token("example.com"), None);
+        assert_eq!(store.take_token("quic.tech"), Some(b"token".to_vec()));
+        assert_eq!(store.take_session("quic.tech"), Some(b"session".to_vec()));
+
+        // Tokens and sessions are only used once.
+        assert_eq!(store.take_token("quic.tech"), None);
+        assert_eq!(store.take_session("quic.tech"), None);
+
+        // Newer entries replace older ones.
+        store.put_token("quic.tech", b"token");
+        store.put_token("quic.tech", b"newer");
+        assert_eq!(store.take_token("quic.tech"), Some(b"newer".to_vec()));
+    }
+
+    #[test]
+    fn memory_store_hints() {
+        let hints = ServerHints {
+            version: Some(crate::PROTOCOL_VERSION),
+            rejected_protos: vec![b"proto1".to_vec()],
+        };
+
+        let store = MemoryClientStore::new();
+        assert_eq!(store.get_hints("quic.tech"), None);
+
+        store.put_hints("quic.tech", &hints);
+        assert_eq!(store.get_hints("quic.tech"), Some(hints.clone()));
+        assert_eq!(store.get_hints("example.com"), None);
+
+        // Hints expire.
+        let store = MemoryClientStore::with_hints_ttl(time::Duration::ZERO);
+
+        store.put_hints("quic.tech", &hints);
+        assert_eq!(store.get_hints("quic.tech"), None);
+    }
+}
@@ -45,7 +45,7 @@ use crate::Result;
 use crate::flowcontrol;
 use crate::ranges;
 
-const DEFAULT_URGENCY: u8 = 127;
+pub const DEFAULT_URGENCY: u8 = 127;
 
 #[cfg(test)]
 const SEND_BUFFER_SIZE: usize = 5;
@@ -59,6 +59,58 @@ const DEFAULT_STREAM_WINDOW: u64 = 32 * 1024;
 /// The maximum size of the receiver stream flow control window.
 pub const MAX_STREAM_WINDOW: u64 = 16 * 1024 * 1024;
 
+/// Per-stream state that can be inspected by plugins.
+#[cfg(feature = "plugins")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamField {
+    /// The offset up to which the application has written data.
+    SendOffset,
+
+    /// The lowest offset of data not yet acknowledged by the peer.
+    SendAckedOffset,
+
+    /// The maximum offset the peer allows us to send.
+    SendMaxData,
+
+    /// Whether the application has written the final data of the stream.
+    SendFin,
+
+    /// The offset up to which the application has read data.
+    RecvOffset,
+
+    /// The largest offset received from the peer.
+    RecvMaxOffset,
+
+    /// The maximum offset we allow the peer to send.
+    RecvMaxData,
+
+    /// Whether the peer's final data has been read by the application.
+    RecvFin,
+
+    /// The stream's urgency.
+    Urgency,
+
+    /// Whether the stream can be flushed incrementally.
+    Incremental,
+}
+
+#[cfg(feature = "plugins")]
+impl StreamField {
+    /// All the fields, in the order they are passed to plugins.
+    pub const ALL: [StreamField; 10] = [
+        StreamField::SendOffset,
+        StreamField::SendAckedOffset,
+        StreamField::SendMaxData,
+        StreamField::SendFin,
+        StreamField::RecvOffset,
+        StreamField::RecvMaxOffset,
+        StreamField::RecvMaxData,
+        StreamField::RecvFin,
+        StreamField::Urgency,
+        StreamField::Incremental,
+    ];
+}
+
 /// A simple no-op hasher for Stream IDs.
 ///
 /// The QUIC protocol and quiche library guarantees stream ID uniqueness, so
@@ -178,11 +230,15 @@ pub struct StreamMap {
 
     /// The maximum size of a stream window.
     max_stream_window: u64,
+
+    /// The maximum amount of data buffered in a stream's send buffer.
+    max_stream_send_buffer: u64,
 }
 
 impl StreamMap {
     pub fn new(
         max_streams_bidi: u64, max_streams_uni: u64, max_stream_window: u64,
+        max_stream_send_buffer: u64,
     ) -> StreamMap {
         StreamMap {
             local_max_streams_bidi: max_streams_bidi,
@@ -193,6 +249,8 @@ impl StreamMap {
 
             max_stream_window,
 
+            max_stream_send_buffer,
+
             ..StreamMap::default()
         }
     }
@@ -207,6 +265,41 @@ impl StreamMap {
         self.streams.get_mut(&id)
     }
 
+    /// Returns the value of the given field of the stream with the given ID.
+    ///
+    /// Boolean fields are returned as `0` or `1`. If the stream doesn't exist,
+    /// [`InvalidStreamState`] is returned.
+    ///
+    /// [`InvalidStreamState`]: ../enum.Error.html#variant.InvalidStreamState
+    #[cfg(feature = "plugins")]
+    pub fn get_field(&self, id: u64, field: StreamField) -> Result<u64> {
+        let stream = self.get(id).ok_or(Error::InvalidStreamState(id))?;
+
+        let v = match field {
+            StreamField::SendOffset => stream.send.off_back(),
+
+            StreamField::SendAckedOffset => stream.send.off_front(),
+
+            StreamField::SendMaxData => stream.send.max_off(),
+
+            StreamField::SendFin => stream.send.fin_off.is_some() as u64,
+
+            StreamField::RecvOffset => stream.recv.off_front(),
+
+            StreamField::RecvMaxOffset => stream.recv.max_off(),
+
+            StreamField::RecvMaxData => stream.recv.max_data(),
+
+            StreamField::RecvFin => stream.recv.is_fin() as u64,
+
+            StreamField::Urgency => u64::from(stream.urgency),
+
+            StreamField::Incremental => stream.incremental as u64,
+        };
+
+        Ok(v)
+    }
+
     /// Returns the mutable stream with the given ID if it exists, or creates
     /// a new one otherwise.
     ///
@@ -315,13 +408,16 @@ impl StreamMap {
                     },
                 };
 
-                let s = Stream::new(
+                let mut s = Stream::new(
                     max_rx_data,
                     max_tx_data,
                     is_bidi(id),
                     local,
                     self.max_stream_window,
                 );
+
+                s.send.set_max_buffered(self.max_stream_send_buffer);
+
                 v.insert(s)
             },
 
@@ -400,6 +496,45 @@ impl StreamMap {
         }
     }
 
+    /// Removes the stream ID from the flushable streams queue corresponding to
+    /// the specified urgency, wherever it is in the queue.
+    ///
+    /// Returns true if the stream was queued.
+    pub fn unqueue_flushable(
+        &mut self, stream_id: u64, urgency: u8, incr: bool,
+    ) -> bool {
+        let queues = match self.flushable.get_mut(&urgency) {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        let queued = if !incr {
+            let len = queues.0.len();
+
+            let heap = std::mem::take(&mut queues.0).into_vec();
+
+            queues.0 = heap
+                .into_iter()
+                .filter(|x| x.0 != stream_id)
+                .collect::<BinaryHeap<_>>();
+
+            queues.0.len() != len
+        } else {
+            let len = queues.1.len();
+
+            queues.1.retain(|id| *id != stream_id);
+
+            queues.1.len() != len
+        };
+
+        if queues.0.is_empty() && queues.1.is_empty() {
+            self.flushable.remove(&urgency);
+        }
+
+        queued
+    }
+
     /// Adds or removes the stream ID to/from the readable streams set.
     ///
     /// If the stream was already in the list, this does nothing.
@@ -557,6 +692,27 @@ impl StreamMap {
         StreamIter::from(&self.writable)
     }
 
+    /// Creates an iterator over streams that can be written to, whose send
+    /// capacity, further limited by `tx_cap`, reaches their low watermark.
+    ///
+    /// Stopped streams are always included.
+    pub fn writable_lowat(&self, tx_cap: usize) -> StreamIter {
+        let streams = self
+            .writable
+            .iter()
+            .copied()
+            .filter(|id| {
+                self.streams.get(id).map_or(false, |stream| {
+                    stream.send.cap().map_or(true, |cap| {
+                        cmp::min(tx_cap, cap) >= stream.send_lowat
+                    })
+                })
+            })
+            .collect();
+
+        StreamIter { streams }
+    }
+
     /// Creates an iterator over streams that need to send MAX_STREAM_DATA.
     pub fn almost_full(&self) -> StreamIter {
         StreamIter::from(&self.almost_full)
@@ -647,6 +803,10 @@ pub struct Stream {
 
     pub send_lowat: usize,
 
+    /// The amount of data that needs to be readable for the stream to be
+    /// reported as readable.
+    pub recv_lowat: usize,
+
     /// Whether the stream is bidirectional.
     pub bidi: bool,
 
@@ -673,6 +833,7 @@ impl Stream {
             recv: RecvBuf::new(max_rx_data, max_window),
             send: SendBuf::new(max_tx_data),
             send_lowat: 1,
+            recv_lowat: 1,
             bidi,
             local,
             data: None,
@@ -686,12 +847,31 @@ impl Stream {
         self.recv.ready()
     }
 
+    /// Returns true if the stream has at least `recv_lowat` bytes to read, or
+    /// less data but no more can be received.
+    pub fn is_readable_lowat(&self) -> bool {
+        if !self.recv.ready() {
+            return false;
+        }
+
+        if self.recv_lowat <= 1 || self.recv.error.is_some() {
+            return true;
+        }
+
+        let len = self.recv.ready_len();
+
+        len >= self.recv_lowat as u64 ||
+            self.recv.fin_off == Some(self.recv.off + len)
+    }
+
     /// Returns true if the stream has enough flow control capacity to be
     /// written to, and is not finished.
     pub fn is_writable(&self) -> bool {
         !self.send.shutdown &&
             !self.send.is_fin() &&
-            (self.send.off + self.send_lowat as u64) < self.send.max_data
+            (self.send.off + self.send_lowat as u64) < self.send.max_data &&
+            (self.send.buffered() + self.send_lowat as u64) <=
+                self.send.max_buffered
     }
 
     /// Returns true if the stream has data to send and is allowed to send at
@@ -786,6 +966,9 @@ pub struct RecvBuf {
     /// The lowest data offset that has yet to be read by the application.
     off: u64,
 
+    /// The end of the contiguous data received starting from `off`.
+    contiguous_off: u64,
+
     /// The total length of data received on this stream.
     len: u64,
 
@@ -800,6 +983,9 @@ pub struct RecvBuf {
 
     /// Whether incoming data is validated but not buffered.
     drain: bool,
+
+    /// The number of received bytes that had already been received.
+    dup_bytes: u64,
 }
 
 impl RecvBuf {
@@ -866,6 +1052,8 @@ impl RecvBuf {
             // By this point all spurious empty buffers should have already been
             // discarded, so allowing empty buffers here should be safe.
             if !buf.is_empty() {
+                self.dup_bytes += buf.len() as u64;
+
                 return Ok(());
             }
         }
@@ -880,7 +1068,10 @@ impl RecvBuf {
             // get stuck when a buffer with lower offset than the stream's is
             // buffered.
             if self.off_front() > buf.off() {
-                buf = buf.split_off((self.off_front() - buf.off()) as usize);
+                let dup = self.off_front() - buf.off();
+                self.dup_bytes += dup;
+
+                buf = buf.split_off(dup as usize);
             }
 
             // Handle overlapping data. If the incoming data's starting offset
@@ -900,12 +1091,17 @@ impl RecvBuf {
 
                     // New buffer is fully contained in existing buffer.
                     if off >= b.off() && buf.max_off() <= b.max_off() {
+                        self.dup_bytes += buf.len() as u64;
+
                         continue 'tmp;
                     }
 
                     // New buffer's start overlaps existing buffer.
                     if off >= b.off() && off < b.max_off() {
-                        buf = buf.split_off((b.max_off() - off) as usize);
+                        let dup = b.max_off() - off;
+                        self.dup_bytes += dup;
+
+                        buf = buf.split_off(dup as usize);
                     }
 
                     // New buffer's end overlaps existing buffer.
@@ -923,6 +1119,16 @@ impl RecvBuf {
             }
         }
 
+        // Advance the end of contiguous data over any newly filled gaps.
+        while let Some((_, b)) = self.data.range(self.contiguous_off + 1..).next()
+        {
+            if b.off() != self.contiguous_off {
+                break;
+            }
+
+            self.contiguous_off = b.max_off();
+        }
+
         Ok(())
     }
 
@@ -981,6 +1187,52 @@ impl RecvBuf {
         Ok((len, self.is_fin()))
     }
 
+    /// Takes the next contiguous chunk of data out of the receive buffer,
+    /// without copying it.
+    ///
+    /// On success the chunk, and a flag indicating if there is no more data
+    /// in the buffer, are returned as a tuple.
+    pub fn emit_buf(&mut self) -> Result<(StreamBuf, bool)> {
+        if !self.ready() {
+            return Err(Error::Done);
+        }
+
+        // The stream was reset, so return the error code instead.
+        if let Some(e) = self.error {
+            return Err(Error::StreamReset(e));
+        }
+
+        let buf = match self.data.first_entry() {
+            Some(entry) => entry.remove(),
+
+            None => return Err(Error::Done),
+        };
+
+        let len = buf.len();
+
+        self.off += len as u64;
+
+        // Update consumed bytes for flow control.
+        self.flow_control.add_consumed(len as u64);
+
+        let out = match &buf.data {
+            BufData::Shared(data) => StreamBuf {
+                data: data.clone(),
+                start: buf.pos,
+                end: buf.start + buf.len,
+            },
+
+            // Application buffers only hold data sent on the stream.
+            BufData::App(_) => StreamBuf {
+                data: Arc::from(&buf[..]),
+                start: 0,
+                end: len,
+            },
+        };
+
+        Ok((out, self.is_fin()))
+    }
+
     /// Resets the stream at the given offset.
     pub fn reset(&mut self, error_code: u64, final_size: u64) -> Result<usize> {
         // Stream's size is already known, forbid changing it.
@@ -1007,6 +1259,7 @@ impl RecvBuf {
 
         // Clear all data already buffered.
         self.off = final_size;
+        self.contiguous_off = final_size;
 
         self.data.clear();
 
@@ -1054,6 +1307,7 @@ impl RecvBuf {
         self.data.clear();
 
         self.off = self.max_off();
+        self.contiguous_off = self.off;
 
         Ok(())
     }
@@ -1063,6 +1317,30 @@ impl RecvBuf {
         self.off
     }
 
+    /// Returns true if the stream was reset by the peer.
+    pub fn is_reset(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Returns true if all of the stream's data was received, up to the
+    /// final size sent by the peer.
+    pub fn is_fin_received(&self) -> bool {
+        if self.error.is_some() || self.drain {
+            return false;
+        }
+
+        match self.fin_off {
+            Some(fin_off) => self.off + self.ready_len() == fin_off,
+
+            None => false,
+        }
+    }
+
+    /// Returns the number of received bytes that had already been received.
+    pub fn dup_bytes(&self) -> u64 {
+        self.dup_bytes
+    }
+
     /// Returns true if we need to update the local flow control limit.
     pub fn almost_full(&self) -> bool {
         self.fin_off.is_none() && self.flow_control.should_update_max_data()
@@ -1094,6 +1372,11 @@ impl RecvBuf {
 
         buf.off() == self.off
     }
+
+    /// Returns the amount of contiguous data that can be read.
+    fn ready_len(&self) -> u64 {
+        self.contiguous_off - self.off
+    }
 }
 
 /// Send-side stream buffer.
@@ -1122,6 +1405,10 @@ pub struct SendBuf {
     /// The maximum offset we are allowed to send to the peer.
     max_data: u64,
 
+    /// The maximum amount of data buffered, including data that was sent but
+    /// not acked yet.
+    max_buffered: u64,
+
     /// The last offset the stream was blocked at, if any.
     blocked_at: Option<u64>,
 
@@ -1143,16 +1430,57 @@ impl SendBuf {
     fn new(max_data: u64) -> SendBuf {
         SendBuf {
             max_data,
+            max_buffered: std::u64::MAX,
             ..SendBuf::default()
         }
     }
 
+    /// Sets the maximum amount of data buffered in the stream.
+    ///
+    /// Once the buffered data, including data that was sent but not acked yet,
+    /// reaches this limit, writes to the buffer are truncated until some of
+    /// the data is acked by the peer.
+    pub fn set_max_buffered(&mut self, v: u64) {
+        self.max_buffered = v;
+    }
+
     /// Inserts the given slice of data at the end of the buffer.
     ///
     /// The number of bytes that were actually stored in the buffer is returned
     /// (this may be lower than the size of the input buffer, in case of partial
     /// writes).
-    pub fn write(&mut self, mut data: &[u8], mut fin: bool) -> Result<usize> {
+    pub fn write(&mut self, data: &[u8], fin: bool) -> Result<usize> {
+        self.write_internal(data, None, fin)
+    }
+
+    /// Inserts `len` bytes of `shared` starting at `start` at the end of the
+    /// buffer.
+    ///
+    /// This is the same as [`write()`], but the buffer keeps a reference to
+    /// `shared` instead of copying its data, so that the same data can be
+    /// queued on many streams at the cost of a single copy.
+    ///
+    /// [`write()`]: struct.SendBuf.html#method.write
+    pub fn write_shared(
+        &mut self, shared: &Arc<[u8]>, start: usize, len: usize, fin: bool,
+    ) -> Result<usize> {
+        self.write_data(&BufData::Shared(shared.clone()), start, len, fin)
+    }
+
+    /// Writes `len` bytes of `data` from `start` into the send buffer,
+    /// without copying them.
+    pub fn write_data(
+        &mut self, data: &BufData, start: usize, len: usize, fin: bool,
+    ) -> Result<usize> {
+        let buf = &data.as_slice()[start..start + len];
+
+        self.write_internal(buf, Some((data, start)), fin)
+    }
+
+    fn write_internal(
+        &mut self, mut data: &[u8], shared: Option<(&BufData, usize)>,
+        mut fin: bool,
+    ) -> Result<usize> {
         let max_off = self.off + data.len() as u64;
 
         // Get the stream send capacity. This will return an error if the stream
@@ -1200,11 +1528,23 @@ impl SendBuf {
         // Split the remaining input data into consistently-sized buffers to
         // avoid fragmentation.
         for chunk in data.chunks(SEND_BUFFER_SIZE) {
+            let chunk_start = len;
+
             len += chunk.len();
 
             let fin = len == data.len() && fin;
 
-            let buf = RangeBuf::from(chunk, self.off, fin);
+            let buf = match shared {
+                Some((shared, start)) => RangeBuf::from_data(
+                    shared.clone(),
+                    start + chunk_start,
+                    chunk.len(),
+                    self.off,
+                    fin,
+                ),
+
+                None => RangeBuf::from(chunk, self.off, fin),
+            };
 
             // The new data can simply be appended at the end of the send buffer.
             self.data.push_back(buf);
@@ -1293,6 +1633,16 @@ impl SendBuf {
         self.acked.insert(off..off + len as u64);
     }
 
+    /// Returns true if the given non-empty range of data was acknowledged.
+    pub fn is_acked(&self, off: u64, len: usize) -> bool {
+        let max_off = off + len as u64;
+
+        len > 0 &&
+            self.acked
+                .iter()
+                .any(|r| r.start <= off && max_off <= r.end)
+    }
+
     pub fn ack_and_drop(&mut self, off: u64, len: usize) {
         self.ack(off, len);
 
@@ -1490,6 +1840,11 @@ impl SendBuf {
         self.error.is_some()
     }
 
+    /// Returns true if the stream was shut down by the local endpoint.
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
     /// Returns true if there is data to be written.
     fn ready(&self) -> bool {
         !self.data.is_empty() && self.off_front() < self.off
@@ -1506,24 +1861,146 @@ impl SendBuf {
         }
     }
 
+    /// Returns the amount of data buffered, including data that was sent but
+    /// not acked yet.
+    pub fn buffered(&self) -> u64 {
+        self.off - self.ack_off()
+    }
+
     /// Returns the outgoing flow control capacity.
+    ///
+    /// This is further limited by the space left in the send buffer.
     pub fn cap(&self) -> Result<usize> {
         // The stream was stopped, so return the error code instead.
         if let Some(e) = self.error {
             return Err(Error::StreamStopped(e));
         }
 
-        Ok((self.max_data - self.off) as usize)
+        let buf_cap = self.max_buffered.saturating_sub(self.buffered());
+
+        Ok(cmp::min(self.max_data - self.off, buf_cap) as usize)
+    }
+}
+
+/// Allocates the buffers holding the data received on streams.
+///
+/// See [`Config::set_buf_factory()`].
+///
+/// [`Config::set_buf_factory()`]: struct.Config.html#method.set_buf_factory
+pub trait BufFactory: Send + Sync {
+    /// Returns a buffer starting with a copy of `data`.
+    ///
+    /// The buffer can be larger than `data`, e.g. when it is taken from a pool
+    /// of fixed-size buffers, in which case the remaining bytes are ignored.
+    fn buf_from_slice(&self, data: &[u8]) -> Arc<[u8]>;
+}
+
+/// Data received on a stream, referencing the buffer it was stored in.
+///
+/// It is returned by [`Connection::stream_recv_buf()`].
+///
+/// [`Connection::stream_recv_buf()`]:
+/// struct.Connection.html#method.stream_recv_buf
+#[derive(Clone, Debug)]
+pub struct StreamBuf {
+    data: Arc<[u8]>,
+
+    start: usize,
+
+    end: usize,
+}
+
+impl StreamBuf {
+    /// Returns the buffer holding the data.
+    pub fn buf(&self) -> &Arc<[u8]> {
+        &self.data
+    }
+
+    /// Returns the range of the data within the buffer.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl std::ops::Deref for StreamBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for StreamBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// A buffer owned by the application, that can be sent on a stream without
+/// being copied.
+///
+/// See [`Connection::stream_send_zc()`].
+///
+/// [`Connection::stream_send_zc()`]:
+/// struct.Connection.html#method.stream_send_zc
+pub trait Buf: AsRef<[u8]> + Send + Sync + 'static {}
+
+impl<T: AsRef<[u8]> + Send + Sync + 'static> Buf for T {}
+
+/// The storage of the data of a `RangeBuf`.
+#[derive(Clone)]
+pub enum BufData {
+    /// A buffer allocated by quiche, or shared by the application.
+    Shared(Arc<[u8]>),
+
+    /// A buffer owned by the application, kept alive until its data is
+    /// acknowledged.
+    App(Arc<dyn Buf>),
+}
+
+impl BufData {
+    /// Returns the data of the buffer.
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            BufData::Shared(data) => data,
+
+            BufData::App(buf) => (**buf).as_ref(),
+        }
+    }
+}
+
+impl Default for BufData {
+    fn default() -> Self {
+        BufData::Shared(Arc::from(&[][..]))
+    }
+}
+
+impl PartialEq for BufData {
+    fn eq(&self, other: &BufData) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for BufData {}
+
+impl std::fmt::Debug for BufData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BufData::Shared(_) => write!(f, "Shared({})", self.as_slice().len()),
+
+            BufData::App(_) => write!(f, "App({})", self.as_slice().len()),
+        }
     }
 }
 
 /// Buffer holding data at a specific offset.
 ///
-/// The data is stored in a `Vec<u8>` in such a way that it can be shared
-/// between multiple `RangeBuf` objects.
+/// The data is stored in a reference-counted buffer, allocated by quiche or
+/// owned by the application, in such a way that it can be shared between
+/// multiple `RangeBuf` objects, possibly of different streams.
 ///
 /// Each `RangeBuf` will have its own view of that buffer, where the `start`
-/// value indicates the initial offset within the `Vec`, and `len` indicates the
+/// value indicates the initial offset within the slice, and `len` indicates the
 /// number of bytes, starting from `start` that are included.
 ///
 /// In addition, `pos` indicates the current offset within the `Vec`, starting
@@ -1538,7 +2015,7 @@ pub struct RangeBuf {
     /// To avoid needless allocations when a RangeBuf is split, this field is
     /// reference-counted and can be shared between multiple RangeBuf objects,
     /// and sliced using the `start` and `len` values.
-    data: Arc<Vec<u8>>,
+    data: BufData,
 
     /// The initial offset within the internal buffer.
     start: usize,
@@ -1560,7 +2037,7 @@ impl RangeBuf {
     /// Creates a new `RangeBuf` from the given slice.
     pub fn from(buf: &[u8], off: u64, fin: bool) -> RangeBuf {
         RangeBuf {
-            data: Arc::new(Vec::from(buf)),
+            data: BufData::Shared(Arc::from(buf)),
             start: 0,
             pos: 0,
             len: buf.len(),
@@ -1569,6 +2046,51 @@ impl RangeBuf {
         }
     }
 
+    /// Creates a new `RangeBuf` from the given slice, copied into a buffer
+    /// allocated by `factory`.
+    pub fn from_factory(
+        factory: &dyn BufFactory, buf: &[u8], off: u64, fin: bool,
+    ) -> RangeBuf {
+        let data = factory.buf_from_slice(buf);
+
+        // Don't trust the factory with the size of the buffer.
+        if data.len() < buf.len() {
+            return RangeBuf::from(buf, off, fin);
+        }
+
+        RangeBuf {
+            data: BufData::Shared(data),
+            start: 0,
+            pos: 0,
+            len: buf.len(),
+            off,
+            fin,
+        }
+    }
+
+    /// Creates a new `RangeBuf` referencing `len` bytes of `data` from
+    /// `start`, without copying them.
+    pub fn from_shared(
+        data: &Arc<[u8]>, start: usize, len: usize, off: u64, fin: bool,
+    ) -> RangeBuf {
+        RangeBuf::from_data(BufData::Shared(data.clone()), start, len, off, fin)
+    }
+
+    /// Creates a new `RangeBuf` referencing `len` bytes of `data` from
+    /// `start`.
+    pub fn from_data(
+        data: BufData, start: usize, len: usize, off: u64, fin: bool,
+    ) -> RangeBuf {
+        RangeBuf {
+            data,
+            start,
+            pos: start,
+            len,
+            off,
+            fin,
+        }
+    }
+
     /// Returns whether `self` holds the final offset in the stream.
     pub fn fin(&self) -> bool {
         self.fin
@@ -1629,7 +2151,7 @@ impl std::ops::Deref for RangeBuf {
     type Target = [u8];
 
     fn deref(&self) -> &[u8] {
-        &self.data[self.pos..self.start + self.len]
+        &self.data.as_slice()[self.pos..self.start + self.len]
     }
 }
 
@@ -2260,6 +2782,65 @@ mod tests {
         assert_eq!(recv.emit(&mut buf), Err(Error::Done));
     }
 
+    #[test]
+    fn dup_bytes() {
+        let mut recv = RecvBuf::new(std::u64::MAX, DEFAULT_STREAM_WINDOW);
+
+        let mut buf = [0; 32];
+
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+        assert_eq!(recv.dup_bytes(), 0);
+
+        // Exact retransmission.
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+        assert_eq!(recv.dup_bytes(), 5);
+
+        // Partial overlap with buffered data.
+        assert!(recv.write(RangeBuf::from(b"lloworld", 2, false)).is_ok());
+        assert_eq!(recv.dup_bytes(), 8);
+
+        assert_eq!(recv.emit(&mut buf), Ok((10, false)));
+        assert_eq!(&buf[..10], b"helloworld");
+
+        // Data already read by the application.
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+        assert_eq!(recv.dup_bytes(), 13);
+
+        assert!(recv.write(RangeBuf::from(b"worldwide", 5, false)).is_ok());
+        assert_eq!(recv.dup_bytes(), 18);
+
+        assert_eq!(recv.emit(&mut buf), Ok((4, false)));
+        assert_eq!(&buf[..4], b"wide");
+    }
+
+    #[test]
+    fn fin_received_reordered() {
+        let mut recv = RecvBuf::new(std::u64::MAX, DEFAULT_STREAM_WINDOW);
+
+        let mut buf = [0; 32];
+
+        assert!(recv.write(RangeBuf::from(b"world", 10, true)).is_ok());
+        assert_eq!(recv.ready_len(), 0);
+        assert!(!recv.is_fin_received());
+
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+        assert_eq!(recv.ready_len(), 5);
+        assert!(!recv.is_fin_received());
+
+        assert_eq!(recv.emit(&mut buf), Ok((5, false)));
+        assert_eq!(recv.ready_len(), 0);
+
+        // Filling the gap makes all data up to the final size contiguous.
+        assert!(recv.write(RangeBuf::from(b"stuff", 5, false)).is_ok());
+        assert_eq!(recv.ready_len(), 10);
+        assert!(recv.is_fin_received());
+
+        assert_eq!(recv.emit(&mut buf), Ok((10, true)));
+        assert_eq!(&buf[..10], b"stuffworld");
+        assert_eq!(recv.ready_len(), 0);
+        assert!(recv.is_fin_received());
+    }
+
     #[test]
     fn empty_write() {
         let mut buf = [0; 5];
@@ -2295,6 +2876,61 @@ mod tests {
         assert_eq!(send.len, 0);
     }
 
+    #[test]
+    fn max_buffered_write() {
+        let mut buf = [0; 128];
+
+        let mut send = SendBuf::new(std::u64::MAX);
+        send.set_max_buffered(10);
+        assert_eq!(send.cap(), Ok(10));
+
+        // The write is truncated, so the fin flag is ignored.
+        assert_eq!(send.write(b"somethinghelloworld", true), Ok(10));
+        assert_eq!(send.buffered(), 10);
+        assert_eq!(send.cap(), Ok(0));
+        assert!(!send.is_fin());
+
+        // Sent data stays buffered until it's acked.
+        assert_eq!(send.emit(&mut buf), Ok((10, false)));
+        assert_eq!(send.cap(), Ok(0));
+
+        send.ack_and_drop(0, 5);
+        assert_eq!(send.buffered(), 5);
+        assert_eq!(send.cap(), Ok(5));
+
+        assert_eq!(send.write(b"helloworld", true), Ok(5));
+    }
+
+    #[test]
+    fn shared_write() {
+        let mut buf = [0; 128];
+
+        let shared: Arc<[u8]> = Arc::from(&b"somethinghelloworld"[..]);
+
+        let mut first = SendBuf::new(std::u64::MAX);
+        let mut second = SendBuf::new(5);
+
+        assert_eq!(first.write_shared(&shared, 0, 19, true), Ok(19));
+        assert_eq!(first.len, 19);
+
+        // Only part of the data fits the flow control limit.
+        assert_eq!(second.write_shared(&shared, 9, 10, true), Ok(5));
+        assert_eq!(second.len, 5);
+
+        // The data is referenced, not copied.
+        assert_eq!(Arc::strong_count(&shared), 3);
+
+        let (written, fin) = first.emit(&mut buf[..128]).unwrap();
+        assert_eq!(written, 19);
+        assert_eq!(fin, true);
+        assert_eq!(&buf[..written], b"somethinghelloworld");
+
+        let (written, fin) = second.emit(&mut buf[..128]).unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(fin, false);
+        assert_eq!(&buf[..written], b"hello");
+    }
+
     #[test]
     fn split_write() {
         let mut buf = [0; 10];
@@ -3273,7 +3909,7 @@ mod tests {
         let local_tp = crate::TransportParams::default();
         let peer_tp = crate::TransportParams::default();
 
-        let mut streams = StreamMap::new(5, 5, 5);
+        let mut streams = StreamMap::new(5, 5, 5, std::u64::MAX);
 
         let stream_id = 500;
         assert!(!is_local(stream_id, true), "stream id is peer initiated");
@@ -3294,7 +3930,7 @@ mod tests {
         let local_tp = crate::TransportParams::default();
         let peer_tp = crate::TransportParams::default();
 
-        let mut streams = StreamMap::new(5, 5, 5);
+        let mut streams = StreamMap::new(5, 5, 5, std::u64::MAX);
 
         for stream_id in [8, 12, 4] {
             assert!(is_local(stream_id, false), "stream id is client initiated");
@@ -3311,7 +3947,7 @@ mod tests {
         let local_tp = crate::TransportParams::default();
         let peer_tp = crate::TransportParams::default();
 
-        let mut streams = StreamMap::new(3, 3, 3);
+        let mut streams = StreamMap::new(3, 3, 3, std::u64::MAX);
 
         // Highest permitted
         let stream_id = 8;
@@ -3329,6 +3965,42 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(feature = "plugins")]
+    fn stream_fields() {
+        let mut local_tp = crate::TransportParams::default();
+        local_tp.initial_max_stream_data_bidi_remote = 15;
+
+        let mut peer_tp = crate::TransportParams::default();
+        peer_tp.initial_max_stream_data_bidi_local = 10;
+
+        let mut streams = StreamMap::new(3, 3, 3, std::u64::MAX);
+
+        assert_eq!(
+            streams.get_field(0, StreamField::SendOffset),
+            Err(Error::InvalidStreamState(0))
+        );
+
+        let stream = streams
+            .get_or_create(0, &local_tp, &peer_tp, false, true)
+            .unwrap();
+        assert_eq!(stream.send.write(b"hello", true), Ok(5));
+
+        assert_eq!(streams.get_field(0, StreamField::SendOffset), Ok(5));
+        assert_eq!(streams.get_field(0, StreamField::SendAckedOffset), Ok(0));
+        assert_eq!(streams.get_field(0, StreamField::SendMaxData), Ok(10));
+        assert_eq!(streams.get_field(0, StreamField::SendFin), Ok(1));
+        assert_eq!(streams.get_field(0, StreamField::RecvOffset), Ok(0));
+        assert_eq!(streams.get_field(0, StreamField::RecvMaxOffset), Ok(0));
+        assert_eq!(streams.get_field(0, StreamField::RecvMaxData), Ok(15));
+        assert_eq!(streams.get_field(0, StreamField::RecvFin), Ok(0));
+        assert_eq!(
+            streams.get_field(0, StreamField::Urgency),
+            Ok(DEFAULT_URGENCY as u64)
+        );
+        assert_eq!(streams.get_field(0, StreamField::Incremental), Ok(1));
+    }
+
     /// Check SendBuf::len calculation on a retransmit case
     #[test]
     fn send_buf_len_on_retransmit() {
@@ -28,8 +28,12 @@ use std::ffi;
 use std::ptr;
 use std::slice;
 
+use std::convert::TryFrom;
+
 use std::io::Write;
 
+use std::sync::Arc;
+
 use libc::c_char;
 use libc::c_int;
 use libc::c_long;
@@ -43,11 +47,17 @@ use crate::Connection;
 use crate::ConnectionError;
 
 use crate::crypto;
+use crate::keyring;
 use crate::packet;
 
+use super::CertVerifier;
+use super::CertVerifyMode;
+use super::ExData;
+use super::INTERNAL_ERROR;
+use super::TLS_ALERT_BAD_CERTIFICATE;
+use super::TLS_ALERT_ERROR;
+
 const TLS1_3_VERSION: u16 = 0x0304;
-const TLS_ALERT_ERROR: u64 = 0x100;
-const INTERNAL_ERROR: u64 = 0x01;
 
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
@@ -87,10 +97,34 @@ struct X509(c_void);
 #[repr(transparent)]
 struct STACK_OF(c_void);
 
+#[allow(non_camel_case_types, dead_code)]
+#[repr(C)]
+enum ssl_verify_result_t {
+    ssl_verify_ok,
+    ssl_verify_invalid,
+    ssl_verify_retry,
+}
+
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 struct CRYPTO_BUFFER(c_void);
 
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct EVP_CIPHER(c_void);
+
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct EVP_CIPHER_CTX(c_void);
+
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct EVP_MD(c_void);
+
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct HMAC_CTX(c_void);
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 struct SSL_QUIC_METHOD {
@@ -328,6 +362,40 @@ impl Context {
         }
     }
 
+    pub fn set_cert_verifier(&mut self, v: Option<Arc<dyn CertVerifier>>) {
+        // The verifier itself is passed to the handshake through its ExData,
+        // so that the context doesn't need to own it. Only verifiers replacing
+        // the built-in verification are called from the handshake.
+        let replace =
+            matches!(&v, Some(v) if v.mode() == CertVerifyMode::Replace);
+
+        unsafe {
+            let mode = SSL_CTX_get_verify_mode(self.as_mut_ptr());
+
+            if replace {
+                // Enable SSL_VERIFY_PEER, as the custom verification is skipped
+                // otherwise.
+                SSL_CTX_set_custom_verify(
+                    self.as_mut_ptr(),
+                    mode | 0x01,
+                    Some(custom_verify),
+                );
+            } else {
+                SSL_CTX_set_custom_verify(self.as_mut_ptr(), mode, None);
+            }
+        }
+    }
+
+    pub fn set_verify_required(&mut self, required: bool) {
+        // true  -> 0x03 SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT
+        // false -> 0x01 SSL_VERIFY_PEER
+        let mode = if required { 0x03 } else { 0x01 };
+
+        unsafe {
+            SSL_CTX_set_verify(self.as_mut_ptr(), mode, ptr::null());
+        }
+    }
+
     pub fn enable_keylog(&mut self) {
         unsafe {
             SSL_CTX_set_keylog_callback(self.as_mut_ptr(), keylog);
@@ -371,6 +439,29 @@ impl Context {
         })
     }
 
+    pub fn set_ticket_keys(&mut self, _keys: Arc<keyring::TicketKeys>) {
+        // The keys themselves are passed to the handshake through its ExData,
+        // as with key rings.
+        self.set_ticket_key_callback(true);
+    }
+
+    pub fn set_key_ring(&mut self, _ring: Option<Arc<keyring::KeyRing>>) {
+        // The ring itself is passed to the handshake through its ExData, and
+        // used by the ticket key callback.
+    }
+
+    pub fn set_ticket_key_callback(&mut self, enabled: bool) {
+        let cb = if enabled {
+            Some(ticket_key as TicketKeyCb)
+        } else {
+            None
+        };
+
+        unsafe {
+            SSL_CTX_set_tlsext_ticket_key_cb(self.as_mut_ptr(), cb);
+        }
+    }
+
     pub fn set_early_data_enabled(&mut self, enabled: bool) {
         let enabled = i32::from(enabled);
 
@@ -494,6 +585,21 @@ impl Handshake {
         unsafe { SSL_set_quiet_shutdown(self.as_mut_ptr(), i32::from(mode)) }
     }
 
+    /// Sets the application protocols offered by a client connection, instead
+    /// of those of the context.
+    pub fn set_alpn(&mut self, v: &[&[u8]]) -> Result<()> {
+        let mut protos: Vec<u8> = Vec::new();
+
+        for proto in v {
+            protos.push(proto.len() as u8);
+            protos.extend_from_slice(proto);
+        }
+
+        map_result_zero_is_success(unsafe {
+            SSL_set_alpn_protos(self.as_mut_ptr(), protos.as_ptr(), protos.len())
+        })
+    }
+
     pub fn set_host_name(&mut self, name: &str) -> Result<()> {
         let cstr = ffi::CString::new(name).map_err(|_| Error::TlsFail)?;
         let rc =
@@ -679,36 +785,27 @@ impl Handshake {
     }
 
     pub fn peer_cert_chain(&self) -> Option<Vec<&[u8]>> {
-        let cert_chain = unsafe {
-            let chain =
-                map_result_ptr(SSL_get0_peer_certificates(self.as_ptr())).ok()?;
-
-            let num = sk_num(chain);
-            if num <= 0 {
-                return None;
-            }
-
-            let mut cert_chain = vec![];
-            for i in 0..num {
-                let buffer =
-                    map_result_ptr(sk_value(chain, i) as *const CRYPTO_BUFFER)
-                        .ok()?;
-
-                let out_len = CRYPTO_BUFFER_len(buffer);
-                if out_len == 0 {
-                    return None;
-                }
-
-                let out = CRYPTO_BUFFER_data(buffer);
-                let slice = slice::from_raw_parts(out, out_len);
-
-                cert_chain.push(slice);
-            }
+        get_peer_cert_chain_from_ptr(self.as_ptr())
+    }
 
-            cert_chain
-        };
+    pub fn export_keying_material(
+        &self, label: &[u8], context: Option<&[u8]>, out: &mut [u8],
+    ) -> Result<()> {
+        let (context_ptr, context_len) =
+            context.map_or((ptr::null(), 0), |v| (v.as_ptr(), v.len()));
 
-        Some(cert_chain)
+        map_result(unsafe {
+            SSL_export_keying_material(
+                self.as_ptr(),
+                out.as_mut_ptr(),
+                out.len(),
+                label.as_ptr() as *const c_char,
+                label.len(),
+                context_ptr,
+                context_len,
+                i32::from(context.is_some()),
+            )
+        })
     }
 
     pub fn peer_cert(&self) -> Option<&[u8]> {
@@ -792,6 +889,10 @@ impl Handshake {
         unsafe { SSL_in_early_data(self.as_ptr()) == 1 }
     }
 
+    pub fn early_data_accepted(&self) -> bool {
+        unsafe { SSL_early_data_accepted(self.as_ptr()) == 1 }
+    }
+
     pub fn clear(&mut self) -> Result<()> {
         let rc = unsafe { SSL_clear(self.as_mut_ptr()) };
         self.map_result_ssl(rc)
@@ -868,6 +969,7 @@ impl Handshake {
                 *ex_data.local_error = Some(ConnectionError {
                     is_app: false,
                     error_code: INTERNAL_ERROR,
+                    frame_type: 0,
                     reason: Vec::new(),
                 })
             }
@@ -889,27 +991,43 @@ impl Drop for Handshake {
     }
 }
 
-pub struct ExData<'a> {
-    pub application_protos: &'a Vec<Vec<u8>>,
+fn get_ex_data_from_ptr<'a, T>(ptr: *mut SSL, idx: c_int) -> Option<&'a mut T> {
+    unsafe {
+        let data = SSL_get_ex_data(ptr, idx) as *mut T;
+        data.as_mut()
+    }
+}
 
-    pub pkt_num_spaces: &'a mut [packet::PktNumSpace; packet::Epoch::count()],
+fn get_peer_cert_chain_from_ptr<'a>(ssl: *const SSL) -> Option<Vec<&'a [u8]>> {
+    let cert_chain = unsafe {
+        let chain = map_result_ptr(SSL_get0_peer_certificates(ssl)).ok()?;
 
-    pub session: &'a mut Option<Vec<u8>>,
+        let num = sk_num(chain);
+        if num <= 0 {
+            return None;
+        }
 
-    pub local_error: &'a mut Option<super::ConnectionError>,
+        let mut cert_chain = vec![];
+        for i in 0..num {
+            let buffer =
+                map_result_ptr(sk_value(chain, i) as *const CRYPTO_BUFFER)
+                    .ok()?;
 
-    pub keylog: Option<&'a mut Box<dyn std::io::Write + Send + Sync>>,
+            let out_len = CRYPTO_BUFFER_len(buffer);
+            if out_len == 0 {
+                return None;
+            }
 
-    pub trace_id: &'a str,
+            let out = CRYPTO_BUFFER_data(buffer);
+            let slice = slice::from_raw_parts(out, out_len);
 
-    pub is_server: bool,
-}
+            cert_chain.push(slice);
+        }
 
-fn get_ex_data_from_ptr<'a, T>(ptr: *mut SSL, idx: c_int) -> Option<&'a mut T> {
-    unsafe {
-        let data = SSL_get_ex_data(ptr, idx) as *mut T;
-        data.as_mut()
-    }
+        cert_chain
+    };
+
+    Some(cert_chain)
 }
 
 fn get_cipher_from_ptr(cipher: *const SSL_CIPHER) -> Result<crypto::Algorithm> {
@@ -959,7 +1077,12 @@ extern fn set_read_secret(
     if level != crypto::Level::ZeroRTT || ex_data.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let open = match crypto::Open::from_secret(aead, secret) {
+        let open = match crypto::Open::from_secret(
+            ex_data.crypto_provider,
+            aead,
+            ex_data.version,
+            secret,
+        ) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -1010,7 +1133,12 @@ extern fn set_write_secret(
     if level != crypto::Level::ZeroRTT || !ex_data.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let seal = match crypto::Seal::from_secret(aead, secret) {
+        let seal = match crypto::Seal::from_secret(
+            ex_data.crypto_provider,
+            aead,
+            ex_data.version,
+            secret,
+        ) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -1084,6 +1212,7 @@ extern fn send_alert(ssl: *mut SSL, level: crypto::Level, alert: u8) -> c_int {
     *ex_data.local_error = Some(ConnectionError {
         is_app: false,
         error_code: error,
+        frame_type: 0,
         reason: Vec::new(),
     });
 
@@ -1098,14 +1227,10 @@ extern fn keylog(ssl: *mut SSL, line: *const c_char) {
         None => return,
     };
 
-    if let Some(keylog) = &mut ex_data.keylog {
-        let data = unsafe { ffi::CStr::from_ptr(line).to_bytes() };
-
-        let mut full_line = Vec::with_capacity(data.len() + 1);
-        full_line.extend_from_slice(data);
-        full_line.push(b'\n');
+    let line = unsafe { ffi::CStr::from_ptr(line) };
 
-        keylog.write_all(&full_line[..]).ok();
+    if let Ok(line) = line.to_str() {
+        ex_data.log_key(line);
     }
 }
 
@@ -1158,6 +1283,124 @@ extern fn select_alpn(
     3 // SSL_TLSEXT_ERR_NOACK
 }
 
+type TicketKeyCb = extern fn(
+    ssl: *mut SSL,
+    key_name: *mut u8,
+    iv: *mut u8,
+    cipher_ctx: *mut EVP_CIPHER_CTX,
+    hmac_ctx: *mut HMAC_CTX,
+    encrypt: c_int,
+) -> c_int;
+
+extern fn custom_verify(
+    ssl: *mut SSL, out_alert: *mut u8,
+) -> ssl_verify_result_t {
+    let ex_data = match get_ex_data_from_ptr::<ExData>(ssl, *QUICHE_EX_DATA_INDEX)
+    {
+        Some(v) => v,
+
+        None => return ssl_verify_result_t::ssl_verify_invalid,
+    };
+
+    let verifier = match ex_data.cert_verifier {
+        Some(v) if v.mode() == CertVerifyMode::Replace => v,
+
+        _ => return ssl_verify_result_t::ssl_verify_invalid,
+    };
+
+    let chain = get_peer_cert_chain_from_ptr(ssl).unwrap_or_default();
+
+    match verifier.verify(&chain) {
+        Ok(()) => ssl_verify_result_t::ssl_verify_ok,
+
+        Err(e) => {
+            trace!("{} peer certificate rejected: {}", ex_data.trace_id, e);
+
+            // Errors that are not a valid TLS alert are reported as a bad
+            // certificate.
+            let alert = u8::try_from(e).unwrap_or(TLS_ALERT_BAD_CERTIFICATE);
+
+            unsafe { *out_alert = alert };
+
+            ssl_verify_result_t::ssl_verify_invalid
+        },
+    }
+}
+
+extern fn ticket_key(
+    ssl: *mut SSL, key_name: *mut u8, iv: *mut u8,
+    cipher_ctx: *mut EVP_CIPHER_CTX, hmac_ctx: *mut HMAC_CTX, encrypt: c_int,
+) -> c_int {
+    let ex_data = match get_ex_data_from_ptr::<ExData>(ssl, *QUICHE_EX_DATA_INDEX)
+    {
+        Some(v) => v,
+
+        None => return -1,
+    };
+
+    let key_name =
+        unsafe { slice::from_raw_parts_mut(key_name, keyring::KEY_NAME_LEN) };
+
+    // The IV of AES-128-CBC.
+    let iv = unsafe { slice::from_raw_parts_mut(iv, 16) };
+
+    let (key, renew) = if encrypt == 1 {
+        let key = match ex_data.ticket_encryption_key() {
+            Some(v) => v,
+
+            None => return -1,
+        };
+
+        key_name.copy_from_slice(&key.name);
+        crate::rand::rand_bytes(iv);
+
+        (key, false)
+    } else {
+        match ex_data.ticket_decryption_key(key_name) {
+            Some(v) => v,
+
+            // The key is unknown or expired, so fall back to a full handshake.
+            None => return 0,
+        }
+    };
+
+    let rc = unsafe {
+        EVP_CipherInit_ex(
+            cipher_ctx,
+            EVP_aes_128_cbc(),
+            ptr::null_mut(),
+            key.aes_key.as_ptr(),
+            iv.as_ptr(),
+            encrypt,
+        )
+    };
+
+    if rc != 1 {
+        return -1;
+    }
+
+    let rc = unsafe {
+        HMAC_Init_ex(
+            hmac_ctx,
+            key.hmac_key.as_ptr() as *const c_void,
+            key.hmac_key.len(),
+            EVP_sha256(),
+            ptr::null_mut(),
+        )
+    };
+
+    if rc != 1 {
+        return -1;
+    }
+
+    // Tickets encrypted with a retired key are renewed with the current one.
+    if renew {
+        2
+    } else {
+        1
+    }
+}
+
 extern fn new_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int {
     let ex_data = match get_ex_data_from_ptr::<ExData>(ssl, *QUICHE_EX_DATA_INDEX)
     {
@@ -1279,6 +1522,15 @@ extern {
 
     fn SSL_CTX_set_verify(ctx: *mut SSL_CTX, mode: c_int, cb: *const c_void);
 
+    fn SSL_CTX_get_verify_mode(ctx: *const SSL_CTX) -> c_int;
+
+    fn SSL_CTX_set_custom_verify(
+        ctx: *mut SSL_CTX, mode: c_int,
+        cb: Option<
+            extern fn(ssl: *mut SSL, out_alert: *mut u8) -> ssl_verify_result_t,
+        >,
+    );
+
     fn SSL_CTX_set_keylog_callback(
         ctx: *mut SSL_CTX, cb: extern fn(ssl: *mut SSL, line: *const c_char),
     );
@@ -1287,6 +1539,10 @@ extern {
         ctx: *mut SSL_CTX, key: *const u8, key_len: usize,
     ) -> c_int;
 
+    fn SSL_CTX_set_tlsext_ticket_key_cb(
+        ctx: *mut SSL_CTX, cb: Option<TicketKeyCb>,
+    ) -> c_int;
+
     fn SSL_CTX_set_alpn_protos(
         ctx: *mut SSL_CTX, protos: *const u8, protos_len: usize,
     ) -> c_int;
@@ -1347,6 +1603,12 @@ extern {
 
     fn SSL_get0_peer_certificates(ssl: *const SSL) -> *const STACK_OF;
 
+    fn SSL_export_keying_material(
+        ssl: *const SSL, out: *mut u8, out_len: usize, label: *const c_char,
+        label_len: usize, context: *const u8, context_len: usize,
+        use_context: c_int,
+    ) -> c_int;
+
     fn SSL_set_min_proto_version(ssl: *mut SSL, version: u16);
     fn SSL_set_max_proto_version(ssl: *mut SSL, version: u16);
 
@@ -1354,6 +1616,10 @@ extern {
 
     fn SSL_set_tlsext_host_name(ssl: *mut SSL, name: *const c_char) -> c_int;
 
+    fn SSL_set_alpn_protos(
+        ssl: *mut SSL, protos: *const u8, protos_len: usize,
+    ) -> c_int;
+
     fn SSL_set_quic_transport_params(
         ssl: *mut SSL, params: *const u8, params_len: usize,
     ) -> c_int;
@@ -1404,6 +1670,8 @@ extern {
 
     fn SSL_in_early_data(ssl: *const SSL) -> c_int;
 
+    fn SSL_early_data_accepted(ssl: *const SSL) -> c_int;
+
     fn SSL_clear(ssl: *mut SSL) -> c_int;
 
     fn SSL_free(ssl: *mut SSL);
@@ -1445,6 +1713,23 @@ extern {
     fn CRYPTO_BUFFER_len(buffer: *const CRYPTO_BUFFER) -> usize;
     fn CRYPTO_BUFFER_data(buffer: *const CRYPTO_BUFFER) -> *const u8;
 
+    // EVP_CIPHER
+    fn EVP_aes_128_cbc() -> *const EVP_CIPHER;
+
+    fn EVP_CipherInit_ex(
+        ctx: *mut EVP_CIPHER_CTX, cipher: *const EVP_CIPHER, engine: *mut c_void,
+        key: *const u8, iv: *const u8, enc: c_int,
+    ) -> c_int;
+
+    // EVP_MD
+    fn EVP_sha256() -> *const EVP_MD;
+
+    // HMAC
+    fn HMAC_Init_ex(
+        ctx: *mut HMAC_CTX, key: *const c_void, key_len: usize,
+        md: *const EVP_MD, engine: *mut c_void,
+    ) -> c_int;
+
     // ERR
     fn ERR_peek_error() -> c_uint;
 
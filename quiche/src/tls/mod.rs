@@ -0,0 +1,249 @@
+// Copyright (C) 2018-2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! TLS backends.
+//!
+//! The QUIC handshake is driven through the `Context` and `Handshake` types.
+//! They are implemented with BoringSSL by default, or with rustls when the
+//! `rustls` feature is enabled. Both backends install the packet protection
+//! keys and queue handshake data through the `ExData` of the connection.
+
+use std::io::Write;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::crypto;
+use crate::keyring;
+use crate::packet;
+
+#[cfg(not(feature = "rustls"))]
+mod boringssl;
+
+#[cfg(feature = "rustls")]
+mod rustls;
+
+#[cfg(not(feature = "rustls"))]
+pub use self::boringssl::Context;
+#[cfg(not(feature = "rustls"))]
+pub use self::boringssl::Handshake;
+
+#[cfg(feature = "rustls")]
+pub use self::rustls::Context;
+#[cfg(feature = "rustls")]
+pub use self::rustls::Handshake;
+
+pub(crate) const TLS_ALERT_ERROR: u64 = 0x100;
+pub(crate) const TLS_ALERT_BAD_CERTIFICATE: u8 = 42;
+
+pub(crate) const TLS_ALERT_NO_APPLICATION_PROTOCOL: u8 = 120;
+const INTERNAL_ERROR: u64 = 0x01;
+
+/// When a [`CertVerifier`] vets the peer's certificate chain.
+///
+/// [`CertVerifier`]: trait.CertVerifier.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertVerifyMode {
+    /// Once the handshake is completed, after the verification performed by
+    /// the TLS library (if enabled) succeeded, so the verifier can only
+    /// restrict the set of accepted peers, for example to pin certificates or
+    /// to check application-specific fields.
+    AfterHandshake,
+
+    /// During the handshake, instead of verifying the chain against the
+    /// trusted CA certificates, for example to implement out-of-band trust
+    /// models. This also enables peer verification.
+    Replace,
+}
+
+/// Vets the certificate chain presented by the peer.
+pub trait CertVerifier: Send + Sync {
+    /// Verifies the peer's certificate chain.
+    ///
+    /// The chain is made of DER-encoded certificates, starting with the peer's
+    /// leaf certificate. It is empty if the peer didn't present any.
+    ///
+    /// When the chain is rejected, the returned error is sent to the peer as
+    /// the TLS alert, or `bad_certificate` if it isn't a valid alert. With the
+    /// `rustls` backend, the alert of [`Replace`] verifiers is chosen by the
+    /// TLS library instead.
+    ///
+    /// [`Replace`]: enum.CertVerifyMode.html#variant.Replace
+    fn verify(&self, chain: &[&[u8]]) -> std::result::Result<(), u64>;
+
+    /// Returns when the verifier is called.
+    ///
+    /// The default is [`AfterHandshake`].
+    ///
+    /// [`AfterHandshake`]: enum.CertVerifyMode.html#variant.AfterHandshake
+    fn mode(&self) -> CertVerifyMode {
+        CertVerifyMode::AfterHandshake
+    }
+}
+
+/// Adapts a closure to a [`Replace`] verifier.
+///
+/// [`Replace`]: enum.CertVerifyMode.html#variant.Replace
+pub(crate) struct CallbackCertVerifier {
+    cb: Mutex<Box<dyn FnMut(&[&[u8]]) -> std::result::Result<(), u64> + Send>>,
+}
+
+impl CallbackCertVerifier {
+    pub fn new(
+        cb: Box<dyn FnMut(&[&[u8]]) -> std::result::Result<(), u64> + Send>,
+    ) -> Self {
+        CallbackCertVerifier { cb: Mutex::new(cb) }
+    }
+}
+
+impl CertVerifier for CallbackCertVerifier {
+    fn verify(&self, chain: &[&[u8]]) -> std::result::Result<(), u64> {
+        match self.cb.lock() {
+            Ok(mut cb) => (*cb)(chain),
+
+            Err(_) => Err(u64::from(TLS_ALERT_BAD_CERTIFICATE)),
+        }
+    }
+
+    fn mode(&self) -> CertVerifyMode {
+        CertVerifyMode::Replace
+    }
+}
+
+/// The callback that receives the lines of the keylog file, without their
+/// trailing newline.
+pub type KeylogCallback = Mutex<Box<dyn FnMut(&str) + Send>>;
+
+pub struct ExData<'a> {
+    pub application_protos: &'a Vec<Vec<u8>>,
+
+    pub pkt_num_spaces: &'a mut [packet::PktNumSpace; packet::Epoch::count()],
+
+    pub session: &'a mut Option<Vec<u8>>,
+
+    pub local_error: &'a mut Option<super::ConnectionError>,
+
+    pub keylog: Option<&'a mut Box<dyn std::io::Write + Send + Sync>>,
+
+    pub keylog_callback: Option<&'a KeylogCallback>,
+
+    pub trace_id: &'a str,
+
+    pub key_ring: Option<&'a keyring::KeyRing>,
+
+    pub ticket_keys: Option<&'a keyring::TicketKeys>,
+
+    pub crypto_provider: &'a Arc<dyn crypto::CryptoProvider>,
+
+    pub cert_verifier: Option<&'a dyn CertVerifier>,
+
+    pub is_server: bool,
+
+    pub version: u32,
+}
+
+impl<'a> ExData<'a> {
+    /// Logs a line of the keylog file to the writer and to the callback of the
+    /// connection, if any.
+    pub fn log_key(&mut self, line: &str) {
+        if let Some(keylog) = &mut self.keylog {
+            let mut full_line = Vec::with_capacity(line.len() + 1);
+            full_line.extend_from_slice(line.as_bytes());
+            full_line.push(b'\n');
+
+            keylog.write_all(&full_line[..]).ok();
+        }
+
+        if let Some(Ok(mut cb)) = self.keylog_callback.map(|cb| cb.lock()) {
+            (*cb)(line);
+        }
+    }
+
+    /// Returns the key to encrypt a new session ticket with, from the key
+    /// ring if any, or else from the ticket keys.
+    #[cfg(not(feature = "rustls"))]
+    pub fn ticket_encryption_key(&self) -> Option<keyring::TicketKey> {
+        match (self.key_ring, self.ticket_keys) {
+            (Some(key_ring), _) => key_ring.ticket_encryption_key(),
+
+            (None, Some(keys)) => keys.encryption_key(),
+
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the key to decrypt a session ticket encrypted with the key
+    /// `name`, along with whether the ticket should be renewed.
+    #[cfg(not(feature = "rustls"))]
+    pub fn ticket_decryption_key(
+        &self, name: &[u8],
+    ) -> Option<(keyring::TicketKey, bool)> {
+        match (self.key_ring, self.ticket_keys) {
+            (Some(key_ring), _) => key_ring.ticket_decryption_key(name),
+
+            (None, Some(keys)) => keys.decryption_key(name),
+
+            (None, None) => None,
+        }
+    }
+}
+
+/// Returns the description of a TLS alert, as registered in the TLS Alerts
+/// IANA registry.
+pub(crate) fn alert_description(alert: u8) -> Option<&'static str> {
+    let desc = match alert {
+        0 => "close_notify",
+        10 => "unexpected_message",
+        20 => "bad_record_mac",
+        22 => "record_overflow",
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        43 => "unsupported_certificate",
+        44 => "certificate_revoked",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        47 => "illegal_parameter",
+        48 => "unknown_ca",
+        49 => "access_denied",
+        50 => "decode_error",
+        51 => "decrypt_error",
+        70 => "protocol_version",
+        71 => "insufficient_security",
+        80 => "internal_error",
+        86 => "inappropriate_fallback",
+        90 => "user_canceled",
+        109 => "missing_extension",
+        110 => "unsupported_extension",
+        112 => "unrecognized_name",
+        113 => "bad_certificate_status_response",
+        115 => "unknown_psk_identity",
+        116 => "certificate_required",
+        120 => "no_application_protocol",
+        _ => return None,
+    };
+
+    Some(desc)
+}
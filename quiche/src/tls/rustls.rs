@@ -0,0 +1,1275 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! TLS backend based on rustls.
+//!
+//! rustls doesn't expose the traffic secrets directly, so they are collected
+//! through its key log while the handshake is driven, and used to create the
+//! packet protection contexts the same way as with BoringSSL.
+
+use std::cell::RefCell;
+
+use std::convert::TryFrom;
+
+use std::io::BufReader;
+
+use std::sync::Arc;
+
+use ring::aead;
+
+use rustls::quic::KeyChange;
+
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::server::NoClientAuth;
+
+use crate::Error;
+use crate::Result;
+
+use crate::ConnectionError;
+
+use crate::crypto;
+use crate::keyring;
+use crate::packet;
+
+use super::CertVerifier;
+use super::CertVerifyMode;
+use super::ExData;
+use super::INTERNAL_ERROR;
+use super::TLS_ALERT_ERROR;
+
+// The lifetime of the session tickets encrypted with a static key, matching
+// BoringSSL's default session timeout.
+const TICKET_LIFETIME: u32 = 2 * 60 * 60;
+
+thread_local! {
+    // The secrets logged by rustls while processing handshake data.
+    static SECRETS: RefCell<Vec<Secret>> = RefCell::new(Vec::new());
+}
+
+/// A secret derived by rustls.
+struct Secret {
+    label: String,
+
+    client_random: Vec<u8>,
+
+    secret: Vec<u8>,
+}
+
+/// Collects the secrets derived by rustls, instead of logging them.
+#[derive(Debug)]
+struct SecretLog;
+
+impl rustls::KeyLog for SecretLog {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        SECRETS.with(|s| {
+            s.borrow_mut().push(Secret {
+                label: label.to_string(),
+                client_random: client_random.to_vec(),
+                secret: secret.to_vec(),
+            })
+        });
+    }
+}
+
+/// Calls `f`, appending the secrets derived by rustls meanwhile to `secrets`.
+///
+/// rustls logs the secrets synchronously, so the ones collected on this
+/// thread while `f` runs belong to the connection being driven.
+fn with_secrets<T>(secrets: &mut Vec<Secret>, f: impl FnOnce() -> T) -> T {
+    SECRETS.with(|s| s.borrow_mut().clear());
+
+    let res = f();
+
+    SECRETS.with(|s| secrets.append(&mut s.borrow_mut()));
+
+    res
+}
+
+/// Accepts any server certificate, when peer verification is disabled.
+struct NoVerifier;
+
+impl rustls::client::ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self, _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>, _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> std::result::Result<rustls::client::ServerCertVerified, rustls::Error>
+    {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+/// Verifies the peer's certificate chain with the application's verifier,
+/// instead of the trusted CA certificates.
+struct CallbackVerifier {
+    verifier: Arc<dyn CertVerifier>,
+
+    /// Whether clients must present a certificate, on servers.
+    mandatory: bool,
+}
+
+impl CallbackVerifier {
+    fn verify(
+        &self, end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+    ) -> std::result::Result<(), rustls::Error> {
+        let chain: Vec<&[u8]> = std::iter::once(end_entity)
+            .chain(intermediates)
+            .map(|c| c.0.as_slice())
+            .collect();
+
+        // rustls chooses the alert sent to the peer, so the error returned by
+        // the verifier is not used.
+        self.verifier.verify(&chain).map_err(|_| {
+            rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            )
+        })
+    }
+}
+
+impl rustls::client::ServerCertVerifier for CallbackVerifier {
+    fn verify_server_cert(
+        &self, end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate], _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>, _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> std::result::Result<rustls::client::ServerCertVerified, rustls::Error>
+    {
+        self.verify(end_entity, intermediates)?;
+
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+impl rustls::server::ClientCertVerifier for CallbackVerifier {
+    fn client_auth_mandatory(&self) -> bool {
+        self.mandatory
+    }
+
+    fn client_auth_root_subjects(&self) -> &[rustls::DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self, end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate], _now: std::time::SystemTime,
+    ) -> std::result::Result<rustls::server::ClientCertVerified, rustls::Error>
+    {
+        self.verify(end_entity, intermediates)?;
+
+        Ok(rustls::server::ClientCertVerified::assertion())
+    }
+}
+
+/// The keys protecting session tickets.
+enum TicketKeySource {
+    /// The keys set with `set_ticket_key()` or `set_ticket_keys()`.
+    Static(Arc<keyring::TicketKeys>),
+
+    /// The key ring set with `set_key_ring()`, rotating its keys over time.
+    Ring(Arc<keyring::KeyRing>),
+}
+
+impl TicketKeySource {
+    fn encryption_key(&self) -> Option<keyring::TicketKey> {
+        match self {
+            TicketKeySource::Static(keys) => keys.encryption_key(),
+
+            TicketKeySource::Ring(ring) => ring.ticket_encryption_key(),
+        }
+    }
+
+    fn decryption_key(&self, name: &[u8]) -> Option<keyring::TicketKey> {
+        let key = match self {
+            TicketKeySource::Static(keys) => keys.decryption_key(name),
+
+            TicketKeySource::Ring(ring) => ring.ticket_decryption_key(name),
+        };
+
+        // Tickets encrypted with any of the keys are accepted, though rustls
+        // doesn't allow renewing those of the previous keys.
+        key.map(|(key, _)| key)
+    }
+}
+
+/// Encrypts session tickets with the keys set on the context, so that they
+/// can be decrypted by all the servers sharing them.
+struct StaticTicketer {
+    keys: TicketKeySource,
+}
+
+impl StaticTicketer {
+    // Returns the AES-256-GCM key made of the material of the ticket key.
+    fn aead_key(key: &keyring::TicketKey) -> Option<aead::LessSafeKey> {
+        let mut material = [0; 32];
+        material[..16].copy_from_slice(&key.aes_key);
+        material[16..].copy_from_slice(&key.hmac_key);
+
+        let key = aead::UnboundKey::new(&aead::AES_256_GCM, &material).ok()?;
+
+        Some(aead::LessSafeKey::new(key))
+    }
+}
+
+impl rustls::server::ProducesTickets for StaticTicketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        TICKET_LIFETIME
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let key = self.keys.encryption_key()?;
+
+        let mut nonce = [0; aead::NONCE_LEN];
+        crate::rand::rand_bytes(&mut nonce);
+
+        let mut ticket = Vec::with_capacity(
+            key.name.len() + nonce.len() + plain.len() + aead::MAX_TAG_LEN,
+        );
+
+        ticket.extend_from_slice(&key.name);
+        ticket.extend_from_slice(&nonce);
+
+        let mut ciphertext = plain.to_vec();
+
+        Self::aead_key(&key)?
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(&key.name),
+                &mut ciphertext,
+            )
+            .ok()?;
+
+        ticket.extend_from_slice(&ciphertext);
+
+        Some(ticket)
+    }
+
+    fn decrypt(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        let nonce_end = keyring::KEY_NAME_LEN + aead::NONCE_LEN;
+
+        if ticket.len() < nonce_end {
+            return None;
+        }
+
+        let key = self.keys.decryption_key(&ticket[..keyring::KEY_NAME_LEN])?;
+
+        let mut nonce = [0; aead::NONCE_LEN];
+        nonce.copy_from_slice(&ticket[keyring::KEY_NAME_LEN..nonce_end]);
+
+        let mut plain = ticket[nonce_end..].to_vec();
+
+        let len = Self::aead_key(&key)?
+            .open_in_place(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(&key.name),
+                &mut plain,
+            )
+            .ok()?
+            .len();
+
+        plain.truncate(len);
+
+        Some(plain)
+    }
+}
+
+pub struct Context {
+    roots: rustls::RootCertStore,
+
+    cert_chain: Vec<rustls::Certificate>,
+
+    key: Option<rustls::PrivateKey>,
+
+    /// Whether to verify the peer's certificate, if explicitly set. Clients
+    /// verify the server's certificate by default, while servers don't request
+    /// one from clients.
+    verify: Option<bool>,
+
+    verify_required: bool,
+
+    /// The verifier replacing the built-in verification, if any.
+    cert_verifier: Option<Arc<dyn CertVerifier>>,
+
+    keylog: bool,
+
+    alpn: Vec<Vec<u8>>,
+
+    ticket_keys: Option<Arc<keyring::TicketKeys>>,
+
+    key_ring: Option<Arc<keyring::KeyRing>>,
+
+    early_data: bool,
+
+    // The configurations are built lazily, and reused by all the handshakes
+    // until the context is modified. Reusing the client configuration also
+    // keeps the sessions used for resumption.
+    client_config: Option<Arc<rustls::ClientConfig>>,
+
+    server_config: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Context {
+    pub fn new() -> Result<Context> {
+        let mut ctx = Context {
+            roots: rustls::RootCertStore::empty(),
+            cert_chain: Vec::new(),
+            key: None,
+            verify: None,
+            verify_required: false,
+            cert_verifier: None,
+            keylog: false,
+            alpn: Vec::new(),
+            ticket_keys: None,
+            key_ring: None,
+            early_data: false,
+            client_config: None,
+            server_config: None,
+        };
+
+        ctx.load_ca_certs();
+
+        Ok(ctx)
+    }
+
+    pub fn new_handshake(&mut self) -> Result<Handshake> {
+        let client_config = match &self.client_config {
+            Some(v) => v.clone(),
+
+            None => {
+                let v = Arc::new(self.build_client_config()?);
+                self.client_config = Some(v.clone());
+                v
+            },
+        };
+
+        // A server configuration can only be built once a certificate and its
+        // key are loaded.
+        if self.server_config.is_none() && self.key.is_some() {
+            self.server_config = Some(Arc::new(self.build_server_config()?));
+        }
+
+        Ok(Handshake {
+            client_config,
+            server_config: self.server_config.clone(),
+            keylog: self.keylog,
+            is_server: false,
+            legacy_codepoint: false,
+            host_name: None,
+            verify_host_name: self.verify != Some(false) &&
+                self.cert_verifier.is_none(),
+            transport_params: Vec::new(),
+            conn: None,
+            write_level: crypto::Level::Initial,
+            secrets: Vec::new(),
+            error: None,
+            in_early_data: false,
+        })
+    }
+
+    pub fn load_verify_locations_from_file(&mut self, file: &str) -> Result<()> {
+        for cert in load_certs(file)? {
+            self.roots.add(&cert).map_err(|_| Error::TlsFail)?;
+        }
+
+        self.client_config = None;
+        self.server_config = None;
+
+        Ok(())
+    }
+
+    pub fn load_verify_locations_from_directory(
+        &mut self, path: &str,
+    ) -> Result<()> {
+        let dir = std::fs::read_dir(path).map_err(|_| Error::TlsFail)?;
+
+        // Files that don't contain certificates are ignored.
+        for entry in dir.flatten() {
+            if let Some(file) = entry.path().to_str() {
+                for cert in load_certs(file).unwrap_or_default() {
+                    self.roots.add(&cert).ok();
+                }
+            }
+        }
+
+        self.client_config = None;
+        self.server_config = None;
+
+        Ok(())
+    }
+
+    pub fn use_certificate_chain_file(&mut self, file: &str) -> Result<()> {
+        self.cert_chain = load_certs(file)?;
+
+        self.client_config = None;
+        self.server_config = None;
+
+        Ok(())
+    }
+
+    pub fn use_privkey_file(&mut self, file: &str) -> Result<()> {
+        self.key = Some(load_private_key(file)?);
+
+        self.client_config = None;
+        self.server_config = None;
+
+        Ok(())
+    }
+
+    fn load_ca_certs(&mut self) {
+        // As with BoringSSL's default verify paths, a missing system store
+        // is not an error.
+        if let Ok(certs) = rustls_native_certs::load_native_certs() {
+            let certs: Vec<Vec<u8>> = certs.into_iter().map(|c| c.0).collect();
+
+            self.roots.add_parsable_certificates(&certs);
+        }
+    }
+
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = Some(verify);
+        self.verify_required = false;
+
+        self.client_config = None;
+        self.server_config = None;
+    }
+
+    pub fn set_cert_verifier(&mut self, v: Option<Arc<dyn CertVerifier>>) {
+        // Verifiers called after the handshake are handled by the connection.
+        let v = v.filter(|v| v.mode() == CertVerifyMode::Replace);
+
+        if v.is_some() {
+            self.verify = Some(true);
+        }
+
+        self.cert_verifier = v;
+
+        self.client_config = None;
+        self.server_config = None;
+    }
+
+    pub fn set_verify_required(&mut self, required: bool) {
+        self.verify = Some(true);
+        self.verify_required = required;
+
+        self.client_config = None;
+        self.server_config = None;
+    }
+
+    pub fn enable_keylog(&mut self) {
+        self.keylog = true;
+    }
+
+    pub fn set_alpn(&mut self, v: &[&[u8]]) -> Result<()> {
+        self.alpn = v.iter().map(|p| p.to_vec()).collect();
+
+        self.client_config = None;
+        self.server_config = None;
+
+        Ok(())
+    }
+
+    pub fn set_ticket_key(&mut self, key: &[u8]) -> Result<()> {
+        let key = keyring::TicketKey::new(key)?;
+
+        self.set_ticket_keys(Arc::new(keyring::TicketKeys::new(&[key])?));
+
+        Ok(())
+    }
+
+    pub fn set_ticket_keys(&mut self, keys: Arc<keyring::TicketKeys>) {
+        self.ticket_keys = Some(keys);
+
+        self.server_config = None;
+    }
+
+    pub fn set_key_ring(&mut self, ring: Option<Arc<keyring::KeyRing>>) {
+        self.key_ring = ring;
+
+        self.server_config = None;
+    }
+
+    pub fn set_ticket_key_callback(&mut self, _enabled: bool) {
+        // Tickets are encrypted by the ticketer of the server configuration,
+        // which uses the ticket keys and the key ring set on the context.
+    }
+
+    pub fn set_early_data_enabled(&mut self, enabled: bool) {
+        self.early_data = enabled;
+
+        self.client_config = None;
+        self.server_config = None;
+    }
+
+    fn build_client_config(&self) -> Result<rustls::ClientConfig> {
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|_| Error::TlsFail)?
+            .with_root_certificates(self.roots.clone());
+
+        // The certificate is presented to servers requesting one, when loaded.
+        let mut config = match &self.key {
+            Some(key) if !self.cert_chain.is_empty() => builder
+                .with_client_auth_cert(self.cert_chain.clone(), key.clone())
+                .map_err(|_| Error::TlsFail)?,
+
+            _ => builder.with_no_client_auth(),
+        };
+
+        if self.verify == Some(false) {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerifier));
+        } else if let Some(verifier) = &self.cert_verifier {
+            config.dangerous().set_certificate_verifier(Arc::new(
+                CallbackVerifier {
+                    verifier: verifier.clone(),
+                    mandatory: false,
+                },
+            ));
+        }
+
+        config.alpn_protocols = self.alpn.clone();
+        config.enable_early_data = self.early_data;
+        config.key_log = Arc::new(SecretLog);
+
+        Ok(config)
+    }
+
+    fn build_server_config(&self) -> Result<rustls::ServerConfig> {
+        let key = self.key.clone().ok_or(Error::TlsFail)?;
+
+        let roots = self.roots.clone();
+
+        let client_verifier: Arc<dyn rustls::server::ClientCertVerifier> =
+            match (self.verify, &self.cert_verifier) {
+                (Some(true), Some(verifier)) => Arc::new(CallbackVerifier {
+                    verifier: verifier.clone(),
+                    mandatory: self.verify_required,
+                }),
+
+                (Some(true), None) if self.verify_required =>
+                    AllowAnyAuthenticatedClient::new(roots).boxed(),
+
+                (Some(true), None) =>
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+
+                _ => NoClientAuth::boxed(),
+            };
+
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|_| Error::TlsFail)?
+            .with_client_cert_verifier(client_verifier)
+            .with_single_cert(self.cert_chain.clone(), key)
+            .map_err(|_| Error::TlsFail)?;
+
+        config.alpn_protocols = self.alpn.clone();
+
+        // QUIC requires the maximum early data size to be either 0 or
+        // 0xffffffff.
+        config.max_early_data_size = if self.early_data { u32::MAX } else { 0 };
+
+        // As with BoringSSL, a key ring takes precedence over ticket keys.
+        let keys = match (&self.key_ring, &self.ticket_keys) {
+            (Some(ring), _) => Some(TicketKeySource::Ring(ring.clone())),
+
+            (None, Some(keys)) => Some(TicketKeySource::Static(keys.clone())),
+
+            (None, None) => None,
+        };
+
+        config.ticketer = match keys {
+            Some(keys) => Arc::new(StaticTicketer { keys }),
+
+            None => rustls::Ticketer::new().map_err(|_| Error::TlsFail)?,
+        };
+
+        config.key_log = Arc::new(SecretLog);
+
+        Ok(config)
+    }
+}
+
+pub struct Handshake {
+    client_config: Arc<rustls::ClientConfig>,
+
+    server_config: Option<Arc<rustls::ServerConfig>>,
+
+    keylog: bool,
+
+    is_server: bool,
+
+    legacy_codepoint: bool,
+
+    host_name: Option<String>,
+
+    /// Whether the server's certificate is verified against its host name,
+    /// which is then required.
+    verify_host_name: bool,
+
+    transport_params: Vec<u8>,
+
+    /// The rustls connection, created when the handshake starts.
+    conn: Option<rustls::quic::Connection>,
+
+    /// The level of the handshake data written by rustls.
+    write_level: crypto::Level,
+
+    /// The secrets that were derived but not installed yet.
+    secrets: Vec<Secret>,
+
+    /// The error to close the connection with, after a handshake failure.
+    error: Option<u64>,
+
+    in_early_data: bool,
+}
+
+impl Handshake {
+    pub fn init(&mut self, is_server: bool) -> Result<()> {
+        self.is_server = is_server;
+
+        Ok(())
+    }
+
+    pub fn use_legacy_codepoint(&mut self, use_legacy: bool) {
+        self.legacy_codepoint = use_legacy;
+    }
+
+    /// Sets the application protocols offered by a client connection, instead
+    /// of those of the context.
+    pub fn set_alpn(&mut self, v: &[&[u8]]) -> Result<()> {
+        let mut config = (*self.client_config).clone();
+        config.alpn_protocols = v.iter().map(|p| p.to_vec()).collect();
+
+        self.client_config = Arc::new(config);
+
+        Ok(())
+    }
+
+    pub fn set_host_name(&mut self, name: &str) -> Result<()> {
+        rustls::ServerName::try_from(name).map_err(|_| Error::TlsFail)?;
+
+        self.host_name = Some(name.to_string());
+
+        Ok(())
+    }
+
+    pub fn set_quic_transport_params(&mut self, buf: &[u8]) -> Result<()> {
+        // The parameters can't be changed once the handshake started.
+        if self.conn.is_some() {
+            return Err(Error::TlsFail);
+        }
+
+        self.transport_params = buf.to_vec();
+
+        Ok(())
+    }
+
+    pub fn quic_transport_params(&self) -> &[u8] {
+        self.conn
+            .as_ref()
+            .and_then(|c| c.quic_transport_parameters())
+            .unwrap_or(&[])
+    }
+
+    pub fn alpn_protocol(&self) -> &[u8] {
+        self.conn
+            .as_ref()
+            .and_then(|c| c.alpn_protocol())
+            .unwrap_or(&[])
+    }
+
+    pub fn server_name(&self) -> Option<&str> {
+        match &self.conn {
+            Some(rustls::quic::Connection::Server(c)) => c.server_name(),
+
+            _ => None,
+        }
+    }
+
+    pub fn set_session(&mut self, _session: &[u8]) -> Result<()> {
+        // rustls can't import sessions. Instead, the sessions received by the
+        // client are kept in the context, and resumed automatically.
+        Err(Error::TlsFail)
+    }
+
+    pub fn provide_data(
+        &mut self, _level: crypto::Level, buf: &[u8],
+    ) -> Result<()> {
+        self.start()?;
+
+        let conn = match &mut self.conn {
+            Some(v) => v,
+
+            None => return Err(Error::TlsFail),
+        };
+
+        // rustls tracks the level of the handshake data on its own.
+        let res = with_secrets(&mut self.secrets, || conn.read_hs(buf));
+
+        // Errors are reported when the handshake is driven, so that the alert
+        // is sent to the peer, as with BoringSSL.
+        if res.is_err() && self.error.is_none() {
+            self.error = Some(match conn.alert() {
+                Some(alert) => TLS_ALERT_ERROR + u64::from(alert.get_u8()),
+
+                None => INTERNAL_ERROR,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn do_handshake(&mut self, ex_data: &mut ExData) -> Result<()> {
+        self.start()?;
+
+        self.process(ex_data)?;
+
+        if !self.is_completed() {
+            return Err(Error::Done);
+        }
+
+        Ok(())
+    }
+
+    pub fn process_post_handshake(&mut self, ex_data: &mut ExData) -> Result<()> {
+        self.process(ex_data)
+    }
+
+    pub fn write_level(&self) -> crypto::Level {
+        self.write_level
+    }
+
+    pub fn cipher(&self) -> Option<crypto::Algorithm> {
+        let suite = self.conn.as_ref()?.negotiated_cipher_suite()?;
+
+        let alg = match suite.suite() {
+            rustls::CipherSuite::TLS13_AES_128_GCM_SHA256 =>
+                crypto::Algorithm::AES128_GCM,
+
+            rustls::CipherSuite::TLS13_AES_256_GCM_SHA384 =>
+                crypto::Algorithm::AES256_GCM,
+
+            rustls::CipherSuite::TLS13_CHACHA20_POLY1305_SHA256 =>
+                crypto::Algorithm::ChaCha20_Poly1305,
+
+            _ => return None,
+        };
+
+        Some(alg)
+    }
+
+    pub fn curve(&self) -> Option<String> {
+        // Not exposed by rustls.
+        None
+    }
+
+    pub fn sigalg(&self) -> Option<String> {
+        // Not exposed by rustls.
+        None
+    }
+
+    pub fn peer_cert_chain(&self) -> Option<Vec<&[u8]>> {
+        let certs = self.conn.as_ref()?.peer_certificates()?;
+
+        if certs.is_empty() {
+            return None;
+        }
+
+        Some(certs.iter().map(|c| c.0.as_slice()).collect())
+    }
+
+    pub fn export_keying_material(
+        &self, label: &[u8], context: Option<&[u8]>, out: &mut [u8],
+    ) -> Result<()> {
+        let res = match &self.conn {
+            Some(rustls::quic::Connection::Client(c)) =>
+                c.export_keying_material(out, label, context),
+
+            Some(rustls::quic::Connection::Server(c)) =>
+                c.export_keying_material(out, label, context),
+
+            None => return Err(Error::TlsFail),
+        };
+
+        res.map_err(|_| Error::TlsFail)
+    }
+
+    pub fn peer_cert(&self) -> Option<&[u8]> {
+        let certs = self.conn.as_ref()?.peer_certificates()?;
+
+        certs.first().map(|c| c.0.as_slice())
+    }
+
+    #[cfg(test)]
+    pub fn set_options(&mut self, opts: u32) {
+        // SSL_OP_NO_TICKET
+        if opts & 0x0000_4000 == 0 {
+            return;
+        }
+
+        if let Some(config) = &self.server_config {
+            let mut config = (**config).clone();
+            config.send_tls13_tickets = 0;
+
+            self.server_config = Some(Arc::new(config));
+        }
+    }
+
+    // Only used for testing handling of failure during key signing.
+    #[cfg(test)]
+    pub fn set_failing_private_key_method(&mut self) {
+        struct FailingSigner;
+
+        impl rustls::sign::Signer for FailingSigner {
+            fn sign(
+                &self, _message: &[u8],
+            ) -> std::result::Result<Vec<u8>, rustls::Error> {
+                Err(rustls::Error::General("signing failed".to_string()))
+            }
+
+            fn scheme(&self) -> rustls::SignatureScheme {
+                rustls::SignatureScheme::ECDSA_NISTP256_SHA256
+            }
+        }
+
+        struct FailingKey;
+
+        impl rustls::sign::SigningKey for FailingKey {
+            fn choose_scheme(
+                &self, _offered: &[rustls::SignatureScheme],
+            ) -> Option<Box<dyn rustls::sign::Signer>> {
+                Some(Box::new(FailingSigner))
+            }
+
+            fn algorithm(&self) -> rustls::SignatureAlgorithm {
+                rustls::SignatureAlgorithm::ECDSA
+            }
+        }
+
+        struct FailingResolver;
+
+        impl rustls::server::ResolvesServerCert for FailingResolver {
+            fn resolve(
+                &self, _client_hello: rustls::server::ClientHello,
+            ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+                Some(Arc::new(rustls::sign::CertifiedKey::new(
+                    Vec::new(),
+                    Arc::new(FailingKey),
+                )))
+            }
+        }
+
+        if let Some(config) = &self.server_config {
+            let mut config = (**config).clone();
+            config.cert_resolver = Arc::new(FailingResolver);
+
+            self.server_config = Some(Arc::new(config));
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.conn.as_ref().map_or(false, |c| !c.is_handshaking())
+    }
+
+    pub fn is_resumed(&self) -> bool {
+        // rustls doesn't report whether a session was resumed, but early data
+        // can only be accepted on resumed sessions.
+        self.early_data_accepted()
+    }
+
+    pub fn is_in_early_data(&self) -> bool {
+        self.in_early_data && !self.is_completed()
+    }
+
+    pub fn early_data_accepted(&self) -> bool {
+        match &self.conn {
+            Some(rustls::quic::Connection::Client(c)) =>
+                c.is_early_data_accepted(),
+
+            Some(c) => c.zero_rtt_keys().is_some(),
+
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.conn = None;
+        self.write_level = crypto::Level::Initial;
+        self.secrets.clear();
+        self.error = None;
+        self.in_early_data = false;
+
+        Ok(())
+    }
+
+    /// Creates the rustls connection, if not done yet.
+    fn start(&mut self) -> Result<()> {
+        if self.conn.is_some() {
+            return Ok(());
+        }
+
+        let version = if self.legacy_codepoint {
+            rustls::quic::Version::V1Draft
+        } else {
+            rustls::quic::Version::V1
+        };
+
+        let params = self.transport_params.clone();
+
+        let conn = if self.is_server {
+            let config = self.server_config.clone().ok_or(Error::TlsFail)?;
+
+            rustls::quic::ServerConnection::new(config, version, params)
+                .map(rustls::quic::Connection::Server)
+        } else {
+            let config = self.client_config.clone();
+
+            // Without a host name, no SNI is sent. The certificate can't be
+            // verified then, unless by the application's callback.
+            let name = match &self.host_name {
+                Some(v) => rustls::ServerName::try_from(v.as_str())
+                    .map_err(|_| Error::TlsFail)?,
+
+                None if self.verify_host_name => return Err(Error::TlsFail),
+
+                None => rustls::ServerName::IpAddress(
+                    std::net::Ipv4Addr::UNSPECIFIED.into(),
+                ),
+            };
+
+            // The client derives the early data secret right away, when
+            // resuming a session.
+            with_secrets(&mut self.secrets, || {
+                rustls::quic::ClientConnection::new(config, version, name, params)
+            })
+            .map(rustls::quic::Connection::Client)
+        };
+
+        self.conn = Some(conn.map_err(|_| Error::TlsFail)?);
+
+        Ok(())
+    }
+
+    /// Queues the handshake data produced by rustls and installs the new
+    /// packet protection keys.
+    fn process(&mut self, ex_data: &mut ExData) -> Result<()> {
+        if let Some(error_code) = self.error {
+            if ex_data.local_error.is_none() {
+                *ex_data.local_error = Some(ConnectionError {
+                    is_app: false,
+                    error_code,
+                    frame_type: 0,
+                    reason: Vec::new(),
+                });
+            }
+
+            return Err(Error::TlsFail);
+        }
+
+        let conn = match &mut self.conn {
+            Some(v) => v,
+
+            None => return Ok(()),
+        };
+
+        loop {
+            let mut buf = Vec::new();
+
+            let key_change =
+                with_secrets(&mut self.secrets, || conn.write_hs(&mut buf));
+
+            if !buf.is_empty() {
+                trace!(
+                    "{} write message lvl={:?} len={}",
+                    ex_data.trace_id,
+                    self.write_level,
+                    buf.len()
+                );
+
+                let space = match self.write_level {
+                    crypto::Level::Initial =>
+                        &mut ex_data.pkt_num_spaces[packet::Epoch::Initial],
+                    crypto::Level::ZeroRTT => unreachable!(),
+                    crypto::Level::Handshake =>
+                        &mut ex_data.pkt_num_spaces[packet::Epoch::Handshake],
+                    crypto::Level::OneRTT =>
+                        &mut ex_data.pkt_num_spaces[packet::Epoch::Application],
+                };
+
+                space.crypto_stream.send.write(&buf, false)?;
+            }
+
+            // The data written after a key change uses the new level.
+            match key_change {
+                Some(KeyChange::Handshake { .. }) =>
+                    self.write_level = crypto::Level::Handshake,
+
+                Some(KeyChange::OneRtt { .. }) =>
+                    self.write_level = crypto::Level::OneRTT,
+
+                None => break,
+            }
+        }
+
+        self.install_secrets(ex_data)
+    }
+
+    /// Creates the packet protection contexts from the collected secrets.
+    fn install_secrets(&mut self, ex_data: &mut ExData) -> Result<()> {
+        let aead = match self.cipher() {
+            Some(v) => v,
+
+            // Wait for the cipher suite to be negotiated.
+            None => return Ok(()),
+        };
+
+        let is_completed = self.is_completed();
+
+        let mut pending = Vec::new();
+
+        for s in self.secrets.drain(..) {
+            let (level, from_client) = match s.label.as_str() {
+                "CLIENT_EARLY_TRAFFIC_SECRET" => (crypto::Level::ZeroRTT, true),
+
+                "CLIENT_HANDSHAKE_TRAFFIC_SECRET" =>
+                    (crypto::Level::Handshake, true),
+
+                "SERVER_HANDSHAKE_TRAFFIC_SECRET" =>
+                    (crypto::Level::Handshake, false),
+
+                "CLIENT_TRAFFIC_SECRET_0" => (crypto::Level::OneRTT, true),
+
+                "SERVER_TRAFFIC_SECRET_0" => (crypto::Level::OneRTT, false),
+
+                _ => continue,
+            };
+
+            // As with BoringSSL, the server only reads 1-RTT packets once the
+            // handshake is completed.
+            if ex_data.is_server &&
+                from_client &&
+                level == crypto::Level::OneRTT &&
+                !is_completed
+            {
+                pending.push(s);
+                continue;
+            }
+
+            if self.keylog {
+                let line = format!(
+                    "{} {} {}",
+                    s.label,
+                    hex(&s.client_random),
+                    hex(&s.secret)
+                );
+
+                ex_data.log_key(&line);
+            }
+
+            trace!(
+                "{} set {} secret lvl={:?}",
+                ex_data.trace_id,
+                if from_client == ex_data.is_server {
+                    "read"
+                } else {
+                    "write"
+                },
+                level
+            );
+
+            let space = match level {
+                crypto::Level::Initial =>
+                    &mut ex_data.pkt_num_spaces[packet::Epoch::Initial],
+                crypto::Level::ZeroRTT =>
+                    &mut ex_data.pkt_num_spaces[packet::Epoch::Application],
+                crypto::Level::Handshake =>
+                    &mut ex_data.pkt_num_spaces[packet::Epoch::Handshake],
+                crypto::Level::OneRTT =>
+                    &mut ex_data.pkt_num_spaces[packet::Epoch::Application],
+            };
+
+            if from_client == ex_data.is_server {
+                let open = crypto::Open::from_secret(
+                    ex_data.crypto_provider,
+                    aead,
+                    ex_data.version,
+                    &s.secret,
+                )?;
+
+                if level == crypto::Level::ZeroRTT {
+                    space.crypto_0rtt_open = Some(open);
+                } else {
+                    space.crypto_open = Some(open);
+                }
+            } else {
+                let seal = crypto::Seal::from_secret(
+                    ex_data.crypto_provider,
+                    aead,
+                    ex_data.version,
+                    &s.secret,
+                )?;
+
+                space.crypto_seal = Some(seal);
+
+                if level == crypto::Level::ZeroRTT {
+                    self.in_early_data = true;
+                }
+            }
+        }
+
+        self.secrets = pending;
+
+        Ok(())
+    }
+}
+
+fn hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn load_certs(file: &str) -> Result<Vec<rustls::Certificate>> {
+    let file = std::fs::File::open(file).map_err(|_| Error::TlsFail)?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|_| Error::TlsFail)?;
+
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn load_private_key(file: &str) -> Result<rustls::PrivateKey> {
+    let file = std::fs::File::open(file).map_err(|_| Error::TlsFail)?;
+
+    let mut reader = BufReader::new(file);
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader) {
+            Ok(Some(rustls_pemfile::Item::RSAKey(key))) |
+            Ok(Some(rustls_pemfile::Item::PKCS8Key(key))) |
+            Ok(Some(rustls_pemfile::Item::ECKey(key))) =>
+                return Ok(rustls::PrivateKey(key)),
+
+            Ok(Some(_)) => continue,
+
+            _ => return Err(Error::TlsFail),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustls::server::ProducesTickets;
+
+    use std::time::Duration;
+
+    #[test]
+    fn ticketer_key_ring() {
+        let ring = Arc::new(keyring::KeyRing::new(
+            Duration::from_secs(3600),
+            Duration::from_secs(600),
+        ));
+
+        let ticketer = StaticTicketer {
+            keys: TicketKeySource::Ring(ring.clone()),
+        };
+
+        let ticket = ticketer.encrypt(b"session").unwrap();
+        assert_eq!(ticketer.decrypt(&ticket), Some(b"session".to_vec()));
+
+        // Tickets of the retired key are still accepted.
+        ring.rotate();
+        assert_eq!(ticketer.decrypt(&ticket), Some(b"session".to_vec()));
+
+        // Tickets of another ring are not.
+        let other = StaticTicketer {
+            keys: TicketKeySource::Ring(Arc::new(keyring::KeyRing::new(
+                Duration::from_secs(3600),
+                Duration::from_secs(600),
+            ))),
+        };
+
+        assert_eq!(other.decrypt(&ticket), None);
+    }
+
+    #[test]
+    fn ticketer_ticket_keys() {
+        let key = |b: u8| keyring::TicketKey::new(&[b; 48]).unwrap();
+
+        let keys = Arc::new(keyring::TicketKeys::new(&[key(1)]).unwrap());
+
+        let ticketer = StaticTicketer {
+            keys: TicketKeySource::Static(keys.clone()),
+        };
+
+        let ticket = ticketer.encrypt(b"session").unwrap();
+
+        keys.rotate(key(2));
+        assert_eq!(ticketer.decrypt(&ticket), Some(b"session".to_vec()));
+
+        // Tampered tickets are rejected.
+        let mut tampered = ticket.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(ticketer.decrypt(&tampered), None);
+
+        assert_eq!(ticketer.decrypt(&ticket[..8]), None);
+    }
+
+    #[test]
+    fn client_host_name() {
+        let mut ctx = Context::new().unwrap();
+
+        // The server's certificate can't be verified without a host name.
+        let mut hs = ctx.new_handshake().unwrap();
+        hs.init(false).unwrap();
+        assert_eq!(hs.start(), Err(Error::TlsFail));
+
+        let mut hs = ctx.new_handshake().unwrap();
+        hs.init(false).unwrap();
+        hs.set_host_name("quic.tech").unwrap();
+        assert_eq!(hs.start(), Ok(()));
+
+        // It isn't required when verification is disabled.
+        ctx.set_verify(false);
+
+        let mut hs = ctx.new_handshake().unwrap();
+        hs.init(false).unwrap();
+        assert_eq!(hs.start(), Ok(()));
+    }
+}
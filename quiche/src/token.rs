@@ -0,0 +1,355 @@
+// Copyright (C) 2022, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::ConnectionId;
+use crate::Error;
+use crate::KeyRing;
+use crate::Result;
+
+const RETRY_TOKEN: u8 = 0;
+
+const NEW_TOKEN: u8 = 1;
+
+/// The content of an address validation token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressToken {
+    /// A token sent in a Retry packet.
+    ///
+    /// It holds the original destination connection ID chosen by the client,
+    /// which is needed to accept the connection once the client echoes the
+    /// token back.
+    Retry {
+        /// The destination connection ID of the client's first Initial
+        /// packet.
+        odcid: ConnectionId<'static>,
+    },
+
+    /// A token sent in a NEW_TOKEN frame, for the client to validate its
+    /// address with in future connections.
+    NewToken,
+}
+
+/// A source of address validation tokens.
+///
+/// Servers configured with a minter through [`set_token_minter()`] use it
+/// to create the tokens of the Retry packets written by [`retry_minted()`],
+/// to validate the tokens echoed back by clients in [`accept_with_token()`],
+/// and to send clients a token in a NEW_TOKEN frame once the handshake is
+/// completed.
+///
+/// Tokens must be bound to the client's address and authenticated, so that
+/// they can't be forged or replayed from another address.
+/// [`KeyRingTokenMinter`] provides such tokens, encrypted with the keys of
+/// a [`KeyRing`].
+///
+/// [`set_token_minter()`]: struct.Config.html#method.set_token_minter
+/// [`retry_minted()`]: fn.retry_minted.html
+/// [`accept_with_token()`]: fn.accept_with_token.html
+/// [`KeyRingTokenMinter`]: struct.KeyRingTokenMinter.html
+/// [`KeyRing`]: struct.KeyRing.html
+pub trait TokenMinter: Send + Sync {
+    /// Creates a token holding `token` for a client at `peer`.
+    fn mint(&self, token: &AddressToken, peer: &SocketAddr) -> Result<Vec<u8>>;
+
+    /// Validates a token sent by a client at `peer`, and returns its content.
+    ///
+    /// An error is returned if the token is not valid for that client.
+    fn validate(&self, token: &[u8], peer: &SocketAddr) -> Result<AddressToken>;
+
+    /// Returns whether `token` was minted for a Retry packet, without
+    /// validating it.
+    ///
+    /// Initial packets carrying an invalid Retry token are dropped, while an
+    /// invalid NEW_TOKEN token is handled as if no token was sent, as required
+    /// by [RFC 9000]. By default all tokens are considered Retry tokens.
+    ///
+    /// [RFC 9000]: https://www.rfc-editor.org/rfc/rfc9000.html#section-8.1.3
+    fn is_retry_token(&self, _token: &[u8]) -> bool {
+        true
+    }
+}
+
+/// A [`TokenMinter`] encrypting tokens with the keys of a [`KeyRing`].
+///
+/// Tokens hold the client's IP address and the time they were created at,
+/// and are only accepted from the same IP address until they expire. Retry
+/// tokens are expected to be echoed back immediately, so they usually have a
+/// much shorter lifetime than NEW_TOKEN ones. Tokens are also rejected once
+/// the key they were encrypted with is dropped from the ring, so a token
+/// minted right before a rotation only remains valid during the overlap
+/// window of the ring: lifetimes should not exceed it.
+///
+/// [`TokenMinter`]: trait.TokenMinter.html
+/// [`KeyRing`]: struct.KeyRing.html
+///
+/// ## Examples:
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// use quiche::TokenMinter;
+///
+/// let ring = Arc::new(quiche::KeyRing::new(
+///     Duration::from_secs(3600),
+///     Duration::from_secs(600),
+/// ));
+///
+/// let minter = quiche::KeyRingTokenMinter::new(
+///     ring,
+///     Duration::from_secs(10),
+///     Duration::from_secs(3600),
+/// );
+///
+/// let peer = "127.0.0.1:1234".parse().unwrap();
+///
+/// let token = minter.mint(&quiche::AddressToken::NewToken, &peer)?;
+/// assert_eq!(
+///     minter.validate(&token, &peer)?,
+///     quiche::AddressToken::NewToken
+/// );
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub struct KeyRingTokenMinter {
+    ring: Arc<KeyRing>,
+
+    retry_lifetime: Duration,
+
+    new_token_lifetime: Duration,
+}
+
+impl KeyRingTokenMinter {
+    /// Creates a minter encrypting tokens with the keys of `ring`, and
+    /// accepting Retry and NEW_TOKEN tokens during `retry_lifetime` and
+    /// `new_token_lifetime` respectively.
+    pub fn new(
+        ring: Arc<KeyRing>, retry_lifetime: Duration,
+        new_token_lifetime: Duration,
+    ) -> KeyRingTokenMinter {
+        KeyRingTokenMinter {
+            ring,
+            retry_lifetime,
+            new_token_lifetime,
+        }
+    }
+
+    fn mint_at(
+        &self, token: &AddressToken, peer: &SocketAddr, now: SystemTime,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        match token {
+            AddressToken::Retry { .. } => data.push(RETRY_TOKEN),
+
+            AddressToken::NewToken => data.push(NEW_TOKEN),
+        }
+
+        data.extend_from_slice(&unix_time(now).to_be_bytes());
+
+        match peer.ip() {
+            IpAddr::V4(a) => data.extend_from_slice(&a.octets()),
+
+            IpAddr::V6(a) => data.extend_from_slice(&a.octets()),
+        }
+
+        if let AddressToken::Retry { odcid } = token {
+            data.extend_from_slice(odcid);
+        }
+
+        // The kind of token is also sent in the clear, so that invalid tokens
+        // can be told apart.
+        let mut token = vec![data[0]];
+        token.extend_from_slice(&self.ring.seal_token(&data)?);
+
+        Ok(token)
+    }
+
+    fn validate_at(
+        &self, token: &[u8], peer: &SocketAddr, now: SystemTime,
+    ) -> Result<AddressToken> {
+        let (clear_kind, sealed) =
+            token.split_first().ok_or(Error::CryptoFail)?;
+
+        let data = self.ring.open_token(sealed)?;
+
+        let mut b = octets::Octets::with_slice(&data);
+
+        let kind = b.get_u8().map_err(|_| Error::CryptoFail)?;
+        let created = b.get_u64().map_err(|_| Error::CryptoFail)?;
+
+        if kind != *clear_kind {
+            return Err(Error::CryptoFail);
+        }
+
+        let lifetime = match kind {
+            RETRY_TOKEN => self.retry_lifetime,
+
+            NEW_TOKEN => self.new_token_lifetime,
+
+            _ => return Err(Error::CryptoFail),
+        };
+
+        if unix_time(now).saturating_sub(created) > lifetime.as_secs() {
+            return Err(Error::CryptoFail);
+        }
+
+        let addr_len = match peer.ip() {
+            IpAddr::V4(_) => 4,
+
+            IpAddr::V6(_) => 16,
+        };
+
+        let addr = b.get_bytes(addr_len).map_err(|_| Error::CryptoFail)?;
+
+        let matches = match peer.ip() {
+            IpAddr::V4(a) => addr.as_ref() == a.octets(),
+
+            IpAddr::V6(a) => addr.as_ref() == a.octets(),
+        };
+
+        if !matches {
+            return Err(Error::CryptoFail);
+        }
+
+        match kind {
+            RETRY_TOKEN => Ok(AddressToken::Retry {
+                odcid: ConnectionId::from_vec(b.to_vec()),
+            }),
+
+            _ if b.cap() > 0 => Err(Error::CryptoFail),
+
+            _ => Ok(AddressToken::NewToken),
+        }
+    }
+}
+
+impl TokenMinter for KeyRingTokenMinter {
+    fn mint(&self, token: &AddressToken, peer: &SocketAddr) -> Result<Vec<u8>> {
+        self.mint_at(token, peer, SystemTime::now())
+    }
+
+    fn validate(&self, token: &[u8], peer: &SocketAddr) -> Result<AddressToken> {
+        self.validate_at(token, peer, SystemTime::now())
+    }
+
+    fn is_retry_token(&self, token: &[u8]) -> bool {
+        token.first() == Some(&RETRY_TOKEN)
+    }
+}
+
+fn unix_time(now: SystemTime) -> u64 {
+    now.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minter() -> KeyRingTokenMinter {
+        let ring = KeyRing::new(Duration::from_secs(60), Duration::from_secs(10));
+
+        KeyRingTokenMinter::new(
+            Arc::new(ring),
+            Duration::from_secs(10),
+            Duration::from_secs(3600),
+        )
+    }
+
+    #[test]
+    fn retry_token() {
+        let minter = minter();
+
+        let peer = "127.0.0.1:1234".parse().unwrap();
+        let odcid = ConnectionId::from_vec(vec![0xba; 16]);
+
+        let token = AddressToken::Retry { odcid };
+
+        let minted = minter.mint(&token, &peer).unwrap();
+        assert_eq!(minter.validate(&minted, &peer), Ok(token));
+
+        // Only the IP address of the client is checked.
+        let other_port = "127.0.0.1:4321".parse().unwrap();
+        assert!(minter.validate(&minted, &other_port).is_ok());
+
+        let other_addr = "127.0.0.2:1234".parse().unwrap();
+        assert_eq!(
+            minter.validate(&minted, &other_addr),
+            Err(Error::CryptoFail)
+        );
+
+        let v6 = "[::1]:1234".parse().unwrap();
+        assert_eq!(minter.validate(&minted, &v6), Err(Error::CryptoFail));
+
+        assert!(minter.is_retry_token(&minted));
+
+        // The kind of token sent in the clear is authenticated.
+        let mut forged = minted.clone();
+        forged[0] = NEW_TOKEN;
+        assert!(!minter.is_retry_token(&forged));
+        assert_eq!(minter.validate(&forged, &peer), Err(Error::CryptoFail));
+
+        let new_token = minter.mint(&AddressToken::NewToken, &peer).unwrap();
+        assert!(!minter.is_retry_token(&new_token));
+    }
+
+    #[test]
+    fn expired_token() {
+        let minter = minter();
+
+        let peer = "[::1]:1234".parse().unwrap();
+        let now = SystemTime::now();
+
+        let retry = AddressToken::Retry {
+            odcid: ConnectionId::from_vec(vec![0xba; 8]),
+        };
+        let retry_token = minter.mint_at(&retry, &peer, now).unwrap();
+
+        let new_token =
+            minter.mint_at(&AddressToken::NewToken, &peer, now).unwrap();
+
+        let later = now + Duration::from_secs(11);
+        assert_eq!(
+            minter.validate_at(&retry_token, &peer, later),
+            Err(Error::CryptoFail)
+        );
+        assert_eq!(
+            minter.validate_at(&new_token, &peer, later),
+            Ok(AddressToken::NewToken)
+        );
+
+        let later = now + Duration::from_secs(3601);
+        assert_eq!(
+            minter.validate_at(&new_token, &peer, later),
+            Err(Error::CryptoFail)
+        );
+    }
+}
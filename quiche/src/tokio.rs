@@ -0,0 +1,1193 @@
+// Copyright (C) 2023, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Async integration with the [tokio] runtime.
+//!
+//! An [`Endpoint`] owns a UDP socket and drives all the connections using it
+//! from a background task: the task receives and sends packets, fires the
+//! connections' timers, and wakes up the tasks waiting on them. Connections
+//! are then used through [`Connection`] handles, and their streams through
+//! [`Stream`] handles implementing [`AsyncRead`] and [`AsyncWrite`].
+//!
+//! ```no_run
+//! use tokio::io::AsyncReadExt;
+//! use tokio::io::AsyncWriteExt;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+//! config.set_application_protos(&[b"example-proto"])?;
+//!
+//! let local = "0.0.0.0:0".parse()?;
+//! let endpoint = quiche::tokio::Endpoint::client(local, config).await?;
+//!
+//! let peer = "127.0.0.1:4433".parse()?;
+//! let conn = endpoint.connect(Some("quic.tech"), peer).await?;
+//!
+//! let mut stream = conn.open_bi()?;
+//! stream.write_all(b"hello").await?;
+//! stream.shutdown().await?;
+//!
+//! let mut buf = Vec::new();
+//! stream.read_to_end(&mut buf).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Packets are sent as soon as they are produced, without pacing. Server
+//! endpoints can validate the address of clients with a Retry packet, see
+//! [`Endpoint::enable_retry()`].
+//!
+//! [tokio]: https://tokio.rs
+//! [`Endpoint`]: struct.Endpoint.html
+//! [`Endpoint::enable_retry()`]: struct.Endpoint.html#method.enable_retry
+//! [`Connection`]: struct.Connection.html
+//! [`Stream`]: struct.Stream.html
+//! [`AsyncRead`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncRead.html
+//! [`AsyncWrite`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncWrite.html
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use std::io;
+
+use std::net::SocketAddr;
+
+use std::pin::Pin;
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+use std::time::Instant;
+
+use ::tokio::io::AsyncRead;
+use ::tokio::io::AsyncWrite;
+use ::tokio::io::ReadBuf;
+use ::tokio::net::UdpSocket;
+use ::tokio::sync::mpsc;
+use ::tokio::sync::Notify;
+
+use crate::endpoint::InitialToken;
+use crate::ConnectionId;
+use crate::RecvInfo;
+
+// The size of the buffers packets are received into and sent from.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// A UDP socket and the connections using it.
+///
+/// The connections are driven by a task spawned on the tokio runtime the
+/// endpoint is created from. The task stops once the endpoint is dropped and
+/// all of its connections are closed.
+pub struct Endpoint {
+    shared: Arc<EndpointShared>,
+
+    incoming: mpsc::UnboundedReceiver<Connection>,
+}
+
+impl Endpoint {
+    /// Creates a server endpoint bound to `addr`, which accepts the
+    /// connections of clients with `config`.
+    pub async fn server(
+        addr: SocketAddr, config: crate::Config,
+    ) -> io::Result<Endpoint> {
+        let socket = UdpSocket::bind(addr).await?;
+
+        Endpoint::new(socket, config, true)
+    }
+
+    /// Creates a client endpoint bound to `addr`, which connects to servers
+    /// with `config`.
+    pub async fn client(
+        addr: SocketAddr, config: crate::Config,
+    ) -> io::Result<Endpoint> {
+        let socket = UdpSocket::bind(addr).await?;
+
+        Endpoint::new(socket, config, false)
+    }
+
+    /// Creates an endpoint using `socket`.
+    ///
+    /// When `is_server` is true, the endpoint accepts the connections of
+    /// clients. This must be called from the context of a tokio runtime.
+    pub fn new(
+        socket: UdpSocket, config: crate::Config, is_server: bool,
+    ) -> io::Result<Endpoint> {
+        let local_addr = socket.local_addr()?;
+
+        let shared = Arc::new(EndpointShared {
+            socket,
+            local_addr,
+            is_server,
+            config: Mutex::new(config),
+            table: Mutex::new(ConnTable::default()),
+            driver: Arc::new(Driver::default()),
+            retry: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        });
+
+        let (tx, incoming) = mpsc::unbounded_channel();
+
+        ::tokio::spawn(drive(shared.clone(), tx));
+
+        Ok(Endpoint { shared, incoming })
+    }
+
+    /// Returns the local address of the endpoint's socket.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.shared.local_addr
+    }
+
+    /// Configures whether server endpoints validate the address of clients
+    /// with a Retry packet before creating connections.
+    ///
+    /// The Retry tokens are created and validated by the minter set with
+    /// [`set_token_minter()`] on the endpoint's configuration, and this has
+    /// no effect when no minter is configured. See
+    /// [`quiche::Endpoint::enable_retry()`] for more details.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`set_token_minter()`]: ../struct.Config.html#method.set_token_minter
+    /// [`quiche::Endpoint::enable_retry()`]:
+    /// ../struct.Endpoint.html#method.enable_retry
+    pub fn enable_retry(&self, v: bool) {
+        self.shared.retry.store(v, Ordering::Release);
+    }
+
+    /// Waits for a client connection, and returns it once its handshake is
+    /// completed.
+    ///
+    /// `None` is returned on client endpoints.
+    pub async fn accept(&mut self) -> Option<Connection> {
+        if !self.shared.is_server {
+            return None;
+        }
+
+        self.incoming.recv().await
+    }
+
+    /// Connects to the server at `peer`, and waits for the handshake to
+    /// complete.
+    ///
+    /// The optional `server_name` is used to verify the server's certificate.
+    pub async fn connect(
+        &self, server_name: Option<&str>, peer: SocketAddr,
+    ) -> io::Result<Connection> {
+        let scid = new_scid();
+
+        let conn = {
+            let mut config = lock(&self.shared.config);
+
+            crate::connect(
+                server_name,
+                &ConnectionId::from_ref(&scid),
+                self.shared.local_addr,
+                peer,
+                &mut config,
+            )
+            .map_err(io_error)?
+        };
+
+        let conn = self.shared.insert(conn, vec![scid]);
+
+        conn.shared.wake_driver();
+
+        conn.established().await?;
+
+        Ok(conn)
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.driver.notify.notify_one();
+    }
+}
+
+/// A QUIC connection driven by an [`Endpoint`].
+///
+/// Dropping the handle closes the connection, so it needs to outlive the
+/// connection's streams.
+///
+/// [`Endpoint`]: struct.Endpoint.html
+pub struct Connection {
+    shared: Arc<ConnShared>,
+}
+
+impl Connection {
+    /// Opens a new bidirectional stream.
+    pub fn open_bi(&self) -> io::Result<Stream> {
+        self.open(true)
+    }
+
+    /// Opens a new unidirectional stream.
+    pub fn open_uni(&self) -> io::Result<Stream> {
+        self.open(false)
+    }
+
+    /// Waits for a new bidirectional stream opened by the peer.
+    ///
+    /// Streams are returned once the peer sent data on them, and `None` is
+    /// returned once the connection is closed.
+    pub async fn accept_bi(&self) -> Option<Stream> {
+        std::future::poll_fn(|cx| self.poll_accept(cx, true)).await
+    }
+
+    /// Waits for a new unidirectional stream opened by the peer.
+    ///
+    /// Streams are returned once the peer sent data on them, and `None` is
+    /// returned once the connection is closed.
+    pub async fn accept_uni(&self) -> Option<Stream> {
+        std::future::poll_fn(|cx| self.poll_accept(cx, false)).await
+    }
+
+    /// Closes the connection with the given application error code and
+    /// reason.
+    pub fn close(&self, err: u64, reason: &[u8]) -> io::Result<()> {
+        let mut state = self.shared.lock();
+
+        match state.conn.close(true, err, reason) {
+            // The connection is already closed.
+            Ok(()) | Err(crate::Error::Done) => (),
+
+            Err(e) => return Err(io_error(e)),
+        }
+
+        self.shared.wake_driver();
+
+        Ok(())
+    }
+
+    /// Waits for the connection to be closed.
+    pub async fn closed(&self) {
+        std::future::poll_fn(|cx| {
+            let mut state = self.shared.lock();
+
+            if state.conn.is_closed() {
+                return Poll::Ready(());
+            }
+
+            state.register(cx);
+
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Calls `f` with the underlying connection, for example to get its
+    /// statistics or to send datagrams.
+    pub fn with<R>(&self, f: impl FnOnce(&mut crate::Connection) -> R) -> R {
+        let mut state = self.shared.lock();
+
+        let res = f(&mut state.conn);
+
+        // The connection's state might have changed.
+        state.wake_all();
+        self.shared.wake_driver();
+
+        res
+    }
+
+    fn open(&self, bidi: bool) -> io::Result<Stream> {
+        let mut state = self.shared.lock();
+
+        if state.conn.is_closed() {
+            return Err(state.closed_error());
+        }
+
+        let stream_id = if bidi {
+            state.next_bidi
+        } else {
+            state.next_uni
+        };
+
+        // Create the stream right away, so that it can be read from before
+        // anything is written to it.
+        state
+            .conn
+            .stream_priority(stream_id, crate::stream::DEFAULT_URGENCY, true)
+            .map_err(io_error)?;
+
+        if bidi {
+            state.next_bidi += 4;
+        } else {
+            state.next_uni += 4;
+        }
+
+        Ok(Stream::new(self.shared.clone(), stream_id))
+    }
+
+    fn poll_accept(&self, cx: &Context, bidi: bool) -> Poll<Option<Stream>> {
+        let mut guard = self.shared.lock();
+        let state = &mut *guard;
+
+        let is_server = state.conn.is_server;
+
+        let stream_id = state.conn.readable().find(|id| {
+            !crate::stream::is_local(*id, is_server) &&
+                crate::stream::is_bidi(*id) == bidi &&
+                !state.accepted.contains(id)
+        });
+
+        if let Some(stream_id) = stream_id {
+            state.accepted.insert(stream_id);
+
+            let stream = Stream::new(self.shared.clone(), stream_id);
+
+            return Poll::Ready(Some(stream));
+        }
+
+        if state.conn.is_closed() {
+            return Poll::Ready(None);
+        }
+
+        state.register(cx);
+
+        Poll::Pending
+    }
+
+    async fn established(&self) -> io::Result<()> {
+        std::future::poll_fn(|cx| {
+            let mut state = self.shared.lock();
+
+            if state.conn.is_established() {
+                return Poll::Ready(Ok(()));
+            }
+
+            if state.conn.is_closed() {
+                return Poll::Ready(Err(state.closed_error()));
+            }
+
+            state.register(cx);
+
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+
+        if state.conn.close(true, 0x0, b"").is_ok() {
+            self.shared.wake_driver();
+        }
+    }
+}
+
+/// A stream of a [`Connection`].
+///
+/// Shutting the stream down with [`AsyncWrite::poll_shutdown()`] sends a FIN
+/// to the peer.
+///
+/// [`Connection`]: struct.Connection.html
+/// [`AsyncWrite::poll_shutdown()`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncWrite.html#tymethod.poll_shutdown
+pub struct Stream {
+    shared: Arc<ConnShared>,
+
+    stream_id: u64,
+
+    // Whether the FIN was read from or written to the stream.
+    fin_read: bool,
+    fin_written: bool,
+}
+
+impl Stream {
+    fn new(shared: Arc<ConnShared>, stream_id: u64) -> Stream {
+        Stream {
+            shared,
+            stream_id,
+            fin_read: false,
+            fin_written: false,
+        }
+    }
+
+    /// Returns the stream's ID.
+    pub fn id(&self) -> u64 {
+        self.stream_id
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.fin_read {
+            return Poll::Ready(Ok(()));
+        }
+
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+
+        let stream_id = self.stream_id;
+
+        match state.conn.stream_recv(stream_id, buf.initialize_unfilled()) {
+            Ok((len, fin)) => {
+                buf.advance(len);
+                self.fin_read = fin;
+
+                // Reading data frees flow control credit, which might need to
+                // be sent to the peer.
+                shared.wake_driver();
+
+                Poll::Ready(Ok(()))
+            },
+
+            Err(crate::Error::Done) if state.conn.is_closed() =>
+                Poll::Ready(Err(state.closed_error())),
+
+            Err(crate::Error::Done) => {
+                state.register(cx);
+
+                Poll::Pending
+            },
+
+            Err(e) => Poll::Ready(Err(io_error(e))),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.lock();
+
+        if state.conn.is_closed() {
+            return Poll::Ready(Err(state.closed_error()));
+        }
+
+        match state.conn.stream_send(self.stream_id, buf, false) {
+            Ok(len) if len > 0 || buf.is_empty() => {
+                self.shared.wake_driver();
+
+                Poll::Ready(Ok(len))
+            },
+
+            // The stream is blocked by flow or congestion control.
+            Ok(_) | Err(crate::Error::Done) => {
+                state.register(cx);
+
+                Poll::Pending
+            },
+
+            Err(e) => Poll::Ready(Err(io_error(e))),
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>, _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        // Data written to the stream is sent by the endpoint's task as soon as
+        // the connection allows it.
+        self.shared.wake_driver();
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.fin_written {
+            return Poll::Ready(Ok(()));
+        }
+
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+
+        if state.conn.is_closed() {
+            return Poll::Ready(Err(state.closed_error()));
+        }
+
+        match state.conn.stream_send(self.stream_id, b"", true) {
+            Ok(_) => {
+                self.fin_written = true;
+
+                shared.wake_driver();
+
+                Poll::Ready(Ok(()))
+            },
+
+            Err(crate::Error::Done) => {
+                state.register(cx);
+
+                Poll::Pending
+            },
+
+            Err(e) => Poll::Ready(Err(io_error(e))),
+        }
+    }
+}
+
+/// The state of an endpoint, shared by its handle and its task.
+struct EndpointShared {
+    socket: UdpSocket,
+
+    local_addr: SocketAddr,
+
+    is_server: bool,
+
+    config: Mutex<crate::Config>,
+
+    table: Mutex<ConnTable>,
+
+    /// Wakes the endpoint's task up, when connections have packets to send.
+    driver: Arc<Driver>,
+
+    /// Whether the address of clients is validated with a Retry packet.
+    retry: AtomicBool,
+
+    /// Whether the endpoint's handle was dropped.
+    closed: AtomicBool,
+}
+
+/// The connections of an endpoint, indexed by their connection IDs.
+#[derive(Default)]
+struct ConnTable {
+    conns: HashMap<u64, ConnEntry>,
+
+    ids: HashMap<Vec<u8>, u64>,
+
+    /// The connections' timers, ordered by deadline.
+    timers: BTreeSet<(Instant, u64)>,
+
+    next_id: u64,
+}
+
+struct ConnEntry {
+    conn: Arc<ConnShared>,
+
+    /// The connection IDs routed to the connection.
+    cids: Vec<Vec<u8>>,
+
+    /// The deadline of the connection's timer, as stored in `timers`.
+    timer: Option<Instant>,
+}
+
+impl ConnTable {
+    /// Updates the deadline of the connection's timer.
+    fn set_timer(&mut self, id: u64, timer: Option<Instant>) {
+        let entry = match self.conns.get_mut(&id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if entry.timer == timer {
+            return;
+        }
+
+        if let Some(t) = entry.timer.take() {
+            self.timers.remove(&(t, id));
+        }
+
+        if let Some(t) = timer {
+            self.timers.insert((t, id));
+        }
+
+        entry.timer = timer;
+    }
+
+    /// Stops routing the connection ID `cid` to its connection.
+    fn retire(&mut self, cid: &[u8]) {
+        if let Some(id) = self.ids.remove(cid) {
+            if let Some(entry) = self.conns.get_mut(&id) {
+                entry.cids.retain(|v| v[..] != cid[..]);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.set_timer(id, None);
+
+        if let Some(entry) = self.conns.remove(&id) {
+            for cid in &entry.cids {
+                self.ids.remove(cid);
+            }
+        }
+    }
+}
+
+impl EndpointShared {
+    fn table(&self) -> MutexGuard<ConnTable> {
+        lock(&self.table)
+    }
+
+    fn insert(&self, conn: crate::Connection, cids: Vec<Vec<u8>>) -> Connection {
+        let (next_bidi, next_uni) = if conn.is_server { (1, 3) } else { (0, 2) };
+
+        let mut table = self.table();
+
+        let id = table.next_id;
+        table.next_id += 1;
+
+        let shared = Arc::new(ConnShared {
+            id,
+
+            state: Mutex::new(ConnState {
+                conn,
+                wakers: Vec::new(),
+                accepted: HashSet::new(),
+                next_bidi,
+                next_uni,
+            }),
+
+            driver: self.driver.clone(),
+        });
+
+        for cid in &cids {
+            table.ids.insert(cid.clone(), id);
+        }
+
+        table.conns.insert(id, ConnEntry {
+            conn: shared.clone(),
+            cids,
+            timer: None,
+        });
+
+        Connection { shared }
+    }
+
+    fn recv(
+        &self, buf: &mut [u8], from: SocketAddr,
+        handshaking: &mut HashMap<u64, Connection>,
+    ) {
+        let len = buf.len();
+
+        let hdr = match crate::Header::from_slice(buf, crate::MAX_CONN_ID_LEN) {
+            Ok(v) => v,
+
+            Err(_) => return,
+        };
+
+        let conn = {
+            let table = self.table();
+
+            table
+                .ids
+                .get(&hdr.dcid[..])
+                .and_then(|id| table.conns.get(id))
+                .map(|entry| entry.conn.clone())
+        };
+
+        let conn = match conn {
+            Some(v) => v,
+
+            None if self.is_server &&
+                hdr.ty == crate::Type::Initial &&
+                len >= crate::MIN_CLIENT_INITIAL_LEN =>
+            {
+                let conn = match self.accept(&hdr, from) {
+                    Some(v) => v,
+
+                    None => return,
+                };
+
+                let shared = conn.shared.clone();
+                handshaking.insert(shared.id, conn);
+
+                shared
+            },
+
+            // Packets of unknown connections are dropped.
+            None => return,
+        };
+
+        let info = RecvInfo {
+            from,
+            to: self.local_addr,
+        };
+
+        let mut state = conn.lock();
+
+        if let Err(e) = state.conn.recv(buf, info) {
+            trace!("{} recv failed: {:?}", state.conn.trace_id(), e);
+        }
+
+        // The endpoint's task polls the connection right after this.
+        lock(&self.driver.ready).insert(conn.id);
+    }
+
+    fn accept(
+        &self, hdr: &crate::Header, from: SocketAddr,
+    ) -> Option<Connection> {
+        if self.closed.load(Ordering::Acquire) {
+            return None;
+        }
+
+        if !crate::version_is_supported(hdr.version) {
+            let mut out = [0; crate::MIN_CLIENT_INITIAL_LEN];
+
+            let len =
+                crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut out).ok()?;
+
+            self.socket.try_send_to(&out[..len], from).ok();
+
+            return None;
+        }
+
+        let mut config = lock(&self.config);
+
+        let retry = self.retry.load(Ordering::Acquire);
+
+        let validated = crate::endpoint::validate_initial(
+            &config, hdr, from, retry, new_scid,
+        );
+
+        let (scid, token) = match validated {
+            InitialToken::Accept(scid, token) => (scid, token),
+
+            InitialToken::Retry(pkt) => {
+                self.socket.try_send_to(&pkt, from).ok();
+
+                return None;
+            },
+
+            InitialToken::Drop => return None,
+        };
+
+        let conn = crate::endpoint::accept_initial(
+            &scid,
+            token,
+            self.local_addr,
+            from,
+            &mut config,
+        )
+        .ok()?;
+
+        drop(config);
+
+        let mut cids = vec![scid];
+
+        // The client keeps using the original destination connection ID until
+        // it receives the server's.
+        if hdr.dcid[..] != cids[0][..] {
+            cids.push(hdr.dcid.to_vec());
+        }
+
+        Some(self.insert(conn, cids))
+    }
+
+    /// Fires the expired timers of the connections, sends the pending packets
+    /// of the connections that are ready, and drops the closed ones.
+    ///
+    /// Only the connections whose timer expired, that received packets, or
+    /// that were used through their handles are polled. Their IDs are
+    /// returned.
+    fn flush(&self, out: &mut [u8]) -> Vec<u64> {
+        let now = Instant::now();
+
+        let mut polled = std::mem::take(&mut *lock(&self.driver.ready));
+
+        {
+            let mut table = self.table();
+
+            while let Some(&(t, id)) = table.timers.first() {
+                if t > now {
+                    break;
+                }
+
+                table.timers.remove(&(t, id));
+
+                if let Some(entry) = table.conns.get_mut(&id) {
+                    entry.timer = None;
+                }
+
+                polled.insert(id);
+            }
+        }
+
+        let polled: Vec<u64> = polled.into_iter().collect();
+
+        for &id in &polled {
+            let conn = match self.table().conns.get(&id) {
+                Some(entry) => entry.conn.clone(),
+
+                None => continue,
+            };
+
+            let mut state = conn.lock();
+
+            if state.conn.timeout_instant().map_or(false, |t| t <= now) {
+                state.conn.on_timeout();
+            }
+
+            loop {
+                let (len, info) = match state.conn.send(out) {
+                    Ok(v) => v,
+
+                    Err(crate::Error::Done) => break,
+
+                    Err(e) => {
+                        trace!("{} send failed: {:?}", state.conn.trace_id(), e);
+
+                        state.conn.close(false, 0x1, b"fail").ok();
+                        break;
+                    },
+                };
+
+                // Packets that don't fit in the socket's buffer are dropped,
+                // and recovered from as any other lost packet.
+                self.socket.try_send_to(&out[..len], info.to).ok();
+            }
+
+            let mut retired = Vec::new();
+
+            while let Some(cid) = state.conn.retired_scid_next() {
+                retired.push(cid.to_vec());
+            }
+
+            let closed = state.conn.is_closed();
+            let timer = state.conn.timeout_instant();
+
+            state.wake_all();
+
+            drop(state);
+
+            let mut table = self.table();
+
+            for cid in retired {
+                table.retire(&cid);
+            }
+
+            if closed {
+                table.remove(id);
+            } else {
+                table.set_timer(id, timer);
+            }
+        }
+
+        polled
+    }
+
+    /// Returns when the earliest timer of the connections expires.
+    fn timeout(&self) -> Option<Instant> {
+        self.table().timers.first().map(|(t, _)| *t)
+    }
+}
+
+/// Drives the connections of an endpoint, until the endpoint is dropped and
+/// all of its connections are closed.
+async fn drive(
+    shared: Arc<EndpointShared>, incoming: mpsc::UnboundedSender<Connection>,
+) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let mut out = vec![0; MAX_DATAGRAM_SIZE];
+
+    // The server connections that are returned by `accept()` once their
+    // handshake is completed.
+    let mut handshaking: HashMap<u64, Connection> = HashMap::new();
+
+    loop {
+        let timeout = shared.timeout();
+
+        let sleep = async {
+            match timeout {
+                Some(t) => ::tokio::time::sleep_until(t.into()).await,
+
+                None => std::future::pending().await,
+            }
+        };
+
+        ::tokio::select! {
+            res = shared.socket.recv_from(&mut buf) => {
+                // Errors are ignored, as they can be caused by ICMP messages
+                // for any of the peers.
+                if let Ok((len, from)) = res {
+                    let buf = &mut buf[..len];
+
+                    shared.recv(buf, from, &mut handshaking);
+                }
+            },
+
+            _ = shared.driver.notify.notified() => (),
+
+            _ = sleep => (),
+        }
+
+        for id in shared.flush(&mut out) {
+            let (established, closed) = match handshaking.get(&id) {
+                Some(conn) => {
+                    let state = conn.shared.lock();
+
+                    (state.conn.is_established(), state.conn.is_closed())
+                },
+
+                None => continue,
+            };
+
+            if !established && !closed {
+                continue;
+            }
+
+            // Connections that can't be returned anymore are closed when
+            // dropped.
+            if let Some(conn) = handshaking.remove(&id).filter(|_| established) {
+                incoming.send(conn).ok();
+            }
+        }
+
+        if shared.closed.load(Ordering::Acquire) &&
+            shared.table().conns.is_empty()
+        {
+            break;
+        }
+    }
+}
+
+/// Wakes the endpoint's task up, and tells it which connections to poll.
+#[derive(Default)]
+struct Driver {
+    notify: Notify,
+
+    /// The connections that might have packets to send.
+    ready: Mutex<HashSet<u64>>,
+}
+
+/// The state of a connection, shared by its handles and the endpoint's task.
+struct ConnShared {
+    /// The connection's ID in the endpoint's table.
+    id: u64,
+
+    state: Mutex<ConnState>,
+
+    /// Wakes the endpoint's task up, when the connection has packets to send.
+    driver: Arc<Driver>,
+}
+
+impl ConnShared {
+    fn lock(&self) -> MutexGuard<ConnState> {
+        lock(&self.state)
+    }
+
+    /// Makes the endpoint's task poll the connection.
+    fn wake_driver(&self) {
+        lock(&self.driver.ready).insert(self.id);
+
+        self.driver.notify.notify_one();
+    }
+}
+
+struct ConnState {
+    conn: crate::Connection,
+
+    /// The tasks waiting for the connection's state to change.
+    wakers: Vec<Waker>,
+
+    /// The peer-initiated streams already returned to the application.
+    accepted: HashSet<u64>,
+
+    /// The IDs of the next locally-initiated streams.
+    next_bidi: u64,
+    next_uni: u64,
+}
+
+impl ConnState {
+    fn register(&mut self, cx: &Context) {
+        if !self.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            self.wakers.push(cx.waker().clone());
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Returns the error reported to the tasks using a closed connection.
+    fn closed_error(&self) -> io::Error {
+        let msg = match self.conn.peer_error().or_else(|| self.conn.local_error())
+        {
+            Some(e) => format!(
+                "connection closed with {} error {:#x}",
+                if e.is_app { "application" } else { "transport" },
+                e.error_code
+            ),
+
+            None => "connection closed".to_string(),
+        };
+
+        io::Error::new(io::ErrorKind::ConnectionAborted, msg)
+    }
+}
+
+// Locks a mutex, ignoring poisoning: the state it protects is only changed
+// through the connection's API, which leaves it consistent.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn new_scid() -> Vec<u8> {
+    let mut scid = vec![0; crate::MAX_CONN_ID_LEN];
+    crate::rand::rand_bytes(&mut scid);
+
+    scid
+}
+
+fn io_error(e: crate::Error) -> io::Error {
+    let kind = match e {
+        crate::Error::StreamReset(_) => io::ErrorKind::ConnectionReset,
+
+        crate::Error::StreamStopped(_) => io::ErrorKind::BrokenPipe,
+
+        _ => io::ErrorKind::Other,
+    };
+
+    io::Error::new(kind, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ::tokio::io::AsyncReadExt;
+    use ::tokio::io::AsyncWriteExt;
+
+    fn config(is_server: bool) -> crate::Config {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config.set_application_protos(&[b"proto1"]).unwrap();
+        config.set_initial_max_data(1_000_000);
+        config.set_initial_max_stream_data_bidi_local(100_000);
+        config.set_initial_max_stream_data_bidi_remote(100_000);
+        config.set_initial_max_streams_bidi(10);
+        config.set_max_idle_timeout(5000);
+
+        if is_server {
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+        } else {
+            config.verify_peer(false);
+        }
+
+        config
+    }
+
+    #[::tokio::test]
+    async fn echo() {
+        let local = "127.0.0.1:0".parse().unwrap();
+
+        let mut server = Endpoint::server(local, config(true)).await.unwrap();
+        let server_addr = server.local_addr();
+
+        let server_task = ::tokio::spawn(async move {
+            let conn = server.accept().await.unwrap();
+
+            let mut stream = conn.accept_bi().await.unwrap();
+            assert_eq!(stream.id(), 0);
+
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+
+            stream.write_all(&buf).await.unwrap();
+            stream.shutdown().await.unwrap();
+
+            conn.closed().await;
+        });
+
+        let client = Endpoint::client(local, config(false)).await.unwrap();
+        assert_eq!(client.local_addr().ip(), local.ip());
+
+        let conn = client
+            .connect(Some("quic.tech"), server_addr)
+            .await
+            .unwrap();
+        assert!(conn.with(|c| c.is_established()));
+
+        let data = vec![0xab; 50_000];
+
+        let mut stream = conn.open_bi().unwrap();
+        stream.write_all(&data).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+
+        conn.close(0x0, b"bye").unwrap();
+        conn.closed().await;
+
+        server_task.await.unwrap();
+    }
+
+    #[::tokio::test]
+    async fn retry() {
+        use std::time::Duration;
+
+        let ring =
+            crate::KeyRing::new(Duration::from_secs(60), Duration::from_secs(60));
+
+        let minter = crate::KeyRingTokenMinter::new(
+            Arc::new(ring),
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+        );
+
+        let mut config = config(true);
+        config.set_token_minter(Some(Arc::new(minter)));
+
+        let local = "127.0.0.1:0".parse().unwrap();
+
+        let mut server = Endpoint::server(local, config).await.unwrap();
+        server.enable_retry(true);
+
+        let server_addr = server.local_addr();
+
+        let server_task = ::tokio::spawn(async move {
+            let conn = server.accept().await.unwrap();
+
+            conn.closed().await;
+        });
+
+        let client = Endpoint::client(local, config(false)).await.unwrap();
+
+        let conn = client
+            .connect(Some("quic.tech"), server_addr)
+            .await
+            .unwrap();
+        // The client's address was validated with a Retry packet.
+        assert!(conn.with(|c| c.did_retry));
+
+        conn.close(0x0, b"bye").unwrap();
+        conn.closed().await;
+
+        server_task.await.unwrap();
+    }
+}
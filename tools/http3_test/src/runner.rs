@@ -366,6 +366,12 @@ pub fn run(
 
                     Ok((_goaway_id, quiche::h3::Event::GoAway)) => (),
 
+                    Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+
+                    Ok((_, quiche::h3::Event::InterimHeaders { .. })) => (),
+
+                    Ok((_, quiche::h3::Event::Trailers { .. })) => (),
+
                     Err(quiche::h3::Error::Done) => {
                         break;
                     },